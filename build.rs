@@ -0,0 +1,48 @@
+//! Records what's being built (the git commit and the enabled features) for
+//! the `version` module, so a running server can say exactly what it is.
+
+use std::{env, path::Path, process::Command};
+
+fn main() {
+  println!("cargo:rerun-if-env-changed=KRAGLIN_GIT_SHA");
+  // rebuild when the checked out commit or the working tree changes. Outside
+  // a checkout (e.g. in a source tarball) there's nothing to watch.
+  for path in [".git/HEAD", ".git/index"] {
+    if Path::new(path).exists() {
+      println!("cargo:rerun-if-changed={path}");
+    }
+  }
+
+  // builds without git, like nix's, can pass the commit in themselves
+  let (sha, dirty) = match env::var("KRAGLIN_GIT_SHA") {
+    Ok(sha) => (sha, false),
+    Err(_) => (
+      git(&["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_owned()),
+      git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty()),
+    ),
+  };
+  println!("cargo:rustc-env=KRAGLIN_GIT_SHA={sha}");
+  println!("cargo:rustc-env=KRAGLIN_GIT_DIRTY={}", u8::from(dirty));
+
+  let mut features = env::vars()
+    .filter_map(|(var, _)| {
+      var
+        .strip_prefix("CARGO_FEATURE_")
+        .filter(|f| *f != "DEFAULT")
+        .map(|f| f.to_lowercase().replace('_', "-"))
+    })
+    .collect::<Vec<_>>();
+  features.sort();
+  println!("cargo:rustc-env=KRAGLIN_FEATURES={}", features.join(","));
+}
+
+/// Runs git with `args`, returning its trimmed output if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+  let output = Command::new("git").args(args).output().ok()?;
+  if !output.status.success() {
+    return None;
+  }
+  Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+}
@@ -19,11 +19,16 @@
           overlays = [ (import rust-overlay) ];
         };
 
-        toolchain = pkgs.rust-bin.selectLatestNightlyWith (toolchain: toolchain.default.override {
+        toolchain = pkgs.rust-bin.stable.latest.default.override {
           extensions = [ "rust-src" "rust-analyzer" ];
+        };
+        # rustfmt.toml uses unstable options, so formatting still needs nightly
+        fmt_toolchain = pkgs.rust-bin.selectLatestNightlyWith (toolchain: toolchain.minimal.override {
+          extensions = [ "rustfmt" ];
         });
 
         craneLib = (crane.mkLib pkgs).overrideToolchain toolchain;
+        fmtCraneLib = (crane.mkLib pkgs).overrideToolchain fmt_toolchain;
         src = ./.;
 
         common_args = {
@@ -33,6 +38,9 @@
           version = "0.1.0";
 
           doCheck = false;
+
+          # there's no `.git` in the store, so the build script can't ask git
+          KRAGLIN_GIT_SHA = self.shortRev or self.dirtyShortRev or "unknown";
         };
 
         deps_only = craneLib.buildDepsOnly common_args;
@@ -44,6 +52,10 @@
         devShells.default = pkgs.mkShell {
           nativeBuildInputs = with pkgs; [
             netcat bacon cargo-nextest cargo-deny
+            # listed first so that its `rustfmt` takes precedence
+            (pkgs.writeShellScriptBin "rustfmt" ''
+              exec ${fmt_toolchain}/bin/rustfmt "$@"
+            '')
             toolchain
           ];
         };
@@ -55,7 +67,7 @@
           docs-check = craneLib.cargoDoc (common_args // {
             cargoArtifacts = deps_only;
           });
-          fmt-check = craneLib.cargoFmt {
+          fmt-check = fmtCraneLib.cargoFmt {
             pname = common_args.pname;
             version = common_args.version;
             
@@ -0,0 +1,25 @@
+//! Feeds arbitrary arguments into the command parser.
+//!
+//! The input is split on `\n` into the frame's arguments, so seeds are
+//! readable commands like `SET\nkey\nvalue`. Parsing must never panic, and
+//! every command which parses must encode back to a frame which parses to the
+//! same command.
+
+#![no_main]
+
+use kraglin::{command::Command, value::Value};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let frame = Value::Array(
+    data
+      .split(|&b| b == b'\n')
+      .map(|arg| Value::BulkString(arg.to_vec().into()))
+      .collect(),
+  );
+
+  if let Ok(command) = Command::parse(frame) {
+    let encoded = command.to_resp();
+    assert_eq!(Command::parse(encoded), Ok(command));
+  }
+});
@@ -0,0 +1,41 @@
+//! Feeds arbitrary bytes into the RESP decoder.
+//!
+//! Decoding must never panic, and must find the same frames whether the input
+//! arrives in one read or split across two, at an offset taken from the
+//! input's first byte.
+
+#![no_main]
+
+use bytes::BytesMut;
+use kraglin::{resp::decode, value::Value};
+use libfuzzer_sys::fuzz_target;
+
+/// Decodes every frame in `reads`, fed to the decoder one after another,
+/// and whether decoding stopped at a malformed frame.
+fn decode_reads(reads: &[&[u8]]) -> (Vec<Value>, bool) {
+  let mut buf = BytesMut::new();
+  let mut values = Vec::new();
+  for read in reads {
+    buf.extend_from_slice(read);
+    loop {
+      match decode(&mut buf) {
+        Ok(Some(value)) => values.push(value),
+        Ok(None) => break,
+        Err(_) => return (values, true),
+      }
+    }
+  }
+  (values, false)
+}
+
+fuzz_target!(|data: &[u8]| {
+  let Some((&split, data)) = data.split_first() else {
+    return;
+  };
+  let split = usize::from(split).min(data.len());
+
+  let whole = decode_reads(&[data]);
+  let (first, second) = data.split_at(split);
+  let split = decode_reads(&[first, second]);
+  assert_eq!(whole, split);
+});
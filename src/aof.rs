@@ -0,0 +1,236 @@
+//! Verification and repair of append-only files.
+//!
+//! An append-only file is a sequence of commands, each encoded as a RESP
+//! array of bulk strings, as [`Command::to_resp()`] encodes them. Its
+//! segments live in the data directory's
+//! [`AOF_DIR`](crate::data_dir::AOF_DIR), and are replayed in name order.
+//!
+//! A crash in the middle of an append leaves a torn record at the end of the
+//! last segment. That's expected, and is repaired on startup by truncating the
+//! segment to its last complete command, since the dropped command was never
+//! acknowledged. Anything else which doesn't parse is corruption, which
+//! isn't repaired automatically; `kraglin-check-aof --fix` can truncate a
+//! segment to the last valid command before it, losing everything after.
+
+use std::{
+  fs::OpenOptions,
+  path::{Path, PathBuf},
+};
+
+use bytes::BytesMut;
+use color_eyre::eyre::{bail, Result, WrapErr};
+
+use crate::{command::Command, resp};
+
+/// What's wrong with a segment, if anything, as found by [`check()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AofProblem {
+  /// The last record is incomplete, as an append interrupted by a crash
+  /// leaves it.
+  Torn,
+  /// A record is malformed, or isn't a command.
+  Corrupt(String),
+}
+
+/// The result of checking a segment with [`check()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AofCheck {
+  /// How many valid commands the segment starts with.
+  pub commands:  usize,
+  /// How many bytes those commands take up. Everything after is dropped
+  /// when the segment is repaired.
+  pub valid_len: u64,
+  /// The problem found after the valid commands, if any.
+  pub problem:   Option<AofProblem>,
+}
+
+impl AofCheck {
+  /// Returns whether the whole segment is valid.
+  pub fn is_valid(&self) -> bool { self.problem.is_none() }
+}
+
+/// Checks that `data`, the contents of a segment, is a sequence of complete,
+/// valid commands.
+pub fn check(data: &[u8]) -> AofCheck {
+  let mut buf = BytesMut::from(data);
+  let mut commands = 0;
+  let mut valid_len = 0;
+  let problem = loop {
+    if buf.is_empty() {
+      break None;
+    }
+    match resp::decode(&mut buf) {
+      Ok(None) => break Some(AofProblem::Torn),
+      Ok(Some(frame)) => {
+        if let Err(e) = Command::parse(frame) {
+          break Some(AofProblem::Corrupt(e.to_string()));
+        }
+      }
+      Err(e) => break Some(AofProblem::Corrupt(e.to_string())),
+    }
+    commands += 1;
+    valid_len = (data.len() - buf.len()) as u64;
+  };
+  AofCheck {
+    commands,
+    valid_len,
+    problem,
+  }
+}
+
+/// Checks the segment at `path`, truncating it to its valid commands if
+/// `fix` is set and it isn't valid. Returns the result of the check, from
+/// before any truncation.
+pub fn check_file(path: &Path, fix: bool) -> Result<AofCheck> {
+  let data = std::fs::read(path)
+    .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+  let result = check(&data);
+  if fix && !result.is_valid() {
+    truncate(path, result.valid_len)?;
+  }
+  Ok(result)
+}
+
+/// Returns the segments in `dir`, in the order they're replayed. A missing
+/// directory has none.
+pub fn segments(dir: &Path) -> Result<Vec<PathBuf>> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => {
+      return Err(e)
+        .wrap_err_with(|| format!("failed to list {}", dir.display()))
+    }
+  };
+  let mut segments = Vec::new();
+  for entry in entries {
+    let entry =
+      entry.wrap_err_with(|| format!("failed to list {}", dir.display()))?;
+    if entry.file_type()?.is_file() {
+      segments.push(entry.path());
+    }
+  }
+  segments.sort();
+  Ok(segments)
+}
+
+/// Checks every segment in `dir` before it's loaded, truncating a torn
+/// record off the end of the last one.
+///
+/// Fails if any segment is corrupt, or if one before the last is torn, since
+/// those can't be the result of a crash while appending.
+pub fn recover(dir: &Path) -> Result<()> {
+  let segments = segments(dir)?;
+  for (i, path) in segments.iter().enumerate() {
+    let last = i + 1 == segments.len();
+    let result = check_file(path, false)?;
+    match result.problem {
+      None => {}
+      Some(AofProblem::Torn) if last => {
+        let len = std::fs::metadata(path)?.len();
+        tracing::warn!(
+          "{} ends with a torn record; truncating it from {len} to {} bytes, \
+           dropping {} bytes after its last {} commands",
+          path.display(),
+          result.valid_len,
+          len - result.valid_len,
+          result.commands,
+        );
+        truncate(path, result.valid_len)?;
+      }
+      Some(AofProblem::Torn) => bail!(
+        "{} is truncated after {} commands, but isn't the last segment; run \
+         `kraglin-check-aof --fix` on it to drop what follows",
+        path.display(),
+        result.commands,
+      ),
+      Some(AofProblem::Corrupt(e)) => bail!(
+        "{} is corrupt after {} commands ({} bytes): {e}; run \
+         `kraglin-check-aof --fix` on it to drop what follows",
+        path.display(),
+        result.commands,
+        result.valid_len,
+      ),
+    }
+  }
+  Ok(())
+}
+
+/// Truncates the file at `path` to `len` bytes, durably.
+fn truncate(path: &Path, len: u64) -> Result<()> {
+  let file = OpenOptions::new()
+    .write(true)
+    .open(path)
+    .wrap_err_with(|| format!("failed to open {}", path.display()))?;
+  file
+    .set_len(len)
+    .and_then(|()| file.sync_all())
+    .wrap_err_with(|| format!("failed to truncate {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::BytesMut;
+
+  use super::{check, recover, AofCheck, AofProblem};
+  use crate::command::Command;
+
+  fn encode(commands: &[Command]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    for command in commands {
+      command.to_resp().write_resp2(&mut buf);
+    }
+    buf.to_vec()
+  }
+
+  #[test]
+  fn torn_and_corrupt_records_are_found() {
+    let data = encode(&[Command::set("a", "1").build(), Command::Get {
+      key: "a".into(),
+    }]);
+    assert_eq!(check(&data), AofCheck {
+      commands:  2,
+      valid_len: data.len() as u64,
+      problem:   None,
+    });
+
+    let first_len = encode(&[Command::set("a", "1").build()]).len() as u64;
+    let torn = check(&data[..data.len() - 3]);
+    assert_eq!(torn.commands, 1);
+    assert_eq!(torn.valid_len, first_len);
+    assert_eq!(torn.problem, Some(AofProblem::Torn));
+
+    let mut corrupt = data.clone();
+    corrupt.extend_from_slice(b"*1\r\n$7\r\nNOTACMD\r\n");
+    let corrupt = check(&corrupt);
+    assert_eq!(corrupt.commands, 2);
+    assert!(matches!(corrupt.problem, Some(AofProblem::Corrupt(_))));
+
+    let garbage = check(b"*1\r\n$3\r\nGETX\r\n");
+    assert_eq!(garbage.commands, 0);
+    assert!(matches!(garbage.problem, Some(AofProblem::Corrupt(_))));
+  }
+
+  #[test]
+  fn recovery_truncates_a_torn_last_segment() {
+    let dir = std::env::temp_dir()
+      .join(format!("kraglin-aof-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let data = encode(&[Command::set("a", "1").build()]);
+    let mut torn = data.clone();
+    torn.extend_from_slice(b"*2\r\n$3\r\nGET\r\n$1");
+    std::fs::write(dir.join("0001.aof"), &data).unwrap();
+    std::fs::write(dir.join("0002.aof"), &torn).unwrap();
+    recover(&dir).unwrap();
+    assert_eq!(std::fs::read(dir.join("0002.aof")).unwrap(), data);
+
+    // only the last segment can have been torn by a crash
+    std::fs::write(dir.join("0001.aof"), &torn).unwrap();
+    assert!(recover(&dir).is_err());
+    assert_eq!(std::fs::read(dir.join("0001.aof")).unwrap(), torn);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,1436 @@
+//! A backend conformance suite, which every [`Backend`] should pass.
+//!
+//! This is the suite kraglin runs against its own backends. Implementors of
+//! third-party backends can run it against theirs by enabling the
+//! `conformance` feature and invoking [`backend_conformance_tests!`]
+//! (crate::backend_conformance_tests) inside a test module:
+//!
+//! ```ignore
+//! #[cfg(test)]
+//! mod tests {
+//!   kraglin::backend_conformance_tests!(my_crate::MyBackend);
+//! }
+//! ```
+//!
+//! This generates one `#[test]` per check, named after it. Each check is
+//! also a public function, so they can be run individually with [`run()`].
+//! Checks for command groups which are compiled out of kraglin are skipped.
+
+// the checks are named after the commands they exercise
+#![allow(non_snake_case, missing_docs)]
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  future::Future,
+  time::Duration,
+};
+
+use smol_str::SmolStr;
+
+use super::{collect_reply, Backend, BackendConfig, BackendExt};
+use crate::{
+  command::{Command, ExpireCondition, SetBuilder},
+  value::Value,
+  KraglinError,
+};
+
+/// Runs an async check to completion on a fresh single-threaded runtime,
+/// panicking if it fails.
+pub fn run(check: impl Future<Output = Result<(), KraglinError>>) {
+  tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .expect("failed to build a tokio runtime")
+    .block_on(check)
+    .unwrap_or_else(|e| panic!("conformance check failed: {e}"));
+}
+
+/// Generates a `#[test]` for every conformance check, run against the given
+/// [`Backend`] type.
+#[macro_export]
+macro_rules! backend_conformance_tests {
+  ($backend:ty) => {
+    $crate::__conformance_tests!($backend;
+      SET_sets_and_GET_gets,
+      MGET_gets_multiple_keys,
+      INCR_works,
+      GET_AND_DELETE_SET_AND_GET_OLD_and_INCR_BY_work,
+      replies_convert_to_rust_types,
+      KEYS_works,
+      SCAN_works,
+      SCAN_returns_stable_keys_exactly_once,
+      EXISTS_works,
+      DELETE_works,
+      DUMP_and_RESTORE_work,
+      keys_expire_at_their_deadline,
+      EXPIRE_and_its_conditions_work,
+      SET_options_work,
+      INFO_works,
+      streamed_KEYS_matches_KEYS,
+      MEMORY_USAGE_works,
+      large_bulk_strings_are_compressed,
+      values_are_normalized_when_enabled,
+      writes_rejected_over_max_memory,
+      DEBUG_HOTKEYS_reports_hottest_keys,
+      DEBUG_OBJECT_describes_values,
+      DEBUG_KEYSTATS_summarizes_the_keyspace,
+      defragment_preserves_data
+    );
+    #[test]
+    #[allow(non_snake_case)]
+    fn matches_reference_model() {
+      $crate::backends::conformance::matches_reference_model::<$backend>();
+    }
+    $crate::__conformance_tests_hashes!($backend);
+    $crate::__conformance_tests_sets!($backend);
+    $crate::__conformance_tests_json!($backend);
+  };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests {
+  ($backend:ty; $($check:ident),* $(,)?) => {
+    $(
+      #[test]
+      #[allow(non_snake_case)]
+      fn $check() {
+        $crate::backends::conformance::run(
+          $crate::backends::conformance::$check::<$backend>(),
+        );
+      }
+    )*
+  };
+}
+
+// The checks for each command group are only generated if kraglin was built
+// with the group, which the invoking crate's `cfg`s can't see.
+
+#[cfg(feature = "hashes")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_hashes {
+  ($backend:ty) => {
+    $crate::__conformance_tests!($backend;
+      HSET_sets_and_HGET_gets,
+      HGETALL_works,
+      HMGET_works,
+      HDEL_deletes_fields,
+      SET_AND_GET_OLD_and_GET_AND_DELETE_reject_hashes,
+      HEXPIRE_expires_fields,
+      used_memory_tracks_writes,
+      small_values_are_interned
+    );
+  };
+}
+
+#[cfg(not(feature = "hashes"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_hashes {
+  ($backend:ty) => {};
+}
+
+#[cfg(feature = "sets")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_sets {
+  ($backend:ty) => {
+    $crate::__conformance_tests!($backend;
+      SADD_and_SISMEMBER_normalize_members,
+      SDIFF_SDIFFSTORE_and_SREM_work,
+      streamed_SMEMBERS_matches_SMEMBERS
+    );
+  };
+}
+
+#[cfg(not(feature = "sets"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_sets {
+  ($backend:ty) => {};
+}
+
+#[cfg(feature = "json")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_json {
+  ($backend:ty) => {
+    $crate::__conformance_tests!($backend;
+      JSON_SET_and_JSON_GET_work,
+      JSON_DEL_and_JSON_NUMINCRBY_work
+    );
+  };
+}
+
+#[cfg(not(feature = "json"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __conformance_tests_json {
+  ($backend:ty) => {};
+}
+
+pub async fn SET_sets_and_GET_gets<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(
+    backend.SET("key_a", "a").await?,
+    Value::SimpleString("OK".into())
+  );
+  assert_eq!(backend.GET("key_a").await?, Value::SimpleString("a".into()));
+  assert_eq!(backend.GET("missing").await?, Value::Nothing);
+
+  Ok(())
+}
+
+pub async fn MGET_gets_multiple_keys<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("key_a", 2).await?;
+  backend.SET("key_b", 4).await?;
+  assert_eq!(
+    backend.MGET(vec!["key_a".into(), "key_b".into()]).await?,
+    Value::Array(vec![Value::Integer(2), Value::Integer(4)])
+  );
+
+  Ok(())
+}
+
+pub async fn INCR_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("int", 2).await?;
+  backend.SET("big_num", Value::BigNumber(4.into())).await?;
+  backend.SET("string", "24").await?;
+  backend.SET("bulk_string", "24".as_bytes()).await?;
+
+  backend.INCR("int").await?;
+  backend.INCR("big_num").await?;
+  backend.INCR("string").await?;
+  backend.INCR("bulk_string").await?;
+
+  assert_eq!(backend.GET("int").await?, Value::Integer(3));
+  assert_eq!(backend.GET("big_num").await?, Value::BigNumber(5.into()));
+  assert_eq!(
+    backend.GET("string").await?,
+    Value::SimpleString("25".into())
+  );
+  assert_eq!(
+    backend.GET("bulk_string").await?,
+    Value::BulkString("25".into())
+  );
+
+  Ok(())
+}
+
+pub async fn GET_AND_DELETE_SET_AND_GET_OLD_and_INCR_BY_work<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(backend.SET_AND_GET_OLD("a", "1").await?, Value::Nothing);
+  assert_eq!(
+    backend.SET_AND_GET_OLD("a", "2").await?,
+    Value::SimpleString("1".into())
+  );
+  assert_eq!(backend.INCR_BY("a", 40).await?, Value::Integer(42));
+  assert_eq!(backend.INCR_BY("a", -50).await?, Value::Integer(-8));
+  assert_eq!(backend.INCR_BY("new", 5).await?, Value::Integer(5));
+  assert!(matches!(
+    backend.INCR_BY("new", i64::MAX).await,
+    Err(KraglinError::OutOfRange)
+  ));
+
+  assert_eq!(
+    backend.GET_AND_DELETE("a").await?,
+    Value::SimpleString("-8".into())
+  );
+  assert_eq!(backend.EXISTS(vec!["a".into()]).await?, Value::Integer(0));
+  assert_eq!(backend.GET_AND_DELETE("a").await?, Value::Nothing);
+
+  Ok(())
+}
+
+pub async fn replies_convert_to_rust_types<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("s", "hello").await?;
+  backend.SET("n", "41").await?;
+
+  let s: String = backend.GET("s").await?.try_into()?;
+  assert_eq!(s, "hello");
+  let n: i64 = backend.INCR("n").await?.try_into()?;
+  assert_eq!(n, 42);
+  let all: Vec<String> = backend
+    .MGET(vec!["s".into(), "n".into()])
+    .await?
+    .try_into()?;
+  assert_eq!(all, vec!["hello".to_string(), "42".to_string()]);
+  assert!(matches!(
+    i64::try_from(backend.GET("s").await?),
+    Err(KraglinError::CannotParseAsInteger)
+  ));
+
+  Ok(())
+}
+
+pub async fn KEYS_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("a", 1).await?;
+  backend.SET("b", 2).await?;
+
+  assert_eq!(
+    backend.KEYS().await?,
+    Value::Array(vec![
+      Value::BulkString("a".into()),
+      Value::BulkString("b".into())
+    ])
+  );
+
+  Ok(())
+}
+
+/// Runs one `SCAN` call, returning the next cursor and the keys.
+async fn scan<B: Backend>(
+  backend: &B,
+  cursor: u64,
+  pattern: Option<&str>,
+  count: usize,
+) -> Result<(u64, Vec<SmolStr>), KraglinError> {
+  let reply = backend.SCAN(cursor, pattern.map(SmolStr::from), Some(count));
+  let Value::Array(reply) = reply.await? else {
+    panic!("SCAN should return an array");
+  };
+  let [Value::BulkString(cursor), Value::Array(keys)] = &reply[..] else {
+    panic!("SCAN should return a cursor and an array of keys");
+  };
+  let cursor = std::str::from_utf8(cursor).unwrap().parse().unwrap();
+  let keys = keys
+    .iter()
+    .map(|key| {
+      let Value::BulkString(key) = key else {
+        panic!("SCAN should return keys as bulk strings");
+      };
+      std::str::from_utf8(key).unwrap().into()
+    })
+    .collect();
+  Ok((cursor, keys))
+}
+
+pub async fn SCAN_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(scan(&backend, 0, None, 10).await?, (0, vec![]));
+
+  for key in ["a", "b", "c", "user:1", "user:2"] {
+    backend.SET(key, 1).await?;
+  }
+  for (pattern, expected) in [
+    (None, &["a", "b", "c", "user:1", "user:2"][..]),
+    (Some("user:*"), &["user:1", "user:2"]),
+  ] {
+    let mut keys = Vec::new();
+    let mut cursor = 0;
+    loop {
+      let (next, batch) = scan(&backend, cursor, pattern, 2).await?;
+      keys.extend(batch);
+      if next == 0 {
+        break;
+      }
+      cursor = next;
+    }
+    keys.sort_unstable();
+    assert_eq!(keys, expected);
+  }
+
+  Ok(())
+}
+
+/// Scans while keys are written, deleted, and added in bulk between calls,
+/// checking that every key which exists throughout is returned exactly once
+/// and that no key is returned twice.
+pub async fn SCAN_returns_stable_keys_exactly_once<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let stable = (0..200).map(|i| format!("stable:{i}")).collect::<Vec<_>>();
+  for key in &stable {
+    backend.SET(key.as_str(), 1).await?;
+  }
+
+  let mut seen = BTreeMap::<SmolStr, usize>::new();
+  let mut cursor = 0;
+  for round in 0.. {
+    assert!(round < 10_000, "the scan should finish");
+    let (next, keys) = scan(&backend, cursor, None, 7).await?;
+    for key in keys {
+      *seen.entry(key).or_default() += 1;
+    }
+    if next == 0 {
+      break;
+    }
+    cursor = next;
+
+    // churn the keyspace between calls: overwrite a stable key, add some
+    // keys and delete older ones, and once grow the keyspace enough to
+    // resize any underlying table
+    backend
+      .SET(stable[round % stable.len()].as_str(), 2)
+      .await?;
+    for i in 0..5 {
+      backend.SET(format!("churn:{round}:{i}"), 1).await?;
+    }
+    if round >= 2 {
+      let old = (0..3).map(|i| format!("churn:{}:{i}", round - 2).into());
+      backend.DEL(old.collect()).await?;
+    }
+    if round == 10 {
+      for i in 0..2_000 {
+        backend.SET(format!("bulk:{i}"), 1).await?;
+      }
+    }
+    if round == 20 {
+      let bulk = (0..2_000).step_by(2).map(|i| format!("bulk:{i}").into());
+      backend.DEL(bulk.collect()).await?;
+    }
+  }
+
+  for key in &stable {
+    assert_eq!(seen.get(key.as_str()), Some(&1), "{key}");
+  }
+  if let Some((key, _)) = seen.iter().find(|(_, &times)| times > 1) {
+    panic!("{key} was returned more than once");
+  }
+
+  Ok(())
+}
+
+pub async fn EXISTS_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("a", 1).await?;
+  assert_eq!(backend.EXISTS(vec!["a".into()]).await?, Value::Integer(1));
+
+  assert_eq!(backend.EXISTS(vec!["b".into()]).await?, Value::Integer(0));
+
+  // keys are counted each time they're given
+  backend.SET("c", 1).await?;
+  let keys = ["a", "b", "c", "a"].map(SmolStr::from).to_vec();
+  assert_eq!(backend.EXISTS(keys.clone()).await?, Value::Integer(3));
+  assert_eq!(backend.TOUCH(keys).await?, Value::Integer(3));
+
+  Ok(())
+}
+
+pub async fn DELETE_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("a", 1).await?;
+  assert_eq!(backend.EXISTS(vec!["a".into()]).await?, Value::Integer(1));
+  assert_eq!(backend.DEL(vec!["a".into()]).await?, Value::Integer(1));
+  assert_eq!(backend.EXISTS(vec!["a".into()]).await?, Value::Integer(0));
+  assert_eq!(backend.DEL(vec!["a".into()]).await?, Value::Integer(0));
+
+  // only the keys which existed are counted, each once
+  for key in ["a", "b", "c"] {
+    backend.SET(key, 1).await?;
+  }
+  let keys = ["a", "b", "a", "d"].map(SmolStr::from).to_vec();
+  assert_eq!(backend.DEL(keys.clone()).await?, Value::Integer(2));
+  assert_eq!(backend.UNLINK(keys).await?, Value::Integer(0));
+  let keys = ["c", "d"].map(SmolStr::from).to_vec();
+  assert_eq!(backend.UNLINK(keys).await?, Value::Integer(1));
+  assert_eq!(backend.KEYS().await?, Value::Array(vec![]));
+
+  Ok(())
+}
+
+pub async fn DUMP_and_RESTORE_work<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(backend.DUMP("missing").await?, Value::Nothing);
+  let values = [
+    Value::BulkString("hello".into()),
+    Value::Integer(7),
+    Value::Array(vec![Value::Integer(1), Value::BulkString("two".into())]),
+  ];
+  for value in values {
+    backend.SET("a", value.clone()).await?;
+    let Value::BulkString(payload) = backend.DUMP("a").await? else {
+      panic!("DUMP should return a bulk string");
+    };
+    assert_eq!(
+      backend.RESTORE("b", 0, payload.clone(), true).await?,
+      "OK".into()
+    );
+    assert_eq!(backend.GET("b").await?, value);
+
+    // existing keys are only replaced with REPLACE
+    assert!(matches!(
+      backend.RESTORE("b", 0, payload.clone(), false).await,
+      Err(KraglinError::BusyKey)
+    ));
+
+    // corrupt payloads are refused rather than deserialized
+    let mut corrupt = payload.to_vec();
+    corrupt[1] ^= 1;
+    assert!(matches!(
+      backend.RESTORE("c", 0, corrupt, false).await,
+      Err(KraglinError::BadDumpPayload)
+    ));
+    assert!(matches!(
+      backend.RESTORE("c", -1, payload, false).await,
+      Err(KraglinError::InvalidTtl)
+    ));
+    assert_eq!(backend.EXISTS(vec!["c".into()]).await?, Value::Integer(0));
+  }
+
+  Ok(())
+}
+
+pub async fn keys_expire_at_their_deadline<B: Backend>(
+) -> Result<(), KraglinError> {
+  // the check steps through the TTLs on virtual time
+  tokio::time::pause();
+  let backend = B::new(BackendConfig::default()).unwrap();
+  backend.SET("a", 1).await?;
+  let Value::BulkString(payload) = backend.DUMP("a").await? else {
+    panic!("DUMP should return a bulk string");
+  };
+
+  backend.RESTORE("b", 1000, payload.clone(), false).await?;
+  // a deadline which has already passed creates nothing
+  let restore_at = |key: &str, unix_time_ms| Command::Restore {
+    key:     key.into(),
+    ttl:     unix_time_ms,
+    payload: payload.clone(),
+    replace: true,
+    absttl:  true,
+  };
+  backend.execute(restore_at("c", 1)).await?;
+  assert_eq!(
+    backend.EXISTS(vec!["b".into(), "c".into()]).await?,
+    Value::Integer(1)
+  );
+
+  tokio::time::advance(Duration::from_millis(999)).await;
+  assert_eq!(backend.GET("b").await?, backend.GET("a").await?);
+  tokio::time::advance(Duration::from_millis(1)).await;
+  // `SCAN` skips expired keys too
+  assert_eq!(
+    backend.SCAN(0, None, None).await?,
+    Value::Array(vec![
+      Value::BulkString("0".into()),
+      Value::Array(vec![Value::BulkString("a".into())]),
+    ])
+  );
+  assert_eq!(backend.GET("b").await?, Value::Nothing);
+  assert_eq!(
+    backend.KEYS().await?,
+    Value::Array(vec![Value::BulkString("a".into())])
+  );
+  // expired keys are missing to writes too
+  backend.RESTORE("b", 0, payload.clone(), false).await?;
+
+  // overwriting a key removes its time to live
+  let at = crate::clock::unix_time_ms() as i64 + 1000;
+  backend.execute(restore_at("d", at)).await?;
+  backend.SET("d", 2).await?;
+  tokio::time::advance(Duration::from_secs(2)).await;
+  assert_eq!(backend.EXISTS(vec!["d".into()]).await?, Value::Integer(1));
+
+  Ok(())
+}
+
+pub async fn EXPIRE_and_its_conditions_work<B: Backend>(
+) -> Result<(), KraglinError> {
+  // the check steps through the TTLs on virtual time
+  tokio::time::pause();
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let exists = |key: &'static str| backend.EXISTS(vec![key.into()]);
+  let expire = |condition| Command::PExpire {
+    key:          "a".into(),
+    milliseconds: 1000,
+    condition:    Some(condition),
+  };
+
+  // missing keys can't be expired
+  assert_eq!(backend.EXPIRE("a", 10).await?, Value::Integer(0));
+  backend.SET("a", 1).await?;
+  // XX and GT need a time to live already, while NX and LT don't
+  assert_eq!(
+    backend.execute(expire(ExpireCondition::Xx)).await?,
+    Value::Integer(0)
+  );
+  assert_eq!(
+    backend.execute(expire(ExpireCondition::Gt)).await?,
+    Value::Integer(0)
+  );
+  assert_eq!(
+    backend.execute(expire(ExpireCondition::Nx)).await?,
+    Value::Integer(1)
+  );
+  assert_eq!(
+    backend.execute(expire(ExpireCondition::Nx)).await?,
+    Value::Integer(0)
+  );
+  // the deadline only moves in the direction the condition allows
+  let at = crate::clock::unix_time_ms() as i64;
+  let expire_at = |unix_time_ms, condition| Command::PExpireAt {
+    key: "a".into(),
+    unix_time_ms,
+    condition: Some(condition),
+  };
+  assert_eq!(
+    backend
+      .execute(expire_at(at + 500, ExpireCondition::Gt))
+      .await?,
+    Value::Integer(0)
+  );
+  assert_eq!(
+    backend
+      .execute(expire_at(at + 2000, ExpireCondition::Lt))
+      .await?,
+    Value::Integer(0)
+  );
+  assert_eq!(
+    backend
+      .execute(expire_at(at + 2000, ExpireCondition::Xx))
+      .await?,
+    Value::Integer(1)
+  );
+
+  tokio::time::advance(Duration::from_millis(1999)).await;
+  assert_eq!(exists("a").await?, Value::Integer(1));
+  tokio::time::advance(Duration::from_millis(1)).await;
+  assert_eq!(exists("a").await?, Value::Integer(0));
+
+  // deadlines which have already passed delete the key
+  backend.SET("a", 1).await?;
+  backend.SET("b", 1).await?;
+  backend.SET("c", 1).await?;
+  assert_eq!(backend.EXPIRE("a", -1).await?, Value::Integer(1));
+  assert_eq!(backend.EXPIREAT("b", 1).await?, Value::Integer(1));
+  assert_eq!(backend.PEXPIREAT("c", -1).await?, Value::Integer(1));
+  assert_eq!(
+    backend
+      .EXISTS(vec!["a".into(), "b".into(), "c".into()])
+      .await?,
+    Value::Integer(0)
+  );
+
+  // seconds are seconds, and times out of range are rejected
+  backend.SET("a", 1).await?;
+  assert_eq!(backend.EXPIRE("a", 2).await?, Value::Integer(1));
+  tokio::time::advance(Duration::from_millis(1999)).await;
+  assert_eq!(exists("a").await?, Value::Integer(1));
+  assert!(matches!(
+    backend.EXPIRE("a", i64::MAX).await,
+    Err(KraglinError::InvalidExpireTime(_))
+  ));
+  assert!(matches!(
+    backend.PEXPIRE("a", i64::MAX).await,
+    Err(KraglinError::InvalidExpireTime(_))
+  ));
+  tokio::time::advance(Duration::from_millis(1)).await;
+  assert_eq!(exists("a").await?, Value::Integer(0));
+
+  Ok(())
+}
+
+pub async fn SET_options_work<B: Backend>() -> Result<(), KraglinError> {
+  // the check steps through the TTLs on virtual time
+  tokio::time::pause();
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let set = |command: SetBuilder| backend.execute(command.build());
+  let exists = |key: &'static str| backend.EXISTS(vec![key.into()]);
+  let ok = Value::SimpleString("OK".into());
+
+  // NX only sets missing keys, and XX only existing ones
+  assert_eq!(set(Command::set("a", 1).xx()).await?, Value::Nothing);
+  assert_eq!(set(Command::set("a", 1).nx()).await?, ok);
+  assert_eq!(set(Command::set("a", 2).nx()).await?, Value::Nothing);
+  // with GET, the old value is returned whether or not the key is set
+  let one = backend.GET("a").await?;
+  assert_eq!(set(Command::set("a", 2).nx().get()).await?, one);
+  assert_eq!(set(Command::set("a", 3).xx().get()).await?, one);
+  assert_eq!(backend.GET("a").await?, Value::Integer(3));
+
+  // times to live start when the key is set, and KEEPTTL keeps them
+  assert_eq!(set(Command::set("b", 1).ex(2)).await?, ok);
+  assert_eq!(set(Command::set("c", 1).px(1000)).await?, ok);
+  assert_eq!(set(Command::set("c", 2).keep_ttl()).await?, ok);
+  tokio::time::advance(Duration::from_millis(1000)).await;
+  assert_eq!(exists("c").await?, Value::Integer(0));
+  tokio::time::advance(Duration::from_millis(999)).await;
+  assert_eq!(exists("b").await?, Value::Integer(1));
+  tokio::time::advance(Duration::from_millis(1)).await;
+  assert_eq!(exists("b").await?, Value::Integer(0));
+
+  // deadlines which have already passed delete the key
+  let at = crate::clock::unix_time_ms() as i64;
+  assert_eq!(set(Command::set("d", 1).pxat(at + 1000)).await?, ok);
+  assert_eq!(set(Command::set("d", 2).exat(1)).await?, ok);
+  assert_eq!(exists("d").await?, Value::Integer(0));
+
+  // times which aren't positive or are out of range are rejected
+  for command in [
+    Command::set("e", 1).ex(0),
+    Command::set("e", 1).px(-1),
+    Command::set("e", 1).ex(i64::MAX),
+  ] {
+    assert!(matches!(
+      set(command).await,
+      Err(KraglinError::InvalidExpireTime(_))
+    ));
+  }
+  assert_eq!(exists("e").await?, Value::Integer(0));
+
+  Ok(())
+}
+
+pub async fn INFO_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.SET("a", 1).await?;
+  let Value::BulkString(info) = backend.INFO().await? else {
+    panic!("INFO should return a bulk string");
+  };
+  let info = std::str::from_utf8(&info).unwrap();
+  assert!(info.contains("# Keyspace\r\nkeys:1\r\n"));
+  assert!(info.contains("# Memory\r\n"));
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn HSET_sets_and_HGET_gets<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.HSET("a", "b", 1).await?;
+  assert_eq!(backend.HGET("a", "b").await?, Value::Integer(1));
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn HGETALL_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.HSET("a", "b", 1).await?;
+  backend.HSET("a", "c", 2).await?;
+  assert_eq!(
+    backend.HGETALL("a").await?,
+    Value::Map(
+      [
+        ("b".into(), Value::Integer(1)),
+        ("c".into(), Value::Integer(2))
+      ]
+      .into_iter()
+      .collect::<BTreeMap<smol_str::SmolStr, Value>>()
+    )
+  );
+  assert_eq!(
+    backend.HGETALL("missing").await?,
+    Value::Map(BTreeMap::new())
+  );
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn HMGET_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.HSET("a", "b", 1).await?;
+  backend.HSET("a", "c", 2).await?;
+  backend.HSET("a", "d", 3).await?;
+
+  assert_eq!(
+    backend.HMGET("a", vec!["b".into(), "c".into()]).await?,
+    Value::Array(vec![Value::Integer(1), Value::Integer(2)])
+  );
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn HDEL_deletes_fields<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.HSET("a", "b", 1).await?;
+  backend.HSET("a", "c", 2).await?;
+  assert_eq!(
+    backend
+      .HDEL("a", vec!["b".into(), "missing".into()])
+      .await?,
+    Value::Integer(1)
+  );
+  assert_eq!(backend.HGET("a", "b").await?, Value::Nothing);
+  assert_eq!(
+    backend.HDEL("missing", vec!["b".into()]).await?,
+    Value::Integer(0)
+  );
+
+  // deleting the last field deletes the key
+  backend.HDEL("a", vec!["c".into()]).await?;
+  assert_eq!(backend.EXISTS(vec!["a".into()]).await?, Value::Integer(0));
+
+  backend.SET("s", "x").await?;
+  assert!(matches!(
+    backend.HDEL("s", vec!["b".into()]).await,
+    Err(KraglinError::WrongType)
+  ));
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn SET_AND_GET_OLD_and_GET_AND_DELETE_reject_hashes<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend.HSET("h", "a", 1).await?;
+  assert!(matches!(
+    backend.SET_AND_GET_OLD("h", "x").await,
+    Err(KraglinError::WrongType)
+  ));
+  assert!(matches!(
+    backend.GET_AND_DELETE("h").await,
+    Err(KraglinError::WrongType)
+  ));
+
+  // neither overwrote nor deleted the hash
+  assert_eq!(backend.HGET("h", "a").await?, Value::Integer(1));
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn HEXPIRE_expires_fields<B: Backend>() -> Result<(), KraglinError> {
+  // the check steps through the TTLs on virtual time
+  tokio::time::pause();
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let replies = |replies: &[i64]| {
+    Value::Array(replies.iter().map(|&r| Value::Integer(r)).collect())
+  };
+  let fields = |fields: &[&str]| fields.iter().map(|&f| f.into()).collect();
+
+  backend.HSET("h", "a", 1).await?;
+  backend.HSET("h", "b", 2).await?;
+  assert_eq!(
+    backend.HEXPIRE("h", 10, fields(&["a", "missing"])).await?,
+    replies(&[1, -2])
+  );
+  assert_eq!(
+    backend.HTTL("h", fields(&["a", "b"])).await?,
+    replies(&[10, -1])
+  );
+  assert_eq!(
+    backend.HTTL("missing", fields(&["a"])).await?,
+    replies(&[-2])
+  );
+
+  let expire = |ms, condition| Command::HashPExpire {
+    key:          "h".into(),
+    milliseconds: ms,
+    condition:    Some(condition),
+    fields:       fields(&["a"]),
+  };
+  assert_eq!(
+    backend.execute(expire(5000, ExpireCondition::Nx)).await?,
+    replies(&[0])
+  );
+  assert_eq!(
+    backend.execute(expire(5000, ExpireCondition::Gt)).await?,
+    replies(&[0])
+  );
+  assert_eq!(
+    backend.execute(expire(5000, ExpireCondition::Lt)).await?,
+    replies(&[1])
+  );
+  assert_eq!(backend.HPTTL("h", fields(&["a"])).await?, replies(&[5000]));
+
+  tokio::time::advance(Duration::from_secs(5)).await;
+  assert_eq!(backend.HGET("h", "a").await?, Value::Nothing);
+  assert_eq!(
+    backend.HGETALL("h").await?,
+    Value::Map(BTreeMap::from([("b".into(), Value::Integer(2))]))
+  );
+
+  backend.HEXPIRE("h", 10, fields(&["b"])).await?;
+  assert_eq!(
+    backend.HPERSIST("h", fields(&["b", "missing"])).await?,
+    replies(&[1, -2])
+  );
+  assert_eq!(backend.HPERSIST("h", fields(&["b"])).await?, replies(&[-1]));
+
+  let at = crate::clock::unix_time_ms() as i64 + 3000;
+  assert_eq!(
+    backend.HPEXPIREAT("h", at, fields(&["b"])).await?,
+    replies(&[1])
+  );
+  assert_eq!(backend.HPTTL("h", fields(&["b"])).await?, replies(&[3000]));
+
+  // a TTL of zero deletes the field, and with it the emptied hash
+  assert_eq!(
+    backend.HEXPIRE("h", 0, fields(&["b"])).await?,
+    replies(&[2])
+  );
+  assert_eq!(backend.EXISTS(vec!["h".into()]).await?, Value::Integer(0));
+
+  // overwriting a field removes its TTL
+  backend.HSET("h", "a", 1).await?;
+  backend.HEXPIRE("h", 10, fields(&["a"])).await?;
+  backend.HSET("h", "a", 2).await?;
+  assert_eq!(backend.HTTL("h", fields(&["a"])).await?, replies(&[-1]));
+
+  assert!(matches!(
+    backend.HEXPIRE("h", -1, fields(&["a"])).await,
+    Err(KraglinError::InvalidExpireTime(_))
+  ));
+
+  Ok(())
+}
+
+#[cfg(feature = "sets")]
+pub async fn SADD_and_SISMEMBER_normalize_members<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  // members are compared by the argument they'd be sent as
+  assert_eq!(backend.SADD("set", 2).await?, Value::Integer(1));
+  assert_eq!(
+    backend.SADD("set", Value::BigNumber(2.into())).await?,
+    Value::Integer(0)
+  );
+  assert_eq!(backend.SADD("set", "2").await?, Value::Integer(0));
+  assert_eq!(backend.SADD("set", 2.5).await?, Value::Integer(1));
+  assert_eq!(backend.SISMEMBER("set", "2").await?, Value::Integer(1));
+  assert_eq!(
+    backend.SISMEMBER("set", "2.5".as_bytes()).await?,
+    Value::Integer(1)
+  );
+  assert_eq!(backend.SISMEMBER("set", 3).await?, Value::Integer(0));
+  assert_eq!(backend.SCARD("set").await?, Value::Integer(2));
+  assert_eq!(
+    backend.SMEMBERS("set").await?,
+    Value::Set(
+      [
+        Value::BulkString("2".into()),
+        Value::BulkString("2.5".into())
+      ]
+      .into_iter()
+      .collect()
+    )
+  );
+
+  assert!(matches!(
+    backend.SADD("set", Value::Array(vec![])).await,
+    Err(KraglinError::WrongType)
+  ));
+  backend.SET("string", "a").await?;
+  assert!(matches!(
+    backend.SADD("string", 1).await,
+    Err(KraglinError::WrongType)
+  ));
+
+  Ok(())
+}
+
+#[cfg(feature = "sets")]
+pub async fn SDIFF_SDIFFSTORE_and_SREM_work<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  for member in ["a", "b", "c"] {
+    backend.SADD("x", member).await?;
+  }
+  backend.SADD("y", "b").await?;
+
+  let members = |ms: &[&str]| {
+    Value::Set(
+      ms.iter()
+        .map(|m| Value::BulkString(m.as_bytes().to_vec().into()))
+        .collect(),
+    )
+  };
+  assert_eq!(backend.SDIFF("x", "y").await?, members(&["a", "c"]));
+  assert_eq!(
+    backend.SDIFF("x", "missing").await?,
+    members(&["a", "b", "c"])
+  );
+  assert_eq!(backend.SDIFF("missing", "x").await?, members(&[]));
+
+  assert_eq!(backend.SDIFFSTORE("x", "y", "z").await?, Value::Integer(2));
+  assert_eq!(backend.SMEMBERS("z").await?, members(&["a", "c"]));
+
+  assert_eq!(backend.SREM("z", "a").await?, Value::Integer(1));
+  assert_eq!(backend.SREM("z", "a").await?, Value::Integer(0));
+  assert_eq!(backend.SREM("z", "c").await?, Value::Integer(1));
+  assert_eq!(backend.EXISTS(vec!["z".into()]).await?, Value::Integer(0));
+
+  Ok(())
+}
+
+#[cfg(feature = "json")]
+pub async fn JSON_SET_and_JSON_GET_work<B: Backend>() -> Result<(), KraglinError>
+{
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(
+    backend.JSON_SET("doc", "$", r#"{"a":{"b":[1,2]}}"#).await?,
+    Value::SimpleString("OK".into())
+  );
+  backend.JSON_SET("doc", "$.a.c", r#""new""#).await?;
+  backend.JSON_SET("doc", "$.a.b[-1]", "3").await?;
+  // `$` paths reply with an array of their matches, and legacy paths with
+  // the match itself
+  assert_eq!(
+    backend.JSON_GET("doc", "$").await?,
+    Value::BulkString(r#"[{"a":{"b":[1,3],"c":"new"}}]"#.into())
+  );
+  assert_eq!(
+    backend.JSON_GET("doc", "a.b[0]").await?,
+    Value::BulkString("1".into())
+  );
+  assert_eq!(
+    backend.JSON_GET("doc", "$.missing").await?,
+    Value::BulkString("[]".into())
+  );
+  assert_eq!(backend.JSON_GET("doc", ".missing").await?, Value::Nothing);
+  assert_eq!(backend.JSON_GET("missing", "$").await?, Value::Nothing);
+
+  assert!(matches!(
+    backend.JSON_SET("doc", "$.x.y", "1").await,
+    Err(KraglinError::JsonPathNotFound)
+  ));
+  assert!(matches!(
+    backend.JSON_SET("missing", "$.a", "1").await,
+    Err(KraglinError::JsonPathNotFound)
+  ));
+  assert!(matches!(
+    backend.JSON_SET("doc", "$", "{").await,
+    Err(KraglinError::InvalidJson(_))
+  ));
+  assert!(matches!(
+    backend.JSON_GET("doc", "$..a").await,
+    Err(KraglinError::InvalidJsonPath(_))
+  ));
+
+  backend.SET("string", "a").await?;
+  assert!(matches!(
+    backend.JSON_GET("string", "$").await,
+    Err(KraglinError::WrongType)
+  ));
+
+  Ok(())
+}
+
+#[cfg(feature = "json")]
+pub async fn JSON_DEL_and_JSON_NUMINCRBY_work<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend
+    .JSON_SET("doc", "$", r#"{"n":1,"f":1.5,"s":"x"}"#)
+    .await?;
+  assert_eq!(
+    backend.JSON_NUMINCRBY("doc", "$.n", 1).await?,
+    Value::BulkString("[2]".into())
+  );
+  assert_eq!(
+    backend.JSON_NUMINCRBY("doc", ".n", 1).await?,
+    Value::Integer(3)
+  );
+  assert_eq!(
+    backend.JSON_NUMINCRBY("doc", ".f", 1).await?,
+    Value::Double(2.5)
+  );
+  assert!(matches!(
+    backend.JSON_NUMINCRBY("doc", "$.s", 1).await,
+    Err(KraglinError::JsonWrongType {
+      expected: "number",
+      found:    "string",
+    })
+  ));
+
+  assert_eq!(backend.JSON_DEL("doc", "$.s").await?, Value::Integer(1));
+  assert_eq!(backend.JSON_DEL("doc", "$.s").await?, Value::Integer(0));
+  assert_eq!(
+    backend.JSON_GET("doc", ".").await?,
+    Value::BulkString(r#"{"f":2.5,"n":3}"#.into())
+  );
+
+  assert_eq!(backend.JSON_DEL("doc", "$").await?, Value::Integer(1));
+  assert_eq!(backend.EXISTS(vec!["doc".into()]).await?, Value::Integer(0));
+
+  Ok(())
+}
+
+pub async fn streamed_KEYS_matches_KEYS<B: Backend>() -> Result<(), KraglinError>
+{
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  for i in 0..(super::REPLY_CHUNK_SIZE * 2 + 1) {
+    backend.SET(format!("key_{i}"), 1).await?;
+  }
+
+  assert_eq!(
+    collect_reply(backend.execute_streaming(0, Command::Keys, true)).await?,
+    backend.KEYS().await?
+  );
+  assert_eq!(
+    collect_reply(backend.execute_streaming(
+      0,
+      Command::Get {
+        key: "key_0".into(),
+      },
+      true,
+    ))
+    .await?,
+    Value::Integer(1)
+  );
+
+  // other databases stream their own keys
+  if backend.databases() > 1 {
+    let set = Command::set("other", 1).build();
+    backend.execute_in(1, set, true).await?;
+    assert_eq!(
+      collect_reply(backend.execute_streaming(1, Command::Keys, true)).await?,
+      Value::Array(vec![Value::BulkString("other".into())])
+    );
+  }
+
+  Ok(())
+}
+
+#[cfg(feature = "sets")]
+pub async fn streamed_SMEMBERS_matches_SMEMBERS<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  for i in 0..(super::REPLY_CHUNK_SIZE * 2 + 1) {
+    backend.SADD("set", format!("member_{i}")).await?;
+  }
+  let members = Command::SetMembers { key: "set".into() };
+  assert_eq!(
+    collect_reply(backend.execute_streaming(0, members, true)).await?,
+    backend.SMEMBERS("set").await?
+  );
+
+  backend.SET("string", 1).await?;
+  let members = Command::SetMembers {
+    key: "string".into(),
+  };
+  assert!(matches!(
+    collect_reply(backend.execute_streaming(0, members, true)).await,
+    Err(KraglinError::WrongType)
+  ));
+
+  Ok(())
+}
+
+/// Reads an integer field from the `INFO` reply.
+async fn info_field<B: Backend>(backend: &B, field: &str) -> i64 {
+  let Value::BulkString(info) = backend.INFO().await.unwrap() else {
+    panic!("INFO should return a bulk string");
+  };
+  std::str::from_utf8(&info)
+    .unwrap()
+    .lines()
+    .find_map(|line| line.strip_prefix(&format!("{field}:")))
+    .and_then(|v| v.parse().ok())
+    .unwrap_or_else(|| panic!("INFO should contain an integer `{field}`"))
+}
+
+#[cfg(feature = "hashes")]
+pub async fn used_memory_tracks_writes<B: Backend>() -> Result<(), KraglinError>
+{
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let empty = info_field(&backend, "used_memory").await;
+
+  backend
+    .SET("a", Value::BulkString(vec![0; 1024].into()))
+    .await?;
+  let after_set = info_field(&backend, "used_memory").await;
+  assert!(after_set >= empty + 1024);
+
+  backend.HSET("h", "f", 1).await?;
+  backend.HSET("h", "f", 2).await?;
+  backend.INCR("i").await?;
+  assert!(info_field(&backend, "used_memory").await > after_set);
+
+  backend.DEL(vec!["a".into()]).await?;
+  backend.DEL(vec!["h".into()]).await?;
+  backend.DEL(vec!["i".into()]).await?;
+  assert_eq!(info_field(&backend, "used_memory").await, empty);
+
+  Ok(())
+}
+
+pub async fn MEMORY_USAGE_works<B: Backend>() -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert_eq!(backend.MEMORY_USAGE("a").await?, Value::Nothing);
+
+  backend.SET("a", 1).await?;
+  let small = i64::try_from(backend.MEMORY_USAGE("a").await?)?;
+  backend
+    .SET("a", Value::BulkString(vec![0; 1024].into()))
+    .await?;
+  let large = i64::try_from(backend.MEMORY_USAGE("a").await?)?;
+  assert_eq!(large, small + 1024);
+
+  Ok(())
+}
+
+pub async fn DEBUG_OBJECT_describes_values<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  assert!(matches!(
+    backend.DEBUG_OBJECT("a").await,
+    Err(KraglinError::NoSuchKey)
+  ));
+
+  backend.SET("a", "hello".as_bytes()).await?;
+  let Value::SimpleString(description) = backend.DEBUG_OBJECT("a").await?
+  else {
+    panic!("DEBUG OBJECT should return a simple string");
+  };
+  assert!(description.starts_with("Value at:"));
+  assert!(description.contains(" encoding:raw serializedlength:5 "));
+  assert!(description.ends_with(" type:string"));
+
+  Ok(())
+}
+
+pub async fn DEBUG_KEYSTATS_summarizes_the_keyspace<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig::default()).unwrap();
+  let field = |stats: &Value, name: &str| {
+    let Value::Map(stats) = stats else {
+      panic!("DEBUG KEYSTATS should return a map");
+    };
+    stats[name].clone()
+  };
+
+  backend.SET("a", 1).await?;
+  backend.SET("b", "x".as_bytes()).await?;
+  backend
+    .SET("big", Value::BulkString(vec![0; 2000].into()))
+    .await?;
+
+  let stats = backend.DEBUG_KEYSTATS().await?;
+  assert_eq!(field(&stats, "keys"), Value::Integer(3));
+  assert_eq!(field(&stats, "keys_with_ttl"), Value::Integer(0));
+  assert_eq!(field(&field(&stats, "types"), "string"), Value::Integer(3));
+  let Value::Array(biggest) = field(&field(&stats, "biggest"), "string") else {
+    panic!("the biggest key should be a [key, size] pair");
+  };
+  assert_eq!(biggest[0], Value::BulkString("big".into()));
+  let Value::Array(sizes) = field(&stats, "sizes") else {
+    panic!("sizes should be an array of buckets");
+  };
+  let bucket = |label: &str| {
+    sizes
+      .iter()
+      .find_map(|bucket| match bucket {
+        Value::Array(pair) if pair[0] == Value::SimpleString(label.into()) => {
+          Some(pair[1].clone())
+        }
+        _ => None,
+      })
+      .unwrap()
+  };
+  assert_eq!(bucket("<4KB"), Value::Integer(1));
+  assert_eq!(bucket(">=1MB"), Value::Integer(0));
+
+  #[cfg(feature = "hashes")]
+  {
+    backend.HSET("h", "f", 1).await?;
+    backend.HEXPIRE("h", 10, vec!["f".into()]).await?;
+    let stats = backend.DEBUG_KEYSTATS().await?;
+    assert_eq!(field(&stats, "keys_with_ttl"), Value::Integer(1));
+    assert_eq!(field(&field(&stats, "types"), "hash"), Value::Integer(1));
+  }
+
+  let sampled = backend
+    .execute(Command::DebugKeyStats { samples: Some(2) })
+    .await?;
+  assert_eq!(field(&sampled, "keys"), Value::Integer(2));
+
+  Ok(())
+}
+
+pub async fn large_bulk_strings_are_compressed<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig {
+    compression_threshold: Some(64),
+    ..Default::default()
+  })
+  .unwrap();
+
+  let large = br#"{"field":"value"}"#.repeat(64);
+  backend.SET("large", large.as_slice()).await?;
+  backend.SET("small", "abc".as_bytes()).await?;
+
+  assert_eq!(
+    backend.OBJECT_ENCODING("large").await?,
+    Value::SimpleString("lz4".into())
+  );
+  assert_eq!(
+    backend.OBJECT_ENCODING("small").await?,
+    Value::SimpleString("raw".into())
+  );
+  assert_eq!(backend.GET("large").await?, Value::BulkString(large.into()));
+  assert!(i64::try_from(backend.MEMORY_USAGE("large").await?)? < 17 * 64);
+  assert_eq!(info_field(&backend, "compressed_values").await, 1);
+  assert!(matches!(
+    backend.INCR("large").await,
+    Err(KraglinError::CannotParseAsInteger)
+  ));
+
+  Ok(())
+}
+
+pub async fn values_are_normalized_when_enabled<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig {
+    normalize_values: true,
+    ..Default::default()
+  })
+  .unwrap();
+
+  backend.SET("simple", "42").await?;
+  backend.SET("bulk", "-7".as_bytes()).await?;
+  backend.SET("padded", "007".as_bytes()).await?;
+  backend.SET("word", "abc").await?;
+
+  // integers are stored as integers however they were sent
+  assert_eq!(backend.GET("simple").await?, Value::Integer(42));
+  assert_eq!(backend.GET("bulk").await?, Value::Integer(-7));
+  assert_eq!(
+    backend.OBJECT_ENCODING("simple").await?,
+    Value::SimpleString("int".into())
+  );
+  // and strings which don't read back as the same integer keep their bytes
+  assert_eq!(
+    backend.GET("padded").await?,
+    Value::BulkString("007".into())
+  );
+  assert_eq!(backend.GET("word").await?, Value::BulkString("abc".into()));
+  assert_eq!(
+    backend.OBJECT_ENCODING("word").await?,
+    Value::SimpleString("raw".into())
+  );
+
+  backend.INCR("bulk").await?;
+  assert_eq!(backend.GET("bulk").await?, Value::Integer(-6));
+
+  Ok(())
+}
+
+#[cfg(feature = "hashes")]
+pub async fn small_values_are_interned<B: Backend>() -> Result<(), KraglinError>
+{
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  for i in 0..10 {
+    backend.SET(format!("flag{i}"), "1".as_bytes()).await?;
+    backend
+      .HSET("h", format!("field{i}"), "on".as_bytes())
+      .await?;
+  }
+  assert_eq!(info_field(&backend, "interned_values").await, 19);
+  assert_eq!(info_field(&backend, "interning_bytes_saved").await, 28);
+  assert_eq!(backend.GET("flag3").await?, Value::BulkString("1".into()));
+
+  Ok(())
+}
+
+pub async fn writes_rejected_over_max_memory<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig {
+    max_memory: Some(1),
+    ..Default::default()
+  })
+  .unwrap();
+
+  backend.SET("a", 1).await?;
+  assert!(matches!(
+    backend.SET("b", 1).await,
+    Err(KraglinError::OutOfMemory)
+  ));
+  // reads and deletes are still allowed
+  assert_eq!(backend.GET("a").await?, Value::Integer(1));
+  assert_eq!(backend.DEL(vec!["a".into()]).await?, Value::Integer(1));
+  backend.SET("b", 1).await?;
+
+  Ok(())
+}
+
+pub async fn DEBUG_HOTKEYS_reports_hottest_keys<B: Backend>(
+) -> Result<(), KraglinError> {
+  let backend = B::new(BackendConfig {
+    hot_keys_sample_rate: std::num::NonZeroU32::new(1),
+    ..Default::default()
+  })
+  .unwrap();
+
+  backend.SET("hot", 1).await?;
+  backend.SET("cold", 1).await?;
+  for _ in 0..10 {
+    backend.GET("hot").await?;
+  }
+
+  assert_eq!(
+    backend.DEBUG_HOTKEYS().await?,
+    Value::Array(vec![
+      Value::BulkString("hot".into()),
+      Value::Integer(11),
+      Value::BulkString("cold".into()),
+      Value::Integer(1),
+    ])
+  );
+
+  Ok(())
+}
+
+pub async fn defragment_preserves_data<B: Backend>() -> Result<(), KraglinError>
+{
+  let backend = B::new(BackendConfig::default()).unwrap();
+
+  backend
+    .SET("a", Value::Array((0..4).map(Value::Integer).collect()))
+    .await?;
+  backend.SET("b", 1).await?;
+  // collections nested in maps and sets are shrunk too. Cloning a `Vec`
+  // drops its spare capacity, so each value is built afresh
+  let map = || {
+    let mut shrunk = Vec::with_capacity(256);
+    shrunk.push(Value::Integer(1));
+    Value::Map(BTreeMap::from([("f".into(), Value::Array(shrunk))]))
+  };
+  let set = || Value::Set(BTreeSet::from([map(), Value::Integer(2)]));
+  backend.SET("m", map()).await?;
+  backend.SET("s", set()).await?;
+  backend.defragment().await;
+
+  assert_eq!(
+    backend.GET("a").await?,
+    Value::Array((0..4).map(Value::Integer).collect())
+  );
+  assert_eq!(backend.GET("b").await?, Value::Integer(1));
+  assert_eq!(backend.GET("m").await?, map());
+  assert_eq!(backend.GET("s").await?, set());
+
+  Ok(())
+}
+
+/// Runs random command sequences against the backend and a reference model
+/// of the keyspace, failing on the first reply which differs.
+pub fn matches_reference_model<B: Backend>() {
+  super::model::check_against_model::<B>();
+}
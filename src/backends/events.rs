@@ -0,0 +1,179 @@
+//! Typed events about changes to keys, for embedders which want to react to
+//! writes (e.g. to keep a cache in sync) without going through pub/sub.
+//!
+//! Backends which support events broadcast them through a [`KeyEvents`], and
+//! hand out receivers from
+//! [`Backend::subscribe_events()`](super::Backend::subscribe_events).
+
+use smol_str::SmolStr;
+use tokio::sync::broadcast;
+
+/// How many events a subscriber may fall behind by before it starts missing
+/// them (see [`broadcast::error::RecvError::Lagged`]).
+pub const KEY_EVENT_CAPACITY: usize = 1024;
+
+/// What happened to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyEventKind {
+  /// The key was created.
+  Create,
+  /// The key's value was changed, or replaced.
+  Update,
+  /// The key was deleted.
+  Delete,
+  /// Part or all of the key's value expired. If all of it did, the key no
+  /// longer exists, and no [`Delete`](KeyEventKind::Delete) is sent.
+  Expire,
+}
+
+/// A change to a key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+  /// The database the key is in.
+  pub db:   usize,
+  /// The key which changed.
+  pub key:  SmolStr,
+  /// What happened to it.
+  pub kind: KeyEventKind,
+}
+
+/// Broadcasts [`KeyEvent`]s to every subscriber.
+///
+/// Clones share the same channel. Sending is a no-op while nobody is
+/// subscribed, so backends can emit events unconditionally.
+#[derive(Debug, Clone)]
+pub struct KeyEvents {
+  sender: broadcast::Sender<KeyEvent>,
+  /// The database events are emitted for.
+  db:     usize,
+}
+
+impl Default for KeyEvents {
+  fn default() -> Self {
+    KeyEvents {
+      sender: broadcast::Sender::new(KEY_EVENT_CAPACITY),
+      db:     0,
+    }
+  }
+}
+
+impl KeyEvents {
+  /// Returns a receiver of every event emitted from now on.
+  pub fn subscribe(&self) -> broadcast::Receiver<KeyEvent> {
+    self.sender.subscribe()
+  }
+
+  /// Returns a clone which emits its events for database `db`, to the same
+  /// subscribers.
+  pub fn in_database(&self, db: usize) -> KeyEvents {
+    KeyEvents {
+      sender: self.sender.clone(),
+      db,
+    }
+  }
+
+  /// Sends an event to every subscriber.
+  pub fn emit(&self, key: &SmolStr, kind: KeyEventKind) {
+    if self.sender.receiver_count() > 0 {
+      let _ = self.sender.send(KeyEvent {
+        db: self.db,
+        key: key.clone(),
+        kind,
+      });
+    }
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use tokio::sync::broadcast::{self, error::TryRecvError};
+
+  use super::{KeyEvent, KeyEventKind};
+  use crate::backends::{
+    simple::SimpleBackend, Backend, BackendConfig, BackendExt,
+  };
+
+  fn drain(
+    events: &mut broadcast::Receiver<KeyEvent>,
+  ) -> Vec<(String, KeyEventKind)> {
+    let mut drained = Vec::new();
+    loop {
+      match events.try_recv() {
+        Ok(event) => drained.push((event.key.to_string(), event.kind)),
+        Err(TryRecvError::Empty) => return drained,
+        Err(e) => panic!("unexpected error: {e}"),
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn writes_emit_typed_events() {
+    use KeyEventKind::*;
+
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut events = backend.subscribe_events();
+
+    backend.SET("a", "1").await.unwrap();
+    backend.INCR("a").await.unwrap();
+    backend.INCR_BY("b", 2).await.unwrap();
+    // failed and no-op writes don't emit anything
+    backend.SET("s", "x").await.unwrap();
+    assert!(backend.INCR("s").await.is_err());
+    backend.DEL(vec!["missing".into()]).await.unwrap();
+    backend.GET_AND_DELETE("a").await.unwrap();
+
+    assert_eq!(drain(&mut events), vec![
+      ("a".into(), Create),
+      ("a".into(), Update),
+      ("b".into(), Create),
+      ("s".into(), Create),
+      ("a".into(), Delete),
+    ]);
+  }
+
+  #[cfg(feature = "sets")]
+  #[tokio::test]
+  async fn collections_only_emit_when_they_change() {
+    use KeyEventKind::*;
+
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut events = backend.subscribe_events();
+
+    backend.SADD("s", "x").await.unwrap();
+    backend.SADD("s", "x").await.unwrap();
+    backend.SADD("s", "y").await.unwrap();
+    backend.SREM("s", "missing").await.unwrap();
+    backend.SREM("s", "x").await.unwrap();
+    backend.SREM("s", "y").await.unwrap();
+
+    assert_eq!(drain(&mut events), vec![
+      ("s".into(), Create),
+      ("s".into(), Update),
+      ("s".into(), Update),
+      ("s".into(), Delete),
+    ]);
+  }
+
+  #[cfg(feature = "hashes")]
+  #[tokio::test]
+  async fn expired_fields_emit_expire_events() {
+    use KeyEventKind::*;
+
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut events = backend.subscribe_events();
+
+    backend.HSET("h", "a", 1).await.unwrap();
+    backend.HSET("h", "b", 2).await.unwrap();
+    backend.HPEXPIRE("h", 0, vec!["a".into()]).await.unwrap();
+    backend.HPEXPIRE("h", 0, vec!["b".into()]).await.unwrap();
+
+    // the hash is deleted by expiring its last field, not by `DEL`
+    assert_eq!(drain(&mut events), vec![
+      ("h".into(), Create),
+      ("h".into(), Update),
+      ("h".into(), Expire),
+      ("h".into(), Expire),
+    ]);
+    assert_eq!(backend.EXISTS(vec!["h".into()]).await.unwrap(), 0.into());
+  }
+}
@@ -0,0 +1,109 @@
+//! Defines the `HotKeys` item, a sampled top-K sketch of the most accessed
+//! keys.
+
+use std::{
+  collections::HashMap,
+  num::NonZeroU32,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+};
+
+use smol_str::SmolStr;
+
+/// The number of keys tracked by the sketch.
+pub const HOT_KEYS_CAPACITY: usize = 32;
+/// The default sampling rate: one in this many commands is sampled.
+pub const DEFAULT_HOT_KEYS_SAMPLE_RATE: NonZeroU32 = match NonZeroU32::new(8) {
+  Some(rate) => rate,
+  None => unreachable!(),
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+  /// The number of sampled hits attributed to the key.
+  count: u64,
+  /// The maximum amount by which `count` overestimates the true number of
+  /// sampled hits, inherited from the key this counter replaced.
+  error: u64,
+}
+
+/// A top-K sketch of the hottest keys, using the Space-Saving algorithm over
+/// a sample of commands.
+///
+/// Only one in every `sample_rate` commands is recorded, so that tracking adds
+/// negligible overhead to the dispatch path. Counts are scaled back up by the
+/// sample rate when reported.
+#[derive(Debug)]
+pub(crate) struct HotKeys {
+  sample_rate: u64,
+  seen:        AtomicU64,
+  counters:    Mutex<HashMap<SmolStr, Counter>>,
+}
+
+impl HotKeys {
+  /// Creates an empty sketch which samples one in every `sample_rate`
+  /// commands.
+  pub fn new(sample_rate: NonZeroU32) -> Self {
+    HotKeys {
+      sample_rate: sample_rate.get().into(),
+      seen:        AtomicU64::new(0),
+      counters:    Mutex::new(HashMap::with_capacity(HOT_KEYS_CAPACITY)),
+    }
+  }
+
+  /// Records an access to the given keys, if this command is sampled.
+  pub fn record<'a>(&self, keys: impl IntoIterator<Item = &'a SmolStr>) {
+    let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+    if !seen.is_multiple_of(self.sample_rate) {
+      return;
+    }
+
+    let mut counters = self.counters.lock().unwrap();
+    for key in keys {
+      if let Some(counter) = counters.get_mut(key) {
+        counter.count += 1;
+        continue;
+      }
+      if counters.len() < HOT_KEYS_CAPACITY {
+        counters.insert(key.clone(), Counter { count: 1, error: 0 });
+        continue;
+      }
+
+      // evict the coldest key; the newcomer inherits its count as error
+      let (coldest, min) = counters
+        .iter()
+        .min_by_key(|(_, c)| c.count)
+        .map(|(k, c)| (k.clone(), c.count))
+        .expect("sketch is full, so it is non-empty");
+      counters.remove(&coldest);
+      counters.insert(key.clone(), Counter {
+        count: min + 1,
+        error: min,
+      });
+    }
+  }
+
+  /// Returns the tracked keys and their estimated hit counts, hottest first.
+  /// The estimates are lower bounds, scaled up by the sampling rate.
+  pub fn top(&self) -> Vec<(SmolStr, u64)> {
+    let counters = self.counters.lock().unwrap();
+    let mut top = counters
+      .iter()
+      .map(|(k, c)| (k.clone(), c.count.saturating_sub(c.error)))
+      .collect::<Vec<_>>();
+    drop(counters);
+
+    top.sort_unstable_by(|(a_key, a), (b_key, b)| {
+      b.cmp(a).then_with(|| a_key.cmp(b_key))
+    });
+    for (_, count) in top.iter_mut() {
+      *count *= self.sample_rate;
+    }
+    top
+  }
+
+  /// Returns the sampling rate.
+  pub fn sample_rate(&self) -> u64 { self.sample_rate }
+}
@@ -0,0 +1,142 @@
+//! Defines the `Interner` item, which shares the allocations of small,
+//! frequently stored values.
+
+use std::{
+  collections::HashSet,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex, OnceLock,
+  },
+};
+
+use bytes::Bytes;
+
+use crate::value::StoredValue;
+#[cfg(feature = "hashes")]
+use crate::value::Value;
+
+/// Bulk strings up to this many bytes long are interned.
+pub const MAX_INTERNED_LEN: usize = 16;
+/// The maximum number of distinct strings the interner will hold. Once full,
+/// new strings are no longer interned, but existing ones are still shared.
+pub const INTERNED_STRINGS_CAPACITY: usize = 4096;
+/// Bulk strings holding the decimal integers `0..SHARED_INTEGERS` always
+/// share one of a set of preallocated strings.
+pub const SHARED_INTEGERS: usize = 10_000;
+
+/// The preallocated strings for small integers.
+fn shared_integers() -> &'static [Bytes] {
+  static SHARED: OnceLock<Vec<Bytes>> = OnceLock::new();
+  SHARED.get_or_init(|| {
+    (0..SHARED_INTEGERS)
+      .map(|i| Bytes::from(i.to_string()))
+      .collect()
+  })
+}
+
+/// Returns the preallocated string for `bytes`, if it holds a small integer
+/// in canonical form (no sign or leading zeroes).
+fn shared_integer(bytes: &[u8]) -> Option<Bytes> {
+  if !bytes.iter().all(u8::is_ascii_digit)
+    || (bytes.len() > 1 && bytes[0] == b'0')
+  {
+    return None;
+  }
+  let i = std::str::from_utf8(bytes).ok()?.parse::<usize>().ok()?;
+  shared_integers().get(i).cloned()
+}
+
+/// A table of shared bulk strings.
+///
+/// Bulk strings read off the wire are views into the connection's read
+/// buffer, so storing many small ones pins many buffers and pays an
+/// allocation each. Interning them means that millions of identical values
+/// (like `"0"`/`"1"` flags) share one allocation instead.
+#[derive(Debug, Default)]
+pub(crate) struct Interner {
+  strings:     Mutex<HashSet<Bytes>>,
+  /// The number of values which were replaced with a shared string.
+  hits:        AtomicU64,
+  /// The total number of bytes not allocated thanks to sharing.
+  bytes_saved: AtomicU64,
+}
+
+impl Interner {
+  /// Returns a shared copy of `bytes` if it's short enough to be interned.
+  pub fn intern(&self, bytes: Bytes) -> Bytes {
+    if bytes.is_empty() || bytes.len() > MAX_INTERNED_LEN {
+      return bytes;
+    }
+
+    let shared = shared_integer(&bytes)
+      .or_else(|| self.strings.lock().unwrap().get(&bytes).cloned());
+    if let Some(shared) = shared {
+      self.hits.fetch_add(1, Ordering::Relaxed);
+      self
+        .bytes_saved
+        .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+      return shared;
+    }
+
+    let mut strings = self.strings.lock().unwrap();
+    if strings.len() >= INTERNED_STRINGS_CAPACITY {
+      return bytes;
+    }
+    let owned = Bytes::copy_from_slice(&bytes);
+    strings.insert(owned.clone());
+    owned
+  }
+
+  /// Interns the value if it's a bulk string.
+  #[cfg(feature = "hashes")]
+  pub fn intern_value(&self, value: Value) -> Value {
+    match value {
+      Value::BulkString(b) => Value::BulkString(self.intern(b)),
+      value => value,
+    }
+  }
+
+  /// Interns the stored value if it's a bulk string.
+  pub fn intern_stored_value(&self, value: StoredValue) -> StoredValue {
+    match value {
+      StoredValue::BulkString(b) => StoredValue::BulkString(self.intern(b)),
+      value => value,
+    }
+  }
+
+  /// The number of values which were replaced with a shared string.
+  pub fn hits(&self) -> u64 { self.hits.load(Ordering::Relaxed) }
+
+  /// The total number of bytes not allocated thanks to sharing.
+  pub fn bytes_saved(&self) -> u64 { self.bytes_saved.load(Ordering::Relaxed) }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::Bytes;
+
+  use super::Interner;
+
+  #[test]
+  fn identical_strings_share_allocations() {
+    let interner = Interner::default();
+
+    let a = interner.intern(Bytes::copy_from_slice(b"flag"));
+    let b = interner.intern(Bytes::copy_from_slice(b"flag"));
+    assert_eq!(a.as_ptr(), b.as_ptr());
+
+    let one = interner.intern(Bytes::copy_from_slice(b"1"));
+    let other_one = interner.intern(Bytes::copy_from_slice(b"1"));
+    assert_eq!(one.as_ptr(), other_one.as_ptr());
+
+    // non-canonical integers are interned as plain strings
+    let padded = interner.intern(Bytes::copy_from_slice(b"01"));
+    assert_ne!(padded.as_ptr(), one.as_ptr());
+
+    let long = Bytes::from(vec![b'x'; 64]);
+    assert_eq!(interner.intern(long.clone()).as_ptr(), long.as_ptr());
+
+    assert_eq!(interner.hits(), 3);
+    assert_eq!(interner.bytes_saved(), 6);
+  }
+}
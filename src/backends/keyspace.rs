@@ -0,0 +1,775 @@
+//! Defines the `Keyspace` item, a key-value map which keeps an approximate
+//! count of the memory it uses.
+
+use std::{
+  collections::{hash_map, BTreeSet, HashMap, HashSet},
+  hash::{BuildHasher, RandomState},
+  mem::size_of,
+  sync::Arc,
+};
+
+use smol_str::SmolStr;
+
+#[cfg(feature = "hashes")]
+use crate::value::field_size;
+use crate::{
+  backends::events::{KeyEventKind, KeyEvents},
+  value::{str_size, Dump, StoredValue},
+};
+
+/// The fixed cost of a keyspace entry, on top of its key and value contents,
+/// including its place in the scan order. The value's inline size is counted
+/// by [`StoredValue::approximate_size()`].
+const ENTRY_OVERHEAD: usize = size_of::<SmolStr>() + size_of::<Entry>()
+  - size_of::<StoredValue>()
+  + size_of::<(u64, SmolStr)>();
+
+fn entry_size(key: &SmolStr, value: &StoredValue) -> usize {
+  ENTRY_OVERHEAD + str_size(key) + value.approximate_size()
+}
+
+/// Where `key` is in the scan order. Positions are odd, so that no key is at
+/// the cursor `0`, which starts and ends a scan.
+fn scan_position(hasher: &RandomState, key: &str) -> u64 {
+  hasher.hash_one(key) | 1
+}
+
+#[derive(Clone)]
+struct Entry {
+  value: StoredValue,
+  /// The cached result of [`entry_size()`] for this entry.
+  size:  usize,
+}
+
+/// A map from keys to [`StoredValue`]s which keeps an approximate count of
+/// the memory used by its keys and values.
+///
+/// The count is updated on every write, so reading it is O(1). Writes which
+/// mutate values in place must go through [`Keyspace::modify`] or
+/// [`Keyspace::modify_collection`] so that the count stays accurate.
+///
+/// The keyspace also tracks the deadlines of keys and hash fields with a time
+/// to live. Expired keys and fields aren't removed until
+/// [`Keyspace::expire`] or [`Keyspace::expire_fields`] is called for their
+/// key, which backends do before running a command on it, so that commands
+/// treat them as missing.
+///
+/// Inserting, removing, and expiring keys emits [`KeyEvents`]. Mutations in
+/// place don't know whether they changed anything, so callers report them
+/// with [`Keyspace::notify_write`].
+///
+/// Keys are also kept ordered by a hash of their name, which never changes
+/// while they exist, so that [`Keyspace::scan`] can resume from a cursor no
+/// matter how the map has been resized in the meantime.
+///
+/// [`Keyspace::snapshot`] takes a read-only copy in constant time. The copy
+/// shares the keyspace's maps, and whichever side is written first copies
+/// them for itself.
+#[derive(Default)]
+pub(crate) struct Keyspace {
+  entries:         Arc<HashMap<SmolStr, Entry>>,
+  used_memory:     usize,
+  events:          KeyEvents,
+  /// Every key, by its [`scan_position()`].
+  scan_order:      Arc<BTreeSet<(u64, SmolStr)>>,
+  /// Hashes keys into their scan positions. Seeded randomly, so that keys
+  /// can't be chosen to collide.
+  scan_hasher:     RandomState,
+  /// The deadlines of keys, as unix times in milliseconds. Keys without one
+  /// are absent.
+  deadlines:       Arc<HashMap<SmolStr, u64>>,
+  /// The deadlines of hash fields, as unix times in milliseconds, by key and
+  /// then field. Keys without any are absent.
+  #[cfg(feature = "hashes")]
+  field_deadlines: Arc<HashMap<SmolStr, HashMap<SmolStr, u64>>>,
+  /// The keys written or deleted since they were last taken, if they're
+  /// being tracked. See [`Keyspace::track_dirty_keys()`].
+  dirty:           Option<HashSet<SmolStr>>,
+}
+
+impl Keyspace {
+  /// Creates an empty keyspace which emits its events to `events`.
+  pub fn with_events(events: KeyEvents) -> Self {
+    Keyspace {
+      events,
+      ..Keyspace::default()
+    }
+  }
+
+  /// Takes a copy of the keyspace as it is now, for reading consistently
+  /// without holding its lock. The copy doesn't emit events or track dirty
+  /// keys.
+  pub fn snapshot(&self) -> Keyspace {
+    Keyspace {
+      entries: self.entries.clone(),
+      used_memory: self.used_memory,
+      events: KeyEvents::default(),
+      scan_order: self.scan_order.clone(),
+      scan_hasher: self.scan_hasher.clone(),
+      deadlines: self.deadlines.clone(),
+      #[cfg(feature = "hashes")]
+      field_deadlines: self.field_deadlines.clone(),
+      dirty: None,
+    }
+  }
+
+  /// Starts tracking which keys are written or deleted, so that a snapshot
+  /// can be saved as a delta of just those keys. See
+  /// [`Keyspace::take_dirty_keys()`].
+  pub fn track_dirty_keys(&mut self) { self.dirty.get_or_insert_default(); }
+
+  /// Takes the keys written or deleted since the last call, or since
+  /// tracking started. Empty if they aren't being tracked.
+  pub fn take_dirty_keys(&mut self) -> HashSet<SmolStr> {
+    self.dirty.as_mut().map(std::mem::take).unwrap_or_default()
+  }
+
+  /// Marks `keys` as written again, e.g. after saving them failed.
+  pub fn mark_dirty_keys(&mut self, keys: HashSet<SmolStr>) {
+    if let Some(dirty) = &mut self.dirty {
+      dirty.extend(keys);
+    }
+  }
+
+  /// Returns the number of keys written or deleted since the dirty keys were
+  /// last taken.
+  pub fn dirty_len(&self) -> usize {
+    self.dirty.as_ref().map_or(0, HashSet::len)
+  }
+
+  fn mark_dirty(&mut self, key: &SmolStr) {
+    if let Some(dirty) = &mut self.dirty {
+      if !dirty.contains(key) {
+        dirty.insert(key.clone());
+      }
+    }
+  }
+
+  /// Returns whether any hash has fields whose deadlines are at or before
+  /// `now` (a unix time in milliseconds).
+  #[cfg(feature = "hashes")]
+  pub fn has_any_expired_fields(&self, now: u64) -> bool {
+    self
+      .field_deadlines
+      .values()
+      .any(|deadlines| deadlines.values().any(|&d| d <= now))
+  }
+
+  /// Returns whether any key, or any hash field, has a deadline at or before
+  /// `now` (a unix time in milliseconds).
+  pub fn has_any_expired(&self, now: u64) -> bool {
+    #[cfg(feature = "hashes")]
+    if self.has_any_expired_fields(now) {
+      return true;
+    }
+    self.deadlines.values().any(|&d| d <= now)
+  }
+
+  /// Returns whether `key`, or any of its hash fields, has a deadline at or
+  /// before `now` (a unix time in milliseconds).
+  pub fn has_expired(&self, key: &str, now: u64) -> bool {
+    #[cfg(feature = "hashes")]
+    if self.has_expired_fields(key, now) {
+      return true;
+    }
+    self.deadline(key).is_some_and(|d| d <= now)
+  }
+
+  /// Returns whether expiring `key` at `now` (a unix time in milliseconds)
+  /// would remove it: its deadline has passed, or it's a hash whose fields
+  /// have all expired.
+  pub fn is_expired(&self, key: &str, now: u64) -> bool {
+    if self.deadline(key).is_some_and(|d| d <= now) {
+      return true;
+    }
+    #[cfg(feature = "hashes")]
+    if let (Some(deadlines), Some(StoredValue::Map(h))) =
+      (self.field_deadlines.get(key), self.get(key))
+    {
+      return h
+        .keys()
+        .all(|field| deadlines.get(field).is_some_and(|&d| d <= now));
+    }
+    false
+  }
+
+  /// Takes a copy of just the entries at `keys`, for reading them without
+  /// changing the keyspace. The copy doesn't emit events, and only counts the
+  /// memory of the entries it has.
+  pub fn copy_of<'a>(
+    &self,
+    keys: impl IntoIterator<Item = &'a SmolStr>,
+  ) -> Keyspace {
+    let mut copy = Keyspace {
+      scan_hasher: self.scan_hasher.clone(),
+      ..Keyspace::default()
+    };
+    for key in keys {
+      if let Some(value) = self.get(key) {
+        copy.insert(key.clone(), value.clone());
+      }
+      if let Some(deadline) = self.deadline(key) {
+        copy.set_deadline(key, Some(deadline));
+      }
+      #[cfg(feature = "hashes")]
+      if let Some(deadlines) = self.field_deadlines.get(key) {
+        Arc::make_mut(&mut copy.field_deadlines)
+          .insert(key.clone(), deadlines.clone());
+      }
+    }
+    copy
+  }
+
+  /// Removes `key`'s deadline and its field deadlines, if it has any.
+  fn remove_deadlines(&mut self, key: &str) {
+    // checked first so that a shared map isn't copied for nothing
+    if self.deadlines.contains_key(key) {
+      Arc::make_mut(&mut self.deadlines).remove(key);
+    }
+    #[cfg(feature = "hashes")]
+    self.remove_field_deadlines(key);
+  }
+
+  /// Removes `key`'s field deadlines, if it has any.
+  #[cfg(feature = "hashes")]
+  fn remove_field_deadlines(&mut self, key: &str) {
+    // checked first so that a shared map isn't copied for nothing
+    if self.field_deadlines.contains_key(key) {
+      Arc::make_mut(&mut self.field_deadlines).remove(key);
+    }
+  }
+
+  /// Emits a [`KeyEventKind::Create`] or [`KeyEventKind::Update`] event for
+  /// a write to `key`, depending on whether it `existed` beforehand.
+  pub fn notify_write(&self, key: &SmolStr, existed: bool) {
+    let kind = if existed {
+      KeyEventKind::Update
+    } else {
+      KeyEventKind::Create
+    };
+    self.events.emit(key, kind);
+  }
+
+  /// The approximate number of bytes used by the keys and values.
+  pub fn used_memory(&self) -> usize { self.used_memory }
+
+  /// The number of keys.
+  pub fn len(&self) -> usize { self.entries.len() }
+
+  /// The approximate number of bytes used by the entry at `key`, including
+  /// the key itself.
+  pub fn entry_size(&self, key: &str) -> Option<usize> {
+    self.entries.get(key).map(|e| e.size)
+  }
+
+  /// Gets the value at `key`.
+  pub fn get(&self, key: &str) -> Option<&StoredValue> {
+    self.entries.get(key).map(|e| &e.value)
+  }
+
+  /// Returns whether `key` exists.
+  pub fn contains_key(&self, key: &str) -> bool {
+    self.entries.contains_key(key)
+  }
+
+  /// Iterates over all keys, their values, and their sizes (see
+  /// [`Keyspace::entry_size`]), in arbitrary order.
+  pub fn iter(&self) -> impl Iterator<Item = (&SmolStr, &StoredValue, usize)> {
+    self.entries.iter().map(|(k, e)| (k, &e.value, e.size))
+  }
+
+  /// Returns whether the value at `key`, or any part of it, has a time to
+  /// live.
+  pub fn has_ttl(&self, key: &str) -> bool {
+    #[cfg(feature = "hashes")]
+    if self.field_deadlines.contains_key(key) {
+      return true;
+    }
+    self.deadlines.contains_key(key)
+  }
+
+  /// Iterates over all keys, in arbitrary order.
+  pub fn keys(&self) -> impl Iterator<Item = &SmolStr> { self.entries.keys() }
+
+  /// Returns about `count` keys after `cursor` in the scan order, and the
+  /// cursor to continue from, which is `0` once every key has been returned.
+  ///
+  /// A key which exists for the whole scan is returned exactly once, however
+  /// the keyspace changes between calls. Keys which are added or removed
+  /// during the scan are returned at most once. Keys at the same position
+  /// are always returned together, so more than `count` keys may be.
+  pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&SmolStr>) {
+    let Some(start) = cursor.checked_add(1) else {
+      return (0, Vec::new());
+    };
+    let mut keys = Vec::with_capacity(count);
+    let mut last = 0;
+    for (position, key) in self.scan_order.range((start, SmolStr::default())..)
+    {
+      if keys.len() >= count && *position != last {
+        return (last, keys);
+      }
+      keys.push(key);
+      last = *position;
+    }
+    (0, keys)
+  }
+
+  /// Inserts `value` at `key`, returning the previous value. Like Redis, the
+  /// key loses any time to live the previous value had.
+  pub fn insert(
+    &mut self,
+    key: SmolStr,
+    value: StoredValue,
+  ) -> Option<StoredValue> {
+    self.remove_deadlines(&key);
+    self.mark_dirty(&key);
+    let size = entry_size(&key, &value);
+    self.used_memory += size;
+    let old = Arc::make_mut(&mut self.entries)
+      .insert(key.clone(), Entry { value, size });
+    self.notify_write(&key, old.is_some());
+    if old.is_none() {
+      let position = scan_position(&self.scan_hasher, &key);
+      Arc::make_mut(&mut self.scan_order).insert((position, key));
+    }
+    let old = old?;
+    self.used_memory -= old.size;
+    Some(old.value)
+  }
+
+  /// Removes `key`, returning its value.
+  pub fn remove(&mut self, key: &str) -> Option<StoredValue> {
+    let (key, value) = self.take(key)?;
+    self.events.emit(&key, KeyEventKind::Delete);
+    Some(value)
+  }
+
+  /// Removes `key` without emitting an event.
+  fn take(&mut self, key: &str) -> Option<(SmolStr, StoredValue)> {
+    if !self.entries.contains_key(key) {
+      return None;
+    }
+    self.remove_deadlines(key);
+    let (key, old) = Arc::make_mut(&mut self.entries).remove_entry(key)?;
+    self.mark_dirty(&key);
+    self.used_memory -= old.size;
+    let position = scan_position(&self.scan_hasher, &key);
+    Arc::make_mut(&mut self.scan_order).remove(&(position, key.clone()));
+    Some((key, old.value))
+  }
+
+  /// Serializes the value at `key` along with its deadline and field
+  /// deadlines, for `DUMP` and snapshots.
+  pub fn dump(&self, key: &str) -> Option<Dump> {
+    let value = self.get(key)?.clone();
+    #[cfg(feature = "hashes")]
+    let field_deadlines =
+      self
+        .field_deadlines
+        .get(key)
+        .map_or_else(Vec::new, |deadlines| {
+          let mut deadlines = deadlines
+            .iter()
+            .map(|(field, &deadline)| (field.clone(), deadline))
+            .collect::<Vec<_>>();
+          deadlines.sort_unstable();
+          deadlines
+        });
+    #[cfg(not(feature = "hashes"))]
+    let field_deadlines = Vec::new();
+    Some(Dump {
+      value,
+      deadline: self.deadline(key),
+      field_deadlines,
+    })
+  }
+
+  /// Inserts `value` at `key` with the `deadline` and field deadlines of a
+  /// [`Dump`], for `RESTORE` and loading snapshots. Deadlines of fields the
+  /// value doesn't have are ignored.
+  pub fn restore(
+    &mut self,
+    key: SmolStr,
+    value: StoredValue,
+    deadline: Option<u64>,
+    field_deadlines: Vec<(SmolStr, u64)>,
+  ) {
+    #[cfg(feature = "hashes")]
+    let fields = match &value {
+      StoredValue::Map(map) => field_deadlines
+        .into_iter()
+        .filter(|(field, _)| map.contains_key(field))
+        .collect(),
+      _ => Vec::new(),
+    };
+    self.insert(key.clone(), value);
+    if deadline.is_some() {
+      self.set_deadline(&key, deadline);
+    }
+    #[cfg(feature = "hashes")]
+    for (field, deadline) in fields {
+      self.set_field_deadline(&key, &field, Some(deadline));
+    }
+    #[cfg(not(feature = "hashes"))]
+    let _ = field_deadlines;
+  }
+
+  /// Sets a key with an optional value. If `value` is `Some()`, inserts the
+  /// value. If `None`, deletes the previous value if it existed.
+  pub fn set(&mut self, key: SmolStr, value: Option<StoredValue>) {
+    match value {
+      Some(v) => {
+        self.insert(key, v);
+      }
+      None => {
+        self.remove(&key);
+      }
+    }
+  }
+
+  fn entry_or_insert_with(
+    &mut self,
+    key: SmolStr,
+    default: impl FnOnce() -> StoredValue,
+  ) -> &mut Entry {
+    self.mark_dirty(&key);
+    match Arc::make_mut(&mut self.entries).entry(key) {
+      hash_map::Entry::Occupied(o) => o.into_mut(),
+      hash_map::Entry::Vacant(v) => {
+        let value = default();
+        let size = entry_size(v.key(), &value);
+        self.used_memory += size;
+        let position = scan_position(&self.scan_hasher, v.key());
+        Arc::make_mut(&mut self.scan_order).insert((position, v.key().clone()));
+        v.insert(Entry { value, size })
+      }
+    }
+  }
+
+  /// Mutates the value at `key` in place, inserting `default()` first if the
+  /// key doesn't exist. No event is emitted. The entry's size is recomputed
+  /// afterwards, which is linear in the size of the value, so this is
+  /// intended for scalar values.
+  pub fn modify<R>(
+    &mut self,
+    key: SmolStr,
+    default: impl FnOnce() -> StoredValue,
+    f: impl FnOnce(&mut StoredValue) -> R,
+  ) -> R {
+    let key_size = ENTRY_OVERHEAD + str_size(&key);
+    let entry = self.entry_or_insert_with(key, default);
+    let old_size = entry.size;
+
+    let result = f(&mut entry.value);
+    entry.size = key_size + entry.value.approximate_size();
+
+    let new_size = entry.size;
+    self.used_memory = self.used_memory - old_size + new_size;
+    result
+  }
+
+  /// Mutates the collection at `key` in place, inserting `default()` first if
+  /// the key doesn't exist. No event is emitted. `f` must return the change in
+  /// the value's approximate size (as computed by
+  /// [`StoredValue::approximate_size()`]) alongside its result, so that
+  /// updates stay independent of the size of the collection.
+  #[cfg(any(feature = "hashes", feature = "sets"))]
+  pub fn modify_collection<R>(
+    &mut self,
+    key: SmolStr,
+    default: impl FnOnce() -> StoredValue,
+    f: impl FnOnce(&mut StoredValue) -> (R, isize),
+  ) -> R {
+    let entry = self.entry_or_insert_with(key, default);
+
+    let (result, delta) = f(&mut entry.value);
+    entry.size = entry.size.saturating_add_signed(delta);
+
+    self.used_memory = self.used_memory.saturating_add_signed(delta);
+    result
+  }
+
+  /// Returns the deadline of `key`, as a unix time in milliseconds, if it has
+  /// a time to live.
+  pub fn deadline(&self, key: &str) -> Option<u64> {
+    self.deadlines.get(key).copied()
+  }
+
+  /// Sets the deadline of `key`, or removes its time to live if `deadline` is
+  /// `None`. Returns the previous deadline.
+  ///
+  /// The key must exist; deadlines aren't counted in the memory usage.
+  pub fn set_deadline(
+    &mut self,
+    key: &SmolStr,
+    deadline: Option<u64>,
+  ) -> Option<u64> {
+    self.mark_dirty(key);
+    match deadline {
+      Some(deadline) => {
+        Arc::make_mut(&mut self.deadlines).insert(key.clone(), deadline)
+      }
+      // checked first so that a shared map isn't copied for nothing
+      None if self.deadlines.contains_key(key.as_str()) => {
+        Arc::make_mut(&mut self.deadlines).remove(key.as_str())
+      }
+      None => None,
+    }
+  }
+
+  /// Removes `key` if its deadline is at or before `now` (a unix time in
+  /// milliseconds), emitting a [`KeyEventKind::Expire`] event rather than a
+  /// deletion. Returns whether it was removed.
+  pub fn expire(&mut self, key: &str, now: u64) -> bool {
+    if self.deadline(key).is_none_or(|d| d > now) {
+      return false;
+    }
+    let Some((key, _)) = self.take(key) else {
+      return false;
+    };
+    self.events.emit(&key, KeyEventKind::Expire);
+    true
+  }
+
+  /// Removes every key whose deadline has passed. See [`Keyspace::expire`].
+  /// Returns the keys removed.
+  pub fn expire_all(&mut self, now: u64) -> Vec<SmolStr> {
+    let keys = self
+      .deadlines
+      .iter()
+      .filter(|(_, &deadline)| deadline <= now)
+      .map(|(key, _)| key.clone())
+      .collect::<Vec<_>>();
+    keys
+      .into_iter()
+      .filter(|key| self.expire(key, now))
+      .collect()
+  }
+
+  /// Returns the deadline of `field` in the hash at `key`, as a unix time in
+  /// milliseconds, if it has a time to live.
+  #[cfg(feature = "hashes")]
+  pub fn field_deadline(&self, key: &str, field: &str) -> Option<u64> {
+    self.field_deadlines.get(key)?.get(field).copied()
+  }
+
+  /// Sets the deadline of `field` in the hash at `key`, or removes its time
+  /// to live if `deadline` is `None`. Returns the previous deadline.
+  ///
+  /// The field must exist; deadlines aren't counted in the memory usage.
+  #[cfg(feature = "hashes")]
+  pub fn set_field_deadline(
+    &mut self,
+    key: &SmolStr,
+    field: &SmolStr,
+    deadline: Option<u64>,
+  ) -> Option<u64> {
+    self.mark_dirty(key);
+    match deadline {
+      Some(deadline) => Arc::make_mut(&mut self.field_deadlines)
+        .entry(key.clone())
+        .or_default()
+        .insert(field.clone(), deadline),
+      None => {
+        if !self.field_deadlines.contains_key(key.as_str()) {
+          return None;
+        }
+        let field_deadlines = Arc::make_mut(&mut self.field_deadlines);
+        let deadlines = field_deadlines.get_mut(key.as_str())?;
+        let old = deadlines.remove(field.as_str());
+        if deadlines.is_empty() {
+          field_deadlines.remove(key.as_str());
+        }
+        old
+      }
+    }
+  }
+
+  /// Returns whether the hash at `key` has fields whose deadlines are at or
+  /// before `now` (a unix time in milliseconds).
+  #[cfg(feature = "hashes")]
+  pub fn has_expired_fields(&self, key: &str, now: u64) -> bool {
+    self
+      .field_deadlines
+      .get(key)
+      .is_some_and(|deadlines| deadlines.values().any(|&d| d <= now))
+  }
+
+  /// Removes the fields of the hash at `key` whose deadlines are at or
+  /// before `now` (a unix time in milliseconds), deleting the key if none
+  /// are left. Returns the fields removed.
+  #[cfg(feature = "hashes")]
+  pub fn expire_fields(&mut self, key: &str, now: u64) -> Vec<SmolStr> {
+    // checked first so that shared maps aren't copied for nothing
+    if !self.has_expired_fields(key, now) {
+      return Vec::new();
+    }
+    let field_deadlines = Arc::make_mut(&mut self.field_deadlines);
+    let Some(deadlines) = field_deadlines.get_mut(key) else {
+      return Vec::new();
+    };
+    let mut expired = Vec::new();
+    deadlines.retain(|field, deadline| {
+      let live = *deadline > now;
+      if !live {
+        expired.push(field.clone());
+      }
+      live
+    });
+    if deadlines.is_empty() {
+      field_deadlines.remove(key);
+    }
+
+    let Some(entry) = Arc::make_mut(&mut self.entries).get_mut(key) else {
+      return Vec::new();
+    };
+    let StoredValue::Map(h) = &mut entry.value else {
+      return Vec::new();
+    };
+    let mut freed = 0;
+    for field in &expired {
+      if let Some(value) = h.remove(field) {
+        freed += field_size(field, &value);
+      }
+    }
+    entry.size -= freed;
+    self.used_memory -= freed;
+    if h.is_empty() {
+      self.take(key);
+    }
+    if !expired.is_empty() {
+      let key = SmolStr::new(key);
+      self.mark_dirty(&key);
+      self.events.emit(&key, KeyEventKind::Expire);
+    }
+    expired
+  }
+
+  /// Removes the expired fields of every hash. See
+  /// [`Keyspace::expire_fields`]. Returns the fields removed, by key.
+  #[cfg(feature = "hashes")]
+  pub fn expire_all_fields(
+    &mut self,
+    now: u64,
+  ) -> Vec<(SmolStr, Vec<SmolStr>)> {
+    let keys = self
+      .field_deadlines
+      .iter()
+      .filter(|(_, deadlines)| deadlines.values().any(|&d| d <= now))
+      .map(|(key, _)| key.clone())
+      .collect::<Vec<_>>();
+    keys
+      .into_iter()
+      .map(|key| {
+        let fields = self.expire_fields(&key, now);
+        (key, fields)
+      })
+      .collect()
+  }
+
+  /// Whether the entries are shared with a [snapshot](Keyspace::snapshot),
+  /// so that changing any of them copies them all first.
+  pub fn is_shared(&self) -> bool { Arc::strong_count(&self.entries) > 1 }
+
+  /// Iterates over all values mutably, for operations which don't change
+  /// their approximate size (like shrinking allocations). Copies the entries
+  /// if they're [shared](Keyspace::is_shared).
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut StoredValue> {
+    Arc::make_mut(&mut self.entries)
+      .values_mut()
+      .map(|e| &mut e.value)
+  }
+
+  /// The number of entries the keyspace can hold without reallocating.
+  pub fn capacity(&self) -> usize { self.entries.capacity() }
+
+  /// Shrinks the keyspace's allocation as much as possible.
+  pub fn shrink_to_fit(&mut self) {
+    Arc::make_mut(&mut self.entries).shrink_to_fit()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use smol_str::SmolStr;
+
+  use super::Keyspace;
+  use crate::value::StoredValue;
+
+  #[test]
+  fn snapshots_are_unaffected_by_writes() {
+    let mut keyspace = Keyspace::default();
+    keyspace.insert("a".into(), StoredValue::Integer(1));
+    let snapshot = keyspace.snapshot();
+
+    keyspace.insert("a".into(), StoredValue::Integer(2));
+    keyspace.insert("b".into(), StoredValue::Integer(3));
+    assert!(matches!(snapshot.get("a"), Some(StoredValue::Integer(1))));
+    assert!(!snapshot.contains_key("b"));
+    assert_eq!(snapshot.scan(0, 10), (0, vec![&"a".into()]));
+    assert_eq!(keyspace.len(), 2);
+  }
+
+  #[test]
+  fn entries_are_shared_until_snapshots_are_dropped() {
+    let mut keyspace = Keyspace::default();
+    keyspace.insert("a".into(), StoredValue::Integer(1));
+    assert!(!keyspace.is_shared());
+
+    let snapshot = keyspace.snapshot();
+    assert!(keyspace.is_shared());
+    drop(snapshot);
+    assert!(!keyspace.is_shared());
+  }
+
+  #[test]
+  fn written_and_deleted_keys_are_dirty() {
+    let mut keyspace = Keyspace::default();
+    keyspace.insert("untracked".into(), StoredValue::Integer(1));
+    assert!(keyspace.take_dirty_keys().is_empty());
+
+    keyspace.track_dirty_keys();
+    keyspace.insert("a".into(), StoredValue::Integer(1));
+    keyspace.modify("b".into(), || StoredValue::Integer(0), |_| ());
+    keyspace.remove("untracked");
+    keyspace.remove("missing");
+    assert_eq!(keyspace.dirty_len(), 3);
+    let dirty = keyspace.take_dirty_keys();
+    assert_eq!(dirty, ["a", "b", "untracked"].map(SmolStr::from).into());
+    assert_eq!(keyspace.dirty_len(), 0);
+
+    keyspace.mark_dirty_keys(dirty);
+    assert_eq!(keyspace.dirty_len(), 3);
+  }
+
+  #[test]
+  fn keys_expire_once_their_deadline_passes() {
+    let mut keyspace = Keyspace::default();
+    for key in ["a", "b", "c"] {
+      keyspace.insert(key.into(), StoredValue::Integer(1));
+    }
+    keyspace.set_deadline(&"a".into(), Some(100));
+    keyspace.set_deadline(&"b".into(), Some(200));
+    keyspace.set_deadline(&"c".into(), Some(100));
+    // overwriting a key removes its time to live
+    keyspace.insert("c".into(), StoredValue::Integer(2));
+    assert_eq!(keyspace.deadline("c"), None);
+
+    assert!(!keyspace.has_expired("a", 99));
+    assert!(!keyspace.is_expired("a", 99));
+    assert!(keyspace.is_expired("a", 100));
+    assert!(!keyspace.expire("a", 99));
+    assert!(keyspace.has_any_expired(100));
+    let snapshot = keyspace.snapshot();
+    assert_eq!(keyspace.expire_all(100), vec![SmolStr::from("a")]);
+    assert!(!keyspace.contains_key("a"));
+    assert_eq!(keyspace.deadline("a"), None);
+    assert!(keyspace.expire("b", 250));
+    assert_eq!(keyspace.len(), 1);
+
+    // the snapshot keeps the keys and their deadlines
+    assert_eq!(snapshot.deadline("b"), Some(200));
+    assert!(snapshot.contains_key("a"));
+  }
+}
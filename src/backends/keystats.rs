@@ -0,0 +1,109 @@
+//! Defines the `KeyStats` item, a summary of the keyspace reported by `DEBUG
+//! KEYSTATS`, like an in-server `redis-cli --bigkeys`.
+
+use std::collections::BTreeMap;
+
+use smol_str::SmolStr;
+
+use crate::value::{StoredValue, Value};
+
+/// The upper bounds (exclusive) of the size buckets, in bytes, and their
+/// labels. Larger keys fall into a final, unbounded bucket.
+const SIZE_BUCKETS: &[(usize, &str)] = &[
+  (64, "<64B"),
+  (256, "<256B"),
+  (1 << 10, "<1KB"),
+  (4 << 10, "<4KB"),
+  (16 << 10, "<16KB"),
+  (64 << 10, "<64KB"),
+  (256 << 10, "<256KB"),
+  (1 << 20, "<1MB"),
+];
+const LARGEST_SIZE_BUCKET: &str = ">=1MB";
+
+/// Statistics gathered over (a sample of) the keyspace.
+#[derive(Debug, Default)]
+pub(crate) struct KeyStats {
+  keys:     u64,
+  with_ttl: u64,
+  types:    BTreeMap<&'static str, u64>,
+  sizes:    BTreeMap<&'static str, u64>,
+  /// The biggest key of each type, and its size.
+  biggest:  BTreeMap<&'static str, (SmolStr, usize)>,
+}
+
+impl KeyStats {
+  /// Records a key, its value, and its size (as reported by `MEMORY USAGE`),
+  /// and whether it (or part of it) has a time to live.
+  pub fn record(
+    &mut self,
+    key: &SmolStr,
+    value: &StoredValue,
+    size: usize,
+    has_ttl: bool,
+  ) {
+    let type_name = value.type_name();
+    self.keys += 1;
+    self.with_ttl += u64::from(has_ttl);
+    *self.types.entry(type_name).or_default() += 1;
+
+    let bucket = SIZE_BUCKETS
+      .iter()
+      .find(|(bound, _)| size < *bound)
+      .map_or(LARGEST_SIZE_BUCKET, |(_, label)| label);
+    *self.sizes.entry(bucket).or_default() += 1;
+
+    let biggest = self
+      .biggest
+      .entry(type_name)
+      .or_insert_with(|| (key.clone(), size));
+    if size > biggest.1 {
+      *biggest = (key.clone(), size);
+    }
+  }
+
+  /// Describes the statistics as a `DEBUG KEYSTATS` reply.
+  pub fn to_value(&self) -> Value {
+    let counts = |counts: &BTreeMap<&'static str, u64>| {
+      Value::Map(
+        counts
+          .iter()
+          .map(|(name, count)| ((*name).into(), Value::Integer(*count as i64)))
+          .collect(),
+      )
+    };
+    // buckets are listed smallest first, rather than by label
+    let sizes = SIZE_BUCKETS
+      .iter()
+      .map(|(_, label)| *label)
+      .chain([LARGEST_SIZE_BUCKET])
+      .map(|label| {
+        Value::Array(vec![
+          Value::SimpleString(label.into()),
+          Value::Integer(self.sizes.get(label).copied().unwrap_or(0) as i64),
+        ])
+      })
+      .collect();
+    let biggest = self
+      .biggest
+      .iter()
+      .map(|(type_name, (key, size))| {
+        (
+          (*type_name).into(),
+          Value::Array(vec![
+            Value::BulkString(key.as_bytes().to_vec().into()),
+            Value::Integer(*size as i64),
+          ]),
+        )
+      })
+      .collect();
+
+    Value::Map(BTreeMap::from([
+      ("keys".into(), Value::Integer(self.keys as i64)),
+      ("keys_with_ttl".into(), Value::Integer(self.with_ttl as i64)),
+      ("types".into(), counts(&self.types)),
+      ("sizes".into(), Value::Array(sizes)),
+      ("biggest".into(), Value::Map(biggest)),
+    ]))
+  }
+}
@@ -1,24 +1,408 @@
 //! Defines the `Backend` trait and contains its implementors.
 
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod events;
+#[cfg(feature = "simple")]
+mod hotkeys;
+#[cfg(feature = "simple")]
+mod interner;
+#[cfg(feature = "simple")]
+mod keyspace;
+#[cfg(feature = "simple")]
+mod keystats;
+#[cfg(any(test, feature = "conformance"))]
+mod model;
+pub mod propagate;
+#[cfg(feature = "replicated")]
+pub mod replicated;
+#[cfg(feature = "simple")]
 pub mod simple;
+pub mod typed;
+pub mod write_log;
 
-use std::future::Future;
+use std::{
+  fmt,
+  future::Future,
+  num::{NonZeroU32, NonZeroUsize},
+  path::PathBuf,
+  str::FromStr,
+  sync::Arc,
+  time::Duration,
+};
 
+use bytes::Bytes;
+use color_eyre::eyre::{bail, Report, Result};
+use futures::{Stream, StreamExt};
 use smol_str::SmolStr;
+use tokio::{sync::broadcast, task::JoinHandle};
 
-use crate::{command::Command, value::Value, KraglinResult};
+use self::events::{KeyEvent, KeyEvents};
+pub use self::typed::{FromReply, TypedBackendExt};
+use crate::{
+  clock, command::Command, value::Value, KraglinError, KraglinResult,
+};
+
+/// The number of databases backends which support `SELECT` hold, unless
+/// [`BackendConfig::databases`] says otherwise.
+pub const DEFAULT_DATABASES: usize = 16;
+
+/// Configuration passed to [`Backend::new`].
+///
+/// Backends ignore settings which don't apply to them.
+#[derive(Debug, Clone, Default)]
+pub struct BackendConfig {
+  /// The directory in which persistent backends store their data. Backends
+  /// can assume it exists, and that no other server uses it while they do
+  /// (see [`DataDir`](crate::data_dir::DataDir)). Without one, they don't
+  /// persist anything.
+  pub data_dir:              Option<PathBuf>,
+  /// The number of databases to hold, which connections switch between with
+  /// `SELECT`. Defaults to [`DEFAULT_DATABASES`].
+  pub databases:             Option<NonZeroUsize>,
+  /// The number of shards to split the keyspace into, for sharded backends.
+  pub shards:                Option<NonZeroUsize>,
+  /// The approximate maximum number of bytes the backend may use for data.
+  pub max_memory:            Option<u64>,
+  /// Hot keys are tracked for one in every this many commands. Defaults to
+  /// `8`.
+  pub hot_keys_sample_rate:  Option<NonZeroU32>,
+  /// Bulk strings at least this many bytes long are compressed at rest.
+  /// Compression is disabled if `None`.
+  pub compression_threshold: Option<usize>,
+  /// Whether written values are normalized before they're stored (see
+  /// [`StoredValue::normalize()`](crate::value::StoredValue::normalize)), so
+  /// that a value is stored the same way however it was sent: strings
+  /// holding canonical integers as integers, and simple strings as bulk
+  /// strings. Values keep their exact string form, but are read back as the
+  /// normalized type, e.g. `GET` replies with an integer for a key set to
+  /// `"42"`.
+  pub normalize_values:      bool,
+  /// Whether the backend is a replica, whose data only changes when its
+  /// master says so. Replicas hide expired data from reads rather than
+  /// deleting it, and wait for the master's deletion to be replicated, so
+  /// that their clocks never decide what they hold.
+  pub replica:               bool,
+}
 
 /// The generalized backend trait. All storage/execution backends implement
 /// this.
-pub trait Backend: Send + Sync + 'static {
-  /// Creates a new instance of `Self`.
-  fn new() -> Self;
+pub trait Backend: Send + Sync + Sized + 'static {
+  /// Creates a new instance of `Self` with the given configuration.
+  ///
+  /// Fails if the configuration is invalid for the backend, or if the backend
+  /// cannot be initialized (e.g. its data directory is unreadable).
+  fn new(config: BackendConfig) -> Result<Self>;
 
   /// Executes the given command on the backend.
   fn execute(
     &self,
     command: Command,
   ) -> impl Future<Output = KraglinResult> + Send;
+
+  /// Executes the given command on the backend without recording the access
+  /// to its keys in access statistics (like hot key tracking), for
+  /// connections which set `CLIENT NO-TOUCH`.
+  ///
+  /// Backends which don't track accesses can rely on the default
+  /// implementation, which calls [`execute()`](Backend::execute).
+  fn execute_without_touch(
+    &self,
+    command: Command,
+  ) -> impl Future<Output = KraglinResult> + Send {
+    self.execute(command)
+  }
+
+  /// The number of databases the backend holds, numbered from 0, which
+  /// connections switch between with `SELECT`.
+  ///
+  /// Backends which hold a single keyspace can rely on the default of 1.
+  fn databases(&self) -> usize { 1 }
+
+  /// Executes the given command on database `db`, which is below
+  /// [`databases()`](Backend::databases). `touch` is whether the access is
+  /// recorded, as by [`execute()`](Backend::execute) rather than
+  /// [`execute_without_touch()`](Backend::execute_without_touch), which run
+  /// commands on database 0.
+  ///
+  /// Backends which hold a single keyspace can rely on the default
+  /// implementation, which calls one of those.
+  fn execute_in(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> impl Future<Output = KraglinResult> + Send {
+    debug_assert_eq!(db, 0, "the backend only has database 0");
+    async move {
+      if touch {
+        self.execute(command).await
+      } else {
+        self.execute_without_touch(command).await
+      }
+    }
+  }
+
+  /// Executes `commands`, none of which write, against a consistent snapshot
+  /// of database `db`, for an `EXEC` whose queued commands only read. Writes
+  /// made meanwhile aren't seen by the commands, and aren't blocked by them.
+  /// `touch` is as for [`execute_in()`](Backend::execute_in).
+  ///
+  /// Backends which can't take snapshots can rely on the default
+  /// implementation, which executes the commands one at a time, so writes
+  /// from other connections may land in between.
+  fn execute_read_only(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> impl Future<Output = Vec<KraglinResult>> + Send {
+    async move {
+      let mut replies = Vec::with_capacity(commands.len());
+      for command in commands {
+        replies.push(self.execute_in(db, command, touch).await);
+      }
+      replies
+    }
+  }
+
+  /// Executes `commands` on database `db` as a unit, for an `EXEC`, so that
+  /// no command from another connection runs on the database in between
+  /// them. `touch` is as for [`execute_in()`](Backend::execute_in).
+  ///
+  /// Backends which can't exclude other commands can rely on the default
+  /// implementation, which executes the commands one at a time, so writes
+  /// from other connections may land in between.
+  fn execute_batch(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> impl Future<Output = Vec<KraglinResult>> + Send {
+    async move {
+      let mut replies = Vec::with_capacity(commands.len());
+      for command in commands {
+        replies.push(self.execute_in(db, command, touch).await);
+      }
+      replies
+    }
+  }
+
+  /// Starts recording the data the backend removes on its own, like expired
+  /// hash fields, for [`take_expirations()`](Backend::take_expirations).
+  /// Called by wrappers which propagate writes, so that backends which
+  /// nobody propagates from don't keep the record.
+  ///
+  /// Backends which never remove data on their own can rely on the default
+  /// no-op.
+  fn track_expirations(&self) {}
+
+  /// Returns the commands which reproduce the data the backend has removed on
+  /// its own since it was last called, in order, once
+  /// [`track_expirations()`](Backend::track_expirations) has been called.
+  /// Removals are reproduced as explicit deletions (`DEL` or `HDEL`), so
+  /// that replicas and the append-only file never expire data themselves.
+  ///
+  /// Backends which never remove data on their own can rely on the default
+  /// implementation, which returns nothing.
+  fn take_expirations(&self) -> Vec<Command> { Vec::new() }
+
+  /// Executes the given command on database `db` of the backend, producing
+  /// the reply in chunks so that it can be serialized incrementally. `db`
+  /// and `touch` are as for [`execute_in()`](Backend::execute_in).
+  ///
+  /// This is intended for commands like `KEYS` and `SMEMBERS` whose replies
+  /// can be huge. Backends which don't support streaming a command can rely
+  /// on the default implementation, which yields the whole reply from
+  /// [`execute_in()`](Backend::execute_in) as a single
+  /// [`ReplyChunk::Value`].
+  fn execute_streaming(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send {
+    futures::stream::once(self.execute_in(db, command, touch))
+      .map(|result| result.map(ReplyChunk::Value))
+  }
+
+  /// Shrinks over-allocated storage for keys which have shrunk dramatically.
+  ///
+  /// Backends which don't over-allocate can rely on the default no-op.
+  fn defragment(&self) -> impl Future<Output = ()> + Send { async {} }
+
+  /// Runs once the server has stopped serving commands, before it exits, to
+  /// e.g. flush data to disk one last time.
+  ///
+  /// Backends with nothing to do on shutdown can rely on the default no-op.
+  fn shutdown(&self) -> impl Future<Output = Result<()>> + Send {
+    async { Ok(()) }
+  }
+
+  /// Returns whether the backend is in a state to serve traffic, or a
+  /// description of why not, for readiness probes. A backend which can't
+  /// persist writes, for example, may be better taken out of rotation.
+  ///
+  /// Backends which are always ready once created can rely on the default
+  /// implementation.
+  fn readiness(&self) -> Result<(), String> { Ok(()) }
+
+  /// Returns a receiver of an event for every change to a key from now on,
+  /// whether made by a command or by expiration.
+  ///
+  /// Backends which don't emit events can rely on the default
+  /// implementation, whose receiver is closed immediately.
+  fn subscribe_events(&self) -> broadcast::Receiver<KeyEvent> {
+    KeyEvents::default().subscribe()
+  }
+}
+
+/// The maximum number of elements in a single [`ReplyChunk::Elements`].
+pub const REPLY_CHUNK_SIZE: usize = 1024;
+
+/// A piece of a reply produced by [`Backend::execute_streaming`].
+///
+/// A stream either consists of a single `Value`, or of a header followed by
+/// `Elements` chunks which together contain exactly as many elements as the
+/// header announced.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplyChunk {
+  /// A complete reply.
+  Value(Value),
+  /// The start of an array reply with the given number of elements.
+  ArrayHeader(usize),
+  /// The start of a set reply with the given number of elements.
+  SetHeader(usize),
+  /// A run of elements belonging to the current array or set reply.
+  Elements(Vec<Value>),
+}
+
+impl ReplyChunk {
+  /// Produces the chunks of an array reply from an iterator of its elements,
+  /// converting elements lazily as the chunks are consumed.
+  pub fn array<I>(elements: I) -> impl Stream<Item = ReplyChunk> + Send
+  where
+    I: IntoIterator,
+    I::IntoIter: ExactSizeIterator<Item = Value> + Send,
+  {
+    let elements = elements.into_iter();
+    ReplyChunk::chunked(ReplyChunk::ArrayHeader(elements.len()), elements)
+  }
+
+  /// Produces the chunks of a set reply from an iterator of its (distinct)
+  /// members, like [`array()`](ReplyChunk::array).
+  pub fn set<I>(members: I) -> impl Stream<Item = ReplyChunk> + Send
+  where
+    I: IntoIterator,
+    I::IntoIter: ExactSizeIterator<Item = Value> + Send,
+  {
+    let members = members.into_iter();
+    ReplyChunk::chunked(ReplyChunk::SetHeader(members.len()), members)
+  }
+
+  fn chunked(
+    header: ReplyChunk,
+    mut elements: impl Iterator<Item = Value> + Send,
+  ) -> impl Stream<Item = ReplyChunk> + Send {
+    let chunks = std::iter::from_fn(move || {
+      let chunk = elements.by_ref().take(REPLY_CHUNK_SIZE).collect::<Vec<_>>();
+      (!chunk.is_empty()).then_some(ReplyChunk::Elements(chunk))
+    });
+    futures::stream::iter(std::iter::once(header).chain(chunks))
+  }
+}
+
+/// Reassembles a streamed reply into a single [`Value`].
+pub async fn collect_reply(
+  stream: impl Stream<Item = Result<ReplyChunk, KraglinError>>,
+) -> KraglinResult {
+  let mut stream = std::pin::pin!(stream);
+
+  let (mut elements, mut set) = (Vec::new(), false);
+  while let Some(chunk) = stream.next().await {
+    match chunk? {
+      ReplyChunk::Value(value) => return Ok(value),
+      ReplyChunk::ArrayHeader(len) => elements.reserve(len),
+      ReplyChunk::SetHeader(_) => set = true,
+      ReplyChunk::Elements(chunk) => elements.extend(chunk),
+    }
+  }
+  if set {
+    Ok(Value::Set(elements.into_iter().collect()))
+  } else {
+    Ok(Value::Array(elements))
+  }
+}
+
+/// The storage engines which can be selected at runtime.
+///
+/// Only the engines whose cargo features are enabled can be selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+  /// The [`SimpleBackend`](simple::SimpleBackend). Requires the `simple`
+  /// feature.
+  #[cfg(feature = "simple")]
+  Simple,
+}
+
+impl BackendKind {
+  /// The names of every storage engine, whether compiled in or not. Each
+  /// engine is gated behind a cargo feature of the same name.
+  const ALL: &'static [&'static str] = &["simple"];
+
+  /// Returns the name of the storage engine, as used in the `BACKEND` setting.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      #[cfg(feature = "simple")]
+      BackendKind::Simple => "simple",
+    }
+  }
+}
+
+impl FromStr for BackendKind {
+  type Err = Report;
+
+  fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      #[cfg(feature = "simple")]
+      "simple" => Ok(BackendKind::Simple),
+      name if BackendKind::ALL.contains(&name) => bail!(
+        "backend `{name}` is not compiled in; rebuild with the `{name}` \
+         feature"
+      ),
+      name => bail!(
+        "unknown backend `{name}`, expected one of: {}",
+        BackendKind::ALL.join(", ")
+      ),
+    }
+  }
+}
+
+impl fmt::Display for BackendKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.name())
+  }
+}
+
+/// Spawns a task which calls [`Backend::defragment`] every `interval`.
+///
+/// # Panics
+///
+/// Panics if `interval` is zero.
+pub fn spawn_defrag_task<B: Backend>(
+  backend: Arc<B>,
+  interval: Duration,
+) -> JoinHandle<()> {
+  assert!(!interval.is_zero(), "the defragmentation interval is zero");
+  tokio::spawn(async move {
+    let mut ticker = clock::interval(interval);
+    ticker.set_missed_tick_behavior(clock::MissedTickBehavior::Delay);
+    loop {
+      ticker.tick().await;
+      backend.defragment().await;
+      tracing::trace!("completed active defragmentation pass");
+    }
+  })
 }
 
 /// Extension trait for using commands as functions. Mostly for testing
@@ -29,12 +413,21 @@ pub trait BackendExt: Backend {
   fn SET(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
   fn GET(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  fn GET_AND_DELETE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn SET_AND_GET_OLD(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
   fn MGET(
     &self,
     keys: Vec<SmolStr>,
@@ -43,144 +436,452 @@ pub trait BackendExt: Backend {
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  fn INCR_BY(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    increment: i64,
+  ) -> impl Future<Output = KraglinResult> + Send;
   fn KEYS(&self) -> impl Future<Output = KraglinResult> + Send;
+  fn SCAN(
+    &self,
+    cursor: u64,
+    pattern: Option<SmolStr>,
+    count: Option<usize>,
+  ) -> impl Future<Output = KraglinResult> + Send;
   fn EXISTS(
     &self,
-    key: impl Into<SmolStr> + Send,
+    keys: Vec<SmolStr>,
   ) -> impl Future<Output = KraglinResult> + Send;
   fn DEL(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn TOUCH(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn UNLINK(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn EXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn PEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn EXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time: i64,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn PEXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn DUMP(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn RESTORE(
     &self,
     key: impl Into<SmolStr> + Send,
+    ttl: i64,
+    payload: impl Into<Bytes> + Send,
+    replace: bool,
   ) -> impl Future<Output = KraglinResult> + Send;
   fn INFO(&self) -> impl Future<Output = KraglinResult> + Send;
+  fn BGSAVE(&self) -> impl Future<Output = KraglinResult> + Send;
+  fn MEMORY_USAGE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn OBJECT_ENCODING(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn DEBUG_HOTKEYS(&self) -> impl Future<Output = KraglinResult> + Send;
+  fn DEBUG_OBJECT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  fn DEBUG_KEYSTATS(&self) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
   fn HSET(
     &self,
     key: impl Into<SmolStr> + Send,
     field: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
   fn HGET(
     &self,
     key: impl Into<SmolStr> + Send,
     field: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
   fn HGETALL(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
   fn HMGET(
     &self,
     key: impl Into<SmolStr> + Send,
     fields: Vec<SmolStr>,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HDEL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HPEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HPEXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HTTL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HPTTL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "hashes")]
+  fn HPERSIST(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SADD(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SMEMBERS(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SCARD(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SISMEMBER(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SDIFF(
     &self,
     set_a: impl Into<SmolStr> + Send,
     set_b: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SDIFFSTORE(
-    self,
+    &self,
     set_a: impl Into<SmolStr> + Send,
     set_b: impl Into<SmolStr> + Send,
     new_set: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "sets")]
   fn SREM(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn LPUSH(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn RPUSH(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn LRANGE(
     &self,
     key: impl Into<SmolStr> + Send,
     start: i64,
     end: i64,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn LLEN(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn LPOP(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "lists")]
   fn RPOP(
     &self,
     key: impl Into<SmolStr> + Send,
   ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "json")]
+  fn JSON_SET(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    json: impl Into<Bytes> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "json")]
+  fn JSON_GET(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "json")]
+  fn JSON_DEL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
+  #[cfg(feature = "json")]
+  fn JSON_NUMINCRBY(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    by: impl Into<Value> + Send,
+  ) -> impl Future<Output = KraglinResult> + Send;
 }
 
 impl<B: Backend> BackendExt for B {
   async fn SET(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::Set {
-        key: key.into(),
-        value,
+        key:     key.into(),
+        value:   value.into(),
+        options: Default::default(),
       })
       .await
   }
   async fn GET(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::Get { key: key.into() }).await
   }
+  async fn GET_AND_DELETE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self.execute(Command::GetDelete { key: key.into() }).await
+  }
+  async fn SET_AND_GET_OLD(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::SetAndGet {
+        key:     key.into(),
+        value:   value.into(),
+        options: Default::default(),
+      })
+      .await
+  }
   async fn MGET(&self, keys: Vec<SmolStr>) -> KraglinResult {
     self.execute(Command::MultipleGet { keys }).await
   }
   async fn INCR(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::Increment { key: key.into() }).await
   }
+  async fn INCR_BY(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    increment: i64,
+  ) -> KraglinResult {
+    self
+      .execute(Command::IncrementBy {
+        key: key.into(),
+        increment,
+      })
+      .await
+  }
   async fn KEYS(&self) -> KraglinResult { self.execute(Command::Keys).await }
-  async fn EXISTS(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
-    self.execute(Command::Exists { key: key.into() }).await
+  async fn SCAN(
+    &self,
+    cursor: u64,
+    pattern: Option<SmolStr>,
+    count: Option<usize>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::Scan {
+        cursor,
+        pattern,
+        count,
+      })
+      .await
+  }
+  async fn EXISTS(&self, keys: Vec<SmolStr>) -> KraglinResult {
+    self.execute(Command::Exists { keys }).await
   }
-  async fn DEL(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
-    self.execute(Command::Delete { key: key.into() }).await
+  async fn DEL(&self, keys: Vec<SmolStr>) -> KraglinResult {
+    self.execute(Command::Delete { keys }).await
+  }
+  async fn TOUCH(&self, keys: Vec<SmolStr>) -> KraglinResult {
+    self.execute(Command::Touch { keys }).await
+  }
+  async fn UNLINK(&self, keys: Vec<SmolStr>) -> KraglinResult {
+    self.execute(Command::Unlink { keys }).await
+  }
+  async fn EXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+  ) -> KraglinResult {
+    self
+      .execute(Command::Expire {
+        key: key.into(),
+        seconds,
+        condition: None,
+      })
+      .await
+  }
+  async fn PEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+  ) -> KraglinResult {
+    self
+      .execute(Command::PExpire {
+        key: key.into(),
+        milliseconds,
+        condition: None,
+      })
+      .await
+  }
+  async fn EXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time: i64,
+  ) -> KraglinResult {
+    self
+      .execute(Command::ExpireAt {
+        key: key.into(),
+        unix_time,
+        condition: None,
+      })
+      .await
+  }
+  async fn PEXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+  ) -> KraglinResult {
+    self
+      .execute(Command::PExpireAt {
+        key: key.into(),
+        unix_time_ms,
+        condition: None,
+      })
+      .await
+  }
+  async fn DUMP(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
+    self.execute(Command::Dump { key: key.into() }).await
+  }
+  async fn RESTORE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    ttl: i64,
+    payload: impl Into<Bytes> + Send,
+    replace: bool,
+  ) -> KraglinResult {
+    self
+      .execute(Command::Restore {
+        key: key.into(),
+        ttl,
+        payload: payload.into(),
+        replace,
+        absttl: false,
+      })
+      .await
   }
   async fn INFO(&self) -> KraglinResult { self.execute(Command::Info).await }
+  async fn BGSAVE(&self) -> KraglinResult {
+    self.execute(Command::BackgroundSave).await
+  }
+  async fn MEMORY_USAGE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self.execute(Command::MemoryUsage { key: key.into() }).await
+  }
+  async fn OBJECT_ENCODING(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::ObjectEncoding { key: key.into() })
+      .await
+  }
+  async fn DEBUG_HOTKEYS(&self) -> KraglinResult {
+    self.execute(Command::DebugHotKeys).await
+  }
+  async fn DEBUG_OBJECT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self.execute(Command::DebugObject { key: key.into() }).await
+  }
+  async fn DEBUG_KEYSTATS(&self) -> KraglinResult {
+    self.execute(Command::DebugKeyStats { samples: None }).await
+  }
+  #[cfg(feature = "hashes")]
   async fn HSET(
     &self,
     key: impl Into<SmolStr> + Send,
     field: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::HashSet {
-        key: key.into(),
+        key:   key.into(),
         field: field.into(),
-        value,
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "hashes")]
   async fn HGET(
     &self,
     key: impl Into<SmolStr> + Send,
@@ -193,9 +894,11 @@ impl<B: Backend> BackendExt for B {
       })
       .await
   }
+  #[cfg(feature = "hashes")]
   async fn HGETALL(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::HashGetAll { key: key.into() }).await
   }
+  #[cfg(feature = "hashes")]
   async fn HMGET(
     &self,
     key: impl Into<SmolStr> + Send,
@@ -208,38 +911,143 @@ impl<B: Backend> BackendExt for B {
       })
       .await
   }
+  #[cfg(feature = "hashes")]
+  async fn HDEL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashDelete {
+        key: key.into(),
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashExpire {
+        key: key.into(),
+        seconds,
+        condition: None,
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HPEXPIRE(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashPExpire {
+        key: key.into(),
+        milliseconds,
+        condition: None,
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HPEXPIREAT(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashPExpireAt {
+        key: key.into(),
+        unix_time_ms,
+        condition: None,
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HTTL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashTtl {
+        key: key.into(),
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HPTTL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashPTtl {
+        key: key.into(),
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "hashes")]
+  async fn HPERSIST(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    self
+      .execute(Command::HashPersist {
+        key: key.into(),
+        fields,
+      })
+      .await
+  }
+  #[cfg(feature = "sets")]
   async fn SADD(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::SetAdd {
-        key: key.into(),
-        value,
+        key:   key.into(),
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "sets")]
   async fn SMEMBERS(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::SetMembers { key: key.into() }).await
   }
+  #[cfg(feature = "sets")]
   async fn SCARD(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self
       .execute(Command::SetCardinality { key: key.into() })
       .await
   }
+  #[cfg(feature = "sets")]
   async fn SISMEMBER(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::SetIsMember {
-        key: key.into(),
-        value,
+        key:   key.into(),
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "sets")]
   async fn SDIFF(
     &self,
     set_a: impl Into<SmolStr> + Send,
@@ -252,8 +1060,9 @@ impl<B: Backend> BackendExt for B {
       })
       .await
   }
+  #[cfg(feature = "sets")]
   async fn SDIFFSTORE(
-    self,
+    &self,
     set_a: impl Into<SmolStr> + Send,
     set_b: impl Into<SmolStr> + Send,
     new_set: impl Into<SmolStr> + Send,
@@ -266,42 +1075,46 @@ impl<B: Backend> BackendExt for B {
       })
       .await
   }
+  #[cfg(feature = "sets")]
   async fn SREM(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::SetRemove {
-        key: key.into(),
-        value,
+        key:   key.into(),
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "lists")]
   async fn LPUSH(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::LeftPush {
-        key: key.into(),
-        value,
+        key:   key.into(),
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "lists")]
   async fn RPUSH(
     &self,
     key: impl Into<SmolStr> + Send,
-    value: Value,
+    value: impl Into<Value> + Send,
   ) -> KraglinResult {
     self
       .execute(Command::RightPush {
-        key: key.into(),
-        value,
+        key:   key.into(),
+        value: value.into(),
       })
       .await
   }
+  #[cfg(feature = "lists")]
   async fn LRANGE(
     &self,
     key: impl Into<SmolStr> + Send,
@@ -316,189 +1129,215 @@ impl<B: Backend> BackendExt for B {
       })
       .await
   }
+  #[cfg(feature = "lists")]
   async fn LLEN(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::ListLength { key: key.into() }).await
   }
+  #[cfg(feature = "lists")]
   async fn LPOP(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::LeftPop { key: key.into() }).await
   }
+  #[cfg(feature = "lists")]
   async fn RPOP(&self, key: impl Into<SmolStr> + Send) -> KraglinResult {
     self.execute(Command::RightPop { key: key.into() }).await
   }
-}
-
-#[cfg(test)]
-#[generic_tests::define(attrs(tokio::test))]
-#[allow(non_snake_case)]
-mod tests {
-  use std::collections::BTreeMap;
-
-  use super::{simple::SimpleBackend, Backend, BackendExt};
-  use crate::{value::Value, KraglinError};
-
-  #[tokio::test]
-  async fn SET_sets_and_GET_gets<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
-
-    backend
-      .SET("key_a", Value::SimpleString("a".into()))
-      .await?;
-    assert_eq!(backend.GET("key_a").await?, Value::SimpleString("a".into()));
-
-    Ok(())
+  #[cfg(feature = "json")]
+  async fn JSON_SET(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    json: impl Into<Bytes> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::JsonSet {
+        key:  key.into(),
+        path: path.into(),
+        json: json.into(),
+      })
+      .await
   }
-
-  #[tokio::test]
-  async fn MGET_gets_multiple_keys<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
-
-    backend.SET("key_a", Value::Integer(2)).await?;
-    backend.SET("key_b", Value::Integer(4)).await?;
-    assert_eq!(
-      backend.MGET(vec!["key_a".into(), "key_b".into()]).await?,
-      Value::Array(vec![Value::Integer(2), Value::Integer(4)])
-    );
-
-    Ok(())
+  #[cfg(feature = "json")]
+  async fn JSON_GET(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::JsonGet {
+        key:  key.into(),
+        path: path.into(),
+      })
+      .await
   }
-
-  #[tokio::test]
-  async fn INCR_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
-
-    backend.SET("int", Value::Integer(2)).await?;
-    backend.SET("big_num", Value::BigNumber(4.into())).await?;
-    backend
-      .SET("string", Value::SimpleString("24".into()))
-      .await?;
-    backend
-      .SET("bulk_string", Value::BulkString("24".into()))
-      .await?;
-
-    backend.INCR("int").await?;
-    backend.INCR("big_num").await?;
-    backend.INCR("string").await?;
-    backend.INCR("bulk_string").await?;
-
-    assert_eq!(backend.GET("int").await?, Value::Integer(3));
-    assert_eq!(backend.GET("big_num").await?, Value::BigNumber(5.into()));
-    assert_eq!(
-      backend.GET("string").await?,
-      Value::SimpleString("25".into())
-    );
-    assert_eq!(
-      backend.GET("bulk_string").await?,
-      Value::BulkString("25".into())
-    );
-
-    Ok(())
+  #[cfg(feature = "json")]
+  async fn JSON_DEL(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::JsonDelete {
+        key:  key.into(),
+        path: path.into(),
+      })
+      .await
   }
-
-  #[tokio::test]
-  async fn KEYS_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
-
-    backend.SET("a", Value::Integer(1)).await?;
-    backend.SET("b", Value::Integer(2)).await?;
-
-    assert_eq!(
-      backend.KEYS().await?,
-      Value::Array(vec![
-        Value::SimpleString("a".into()),
-        Value::SimpleString("b".into())
-      ])
-    );
-
-    Ok(())
+  #[cfg(feature = "json")]
+  async fn JSON_NUMINCRBY(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    by: impl Into<Value> + Send,
+  ) -> KraglinResult {
+    self
+      .execute(Command::JsonNumIncrBy {
+        key:  key.into(),
+        path: path.into(),
+        by:   by.into(),
+      })
+      .await
   }
+}
 
-  #[tokio::test]
-  async fn EXISTS_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
+#[cfg(test)]
+mod tests {
+  #[cfg(feature = "simple")]
+  mod simple_backend {
+    use crate::{
+      backends::{simple::SimpleBackend, Backend, BackendConfig, BackendExt},
+      command::Command,
+      data_dir::database_snapshot_file,
+      value::Value,
+    };
 
-    backend.SET("a", Value::Integer(1)).await?;
-    assert_eq!(backend.EXISTS("a").await?, Value::Integer(1));
+    crate::backend_conformance_tests!(crate::backends::simple::SimpleBackend);
 
-    assert_eq!(backend.EXISTS("b").await?, Value::Integer(0));
+    #[tokio::test]
+    async fn snapshots_are_saved_on_shutdown_and_loaded_on_startup() {
+      let dir = std::env::temp_dir().join(format!(
+        "kraglin-simple-snapshot-test-{}",
+        std::process::id()
+      ));
+      let _ = std::fs::remove_dir_all(&dir);
+      std::fs::create_dir_all(&dir).unwrap();
+      let config = || BackendConfig {
+        data_dir: Some(dir.clone()),
+        ..BackendConfig::default()
+      };
 
-    Ok(())
-  }
+      let backend = SimpleBackend::new(config()).unwrap();
+      backend.SET("a", "hello").await.unwrap();
+      backend.SET("b", 1).await.unwrap();
+      backend
+        .execute_in(3, Command::set("a", "three").build(), true)
+        .await
+        .unwrap();
+      backend.shutdown().await.unwrap();
+      drop(backend);
+      // databases which were never written aren't saved
+      assert!(dir.join(database_snapshot_file(3)).exists());
+      assert!(!dir.join(database_snapshot_file(1)).exists());
 
-  #[tokio::test]
-  async fn DELETE_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
+      let backend = SimpleBackend::new(config()).unwrap();
+      assert_eq!(backend.GET("a").await.unwrap(), "hello".into());
+      assert_eq!(backend.GET("b").await.unwrap(), 1.into());
+      assert_eq!(
+        backend
+          .execute_in(3, Command::Get { key: "a".into() }, true)
+          .await
+          .unwrap(),
+        "three".into()
+      );
+      drop(backend);
 
-    backend.SET("a", Value::Integer(1)).await?;
-    assert_eq!(backend.EXISTS("a").await?, Value::Integer(1));
-    assert_eq!(backend.DEL("a").await?, Value::Integer(1));
-    assert_eq!(backend.EXISTS("a").await?, Value::Integer(0));
-    assert_eq!(backend.DEL("a").await?, Value::Integer(0));
+      // a damaged snapshot fails startup rather than loading garbage
+      let path = dir.join(crate::data_dir::SNAPSHOT_FILE);
+      let mut snapshot = std::fs::read(&path).unwrap();
+      let last = snapshot.len() - 1;
+      snapshot[last] ^= 1;
+      std::fs::write(&path, snapshot).unwrap();
+      let error = SimpleBackend::new(config()).err().unwrap();
+      assert!(format!("{error:#}").contains("checksum mismatch"));
 
-    Ok(())
-  }
+      std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-  #[tokio::test]
-  async fn INFO_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
+    /// Waits for the backend's `saves`th snapshot to be saved.
+    async fn wait_for_save(backend: &SimpleBackend, saves: usize) {
+      for _ in 0..500 {
+        let Value::BulkString(info) = backend.INFO().await.unwrap() else {
+          panic!("INFO should return a bulk string");
+        };
+        let info = String::from_utf8(info.to_vec()).unwrap();
+        if info.contains(&format!("rdb_saves:{saves}\r\n")) {
+          assert!(info.contains("rdb_last_bgsave_status:ok"));
+          return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+      }
+      panic!("the snapshot wasn't saved");
+    }
 
-    backend.SET("a", Value::Integer(1)).await?;
-    assert_eq!(
-      backend.INFO().await?,
-      Value::SimpleString(
-        "We've got 1 key right now, thanks for asking :)".into()
-      )
-    );
+    #[tokio::test]
+    async fn background_saves_write_deltas_of_changed_keys() {
+      let dir = std::env::temp_dir()
+        .join(format!("kraglin-simple-bgsave-test-{}", std::process::id()));
+      let _ = std::fs::remove_dir_all(&dir);
+      std::fs::create_dir_all(&dir).unwrap();
+      let config = || BackendConfig {
+        data_dir: Some(dir.clone()),
+        ..BackendConfig::default()
+      };
+      let deltas = || {
+        std::fs::read_dir(dir.join(crate::data_dir::SNAPSHOT_DELTAS_DIR))
+          .map_or(0, |entries| entries.count())
+      };
 
-    Ok(())
-  }
+      let backend = SimpleBackend::new(config()).unwrap();
+      for i in 0..10 {
+        backend.SET(format!("key{i}"), i).await.unwrap();
+      }
+      // the first save is always full
+      backend.BGSAVE().await.unwrap();
+      wait_for_save(&backend, 1).await;
+      assert_eq!(deltas(), 0);
 
-  #[tokio::test]
-  async fn HSET_sets_and_HGET_gets<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
+      backend.SET("key0", "changed").await.unwrap();
+      backend.DEL(vec!["key1".into()]).await.unwrap();
+      backend.BGSAVE().await.unwrap();
+      wait_for_save(&backend, 2).await;
+      assert_eq!(deltas(), 1);
+      drop(backend);
 
-    backend.HSET("a", "b", Value::Integer(1)).await?;
-    assert_eq!(backend.HGET("a", "b").await?, Value::Integer(1));
+      // the delta is applied on top of the full snapshot
+      let backend = SimpleBackend::new(config()).unwrap();
+      assert_eq!(backend.GET("key0").await.unwrap(), "changed".into());
+      assert_eq!(backend.GET("key1").await.unwrap(), Value::Nothing);
+      assert_eq!(backend.GET("key9").await.unwrap(), 9.into());
 
-    Ok(())
-  }
+      // shutting down compacts everything into a full snapshot
+      backend.shutdown().await.unwrap();
+      assert_eq!(deltas(), 0);
 
-  #[tokio::test]
-  async fn HGETALL_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
+      std::fs::remove_dir_all(&dir).unwrap();
+    }
+  }
 
-    backend.HSET("a", "b", Value::Integer(1)).await?;
-    backend.HSET("a", "c", Value::Integer(2)).await?;
-    assert_eq!(
-      backend.HGETALL("a").await?,
-      Value::Map(
-        [
-          ("b".into(), Value::Integer(1)),
-          ("c".into(), Value::Integer(2))
-        ]
-        .into_iter()
-        .collect::<BTreeMap<smol_str::SmolStr, Value>>()
-      )
+  #[cfg(feature = "simple")]
+  mod hooked_backend {
+    crate::backend_conformance_tests!(
+      crate::backends::propagate::HookedBackend<
+        crate::backends::simple::SimpleBackend,
+      >
     );
-
-    Ok(())
   }
 
-  #[tokio::test]
-  async fn HMGET_works<B: Backend>() -> Result<(), KraglinError> {
-    let backend = B::new();
-
-    backend.HSET("a", "b", Value::Integer(1)).await?;
-    backend.HSET("a", "c", Value::Integer(2)).await?;
-    backend.HSET("a", "d", Value::Integer(3)).await?;
-
-    assert_eq!(
-      backend.HMGET("a", vec!["b".into(), "c".into()]).await?,
-      Value::Array(vec![Value::Integer(1), Value::Integer(2)])
+  #[cfg(all(feature = "simple", feature = "replicated"))]
+  mod replicated_backend {
+    crate::backend_conformance_tests!(
+      crate::backends::replicated::ReplicatedBackend<
+        crate::backends::simple::SimpleBackend,
+      >
     );
-
-    Ok(())
   }
-
-  #[instantiate_tests(<SimpleBackend>)]
-  mod simple_backend {}
 }
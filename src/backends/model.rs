@@ -0,0 +1,441 @@
+//! A reference model of the keyspace, for differential testing of
+//! [`Backend`](super::Backend)s.
+//!
+//! The model is deliberately naive: every command is a few lines over a
+//! `BTreeMap`, so that its behavior is easy to check by eye. Random command
+//! sequences are run against both the model and a backend, and every reply
+//! must match. It covers the string, keyspace, hash, and set commands; list
+//! and JSON commands aren't modelled yet.
+
+use std::collections::BTreeMap;
+#[cfg(feature = "sets")]
+use std::collections::BTreeSet;
+
+use bytes::Bytes;
+use proptest::{prelude::*, test_runner::TestRunner};
+use smol_str::SmolStr;
+
+use super::{Backend, BackendConfig};
+use crate::{
+  command::{Command, SetCondition, SetOptions},
+  value::Value,
+  KraglinError, KraglinResult,
+};
+
+/// A value held by the model.
+#[derive(Debug, Clone)]
+enum Entry {
+  String(Bytes),
+  Integer(i64),
+  #[cfg(feature = "hashes")]
+  Hash(BTreeMap<SmolStr, Value>),
+  #[cfg(feature = "sets")]
+  Set(BTreeSet<Value>),
+}
+
+impl Entry {
+  fn to_value(&self) -> Value {
+    match self {
+      Entry::String(s) => Value::BulkString(s.clone()),
+      Entry::Integer(i) => Value::Integer(*i),
+      #[cfg(feature = "hashes")]
+      Entry::Hash(h) => Value::Map(h.clone()),
+      #[cfg(feature = "sets")]
+      Entry::Set(s) => Value::Set(s.clone()),
+    }
+  }
+}
+
+/// The reference model of a keyspace.
+#[derive(Debug, Default)]
+pub(crate) struct Model {
+  entries: BTreeMap<SmolStr, Entry>,
+}
+
+impl Model {
+  #[cfg(feature = "hashes")]
+  fn hash(
+    &self,
+    key: &str,
+  ) -> Result<Option<&BTreeMap<SmolStr, Value>>, KraglinError> {
+    match self.entries.get(key) {
+      None => Ok(None),
+      Some(Entry::Hash(h)) => Ok(Some(h)),
+      Some(_) => Err(KraglinError::WrongType),
+    }
+  }
+
+  #[cfg(feature = "sets")]
+  fn set(&self, key: &str) -> Result<Option<&BTreeSet<Value>>, KraglinError> {
+    match self.entries.get(key) {
+      None => Ok(None),
+      Some(Entry::Set(s)) => Ok(Some(s)),
+      Some(_) => Err(KraglinError::WrongType),
+    }
+  }
+
+  #[cfg(feature = "sets")]
+  fn difference(
+    &self,
+    a: &str,
+    b: &str,
+  ) -> Result<BTreeSet<Value>, KraglinError> {
+    let (a, b) = (self.set(a)?, self.set(b)?);
+    let (a, b) = (
+      a.cloned().unwrap_or_default(),
+      b.cloned().unwrap_or_default(),
+    );
+    Ok(a.difference(&b).cloned().collect())
+  }
+
+  fn check_string(&self, key: &str) -> Result<(), KraglinError> {
+    match self.entries.get(key) {
+      None | Some(Entry::String(_) | Entry::Integer(_)) => Ok(()),
+      #[allow(unreachable_patterns)]
+      Some(_) => Err(KraglinError::WrongType),
+    }
+  }
+
+  fn increment(&mut self, key: SmolStr, by: i64) -> KraglinResult {
+    let entry = self.entries.entry(key).or_insert(Entry::Integer(0));
+    let current = match entry {
+      Entry::Integer(i) => *i,
+      Entry::String(s) => std::str::from_utf8(s)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(KraglinError::CannotParseAsInteger)?,
+      #[allow(unreachable_patterns)]
+      _ => return Err(KraglinError::WrongType),
+    };
+    let incremented =
+      current.checked_add(by).ok_or(KraglinError::OutOfRange)?;
+    match entry {
+      Entry::Integer(i) => *i = incremented,
+      Entry::String(s) => *s = incremented.to_string().into(),
+      #[allow(unreachable_patterns)]
+      _ => unreachable!("only numbers were incremented"),
+    }
+    Ok(Value::Integer(incremented))
+  }
+
+  /// Runs a command against the model. Panics on commands it doesn't model.
+  pub(crate) fn execute(&mut self, command: Command) -> KraglinResult {
+    match command {
+      Command::Set {
+        key,
+        value,
+        options,
+      } => {
+        let Value::BulkString(value) = value else {
+          panic!("the model only stores bulk strings");
+        };
+        assert!(
+          options.expiration.is_none(),
+          "the model doesn't expire keys"
+        );
+        let exists = self.entries.contains_key(&key);
+        if options.condition.is_some_and(|c| !c.allows(exists)) {
+          return Ok(Value::Nothing);
+        }
+        self.entries.insert(key, Entry::String(value));
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::Get { key } => Ok(
+        self
+          .entries
+          .get(&key)
+          .map_or(Value::Nothing, Entry::to_value),
+      ),
+      Command::MultipleGet { keys } => Ok(Value::Array(
+        keys
+          .iter()
+          .map(|key| {
+            self
+              .entries
+              .get(key)
+              .map_or(Value::Nothing, Entry::to_value)
+          })
+          .collect(),
+      )),
+      Command::SetAndGet {
+        key,
+        value,
+        options,
+      } => {
+        self.check_string(&key)?;
+        let old = self.execute(Command::Get { key: key.clone() });
+        self.execute(Command::Set {
+          key,
+          value,
+          options,
+        })?;
+        old
+      }
+      Command::GetDelete { key } => {
+        self.check_string(&key)?;
+        Ok(
+          self
+            .entries
+            .remove(&key)
+            .as_ref()
+            .map_or(Value::Nothing, Entry::to_value),
+        )
+      }
+      Command::Increment { key } => self.increment(key, 1),
+      Command::IncrementBy { key, increment } => self.increment(key, increment),
+      Command::Keys => Ok(Value::Array(
+        self
+          .entries
+          .keys()
+          .map(|key| Value::BulkString(key.as_bytes().to_vec().into()))
+          .collect(),
+      )),
+      Command::Exists { keys } | Command::Touch { keys } => {
+        let existing = keys
+          .iter()
+          .filter(|k| self.entries.contains_key(*k))
+          .count();
+        Ok(Value::Integer(existing as i64))
+      }
+      Command::Delete { keys } | Command::Unlink { keys } => {
+        let deleted = keys
+          .iter()
+          .filter(|k| self.entries.remove(*k).is_some())
+          .count();
+        Ok(Value::Integer(deleted as i64))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashSet { key, field, value } => {
+        self.hash(&key)?;
+        let Entry::Hash(h) = self
+          .entries
+          .entry(key)
+          .or_insert_with(|| Entry::Hash(BTreeMap::new()))
+        else {
+          unreachable!("the key holds a hash");
+        };
+        Ok(Value::Integer(h.insert(field, value).is_none().into()))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashGet { key, field } => Ok(
+        self
+          .hash(&key)?
+          .and_then(|h| h.get(&field).cloned())
+          .unwrap_or(Value::Nothing),
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { key } => Ok(self.hash(&key)?.map_or_else(
+        || Value::Map(BTreeMap::new()),
+        |h| Value::Map(h.clone()),
+      )),
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { key, fields } => {
+        let h = self.hash(&key)?;
+        Ok(Value::Array(
+          fields
+            .iter()
+            .map(|f| {
+              h.and_then(|h| h.get(f).cloned()).unwrap_or(Value::Nothing)
+            })
+            .collect(),
+        ))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashDelete { key, fields } => {
+        let Some(Entry::Hash(h)) = self.entries.get_mut(&key) else {
+          self.hash(&key)?;
+          return Ok(Value::Integer(0));
+        };
+        let removed = fields.iter().filter(|f| h.remove(*f).is_some()).count();
+        if h.is_empty() {
+          self.entries.remove(&key);
+        }
+        Ok(Value::Integer(removed as i64))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetAdd { key, value } => {
+        self.set(&key)?;
+        let Entry::Set(s) = self
+          .entries
+          .entry(key)
+          .or_insert_with(|| Entry::Set(BTreeSet::new()))
+        else {
+          unreachable!("the key holds a set");
+        };
+        Ok(Value::Integer(s.insert(value).into()))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetMembers { key } => {
+        Ok(Value::Set(self.set(&key)?.cloned().unwrap_or_default()))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetCardinality { key } => Ok(Value::Integer(
+        self.set(&key)?.map_or(0, BTreeSet::len) as i64,
+      )),
+      #[cfg(feature = "sets")]
+      Command::SetIsMember { key, value } => Ok(Value::Integer(
+        self.set(&key)?.is_some_and(|s| s.contains(&value)).into(),
+      )),
+      #[cfg(feature = "sets")]
+      Command::SetDifference { set_a, set_b } => {
+        Ok(Value::Set(self.difference(&set_a, &set_b)?))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore {
+        set_a,
+        set_b,
+        new_set,
+      } => {
+        let difference = self.difference(&set_a, &set_b)?;
+        let len = difference.len() as i64;
+        if difference.is_empty() {
+          self.entries.remove(&new_set);
+        } else {
+          self.entries.insert(new_set, Entry::Set(difference));
+        }
+        Ok(Value::Integer(len))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetRemove { key, value } => {
+        if self.set(&key)?.is_none() {
+          return Ok(Value::Integer(0));
+        }
+        let Some(Entry::Set(s)) = self.entries.get_mut(&key) else {
+          unreachable!("the key holds a set");
+        };
+        let removed = s.remove(&value);
+        if s.is_empty() {
+          self.entries.remove(&key);
+        }
+        Ok(Value::Integer(removed.into()))
+      }
+      command => panic!("the model doesn't support {command:?}"),
+    }
+  }
+}
+
+/// A small pool of keys, so that commands often collide on the same key and
+/// with keys of other types.
+fn key() -> impl Strategy<Value = SmolStr> {
+  prop::sample::select(&["a", "b", "c", "d"][..]).prop_map(SmolStr::from)
+}
+
+/// Values which are sometimes integers (including the largest one), so that
+/// `INCR` both succeeds and fails.
+fn value() -> impl Strategy<Value = Value> {
+  prop_oneof![
+    prop::sample::select(&["x", "y", "0", "-7", "9223372036854775807"][..])
+      .prop_map(|s| Value::BulkString(s.into())),
+    any::<i16>().prop_map(|i| Value::BulkString(i.to_string().into())),
+  ]
+}
+
+/// `SET`'s options, which sometimes have a condition on the key existing.
+/// Times to live aren't modelled.
+fn set_options() -> impl Strategy<Value = SetOptions> {
+  prop::option::of(prop_oneof![Just(SetCondition::Nx), Just(SetCondition::Xx)])
+    .prop_map(|condition| SetOptions {
+      condition,
+      ..SetOptions::default()
+    })
+}
+
+/// A command the model supports.
+pub(crate) fn command() -> impl Strategy<Value = Command> {
+  let strings = prop_oneof![
+    (key(), value(), set_options()).prop_map(|(key, value, options)| {
+      Command::Set {
+        key,
+        value,
+        options,
+      }
+    }),
+    key().prop_map(|key| Command::Get { key }),
+    prop::collection::vec(key(), 1..4)
+      .prop_map(|keys| Command::MultipleGet { keys }),
+    (key(), value(), set_options()).prop_map(|(key, value, options)| {
+      Command::SetAndGet {
+        key,
+        value,
+        options,
+      }
+    }),
+    key().prop_map(|key| Command::GetDelete { key }),
+    key().prop_map(|key| Command::Increment { key }),
+    (key(), any::<i8>().prop_map(i64::from))
+      .prop_map(|(key, increment)| Command::IncrementBy { key, increment }),
+    Just(Command::Keys),
+    prop::collection::vec(key(), 1..4)
+      .prop_map(|keys| Command::Exists { keys }),
+    prop::collection::vec(key(), 1..4)
+      .prop_map(|keys| Command::Delete { keys }),
+  ]
+  .boxed();
+
+  #[cfg(feature = "hashes")]
+  let strings =
+    prop_oneof![
+      strings,
+      (key(), key(), value())
+        .prop_map(|(key, field, value)| Command::HashSet { key, field, value }),
+      (key(), key()).prop_map(|(key, field)| Command::HashGet { key, field }),
+      key().prop_map(|key| Command::HashGetAll { key }),
+      (key(), prop::collection::vec(key(), 1..4))
+        .prop_map(|(key, fields)| Command::HashMultipleGet { key, fields }),
+      (key(), prop::collection::vec(key(), 1..4))
+        .prop_map(|(key, fields)| Command::HashDelete { key, fields }),
+    ]
+    .boxed();
+
+  #[cfg(feature = "sets")]
+  let strings = prop_oneof![
+    strings,
+    (key(), value()).prop_map(|(key, value)| Command::SetAdd { key, value }),
+    key().prop_map(|key| Command::SetMembers { key }),
+    key().prop_map(|key| Command::SetCardinality { key }),
+    (key(), value())
+      .prop_map(|(key, value)| Command::SetIsMember { key, value }),
+    (key(), key())
+      .prop_map(|(set_a, set_b)| Command::SetDifference { set_a, set_b }),
+    (key(), key(), key()).prop_map(|(set_a, set_b, new_set)| {
+      Command::SetDifferenceStore {
+        set_a,
+        set_b,
+        new_set,
+      }
+    }),
+    (key(), value()).prop_map(|(key, value)| Command::SetRemove { key, value }),
+  ]
+  .boxed();
+
+  strings
+}
+
+/// Runs random command sequences against a fresh `B` and the model, failing
+/// (with a shrunk sequence) on the first reply which differs.
+pub(crate) fn check_against_model<B: Backend>() {
+  let runtime = tokio::runtime::Builder::new_current_thread()
+    .enable_all()
+    .build()
+    .unwrap();
+  let sequences = prop::collection::vec(command(), 1..64);
+
+  let mut runner = TestRunner::default();
+  let result = runner.run(&sequences, |commands| {
+    let backend = B::new(BackendConfig::default()).unwrap();
+    let mut model = Model::default();
+    runtime.block_on(async {
+      for (i, command) in commands.into_iter().enumerate() {
+        let expected =
+          model.execute(command.clone()).map_err(|e| e.to_string());
+        let actual = backend.execute(command.clone()).await;
+        let actual = actual.map_err(|e| e.to_string());
+        prop_assert_eq!(actual, expected, "command {} ({:?})", i, command);
+      }
+      Ok(())
+    })
+  });
+  if let Err(e) = result {
+    panic!("{e}");
+  }
+}
@@ -0,0 +1,1118 @@
+//! Hooks which are told about every write a backend applies, so that the
+//! subsystems which propagate writes (replication, the append-only file, and
+//! keyspace notifications) all agree on what a write is.
+//!
+//! Writes are propagated as their effects rather than as they were sent, so
+//! that replaying them elsewhere or later has the same result. For example,
+//! `HEXPIRE h 10 FIELDS 1 f` runs (and is propagated) as `HPEXPIREAT` with
+//! the deadline it computed, rather than expiring `f` ten seconds after
+//! whenever a replica happens to apply it. `EXPIRE`, `PEXPIRE`, and
+//! `EXPIREAT` are likewise propagated as `PEXPIREAT`, `SET`s with a time to
+//! live with `PXAT`, and `RESTORE`s with a time to live with `ABSTTL`.
+//!
+//! Data the backend removes on its own (see
+//! [`Backend::take_expirations()`]) is propagated as explicit deletions along
+//! with the command which was running, so that replicas and the append-only
+//! file never have to decide for themselves what has expired.
+//!
+//! Effects are in database 0, except between a `SELECT` of another database
+//! and the `SELECT 0` which follows it (see [`in_database()`]), so every
+//! batch of them starts and ends in database 0 no matter how they're
+//! interleaved with other writes'.
+
+use std::{future::Future, sync::Arc};
+
+use color_eyre::eyre::Result;
+use futures::{future::BoxFuture, Stream, StreamExt};
+use tokio::sync::broadcast;
+
+use crate::{
+  backends::{events::KeyEvent, Backend, BackendConfig, ReplyChunk},
+  clock,
+  command::{Command, SetExpiration, SetOptions},
+  value::Value,
+  KraglinError, KraglinResult,
+};
+
+/// Something which is told about every write a backend applies.
+pub trait WriteHook: Send + Sync + 'static {
+  /// Runs after `command` has been applied successfully. `effects` are the
+  /// commands which reproduce what it did, and are what should be
+  /// propagated. They start with the deletions of any data which expired
+  /// while it ran, which is why reads may be propagated too.
+  ///
+  /// An error is returned to the client, though the write has still been
+  /// applied.
+  fn on_write<'a>(
+    &'a self,
+    command: &'a Command,
+    effects: &'a [Command],
+  ) -> BoxFuture<'a, Result<(), KraglinError>>;
+}
+
+/// Rewrites `command` so that it has the same effect wherever and whenever
+/// it runs, e.g. by turning relative times to live into absolute deadlines.
+/// Deterministic commands are returned as they are.
+pub fn make_deterministic(command: Command) -> Command {
+  match command {
+    #[cfg(feature = "hashes")]
+    Command::HashExpire { .. } | Command::HashPExpire { .. } => {
+      hash_expire_at(command)
+    }
+    Command::Expire { .. }
+    | Command::PExpire { .. }
+    | Command::ExpireAt { .. } => expire_at(command),
+    Command::Restore {
+      ttl, absttl: false, ..
+    } if ttl > 0 => restore_at(command),
+    Command::Set {
+      options:
+        SetOptions {
+          expiration:
+            Some(
+              SetExpiration::Ex(_)
+              | SetExpiration::Px(_)
+              | SetExpiration::ExAt(_),
+            ),
+          ..
+        },
+      ..
+    }
+    | Command::SetAndGet {
+      options:
+        SetOptions {
+          expiration:
+            Some(
+              SetExpiration::Ex(_)
+              | SetExpiration::Px(_)
+              | SetExpiration::ExAt(_),
+            ),
+          ..
+        },
+      ..
+    } => set_at(command),
+    command => command,
+  }
+}
+
+/// Rewrites a `SET` with `EX`, `PX`, or `EXAT` as one with `PXAT`. Times
+/// which are out of range are left for the backend to reject.
+fn set_at(mut command: Command) -> Command {
+  let (Command::Set { options, .. } | Command::SetAndGet { options, .. }) =
+    &mut command
+  else {
+    unreachable!("only `SET` is rewritten");
+  };
+  let deadline = options
+    .expiration
+    .and_then(|expiration| expiration.unix_time_ms(clock::unix_time_ms()))
+    .and_then(|deadline| i64::try_from(deadline).ok());
+  if let Some(deadline) = deadline {
+    options.expiration = Some(SetExpiration::PxAt(deadline));
+  }
+  command
+}
+
+/// Rewrites `EXPIRE`, `PEXPIRE`, or `EXPIREAT` as `PEXPIREAT`. Times which
+/// are out of range are left for the backend to reject.
+fn expire_at(command: Command) -> Command {
+  let now = clock::unix_time_ms() as i64;
+  let unix_time_ms = match &command {
+    Command::Expire { seconds, .. } => seconds
+      .checked_mul(1000)
+      .and_then(|ttl| now.checked_add(ttl)),
+    Command::PExpire { milliseconds, .. } => now.checked_add(*milliseconds),
+    Command::ExpireAt { unix_time, .. } => unix_time.checked_mul(1000),
+    _ => unreachable!("only other expirations are rewritten"),
+  };
+  let Some(unix_time_ms) = unix_time_ms else {
+    return command;
+  };
+
+  match command {
+    Command::Expire { key, condition, .. }
+    | Command::PExpire { key, condition, .. }
+    | Command::ExpireAt { key, condition, .. } => Command::PExpireAt {
+      key,
+      unix_time_ms,
+      condition,
+    },
+    _ => unreachable!("only other expirations are rewritten"),
+  }
+}
+
+/// Rewrites a `RESTORE` with a time to live as one with `ABSTTL`. Times to
+/// live which are out of range are left for the backend to reject.
+fn restore_at(command: Command) -> Command {
+  let Command::Restore {
+    key,
+    ttl,
+    payload,
+    replace,
+    absttl,
+  } = command
+  else {
+    unreachable!("only `RESTORE` is rewritten");
+  };
+  match clock::unix_time_ms_after(ttl)
+    .and_then(|deadline| i64::try_from(deadline).ok())
+  {
+    Some(deadline) => Command::Restore {
+      key,
+      ttl: deadline,
+      payload,
+      replace,
+      absttl: true,
+    },
+    None => Command::Restore {
+      key,
+      ttl,
+      payload,
+      replace,
+      absttl,
+    },
+  }
+}
+
+/// Rewrites `HEXPIRE` or `HPEXPIRE` as `HPEXPIREAT`. Times to live which are
+/// out of range are left for the backend to reject.
+#[cfg(feature = "hashes")]
+fn hash_expire_at(command: Command) -> Command {
+  let ttl = match &command {
+    Command::HashExpire { seconds, .. } => seconds.checked_mul(1000),
+    Command::HashPExpire { milliseconds, .. } => Some(*milliseconds),
+    _ => unreachable!("only relative expirations are rewritten"),
+  };
+  let Some(unix_time_ms) = ttl
+    .and_then(clock::unix_time_ms_after)
+    .and_then(|deadline| i64::try_from(deadline).ok())
+  else {
+    return command;
+  };
+
+  match command {
+    Command::HashExpire {
+      key,
+      condition,
+      fields,
+      ..
+    }
+    | Command::HashPExpire {
+      key,
+      condition,
+      fields,
+      ..
+    } => Command::HashPExpireAt {
+      key,
+      unix_time_ms,
+      condition,
+      fields,
+    },
+    _ => unreachable!("only relative expirations are rewritten"),
+  }
+}
+
+/// Wraps `effects` which happened in database `db` between a `SELECT` of it
+/// and a `SELECT 0`, unless `db` is 0 already (or there aren't any).
+pub fn in_database(db: usize, effects: Vec<Command>) -> Vec<Command> {
+  if db == 0 || effects.is_empty() {
+    return effects;
+  }
+  let mut wrapped = Vec::with_capacity(effects.len() + 2);
+  wrapped.push(Command::Select { db });
+  wrapped.extend(effects);
+  wrapped.push(Command::Select { db: 0 });
+  wrapped
+}
+
+/// Returns the commands which reproduce what the deterministic `command`
+/// did, given its `reply`. Writes which changed nothing have no effects.
+pub fn effects(command: &Command, reply: &Value) -> Vec<Command> {
+  match (command, reply) {
+    // a `SET` whose condition didn't hold replies with nothing, or with `GET`,
+    // with the old value, which shows whether the key existed. Otherwise
+    // the condition has already been checked, and reading the old value
+    // doesn't need to be repeated.
+    (Command::Set { .. }, Value::Nothing) => Vec::new(),
+    (Command::SetAndGet { options, .. }, reply)
+      if options
+        .condition
+        .is_some_and(|c| !c.allows(*reply != Value::Nothing)) =>
+    {
+      Vec::new()
+    }
+    (
+      Command::Set {
+        key,
+        value,
+        options,
+      }
+      | Command::SetAndGet {
+        key,
+        value,
+        options,
+      },
+      _,
+    ) => match options.expiration {
+      // a deadline which has already passed deleted the key
+      Some(SetExpiration::PxAt(unix_time_ms))
+        if unix_time_ms <= clock::unix_time_ms() as i64 =>
+      {
+        vec![Command::Delete {
+          keys: vec![key.clone()],
+        }]
+      }
+      expiration => vec![Command::Set {
+        key:     key.clone(),
+        value:   value.clone(),
+        options: SetOptions {
+          expiration,
+          condition: None,
+        },
+      }],
+    },
+    (Command::GetDelete { .. }, Value::Nothing) => Vec::new(),
+    (Command::GetDelete { key }, _) => {
+      vec![Command::Delete {
+        keys: vec![key.clone()],
+      }]
+    }
+    // the condition has already been checked, and a deadline which has
+    // already passed deleted the key
+    (Command::PExpireAt { .. }, Value::Integer(0)) => Vec::new(),
+    (
+      Command::PExpireAt {
+        key, unix_time_ms, ..
+      },
+      _,
+    ) => {
+      if *unix_time_ms <= clock::unix_time_ms() as i64 {
+        vec![Command::Delete {
+          keys: vec![key.clone()],
+        }]
+      } else {
+        vec![Command::PExpireAt {
+          key:          key.clone(),
+          unix_time_ms: *unix_time_ms,
+          condition:    None,
+        }]
+      }
+    }
+    // only the fields which were given the deadline (1) or deleted by it (2)
+    // changed, and their condition has already been checked. Deleted fields
+    // are deleted explicitly, rather than leaving replicas to decide that the
+    // deadline has passed.
+    #[cfg(feature = "hashes")]
+    (
+      Command::HashPExpireAt {
+        key,
+        unix_time_ms,
+        fields,
+        ..
+      },
+      Value::Array(replies),
+    ) => {
+      let with_reply = |code| {
+        fields
+          .iter()
+          .zip(replies)
+          .filter(|(_, reply)| **reply == Value::Integer(code))
+          .map(|(field, _)| field.clone())
+          .collect::<Vec<_>>()
+      };
+      let (expiring, deleted) = (with_reply(1), with_reply(2));
+      let mut effects = Vec::new();
+      if !expiring.is_empty() {
+        effects.push(Command::HashPExpireAt {
+          key:          key.clone(),
+          unix_time_ms: *unix_time_ms,
+          condition:    None,
+          fields:       expiring,
+        });
+      }
+      if !deleted.is_empty() {
+        effects.push(Command::HashDelete {
+          key:    key.clone(),
+          fields: deleted,
+        });
+      }
+      effects
+    }
+    _ => vec![command.clone()],
+  }
+}
+
+/// Runs `command` with `run` (typically `backend`'s
+/// [`execute_in()`](Backend::execute_in) database `db`), and then runs
+/// `hooks` with its effects if it was a successful write, after the
+/// deletions of any data `backend` expired meanwhile.
+///
+/// This is the one place which decides what counts as a write and what it
+/// did, so every subsystem sees the same writes. Writes are made
+/// deterministic (see [`make_deterministic()`]) before they run. All the
+/// hooks run even if one fails, and the first error is returned, though the
+/// write has still been applied. Errors propagating the expirations of a
+/// read are only logged, since its reply is still correct.
+pub async fn execute<B, F, Fut>(
+  hooks: &[&dyn WriteHook],
+  backend: &B,
+  db: usize,
+  command: Command,
+  run: F,
+) -> KraglinResult
+where
+  B: Backend,
+  F: FnOnce(Command) -> Fut,
+  Fut: Future<Output = KraglinResult>,
+{
+  if hooks.is_empty() {
+    return run(command).await;
+  }
+
+  let is_write = command.is_write();
+  let executed = if is_write {
+    make_deterministic(command.clone())
+  } else {
+    command.clone()
+  };
+  let result = run(executed.clone()).await;
+  let expirations = backend.take_expirations();
+  propagate(hooks, db, &command, &executed, expirations, result).await
+}
+
+/// Runs `commands` as a unit with `run` (typically `backend`'s
+/// [`execute_batch()`](Backend::execute_batch) on database `db`), and then
+/// runs `hooks` as [`execute()`] does for each of them in order. The
+/// deletions of any data `backend` expired meanwhile go with the first.
+pub async fn execute_batch<B, F, Fut>(
+  hooks: &[&dyn WriteHook],
+  backend: &B,
+  db: usize,
+  commands: Vec<Command>,
+  run: F,
+) -> Vec<KraglinResult>
+where
+  B: Backend,
+  F: FnOnce(Vec<Command>) -> Fut,
+  Fut: Future<Output = Vec<KraglinResult>>,
+{
+  if hooks.is_empty() {
+    return run(commands).await;
+  }
+
+  let executed = commands
+    .iter()
+    .map(|command| {
+      if command.is_write() {
+        make_deterministic(command.clone())
+      } else {
+        command.clone()
+      }
+    })
+    .collect::<Vec<_>>();
+  let results = run(executed.clone()).await;
+  let mut expirations = backend.take_expirations();
+  let mut replies = Vec::with_capacity(results.len());
+  for ((command, executed), result) in
+    commands.iter().zip(&executed).zip(results)
+  {
+    let expirations = std::mem::take(&mut expirations);
+    replies
+      .push(propagate(hooks, db, command, executed, expirations, result).await);
+  }
+  replies
+}
+
+/// Runs `command` on `backend`'s database `db` with
+/// [`execute_streaming()`](Backend::execute_streaming), running `hooks` as
+/// [`execute()`] does. Writes aren't streamed, since they run through
+/// [`execute()`] and their replies are small anyway. Reads are, and run the
+/// hooks with the deletions of any data `backend` expired meanwhile once
+/// their stream has finished.
+pub fn execute_streaming<'a, B: Backend>(
+  hooks: Vec<&'a dyn WriteHook>,
+  backend: &'a B,
+  db: usize,
+  command: Command,
+  touch: bool,
+) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send + 'a {
+  if command.is_write() {
+    return futures::stream::once(async move {
+      execute(&hooks, backend, db, command, |c| {
+        backend.execute_in(db, c, touch)
+      })
+      .await
+      .map(ReplyChunk::Value)
+    })
+    .left_stream();
+  }
+
+  let chunks = backend.execute_streaming(db, command.clone(), touch);
+  let expired = futures::stream::once(async move {
+    let expirations = backend.take_expirations();
+    if hooks.is_empty() || expirations.is_empty() {
+      return;
+    }
+    // the reply has already been sent, so errors are only logged
+    if let Some(e) = run_hooks(&hooks, &command, &expirations).await {
+      tracing::warn!(
+        "failed to propagate expirations during `{}`: {e}",
+        command.full_name()
+      );
+    }
+  });
+  chunks
+    .chain(expired.filter_map(|()| futures::future::ready(None)))
+    .right_stream()
+}
+
+/// Runs `hooks` with `expirations` followed by the effects of `executed`,
+/// which is `command` as it ran, if it was a successful write. Returns
+/// `result`, or the first hook's error if it was a write.
+async fn propagate(
+  hooks: &[&dyn WriteHook],
+  db: usize,
+  command: &Command,
+  executed: &Command,
+  mut effects: Vec<Command>,
+  result: KraglinResult,
+) -> KraglinResult {
+  let is_write = command.is_write();
+  if let (true, Ok(reply)) = (is_write, &result) {
+    effects.extend(in_database(db, self::effects(executed, reply)));
+  }
+  if effects.is_empty() {
+    return result;
+  }
+
+  let error = run_hooks(hooks, command, &effects).await;
+  match error {
+    Some(e) if is_write && result.is_ok() => Err(e),
+    Some(e) => {
+      tracing::warn!(
+        "failed to propagate expirations during `{}`: {e}",
+        command.full_name()
+      );
+      result
+    }
+    None => result,
+  }
+}
+
+/// Runs every hook with `effects`, returning the first error.
+async fn run_hooks(
+  hooks: &[&dyn WriteHook],
+  command: &Command,
+  effects: &[Command],
+) -> Option<KraglinError> {
+  let mut error = None;
+  for hook in hooks {
+    if let Err(e) = hook.on_write(command, effects).await {
+      error.get_or_insert(e);
+    }
+  }
+  error
+}
+
+/// A `Backend` wrapper which runs [`WriteHook`]s after the writes applied to
+/// an inner backend.
+pub struct HookedBackend<B: Backend> {
+  inner: B,
+  hooks: Vec<Arc<dyn WriteHook>>,
+}
+
+impl<B: Backend> HookedBackend<B> {
+  /// Wraps `inner`, running `hooks` in order after each of its writes.
+  pub fn with_hooks(inner: B, hooks: Vec<Arc<dyn WriteHook>>) -> Self {
+    if !hooks.is_empty() {
+      inner.track_expirations();
+    }
+    HookedBackend { inner, hooks }
+  }
+
+  /// Returns the wrapped backend.
+  pub fn inner(&self) -> &B { &self.inner }
+
+  fn hooks(&self) -> Vec<&dyn WriteHook> {
+    self.hooks.iter().map(|h| &**h).collect()
+  }
+}
+
+impl<B: Backend> Backend for HookedBackend<B> {
+  /// Creates a hooked backend with no hooks. Use
+  /// [`HookedBackend::with_hooks`] to add hooks.
+  fn new(config: BackendConfig) -> Result<Self> {
+    Ok(HookedBackend::with_hooks(B::new(config)?, Vec::new()))
+  }
+
+  async fn execute(&self, command: Command) -> KraglinResult {
+    execute(&self.hooks(), &self.inner, 0, command, |c| {
+      self.inner.execute(c)
+    })
+    .await
+  }
+
+  async fn execute_without_touch(&self, command: Command) -> KraglinResult {
+    execute(&self.hooks(), &self.inner, 0, command, |c| {
+      self.inner.execute_without_touch(c)
+    })
+    .await
+  }
+
+  fn databases(&self) -> usize { self.inner.databases() }
+
+  async fn execute_in(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> KraglinResult {
+    execute(&self.hooks(), &self.inner, db, command, |c| {
+      self.inner.execute_in(db, c, touch)
+    })
+    .await
+  }
+
+  async fn execute_batch(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    execute_batch(&self.hooks(), &self.inner, db, commands, |c| {
+      self.inner.execute_batch(db, c, touch)
+    })
+    .await
+  }
+
+  // reads aren't hooked, so they can all go to the inner backend
+  async fn execute_read_only(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    self.inner.execute_read_only(db, commands, touch).await
+  }
+
+  fn execute_streaming(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send {
+    execute_streaming(self.hooks(), &self.inner, db, command, touch)
+  }
+
+  async fn defragment(&self) { self.inner.defragment().await }
+
+  async fn shutdown(&self) -> Result<()> { self.inner.shutdown().await }
+
+  fn readiness(&self) -> Result<(), String> { self.inner.readiness() }
+
+  fn subscribe_events(&self) -> broadcast::Receiver<KeyEvent> {
+    self.inner.subscribe_events()
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+  };
+
+  use futures::{future::BoxFuture, FutureExt, StreamExt};
+
+  use super::{HookedBackend, WriteHook};
+  #[cfg(feature = "hashes")]
+  use crate::command::ExpireCondition;
+  use crate::{
+    backends::{
+      simple::SimpleBackend, Backend, BackendConfig, BackendExt, ReplyChunk,
+    },
+    command::{Command, SetBuilder},
+    value::Value,
+    KraglinError,
+  };
+
+  /// Records the effects of every write it's told about.
+  #[derive(Default)]
+  struct Record(Mutex<Vec<Command>>);
+
+  impl Record {
+    fn names(&self) -> Vec<String> {
+      self
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(Command::full_name)
+        .collect()
+    }
+  }
+
+  impl WriteHook for Record {
+    fn on_write<'a>(
+      &'a self,
+      _: &'a Command,
+      effects: &'a [Command],
+    ) -> BoxFuture<'a, Result<(), KraglinError>> {
+      self.0.lock().unwrap().extend_from_slice(effects);
+      async { Ok(()) }.boxed()
+    }
+  }
+
+  /// Fails every write.
+  struct Fail;
+
+  impl WriteHook for Fail {
+    fn on_write<'a>(
+      &'a self,
+      _: &'a Command,
+      _: &'a [Command],
+    ) -> BoxFuture<'a, Result<(), KraglinError>> {
+      async { Err(KraglinError::ReplicationFailed("nope".into())) }.boxed()
+    }
+  }
+
+  #[tokio::test]
+  async fn hooks_see_successful_writes() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+
+    backend
+      .SET("a", Value::BulkString("x".into()))
+      .await
+      .unwrap();
+    backend.GET("a").await.unwrap();
+    backend.SET_AND_GET_OLD("b", 1).await.unwrap();
+    backend.GET_AND_DELETE("b").await.unwrap();
+    backend.GET_AND_DELETE("b").await.unwrap();
+    // failed writes aren't propagated
+    assert!(backend.INCR("a").await.is_err());
+    backend.DEL(vec!["a".into()]).await.unwrap();
+
+    assert_eq!(record.names(), vec!["set", "set", "del", "del"]);
+  }
+
+  #[tokio::test]
+  async fn writes_to_other_databases_are_wrapped_in_selects() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+
+    backend.SET("a", 0).await.unwrap();
+    backend
+      .execute_in(2, Command::set("a", "2").build(), true)
+      .await
+      .unwrap();
+    backend
+      .execute_in(2, Command::Get { key: "a".into() }, true)
+      .await
+      .unwrap();
+
+    assert_eq!(record.names(), vec!["set", "select", "set", "select"]);
+    let selects = {
+      let effects = record.0.lock().unwrap();
+      (effects[1].clone(), effects[3].clone())
+    };
+    assert!(matches!(
+      selects,
+      (Command::Select { db: 2 }, Command::Select { db: 0 })
+    ));
+    // the databases are separate
+    assert_eq!(backend.GET("a").await.unwrap(), Value::Integer(0));
+  }
+
+  #[tokio::test]
+  async fn batched_writes_are_hooked_in_order() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+
+    let replies = backend
+      .execute_batch(
+        0,
+        vec![
+          Command::set("a", "x").build(),
+          Command::Get { key: "a".into() },
+          Command::Increment { key: "a".into() },
+          Command::Delete {
+            keys: vec!["a".into()],
+          },
+        ],
+        true,
+      )
+      .await;
+
+    assert_eq!(replies.len(), 4);
+    assert_eq!(
+      replies[1].as_ref().unwrap(),
+      &Value::SimpleString("x".into())
+    );
+    assert!(replies[2].is_err());
+    assert_eq!(record.names(), vec!["set", "del"]);
+  }
+
+  #[tokio::test]
+  async fn hook_errors_are_returned_after_every_hook_runs() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![Arc::new(Fail), record.clone()],
+    );
+
+    let result = backend.SET("a", Value::Integer(1)).await;
+    assert!(matches!(result, Err(KraglinError::ReplicationFailed(_))));
+    assert_eq!(record.names(), vec!["set"]);
+    // the write was still applied
+    assert_eq!(backend.GET("a").await.unwrap(), Value::Integer(1));
+  }
+
+  #[cfg(feature = "hashes")]
+  #[tokio::test(start_paused = true)]
+  async fn relative_expirations_propagate_as_deadlines() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+    let fields = |fields: &[&str]| fields.iter().map(|&f| f.into()).collect();
+
+    backend.HSET("h", "a", 1).await.unwrap();
+    backend.HSET("h", "b", 2).await.unwrap();
+    let deadline = crate::clock::unix_time_ms() as i64 + 10_000;
+    backend
+      .HEXPIRE("h", 10, fields(&["a", "missing"]))
+      .await
+      .unwrap();
+    // nothing changed, so nothing is propagated
+    backend
+      .execute(Command::HashPExpire {
+        key:          "h".into(),
+        milliseconds: 5000,
+        condition:    Some(ExpireCondition::Gt),
+        fields:       fields(&["a"]),
+      })
+      .await
+      .unwrap();
+    backend.HPEXPIRE("h", 0, fields(&["b"])).await.unwrap();
+
+    let effects = record.0.lock().unwrap().split_off(2);
+    assert_eq!(effects, vec![
+      Command::HashPExpireAt {
+        key:          "h".into(),
+        unix_time_ms: deadline,
+        condition:    None,
+        fields:       fields(&["a"]),
+      },
+      Command::HashDelete {
+        key:    "h".into(),
+        fields: fields(&["b"]),
+      },
+    ]);
+    assert_eq!(
+      backend.HGETALL("h").await.unwrap(),
+      Value::Map([("a".into(), Value::Integer(1))].into())
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn sets_with_options_propagate_as_deadlines() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+
+    let deadline = crate::clock::unix_time_ms() as i64 + 10_000;
+    let set = |command: SetBuilder| backend.execute(command.build());
+    set(Command::set("a", 1).ex(10).nx()).await.unwrap();
+    // the conditions don't hold, so nothing changed
+    set(Command::set("a", 2).nx()).await.unwrap();
+    set(Command::set("b", 2).xx().get()).await.unwrap();
+    // a deadline which has already passed deletes the key
+    set(Command::set("a", 3).pxat(1).get()).await.unwrap();
+
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::set("a", 1).pxat(deadline).build(),
+      Command::Delete {
+        keys: vec!["a".into()],
+      },
+    ]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn expiring_keys_propagate_as_deadlines_and_deletions() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+    backend.SET("a", 1).await.unwrap();
+    let Value::BulkString(payload) = backend.DUMP("a").await.unwrap() else {
+      panic!("DUMP should return a bulk string");
+    };
+    record.0.lock().unwrap().clear();
+
+    let deadline = crate::clock::unix_time_ms() as i64 + 100;
+    backend
+      .RESTORE("b", 100, payload.clone(), false)
+      .await
+      .unwrap();
+    tokio::time::advance(Duration::from_millis(100)).await;
+    assert_eq!(backend.GET("b").await.unwrap(), Value::Nothing);
+
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::Restore {
+        key: "b".into(),
+        ttl: deadline,
+        payload,
+        replace: false,
+        absttl: true,
+      },
+      Command::Delete {
+        keys: vec!["b".into()],
+      },
+    ]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn only_the_keys_commands_access_are_expired() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+    backend.SET("a", 1).await.unwrap();
+    backend.SET("b", 2).await.unwrap();
+    backend.PEXPIRE("a", 100).await.unwrap();
+    backend.PEXPIRE("b", 100).await.unwrap();
+    record.0.lock().unwrap().clear();
+    tokio::time::advance(Duration::from_millis(100)).await;
+
+    // commands which don't name keys don't sweep the keyspace, except for
+    // `KEYS`, which reads all of it
+    backend.SCAN(0, None, None).await.unwrap();
+    backend.INFO().await.unwrap();
+    assert!(record.0.lock().unwrap().is_empty());
+    backend.GET("a").await.unwrap();
+    backend.KEYS().await.unwrap();
+
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::Delete {
+        keys: vec!["a".into()],
+      },
+      Command::Delete {
+        keys: vec!["b".into()],
+      },
+    ]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn key_expirations_propagate_as_deadlines() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+    backend.SET("a", 1).await.unwrap();
+    backend.SET("b", 2).await.unwrap();
+    record.0.lock().unwrap().clear();
+
+    let now = crate::clock::unix_time_ms() as i64;
+    backend.EXPIRE("a", 10).await.unwrap();
+    backend.PEXPIRE("b", 500).await.unwrap();
+    // nothing changed, so nothing is propagated
+    backend.EXPIRE("missing", 10).await.unwrap();
+    backend
+      .execute(Command::expireat("a", 1).nx().build())
+      .await
+      .unwrap();
+    // and a deadline which has already passed is a deletion
+    backend.EXPIREAT("b", 1).await.unwrap();
+
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::PExpireAt {
+        key:          "a".into(),
+        unix_time_ms: now + 10_000,
+        condition:    None,
+      },
+      Command::PExpireAt {
+        key:          "b".into(),
+        unix_time_ms: now + 500,
+        condition:    None,
+      },
+      Command::Delete {
+        keys: vec!["b".into()],
+      },
+    ]);
+  }
+
+  /// Applies the effects it's told about to a replica.
+  #[cfg(feature = "hashes")]
+  struct Replicate(SimpleBackend);
+
+  #[cfg(feature = "hashes")]
+  impl WriteHook for Replicate {
+    fn on_write<'a>(
+      &'a self,
+      _: &'a Command,
+      effects: &'a [Command],
+    ) -> BoxFuture<'a, Result<(), KraglinError>> {
+      async move {
+        for effect in effects {
+          self.0.execute(effect.clone()).await?;
+        }
+        Ok(())
+      }
+      .boxed()
+    }
+  }
+
+  #[cfg(feature = "hashes")]
+  #[tokio::test(start_paused = true)]
+  async fn expirations_propagate_as_deletions() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+    let fields = |fields: &[&str]| fields.iter().map(|&f| f.into()).collect();
+
+    backend.HSET("h", "a", 1).await.unwrap();
+    backend.HSET("h", "b", 2).await.unwrap();
+    backend.HPEXPIRE("h", 100, fields(&["a"])).await.unwrap();
+    record.0.lock().unwrap().clear();
+
+    // expirations are noticed by reads too, and propagated with them
+    tokio::time::advance(Duration::from_millis(100)).await;
+    backend.HGET("h", "b").await.unwrap();
+    backend.HPEXPIRE("h", 100, fields(&["b"])).await.unwrap();
+    tokio::time::advance(Duration::from_millis(100)).await;
+    backend.EXISTS(vec!["h".into()]).await.unwrap();
+
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::HashDelete {
+        key:    "h".into(),
+        fields: fields(&["a"]),
+      },
+      Command::HashPExpireAt {
+        key:          "h".into(),
+        unix_time_ms: crate::clock::unix_time_ms() as i64,
+        condition:    None,
+        fields:       fields(&["b"]),
+      },
+      // the hash's last field expired, so it was deleted with it
+      Command::Delete {
+        keys: vec!["h".into()],
+      },
+    ]);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn streamed_reads_propagate_their_expirations_once_finished() {
+    let record = Arc::new(Record::default());
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![record.clone()],
+    );
+
+    backend.SET("a", 1).await.unwrap();
+    backend
+      .execute_in(1, Command::set("b", 1).px(100).build(), true)
+      .await
+      .unwrap();
+    record.0.lock().unwrap().clear();
+    tokio::time::advance(Duration::from_millis(100)).await;
+
+    let mut chunks =
+      std::pin::pin!(backend.execute_streaming(1, Command::Keys, true));
+    assert_eq!(
+      chunks.next().await.unwrap().unwrap(),
+      ReplyChunk::ArrayHeader(0)
+    );
+    assert!(chunks.next().await.is_none());
+    assert_eq!(record.0.lock().unwrap()[..], [
+      Command::Select { db: 1 },
+      Command::Delete {
+        keys: vec!["b".into()],
+      },
+      Command::Select { db: 0 },
+    ]);
+  }
+
+  #[cfg(feature = "hashes")]
+  #[tokio::test(start_paused = true)]
+  async fn replicas_only_expire_when_their_master_does() {
+    let replica = Arc::new(Replicate(
+      SimpleBackend::new(BackendConfig {
+        replica: true,
+        ..Default::default()
+      })
+      .unwrap(),
+    ));
+    let master = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![replica.clone()],
+    );
+    let replica = &replica.0;
+    let fields = |fields: &[&str]| fields.iter().map(|&f| f.into()).collect();
+    let mut events = replica.subscribe_events();
+
+    master.HSET("h", "a", 1).await.unwrap();
+    master.HSET("h", "b", 2).await.unwrap();
+    master
+      .HPEXPIRE("h", 100, fields(&["a", "b"]))
+      .await
+      .unwrap();
+    while events.try_recv().is_ok() {}
+
+    // a replica whose clock is ahead of its master's hides the expired
+    // fields, but keeps them until its master has expired them too
+    tokio::time::advance(Duration::from_millis(100)).await;
+    assert_eq!(replica.HGET("h", "a").await.unwrap(), Value::Nothing);
+    assert_eq!(
+      replica.HGETALL("h").await.unwrap(),
+      Value::Map(Default::default())
+    );
+    assert_eq!(replica.KEYS().await.unwrap(), Value::Array(vec![]));
+    assert!(events.try_recv().is_err());
+    assert!(replica.take_expirations().is_empty());
+
+    master.HGET("h", "a").await.unwrap();
+    assert_eq!(
+      events.try_recv().unwrap().kind,
+      crate::backends::events::KeyEventKind::Delete
+    );
+
+    // and one whose clock is behind deletes fields as soon as its master
+    // does, even though they haven't expired by its own clock yet
+    master.HSET("h", "a", 1).await.unwrap();
+    let deadline = crate::clock::unix_time_ms() as i64 + 100;
+    replica
+      .HPEXPIREAT("h", deadline, fields(&["a"]))
+      .await
+      .unwrap();
+    master
+      .HPEXPIREAT("h", deadline - 200, fields(&["a"]))
+      .await
+      .unwrap();
+    assert_eq!(
+      replica.HGETALL("h").await.unwrap(),
+      Value::Map(Default::default())
+    );
+    assert_eq!(
+      replica.EXISTS(vec!["h".into()]).await.unwrap(),
+      Value::Integer(0)
+    );
+  }
+}
@@ -0,0 +1,430 @@
+//! A `Backend` wrapper which applies writes locally and fans them out to a set
+//! of downstream RESP endpoints.
+
+use std::sync::Arc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use color_eyre::eyre::Result;
+use futures::{future::BoxFuture, FutureExt, Stream};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+  sync::{broadcast, mpsc, oneshot},
+};
+
+use crate::{
+  backends::{
+    events::KeyEvent,
+    propagate::{self, WriteHook},
+    Backend, BackendConfig, ReplyChunk,
+  },
+  command::Command,
+  resp,
+  value::Value,
+  KraglinError, KraglinResult,
+};
+
+/// How a [`ReplicatedBackend`] waits on its downstreams before replying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckMode {
+  /// Wait for every downstream to acknowledge a write before replying. If any
+  /// downstream fails, the write returns
+  /// [`KraglinError::ReplicationFailed`], though it has still been applied
+  /// locally.
+  Sync,
+  /// Queue the write for each downstream and reply immediately.
+  #[default]
+  Async,
+}
+
+/// A batch of writes queued for a downstream, with how many commands it
+/// holds and an optional acknowledgment channel.
+type QueuedWrite = (Bytes, usize, Option<oneshot::Sender<Result<(), String>>>);
+
+/// A handle to a downstream kraglin/Redis endpoint.
+///
+/// Writes are sent in order by a dedicated task which owns the connection, a
+/// batch at a time, so that one write's effects are never interleaved with
+/// another's. The connection is made lazily and re-established on the next
+/// write if it fails.
+#[derive(Debug, Clone)]
+pub struct Downstream {
+  address: Arc<str>,
+  queue:   mpsc::UnboundedSender<QueuedWrite>,
+}
+
+impl Downstream {
+  /// Creates a new downstream for the given address, spawning its writer
+  /// task. Must be called from within a tokio runtime.
+  pub fn new(address: impl Into<Arc<str>>) -> Self {
+    let address = address.into();
+    let (queue, rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_downstream(address.clone(), rx));
+    Downstream { address, queue }
+  }
+
+  /// Returns the address of the downstream.
+  pub fn address(&self) -> &str { &self.address }
+
+  /// Sends `payload`, which holds `commands` encoded commands.
+  async fn send(
+    &self,
+    payload: Bytes,
+    commands: usize,
+    ack_mode: AckMode,
+  ) -> Result<(), String> {
+    match ack_mode {
+      AckMode::Async => self
+        .queue
+        .send((payload, commands, None))
+        .map_err(|_| "downstream task has stopped".to_string()),
+      AckMode::Sync => {
+        let (tx, rx) = oneshot::channel();
+        self
+          .queue
+          .send((payload, commands, Some(tx)))
+          .map_err(|_| "downstream task has stopped".to_string())?;
+        rx.await
+          .map_err(|_| "downstream task has stopped".to_string())?
+      }
+    }
+  }
+}
+
+async fn run_downstream(
+  address: Arc<str>,
+  mut rx: mpsc::UnboundedReceiver<QueuedWrite>,
+) {
+  let mut connection = None;
+
+  while let Some((payload, commands, ack)) = rx.recv().await {
+    let result =
+      write_to_downstream(&address, &mut connection, &payload, commands).await;
+    if let Err(e) = &result {
+      tracing::warn!("failed to replicate write to {address}: {e}");
+      // drop the connection so that it gets re-established next time
+      connection = None;
+    }
+    if let Some(ack) = ack {
+      let _ = ack.send(result);
+    }
+  }
+}
+
+/// Writes `payload`, which holds `commands` encoded commands, and reads their
+/// replies. The connection is kept with the buffer its replies are read
+/// into.
+async fn write_to_downstream(
+  address: &str,
+  connection: &mut Option<(TcpStream, BytesMut)>,
+  payload: &[u8],
+  commands: usize,
+) -> Result<(), String> {
+  let (stream, buf) = match connection {
+    Some(connection) => connection,
+    None => {
+      let stream = TcpStream::connect(address)
+        .await
+        .map_err(|e| format!("failed to connect: {e}"))?;
+      connection.insert((stream, BytesMut::new()))
+    }
+  };
+
+  stream
+    .write_all(payload)
+    .await
+    .map_err(|e| format!("failed to write: {e}"))?;
+  for _ in 0..commands {
+    read_reply(stream, buf).await?;
+  }
+  Ok(())
+}
+
+/// Reads a single RESP reply from the stream into `buf`, reading more
+/// whenever it doesn't hold a whole one yet. Returns an error if the reply
+/// (or any nested reply) is an error.
+async fn read_reply(
+  stream: &mut TcpStream,
+  buf: &mut BytesMut,
+) -> Result<(), String> {
+  loop {
+    let reply = resp::decode(buf).map_err(|e| format!("invalid reply: {e}"))?;
+    if let Some(reply) = reply {
+      return match first_error(&reply) {
+        Some(e) => Err(format!("downstream replied with error: {e}")),
+        None => Ok(()),
+      };
+    }
+    let n = stream
+      .read_buf(buf)
+      .await
+      .map_err(|e| format!("failed to read reply: {e}"))?;
+    if n == 0 {
+      return Err("connection closed before reply".to_string());
+    }
+  }
+}
+
+/// Returns the first error in `reply`, looking inside arrays.
+fn first_error(reply: &Value) -> Option<&str> {
+  match reply {
+    Value::Error(e) => Some(e),
+    Value::Array(replies) => replies.iter().find_map(first_error),
+    _ => None,
+  }
+}
+
+/// Encodes a write command as a RESP array of bulk strings onto `buf`.
+/// Returns `false`, leaving `buf` as it was, if one of its values cannot be
+/// represented as an argument.
+fn encode_command(command: &Command, buf: &mut BytesMut) -> bool {
+  let Value::Array(args) = command.to_resp() else {
+    unreachable!("commands are encoded as arrays");
+  };
+  if !args.iter().all(|arg| matches!(arg, Value::BulkString(_))) {
+    return false;
+  }
+
+  buf.put_slice(format!("*{}\r\n", args.len()).as_bytes());
+  for arg in args {
+    let Value::BulkString(arg) = arg else {
+      unreachable!("every argument is a bulk string");
+    };
+    buf.put_slice(format!("${}\r\n", arg.len()).as_bytes());
+    buf.put_slice(&arg);
+    buf.put_slice(b"\r\n");
+  }
+  true
+}
+
+/// A `Backend` wrapper which applies writes to an inner backend and then fans
+/// them out to a set of [`Downstream`] endpoints.
+///
+/// This is a building block for replication, not a full replication protocol:
+/// downstreams are not synchronized on connect, and writes which fail to
+/// reach a downstream are not retried.
+pub struct ReplicatedBackend<B: Backend> {
+  inner:       B,
+  downstreams: Vec<Downstream>,
+  ack_mode:    AckMode,
+}
+
+impl<B: Backend> ReplicatedBackend<B> {
+  /// Wraps `inner`, replicating its writes to `downstreams`.
+  pub fn with_downstreams(
+    inner: B,
+    downstreams: Vec<Downstream>,
+    ack_mode: AckMode,
+  ) -> Self {
+    if !downstreams.is_empty() {
+      inner.track_expirations();
+    }
+    ReplicatedBackend {
+      inner,
+      downstreams,
+      ack_mode,
+    }
+  }
+
+  /// Returns the wrapped backend.
+  pub fn inner(&self) -> &B { &self.inner }
+
+  /// Returns the downstreams writes are replicated to.
+  pub fn downstreams(&self) -> &[Downstream] { &self.downstreams }
+
+  async fn replicate(&self, effects: &[Command]) -> Result<(), KraglinError> {
+    if self.downstreams.is_empty() {
+      return Ok(());
+    }
+
+    // the effects are sent as one batch, so that another write's can't land
+    // in between them, e.g. after a `SELECT`
+    let (mut payload, mut commands) = (BytesMut::new(), 0);
+    for effect in effects {
+      if encode_command(effect, &mut payload) {
+        commands += 1;
+      } else {
+        tracing::warn!(
+          "cannot replicate `{}` command: value has no RESP argument form",
+          effect.command_name()
+        );
+      }
+    }
+    if commands == 0 {
+      return Ok(());
+    }
+
+    let payload = payload.freeze();
+    let mut failures = Vec::new();
+    for downstream in &self.downstreams {
+      if let Err(e) = downstream
+        .send(payload.clone(), commands, self.ack_mode)
+        .await
+      {
+        failures.push(format!("{}: {e}", downstream.address()));
+      }
+    }
+
+    if !failures.is_empty() {
+      return Err(KraglinError::ReplicationFailed(failures.join("; ")));
+    }
+    Ok(())
+  }
+}
+
+impl<B: Backend> WriteHook for ReplicatedBackend<B> {
+  fn on_write<'a>(
+    &'a self,
+    _: &'a Command,
+    effects: &'a [Command],
+  ) -> BoxFuture<'a, Result<(), KraglinError>> {
+    self.replicate(effects).boxed()
+  }
+}
+
+impl<B: Backend> Backend for ReplicatedBackend<B> {
+  /// Creates a replicated backend with no downstreams. Use
+  /// [`ReplicatedBackend::with_downstreams`] to replicate to downstreams.
+  fn new(config: BackendConfig) -> Result<Self> {
+    Ok(ReplicatedBackend::with_downstreams(
+      B::new(config)?,
+      Vec::new(),
+      AckMode::default(),
+    ))
+  }
+
+  async fn execute(&self, command: Command) -> KraglinResult {
+    propagate::execute(&[self], &self.inner, 0, command, |c| {
+      self.inner.execute(c)
+    })
+    .await
+  }
+
+  async fn execute_without_touch(&self, command: Command) -> KraglinResult {
+    propagate::execute(&[self], &self.inner, 0, command, |c| {
+      self.inner.execute_without_touch(c)
+    })
+    .await
+  }
+
+  fn databases(&self) -> usize { self.inner.databases() }
+
+  async fn execute_in(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> KraglinResult {
+    propagate::execute(&[self], &self.inner, db, command, |c| {
+      self.inner.execute_in(db, c, touch)
+    })
+    .await
+  }
+
+  async fn execute_batch(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    propagate::execute_batch(&[self], &self.inner, db, commands, |c| {
+      self.inner.execute_batch(db, c, touch)
+    })
+    .await
+  }
+
+  // reads aren't replicated, so they can all go to the inner backend
+  async fn execute_read_only(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    self.inner.execute_read_only(db, commands, touch).await
+  }
+
+  fn execute_streaming(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send {
+    propagate::execute_streaming(vec![self], &self.inner, db, command, touch)
+  }
+
+  async fn defragment(&self) { self.inner.defragment().await }
+
+  async fn shutdown(&self) -> Result<()> { self.inner.shutdown().await }
+
+  fn readiness(&self) -> Result<(), String> { self.inner.readiness() }
+
+  fn subscribe_events(&self) -> broadcast::Receiver<KeyEvent> {
+    self.inner.subscribe_events()
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+  };
+
+  use super::{AckMode, Downstream, ReplicatedBackend};
+  use crate::{
+    backends::{simple::SimpleBackend, Backend, BackendConfig, BackendExt},
+    value::Value,
+    KraglinError,
+  };
+
+  #[tokio::test]
+  async fn sync_writes_reach_downstream() -> Result<(), KraglinError> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    let received = tokio::spawn(async move {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      let expected = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+      let mut buf = vec![0; expected.len()];
+      stream.read_exact(&mut buf).await.unwrap();
+      stream.write_all(b"+OK\r\n").await.unwrap();
+      buf == expected
+    });
+
+    let backend = ReplicatedBackend::with_downstreams(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![Downstream::new(address)],
+      AckMode::Sync,
+    );
+    backend.SET("a", Value::Integer(1)).await?;
+    // reads are not replicated
+    backend.GET("a").await?;
+
+    assert!(received.await.unwrap());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn sync_write_fails_on_downstream_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+
+    tokio::spawn(async move {
+      let (mut stream, _) = listener.accept().await.unwrap();
+      let mut buf = vec![0; 64];
+      let _ = stream.read(&mut buf).await.unwrap();
+      stream.write_all(b"-ERR nope\r\n").await.unwrap();
+    });
+
+    let backend = ReplicatedBackend::with_downstreams(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![Downstream::new(address)],
+      AckMode::Sync,
+    );
+    let result = backend.SET("a", Value::Integer(1)).await;
+
+    assert!(matches!(result, Err(KraglinError::ReplicationFailed(_))));
+    // the write was still applied locally
+    assert_eq!(backend.GET("a").await.unwrap(), Value::Integer(1));
+  }
+}
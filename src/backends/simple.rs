@@ -1,213 +1,1885 @@
-//! The naive `Backend` implementation, using a `Arc<Mutex<HashMap<SmolStr,
-//! StoredValue>>>`.
+//! The naive `Backend` implementation, using a mutex-guarded `HashMap<SmolStr,
+//! StoredValue>`.
 
 use std::{
-  collections::{BTreeMap, HashMap},
-  hash::Hash,
-  sync::Arc,
+  collections::{BTreeMap, BTreeSet, HashSet},
+  fmt::Write,
+  num::NonZeroUsize,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+  },
 };
 
+use color_eyre::eyre::Result;
+use futures::{Stream, StreamExt};
 use smol_str::SmolStr;
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, Mutex, MutexGuard};
 
+#[cfg(feature = "hashes")]
+use crate::value::field_size;
+#[cfg(feature = "json")]
+use crate::value::{json_type_name, JsonPath};
 use crate::{
-  backends::Backend,
-  command::Command,
-  value::{StoredValue, Value},
-  KraglinError,
+  backends::{
+    events::{KeyEvent, KeyEvents},
+    hotkeys::{HotKeys, DEFAULT_HOT_KEYS_SAMPLE_RATE},
+    interner::Interner,
+    keyspace::Keyspace,
+    keystats::KeyStats,
+    Backend, BackendConfig, ReplyChunk, DEFAULT_DATABASES,
+  },
+  clock,
+  command::{
+    Command, ExpireCondition, SetExpiration, SetOptions, DEFAULT_SCAN_COUNT,
+  },
+  data_dir::{database_deltas_dir, database_snapshot_file},
+  server::glob_match,
+  snapshot::SnapshotStore,
+  value::{Dump, StoredValue, Value},
+  version, KraglinError, KraglinResult,
 };
 
-/// A trait to extend `HashMap` to allow directly setting a key with `Option<V>`
-trait SettableHashMap<K: Eq + Hash, V: Hash> {
-  /// Sets a key with an optional value. If `val` is `Some()`, inserts the
-  /// value. If `None`, deletes the previous value if it existed.
-  fn set(&mut self, key: K, val: Option<V>);
+/// Collections are only shrunk if their capacity is this many times larger
+/// than their length, so that only dramatically shrunk keys are reallocated.
+const DEFRAG_OVERALLOCATION_FACTOR: usize = 4;
+/// Collections with a capacity below this are never worth shrinking.
+const DEFRAG_MIN_CAPACITY: usize = 64;
+
+fn is_overallocated(capacity: usize, len: usize) -> bool {
+  capacity >= DEFRAG_MIN_CAPACITY
+    && capacity > len.saturating_mul(DEFRAG_OVERALLOCATION_FACTOR)
+}
+
+/// Shrinks any over-allocated collections within the value. Returns whether
+/// anything was reallocated.
+fn defragment_value(value: &mut Value) -> bool {
+  match value {
+    Value::Array(a) => defragment_array(a),
+    Value::Map(m) => defragment_map(m),
+    Value::Set(s) => defragment_set(s),
+    _ => false,
+  }
+}
+
+/// Whether [`defragment_value()`] would reallocate anything within the value.
+fn needs_defragment(value: &Value) -> bool {
+  match value {
+    Value::Array(a) => {
+      is_overallocated(a.capacity(), a.len()) || a.iter().any(needs_defragment)
+    }
+    Value::Map(m) => m.values().any(needs_defragment),
+    Value::Set(s) => s.iter().any(needs_defragment),
+    _ => false,
+  }
+}
+
+fn defragment_array(array: &mut Vec<Value>) -> bool {
+  let mut reallocated = false;
+  for value in array.iter_mut() {
+    reallocated |= defragment_value(value);
+  }
+  if is_overallocated(array.capacity(), array.len()) {
+    array.shrink_to_fit();
+    reallocated = true;
+  }
+  reallocated
+}
+
+fn defragment_map(map: &mut BTreeMap<SmolStr, Value>) -> bool {
+  let mut reallocated = false;
+  for value in map.values_mut() {
+    reallocated |= defragment_value(value);
+  }
+  reallocated
+}
+
+/// Set members can't be changed in place, so a set holding over-allocated
+/// members is rebuilt from the shrunk members instead.
+fn defragment_set(set: &mut BTreeSet<Value>) -> bool {
+  if !set.iter().any(needs_defragment) {
+    return false;
+  }
+  *set = std::mem::take(set)
+    .into_iter()
+    .map(|mut member| {
+      defragment_value(&mut member);
+      member
+    })
+    .collect();
+  true
+}
+
+/// Normalizes a value into its canonical form as a set member, so that e.g.
+/// `Integer(2)` and `SimpleString("2")` are the same member. See
+/// [`Value::to_argument()`].
+#[cfg(feature = "sets")]
+fn set_member(value: &Value) -> Result<Value, KraglinError> {
+  value
+    .to_argument()
+    .map(Value::BulkString)
+    .ok_or(KraglinError::WrongType)
 }
 
-impl<K: Eq + Hash, V: Hash> SettableHashMap<K, V> for HashMap<K, V> {
-  fn set(&mut self, key: K, val: Option<V>) {
-    match val {
-      Some(v) => {
-        self.insert(key, v);
+/// Computes the members of the set at `a` which aren't in the set at `b`.
+/// Missing keys are treated as empty sets.
+#[cfg(feature = "sets")]
+fn set_difference(
+  m: &Keyspace,
+  a: &str,
+  b: &str,
+) -> Result<BTreeSet<Value>, KraglinError> {
+  let a = m.get(a).map(StoredValue::as_set).transpose()?;
+  let b = m.get(b).map(StoredValue::as_set).transpose()?;
+  Ok(match (a, b) {
+    (Some(a), Some(b)) => a.difference(b).cloned().collect(),
+    (Some(a), None) => a.clone(),
+    (None, _) => BTreeSet::new(),
+  })
+}
+
+/// Writes an `INFO` section in the `# Name\r\nfield:value\r\n` format.
+fn write_info_section(
+  out: &mut String,
+  name: &str,
+  fields: &[(impl AsRef<str>, String)],
+) {
+  if !out.is_empty() {
+    out.push_str("\r\n");
+  }
+  let _ = write!(out, "# {name}\r\n");
+  for (field, value) in fields {
+    let _ = write!(out, "{}:{value}\r\n", field.as_ref());
+  }
+}
+
+fn load(counter: &AtomicU64) -> String {
+  counter.load(Ordering::Relaxed).to_string()
+}
+
+/// The number of hot keys listed in `INFO`.
+const INFO_HOT_KEYS: usize = 5;
+
+/// Statistics about active defragmentation, reported in `INFO`.
+#[derive(Debug, Default)]
+struct DefragStats {
+  /// The number of completed defragmentation passes.
+  runs:       AtomicU64,
+  /// The number of keys whose values were reallocated.
+  key_hits:   AtomicU64,
+  /// The number of times the keyspace table itself was reallocated.
+  table_hits: AtomicU64,
+}
+
+/// Statistics about compression of values at rest, reported in `INFO`.
+#[derive(Debug, Default)]
+struct CompressionStats {
+  /// The number of values which were compressed when written.
+  values:      AtomicU64,
+  /// The total number of bytes saved by compression when written.
+  bytes_saved: AtomicU64,
+}
+
+/// The state of snapshot saving, reported in `INFO`.
+#[derive(Debug, Default)]
+struct SaveStats {
+  /// Whether a `BGSAVE` is running.
+  in_progress:    AtomicBool,
+  /// The number of snapshots saved, full or delta.
+  saves:          AtomicU64,
+  /// The number of those which were deltas.
+  delta_saves:    AtomicU64,
+  /// When the last snapshot was saved, as a unix time in seconds.
+  last_save_time: AtomicU64,
+  /// Whether the last attempt to save failed.
+  last_failed:    AtomicBool,
+}
+
+impl SaveStats {
+  /// Records the result of a save, which is whether it was a delta.
+  fn record(&self, result: &Result<bool>) {
+    self.last_failed.store(result.is_err(), Ordering::Relaxed);
+    if let Ok(delta) = result {
+      self.saves.fetch_add(1, Ordering::Relaxed);
+      self
+        .delta_saves
+        .fetch_add(u64::from(*delta), Ordering::Relaxed);
+      self
+        .last_save_time
+        .store(clock::unix_time().as_secs(), Ordering::Relaxed);
+    }
+  }
+}
+
+/// Saves `keyspace` to `store`: just the `dirty` keys as a delta if there
+/// are few enough of them, and otherwise (or if they aren't given) the whole
+/// keyspace. Returns whether a delta was saved.
+fn save_snapshot(
+  store: &mut SnapshotStore,
+  keyspace: &Keyspace,
+  dirty: Option<&HashSet<SmolStr>>,
+) -> Result<bool> {
+  match dirty {
+    Some(dirty) if !store.wants_full(dirty.len(), keyspace.len()) => {
+      if !dirty.is_empty() {
+        store.write_delta(dirty.iter().map(|key| (key, keyspace.dump(key))))?;
       }
-      None => {
-        self.remove(&key);
+      Ok(true)
+    }
+    _ => {
+      store.write_full(
+        keyspace
+          .keys()
+          .filter_map(|key| Some((key, keyspace.dump(key)?))),
+      )?;
+      Ok(false)
+    }
+  }
+}
+
+/// Saves each database's keyspace, with its dirty keys if it's a delta, to
+/// its store, as [`save_snapshot()`] does. Databases after the first are
+/// skipped if they've never held anything, so that unused ones don't litter
+/// the data directory. `failed` is called with each database which fails to
+/// save, and its dirty keys. Returns whether every save was a delta.
+fn save_databases(
+  stores: &mut [SnapshotStore],
+  keyspaces: Vec<(Keyspace, Option<HashSet<SmolStr>>)>,
+  mut failed: impl FnMut(usize, Option<HashSet<SmolStr>>),
+) -> Result<bool> {
+  let mut result = Ok(true);
+  for (db, (store, (keyspace, dirty))) in
+    stores.iter_mut().zip(keyspaces).enumerate()
+  {
+    if db != 0 && !store.has_full() && keyspace.len() == 0 {
+      continue;
+    }
+    match save_snapshot(store, &keyspace, dirty.as_ref()) {
+      Ok(delta) => {
+        if let Ok(all) = &mut result {
+          *all &= delta;
+        }
+      }
+      Err(e) => {
+        failed(db, dirty);
+        result = Err(e);
       }
     }
   }
+  result
 }
 
-/// The naive `Backend` implementation, using a `Arc<Mutex<HashMap<SmolStr,
-/// StoredValue>>>`.
-pub struct SimpleBackend(Arc<Mutex<HashMap<SmolStr, StoredValue>>>);
+/// The commands which reproduce the data the backend has expired, once
+/// something propagates them. See [`Backend::take_expirations()`].
+#[derive(Debug, Default)]
+struct Expirations {
+  tracked: AtomicBool,
+  pending: std::sync::Mutex<Vec<Command>>,
+}
 
-impl Backend for SimpleBackend {
-  fn new() -> SimpleBackend {
-    SimpleBackend(Arc::new(Mutex::new(HashMap::new())))
+impl Expirations {
+  fn record(&self, expirations: Vec<Command>) {
+    if !expirations.is_empty() && self.tracked.load(Ordering::Relaxed) {
+      self.pending.lock().unwrap().extend(expirations);
+    }
   }
+}
 
-  async fn execute(&self, command: Command) -> Result<Value, KraglinError> {
-    match command {
-      Command::Set { key, value } => {
-        let mut m = self.0.lock().await;
-        m.set(key, value.into());
-        Ok(Value::Nothing)
+/// What happens to the expired data a command accesses.
+enum Expiry<'a> {
+  /// It's left alone, for replicas, whose data only changes when their
+  /// master deletes it.
+  Keep,
+  /// It's removed without being recorded, from copies of the keyspace which
+  /// only need to hide it.
+  Hide,
+  /// It's removed, and its deletions recorded as expirations of the database
+  /// with the given index.
+  Remove(&'a Expirations, usize),
+}
+
+/// A keyspace for a command to run on (one of the databases, or a snapshot
+/// or copy of one), which lazily expires the keys and hash fields the
+/// command accesses each time it's locked, so that they're expired under the
+/// same guard the command reads them with.
+///
+/// Commands like `KEYS`, which read the whole keyspace, expire all of it.
+/// Other commands without keys, like `SCAN` and `INFO`, expire nothing.
+struct Access<'a> {
+  data:   &'a Mutex<Keyspace>,
+  keys:   Vec<SmolStr>,
+  all:    bool,
+  expiry: Expiry<'a>,
+}
+
+impl<'a> Access<'a> {
+  fn new(
+    data: &'a Mutex<Keyspace>,
+    command: &Command,
+    expiry: Expiry<'a>,
+  ) -> Self {
+    Access {
+      data,
+      keys: command.keys().into_iter().cloned().collect(),
+      all: reads_everything(command),
+      expiry,
+    }
+  }
+
+  /// Locks the keyspace, after expiring what the command accesses.
+  async fn lock(&self) -> MutexGuard<'a, Keyspace> {
+    let mut m = self.data.lock().await;
+    match self.expiry {
+      Expiry::Keep => {}
+      Expiry::Hide => {
+        self.expire(&mut m);
+      }
+      // recorded under the guard, so that the deletions are propagated
+      // before the writes of any command which runs after them
+      Expiry::Remove(expirations, db) => {
+        let expired = self.expire(&mut m);
+        expirations.record(super::propagate::in_database(db, expired));
       }
+    }
+    m
+  }
+
+  /// Removes the expired keys and hash fields the command accesses. Returns
+  /// the deletions which reproduce what was removed.
+  fn expire(&self, m: &mut Keyspace) -> Vec<Command> {
+    let now = clock::unix_time_ms();
+    // expired keys go first, taking their fields with them
+    let expired = if self.all {
+      m.expire_all(now)
+    } else {
+      self
+        .keys
+        .iter()
+        .filter(|key| m.expire(key, now))
+        .cloned()
+        .collect()
+    };
+    #[cfg_attr(not(feature = "hashes"), allow(unused_mut))]
+    let mut expirations = expired
+      .into_iter()
+      .map(|key| Command::Delete { keys: vec![key] })
+      .collect::<Vec<_>>();
+
+    #[cfg(feature = "hashes")]
+    {
+      let expired = if self.all {
+        m.expire_all_fields(now)
+      } else {
+        self
+          .keys
+          .iter()
+          .map(|key| (key.clone(), m.expire_fields(key, now)))
+          .filter(|(_, fields)| !fields.is_empty())
+          .collect()
+      };
+      expirations.extend(expired.into_iter().map(|(key, fields)| {
+        // hashes whose last fields expired were deleted with them
+        if m.contains_key(&key) {
+          Command::HashDelete { key, fields }
+        } else {
+          Command::Delete { keys: vec![key] }
+        }
+      }));
+    }
+    expirations
+  }
+}
+
+/// Whether `command` reads the whole keyspace, and so expires all of it.
+fn reads_everything(command: &Command) -> bool {
+  matches!(command, Command::Keys)
+}
+
+/// Whether `command` locks every database itself, and so can't run while one
+/// is held.
+fn locks_every_database(command: &Command) -> bool {
+  matches!(command, Command::Info | Command::BackgroundSave)
+}
+
+/// A database's keyspace, moved out from under its lock for a batch of
+/// commands to run on without any other command landing in between. It's
+/// moved back when this is dropped, even if a command panics or the batch is
+/// cancelled.
+struct Exclusive<'a> {
+  guard: MutexGuard<'a, Keyspace>,
+  data:  Mutex<Keyspace>,
+}
+
+impl<'a> Exclusive<'a> {
+  async fn lock(data: &'a Mutex<Keyspace>) -> Self {
+    let mut guard = data.lock().await;
+    let keyspace = std::mem::take(&mut *guard);
+    Exclusive {
+      guard,
+      data: Mutex::new(keyspace),
+    }
+  }
+}
+
+impl Drop for Exclusive<'_> {
+  fn drop(&mut self) { std::mem::swap(&mut *self.guard, self.data.get_mut()); }
+}
+
+/// One of the backend's numbered databases.
+struct Database {
+  data:        Arc<Mutex<Keyspace>>,
+  /// The memory the keyspace used after the last write to it, so that the
+  /// memory limit can be checked without locking every database.
+  used_memory: AtomicUsize,
+}
+
+impl Database {
+  /// Records the memory the keyspace uses now.
+  fn record_memory(&self, m: &Keyspace) {
+    self.used_memory.store(m.used_memory(), Ordering::Relaxed);
+  }
+}
+
+/// The naive `Backend` implementation, using a mutex-guarded `HashMap<SmolStr,
+/// StoredValue>` for each database.
+///
+/// Given a data directory, it loads the snapshots there on startup, and saves
+/// them on `BGSAVE` and on shutdown. `BGSAVE` only writes the keys which
+/// changed since the last save, as a delta, unless most of them did. Each
+/// database has its own snapshots (see
+/// [`database_snapshot_file()`](crate::data_dir::database_snapshot_file)).
+pub struct SimpleBackend {
+  databases:             Vec<Database>,
+  events:                KeyEvents,
+  max_memory:            Option<u64>,
+  defrag_stats:          DefragStats,
+  hot_keys:              HotKeys,
+  compression_threshold: Option<usize>,
+  compression_stats:     CompressionStats,
+  normalize_values:      bool,
+  interner:              Interner,
+  replica:               bool,
+  expirations:           Expirations,
+  /// Where each database's snapshots are loaded from and saved to, if
+  /// anywhere. Locked for the whole of a save, from taking the keyspaces'
+  /// snapshots to writing them, so that saves are written in the order
+  /// they're taken.
+  snapshots:             Option<Arc<Mutex<Vec<SnapshotStore>>>>,
+  save_stats:            Arc<SaveStats>,
+}
+
+impl SimpleBackend {
+  /// Loads the keys of a snapshot into database `db`, which must be empty.
+  /// Keys which expired while the snapshot was on disk are skipped.
+  fn load_snapshot(&self, db: usize, entries: Vec<(SmolStr, Dump)>) {
+    let database = &self.databases[db];
+    let mut m = database
+      .data
+      .try_lock()
+      .expect("the keyspace isn't shared before the backend is created");
+    let now = clock::unix_time_ms();
+    for (
+      key,
+      Dump {
+        value,
+        deadline,
+        field_deadlines,
+      },
+    ) in entries
+    {
+      if deadline.is_some_and(|deadline| deadline <= now) {
+        continue;
+      }
+      let value = self.prepare(value);
+      m.restore(key, value, deadline, field_deadlines);
+    }
+    // the loaded keys are already saved
+    m.track_dirty_keys();
+    m.take_dirty_keys();
+    database.record_memory(&m);
+  }
+
+  /// Starts saving a snapshot of each database on a blocking thread, as a
+  /// delta of the keys written since the last save if there are few enough
+  /// of them.
+  async fn background_save(&self) -> KraglinResult {
+    let Some(stores) = &self.snapshots else {
+      return Err(KraglinError::NoDataDir);
+    };
+    let Ok(mut stores) = stores.clone().try_lock_owned() else {
+      return Err(KraglinError::BackgroundSaveInProgress);
+    };
+    let mut keyspaces = Vec::with_capacity(self.databases.len());
+    for database in &self.databases {
+      let mut m = database.data.lock().await;
+      keyspaces.push((m.snapshot(), Some(m.take_dirty_keys())));
+    }
+    let data = self
+      .databases
+      .iter()
+      .map(|database| database.data.clone())
+      .collect::<Vec<_>>();
+    let stats = self.save_stats.clone();
+    stats.in_progress.store(true, Ordering::Relaxed);
+    tokio::task::spawn_blocking(move || {
+      let result = save_databases(&mut stores, keyspaces, |db, dirty| {
+        // the keys are saved by the next snapshot instead
+        data[db]
+          .blocking_lock()
+          .mark_dirty_keys(dirty.unwrap_or_default());
+      });
+      if let Err(e) = &result {
+        tracing::error!("background save failed: {e:#}");
+      }
+      stats.record(&result);
+      stats.in_progress.store(false, Ordering::Relaxed);
+    });
+    Ok(Value::SimpleString("Background saving started".into()))
+  }
+
+  /// Rejects writes which may grow memory usage if it's already over the
+  /// configured maximum, as of each database's last write.
+  fn check_memory(&self) -> Result<(), KraglinError> {
+    let used = self
+      .databases
+      .iter()
+      .map(|database| database.used_memory.load(Ordering::Relaxed))
+      .sum::<usize>();
+    match self.max_memory {
+      Some(max) if used as u64 > max => Err(KraglinError::OutOfMemory),
+      _ => Ok(()),
+    }
+  }
+
+  /// Adds `by` to the integer stored at `key` in place, for `INCR` and
+  /// `INCRBY`. The value keeps its type.
+  async fn increment(
+    &self,
+    data: &Access<'_>,
+    key: SmolStr,
+    by: i64,
+  ) -> KraglinResult {
+    let mut m = data.lock().await;
+    self.check_memory()?;
+    let existed = m.contains_key(&key);
+
+    // try to parse the value as an `i64`, increment it, and then return the
+    // incremented value as an Integer
+    let result = m.modify(
+      key.clone(),
+      || StoredValue::Integer(0),
+      |entry| {
+        let incremented = entry
+          .as_int()?
+          .checked_add(by)
+          .ok_or(KraglinError::OutOfRange)?;
+        match entry {
+          StoredValue::Integer(i) => *i = incremented,
+          StoredValue::BigNumber(n) => *n = incremented.into(),
+          StoredValue::SimpleString(s) => *s = incremented.to_string().into(),
+          StoredValue::BulkString(b) => *b = incremented.to_string().into(),
+          _ => unreachable!("`as_int()` only accepts integers and strings"),
+        }
+        Ok(Value::Integer(incremented))
+      },
+    );
+    if result.is_ok() {
+      m.notify_write(&key, existed);
+    }
+    result
+  }
+
+  /// Prepares a written value for storage: normalizes it if that's enabled,
+  /// and then interns and compresses it.
+  fn prepare(&self, value: StoredValue) -> StoredValue {
+    let value = if self.normalize_values {
+      value.normalize()
+    } else {
+      value
+    };
+    self.compress(self.interner.intern_stored_value(value))
+  }
+
+  /// Compresses the value for storage if compression is enabled and it's
+  /// large enough.
+  fn compress(&self, value: StoredValue) -> StoredValue {
+    let Some(threshold) = self.compression_threshold else {
+      return value;
+    };
+    let compressed = value.compress_above(threshold);
+    if let StoredValue::CompressedBulkString(c) = &compressed {
+      let stats = &self.compression_stats;
+      stats.values.fetch_add(1, Ordering::Relaxed);
+      stats
+        .bytes_saved
+        .fetch_add((c.len() - c.compressed_len()) as u64, Ordering::Relaxed);
+    }
+    compressed
+  }
+
+  /// Returns a copy of the data `command` reads, if any of it has expired,
+  /// for the command to run on with [`Expiry::Hide`]. Replicas and read-only
+  /// snapshots hide expired data this way rather than deleting it.
+  async fn unexpired_view(
+    &self,
+    data: &Mutex<Keyspace>,
+    command: &Command,
+  ) -> Option<Mutex<Keyspace>> {
+    let now = clock::unix_time_ms();
+    let view = {
+      let m = data.lock().await;
+      let keys = command.keys();
+      if reads_everything(command) {
+        m.has_any_expired(now).then(|| m.snapshot())
+      } else if keys.iter().any(|key| m.has_expired(key, now)) {
+        Some(m.copy_of(keys))
+      } else {
+        None
+      }
+    };
+    view.map(Mutex::new)
+  }
+
+  /// Runs `SET`, replying with the key's old value if `get` is set, or else
+  /// with `OK`, or nothing if the `options`' condition doesn't hold.
+  async fn set(
+    &self,
+    data: &Access<'_>,
+    key: SmolStr,
+    value: Value,
+    options: SetOptions,
+    get: bool,
+  ) -> KraglinResult {
+    let now = clock::unix_time_ms();
+    let deadline = match options.expiration {
+      None | Some(SetExpiration::KeepTtl) => None,
+      Some(expiration) => Some(
+        expiration
+          .unix_time_ms(now)
+          .ok_or_else(|| KraglinError::InvalidExpireTime("set".to_owned()))?,
+      ),
+    };
+
+    let mut m = data.lock().await;
+    self.check_memory()?;
+    let old = if get {
+      m.get(&key)
+        .map(StoredValue::as_string)
+        .transpose()?
+        .cloned()
+        .into()
+    } else {
+      Value::SimpleString("OK".into())
+    };
+    let exists = m.contains_key(&key);
+    if options.condition.is_some_and(|c| !c.allows(exists)) {
+      return Ok(if get { old } else { Value::Nothing });
+    }
+    let deadline = match options.expiration {
+      Some(SetExpiration::KeepTtl) => m.deadline(&key),
+      _ => deadline,
+    };
+    // like `EXPIREAT`, a deadline which has already passed deletes the key,
+    // except on replicas, which wait for their master's deletion
+    if deadline.is_some_and(|deadline| deadline <= now) && !self.replica {
+      m.remove(&key);
+      return Ok(old);
+    }
+    let value: Option<StoredValue> = value.into();
+    m.set(key.clone(), value.map(|v| self.prepare(v)));
+    if deadline.is_some() && m.contains_key(&key) {
+      m.set_deadline(&key, deadline);
+    }
+    Ok(old)
+  }
+
+  /// Runs `EXPIRE`, `PEXPIRE`, `EXPIREAT`, or `PEXPIREAT`, given the unix
+  /// time in milliseconds at which the key expires, or `None` if it's out of
+  /// range. Times before the unix epoch have passed like any other.
+  async fn expire_key(
+    &self,
+    data: &Access<'_>,
+    command: &str,
+    key: SmolStr,
+    deadline: Option<i64>,
+    condition: Option<ExpireCondition>,
+  ) -> KraglinResult {
+    let now = clock::unix_time_ms();
+    let deadline = deadline
+      .ok_or_else(|| KraglinError::InvalidExpireTime(command.to_owned()))?;
+    let deadline = u64::try_from(deadline).unwrap_or(0);
+
+    let mut m = data.lock().await;
+    if !m.contains_key(&key) {
+      return Ok(Value::Integer(0));
+    }
+    if condition.is_some_and(|c| !c.allows(m.deadline(&key), deadline)) {
+      return Ok(Value::Integer(0));
+    }
+    // like Redis, a deadline which has already passed deletes the key, except
+    // on replicas, which wait for their master's deletion
+    if deadline <= now && !self.replica {
+      m.remove(&key);
+    } else {
+      m.set_deadline(&key, Some(deadline));
+    }
+    Ok(Value::Integer(1))
+  }
+
+  /// Runs `HEXPIRE`, `HPEXPIRE`, or `HPEXPIREAT`, given the unix time in
+  /// milliseconds at which the fields expire, or `None` if it's out of range.
+  #[cfg(feature = "hashes")]
+  async fn expire_hash_fields(
+    &self,
+    data: &Access<'_>,
+    command: &str,
+    key: SmolStr,
+    deadline: Option<u64>,
+    condition: Option<ExpireCondition>,
+    fields: Vec<SmolStr>,
+  ) -> KraglinResult {
+    let now = clock::unix_time_ms();
+    let deadline = deadline
+      .ok_or_else(|| KraglinError::InvalidExpireTime(command.to_owned()))?;
+
+    let mut m = data.lock().await;
+    let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+      return Ok(Value::Array(vec![Value::Integer(-2); fields.len()]));
+    };
+    let exists = fields.iter().map(|f| h.contains_key(f)).collect::<Vec<_>>();
+
+    let replies = fields
+      .iter()
+      .zip(exists)
+      .map(|(field, exists)| {
+        if !exists {
+          return Value::Integer(-2);
+        }
+        let current = m.field_deadline(&key, field);
+        if condition.is_some_and(|c| !c.allows(current, deadline)) {
+          return Value::Integer(0);
+        }
+        m.set_field_deadline(&key, field, Some(deadline));
+        Value::Integer(if deadline <= now { 2 } else { 1 })
+      })
+      .collect();
+    // fields given a deadline which has already passed are deleted now,
+    // except on replicas, which wait for their master's deletion
+    if !self.replica {
+      m.expire_fields(&key, now);
+    }
+    Ok(Value::Array(replies))
+  }
+
+  /// Runs `HTTL` or `HPTTL`.
+  #[cfg(feature = "hashes")]
+  async fn hash_field_ttls(
+    &self,
+    data: &Access<'_>,
+    key: SmolStr,
+    fields: Vec<SmolStr>,
+    in_millis: bool,
+  ) -> KraglinResult {
+    let now = clock::unix_time_ms();
+    let m = data.lock().await;
+    let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+      return Ok(Value::Array(vec![Value::Integer(-2); fields.len()]));
+    };
+    Ok(
+      Value::Array(
+        fields
+          .iter()
+          .map(|field| {
+            if !h.contains_key(field) {
+              return Value::Integer(-2);
+            }
+            let Some(deadline) = m.field_deadline(&key, field) else {
+              return Value::Integer(-1);
+            };
+            // like Redis, seconds are rounded to the nearest second
+            let ttl = deadline.saturating_sub(now);
+            Value::Integer(
+              if in_millis { ttl } else { (ttl + 500) / 1000 } as i64
+            )
+          })
+          .collect(),
+      ),
+    )
+  }
+
+  /// Runs `command` against `data`, which holds the keyspace of database
+  /// `db`, expiring what it accesses.
+  async fn execute_on_database(
+    &self,
+    db: usize,
+    data: &Mutex<Keyspace>,
+    command: Command,
+  ) -> KraglinResult {
+    let expiry = if !self.replica {
+      Expiry::Remove(&self.expirations, db)
+    } else {
+      // a replica's data only changes when its master says so, so expired
+      // keys and fields are hidden from reads rather than deleted
+      if !command.is_write() {
+        if let Some(view) = self.unexpired_view(data, &command).await {
+          let view = Access::new(&view, &command, Expiry::Hide);
+          return self.execute_on(&view, command).await;
+        }
+      }
+      Expiry::Keep
+    };
+    let is_write = command.is_write();
+    let result = self
+      .execute_on(&Access::new(data, &command, expiry), command)
+      .await;
+    if is_write {
+      self.databases[db].record_memory(&*data.lock().await);
+    }
+    result
+  }
+
+  /// Runs `command` against the keyspace in `data`, which is either one of
+  /// the backend's databases or a snapshot of one.
+  async fn execute_on(
+    &self,
+    data: &Access<'_>,
+    command: Command,
+  ) -> KraglinResult {
+    match command {
+      Command::Set {
+        key,
+        value,
+        options,
+      } => self.set(data, key, value, options, false).await,
+      Command::SetAndGet {
+        key,
+        value,
+        options,
+      } => self.set(data, key, value, options, true).await,
       Command::Get { key } => {
-        let m = self.0.lock().await;
+        let m = data.lock().await;
         Ok(m.get(&key).cloned().into())
       }
+      Command::GetDelete { key } => {
+        let mut m = data.lock().await;
+        m.get(&key).map(StoredValue::as_string).transpose()?;
+        Ok(m.remove(&key).into())
+      }
       Command::MultipleGet { keys } => {
-        let m = self.0.lock().await;
+        let m = data.lock().await;
         let values = keys
           .into_iter()
           .map(|k| m.get(&k).cloned().into())
           .collect::<Vec<_>>();
         Ok(Value::Array(values))
       }
-      Command::Increment { key } => {
-        let mut m = self.0.lock().await;
-        let entry = m.entry(key).or_insert(StoredValue::Integer(0));
-
-        // try to parse the value as an `i64`, increment it, and then return the
-        // incremented value as an Integer
-        match entry {
-          StoredValue::Integer(i) => {
-            *i += 1;
-            Ok(Value::Integer(*i))
-          }
-          StoredValue::BigNumber(n) => {
-            let Ok(as_i64) = i64::try_from(n.clone()) else {
-              return Err(KraglinError::OutOfRange);
-            };
-            *n = (as_i64 + 1).into();
-            Ok(Value::Integer(as_i64 + 1))
-          }
-          StoredValue::SimpleString(s) => {
-            if let Ok(as_i64) = s.parse::<i64>() {
-              *s = format!("{}", as_i64 + 1).into();
-              Ok(Value::Integer(as_i64 + 1))
-            } else {
-              Err(KraglinError::CannotParseAsInteger)
-            }
-          }
-          StoredValue::BulkString(b) => {
-            let Some(as_ascii) = b.as_ascii() else {
-              return Err(KraglinError::CannotParseAsInteger);
-            };
-            let Ok(as_i64) = as_ascii.as_str().parse::<i64>() else {
-              return Err(KraglinError::CannotParseAsInteger);
-            };
-
-            *b = format!("{}", as_i64 + 1).into();
-            Ok(Value::Integer(as_i64 + 1))
-          }
-          _ => Err(KraglinError::WrongType),
-        }
+      Command::Increment { key } => self.increment(data, key, 1).await,
+      Command::IncrementBy { key, increment } => {
+        self.increment(data, key, increment).await
       }
       Command::Keys => {
-        let m = self.0.lock().await;
+        let m = data.lock().await;
         let mut keys = m.keys().cloned().collect::<Vec<_>>();
         keys.sort_unstable();
         Ok(Value::Array(
-          keys.into_iter().map(Value::SimpleString).collect(),
+          keys
+            .into_iter()
+            .map(|key| Value::BulkString(key.as_bytes().to_vec().into()))
+            .collect(),
         ))
       }
-      Command::Exists { key } => {
-        let m = self.0.lock().await;
-        let exists = m.get(&key).is_some();
-        Ok(Value::Integer(exists.into()))
+      Command::Scan {
+        cursor,
+        pattern,
+        count,
+      } => {
+        let now = clock::unix_time_ms();
+        let m = data.lock().await;
+        let (cursor, keys) =
+          m.scan(cursor, count.unwrap_or(DEFAULT_SCAN_COUNT));
+        // only the page is checked for expired keys, which are skipped
+        // rather than removed, so that each call stays incremental
+        let keys = keys
+          .into_iter()
+          .filter(|key| !m.is_expired(key, now))
+          .filter(|key| pattern.as_ref().is_none_or(|p| glob_match(p, key)))
+          .map(|key| Value::BulkString(key.as_bytes().to_vec().into()))
+          .collect();
+        Ok(Value::Array(vec![
+          Value::BulkString(cursor.to_string().into()),
+          Value::Array(keys),
+        ]))
       }
-      Command::Delete { key } => {
-        let mut m = self.0.lock().await;
-        Ok(Value::Integer(m.remove(&key).is_some().into()))
+      // touching is done by `execute()`, like for every other command
+      Command::Exists { keys } | Command::Touch { keys } => {
+        let m = data.lock().await;
+        let existing = keys.iter().filter(|k| m.contains_key(k)).count();
+        Ok(Value::Integer(existing as i64))
       }
+      Command::Delete { keys } | Command::Unlink { keys } => {
+        let mut m = data.lock().await;
+        let deleted = keys.iter().filter(|k| m.remove(k).is_some()).count();
+        Ok(Value::Integer(deleted as i64))
+      }
+      Command::Expire {
+        key,
+        seconds,
+        condition,
+      } => {
+        let deadline = seconds
+          .checked_mul(1000)
+          .and_then(|ttl| (clock::unix_time_ms() as i64).checked_add(ttl));
+        self
+          .expire_key(data, "expire", key, deadline, condition)
+          .await
+      }
+      Command::PExpire {
+        key,
+        milliseconds,
+        condition,
+      } => {
+        let deadline = (clock::unix_time_ms() as i64).checked_add(milliseconds);
+        self
+          .expire_key(data, "pexpire", key, deadline, condition)
+          .await
+      }
+      Command::ExpireAt {
+        key,
+        unix_time,
+        condition,
+      } => {
+        self
+          .expire_key(
+            data,
+            "expireat",
+            key,
+            unix_time.checked_mul(1000),
+            condition,
+          )
+          .await
+      }
+      Command::PExpireAt {
+        key,
+        unix_time_ms,
+        condition,
+      } => {
+        self
+          .expire_key(data, "pexpireat", key, Some(unix_time_ms), condition)
+          .await
+      }
+      Command::Dump { key } => {
+        let m = data.lock().await;
+        Ok(
+          m.dump(&key)
+            .map_or(Value::Nothing, |dump| Value::BulkString(dump.encode())),
+        )
+      }
+      Command::Restore {
+        key,
+        ttl,
+        payload,
+        replace,
+        absttl,
+      } => {
+        let now = clock::unix_time_ms();
+        // the payload's own deadline is ignored, like in Redis
+        let deadline = match ttl {
+          ..0 => return Err(KraglinError::InvalidTtl),
+          0 => None,
+          _ if absttl => Some(ttl as u64),
+          _ => Some(
+            clock::unix_time_ms_after(ttl).ok_or(KraglinError::InvalidTtl)?,
+          ),
+        };
+        let mut m = data.lock().await;
+        self.check_memory()?;
+        if !replace && m.contains_key(&key) {
+          return Err(KraglinError::BusyKey);
+        }
+        let Dump {
+          value,
+          field_deadlines,
+          ..
+        } = Dump::decode(&payload)?;
+        // like Redis, a key restored with a deadline which has already
+        // passed only replaces the existing key with nothing
+        if deadline.is_some_and(|deadline| deadline <= now) {
+          m.remove(&key);
+          return Ok(Value::SimpleString("OK".into()));
+        }
+        let value = self.prepare(value);
+        m.restore(key, value, deadline, field_deadlines);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::BackgroundSave => self.background_save().await,
       Command::Info => {
-        let m = self.0.lock().await;
-        let key_count = m.keys().count();
+        // the databases are locked one at a time, so that this never waits
+        // for one while holding another
+        let (mut keys, mut dirty, mut used_memory) = (Vec::new(), 0, 0);
+        for database in &self.databases {
+          let m = database.data.lock().await;
+          keys.push(m.len());
+          dirty += m.dirty_len();
+          used_memory += m.used_memory();
+        }
+        let stats = &self.defrag_stats;
+
+        let mut info = String::new();
+        write_info_section(&mut info, "Server", &[
+          ("kraglin_version", version::VERSION.to_string()),
+          ("kraglin_git_sha1", version::GIT_SHA.to_string()),
+          (
+            "kraglin_git_dirty",
+            u8::from(version::GIT_DIRTY).to_string(),
+          ),
+          ("kraglin_build_features", version::FEATURES.to_string()),
+        ]);
+        write_info_section(
+          &mut info,
+          "Keyspace",
+          &std::iter::once((
+            "keys".to_string(),
+            keys.iter().sum::<usize>().to_string(),
+          ))
+          .chain(
+            keys
+              .iter()
+              .enumerate()
+              .filter(|(_, &len)| len > 0)
+              .map(|(db, len)| (format!("db{db}"), format!("keys={len}"))),
+          )
+          .collect::<Vec<_>>(),
+        );
+        let saves = &self.save_stats;
+        write_info_section(&mut info, "Persistence", &[
+          ("rdb_changes_since_last_save", dirty.to_string()),
+          (
+            "rdb_bgsave_in_progress",
+            u8::from(saves.in_progress.load(Ordering::Relaxed)).to_string(),
+          ),
+          ("rdb_last_save_time", load(&saves.last_save_time)),
+          (
+            "rdb_last_bgsave_status",
+            if saves.last_failed.load(Ordering::Relaxed) {
+              "err"
+            } else {
+              "ok"
+            }
+            .to_string(),
+          ),
+          ("rdb_saves", load(&saves.saves)),
+          ("rdb_delta_saves", load(&saves.delta_saves)),
+        ]);
+        write_info_section(&mut info, "Memory", &[
+          ("used_memory", used_memory.to_string()),
+          ("maxmemory", self.max_memory.unwrap_or(0).to_string()),
+          ("active_defrag_runs", load(&stats.runs)),
+          ("active_defrag_key_hits", load(&stats.key_hits)),
+          ("active_defrag_table_hits", load(&stats.table_hits)),
+          ("compressed_values", load(&self.compression_stats.values)),
+          (
+            "compression_bytes_saved",
+            load(&self.compression_stats.bytes_saved),
+          ),
+          ("interned_values", self.interner.hits().to_string()),
+          (
+            "interning_bytes_saved",
+            self.interner.bytes_saved().to_string(),
+          ),
+        ]);
+        let hot_keys = self.hot_keys.top();
+        write_info_section(
+          &mut info,
+          "Hotkeys",
+          &std::iter::once((
+            "hotkeys_sample_rate".to_string(),
+            self.hot_keys.sample_rate().to_string(),
+          ))
+          .chain(hot_keys.iter().take(INFO_HOT_KEYS).enumerate().map(
+            |(i, (key, count))| {
+              (format!("hotkey{i}"), format!("key={key},count={count}"))
+            },
+          ))
+          .collect::<Vec<_>>(),
+        );
+
+        Ok(Value::BulkString(info.into()))
+      }
+      Command::MemoryUsage { key } => {
+        let m = data.lock().await;
+        Ok(match m.entry_size(&key) {
+          Some(size) => Value::Integer(size as i64),
+          None => Value::Nothing,
+        })
+      }
+      Command::ObjectEncoding { key } => {
+        let m = data.lock().await;
+        Ok(match m.get(&key) {
+          Some(value) => Value::SimpleString(value.encoding().into()),
+          None => Value::Nothing,
+        })
+      }
+      Command::DebugObject { key } => {
+        let m = data.lock().await;
+        let value = m.get(&key).ok_or(KraglinError::NoSuchKey)?;
         Ok(Value::SimpleString(
           format!(
-            "We've got {key_count} key{} right now, thanks for asking :)",
-            if key_count != 1 { "s" } else { "" }
+            "Value at:{value:p} refcount:1 encoding:{} serializedlength:{} \
+             type:{}",
+            value.encoding(),
+            value.serialized_length(),
+            value.type_name(),
           )
           .into(),
         ))
       }
+      Command::DebugKeyStats { samples } => {
+        let m = data.lock().await;
+        let mut stats = KeyStats::default();
+        for (key, value, size) in m.iter().take(samples.unwrap_or(usize::MAX)) {
+          stats.record(key, value, size, m.has_ttl(key));
+        }
+        Ok(stats.to_value())
+      }
+      Command::DebugHotKeys => Ok(Value::Array(
+        self
+          .hot_keys
+          .top()
+          .into_iter()
+          .flat_map(|(key, count)| {
+            [
+              Value::BulkString(key.as_bytes().to_vec().into()),
+              Value::Integer(count as i64),
+            ]
+          })
+          .collect(),
+      )),
+      #[cfg(feature = "hashes")]
       Command::HashSet { key, field, value } => {
-        let mut m = self.0.lock().await;
+        let mut m = data.lock().await;
+        self.check_memory()?;
+        let value = self.interner.intern_value(value);
+        let existed = m.contains_key(&key);
 
-        // get or insert, with a special case for `Nothing`
-        let entry = m.entry(key).or_insert(StoredValue::Map(BTreeMap::new()));
+        let result = m.modify_collection(
+          key.clone(),
+          || StoredValue::Map(BTreeMap::new()),
+          |entry| match entry {
+            StoredValue::Map(h) => {
+              let added = field_size(&field, &value) as isize;
+              let old = h.insert(field.clone(), value);
+              let removed =
+                old.as_ref().map_or(0, |v| field_size(&field, v) as isize);
+              (Ok(Value::Integer(old.is_none().into())), added - removed)
+            }
+            _ => (Err(KraglinError::WrongType), 0),
+          },
+        );
+        // like Redis, overwriting a field removes its time to live
+        if result.is_ok() {
+          m.set_field_deadline(&key, &field, None);
+          m.notify_write(&key, existed);
+        }
+        result
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashGet { key, field } => {
+        let m = data.lock().await;
+        let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+          return Ok(Value::Nothing);
+        };
+        Ok(h.get(&field).cloned().unwrap_or(Value::Nothing))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { key } => {
+        let m = data.lock().await;
+        let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+          return Ok(Value::Map(BTreeMap::new()));
+        };
+        Ok(Value::Map(h.clone()))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { key, fields } => {
+        let m = data.lock().await;
 
-        match entry {
-          StoredValue::Map(m) => {
-            let inserted = !m.contains_key(&field);
-            m.insert(field, value);
-            Ok(Value::Integer(inserted.into()))
+        let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+          return Ok(Value::Array(vec![Value::Nothing; fields.len()]));
+        };
+        Ok(Value::Array(
+          fields
+            .into_iter()
+            .map(|f| h.get(&f).cloned().unwrap_or(Value::Nothing))
+            .collect(),
+        ))
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashDelete { key, fields } => {
+        let mut m = data.lock().await;
+        let Some(entry) = m.get(&key) else {
+          return Ok(Value::Integer(0));
+        };
+        entry.as_map()?;
+
+        let (removed, now_empty) = m.modify_collection(
+          key.clone(),
+          || unreachable!("the key exists"),
+          |entry| {
+            let StoredValue::Map(h) = entry else {
+              unreachable!("the key holds a hash");
+            };
+            let mut removed = Vec::new();
+            let mut delta = 0;
+            for field in fields {
+              if let Some(value) = h.remove(&field) {
+                delta -= field_size(&field, &value) as isize;
+                removed.push(field);
+              }
+            }
+            ((removed, h.is_empty()), delta)
+          },
+        );
+        if now_empty {
+          m.remove(&key);
+        } else if !removed.is_empty() {
+          for field in &removed {
+            m.set_field_deadline(&key, field, None);
           }
-          _ => Err(KraglinError::WrongType),
+          m.notify_write(&key, true);
         }
+        Ok(Value::Integer(removed.len() as i64))
       }
-      Command::HashGet { key, field } => {
-        let m = self.0.lock().await;
-        match m.get(&key) {
-          Some(StoredValue::Map(h)) => match h.get(&field) {
-            Some(v) => Ok(v.clone()),
-            None => Ok(Value::Nothing),
+      #[cfg(feature = "hashes")]
+      Command::HashExpire {
+        key,
+        seconds,
+        condition,
+        fields,
+      } => {
+        let deadline = seconds
+          .checked_mul(1000)
+          .and_then(clock::unix_time_ms_after);
+        self
+          .expire_hash_fields(data, "hexpire", key, deadline, condition, fields)
+          .await
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashPExpire {
+        key,
+        milliseconds,
+        condition,
+        fields,
+      } => {
+        self
+          .expire_hash_fields(
+            data,
+            "hpexpire",
+            key,
+            clock::unix_time_ms_after(milliseconds),
+            condition,
+            fields,
+          )
+          .await
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashPExpireAt {
+        key,
+        unix_time_ms,
+        condition,
+        fields,
+      } => {
+        self
+          .expire_hash_fields(
+            data,
+            "hpexpireat",
+            key,
+            u64::try_from(unix_time_ms).ok(),
+            condition,
+            fields,
+          )
+          .await
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashTtl { key, fields } => {
+        self.hash_field_ttls(data, key, fields, false).await
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashPTtl { key, fields } => {
+        self.hash_field_ttls(data, key, fields, true).await
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashPersist { key, fields } => {
+        let mut m = data.lock().await;
+        let Some(h) = m.get(&key).map(StoredValue::as_map).transpose()? else {
+          return Ok(Value::Array(vec![Value::Integer(-2); fields.len()]));
+        };
+        let exists =
+          fields.iter().map(|f| h.contains_key(f)).collect::<Vec<_>>();
+        Ok(Value::Array(
+          fields
+            .iter()
+            .zip(exists)
+            .map(|(field, exists)| {
+              Value::Integer(if !exists {
+                -2
+              } else if m.set_field_deadline(&key, field, None).is_some() {
+                1
+              } else {
+                -1
+              })
+            })
+            .collect(),
+        ))
+      }
+      #[cfg(feature = "json")]
+      Command::JsonSet { key, path, json } => {
+        let path = path.parse::<JsonPath>()?;
+        let new = serde_json::from_slice::<serde_json::Value>(&json)
+          .map_err(|e| KraglinError::InvalidJson(e.to_string()))?;
+
+        let mut m = data.lock().await;
+        self.check_memory()?;
+        if !m.contains_key(&key) {
+          // new documents can only be created at the root
+          if !path.is_root() {
+            return Err(KraglinError::JsonPathNotFound);
+          }
+          m.insert(key, StoredValue::Json(new));
+          return Ok(Value::SimpleString("OK".into()));
+        }
+
+        let result = m.modify(
+          key.clone(),
+          || unreachable!("the key exists"),
+          |entry| {
+            if path.set(entry.as_json_mut()?, new) {
+              Ok(Value::SimpleString("OK".into()))
+            } else {
+              Err(KraglinError::JsonPathNotFound)
+            }
           },
-          Some(_) => Err(KraglinError::WrongType),
-          None => Ok(Value::Nothing),
+        );
+        if result.is_ok() {
+          m.notify_write(&key, true);
         }
+        result
       }
-      Command::HashGetAll { key } => {
-        let m = self.0.lock().await;
-        match m.get(&key) {
-          Some(StoredValue::Map(h)) => Ok(Value::Map(h.clone())),
-          Some(_) => Err(KraglinError::WrongType),
-          None => Ok(Value::Nothing),
+      #[cfg(feature = "json")]
+      Command::JsonGet { key, path } => {
+        let path = path.parse::<JsonPath>()?;
+        let m = data.lock().await;
+        let Some(entry) = m.get(&key) else {
+          return Ok(Value::Nothing);
+        };
+        let found = path.get(entry.as_json()?);
+        Ok(match (found, path.is_legacy()) {
+          (Some(j), true) => Value::BulkString(j.to_string().into()),
+          (None, true) => Value::Nothing,
+          // `$` paths reply with every match, of which there's at most one
+          (found, false) => Value::BulkString(
+            serde_json::Value::Array(found.into_iter().cloned().collect())
+              .to_string()
+              .into(),
+          ),
+        })
+      }
+      #[cfg(feature = "json")]
+      Command::JsonDelete { key, path } => {
+        let path = path.parse::<JsonPath>()?;
+        let mut m = data.lock().await;
+        let Some(entry) = m.get(&key) else {
+          return Ok(Value::Integer(0));
+        };
+        entry.as_json()?;
+        if path.is_root() {
+          m.remove(&key);
+          return Ok(Value::Integer(1));
         }
+
+        let result = m.modify(
+          key.clone(),
+          || unreachable!("the key exists"),
+          |entry| {
+            let removed = path.remove(entry.as_json_mut()?);
+            Ok(Value::Integer(removed.is_some().into()))
+          },
+        );
+        if matches!(result, Ok(Value::Integer(1))) {
+          m.notify_write(&key, true);
+        }
+        result
       }
-      Command::HashMultipleGet { key, fields } => {
-        let m = self.0.lock().await;
+      #[cfg(feature = "json")]
+      Command::JsonNumIncrBy { key, path, by } => {
+        let path = path.parse::<JsonPath>()?;
+        let mut m = data.lock().await;
+        if !m.contains_key(&key) {
+          return Err(KraglinError::JsonPathNotFound);
+        }
 
-        let all_nothing = || {
-          Ok(Value::Array(
-            (0..fields.len()).map(|_| Value::Nothing).collect(),
-          ))
+        let result = m.modify(
+          key.clone(),
+          || unreachable!("the key exists"),
+          |entry| {
+            let target = path
+              .get_mut(entry.as_json_mut()?)
+              .ok_or(KraglinError::JsonPathNotFound)?;
+            let serde_json::Value::Number(n) = target else {
+              return Err(KraglinError::JsonWrongType {
+                expected: "number",
+                found:    json_type_name(target),
+              });
+            };
+
+            // integers stay integers unless either side is a double
+            let result = match (n.as_i64(), &by) {
+              (Some(a), Value::Integer(b)) => Value::Integer(
+                a.checked_add(*b).ok_or(KraglinError::OutOfRange)?,
+              ),
+              (_, by) => {
+                let a = n.as_f64().ok_or(KraglinError::OutOfRange)?;
+                let sum = a + f64::try_from(by.clone())?;
+                if !sum.is_finite() {
+                  return Err(KraglinError::OutOfRange);
+                }
+                Value::Double(sum)
+              }
+            };
+            *target = result.clone().into();
+            Ok(result)
+          },
+        );
+        if result.is_ok() {
+          m.notify_write(&key, true);
+        }
+        if path.is_legacy() {
+          return result;
+        }
+        result.map(|n| {
+          Value::BulkString(
+            serde_json::Value::Array(vec![n.into()]).to_string().into(),
+          )
+        })
+      }
+      #[cfg(feature = "sets")]
+      Command::SetAdd { key, value } => {
+        let member = set_member(&value)?;
+        let mut m = data.lock().await;
+        self.check_memory()?;
+        let existed = m.contains_key(&key);
+
+        let result = m.modify_collection(
+          key.clone(),
+          || StoredValue::Set(BTreeSet::new()),
+          |entry| match entry {
+            StoredValue::Set(s) => {
+              let size = member.approximate_size() as isize;
+              let added = s.insert(member);
+              (
+                Ok(Value::Integer(added.into())),
+                if added { size } else { 0 },
+              )
+            }
+            _ => (Err(KraglinError::WrongType), 0),
+          },
+        );
+        if matches!(result, Ok(Value::Integer(1))) {
+          m.notify_write(&key, existed);
+        }
+        result
+      }
+      #[cfg(feature = "sets")]
+      Command::SetMembers { key } => {
+        let m = data.lock().await;
+        let Some(s) = m.get(&key).map(StoredValue::as_set).transpose()? else {
+          return Ok(Value::Set(BTreeSet::new()));
+        };
+        Ok(Value::Set(s.clone()))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetCardinality { key } => {
+        let m = data.lock().await;
+        let len = m
+          .get(&key)
+          .map(StoredValue::as_set)
+          .transpose()?
+          .map_or(0, BTreeSet::len);
+        Ok(Value::Integer(len as i64))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetIsMember { key, value } => {
+        let member = set_member(&value)?;
+        let m = data.lock().await;
+        let is_member = m
+          .get(&key)
+          .map(StoredValue::as_set)
+          .transpose()?
+          .is_some_and(|s| s.contains(&member));
+        Ok(Value::Integer(is_member.into()))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetDifference { set_a, set_b } => {
+        let m = data.lock().await;
+        Ok(Value::Set(set_difference(&m, &set_a, &set_b)?))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore {
+        set_a,
+        set_b,
+        new_set,
+      } => {
+        let mut m = data.lock().await;
+        self.check_memory()?;
+        let difference = set_difference(&m, &set_a, &set_b)?;
+        let len = difference.len();
+        // like Redis, an empty result deletes the destination
+        m.set(
+          new_set,
+          (!difference.is_empty()).then_some(StoredValue::Set(difference)),
+        );
+        Ok(Value::Integer(len as i64))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetRemove { key, value } => {
+        let member = set_member(&value)?;
+        let mut m = data.lock().await;
+        let Some(entry) = m.get(&key) else {
+          return Ok(Value::Integer(0));
+        };
+        entry.as_set()?;
+
+        let (removed, now_empty) = m.modify_collection(
+          key.clone(),
+          || unreachable!("the key exists"),
+          |entry| {
+            let StoredValue::Set(s) = entry else {
+              unreachable!("the key holds a set");
+            };
+            let removed = s.remove(&member);
+            let delta = if removed {
+              -(member.approximate_size() as isize)
+            } else {
+              0
+            };
+            ((removed, s.is_empty()), delta)
+          },
+        );
+        if now_empty {
+          m.remove(&key);
+        } else if removed {
+          m.notify_write(&key, true);
+        }
+        Ok(Value::Integer(removed.into()))
+      }
+      // connection and server debugging commands are handled by the server,
+      // and custom commands are run by its registry, not the backend
+      command @ (Command::Auth { .. }
+      | Command::Hello { .. }
+      | Command::AclSetUser { .. }
+      | Command::AclGetUser { .. }
+      | Command::AclDelUser { .. }
+      | Command::AclList
+      | Command::AclWhoAmI
+      | Command::AclLog { .. }
+      | Command::AclLogReset
+      | Command::AclCat { .. }
+      | Command::LatencyLatest
+      | Command::LatencyReset { .. }
+      | Command::DebugStringMatchLen
+      | Command::Ping { .. }
+      | Command::Quit
+      | Command::Migrate { .. }
+      | Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::ReadOnly
+      | Command::Select { .. }
+      | Command::ReadWrite
+      | Command::Help { .. }
+      | Command::ClientInfo
+      | Command::ClientNoEvict { .. }
+      | Command::ClientNoTouch { .. }
+      | Command::ClientSetInfo { .. }
+      | Command::Custom { .. }) => Err(KraglinError::UnknownCommand(
+        command.command_name().to_lowercase(),
+      )),
+      // the list commands parse, but lists aren't stored yet
+      #[cfg(feature = "lists")]
+      command @ (Command::LeftPush { .. }
+      | Command::RightPush { .. }
+      | Command::ListRange { .. }
+      | Command::ListLength { .. }
+      | Command::LeftPop { .. }
+      | Command::RightPop { .. }) => Err(KraglinError::NotSupported(
+        command.command_name().to_lowercase(),
+      )),
+    }
+  }
+}
+
+impl Backend for SimpleBackend {
+  /// The simple backend is unsharded, so `shards` is ignored. The snapshots
+  /// in `data_dir` are loaded if there are any, and fail startup if they're
+  /// corrupt.
+  fn new(config: BackendConfig) -> Result<SimpleBackend> {
+    let events = KeyEvents::default();
+    let databases = config
+      .databases
+      .map_or(DEFAULT_DATABASES, NonZeroUsize::get);
+    let (snapshots, entries) = match &config.data_dir {
+      Some(dir) => {
+        let (mut stores, mut entries) = (Vec::new(), Vec::new());
+        for db in 0..databases {
+          let path = dir.join(database_snapshot_file(db));
+          let (store, keys) =
+            SnapshotStore::open(&path, dir.join(database_deltas_dir(db)))?;
+          if !keys.is_empty() {
+            tracing::info!(
+              "loaded {} keys from {} (with {} deltas)",
+              keys.len(),
+              path.display(),
+              store.deltas(),
+            );
+          }
+          stores.push(store);
+          entries.push(keys);
+        }
+        (Some(Arc::new(Mutex::new(stores))), entries)
+      }
+      None => (None, Vec::new()),
+    };
+    let backend = SimpleBackend {
+      databases: (0..databases)
+        .map(|db| Database {
+          data:        Arc::new(Mutex::new(Keyspace::with_events(
+            events.in_database(db),
+          ))),
+          used_memory: AtomicUsize::new(0),
+        })
+        .collect(),
+      events,
+      max_memory: config.max_memory,
+      defrag_stats: DefragStats::default(),
+      hot_keys: HotKeys::new(
+        config
+          .hot_keys_sample_rate
+          .unwrap_or(DEFAULT_HOT_KEYS_SAMPLE_RATE),
+      ),
+      compression_threshold: config.compression_threshold,
+      compression_stats: CompressionStats::default(),
+      normalize_values: config.normalize_values,
+      interner: Interner::default(),
+      replica: config.replica,
+      expirations: Expirations::default(),
+      snapshots,
+      save_stats: Arc::default(),
+    };
+    for (db, entries) in entries.into_iter().enumerate() {
+      backend.load_snapshot(db, entries);
+    }
+    Ok(backend)
+  }
+
+  /// Saves a full snapshot of each database, if there's a data directory,
+  /// after any `BGSAVE` which is still running.
+  async fn shutdown(&self) -> Result<()> {
+    let Some(stores) = &self.snapshots else {
+      return Ok(());
+    };
+    let mut stores = stores.clone().lock_owned().await;
+    let mut keyspaces = Vec::with_capacity(self.databases.len());
+    for database in &self.databases {
+      let mut m = database.data.lock().await;
+      m.take_dirty_keys();
+      keyspaces.push((m.snapshot(), None));
+    }
+    let keys = keyspaces.iter().map(|(m, _)| m.len()).sum::<usize>();
+    let stats = self.save_stats.clone();
+    tokio::task::spawn_blocking(move || {
+      let result = save_databases(&mut stores, keyspaces, |_, _| {});
+      stats.record(&result);
+      result?;
+      tracing::info!("saved {keys} keys");
+      Ok(())
+    })
+    .await?
+  }
+
+  fn databases(&self) -> usize { self.databases.len() }
+
+  fn readiness(&self) -> Result<(), String> {
+    if self.save_stats.last_failed.load(Ordering::Relaxed) {
+      return Err("the last snapshot failed to save".to_owned());
+    }
+    Ok(())
+  }
+
+  fn subscribe_events(&self) -> broadcast::Receiver<KeyEvent> {
+    self.events.subscribe()
+  }
+
+  fn track_expirations(&self) {
+    self.expirations.tracked.store(true, Ordering::Relaxed);
+  }
+
+  fn take_expirations(&self) -> Vec<Command> {
+    std::mem::take(&mut *self.expirations.pending.lock().unwrap())
+  }
+
+  async fn defragment(&self) {
+    let mut key_hits = 0;
+    for database in &self.databases {
+      let mut m = database.data.lock().await;
+      // changing entries a snapshot shares would copy all of them first, so
+      // the database is left for a later pass
+      if m.is_shared() {
+        continue;
+      }
+      for value in m.values_mut() {
+        let reallocated = match value {
+          StoredValue::Array(a) => defragment_array(a),
+          StoredValue::Map(m) => defragment_map(m),
+          StoredValue::Set(s) => defragment_set(s),
+          _ => false,
         };
+        key_hits += u64::from(reallocated);
+      }
+      if is_overallocated(m.capacity(), m.len()) {
+        m.shrink_to_fit();
+        self.defrag_stats.table_hits.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+
+    self.defrag_stats.runs.fetch_add(1, Ordering::Relaxed);
+    self
+      .defrag_stats
+      .key_hits
+      .fetch_add(key_hits, Ordering::Relaxed);
+  }
 
-        match m.get(&key) {
-          Some(StoredValue::Map(m)) => Ok(Value::Array(
-            fields
+  fn execute_streaming(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send {
+    match command {
+      // replicas run `KEYS` on a copy of the keyspace with expired data
+      // removed, if there is any, which is built by `execute_in()`
+      Command::Keys if !self.replica => {
+        let data = &self.databases[db].data;
+        // only the key names are copied under the lock; the reply values are
+        // built chunk by chunk as the stream is consumed
+        futures::stream::once(async move {
+          let expiry = Expiry::Remove(&self.expirations, db);
+          let m = Access::new(data, &Command::Keys, expiry).lock().await;
+          let mut keys = m.keys().cloned().collect::<Vec<_>>();
+          keys.sort_unstable();
+          keys
+        })
+        .flat_map(|keys| {
+          ReplyChunk::array(
+            keys
               .into_iter()
-              .map(|f| m.get(&f).cloned().unwrap_or(Value::Nothing))
-              .collect(),
-          )),
-          Some(_) => Err(KraglinError::WrongType),
-          None => all_nothing(),
-        }
-      }
-      Command::SetAdd { key: _, value: _ } => todo!(),
-      Command::SetMembers { key: _ } => todo!(),
-      Command::SetCardinality { key: _ } => todo!(),
-      Command::SetIsMember { key: _, value: _ } => todo!(),
-      Command::SetDifference { set_a: _, set_b: _ } => todo!(),
-      Command::SetDifferenceStore {
-        set_a: _,
-        set_b: _,
-        new_set: _,
-      } => todo!(),
-      Command::SetRemove { key: _, value: _ } => todo!(),
-      Command::LeftPush { key: _, value: _ } => todo!(),
-      Command::RightPush { key: _, value: _ } => todo!(),
-      Command::ListRange {
-        key: _,
-        start: _,
-        end: _,
-      } => todo!(),
-      Command::ListLength { key: _ } => todo!(),
-      Command::LeftPop { key: _ } => todo!(),
-      Command::RightPop { key: _ } => todo!(),
+              .map(|key| Value::BulkString(key.as_bytes().to_vec().into())),
+          )
+        })
+        .map(Ok)
+        .left_stream()
+      }
+      // sets (from `SMEMBERS`) are copied out of the keyspace as they're
+      // stored, so only their serialization is chunked
+      command => futures::stream::once(self.execute_in(db, command, touch))
+        .flat_map(|result| match result {
+          Ok(Value::Set(members)) => {
+            ReplyChunk::set(members).map(Ok).left_stream()
+          }
+          result => futures::stream::iter([result.map(ReplyChunk::Value)])
+            .right_stream(),
+        })
+        .right_stream(),
+    }
+  }
+
+  async fn execute(&self, command: Command) -> KraglinResult {
+    self.execute_in(0, command, true).await
+  }
+
+  /// Takes a snapshot of the database's keyspace, which is constant time,
+  /// and runs the commands against it without holding the lock. The first
+  /// write to the keyspace while the snapshot is in use copies its maps.
+  async fn execute_read_only(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    let data = &self.databases[db].data;
+    let snapshot = Mutex::new(data.lock().await.snapshot());
+    let mut replies = Vec::with_capacity(commands.len());
+    for command in commands {
+      if touch {
+        self.hot_keys.record(command.keys());
+      }
+      // expired data in the snapshot is only hidden, on a copy of what the
+      // command reads, so that the snapshot's maps aren't copied
+      let reply = match self.unexpired_view(&snapshot, &command).await {
+        Some(view) => {
+          let view = Access::new(&view, &command, Expiry::Hide);
+          self.execute_on(&view, command).await
+        }
+        None => {
+          let snapshot = Access::new(&snapshot, &command, Expiry::Keep);
+          self.execute_on(&snapshot, command).await
+        }
+      };
+      replies.push(reply);
+    }
+    replies
+  }
+
+  async fn execute_without_touch(&self, command: Command) -> KraglinResult {
+    self.execute_in(0, command, false).await
+  }
+
+  async fn execute_in(
+    &self,
+    db: usize,
+    command: Command,
+    touch: bool,
+  ) -> KraglinResult {
+    if touch {
+      self.hot_keys.record(command.keys());
+    }
+    self
+      .execute_on_database(db, &self.databases[db].data, command)
+      .await
+  }
+
+  /// Moves the database's keyspace out from under its lock for as long as
+  /// the commands run, except around commands which lock every database
+  /// themselves (`INFO` and `BGSAVE`), which other commands may land either
+  /// side of.
+  async fn execute_batch(
+    &self,
+    db: usize,
+    commands: Vec<Command>,
+    touch: bool,
+  ) -> Vec<KraglinResult> {
+    let database = &self.databases[db];
+    let mut exclusive = None;
+    let mut replies = Vec::with_capacity(commands.len());
+    for command in commands {
+      if touch {
+        self.hot_keys.record(command.keys());
+      }
+      if locks_every_database(&command) {
+        exclusive = None;
+      } else if exclusive.is_none() {
+        exclusive = Some(Exclusive::lock(&database.data).await);
+      }
+      let data = exclusive.as_ref().map_or(&*database.data, |e| &e.data);
+      replies.push(self.execute_on_database(db, data, command).await);
     }
+    replies
   }
 }
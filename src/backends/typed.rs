@@ -0,0 +1,638 @@
+//! Defines `TypedBackendExt`, which wraps [`BackendExt`] to return each
+//! command's reply as a Rust type rather than a [`Value`].
+//!
+//! Replies of an unexpected shape fail with [`KraglinError::WrongType`].
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  future::Future,
+};
+
+use bytes::Bytes;
+use smol_str::SmolStr;
+
+use super::{Backend, BackendExt};
+use crate::{value::Value, KraglinError, KraglinResult};
+
+/// A type a command's reply can be converted into.
+pub trait FromReply: Sized {
+  /// Converts the reply, or fails with [`KraglinError::WrongType`] if it
+  /// doesn't have the expected shape.
+  fn from_reply(reply: Value) -> Result<Self, KraglinError>;
+}
+
+impl FromReply for Value {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> { Ok(reply) }
+}
+
+/// Accepts `OK` and nothing, the replies of commands which only succeed or
+/// fail.
+impl FromReply for () {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Nothing => Ok(()),
+      Value::SimpleString(s) if s == "OK" => Ok(()),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl FromReply for i64 {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Integer(i) => Ok(i),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts non-negative integers, and strings of them (like `SCAN` cursors).
+impl FromReply for u64 {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Integer(i) => {
+        u64::try_from(i).map_err(|_| KraglinError::WrongType)
+      }
+      reply => SmolStr::from_reply(reply)?
+        .parse()
+        .map_err(|_| KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts booleans, and the integers `0` and `1`.
+impl FromReply for bool {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Boolean(b) => Ok(b),
+      Value::Integer(0) => Ok(false),
+      Value::Integer(1) => Ok(true),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts simple strings, and bulk strings which are valid UTF-8.
+impl FromReply for SmolStr {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::SimpleString(s) => Ok(s),
+      Value::BulkString(b) => std::str::from_utf8(&b)
+        .map(SmolStr::from)
+        .map_err(|_| KraglinError::WrongType),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts bulk strings.
+impl FromReply for Bytes {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::BulkString(b) => Ok(b),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts simple strings, and bulk strings which are valid UTF-8.
+impl FromReply for String {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    SmolStr::from_reply(reply).map(String::from)
+  }
+}
+
+/// Converts nothing to `None`, and anything else to `T`.
+impl<T: FromReply> FromReply for Option<T> {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Nothing => Ok(None),
+      reply => T::from_reply(reply).map(Some),
+    }
+  }
+}
+
+impl<T: FromReply> FromReply for Vec<T> {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Array(a) => a.into_iter().map(T::from_reply).collect(),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts two element arrays.
+impl<A: FromReply, B: FromReply> FromReply for (A, B) {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Array(a) => match <[Value; 2]>::try_from(a) {
+        Ok([a, b]) => Ok((A::from_reply(a)?, B::from_reply(b)?)),
+        Err(_) => Err(KraglinError::WrongType),
+      },
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts sets and arrays. Nothing converts to an empty set.
+impl FromReply for BTreeSet<Value> {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Set(s) => Ok(s),
+      Value::Array(a) => Ok(a.into_iter().collect()),
+      Value::Nothing => Ok(BTreeSet::new()),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Accepts maps. Nothing converts to an empty map.
+impl FromReply for BTreeMap<SmolStr, Value> {
+  fn from_reply(reply: Value) -> Result<Self, KraglinError> {
+    match reply {
+      Value::Map(m) => Ok(m),
+      Value::Nothing => Ok(BTreeMap::new()),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+fn convert<T: FromReply>(result: KraglinResult) -> Result<T, KraglinError> {
+  T::from_reply(result?)
+}
+
+/// Extension trait for using commands as functions with typed replies. Each
+/// method wraps the [`BackendExt`] method of the same name.
+#[allow(missing_docs)]
+pub trait TypedBackendExt: Backend {
+  fn set(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<(), KraglinError>> + Send {
+    async move { convert(self.SET(key, value).await) }
+  }
+  fn get(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.GET(key).await) }
+  }
+  fn get_and_delete(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.GET_AND_DELETE(key).await) }
+  }
+  fn set_and_get_old(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.SET_AND_GET_OLD(key, value).await) }
+  }
+  fn mget(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<Option<Value>>, KraglinError>> + Send {
+    async move { convert(self.MGET(keys).await) }
+  }
+  fn incr(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.INCR(key).await) }
+  }
+  fn incr_by(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    increment: i64,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.INCR_BY(key, increment).await) }
+  }
+  fn keys(
+    &self,
+  ) -> impl Future<Output = Result<Vec<SmolStr>, KraglinError>> + Send {
+    async move { convert(self.KEYS().await) }
+  }
+  /// Returns the next cursor and a page of keys.
+  fn scan(
+    &self,
+    cursor: u64,
+    pattern: Option<SmolStr>,
+    count: Option<usize>,
+  ) -> impl Future<Output = Result<(u64, Vec<SmolStr>), KraglinError>> + Send
+  {
+    async move { convert(self.SCAN(cursor, pattern, count).await) }
+  }
+  fn exists(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.EXISTS(keys).await) }
+  }
+  fn del(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.DEL(keys).await) }
+  }
+  fn touch(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.TOUCH(keys).await) }
+  }
+  fn unlink(
+    &self,
+    keys: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.UNLINK(keys).await) }
+  }
+  fn expire(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+  ) -> impl Future<Output = Result<bool, KraglinError>> + Send {
+    async move { convert(self.EXPIRE(key, seconds).await) }
+  }
+  fn pexpire(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+  ) -> impl Future<Output = Result<bool, KraglinError>> + Send {
+    async move { convert(self.PEXPIRE(key, milliseconds).await) }
+  }
+  fn expireat(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time: i64,
+  ) -> impl Future<Output = Result<bool, KraglinError>> + Send {
+    async move { convert(self.EXPIREAT(key, unix_time).await) }
+  }
+  fn pexpireat(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+  ) -> impl Future<Output = Result<bool, KraglinError>> + Send {
+    async move { convert(self.PEXPIREAT(key, unix_time_ms).await) }
+  }
+  fn dump(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Bytes>, KraglinError>> + Send {
+    async move { convert(self.DUMP(key).await) }
+  }
+  fn restore(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    ttl: i64,
+    payload: impl Into<Bytes> + Send,
+    replace: bool,
+  ) -> impl Future<Output = Result<(), KraglinError>> + Send {
+    async move { convert(self.RESTORE(key, ttl, payload, replace).await) }
+  }
+  fn info(&self) -> impl Future<Output = Result<String, KraglinError>> + Send {
+    async move { convert(self.INFO().await) }
+  }
+  fn bgsave(
+    &self,
+  ) -> impl Future<Output = Result<String, KraglinError>> + Send {
+    async move { convert(self.BGSAVE().await) }
+  }
+  fn memory_usage(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<i64>, KraglinError>> + Send {
+    async move { convert(self.MEMORY_USAGE(key).await) }
+  }
+  fn object_encoding(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<SmolStr>, KraglinError>> + Send {
+    async move { convert(self.OBJECT_ENCODING(key).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hset(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    field: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.HSET(key, field, value).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hget(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    field: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.HGET(key, field).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hgetall(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<BTreeMap<SmolStr, Value>, KraglinError>> + Send
+  {
+    async move { convert(self.HGETALL(key).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hmget(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<Option<Value>>, KraglinError>> + Send {
+    async move { convert(self.HMGET(key, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hdel(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.HDEL(key, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hexpire(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    seconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HEXPIRE(key, seconds, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hpexpire(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    milliseconds: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HPEXPIRE(key, milliseconds, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hpexpireat(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    unix_time_ms: i64,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HPEXPIREAT(key, unix_time_ms, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn httl(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HTTL(key, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hpttl(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HPTTL(key, fields).await) }
+  }
+  #[cfg(feature = "hashes")]
+  fn hpersist(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    fields: Vec<SmolStr>,
+  ) -> impl Future<Output = Result<Vec<i64>, KraglinError>> + Send {
+    async move { convert(self.HPERSIST(key, fields).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn sadd(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.SADD(key, value).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn smembers(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<BTreeSet<Value>, KraglinError>> + Send {
+    async move { convert(self.SMEMBERS(key).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn scard(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.SCARD(key).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn sismember(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<bool, KraglinError>> + Send {
+    async move { convert(self.SISMEMBER(key, value).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn sdiff(
+    &self,
+    set_a: impl Into<SmolStr> + Send,
+    set_b: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<BTreeSet<Value>, KraglinError>> + Send {
+    async move { convert(self.SDIFF(set_a, set_b).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn sdiffstore(
+    &self,
+    set_a: impl Into<SmolStr> + Send,
+    set_b: impl Into<SmolStr> + Send,
+    new_set: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.SDIFFSTORE(set_a, set_b, new_set).await) }
+  }
+  #[cfg(feature = "sets")]
+  fn srem(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.SREM(key, value).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn lpush(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.LPUSH(key, value).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn rpush(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    value: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.RPUSH(key, value).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn lrange(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    start: i64,
+    end: i64,
+  ) -> impl Future<Output = Result<Vec<Value>, KraglinError>> + Send {
+    async move { convert(self.LRANGE(key, start, end).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn llen(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.LLEN(key).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn lpop(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.LPOP(key).await) }
+  }
+  #[cfg(feature = "lists")]
+  fn rpop(
+    &self,
+    key: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<Value>, KraglinError>> + Send {
+    async move { convert(self.RPOP(key).await) }
+  }
+  #[cfg(feature = "json")]
+  fn json_set(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    json: impl Into<Bytes> + Send,
+  ) -> impl Future<Output = Result<(), KraglinError>> + Send {
+    async move { convert(self.JSON_SET(key, path, json).await) }
+  }
+  /// Returns the JSON at `path`, serialized. For a `$` path, that's an array
+  /// of the matches.
+  #[cfg(feature = "json")]
+  fn json_get(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<Option<String>, KraglinError>> + Send {
+    async move { convert(self.JSON_GET(key, path).await) }
+  }
+  #[cfg(feature = "json")]
+  fn json_del(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+  ) -> impl Future<Output = Result<i64, KraglinError>> + Send {
+    async move { convert(self.JSON_DEL(key, path).await) }
+  }
+  /// Returns the new number, as a [`Value::Integer`] or [`Value::Double`], or
+  /// for a `$` path as a serialized array holding it.
+  #[cfg(feature = "json")]
+  fn json_numincrby(
+    &self,
+    key: impl Into<SmolStr> + Send,
+    path: impl Into<SmolStr> + Send,
+    by: impl Into<Value> + Send,
+  ) -> impl Future<Output = Result<Value, KraglinError>> + Send {
+    async move { convert(self.JSON_NUMINCRBY(key, path, by).await) }
+  }
+}
+
+impl<B: Backend> TypedBackendExt for B {}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use smol_str::SmolStr;
+
+  use super::FromReply;
+  use crate::{value::Value, KraglinError};
+
+  #[test]
+  fn replies_convert_or_fail_with_wrong_type() {
+    assert_eq!(Option::<Value>::from_reply(Value::Nothing).unwrap(), None);
+    assert_eq!(
+      Vec::<Option<i64>>::from_reply(Value::Array(vec![
+        Value::Integer(1),
+        Value::Nothing,
+      ]))
+      .unwrap(),
+      vec![Some(1), None]
+    );
+    assert_eq!(
+      <(u64, Vec<SmolStr>)>::from_reply(Value::Array(vec![
+        Value::BulkString("12".into()),
+        Value::Array(vec![Value::BulkString("k".into())]),
+      ]))
+      .unwrap(),
+      (12, vec!["k".into()])
+    );
+    assert!(bool::from_reply(Value::Integer(1)).unwrap());
+    assert_eq!(
+      BTreeMap::<SmolStr, Value>::from_reply(Value::Nothing).unwrap(),
+      BTreeMap::new()
+    );
+
+    for result in [
+      i64::from_reply(Value::BulkString("1".into())).map(drop),
+      bool::from_reply(Value::Integer(2)).map(drop),
+      u64::from_reply(Value::Integer(-1)).map(drop),
+      String::from_reply(Value::BulkString(vec![0xff].into())).map(drop),
+      <(i64, i64)>::from_reply(Value::Array(vec![Value::Integer(1)])).map(drop),
+      <()>::from_reply(Value::SimpleString("QUEUED".into())),
+    ] {
+      assert!(matches!(result, Err(KraglinError::WrongType)));
+    }
+  }
+
+  #[cfg(feature = "simple")]
+  #[tokio::test]
+  async fn commands_return_typed_replies() {
+    use crate::backends::{
+      simple::SimpleBackend, Backend, BackendConfig, BackendExt,
+      TypedBackendExt,
+    };
+
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    backend.set("a", "1").await.unwrap();
+    assert_eq!(backend.get("a").await.unwrap(), Some("1".into()));
+    assert_eq!(backend.get("missing").await.unwrap(), None);
+    assert_eq!(backend.incr("a").await.unwrap(), 2);
+    assert_eq!(
+      backend
+        .exists(vec!["a".into(), "missing".into()])
+        .await
+        .unwrap(),
+      1
+    );
+    assert_eq!(backend.keys().await.unwrap(), vec![SmolStr::from("a")]);
+    assert_eq!(
+      backend.scan(0, None, None).await.unwrap(),
+      (0, vec![SmolStr::from("a")])
+    );
+
+    #[cfg(feature = "sets")]
+    {
+      assert_eq!(backend.sadd("set", "m").await.unwrap(), 1);
+      assert_eq!(backend.scard("set").await.unwrap(), 1);
+      assert!(backend.sismember("set", "m").await.unwrap());
+      assert_eq!(
+        backend.smembers("set").await.unwrap(),
+        [Value::BulkString("m".into())].into()
+      );
+    }
+    // replies of the wrong shape fail like type errors from the backend
+    assert!(matches!(
+      super::convert::<i64>(backend.GET("a").await),
+      Err(KraglinError::WrongType)
+    ));
+  }
+}
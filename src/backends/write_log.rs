@@ -0,0 +1,150 @@
+//! Defines `WriteLog`, a [`WriteHook`] which streams every write a backend
+//! applies to subscribers, for consumers outside the server (like change data
+//! capture pipelines or search indexers) which want to tail the writes in
+//! order.
+//!
+//! Writes are streamed as their effects (see [`propagate`](super::propagate)),
+//! the same commands replication and the append-only file see, so replaying
+//! them against an empty backend, switching databases at each `SELECT`,
+//! reproduces its data. Each is numbered with
+//! its offset in the log, so that a subscriber which falls too far behind
+//! and misses some (see [`broadcast::error::RecvError::Lagged`]) knows which,
+//! and can resynchronize (e.g. from a snapshot).
+
+use std::sync::Mutex;
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::sync::broadcast;
+
+use super::propagate::WriteHook;
+use crate::{command::Command, KraglinError};
+
+/// How many writes a subscriber may fall behind by before it starts missing
+/// them.
+pub const WRITE_LOG_CAPACITY: usize = 4096;
+
+/// A write streamed by a [`WriteLog`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteLogEntry {
+  /// The write's position in the log. The first write has offset `0`, and
+  /// each one after has the next.
+  pub offset:  u64,
+  /// The command which reproduces the write.
+  pub command: Command,
+}
+
+/// Streams every write it's told about, in order, to every subscriber.
+///
+/// Add it to a backend with
+/// [`HookedBackend::with_hooks()`](super::propagate::HookedBackend::with_hooks).
+/// Writes are numbered in the order the hook runs for them, which is the
+/// order they're propagated in everywhere else.
+#[derive(Debug)]
+pub struct WriteLog {
+  sender: broadcast::Sender<WriteLogEntry>,
+  /// The offset of the next write, locked while it's sent, so that writes
+  /// reach subscribers in offset order.
+  next:   Mutex<u64>,
+}
+
+impl Default for WriteLog {
+  fn default() -> Self { WriteLog::new(WRITE_LOG_CAPACITY) }
+}
+
+impl WriteLog {
+  /// Creates a log whose subscribers may fall behind by `capacity` writes
+  /// before they start missing them.
+  pub fn new(capacity: usize) -> Self {
+    WriteLog {
+      sender: broadcast::Sender::new(capacity),
+      next:   Mutex::new(0),
+    }
+  }
+
+  /// Returns a receiver of every write from now on.
+  pub fn subscribe(&self) -> broadcast::Receiver<WriteLogEntry> {
+    self.sender.subscribe()
+  }
+
+  /// Returns the offset the next write will have.
+  pub fn offset(&self) -> u64 { *self.next.lock().unwrap() }
+
+  /// Numbers `commands` and sends them to every subscriber. Writes are
+  /// numbered even while nobody is subscribed, so offsets always count every
+  /// write.
+  fn append(&self, commands: &[Command]) {
+    let mut next = self.next.lock().unwrap();
+    for command in commands {
+      if self.sender.receiver_count() > 0 {
+        let _ = self.sender.send(WriteLogEntry {
+          offset:  *next,
+          command: command.clone(),
+        });
+      }
+      *next += 1;
+    }
+  }
+}
+
+impl WriteHook for WriteLog {
+  fn on_write<'a>(
+    &'a self,
+    _: &'a Command,
+    effects: &'a [Command],
+  ) -> BoxFuture<'a, Result<(), KraglinError>> {
+    self.append(effects);
+    async { Ok(()) }.boxed()
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::sync::Arc;
+
+  use tokio::sync::broadcast::error::TryRecvError;
+
+  use super::{WriteLog, WriteLogEntry};
+  use crate::{
+    backends::{
+      propagate::HookedBackend, simple::SimpleBackend, Backend, BackendConfig,
+      BackendExt,
+    },
+    command::Command,
+  };
+
+  #[tokio::test]
+  async fn writes_are_streamed_in_order() {
+    let log = Arc::new(WriteLog::new(2));
+    let backend = HookedBackend::with_hooks(
+      SimpleBackend::new(BackendConfig::default()).unwrap(),
+      vec![log.clone()],
+    );
+
+    // writes are counted before anyone subscribes
+    backend.SET("a", "1").await.unwrap();
+    let mut follower = log.subscribe();
+    backend.SET_AND_GET_OLD("a", "2").await.unwrap();
+    backend.GET("a").await.unwrap();
+    backend.DEL(vec!["a".into()]).await.unwrap();
+
+    assert_eq!(follower.try_recv().unwrap(), WriteLogEntry {
+      offset:  1,
+      command: Command::set("a", "2").build(),
+    });
+    assert_eq!(follower.try_recv().unwrap(), WriteLogEntry {
+      offset:  2,
+      command: Command::Delete {
+        keys: vec!["a".into()],
+      },
+    });
+    assert_eq!(follower.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(log.offset(), 3);
+
+    // a follower which falls behind is told how many writes it missed
+    for i in 0..3 {
+      backend.SET("b", i).await.unwrap();
+    }
+    assert_eq!(follower.try_recv(), Err(TryRecvError::Lagged(1)));
+    assert_eq!(follower.try_recv().unwrap().offset, 4);
+  }
+}
@@ -0,0 +1,331 @@
+#![deny(missing_docs)]
+
+//! `kraglin-bench`: a RESP load generator.
+//!
+//! Opens a number of client connections against any RESP server (kraglin or
+//! Redis), sends a configurable mix of commands with a configurable pipeline
+//! depth, and prints throughput and percentile latencies.
+//!
+//! # Usage
+//! ```text
+//! kraglin-bench [--host HOST] [--port PORT] [--clients N] [--requests N]
+//!               [--pipeline N] [--mix CMD:WEIGHT,...] [--value-size BYTES]
+//!               [--keyspace N]
+//! ```
+//!
+//! The command mix is a comma-separated list of `COMMAND:WEIGHT` pairs, where
+//! the supported commands are `SET`, `GET`, `INCR`, `HSET`, `HGET`, `LPUSH`,
+//! and `SADD`. For example, `--mix set:1,get:4` sends four `GET`s for every
+//! `SET`.
+
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use color_eyre::eyre::{bail, eyre, Result, WrapErr};
+use kraglin::{resp, value::Value};
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+
+/// The benchmark settings, parsed from the command line.
+#[derive(Debug, Clone)]
+struct BenchConfig {
+  host:       String,
+  port:       u16,
+  clients:    usize,
+  requests:   usize,
+  pipeline:   usize,
+  mix:        Vec<(BenchCommand, u32)>,
+  value_size: usize,
+  keyspace:   u64,
+}
+
+impl Default for BenchConfig {
+  fn default() -> Self {
+    BenchConfig {
+      host:       "127.0.0.1".to_string(),
+      port:       6379,
+      clients:    50,
+      requests:   100_000,
+      pipeline:   1,
+      mix:        vec![(BenchCommand::Set, 1), (BenchCommand::Get, 1)],
+      value_size: 3,
+      keyspace:   10_000,
+    }
+  }
+}
+
+impl BenchConfig {
+  /// Parses the config from the given arguments (excluding the binary name).
+  fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self> {
+    let mut config = BenchConfig::default();
+
+    while let Some(flag) = args.next() {
+      if flag == "--help" || flag == "-h" {
+        println!("{}", USAGE);
+        std::process::exit(0);
+      }
+      let value = args
+        .next()
+        .ok_or_else(|| eyre!("missing value for argument `{flag}`"))?;
+      let wrap = || format!("failed to parse value for `{flag}`");
+
+      match flag.as_str() {
+        "--host" => config.host = value,
+        "--port" => config.port = value.parse().wrap_err_with(wrap)?,
+        "--clients" => config.clients = value.parse().wrap_err_with(wrap)?,
+        "--requests" => config.requests = value.parse().wrap_err_with(wrap)?,
+        "--pipeline" => config.pipeline = value.parse().wrap_err_with(wrap)?,
+        "--mix" => config.mix = parse_mix(&value).wrap_err_with(wrap)?,
+        "--value-size" => {
+          config.value_size = value.parse().wrap_err_with(wrap)?
+        }
+        "--keyspace" => config.keyspace = value.parse().wrap_err_with(wrap)?,
+        _ => bail!("unknown argument `{flag}`\n\n{USAGE}"),
+      }
+    }
+
+    if config.clients == 0 || config.pipeline == 0 || config.keyspace == 0 {
+      bail!("`--clients`, `--pipeline`, and `--keyspace` must be non-zero");
+    }
+    Ok(config)
+  }
+}
+
+const USAGE: &str = "usage: kraglin-bench [--host HOST] [--port PORT] \
+                     [--clients N] [--requests N] [--pipeline N] [--mix \
+                     CMD:WEIGHT,...] [--value-size BYTES] [--keyspace N]";
+
+fn parse_mix(mix: &str) -> Result<Vec<(BenchCommand, u32)>> {
+  let mix = mix
+    .split(',')
+    .map(|entry| {
+      let (name, weight) = entry.split_once(':').unwrap_or((entry, "1"));
+      let command = BenchCommand::from_name(name)?;
+      let weight = weight
+        .parse::<u32>()
+        .wrap_err_with(|| format!("invalid weight for `{name}`"))?;
+      Ok((command, weight))
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  if mix.iter().all(|(_, w)| *w == 0) {
+    bail!("the command mix must have at least one non-zero weight");
+  }
+  Ok(mix)
+}
+
+/// The commands the benchmark knows how to generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BenchCommand {
+  Set,
+  Get,
+  Incr,
+  HashSet,
+  HashGet,
+  LeftPush,
+  SetAdd,
+}
+
+impl BenchCommand {
+  fn from_name(name: &str) -> Result<Self> {
+    Ok(match name.to_ascii_uppercase().as_str() {
+      "SET" => BenchCommand::Set,
+      "GET" => BenchCommand::Get,
+      "INCR" => BenchCommand::Incr,
+      "HSET" => BenchCommand::HashSet,
+      "HGET" => BenchCommand::HashGet,
+      "LPUSH" => BenchCommand::LeftPush,
+      "SADD" => BenchCommand::SetAdd,
+      _ => bail!("unsupported benchmark command `{name}`"),
+    })
+  }
+
+  /// Encodes an instance of the command as a RESP array onto `buf`.
+  fn encode(&self, key: u64, value: &[u8], buf: &mut Vec<u8>) {
+    let key = format!("key:{key:012}");
+    let args: Vec<&[u8]> = match self {
+      BenchCommand::Set => vec![b"SET", key.as_bytes(), value],
+      BenchCommand::Get => vec![b"GET", key.as_bytes()],
+      BenchCommand::Incr => vec![b"INCR", b"counter"],
+      BenchCommand::HashSet => {
+        vec![b"HSET", b"hash", key.as_bytes(), value]
+      }
+      BenchCommand::HashGet => vec![b"HGET", b"hash", key.as_bytes()],
+      BenchCommand::LeftPush => vec![b"LPUSH", b"list", value],
+      BenchCommand::SetAdd => vec![b"SADD", b"set", key.as_bytes()],
+    };
+
+    buf.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+      buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+      buf.extend_from_slice(arg);
+      buf.extend_from_slice(b"\r\n");
+    }
+  }
+}
+
+/// A small xorshift PRNG, so that the benchmark doesn't need a `rand`
+/// dependency.
+struct XorShift(u64);
+
+impl XorShift {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+}
+
+/// Reads a single RESP reply from the stream into `buf`, reading more
+/// whenever it doesn't hold a whole one yet. Returns whether the reply was an
+/// error.
+async fn read_reply(
+  stream: &mut TcpStream,
+  buf: &mut BytesMut,
+) -> Result<bool> {
+  loop {
+    if let Some(reply) = resp::decode(buf)? {
+      return Ok(matches!(reply, Value::Error(_)));
+    }
+    if stream.read_buf(buf).await? == 0 {
+      bail!("connection closed by server");
+    }
+  }
+}
+
+/// The results gathered by a single client.
+#[derive(Default)]
+struct ClientResults {
+  latencies: Vec<Duration>,
+  errors:    usize,
+}
+
+async fn run_client(
+  config: &BenchConfig,
+  client_index: usize,
+  requests: usize,
+) -> Result<ClientResults> {
+  let address = format!("{}:{}", config.host, config.port);
+  let mut stream = TcpStream::connect(&address)
+    .await
+    .wrap_err_with(|| format!("failed to connect to {address}"))?;
+  stream.set_nodelay(true)?;
+  let mut replies = BytesMut::new();
+
+  let total_weight: u64 = config.mix.iter().map(|(_, w)| *w as u64).sum();
+  let value = vec![b'x'; config.value_size];
+  let mut rng = XorShift(0x9E37_79B9_7F4A_7C15 ^ (client_index as u64 + 1));
+  let mut results = ClientResults {
+    latencies: Vec::with_capacity(requests),
+    errors:    0,
+  };
+  let mut buf = Vec::new();
+
+  let mut sent = 0;
+  while sent < requests {
+    let batch = config.pipeline.min(requests - sent);
+
+    buf.clear();
+    for _ in 0..batch {
+      let mut pick = rng.next() % total_weight;
+      let command = config
+        .mix
+        .iter()
+        .find(|(_, w)| {
+          let hit = pick < *w as u64;
+          pick = pick.saturating_sub(*w as u64);
+          hit
+        })
+        .map(|(c, _)| *c)
+        .unwrap_or(config.mix[0].0);
+      command.encode(rng.next() % config.keyspace, &value, &mut buf);
+    }
+
+    let start = Instant::now();
+    stream.write_all(&buf).await?;
+    for _ in 0..batch {
+      if read_reply(&mut stream, &mut replies).await? {
+        results.errors += 1;
+      }
+    }
+    // every request in a pipelined batch observes the batch's latency
+    let elapsed = start.elapsed();
+    results
+      .latencies
+      .extend(std::iter::repeat_n(elapsed, batch));
+
+    sent += batch;
+  }
+
+  Ok(results)
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+  if sorted.is_empty() {
+    return Duration::ZERO;
+  }
+  let index = ((p / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+  sorted[index]
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  color_eyre::install()?;
+  let config = BenchConfig::from_args(std::env::args().skip(1))?;
+
+  println!(
+    "benchmarking {}:{} with {} clients, {} requests, pipeline depth {}, \
+     {}-byte values",
+    config.host,
+    config.port,
+    config.clients,
+    config.requests,
+    config.pipeline,
+    config.value_size
+  );
+
+  let start = Instant::now();
+  let mut handles = Vec::with_capacity(config.clients);
+  for client_index in 0..config.clients {
+    // spread the remainder over the first few clients
+    let requests = config.requests / config.clients
+      + usize::from(client_index < config.requests % config.clients);
+    let config = config.clone();
+    handles.push(tokio::spawn(async move {
+      run_client(&config, client_index, requests).await
+    }));
+  }
+
+  let mut latencies = Vec::with_capacity(config.requests);
+  let mut errors = 0;
+  for handle in handles {
+    let results = handle.await.wrap_err("benchmark client panicked")??;
+    latencies.extend(results.latencies);
+    errors += results.errors;
+  }
+  let elapsed = start.elapsed();
+  latencies.sort_unstable();
+
+  println!(
+    "completed {} requests in {:.2}s ({:.0} req/s), {} errors",
+    latencies.len(),
+    elapsed.as_secs_f64(),
+    latencies.len() as f64 / elapsed.as_secs_f64(),
+    errors
+  );
+  for p in [50.0, 90.0, 99.0, 99.9] {
+    println!(
+      "  p{p:<5} {:>10.3}ms",
+      percentile(&latencies, p).as_secs_f64() * 1000.0
+    );
+  }
+  println!(
+    "  max    {:>10.3}ms",
+    latencies.last().copied().unwrap_or_default().as_secs_f64() * 1000.0
+  );
+
+  Ok(())
+}
@@ -0,0 +1,78 @@
+#![deny(missing_docs)]
+
+//! `kraglin-check-aof`: verifies, and optionally repairs, append-only file
+//! segments offline.
+//!
+//! # Usage
+//! ```text
+//! kraglin-check-aof [--fix] FILE...
+//! ```
+//!
+//! Each segment is checked for a torn or corrupt record. With `--fix`, a
+//! segment which isn't valid is truncated to the last valid command before
+//! the problem, dropping everything after it. The exit status is non-zero if
+//! any segment is left invalid.
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::{bail, Result};
+use kraglin::aof::{self, AofProblem};
+
+const USAGE: &str = "usage: kraglin-check-aof [--fix] FILE...";
+
+fn main() -> Result<()> {
+  color_eyre::install()?;
+
+  let mut fix = false;
+  let mut paths = Vec::new();
+  for arg in std::env::args().skip(1) {
+    match arg.as_str() {
+      "--help" | "-h" => {
+        println!("{USAGE}");
+        return Ok(());
+      }
+      "--fix" => fix = true,
+      flag if flag.starts_with('-') => {
+        bail!("unknown argument `{flag}`\n\n{USAGE}")
+      }
+      _ => paths.push(PathBuf::from(arg)),
+    }
+  }
+  if paths.is_empty() {
+    bail!("no files given\n\n{USAGE}");
+  }
+
+  let mut invalid = 0;
+  for path in &paths {
+    let result = aof::check_file(path, fix)?;
+    let problem = match &result.problem {
+      None => {
+        println!("{}: OK, {} commands", path.display(), result.commands);
+        continue;
+      }
+      Some(AofProblem::Torn) => "the last record is incomplete".to_owned(),
+      Some(AofProblem::Corrupt(e)) => format!("corrupt record: {e}"),
+    };
+    println!(
+      "{}: {problem}, after {} valid commands ({} bytes)",
+      path.display(),
+      result.commands,
+      result.valid_len
+    );
+    if fix {
+      println!(
+        "{}: truncated to {} bytes",
+        path.display(),
+        result.valid_len
+      );
+    } else {
+      invalid += 1;
+    }
+  }
+
+  if invalid > 0 {
+    eprintln!("{invalid} invalid segments; run with `--fix` to truncate them");
+    std::process::exit(1);
+  }
+  Ok(())
+}
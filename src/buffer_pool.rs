@@ -0,0 +1,173 @@
+//! Defines the `BufferPool` item, a pool of reusable connection buffers.
+
+use std::{
+  ops::{Deref, DerefMut},
+  sync::{Arc, Mutex},
+};
+
+use bytes::BytesMut;
+
+/// The capacities of the pool's size classes, smallest first. Most frames are
+/// small, so the smallest class is the one most connections use.
+pub const SIZE_CLASSES: [usize; 4] = [1024, 4 * 1024, 16 * 1024, 64 * 1024];
+/// Buffers which have grown beyond this are discarded rather than pooled, so
+/// that one huge frame doesn't pin a huge allocation forever.
+pub const MAX_POOLED_CAPACITY: usize = 2 * SIZE_CLASSES[SIZE_CLASSES.len() - 1];
+/// The maximum number of idle buffers kept per size class.
+pub const MAX_IDLE_PER_CLASS: usize = 256;
+
+#[derive(Debug)]
+struct SizeClass {
+  capacity: usize,
+  idle:     Mutex<Vec<BytesMut>>,
+}
+
+/// A pool of reusable read/write buffers, bucketed into size classes.
+///
+/// Buffers are taken with [`BufferPool::acquire`] and are returned to the pool
+/// when the [`PooledBuffer`] is dropped. Returned buffers are filed under the
+/// largest class whose capacity they still have, so buffers which have grown
+/// move up a class and buffers which have given away their capacity (e.g. by
+/// freezing chunks of it) move down. Buffers smaller than the smallest class
+/// or larger than [`MAX_POOLED_CAPACITY`] are discarded.
+#[derive(Debug)]
+pub struct BufferPool {
+  classes: Vec<SizeClass>,
+}
+
+impl Default for BufferPool {
+  fn default() -> Self {
+    BufferPool {
+      classes: SIZE_CLASSES
+        .iter()
+        .map(|&capacity| SizeClass {
+          capacity,
+          idle: Mutex::new(Vec::new()),
+        })
+        .collect(),
+    }
+  }
+}
+
+impl BufferPool {
+  /// Takes an empty buffer with at least `size_hint` bytes of capacity (capped
+  /// at the largest size class), reusing an idle one if possible.
+  pub fn acquire(self: &Arc<Self>, size_hint: usize) -> PooledBuffer {
+    let class = self
+      .classes
+      .iter()
+      .find(|c| c.capacity >= size_hint)
+      .unwrap_or_else(|| self.classes.last().expect("pool has size classes"));
+
+    let buf = class
+      .idle
+      .lock()
+      .unwrap()
+      .pop()
+      .unwrap_or_else(|| BytesMut::with_capacity(class.capacity));
+
+    PooledBuffer {
+      buf:  Some(buf),
+      pool: self.clone(),
+    }
+  }
+
+  fn release(&self, mut buf: BytesMut) {
+    if buf.capacity() > MAX_POOLED_CAPACITY {
+      return;
+    }
+    buf.clear();
+    let Some(class) = self
+      .classes
+      .iter()
+      .rev()
+      .find(|c| c.capacity <= buf.capacity())
+    else {
+      return;
+    };
+
+    let mut idle = class.idle.lock().unwrap();
+    if idle.len() < MAX_IDLE_PER_CLASS {
+      idle.push(buf);
+    }
+  }
+
+  /// The number of idle buffers in the pool.
+  pub fn idle_count(&self) -> usize {
+    self
+      .classes
+      .iter()
+      .map(|c| c.idle.lock().unwrap().len())
+      .sum()
+  }
+}
+
+/// A buffer borrowed from a [`BufferPool`], which returns to the pool on
+/// drop.
+#[derive(Debug)]
+pub struct PooledBuffer {
+  buf:  Option<BytesMut>,
+  pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+  type Target = BytesMut;
+
+  fn deref(&self) -> &BytesMut {
+    self.buf.as_ref().expect("buffer is present until drop")
+  }
+}
+
+impl DerefMut for PooledBuffer {
+  fn deref_mut(&mut self) -> &mut BytesMut {
+    self.buf.as_mut().expect("buffer is present until drop")
+  }
+}
+
+impl Drop for PooledBuffer {
+  fn drop(&mut self) {
+    if let Some(buf) = self.buf.take() {
+      self.pool.release(buf);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use super::{BufferPool, SIZE_CLASSES};
+
+  #[test]
+  fn buffers_are_reused() {
+    let pool = Arc::new(BufferPool::default());
+
+    let mut buf = pool.acquire(100);
+    assert!(buf.capacity() >= SIZE_CLASSES[0]);
+    buf.extend_from_slice(b"hello");
+    let ptr = buf.as_ptr();
+    drop(buf);
+    assert_eq!(pool.idle_count(), 1);
+
+    let buf = pool.acquire(100);
+    assert!(buf.is_empty());
+    assert_eq!(buf.as_ptr(), ptr);
+    assert_eq!(pool.idle_count(), 0);
+  }
+
+  #[test]
+  fn buffers_are_filed_by_remaining_capacity() {
+    let pool = Arc::new(BufferPool::default());
+
+    let mut buf = pool.acquire(SIZE_CLASSES[1]);
+    // giving away most of the capacity demotes the buffer below every class
+    buf.resize(SIZE_CLASSES[1] - 10, 0);
+    let _chunk = buf.split().freeze();
+    drop(buf);
+    assert_eq!(pool.idle_count(), 0);
+
+    // a large request is served from the largest class
+    let buf = pool.acquire(usize::MAX);
+    assert!(buf.capacity() >= SIZE_CLASSES[3]);
+  }
+}
@@ -0,0 +1,73 @@
+//! The server's clock.
+//!
+//! Everything time-dependent (expirations, blocking timeouts, logs which
+//! record when something happened) reads the time from here rather than from
+//! [`std::time`]. The clock is backed by tokio's, so under paused time (e.g.
+//! `#[tokio::test(start_paused = true)]`) the whole server runs on virtual
+//! time, and tests can step through TTLs and timeouts deterministically with
+//! [`tokio::time::advance()`].
+
+use std::{
+  sync::OnceLock,
+  time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub use tokio::time::{
+  interval, sleep, sleep_until, timeout, timeout_at, Instant,
+  MissedTickBehavior,
+};
+
+/// The wall-clock time when the wall clock was first read, and the instant it
+/// was read at.
+static EPOCH: OnceLock<(Duration, Instant)> = OnceLock::new();
+
+/// Returns the current instant, for measuring durations and deadlines.
+pub fn now() -> Instant { Instant::now() }
+
+/// Returns the time since the unix epoch, for absolute timestamps like those
+/// `EXPIREAT` takes.
+///
+/// This is the real wall-clock time when the clock was first read, advanced
+/// by the (possibly virtual) time elapsed since, so it follows paused time
+/// too and never goes backwards.
+pub fn unix_time() -> Duration {
+  let (wall, instant) = EPOCH.get_or_init(|| {
+    let wall = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .expect("the system clock is after the unix epoch");
+    (wall, now())
+  });
+  *wall + instant.elapsed()
+}
+
+/// Returns the time since the unix epoch in milliseconds.
+pub fn unix_time_ms() -> u64 { unix_time().as_millis() as u64 }
+
+/// Returns the unix time in milliseconds `ttl` milliseconds from now, for
+/// turning relative times to live into absolute deadlines. Returns `None` if
+/// `ttl` is negative or the deadline overflows.
+pub fn unix_time_ms_after(ttl: i64) -> Option<u64> {
+  unix_time_ms().checked_add(u64::try_from(ttl).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{now, unix_time, unix_time_ms};
+
+  #[tokio::test(start_paused = true)]
+  async fn clock_follows_paused_time() {
+    let start = now();
+    let start_unix = unix_time();
+
+    tokio::time::advance(Duration::from_secs(90)).await;
+    assert_eq!(start.elapsed(), Duration::from_secs(90));
+    assert_eq!(unix_time() - start_unix, Duration::from_secs(90));
+
+    // sleeping auto-advances paused time instead of waiting
+    super::sleep(Duration::from_secs(3600)).await;
+    assert_eq!(start.elapsed(), Duration::from_secs(3690));
+    assert!(unix_time_ms() >= start_unix.as_millis() as u64 + 3_690_000);
+  }
+}
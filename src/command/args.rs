@@ -0,0 +1,267 @@
+//! Defines the `Arguments` cursor, which parses a command's arguments into
+//! typed values, and `ArgumentError`, the errors reported for malformed ones.
+//!
+//! The parsers for numbers and timeouts are also exposed on their own, so
+//! that custom commands can validate their arguments with the same errors as
+//! the built-in ones.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use smol_str::SmolStr;
+
+use super::{ExpireCondition, ParseError};
+use crate::{value::Value, KraglinError};
+
+/// An argument which doesn't parse as the type its command expects. The
+/// messages match Redis'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ArgumentError {
+  /// The argument isn't an integer, or doesn't fit in an [`i64`].
+  #[error("value is not an integer or out of range")]
+  NotAnInteger,
+  /// The argument isn't a number, or is `NaN`.
+  #[error("value is not a valid float")]
+  NotAFloat,
+  /// A timeout isn't a number of seconds, or is too long to represent.
+  #[error("timeout is not a float or out of range")]
+  InvalidTimeout,
+  /// A timeout is negative.
+  #[error("timeout is negative")]
+  NegativeTimeout,
+  /// An argument isn't one of the keywords accepted in its position.
+  #[error("syntax error")]
+  Syntax,
+  /// A `SCAN` cursor isn't an unsigned 64-bit integer.
+  #[error("invalid cursor")]
+  InvalidCursor,
+}
+
+impl From<ArgumentError> for KraglinError {
+  fn from(error: ArgumentError) -> Self {
+    match error {
+      ArgumentError::NotAnInteger => KraglinError::NotAnInteger,
+      ArgumentError::NotAFloat => KraglinError::NotAFloat,
+      ArgumentError::InvalidTimeout => KraglinError::InvalidTimeout,
+      ArgumentError::NegativeTimeout => KraglinError::NegativeTimeout,
+      ArgumentError::Syntax => KraglinError::SyntaxError,
+      ArgumentError::InvalidCursor => KraglinError::InvalidCursor,
+    }
+  }
+}
+
+/// Parses a base 10 integer argument.
+pub fn parse_integer(arg: &[u8]) -> Result<i64, ArgumentError> {
+  std::str::from_utf8(arg)
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .ok_or(ArgumentError::NotAnInteger)
+}
+
+/// Parses a floating point argument. Like Redis, `inf` and `-inf` are
+/// accepted but `nan` isn't.
+pub fn parse_double(arg: &[u8]) -> Result<f64, ArgumentError> {
+  std::str::from_utf8(arg)
+    .ok()
+    .and_then(|s| s.parse::<f64>().ok())
+    .filter(|d| !d.is_nan())
+    .ok_or(ArgumentError::NotAFloat)
+}
+
+/// Parses a timeout given in (possibly fractional) seconds, like a blocking
+/// command's. Zero is accepted; what it means is up to the command.
+pub fn parse_timeout(arg: &[u8]) -> Result<Duration, ArgumentError> {
+  let seconds = parse_double(arg)
+    .ok()
+    .filter(|seconds| seconds.is_finite())
+    .ok_or(ArgumentError::InvalidTimeout)?;
+  if seconds < 0.0 {
+    return Err(ArgumentError::NegativeTimeout);
+  }
+  Duration::try_from_secs_f64(seconds)
+    .map_err(|_| ArgumentError::InvalidTimeout)
+}
+
+/// A cursor over a command's arguments, used by [`Command::parse()`].
+///
+/// [`Command::parse()`]: super::Command::parse()
+pub(super) struct Arguments {
+  /// The lowercased command name, for errors.
+  command: String,
+  args:    std::vec::IntoIter<Bytes>,
+}
+
+impl Arguments {
+  /// Creates a cursor over the arguments of the command `command`
+  /// (lowercased), not including its name.
+  pub(super) fn new(command: String, args: std::vec::IntoIter<Bytes>) -> Self {
+    Arguments { command, args }
+  }
+
+  pub(super) fn invalid(&self, reason: &'static str) -> ParseError {
+    ParseError::InvalidArgument {
+      command: self.command.clone(),
+      reason,
+    }
+  }
+
+  pub(super) fn next(&mut self) -> Result<Bytes, ParseError> {
+    self
+      .args
+      .next()
+      .ok_or_else(|| ParseError::WrongArity(self.command.clone()))
+  }
+
+  pub(super) fn is_empty(&self) -> bool { self.args.len() == 0 }
+
+  /// Takes the rest of the arguments.
+  pub(super) fn rest(self) -> Vec<Bytes> { self.args.collect() }
+
+  /// Takes a subcommand name, uppercased.
+  pub(super) fn subcommand(&mut self) -> Result<String, ParseError> {
+    Ok(String::from_utf8_lossy(&self.next()?).to_ascii_uppercase())
+  }
+
+  pub(super) fn unknown_subcommand(&self, subcommand: String) -> ParseError {
+    ParseError::UnknownSubcommand {
+      command: self.command.clone(),
+      subcommand,
+    }
+  }
+
+  /// Takes a key (or other name, like a hash field). Keys must be UTF-8.
+  pub(super) fn key(&mut self) -> Result<SmolStr, ParseError> {
+    let arg = self.next()?;
+    std::str::from_utf8(&arg)
+      .map(SmolStr::from)
+      .map_err(|_| self.invalid("keys must be valid UTF-8"))
+  }
+
+  /// Takes one or more keys, up to the end of the arguments.
+  pub(super) fn keys(&mut self) -> Result<Vec<SmolStr>, ParseError> {
+    let mut keys = vec![self.key()?];
+    while !self.is_empty() {
+      keys.push(self.key()?);
+    }
+    Ok(keys)
+  }
+
+  /// Takes a value, which is always parsed as a [`Value::BulkString`].
+  pub(super) fn value(&mut self) -> Result<Value, ParseError> {
+    self.next().map(Value::BulkString)
+  }
+
+  /// Takes an integer, with [`parse_integer()`].
+  pub(super) fn integer(&mut self) -> Result<i64, ParseError> {
+    Ok(parse_integer(&self.next()?)?)
+  }
+
+  /// Takes a `SCAN` cursor.
+  pub(super) fn cursor(&mut self) -> Result<u64, ParseError> {
+    let arg = self.next()?;
+    std::str::from_utf8(&arg)
+      .ok()
+      .and_then(|s| s.parse().ok())
+      .ok_or_else(|| ArgumentError::InvalidCursor.into())
+  }
+
+  /// Takes the next argument if `parse` recognizes it as a keyword.
+  pub(super) fn optional_keyword<T>(
+    &mut self,
+    parse: impl FnOnce(&[u8]) -> Option<T>,
+  ) -> Option<T> {
+    let keyword = parse(self.args.as_slice().first()?)?;
+    self.args.next();
+    Some(keyword)
+  }
+
+  /// Takes an `ON` or `OFF` switch.
+  pub(super) fn switch(&mut self) -> Result<bool, ParseError> {
+    let arg = self.next()?;
+    if arg.eq_ignore_ascii_case(b"ON") {
+      Ok(true)
+    } else if arg.eq_ignore_ascii_case(b"OFF") {
+      Ok(false)
+    } else {
+      Err(self.invalid("expected ON or OFF"))
+    }
+  }
+
+  /// Takes an optional `NX`, `XX`, `GT`, or `LT` expiration condition.
+  pub(super) fn expire_condition(&mut self) -> Option<ExpireCondition> {
+    self.optional_keyword(ExpireCondition::from_argument)
+  }
+
+  /// Takes the `FIELDS numfields field [field ...]` block of the hash field
+  /// expiration commands.
+  #[cfg(feature = "hashes")]
+  pub(super) fn fields(&mut self) -> Result<Vec<SmolStr>, ParseError> {
+    if !self.next()?.eq_ignore_ascii_case(b"FIELDS") {
+      return Err(self.invalid("expected the FIELDS keyword"));
+    }
+    let count = self.integer()?;
+    let fields = self.keys()?;
+    if usize::try_from(count) != Ok(fields.len()) {
+      return Err(
+        self.invalid("numfields must match the number of fields given"),
+      );
+    }
+    Ok(fields)
+  }
+
+  /// Takes an integer or a double, as a [`Value::Integer`] or
+  /// [`Value::Double`].
+  #[cfg(feature = "json")]
+  pub(super) fn number(&mut self) -> Result<Value, ParseError> {
+    let arg = self.next()?;
+    if let Ok(i) = parse_integer(&arg) {
+      return Ok(Value::Integer(i));
+    }
+    parse_double(&arg)
+      .ok()
+      .filter(|d| d.is_finite())
+      .map(Value::Double)
+      .ok_or_else(|| self.invalid("value is not a valid number"))
+  }
+
+  /// Fails if there are arguments left over.
+  pub(super) fn finish(self) -> Result<(), ParseError> {
+    if self.is_empty() {
+      Ok(())
+    } else {
+      Err(ParseError::WrongArity(self.command))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use super::{parse_double, parse_integer, parse_timeout, ArgumentError};
+
+  #[test]
+  fn arguments_parse_into_typed_values() {
+    assert_eq!(parse_integer(b"-42"), Ok(-42));
+    for arg in [&b"1.5"[..], b"", b"9223372036854775808", b" 1"] {
+      assert_eq!(parse_integer(arg), Err(ArgumentError::NotAnInteger));
+    }
+
+    assert_eq!(parse_double(b"1.5"), Ok(1.5));
+    assert_eq!(parse_double(b"-inf"), Ok(f64::NEG_INFINITY));
+    for arg in [&b"nan"[..], b"one", b""] {
+      assert_eq!(parse_double(arg), Err(ArgumentError::NotAFloat));
+    }
+
+    assert_eq!(parse_timeout(b"0"), Ok(Duration::ZERO));
+    assert_eq!(parse_timeout(b"0.25"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_timeout(b"-1"), Err(ArgumentError::NegativeTimeout));
+    for arg in [&b"inf"[..], b"soon", b"1e300"] {
+      assert_eq!(parse_timeout(arg), Err(ArgumentError::InvalidTimeout));
+    }
+    assert_eq!(
+      ArgumentError::InvalidTimeout.to_string(),
+      "timeout is not a float or out of range"
+    );
+  }
+}
@@ -0,0 +1,400 @@
+//! Builder-style constructors for the commands which take options, like
+//! `Command::set("k", "v").ex(10).nx()` or `Command::scan(0).count(100)`.
+//!
+//! Each builder converts into its [`Command`] with `build()` or [`From`], and
+//! options which aren't set keep the defaults the command has when they're
+//! left out on the wire.
+
+use smol_str::SmolStr;
+
+use super::{
+  Command, ExpireCondition, SetCondition, SetExpiration, SetOptions,
+};
+use crate::value::Value;
+
+impl Command {
+  /// Starts building a `SET` of `key` to `value`.
+  pub fn set(key: impl Into<SmolStr>, value: impl Into<Value>) -> SetBuilder {
+    SetBuilder {
+      key:     key.into(),
+      value:   value.into(),
+      options: SetOptions::default(),
+      get:     false,
+    }
+  }
+
+  /// Starts building a `SCAN` from `cursor`.
+  pub fn scan(cursor: u64) -> ScanBuilder {
+    ScanBuilder {
+      cursor,
+      pattern: None,
+      count: None,
+    }
+  }
+
+  /// Starts building an `EXPIRE`, which expires `key` in `seconds`.
+  pub fn expire(key: impl Into<SmolStr>, seconds: i64) -> ExpireBuilder {
+    ExpireBuilder(Command::Expire {
+      key: key.into(),
+      seconds,
+      condition: None,
+    })
+  }
+
+  /// Starts building a `PEXPIRE`, which expires `key` in `milliseconds`.
+  pub fn pexpire(key: impl Into<SmolStr>, milliseconds: i64) -> ExpireBuilder {
+    ExpireBuilder(Command::PExpire {
+      key: key.into(),
+      milliseconds,
+      condition: None,
+    })
+  }
+
+  /// Starts building an `EXPIREAT`, which expires `key` at the Unix time
+  /// `unix_time`, in seconds.
+  pub fn expireat(key: impl Into<SmolStr>, unix_time: i64) -> ExpireBuilder {
+    ExpireBuilder(Command::ExpireAt {
+      key: key.into(),
+      unix_time,
+      condition: None,
+    })
+  }
+
+  /// Starts building a `PEXPIREAT`, which expires `key` at the Unix time
+  /// `unix_time_ms`, in milliseconds.
+  pub fn pexpireat(
+    key: impl Into<SmolStr>,
+    unix_time_ms: i64,
+  ) -> ExpireBuilder {
+    ExpireBuilder(Command::PExpireAt {
+      key: key.into(),
+      unix_time_ms,
+      condition: None,
+    })
+  }
+
+  /// Starts building an `HEXPIRE`, which expires `fields` of the hash at
+  /// `key` in `seconds`.
+  #[cfg(feature = "hashes")]
+  pub fn hexpire(
+    key: impl Into<SmolStr>,
+    seconds: i64,
+    fields: impl IntoIterator<Item = impl Into<SmolStr>>,
+  ) -> HashExpireBuilder {
+    HashExpireBuilder(Command::HashExpire {
+      key: key.into(),
+      seconds,
+      condition: None,
+      fields: fields.into_iter().map(Into::into).collect(),
+    })
+  }
+
+  /// Starts building an `HPEXPIRE`, which expires `fields` of the hash at
+  /// `key` in `milliseconds`.
+  #[cfg(feature = "hashes")]
+  pub fn hpexpire(
+    key: impl Into<SmolStr>,
+    milliseconds: i64,
+    fields: impl IntoIterator<Item = impl Into<SmolStr>>,
+  ) -> HashExpireBuilder {
+    HashExpireBuilder(Command::HashPExpire {
+      key: key.into(),
+      milliseconds,
+      condition: None,
+      fields: fields.into_iter().map(Into::into).collect(),
+    })
+  }
+
+  /// Starts building an `HPEXPIREAT`, which expires `fields` of the hash at
+  /// `key` at the Unix time `unix_time_ms`, in milliseconds.
+  #[cfg(feature = "hashes")]
+  pub fn hpexpireat(
+    key: impl Into<SmolStr>,
+    unix_time_ms: i64,
+    fields: impl IntoIterator<Item = impl Into<SmolStr>>,
+  ) -> HashExpireBuilder {
+    HashExpireBuilder(Command::HashPExpireAt {
+      key: key.into(),
+      unix_time_ms,
+      condition: None,
+      fields: fields.into_iter().map(Into::into).collect(),
+    })
+  }
+}
+
+/// Builds a `SET`. See [`Command::set()`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct SetBuilder {
+  key:     SmolStr,
+  value:   Value,
+  options: SetOptions,
+  get:     bool,
+}
+
+impl SetBuilder {
+  /// `GET`: replies with the key's old value, rather than `OK`.
+  pub fn get(mut self) -> Self {
+    self.get = true;
+    self
+  }
+
+  /// `EX`: expires the key in `seconds`.
+  pub fn ex(self, seconds: i64) -> Self {
+    self.expiration(SetExpiration::Ex(seconds))
+  }
+
+  /// `PX`: expires the key in `milliseconds`.
+  pub fn px(self, milliseconds: i64) -> Self {
+    self.expiration(SetExpiration::Px(milliseconds))
+  }
+
+  /// `EXAT`: expires the key at the Unix time `unix_time`, in seconds.
+  pub fn exat(self, unix_time: i64) -> Self {
+    self.expiration(SetExpiration::ExAt(unix_time))
+  }
+
+  /// `PXAT`: expires the key at the Unix time `unix_time_ms`, in
+  /// milliseconds.
+  pub fn pxat(self, unix_time_ms: i64) -> Self {
+    self.expiration(SetExpiration::PxAt(unix_time_ms))
+  }
+
+  /// `KEEPTTL`: keeps the key's current time to live, rather than removing
+  /// it.
+  pub fn keep_ttl(self) -> Self { self.expiration(SetExpiration::KeepTtl) }
+
+  /// Sets the key's time to live, replacing any set before.
+  pub fn expiration(mut self, new: SetExpiration) -> Self {
+    self.options.expiration = Some(new);
+    self
+  }
+
+  /// `NX`: only sets the key if it doesn't exist.
+  pub fn nx(self) -> Self { self.condition(SetCondition::Nx) }
+
+  /// `XX`: only sets the key if it already exists.
+  pub fn xx(self) -> Self { self.condition(SetCondition::Xx) }
+
+  /// Sets the condition for setting the key, replacing any set before.
+  pub fn condition(mut self, new: SetCondition) -> Self {
+    self.options.condition = Some(new);
+    self
+  }
+
+  /// Finishes the command.
+  pub fn build(self) -> Command {
+    let SetBuilder {
+      key,
+      value,
+      options,
+      get,
+    } = self;
+    if get {
+      Command::SetAndGet {
+        key,
+        value,
+        options,
+      }
+    } else {
+      Command::Set {
+        key,
+        value,
+        options,
+      }
+    }
+  }
+}
+
+impl From<SetBuilder> for Command {
+  fn from(builder: SetBuilder) -> Self { builder.build() }
+}
+
+/// Builds a `SCAN`. See [`Command::scan()`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct ScanBuilder {
+  cursor:  u64,
+  pattern: Option<SmolStr>,
+  count:   Option<usize>,
+}
+
+impl ScanBuilder {
+  /// `MATCH`: only returns keys matching the glob-style `pattern`.
+  pub fn pattern(mut self, pattern: impl Into<SmolStr>) -> Self {
+    self.pattern = Some(pattern.into());
+    self
+  }
+
+  /// `COUNT`: looks at about `count` keys, rather than
+  /// [`DEFAULT_SCAN_COUNT`](super::DEFAULT_SCAN_COUNT).
+  ///
+  /// # Panics
+  /// Panics if `count` is zero, which `SCAN` rejects.
+  pub fn count(mut self, count: usize) -> Self {
+    assert!(count > 0, "SCAN's COUNT must be positive");
+    self.count = Some(count);
+    self
+  }
+
+  /// Finishes the command.
+  pub fn build(self) -> Command {
+    let ScanBuilder {
+      cursor,
+      pattern,
+      count,
+    } = self;
+    Command::Scan {
+      cursor,
+      pattern,
+      count,
+    }
+  }
+}
+
+impl From<ScanBuilder> for Command {
+  fn from(builder: ScanBuilder) -> Self { builder.build() }
+}
+
+/// Builds one of the key expiration commands. See [`Command::expire()`],
+/// [`Command::pexpire()`], [`Command::expireat()`], and
+/// [`Command::pexpireat()`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct ExpireBuilder(Command);
+
+impl ExpireBuilder {
+  /// `NX`: only sets a time to live if the key doesn't have one.
+  pub fn nx(self) -> Self { self.condition(ExpireCondition::Nx) }
+
+  /// `XX`: only sets a time to live if the key already has one.
+  pub fn xx(self) -> Self { self.condition(ExpireCondition::Xx) }
+
+  /// `GT`: only sets a time to live later than the current one.
+  pub fn gt(self) -> Self { self.condition(ExpireCondition::Gt) }
+
+  /// `LT`: only sets a time to live earlier than the current one.
+  pub fn lt(self) -> Self { self.condition(ExpireCondition::Lt) }
+
+  /// Sets the condition for the new time to live, replacing any set before.
+  pub fn condition(mut self, new: ExpireCondition) -> Self {
+    match &mut self.0 {
+      Command::Expire { condition, .. }
+      | Command::PExpire { condition, .. }
+      | Command::ExpireAt { condition, .. }
+      | Command::PExpireAt { condition, .. } => *condition = Some(new),
+      _ => unreachable!("the builder holds a key expiration"),
+    }
+    self
+  }
+
+  /// Finishes the command.
+  pub fn build(self) -> Command { self.0 }
+}
+
+impl From<ExpireBuilder> for Command {
+  fn from(builder: ExpireBuilder) -> Self { builder.build() }
+}
+
+/// Builds one of the hash field expiration commands. See
+/// [`Command::hexpire()`], [`Command::hpexpire()`], and
+/// [`Command::hpexpireat()`].
+#[cfg(feature = "hashes")]
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct HashExpireBuilder(Command);
+
+#[cfg(feature = "hashes")]
+impl HashExpireBuilder {
+  /// `NX`: only sets a time to live on fields which don't have one.
+  pub fn nx(self) -> Self { self.condition(ExpireCondition::Nx) }
+
+  /// `XX`: only sets a time to live on fields which already have one.
+  pub fn xx(self) -> Self { self.condition(ExpireCondition::Xx) }
+
+  /// `GT`: only sets a time to live later than the current one.
+  pub fn gt(self) -> Self { self.condition(ExpireCondition::Gt) }
+
+  /// `LT`: only sets a time to live earlier than the current one.
+  pub fn lt(self) -> Self { self.condition(ExpireCondition::Lt) }
+
+  /// Sets the condition for the new time to live, replacing any set before.
+  pub fn condition(mut self, new: ExpireCondition) -> Self {
+    match &mut self.0 {
+      Command::HashExpire { condition, .. }
+      | Command::HashPExpire { condition, .. }
+      | Command::HashPExpireAt { condition, .. } => *condition = Some(new),
+      _ => unreachable!("the builder holds a hash field expiration"),
+    }
+    self
+  }
+
+  /// Finishes the command.
+  pub fn build(self) -> Command { self.0 }
+}
+
+#[cfg(feature = "hashes")]
+impl From<HashExpireBuilder> for Command {
+  fn from(builder: HashExpireBuilder) -> Self { builder.build() }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{command::Command, value::Value};
+
+  fn parse(args: &[&str]) -> Command {
+    Command::parse(Value::Array(
+      args
+        .iter()
+        .map(|arg| Value::BulkString(arg.as_bytes().to_vec().into()))
+        .collect(),
+    ))
+    .unwrap()
+  }
+
+  #[test]
+  fn builders_match_parsed_commands() {
+    let value = || Value::BulkString("v".into());
+    assert_eq!(
+      Command::set("k", value()).build(),
+      parse(&["SET", "k", "v"])
+    );
+    assert_eq!(
+      Command::set("k", value()).get().build(),
+      parse(&["SET", "k", "v", "GET"])
+    );
+    assert_eq!(
+      Command::set("k", value()).ex(10).nx().build(),
+      parse(&["SET", "k", "v", "NX", "EX", "10"])
+    );
+    assert_eq!(
+      Command::from(Command::set("k", value()).px(5).keep_ttl().xx().get()),
+      parse(&["SET", "k", "v", "KEEPTTL", "XX", "GET"])
+    );
+    assert_eq!(Command::scan(0).build(), parse(&["SCAN", "0"]));
+    assert_eq!(
+      Command::from(Command::scan(7).pattern("a*").count(100)),
+      parse(&["SCAN", "7", "MATCH", "a*", "COUNT", "100"])
+    );
+    assert_eq!(
+      Command::expire("k", 10).build(),
+      parse(&["EXPIRE", "k", "10"])
+    );
+    assert_eq!(
+      Command::from(Command::pexpireat("k", 1000).nx().lt()),
+      parse(&["PEXPIREAT", "k", "1000", "LT"])
+    );
+
+    #[cfg(feature = "hashes")]
+    {
+      assert_eq!(
+        Command::hexpire("h", 10, ["a", "b"]).nx().build(),
+        parse(&["HEXPIRE", "h", "10", "NX", "FIELDS", "2", "a", "b"])
+      );
+      assert_eq!(
+        Command::from(Command::hpexpireat("h", 1000, ["a"]).xx().gt()),
+        parse(&["HPEXPIREAT", "h", "1000", "GT", "FIELDS", "1", "a"])
+      );
+    }
+  }
+}
@@ -0,0 +1,2340 @@
+//! Defines the `Command` item, and its conversion to and from RESP frames.
+
+mod args;
+mod builder;
+mod spec;
+mod table;
+
+use bytes::Bytes;
+use smol_str::SmolStr;
+
+use self::args::Arguments;
+#[cfg(feature = "hashes")]
+pub use self::builder::HashExpireBuilder;
+pub use self::{
+  args::{parse_double, parse_integer, parse_timeout, ArgumentError},
+  builder::{ScanBuilder, SetBuilder},
+  spec::{AclCategories, CommandDocs, CommandFlags, CommandSpec},
+  table::CommandTable,
+};
+use crate::{value::Value, KraglinError};
+
+/// An error parsing a [`Command`] from a RESP frame.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParseError {
+  /// The frame isn't an array of arguments.
+  #[error("Protocol error: expected an array of arguments")]
+  NotAnArray,
+  /// The frame is an empty array.
+  #[error("Protocol error: empty command")]
+  Empty,
+  /// The command name isn't recognized.
+  #[error(
+    "unknown command '{command}', with args beginning with: {args}{}",
+    did_you_mean(.suggestion)
+  )]
+  UnknownCommand {
+    /// The command name, as sent.
+    command:    String,
+    /// The first arguments, quoted, as Redis shows them.
+    args:       String,
+    /// The (lowercased) name of a built-in command with a similar name, if
+    /// there is one.
+    suggestion: Option<String>,
+  },
+  /// The subcommand name isn't recognized.
+  #[error("unknown subcommand '{subcommand}' for '{command}'")]
+  UnknownSubcommand {
+    /// The command name.
+    command:    String,
+    /// The unrecognized subcommand name.
+    subcommand: String,
+  },
+  /// The command was given the wrong number of arguments.
+  #[error("wrong number of arguments for '{0}' command")]
+  WrongArity(String),
+  /// An argument doesn't parse as the type the command expects.
+  #[error(transparent)]
+  Argument(#[from] ArgumentError),
+  /// An argument is malformed.
+  #[error("invalid argument for '{command}' command: {reason}")]
+  InvalidArgument {
+    /// The command name.
+    command: String,
+    /// What's wrong with the argument.
+    reason:  &'static str,
+  },
+}
+
+/// How many bytes of a command's name and arguments are shown in an unknown
+/// command error, like Redis.
+const UNKNOWN_COMMAND_SHOWN_LEN: usize = 128;
+
+impl ParseError {
+  /// Creates an [`UnknownCommand`](ParseError::UnknownCommand) error for the
+  /// command `name` sent with `args`, suggesting a built-in command with a
+  /// similar name.
+  pub fn unknown_command(name: &[u8], args: &[Bytes]) -> ParseError {
+    let name = String::from_utf8_lossy(name);
+    let mut shown = String::new();
+    for arg in args {
+      let arg = String::from_utf8_lossy(arg);
+      if shown.len() + arg.len() > UNKNOWN_COMMAND_SHOWN_LEN {
+        break;
+      }
+      shown.push_str(&format!("'{arg}' "));
+    }
+    ParseError::UnknownCommand {
+      command:    name.chars().take(UNKNOWN_COMMAND_SHOWN_LEN).collect(),
+      args:       shown,
+      suggestion: CommandTable::get().suggest(&name).map(str::to_lowercase),
+    }
+  }
+
+  /// Returns the error without its suggestion, e.g. because the suggested
+  /// command is disabled.
+  pub fn without_suggestion(self) -> ParseError {
+    match self {
+      ParseError::UnknownCommand { command, args, .. } => {
+        ParseError::UnknownCommand {
+          command,
+          args,
+          suggestion: None,
+        }
+      }
+      other => other,
+    }
+  }
+}
+
+impl From<ParseError> for KraglinError {
+  fn from(error: ParseError) -> Self {
+    match error {
+      ParseError::Argument(error) => error.into(),
+      error => KraglinError::InvalidCommand(error),
+    }
+  }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+  suggestion
+    .as_ref()
+    .map_or_else(String::new, |s| format!("(did you mean '{s}'?)"))
+}
+
+/// A condition on a key's or field's current time to live, which must hold
+/// for an expiration command to set a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExpireCondition {
+  /// `NX`: only if there's no time to live yet.
+  Nx,
+  /// `XX`: only if there's already a time to live.
+  Xx,
+  /// `GT`: only if the new expiration is later than the current one. No time
+  /// to live counts as an infinitely late expiration.
+  Gt,
+  /// `LT`: only if the new expiration is earlier than the current one.
+  Lt,
+}
+
+impl ExpireCondition {
+  /// Parses a (case-insensitive) condition argument.
+  pub fn from_argument(arg: &[u8]) -> Option<Self> {
+    match arg.to_ascii_uppercase().as_slice() {
+      b"NX" => Some(ExpireCondition::Nx),
+      b"XX" => Some(ExpireCondition::Xx),
+      b"GT" => Some(ExpireCondition::Gt),
+      b"LT" => Some(ExpireCondition::Lt),
+      _ => None,
+    }
+  }
+
+  /// The condition's argument name.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ExpireCondition::Nx => "NX",
+      ExpireCondition::Xx => "XX",
+      ExpireCondition::Gt => "GT",
+      ExpireCondition::Lt => "LT",
+    }
+  }
+
+  /// Whether a new expiration at `new` may replace the `current` one, if
+  /// any. Both are deadlines in the same unit.
+  pub fn allows(&self, current: Option<u64>, new: u64) -> bool {
+    match self {
+      ExpireCondition::Nx => current.is_none(),
+      ExpireCondition::Xx => current.is_some(),
+      ExpireCondition::Gt => current.is_some_and(|current| new > current),
+      ExpireCondition::Lt => current.is_none_or(|current| new < current),
+    }
+  }
+}
+
+/// A condition on whether a key exists, which must hold for `SET` to set it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetCondition {
+  /// `NX`: only if the key doesn't exist yet.
+  Nx,
+  /// `XX`: only if the key already exists.
+  Xx,
+}
+
+impl SetCondition {
+  /// Parses a (case-insensitive) condition argument.
+  pub fn from_argument(arg: &[u8]) -> Option<Self> {
+    match arg.to_ascii_uppercase().as_slice() {
+      b"NX" => Some(SetCondition::Nx),
+      b"XX" => Some(SetCondition::Xx),
+      _ => None,
+    }
+  }
+
+  /// The condition's argument name.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SetCondition::Nx => "NX",
+      SetCondition::Xx => "XX",
+    }
+  }
+
+  /// Whether a key may be set, given whether it `exists`.
+  pub fn allows(&self, exists: bool) -> bool {
+    match self {
+      SetCondition::Nx => !exists,
+      SetCondition::Xx => exists,
+    }
+  }
+}
+
+/// The time to live `SET` gives the key it sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetExpiration {
+  /// `EX`: a time to live in seconds.
+  Ex(i64),
+  /// `PX`: a time to live in milliseconds.
+  Px(i64),
+  /// `EXAT`: the unix time, in seconds, at which the key expires.
+  ExAt(i64),
+  /// `PXAT`: the unix time, in milliseconds, at which the key expires.
+  PxAt(i64),
+  /// `KEEPTTL`: keeps the time to live the key already has, if any.
+  KeepTtl,
+}
+
+impl SetExpiration {
+  /// The unix time in milliseconds at which a key set at `now` (also in unix
+  /// milliseconds) expires. `None` for `KEEPTTL`, and for times which aren't
+  /// positive or are out of range, which `SET` rejects.
+  pub fn unix_time_ms(&self, now: u64) -> Option<u64> {
+    let now = i64::try_from(now).ok()?;
+    let deadline = match *self {
+      SetExpiration::Ex(seconds) if seconds > 0 => {
+        now.checked_add(seconds.checked_mul(1000)?)
+      }
+      SetExpiration::Px(milliseconds) if milliseconds > 0 => {
+        now.checked_add(milliseconds)
+      }
+      SetExpiration::ExAt(unix_time) if unix_time > 0 => {
+        unix_time.checked_mul(1000)
+      }
+      SetExpiration::PxAt(unix_time_ms) if unix_time_ms > 0 => {
+        Some(unix_time_ms)
+      }
+      _ => None,
+    };
+    deadline.and_then(|deadline| u64::try_from(deadline).ok())
+  }
+
+  /// The expiration's arguments, e.g. `["EX", "10"]`.
+  pub fn arguments(&self) -> Vec<String> {
+    let (name, time) = match *self {
+      SetExpiration::Ex(time) => ("EX", time),
+      SetExpiration::Px(time) => ("PX", time),
+      SetExpiration::ExAt(time) => ("EXAT", time),
+      SetExpiration::PxAt(time) => ("PXAT", time),
+      SetExpiration::KeepTtl => return vec!["KEEPTTL".to_owned()],
+    };
+    vec![name.to_owned(), time.to_string()]
+  }
+}
+
+/// The options of `SET`, other than `GET`. By default, the key is set
+/// whether or not it exists, and without a time to live.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct SetOptions {
+  /// The time to live to give the key, if any.
+  pub expiration: Option<SetExpiration>,
+  /// The condition on whether the key exists, if any.
+  pub condition:  Option<SetCondition>,
+}
+
+impl SetOptions {
+  /// The options' arguments, in the order [`Command::parse()`] accepts them.
+  pub fn arguments(&self) -> Vec<String> {
+    let condition = self.condition.map(|c| c.as_str().to_owned());
+    condition
+      .into_iter()
+      .chain(self.expiration.iter().flat_map(SetExpiration::arguments))
+      .collect()
+  }
+}
+
+/// An attribute a client library sets about itself with `CLIENT SETINFO`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientAttribute {
+  /// `LIB-NAME`: the name of the client library.
+  LibName,
+  /// `LIB-VER`: the version of the client library.
+  LibVer,
+}
+
+impl ClientAttribute {
+  /// Parses a (case-insensitive) attribute argument.
+  pub fn from_argument(arg: &[u8]) -> Option<Self> {
+    match arg.to_ascii_uppercase().as_slice() {
+      b"LIB-NAME" => Some(ClientAttribute::LibName),
+      b"LIB-VER" => Some(ClientAttribute::LibVer),
+      _ => None,
+    }
+  }
+
+  /// The attribute's argument name.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ClientAttribute::LibName => "LIB-NAME",
+      ClientAttribute::LibVer => "LIB-VER",
+    }
+  }
+}
+
+/// How many keys `SCAN` looks at when it isn't given a `COUNT`.
+pub const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// All commands supported by [`kraglin`](crate).
+#[derive(Debug, Clone, PartialEq, Hash)]
+pub enum Command {
+  /// `SET`: Sets a key. Returns nothing if the `options`' condition doesn't
+  /// hold.
+  Set {
+    /// The key to set.
+    key:     SmolStr,
+    /// The value to set the key with.
+    value:   Value,
+    /// The key's time to live and the condition on setting it.
+    options: SetOptions,
+  },
+  /// `SET ... GET`: Sets a key, returning its old value whether or not the
+  /// `options`' condition holds.
+  SetAndGet {
+    /// The key to set.
+    key:     SmolStr,
+    /// The value to set the key with.
+    value:   Value,
+    /// The key's time to live and the condition on setting it.
+    options: SetOptions,
+  },
+  /// `GET`: Gets a key.
+  Get {
+    /// The key to get.
+    key: SmolStr,
+  },
+  /// `GETDEL`: Gets a key and deletes it.
+  GetDelete {
+    /// The key to get and delete.
+    key: SmolStr,
+  },
+  /// `MGET`: Gets multiple keys.
+  MultipleGet {
+    /// The keys to get.
+    keys: Vec<SmolStr>,
+  },
+  /// `INCR`: Increments a key.
+  ///
+  /// This works for anything that looks like an integer.
+  Increment {
+    /// The key to increment.
+    ///
+    /// This key is allowed to be any type that can be interpreted as a
+    /// [`Value::Integer`], and will be incremented in place. The type will not
+    /// change.
+    key: SmolStr,
+  },
+  /// `INCRBY`: Increments a key by the given amount.
+  ///
+  /// Like [`Command::Increment`], this works for anything that looks like an
+  /// integer, and doesn't change its type.
+  IncrementBy {
+    /// The key to increment.
+    key:       SmolStr,
+    /// The amount to add, which may be negative.
+    increment: i64,
+  },
+  /// `KEYS`: Lists all keys.
+  Keys,
+  /// `SCAN`: Lists some of the keys, continuing from a cursor returned by
+  /// the previous call.
+  ///
+  /// Every key which exists for the whole of a scan (from cursor `0` until
+  /// `0` is returned again) is returned exactly once.
+  Scan {
+    /// Where to continue from, or `0` to start a scan.
+    cursor:  u64,
+    /// A glob pattern which returned keys must match.
+    pattern: Option<SmolStr>,
+    /// About how many keys to look at, rather than the default of 10. Fewer
+    /// may be returned if they don't match `pattern`.
+    count:   Option<usize>,
+  },
+  /// `EXISTS`: Counts how many of the given keys exist. A key given more
+  /// than once is counted each time.
+  Exists {
+    /// The keys to check.
+    keys: Vec<SmolStr>,
+  },
+  /// `DEL`: Deletes keys, returning how many existed.
+  Delete {
+    /// The keys to delete.
+    keys: Vec<SmolStr>,
+  },
+  /// `TOUCH`: Counts how many of the given keys exist, recording an access to
+  /// each. Unlike other commands, it does so even on connections which set
+  /// `CLIENT NO-TOUCH`.
+  Touch {
+    /// The keys to touch.
+    keys: Vec<SmolStr>,
+  },
+  /// `UNLINK`: Deletes keys, returning how many existed. Redis reclaims
+  /// their memory in the background; here it's the same as `DEL`.
+  Unlink {
+    /// The keys to delete.
+    keys: Vec<SmolStr>,
+  },
+  /// `EXPIRE`: Sets a time to live, in seconds, on a key. Returns whether it
+  /// was set, which fails if the key doesn't exist or `condition` doesn't
+  /// hold.
+  Expire {
+    /// The key to expire.
+    key:       SmolStr,
+    /// The time to live. Zero or less deletes the key immediately.
+    seconds:   i64,
+    /// The condition on the key's current time to live, if any.
+    condition: Option<ExpireCondition>,
+  },
+  /// `PEXPIRE`: Sets a time to live, in milliseconds, on a key.
+  PExpire {
+    /// The key to expire.
+    key:          SmolStr,
+    /// The time to live. Zero or less deletes the key immediately.
+    milliseconds: i64,
+    /// The condition on the key's current time to live, if any.
+    condition:    Option<ExpireCondition>,
+  },
+  /// `EXPIREAT`: Sets the unix time, in seconds, at which a key expires.
+  ExpireAt {
+    /// The key to expire.
+    key:       SmolStr,
+    /// The unix time in seconds. Times in the past delete the key
+    /// immediately.
+    unix_time: i64,
+    /// The condition on the key's current time to live, if any.
+    condition: Option<ExpireCondition>,
+  },
+  /// `PEXPIREAT`: Sets the unix time, in milliseconds, at which a key
+  /// expires.
+  PExpireAt {
+    /// The key to expire.
+    key:          SmolStr,
+    /// The unix time in milliseconds. Times in the past delete the key
+    /// immediately.
+    unix_time_ms: i64,
+    /// The condition on the key's current time to live, if any.
+    condition:    Option<ExpireCondition>,
+  },
+  /// `DUMP`: Serializes a key's value into a checksummed payload, which
+  /// `RESTORE` can recreate it from.
+  Dump {
+    /// The key to serialize.
+    key: SmolStr,
+  },
+  /// `RESTORE`: Creates a key from a `DUMP` payload, which is rejected if its
+  /// checksum doesn't match.
+  Restore {
+    /// The key to create.
+    key:     SmolStr,
+    /// The key's time to live in milliseconds, or 0 for none.
+    ttl:     i64,
+    /// The payload, as returned by `DUMP`.
+    payload: Bytes,
+    /// Whether to overwrite the key if it exists, rather than failing.
+    replace: bool,
+    /// Whether `ttl` is the unix time in milliseconds at which the key
+    /// expires, rather than a time to live.
+    absttl:  bool,
+  },
+  /// `MIGRATE`: Copies keys to another kraglin or Redis instance with `DUMP`
+  /// and `RESTORE`, then deletes them here unless `copy` is set. This is
+  /// handled by the server, not the backend.
+  Migrate {
+    /// The target instance's host.
+    host:    SmolStr,
+    /// The target instance's port.
+    port:    u16,
+    /// The keys to migrate.
+    keys:    Vec<SmolStr>,
+    /// The database on the target to restore the keys into.
+    db:      i64,
+    /// How long the target may take to connect or reply, in milliseconds. A
+    /// timeout which isn't positive means the default of a second.
+    timeout: i64,
+    /// Whether to keep the keys here once they've been migrated.
+    copy:    bool,
+    /// Whether to overwrite keys which already exist on the target.
+    replace: bool,
+    /// The username (if any) and password to authenticate to the target
+    /// with.
+    auth:    Option<(Option<SmolStr>, Bytes)>,
+  },
+  /// `INFO`: Returns server info.
+  Info,
+  /// `BGSAVE`: Saves a snapshot of the keyspace in the background. The
+  /// snapshot may be a delta of the keys which changed since the last one.
+  BackgroundSave,
+  /// `MEMORY USAGE`: Returns the approximate number of bytes used by a key
+  /// and its value.
+  MemoryUsage {
+    /// The key to measure.
+    key: SmolStr,
+  },
+  /// `OBJECT ENCODING`: Returns the name of the internal representation of
+  /// a key's value, e.g. whether it's compressed.
+  ObjectEncoding {
+    /// The key to inspect.
+    key: SmolStr,
+  },
+  /// `DEBUG HOTKEYS`: Returns the most frequently accessed keys.
+  DebugHotKeys,
+  /// `DEBUG OBJECT`: Describes the internals of a key's value, like its
+  /// encoding and serialized length.
+  DebugObject {
+    /// The key to describe.
+    key: SmolStr,
+  },
+  /// `DEBUG KEYSTATS`: Summarizes the keyspace: how many keys there are of
+  /// each type and size, how many have a time to live, and the biggest key
+  /// of each type.
+  DebugKeyStats {
+    /// How many keys to sample, rather than scanning the whole keyspace.
+    samples: Option<usize>,
+  },
+  /// `DEBUG STRINGMATCH-LEN`: Fuzzes the glob pattern matcher with random
+  /// patterns and keys, to check that it doesn't crash or hang.
+  DebugStringMatchLen,
+  /// `AUTH`: Authenticates the connection. This is handled by the server,
+  /// not the backend.
+  Auth {
+    /// The user to authenticate as. Without one, the default user is used.
+    username: Option<SmolStr>,
+    /// The password.
+    password: Bytes,
+  },
+  /// `HELLO`: Negotiates the RESP version the connection speaks, optionally
+  /// authenticating it too, and describes the server. This is handled by the
+  /// server, not the backend.
+  Hello {
+    /// The RESP version to switch to. Without one, the connection keeps
+    /// speaking the version it does.
+    protover: Option<i64>,
+    /// The username and password to authenticate with, from `AUTH`.
+    auth:     Option<(SmolStr, Bytes)>,
+  },
+  /// `ACL SETUSER`: Creates or modifies a user by applying ACL rules.
+  AclSetUser {
+    /// The user to create or modify.
+    username: SmolStr,
+    /// The rules to apply, in order.
+    rules:    Vec<SmolStr>,
+  },
+  /// `ACL GETUSER`: Describes a user's flags, passwords, and permissions.
+  AclGetUser {
+    /// The user to describe.
+    username: SmolStr,
+  },
+  /// `ACL DELUSER`: Deletes users.
+  AclDelUser {
+    /// The users to delete.
+    usernames: Vec<SmolStr>,
+  },
+  /// `ACL LIST`: Describes every user as a list of rules.
+  AclList,
+  /// `ACL WHOAMI`: Returns the connection's user.
+  AclWhoAmI,
+  /// `ACL LOG`: Lists recent authentication failures and denied commands,
+  /// newest first.
+  AclLog {
+    /// How many entries to list, if not the default of 10.
+    count: Option<usize>,
+  },
+  /// `ACL LOG RESET`: Clears the ACL log.
+  AclLogReset,
+  /// `ACL CAT`: Lists the ACL categories, or the commands in one of them.
+  AclCat {
+    /// The category to list the commands of, if any.
+    category: Option<SmolStr>,
+  },
+  /// `LATENCY LATEST`: Lists the latest and worst latency of each event
+  /// recorded by the latency monitor.
+  LatencyLatest,
+  /// `LATENCY RESET`: Clears the samples of events, or of every event.
+  LatencyReset {
+    /// The events to clear, or none to clear them all.
+    events: Vec<SmolStr>,
+  },
+  /// `PING`: Replies with `PONG`, or echoes the message if given one.
+  Ping {
+    /// The message to echo.
+    message: Option<Bytes>,
+  },
+  /// `QUIT`: Replies with `OK` and closes the connection. This is handled by
+  /// the server, not the backend.
+  Quit,
+  /// `MULTI`: Starts a transaction. Commands are queued rather than run
+  /// until `EXEC`.
+  Multi,
+  /// `EXEC`: Runs the commands queued since `MULTI`, replying with an array
+  /// of their replies.
+  Exec,
+  /// `DISCARD`: Discards the commands queued since `MULTI`.
+  Discard,
+  /// `READONLY`: Lets the connection read from a replica, accepting that
+  /// its data may lag behind its master's. See
+  /// [`Dispatcher::with_replica()`](crate::server::Dispatcher::with_replica).
+  ReadOnly,
+  /// `READWRITE`: Undoes `READONLY`.
+  ReadWrite,
+  /// `SELECT`: Switches the connection to another numbered database, which
+  /// its commands run against from then on. This is handled by the server,
+  /// not the backend.
+  Select {
+    /// The index of the database.
+    db: usize,
+  },
+  /// `<command> HELP`: Describes the subcommands of a command which has
+  /// them, like `OBJECT HELP`.
+  Help {
+    /// The (uppercased) name of the command to describe.
+    command: SmolStr,
+  },
+  /// `CLIENT INFO`: Describes the connection, as a line of `field=value`
+  /// pairs.
+  ClientInfo,
+  /// `CLIENT NO-EVICT`: Exempts the connection from client eviction.
+  ClientNoEvict {
+    /// Whether the exemption is turned on or off.
+    enabled: bool,
+  },
+  /// `CLIENT NO-TOUCH`: Stops the connection's commands from updating the
+  /// access statistics of the keys they touch, like hot key tracking.
+  ClientNoTouch {
+    /// Whether the flag is turned on or off.
+    enabled: bool,
+  },
+  /// `CLIENT SETINFO`: Records the name or version of the client library
+  /// the connection uses, which are shown by `CLIENT INFO`.
+  ClientSetInfo {
+    /// The attribute to set.
+    attribute: ClientAttribute,
+    /// The attribute's value. It may be empty, to clear the attribute, but
+    /// can't contain spaces, newlines, or other special characters.
+    value:     SmolStr,
+  },
+  /// `HSET`: Sets a field in a hash map.
+  #[cfg(feature = "hashes")]
+  HashSet {
+    /// The (hash) key which contains the field to set.
+    key:   SmolStr,
+    /// The field to set.
+    field: SmolStr,
+    /// The value to set the field with.
+    value: Value,
+  },
+  /// `HGET`: Gets the value of a hash map field.
+  #[cfg(feature = "hashes")]
+  HashGet {
+    /// The (hash) key which contains the field to get.
+    key:   SmolStr,
+    /// The field to get.
+    field: SmolStr,
+  },
+  /// `HGETALL`: Gets all the fields and values in a hash map.
+  #[cfg(feature = "hashes")]
+  HashGetAll {
+    /// The (hash) key from which to get the fields and values.
+    key: SmolStr,
+  },
+  /// `HMGET`: Gets multiple fields from a hash map.
+  #[cfg(feature = "hashes")]
+  HashMultipleGet {
+    /// The (hash) key which contains the fields to get.
+    key:    SmolStr,
+    /// The fields to get.
+    fields: Vec<SmolStr>,
+  },
+  /// `HDEL`: Deletes fields from a hash map, deleting the key once no fields
+  /// are left.
+  #[cfg(feature = "hashes")]
+  HashDelete {
+    /// The (hash) key which contains the fields to delete.
+    key:    SmolStr,
+    /// The fields to delete.
+    fields: Vec<SmolStr>,
+  },
+  /// `HEXPIRE`: Sets a time to live, in seconds, on fields of a hash map.
+  #[cfg(feature = "hashes")]
+  HashExpire {
+    /// The (hash) key which contains the fields.
+    key:       SmolStr,
+    /// The time to live. Zero expires the fields immediately.
+    seconds:   i64,
+    /// The condition on the fields' current times to live, if any.
+    condition: Option<ExpireCondition>,
+    /// The fields to expire.
+    fields:    Vec<SmolStr>,
+  },
+  /// `HPEXPIRE`: Sets a time to live, in milliseconds, on fields of a hash
+  /// map.
+  #[cfg(feature = "hashes")]
+  HashPExpire {
+    /// The (hash) key which contains the fields.
+    key:          SmolStr,
+    /// The time to live. Zero expires the fields immediately.
+    milliseconds: i64,
+    /// The condition on the fields' current times to live, if any.
+    condition:    Option<ExpireCondition>,
+    /// The fields to expire.
+    fields:       Vec<SmolStr>,
+  },
+  /// `HPEXPIREAT`: Sets the unix time, in milliseconds, at which fields of a
+  /// hash map expire.
+  #[cfg(feature = "hashes")]
+  HashPExpireAt {
+    /// The (hash) key which contains the fields.
+    key:          SmolStr,
+    /// The unix time in milliseconds. Times in the past expire the fields
+    /// immediately.
+    unix_time_ms: i64,
+    /// The condition on the fields' current times to live, if any.
+    condition:    Option<ExpireCondition>,
+    /// The fields to expire.
+    fields:       Vec<SmolStr>,
+  },
+  /// `HTTL`: Gets the remaining time to live, in seconds, of fields of a hash
+  /// map.
+  #[cfg(feature = "hashes")]
+  HashTtl {
+    /// The (hash) key which contains the fields.
+    key:    SmolStr,
+    /// The fields to check.
+    fields: Vec<SmolStr>,
+  },
+  /// `HPTTL`: Gets the remaining time to live, in milliseconds, of fields of
+  /// a hash map.
+  #[cfg(feature = "hashes")]
+  HashPTtl {
+    /// The (hash) key which contains the fields.
+    key:    SmolStr,
+    /// The fields to check.
+    fields: Vec<SmolStr>,
+  },
+  /// `HPERSIST`: Removes the time to live from fields of a hash map.
+  #[cfg(feature = "hashes")]
+  HashPersist {
+    /// The (hash) key which contains the fields.
+    key:    SmolStr,
+    /// The fields to persist.
+    fields: Vec<SmolStr>,
+  },
+  /// `SADD`: Adds a value to a set.
+  #[cfg(feature = "sets")]
+  SetAdd {
+    /// The (set) key to which to add the value.
+    key:   SmolStr,
+    /// The value to add.
+    value: Value,
+  },
+  /// `SMEMBERS`: Gets all the members of a set.
+  #[cfg(feature = "sets")]
+  SetMembers {
+    /// The (set) key to get the set values of.
+    key: SmolStr,
+  },
+  /// `SCARD`: Gets the cardinality of a set.
+  #[cfg(feature = "sets")]
+  SetCardinality {
+    /// The (set) key to get the set cardinality of.
+    key: SmolStr,
+  },
+  /// `SISMEMBER`: Checks if a value is a member of a set.
+  #[cfg(feature = "sets")]
+  SetIsMember {
+    /// The (set) key to check for membership.
+    key:   SmolStr,
+    /// The value to check whether it is a member.
+    value: Value,
+  },
+  /// `SDIFF`: Returns the difference between two sets.
+  #[cfg(feature = "sets")]
+  SetDifference {
+    /// The key of the set to be subtracted against.
+    set_a: SmolStr,
+    /// The key of the set to subtract with.
+    set_b: SmolStr,
+  },
+  /// `SDIFFSTORE`: Calculates and stores the difference between two sets.
+  #[cfg(feature = "sets")]
+  SetDifferenceStore {
+    /// The key of the set to be subtracted against.
+    set_a:   SmolStr,
+    /// The key of the set to subtract with.
+    set_b:   SmolStr,
+    /// The key at which to store the difference.
+    new_set: SmolStr,
+  },
+  /// `SREM`: Removes a value from a set.
+  #[cfg(feature = "sets")]
+  SetRemove {
+    /// The (set) key to remove from.
+    key:   SmolStr,
+    /// The value to remove.
+    value: Value,
+  },
+  /// `LPUSH`: Pushes a value to a list head.
+  #[cfg(feature = "lists")]
+  LeftPush {
+    /// The (list) key to left-push to.
+    key:   SmolStr,
+    /// The value to left-push.
+    value: Value,
+  },
+  /// `RPUSH`: Pushes a value to a list tail.
+  #[cfg(feature = "lists")]
+  RightPush {
+    /// The (list) key to right-push to.
+    key:   SmolStr,
+    /// The value to right-push.
+    value: Value,
+  },
+  /// `LRANGE`: Returns values from a range within a list.
+  #[cfg(feature = "lists")]
+  ListRange {
+    /// The (list) key to pull a range from.
+    key:   SmolStr,
+    /// The start index of the range to pull.
+    start: i64,
+    /// The end index of the range to pull.
+    end:   i64,
+  },
+  /// `LLEN`: Returns the length of a list.
+  #[cfg(feature = "lists")]
+  ListLength {
+    /// The (list) key to check for length.
+    key: SmolStr,
+  },
+  /// `LPOP`: Pops a value from a list head.
+  #[cfg(feature = "lists")]
+  LeftPop {
+    /// The (list) key to left-pop from.
+    key: SmolStr,
+  },
+  /// `RPOP`: Pops a value from a list tail.
+  #[cfg(feature = "lists")]
+  RightPop {
+    /// The (list) key to right-pop from.
+    key: SmolStr,
+  },
+  /// `JSON.SET`: Sets the value at a path in a JSON document.
+  #[cfg(feature = "json")]
+  JsonSet {
+    /// The (JSON) key to set within.
+    key:  SmolStr,
+    /// The [`JsonPath`](crate::value::JsonPath) to set. A new key can only
+    /// be set at the root.
+    path: SmolStr,
+    /// The JSON text of the value to set.
+    json: bytes::Bytes,
+  },
+  /// `JSON.GET`: Gets the value at a path in a JSON document, as JSON text.
+  #[cfg(feature = "json")]
+  JsonGet {
+    /// The (JSON) key to get from.
+    key:  SmolStr,
+    /// The [`JsonPath`](crate::value::JsonPath) to get.
+    path: SmolStr,
+  },
+  /// `JSON.DEL`: Deletes the value at a path in a JSON document.
+  #[cfg(feature = "json")]
+  JsonDelete {
+    /// The (JSON) key to delete from.
+    key:  SmolStr,
+    /// The [`JsonPath`](crate::value::JsonPath) to delete. Deleting the root
+    /// deletes the key.
+    path: SmolStr,
+  },
+  /// `JSON.NUMINCRBY`: Increments the number at a path in a JSON document.
+  #[cfg(feature = "json")]
+  JsonNumIncrBy {
+    /// The (JSON) key containing the number.
+    key:  SmolStr,
+    /// The [`JsonPath`](crate::value::JsonPath) of the number.
+    path: SmolStr,
+    /// The amount to increment by, as a [`Value::Integer`] or
+    /// [`Value::Double`].
+    by:   Value,
+  },
+  /// A command registered at runtime, which is run by its handler in the
+  /// server's [`CommandRegistry`](crate::server::CommandRegistry) rather than
+  /// by the backend.
+  Custom {
+    /// The uppercased command name.
+    name: SmolStr,
+    /// The arguments, not including the name.
+    args: Vec<Bytes>,
+    /// The command's metadata, as registered.
+    spec: CommandSpec,
+  },
+}
+
+impl Command {
+  /// The RESP3 name of the command.
+  pub fn command_name(&self) -> &str {
+    match self {
+      Command::Set { .. } | Command::SetAndGet { .. } => "SET",
+      Command::Get { .. } => "GET",
+      Command::GetDelete { .. } => "GETDEL",
+      Command::MultipleGet { .. } => "MGET",
+      Command::Increment { .. } => "INCR",
+      Command::IncrementBy { .. } => "INCRBY",
+      Command::Keys => "KEYS",
+      Command::Scan { .. } => "SCAN",
+      Command::Exists { .. } => "EXISTS",
+      Command::Delete { .. } => "DEL",
+      Command::Touch { .. } => "TOUCH",
+      Command::Unlink { .. } => "UNLINK",
+      Command::Expire { .. } => "EXPIRE",
+      Command::PExpire { .. } => "PEXPIRE",
+      Command::ExpireAt { .. } => "EXPIREAT",
+      Command::PExpireAt { .. } => "PEXPIREAT",
+      Command::Dump { .. } => "DUMP",
+      Command::Restore { .. } => "RESTORE",
+      Command::Migrate { .. } => "MIGRATE",
+      Command::Info => "INFO",
+      Command::BackgroundSave => "BGSAVE",
+      Command::MemoryUsage { .. } => "MEMORY",
+      Command::ObjectEncoding { .. } => "OBJECT",
+      Command::DebugHotKeys
+      | Command::DebugObject { .. }
+      | Command::DebugKeyStats { .. }
+      | Command::DebugStringMatchLen => "DEBUG",
+      Command::Auth { .. } => "AUTH",
+      Command::Hello { .. } => "HELLO",
+      Command::AclSetUser { .. }
+      | Command::AclGetUser { .. }
+      | Command::AclDelUser { .. }
+      | Command::AclList
+      | Command::AclWhoAmI
+      | Command::AclLog { .. }
+      | Command::AclLogReset
+      | Command::AclCat { .. } => "ACL",
+      Command::LatencyLatest | Command::LatencyReset { .. } => "LATENCY",
+      Command::Ping { .. } => "PING",
+      Command::Quit => "QUIT",
+      Command::Multi => "MULTI",
+      Command::Exec => "EXEC",
+      Command::Discard => "DISCARD",
+      Command::ReadOnly => "READONLY",
+      Command::ReadWrite => "READWRITE",
+      Command::Select { .. } => "SELECT",
+      Command::Help { command } => command,
+      Command::ClientInfo
+      | Command::ClientNoEvict { .. }
+      | Command::ClientNoTouch { .. }
+      | Command::ClientSetInfo { .. } => "CLIENT",
+      #[cfg(feature = "hashes")]
+      Command::HashSet { .. } => "HSET",
+      #[cfg(feature = "hashes")]
+      Command::HashGet { .. } => "HGET",
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { .. } => "HGETALL",
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { .. } => "HMGET",
+      #[cfg(feature = "hashes")]
+      Command::HashDelete { .. } => "HDEL",
+      #[cfg(feature = "hashes")]
+      Command::HashExpire { .. } => "HEXPIRE",
+      #[cfg(feature = "hashes")]
+      Command::HashPExpire { .. } => "HPEXPIRE",
+      #[cfg(feature = "hashes")]
+      Command::HashPExpireAt { .. } => "HPEXPIREAT",
+      #[cfg(feature = "hashes")]
+      Command::HashTtl { .. } => "HTTL",
+      #[cfg(feature = "hashes")]
+      Command::HashPTtl { .. } => "HPTTL",
+      #[cfg(feature = "hashes")]
+      Command::HashPersist { .. } => "HPERSIST",
+      #[cfg(feature = "sets")]
+      Command::SetAdd { .. } => "SADD",
+      #[cfg(feature = "sets")]
+      Command::SetMembers { .. } => "SMEMBERS",
+      #[cfg(feature = "sets")]
+      Command::SetCardinality { .. } => "SCARD",
+      #[cfg(feature = "sets")]
+      Command::SetIsMember { .. } => "SISMEMBER",
+      #[cfg(feature = "sets")]
+      Command::SetDifference { .. } => "SDIFF",
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore { .. } => "SDIFFSTORE",
+      #[cfg(feature = "sets")]
+      Command::SetRemove { .. } => "SREM",
+      #[cfg(feature = "lists")]
+      Command::LeftPush { .. } => "LPUSH",
+      #[cfg(feature = "lists")]
+      Command::RightPush { .. } => "RPUSH",
+      #[cfg(feature = "lists")]
+      Command::ListRange { .. } => "LRANGE",
+      #[cfg(feature = "lists")]
+      Command::ListLength { .. } => "LLEN",
+      #[cfg(feature = "lists")]
+      Command::LeftPop { .. } => "LPOP",
+      #[cfg(feature = "lists")]
+      Command::RightPop { .. } => "RPOP",
+      #[cfg(feature = "json")]
+      Command::JsonSet { .. } => "JSON.SET",
+      #[cfg(feature = "json")]
+      Command::JsonGet { .. } => "JSON.GET",
+      #[cfg(feature = "json")]
+      Command::JsonDelete { .. } => "JSON.DEL",
+      #[cfg(feature = "json")]
+      Command::JsonNumIncrBy { .. } => "JSON.NUMINCRBY",
+      Command::Custom { name, .. } => name,
+    }
+  }
+
+  /// The name of the command's subcommand, for commands which have them.
+  pub fn subcommand_name(&self) -> Option<&'static str> {
+    match self {
+      Command::MemoryUsage { .. } => Some("USAGE"),
+      Command::ObjectEncoding { .. } => Some("ENCODING"),
+      Command::DebugHotKeys => Some("HOTKEYS"),
+      Command::DebugObject { .. } => Some("OBJECT"),
+      Command::DebugKeyStats { .. } => Some("KEYSTATS"),
+      Command::DebugStringMatchLen => Some("STRINGMATCH-LEN"),
+      Command::AclSetUser { .. } => Some("SETUSER"),
+      Command::AclGetUser { .. } => Some("GETUSER"),
+      Command::AclDelUser { .. } => Some("DELUSER"),
+      Command::AclList => Some("LIST"),
+      Command::AclWhoAmI => Some("WHOAMI"),
+      Command::AclLog { .. } | Command::AclLogReset => Some("LOG"),
+      Command::AclCat { .. } => Some("CAT"),
+      Command::LatencyLatest => Some("LATEST"),
+      Command::LatencyReset { .. } => Some("RESET"),
+      Command::ClientInfo => Some("INFO"),
+      Command::ClientNoEvict { .. } => Some("NO-EVICT"),
+      Command::ClientNoTouch { .. } => Some("NO-TOUCH"),
+      Command::ClientSetInfo { .. } => Some("SETINFO"),
+      Command::Help { .. } => Some("HELP"),
+      _ => None,
+    }
+  }
+
+  /// The lowercased name of the command, including its subcommand as
+  /// `command|subcommand` if it has one, e.g. `acl|setuser`.
+  pub fn full_name(&self) -> String {
+    let name = self.command_name().to_lowercase();
+    match self.subcommand_name() {
+      Some(subcommand) => format!("{name}|{}", subcommand.to_lowercase()),
+      None => name,
+    }
+  }
+}
+
+impl Command {
+  /// Parses a command from a RESP frame: an array whose first element is the
+  /// (case-insensitive) command name, followed by its arguments.
+  ///
+  /// Arguments may be any scalar value, and are interpreted as the bytes
+  /// they'd be sent as (see [`Value::to_argument()`]). Values are always
+  /// parsed as [`Value::BulkString`]s, so `Command::parse(c.to_resp())`
+  /// returns `c` itself for commands whose values are bulk strings.
+  pub fn parse(frame: Value) -> Result<Command, ParseError> {
+    let Value::Array(frame) = frame else {
+      return Err(ParseError::NotAnArray);
+    };
+    let args = frame
+      .iter()
+      .map(|v| v.to_argument().ok_or(ParseError::NotAnArray))
+      .collect::<Result<Vec<_>, _>>()?;
+    if args.is_empty() {
+      return Err(ParseError::Empty);
+    }
+    // unknown commands and wrong arities are rejected before the arguments
+    // are parsed
+    CommandTable::get().validate(&args)?;
+    let mut args = args.into_iter();
+    let name = args.next().ok_or(ParseError::Empty)?;
+    let name = String::from_utf8_lossy(&name).to_ascii_uppercase();
+
+    let mut args = Arguments::new(name.to_ascii_lowercase(), args);
+    let command = match name.as_str() {
+      "SET" => {
+        let (key, value) = (args.key()?, args.value()?);
+        let (mut options, mut get) = (SetOptions::default(), false);
+        // each kind of option may be given once, in any order
+        while !args.is_empty() {
+          let option = args.next()?.to_ascii_uppercase();
+          let condition = SetCondition::from_argument(&option);
+          let expiration = match option.as_slice() {
+            b"EX" => Some(SetExpiration::Ex(args.integer()?)),
+            b"PX" => Some(SetExpiration::Px(args.integer()?)),
+            b"EXAT" => Some(SetExpiration::ExAt(args.integer()?)),
+            b"PXAT" => Some(SetExpiration::PxAt(args.integer()?)),
+            b"KEEPTTL" => Some(SetExpiration::KeepTtl),
+            _ => None,
+          };
+          if option == b"GET" && !get {
+            get = true;
+          } else if condition.is_some() && options.condition.is_none() {
+            options.condition = condition;
+          } else if expiration.is_some() && options.expiration.is_none() {
+            options.expiration = expiration;
+          } else {
+            return Err(ArgumentError::Syntax.into());
+          }
+        }
+        if get {
+          Command::SetAndGet {
+            key,
+            value,
+            options,
+          }
+        } else {
+          Command::Set {
+            key,
+            value,
+            options,
+          }
+        }
+      }
+      "GET" => Command::Get { key: args.key()? },
+      "GETDEL" => Command::GetDelete { key: args.key()? },
+      "MGET" => Command::MultipleGet { keys: args.keys()? },
+      "INCR" => Command::Increment { key: args.key()? },
+      "INCRBY" => Command::IncrementBy {
+        key:       args.key()?,
+        increment: args.integer()?,
+      },
+      "KEYS" => {
+        if args.next()?.as_ref() != b"*" {
+          return Err(args.invalid("only the `*` pattern is supported"));
+        }
+        Command::Keys
+      }
+      "SCAN" => {
+        let cursor = args.cursor()?;
+        let (mut pattern, mut count) = (None, None);
+        while !args.is_empty() {
+          let option = args.next()?;
+          if option.eq_ignore_ascii_case(b"MATCH") {
+            pattern = Some(args.key()?);
+          } else if option.eq_ignore_ascii_case(b"COUNT") {
+            let n = usize::try_from(args.integer()?).ok().filter(|&n| n > 0);
+            count = Some(n.ok_or(ArgumentError::Syntax)?);
+          } else {
+            return Err(ArgumentError::Syntax.into());
+          }
+        }
+        Command::Scan {
+          cursor,
+          pattern,
+          count,
+        }
+      }
+      "EXISTS" => Command::Exists { keys: args.keys()? },
+      "DEL" => Command::Delete { keys: args.keys()? },
+      "TOUCH" => Command::Touch { keys: args.keys()? },
+      "UNLINK" => Command::Unlink { keys: args.keys()? },
+      "EXPIRE" => Command::Expire {
+        key:       args.key()?,
+        seconds:   args.integer()?,
+        condition: args.expire_condition(),
+      },
+      "PEXPIRE" => Command::PExpire {
+        key:          args.key()?,
+        milliseconds: args.integer()?,
+        condition:    args.expire_condition(),
+      },
+      "EXPIREAT" => Command::ExpireAt {
+        key:       args.key()?,
+        unix_time: args.integer()?,
+        condition: args.expire_condition(),
+      },
+      "PEXPIREAT" => Command::PExpireAt {
+        key:          args.key()?,
+        unix_time_ms: args.integer()?,
+        condition:    args.expire_condition(),
+      },
+      "DUMP" => Command::Dump { key: args.key()? },
+      "RESTORE" => {
+        let (key, ttl, payload) = (args.key()?, args.integer()?, args.next()?);
+        let (mut replace, mut absttl) = (false, false);
+        while !args.is_empty() {
+          let option = args.next()?;
+          if option.eq_ignore_ascii_case(b"REPLACE") {
+            replace = true;
+          } else if option.eq_ignore_ascii_case(b"ABSTTL") {
+            absttl = true;
+          } else {
+            return Err(ArgumentError::Syntax.into());
+          }
+        }
+        Command::Restore {
+          key,
+          ttl,
+          payload,
+          replace,
+          absttl,
+        }
+      }
+      "MIGRATE" => {
+        let host = std::str::from_utf8(&args.next()?)
+          .map(SmolStr::from)
+          .map_err(|_| args.invalid("hosts must be valid UTF-8"))?;
+        let port = u16::try_from(args.integer()?)
+          .map_err(|_| ArgumentError::NotAnInteger)?;
+        let key = args.key()?;
+        let (db, timeout) = (args.integer()?, args.integer()?);
+        let (mut keys, mut copy, mut replace, mut auth) =
+          (vec![key], false, false, None);
+        while !args.is_empty() {
+          let option = args.next()?;
+          if option.eq_ignore_ascii_case(b"COPY") {
+            copy = true;
+          } else if option.eq_ignore_ascii_case(b"REPLACE") {
+            replace = true;
+          } else if option.eq_ignore_ascii_case(b"AUTH") {
+            auth = Some((None, args.next()?));
+          } else if option.eq_ignore_ascii_case(b"AUTH2") {
+            let username = std::str::from_utf8(&args.next()?)
+              .map(SmolStr::from)
+              .map_err(|_| args.invalid("usernames must be valid UTF-8"))?;
+            auth = Some((Some(username), args.next()?));
+          } else if option.eq_ignore_ascii_case(b"KEYS") {
+            if !keys[0].is_empty() {
+              return Err(
+                args
+                  .invalid("the key argument must be empty when KEYS is given"),
+              );
+            }
+            keys = args.keys()?;
+          } else {
+            return Err(ArgumentError::Syntax.into());
+          }
+        }
+        Command::Migrate {
+          host,
+          port,
+          keys,
+          db,
+          timeout,
+          copy,
+          replace,
+          auth,
+        }
+      }
+      "INFO" => Command::Info,
+      "BGSAVE" => Command::BackgroundSave,
+      "MEMORY" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("MEMORY"),
+        },
+        "USAGE" => Command::MemoryUsage { key: args.key()? },
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "OBJECT" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("OBJECT"),
+        },
+        "ENCODING" => Command::ObjectEncoding { key: args.key()? },
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "DEBUG" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("DEBUG"),
+        },
+        "HOTKEYS" => Command::DebugHotKeys,
+        "OBJECT" => Command::DebugObject { key: args.key()? },
+        "KEYSTATS" if args.is_empty() => {
+          Command::DebugKeyStats { samples: None }
+        }
+        "KEYSTATS" => {
+          if !args.next()?.eq_ignore_ascii_case(b"SAMPLES") {
+            return Err(args.invalid("expected SAMPLES"));
+          }
+          let samples = args.integer()?;
+          Command::DebugKeyStats {
+            samples: Some(usize::try_from(samples).map_err(|_| {
+              args.invalid("samples must be a positive integer")
+            })?),
+          }
+        }
+        "STRINGMATCH-LEN" => Command::DebugStringMatchLen,
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "ACL" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("ACL"),
+        },
+        "SETUSER" => Command::AclSetUser {
+          username: args.key()?,
+          rules:    {
+            let mut rules = Vec::new();
+            while !args.is_empty() {
+              rules.push(args.key()?);
+            }
+            rules
+          },
+        },
+        "GETUSER" => Command::AclGetUser {
+          username: args.key()?,
+        },
+        "DELUSER" => Command::AclDelUser {
+          usernames: args.keys()?,
+        },
+        "LIST" => Command::AclList,
+        "WHOAMI" => Command::AclWhoAmI,
+        "LOG" if args.is_empty() => Command::AclLog { count: None },
+        "LOG" => {
+          let arg = args.next()?;
+          if arg.eq_ignore_ascii_case(b"RESET") {
+            Command::AclLogReset
+          } else {
+            let count = std::str::from_utf8(&arg)
+              .ok()
+              .and_then(|s| s.parse().ok())
+              .ok_or_else(|| {
+                args.invalid("count must be a positive integer")
+              })?;
+            Command::AclLog { count: Some(count) }
+          }
+        }
+        "CAT" => Command::AclCat {
+          category: if args.is_empty() {
+            None
+          } else {
+            Some(args.key()?)
+          },
+        },
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "LATENCY" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("LATENCY"),
+        },
+        "LATEST" => Command::LatencyLatest,
+        "RESET" => {
+          let mut events = Vec::new();
+          while !args.is_empty() {
+            events.push(args.key()?);
+          }
+          Command::LatencyReset { events }
+        }
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "HELLO" => {
+        let protover = if args.is_empty() {
+          None
+        } else {
+          Some(args.integer()?)
+        };
+        let auth = if args.is_empty() {
+          None
+        } else if args.next()?.eq_ignore_ascii_case(b"AUTH") {
+          let username = std::str::from_utf8(&args.next()?)
+            .map(SmolStr::from)
+            .map_err(|_| args.invalid("usernames must be valid UTF-8"))?;
+          Some((username, args.next()?))
+        } else {
+          return Err(ArgumentError::Syntax.into());
+        };
+        Command::Hello { protover, auth }
+      }
+      "PING" => Command::Ping {
+        message: if args.is_empty() {
+          None
+        } else {
+          Some(args.next()?)
+        },
+      },
+      "QUIT" => Command::Quit,
+      "MULTI" => Command::Multi,
+      "EXEC" => Command::Exec,
+      "DISCARD" => Command::Discard,
+      "READONLY" => Command::ReadOnly,
+      "READWRITE" => Command::ReadWrite,
+      "SELECT" => Command::Select {
+        db: usize::try_from(args.integer()?)
+          .map_err(|_| args.invalid("DB index is out of range"))?,
+      },
+      "CLIENT" => match args.subcommand()?.as_str() {
+        "HELP" => Command::Help {
+          command: SmolStr::new_static("CLIENT"),
+        },
+        "INFO" => Command::ClientInfo,
+        "NO-EVICT" => Command::ClientNoEvict {
+          enabled: args.switch()?,
+        },
+        "NO-TOUCH" => Command::ClientNoTouch {
+          enabled: args.switch()?,
+        },
+        "SETINFO" => {
+          let attribute = ClientAttribute::from_argument(&args.next()?)
+            .ok_or_else(|| args.invalid("expected LIB-NAME or LIB-VER"))?;
+          let value = args.next()?;
+          if !value.iter().all(|b| (b'!'..=b'~').contains(b)) {
+            return Err(args.invalid(
+              "lib-name and lib-ver cannot contain spaces, newlines or \
+               special characters",
+            ));
+          }
+          Command::ClientSetInfo {
+            attribute,
+            value: String::from_utf8_lossy(&value).into(),
+          }
+        }
+        other => return Err(args.unknown_subcommand(other.to_owned())),
+      },
+      "AUTH" => {
+        let first = args.next()?;
+        if args.is_empty() {
+          Command::Auth {
+            username: None,
+            password: first,
+          }
+        } else {
+          Command::Auth {
+            username: Some(
+              std::str::from_utf8(&first)
+                .map(SmolStr::from)
+                .map_err(|_| args.invalid("usernames must be valid UTF-8"))?,
+            ),
+            password: args.next()?,
+          }
+        }
+      }
+      #[cfg(feature = "hashes")]
+      "HSET" => Command::HashSet {
+        key:   args.key()?,
+        field: args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HGET" => Command::HashGet {
+        key:   args.key()?,
+        field: args.key()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HGETALL" => Command::HashGetAll { key: args.key()? },
+      #[cfg(feature = "hashes")]
+      "HMGET" => Command::HashMultipleGet {
+        key:    args.key()?,
+        fields: args.keys()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HDEL" => Command::HashDelete {
+        key:    args.key()?,
+        fields: args.keys()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HEXPIRE" => Command::HashExpire {
+        key:       args.key()?,
+        seconds:   args.integer()?,
+        condition: args.expire_condition(),
+        fields:    args.fields()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HPEXPIRE" => Command::HashPExpire {
+        key:          args.key()?,
+        milliseconds: args.integer()?,
+        condition:    args.expire_condition(),
+        fields:       args.fields()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HPEXPIREAT" => Command::HashPExpireAt {
+        key:          args.key()?,
+        unix_time_ms: args.integer()?,
+        condition:    args.expire_condition(),
+        fields:       args.fields()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HTTL" => Command::HashTtl {
+        key:    args.key()?,
+        fields: args.fields()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HPTTL" => Command::HashPTtl {
+        key:    args.key()?,
+        fields: args.fields()?,
+      },
+      #[cfg(feature = "hashes")]
+      "HPERSIST" => Command::HashPersist {
+        key:    args.key()?,
+        fields: args.fields()?,
+      },
+      #[cfg(feature = "sets")]
+      "SADD" => Command::SetAdd {
+        key:   args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "sets")]
+      "SMEMBERS" => Command::SetMembers { key: args.key()? },
+      #[cfg(feature = "sets")]
+      "SCARD" => Command::SetCardinality { key: args.key()? },
+      #[cfg(feature = "sets")]
+      "SISMEMBER" => Command::SetIsMember {
+        key:   args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "sets")]
+      "SDIFF" => Command::SetDifference {
+        set_a: args.key()?,
+        set_b: args.key()?,
+      },
+      #[cfg(feature = "sets")]
+      "SDIFFSTORE" => Command::SetDifferenceStore {
+        new_set: args.key()?,
+        set_a:   args.key()?,
+        set_b:   args.key()?,
+      },
+      #[cfg(feature = "sets")]
+      "SREM" => Command::SetRemove {
+        key:   args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "lists")]
+      "LPUSH" => Command::LeftPush {
+        key:   args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "lists")]
+      "RPUSH" => Command::RightPush {
+        key:   args.key()?,
+        value: args.value()?,
+      },
+      #[cfg(feature = "lists")]
+      "LRANGE" => Command::ListRange {
+        key:   args.key()?,
+        start: args.integer()?,
+        end:   args.integer()?,
+      },
+      #[cfg(feature = "lists")]
+      "LLEN" => Command::ListLength { key: args.key()? },
+      #[cfg(feature = "lists")]
+      "LPOP" => Command::LeftPop { key: args.key()? },
+      #[cfg(feature = "lists")]
+      "RPOP" => Command::RightPop { key: args.key()? },
+      #[cfg(feature = "json")]
+      "JSON.SET" => Command::JsonSet {
+        key:  args.key()?,
+        path: args.key()?,
+        json: args.next()?,
+      },
+      #[cfg(feature = "json")]
+      "JSON.GET" => Command::JsonGet {
+        key:  args.key()?,
+        path: args.key()?,
+      },
+      #[cfg(feature = "json")]
+      "JSON.DEL" => Command::JsonDelete {
+        key:  args.key()?,
+        path: args.key()?,
+      },
+      #[cfg(feature = "json")]
+      "JSON.NUMINCRBY" => Command::JsonNumIncrBy {
+        key:  args.key()?,
+        path: args.key()?,
+        by:   args.number()?,
+      },
+      _ => {
+        let rest = args.rest();
+        return Err(ParseError::unknown_command(name.as_bytes(), &rest));
+      }
+    };
+    args.finish()?;
+    Ok(command)
+  }
+
+  /// Parses a command from the elements of a decoded request array, like
+  /// `["SET", "key", "val"]`. See [`Command::parse()`].
+  ///
+  /// Argument errors fail with the matching [`KraglinError`] variant (like
+  /// [`KraglinError::NotAnInteger`]), and other malformed requests with
+  /// [`KraglinError::InvalidCommand`].
+  pub fn from_resp(frames: Vec<Value>) -> Result<Command, KraglinError> {
+    Ok(Command::parse(Value::Array(frames))?)
+  }
+
+  /// Encodes the command as a RESP frame: an array of bulk strings holding
+  /// the command name and its arguments, as [`Command::parse()`] accepts.
+  ///
+  /// Values which can't be represented as a single argument (aggregates and
+  /// [`Value::Nothing`]) are included as-is, so such frames can't be sent.
+  pub fn to_resp(&self) -> Value {
+    let arg = |s: &str| Value::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+    let value =
+      |v: &Value| v.to_argument().map_or_else(|| v.clone(), Value::BulkString);
+
+    let mut frame = vec![arg(self.command_name())];
+    match self {
+      Command::Set {
+        key,
+        value: v,
+        options,
+      } => {
+        frame.extend([arg(key), value(v)]);
+        frame.extend(options.arguments().iter().map(|a| arg(a)));
+      }
+      Command::SetAndGet {
+        key,
+        value: v,
+        options,
+      } => {
+        frame.extend([arg(key), value(v)]);
+        frame.extend(options.arguments().iter().map(|a| arg(a)));
+        frame.push(arg("GET"));
+      }
+      Command::IncrementBy { key, increment } => {
+        frame.extend([arg(key), arg(&increment.to_string())])
+      }
+      Command::Get { key }
+      | Command::GetDelete { key }
+      | Command::Increment { key }
+      | Command::Dump { key } => frame.push(arg(key)),
+      Command::Restore {
+        key,
+        ttl,
+        payload,
+        replace,
+        absttl,
+      } => {
+        frame.extend([arg(key), arg(&ttl.to_string())]);
+        frame.push(Value::BulkString(payload.clone()));
+        if *replace {
+          frame.push(arg("REPLACE"));
+        }
+        if *absttl {
+          frame.push(arg("ABSTTL"));
+        }
+      }
+      Command::Expire {
+        key,
+        seconds: ttl,
+        condition,
+      }
+      | Command::PExpire {
+        key,
+        milliseconds: ttl,
+        condition,
+      }
+      | Command::ExpireAt {
+        key,
+        unix_time: ttl,
+        condition,
+      }
+      | Command::PExpireAt {
+        key,
+        unix_time_ms: ttl,
+        condition,
+      } => {
+        frame.extend([arg(key), arg(&ttl.to_string())]);
+        frame.extend(condition.map(|c| arg(c.as_str())));
+      }
+      Command::Migrate {
+        host,
+        port,
+        keys,
+        db,
+        timeout,
+        copy,
+        replace,
+        auth,
+      } => {
+        // a single key goes in the key argument, and several after `KEYS`
+        let key = match keys.as_slice() {
+          [key] => key.as_str(),
+          _ => "",
+        };
+        frame.extend([
+          arg(host),
+          arg(&port.to_string()),
+          arg(key),
+          arg(&db.to_string()),
+          arg(&timeout.to_string()),
+        ]);
+        if *copy {
+          frame.push(arg("COPY"));
+        }
+        if *replace {
+          frame.push(arg("REPLACE"));
+        }
+        match auth {
+          Some((None, password)) => {
+            frame.extend([arg("AUTH"), Value::BulkString(password.clone())])
+          }
+          Some((Some(username), password)) => frame.extend([
+            arg("AUTH2"),
+            arg(username),
+            Value::BulkString(password.clone()),
+          ]),
+          None => {}
+        }
+        if keys.len() != 1 {
+          frame.push(arg("KEYS"));
+          frame.extend(keys.iter().map(|k| arg(k)));
+        }
+      }
+      Command::MultipleGet { keys }
+      | Command::Exists { keys }
+      | Command::Delete { keys }
+      | Command::Touch { keys }
+      | Command::Unlink { keys } => frame.extend(keys.iter().map(|k| arg(k))),
+      Command::Keys => frame.push(arg("*")),
+      Command::Scan {
+        cursor,
+        pattern,
+        count,
+      } => {
+        frame.push(arg(&cursor.to_string()));
+        if let Some(pattern) = pattern {
+          frame.extend([arg("MATCH"), arg(pattern)]);
+        }
+        if let Some(count) = count {
+          frame.extend([arg("COUNT"), arg(&count.to_string())]);
+        }
+      }
+      Command::Info | Command::BackgroundSave => {}
+      Command::MemoryUsage { key } => frame.extend([arg("USAGE"), arg(key)]),
+      Command::ObjectEncoding { key } => {
+        frame.extend([arg("ENCODING"), arg(key)])
+      }
+      Command::DebugHotKeys => frame.push(arg("HOTKEYS")),
+      Command::DebugObject { key } => frame.extend([arg("OBJECT"), arg(key)]),
+      Command::DebugKeyStats { samples } => {
+        frame.push(arg("KEYSTATS"));
+        if let Some(samples) = samples {
+          frame.extend([arg("SAMPLES"), arg(&samples.to_string())]);
+        }
+      }
+      Command::DebugStringMatchLen => frame.push(arg("STRINGMATCH-LEN")),
+      Command::Auth { username, password } => {
+        frame.extend(username.iter().map(|u| arg(u)));
+        frame.push(Value::BulkString(password.clone()));
+      }
+      Command::Hello { protover, auth } => {
+        frame.extend(protover.map(|p| arg(&p.to_string())));
+        if let Some((username, password)) = auth {
+          frame.extend([arg("AUTH"), arg(username)]);
+          frame.push(Value::BulkString(password.clone()));
+        }
+      }
+      Command::AclSetUser { username, rules } => {
+        frame.extend([arg("SETUSER"), arg(username)]);
+        frame.extend(rules.iter().map(|r| arg(r)));
+      }
+      Command::AclGetUser { username } => {
+        frame.extend([arg("GETUSER"), arg(username)])
+      }
+      Command::AclDelUser { usernames } => {
+        frame.push(arg("DELUSER"));
+        frame.extend(usernames.iter().map(|u| arg(u)));
+      }
+      Command::AclList => frame.push(arg("LIST")),
+      Command::AclWhoAmI => frame.push(arg("WHOAMI")),
+      Command::AclLog { count } => {
+        frame.push(arg("LOG"));
+        frame.extend(count.map(|c| arg(&c.to_string())));
+      }
+      Command::AclLogReset => frame.extend([arg("LOG"), arg("RESET")]),
+      Command::AclCat { category } => {
+        frame.push(arg("CAT"));
+        frame.extend(category.iter().map(|c| arg(c)));
+      }
+      Command::LatencyLatest => frame.push(arg("LATEST")),
+      Command::LatencyReset { events } => {
+        frame.push(arg("RESET"));
+        frame.extend(events.iter().map(|e| arg(e)));
+      }
+      Command::Ping { message } => {
+        frame.extend(message.iter().cloned().map(Value::BulkString))
+      }
+      Command::Quit
+      | Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::ReadOnly
+      | Command::ReadWrite => {}
+      Command::Select { db } => frame.push(arg(&db.to_string())),
+      Command::Help { .. } => frame.push(arg("HELP")),
+      Command::ClientInfo => frame.push(arg("INFO")),
+      Command::ClientNoEvict { enabled }
+      | Command::ClientNoTouch { enabled } => {
+        frame
+          .push(arg(self.subcommand_name().expect("CLIENT has subcommands")));
+        frame.push(arg(if *enabled { "ON" } else { "OFF" }));
+      }
+      Command::ClientSetInfo { attribute, value } => {
+        frame.extend([arg("SETINFO"), arg(attribute.as_str()), arg(value)])
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashSet {
+        key,
+        field,
+        value: v,
+      } => frame.extend([arg(key), arg(field), value(v)]),
+      #[cfg(feature = "hashes")]
+      Command::HashGet { key, field } => frame.extend([arg(key), arg(field)]),
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { key } => frame.push(arg(key)),
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { key, fields }
+      | Command::HashDelete { key, fields } => {
+        frame.push(arg(key));
+        frame.extend(fields.iter().map(|f| arg(f)));
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashExpire {
+        key,
+        seconds: ttl,
+        condition,
+        fields,
+      }
+      | Command::HashPExpire {
+        key,
+        milliseconds: ttl,
+        condition,
+        fields,
+      }
+      | Command::HashPExpireAt {
+        key,
+        unix_time_ms: ttl,
+        condition,
+        fields,
+      } => {
+        frame.extend([arg(key), arg(&ttl.to_string())]);
+        frame.extend(condition.map(|c| arg(c.as_str())));
+        frame.extend([arg("FIELDS"), arg(&fields.len().to_string())]);
+        frame.extend(fields.iter().map(|f| arg(f)));
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashTtl { key, fields }
+      | Command::HashPTtl { key, fields }
+      | Command::HashPersist { key, fields } => {
+        frame.extend([arg(key), arg("FIELDS"), arg(&fields.len().to_string())]);
+        frame.extend(fields.iter().map(|f| arg(f)));
+      }
+      #[cfg(feature = "sets")]
+      Command::SetAdd { key, value: v }
+      | Command::SetIsMember { key, value: v }
+      | Command::SetRemove { key, value: v } => {
+        frame.extend([arg(key), value(v)])
+      }
+      #[cfg(feature = "sets")]
+      Command::SetMembers { key } | Command::SetCardinality { key } => {
+        frame.push(arg(key))
+      }
+      #[cfg(feature = "sets")]
+      Command::SetDifference { set_a, set_b } => {
+        frame.extend([arg(set_a), arg(set_b)])
+      }
+      // SDIFFSTORE takes the destination first
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore {
+        set_a,
+        set_b,
+        new_set,
+      } => frame.extend([arg(new_set), arg(set_a), arg(set_b)]),
+      #[cfg(feature = "lists")]
+      Command::LeftPush { key, value: v }
+      | Command::RightPush { key, value: v } => {
+        frame.extend([arg(key), value(v)])
+      }
+      #[cfg(feature = "lists")]
+      Command::ListLength { key }
+      | Command::LeftPop { key }
+      | Command::RightPop { key } => frame.push(arg(key)),
+      #[cfg(feature = "lists")]
+      Command::ListRange { key, start, end } => {
+        frame.extend([arg(key), arg(&start.to_string()), arg(&end.to_string())])
+      }
+      #[cfg(feature = "json")]
+      Command::JsonSet { key, path, json } => {
+        frame.extend([arg(key), arg(path), Value::BulkString(json.clone())])
+      }
+      #[cfg(feature = "json")]
+      Command::JsonGet { key, path } | Command::JsonDelete { key, path } => {
+        frame.extend([arg(key), arg(path)])
+      }
+      #[cfg(feature = "json")]
+      Command::JsonNumIncrBy { key, path, by } => {
+        // a whole double like `1.0` must keep its decimal point, or it would
+        // parse back as an integer
+        let by = match by {
+          Value::Double(d) => arg(&format!("{d:?}")),
+          by => value(by),
+        };
+        frame.extend([arg(key), arg(path), by])
+      }
+      Command::Custom { args, .. } => {
+        frame.extend(args.iter().cloned().map(Value::BulkString))
+      }
+    }
+    Value::Array(frame)
+  }
+
+  /// The keys accessed by the command, in argument order.
+  pub fn keys(&self) -> Vec<&SmolStr> {
+    match self {
+      Command::Set { key, .. }
+      | Command::SetAndGet { key, .. }
+      | Command::Get { key }
+      | Command::GetDelete { key }
+      | Command::Increment { key }
+      | Command::IncrementBy { key, .. }
+      | Command::Dump { key }
+      | Command::Restore { key, .. }
+      | Command::Expire { key, .. }
+      | Command::PExpire { key, .. }
+      | Command::ExpireAt { key, .. }
+      | Command::PExpireAt { key, .. }
+      | Command::MemoryUsage { key }
+      | Command::ObjectEncoding { key }
+      | Command::DebugObject { key } => vec![key],
+      Command::MultipleGet { keys }
+      | Command::Exists { keys }
+      | Command::Delete { keys }
+      | Command::Touch { keys }
+      | Command::Unlink { keys }
+      | Command::Migrate { keys, .. } => keys.iter().collect(),
+      #[cfg(feature = "hashes")]
+      Command::HashSet { key, .. }
+      | Command::HashGet { key, .. }
+      | Command::HashGetAll { key }
+      | Command::HashMultipleGet { key, .. }
+      | Command::HashDelete { key, .. }
+      | Command::HashExpire { key, .. }
+      | Command::HashPExpire { key, .. }
+      | Command::HashPExpireAt { key, .. }
+      | Command::HashTtl { key, .. }
+      | Command::HashPTtl { key, .. }
+      | Command::HashPersist { key, .. } => vec![key],
+      #[cfg(feature = "sets")]
+      Command::SetAdd { key, .. }
+      | Command::SetMembers { key }
+      | Command::SetCardinality { key }
+      | Command::SetIsMember { key, .. }
+      | Command::SetRemove { key, .. } => vec![key],
+      #[cfg(feature = "sets")]
+      Command::SetDifference { set_a, set_b } => vec![set_a, set_b],
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore {
+        set_a,
+        set_b,
+        new_set,
+      } => vec![new_set, set_a, set_b],
+      #[cfg(feature = "lists")]
+      Command::LeftPush { key, .. }
+      | Command::RightPush { key, .. }
+      | Command::ListRange { key, .. }
+      | Command::ListLength { key }
+      | Command::LeftPop { key }
+      | Command::RightPop { key } => vec![key],
+      #[cfg(feature = "json")]
+      Command::JsonSet { key, .. }
+      | Command::JsonGet { key, .. }
+      | Command::JsonDelete { key, .. }
+      | Command::JsonNumIncrBy { key, .. } => vec![key],
+      Command::Keys
+      | Command::Scan { .. }
+      | Command::Info
+      | Command::BackgroundSave
+      | Command::DebugHotKeys
+      | Command::DebugKeyStats { .. }
+      | Command::DebugStringMatchLen
+      | Command::Auth { .. }
+      | Command::Hello { .. }
+      | Command::AclSetUser { .. }
+      | Command::AclGetUser { .. }
+      | Command::AclDelUser { .. }
+      | Command::AclList
+      | Command::AclWhoAmI
+      | Command::AclLog { .. }
+      | Command::AclLogReset
+      | Command::AclCat { .. }
+      | Command::LatencyLatest
+      | Command::LatencyReset { .. }
+      | Command::Ping { .. }
+      | Command::Quit
+      | Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::ReadOnly
+      | Command::ReadWrite
+      | Command::Select { .. }
+      | Command::Help { .. }
+      | Command::ClientInfo
+      | Command::ClientNoEvict { .. }
+      | Command::ClientNoTouch { .. }
+      | Command::ClientSetInfo { .. }
+      | Command::Custom { .. } => vec![],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    ArgumentError, Command, ParseError, SetCondition, SetExpiration, SetOptions,
+  };
+  use crate::{value::Value, KraglinError};
+
+  fn frame(args: &[&str]) -> Value {
+    Value::Array(
+      args
+        .iter()
+        .map(|a| Value::BulkString(a.as_bytes().to_vec().into()))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn commands_round_trip_through_resp() {
+    #[allow(unused_mut)]
+    let mut frames: Vec<&[&str]> = vec![
+      &["SET", "k", "v"],
+      &["SET", "k", "v", "GET"],
+      &["SET", "k", "v", "NX", "EX", "10"],
+      &["SET", "k", "v", "PXAT", "1700000000000"],
+      &["SET", "k", "v", "XX", "KEEPTTL", "GET"],
+      &["GET", "k"],
+      &["GETDEL", "k"],
+      &["MGET", "a", "b"],
+      &["INCR", "k"],
+      &["INCRBY", "k", "-5"],
+      &["KEYS", "*"],
+      &["SCAN", "0"],
+      &["SCAN", "17", "MATCH", "user:*", "COUNT", "100"],
+      &["EXISTS", "k"],
+      &["EXISTS", "a", "b", "a"],
+      &["DEL", "k"],
+      &["DEL", "a", "b"],
+      &["TOUCH", "a", "b"],
+      &["UNLINK", "a"],
+      &["EXPIRE", "k", "10"],
+      &["EXPIRE", "k", "-1", "NX"],
+      &["PEXPIRE", "k", "1500", "GT"],
+      &["EXPIREAT", "k", "1700000000", "XX"],
+      &["PEXPIREAT", "k", "1700000000000", "LT"],
+      &["DUMP", "k"],
+      &["RESTORE", "k", "0", "payload"],
+      &["RESTORE", "k", "100", "payload", "REPLACE"],
+      &["RESTORE", "k", "1700000000000", "payload", "ABSTTL"],
+      &["MIGRATE", "h", "6379", "k", "0", "1000"],
+      &[
+        "MIGRATE", "h", "6379", "a", "2", "1000", "COPY", "AUTH", "p",
+      ],
+      &[
+        "MIGRATE", "h", "6379", "", "0", "1000", "REPLACE", "AUTH2", "u", "p",
+        "KEYS", "a", "b",
+      ],
+      &["INFO"],
+      &["BGSAVE"],
+      &["MEMORY", "USAGE", "k"],
+      &["OBJECT", "ENCODING", "k"],
+      &["DEBUG", "HOTKEYS"],
+      &["DEBUG", "OBJECT", "k"],
+      &["DEBUG", "STRINGMATCH-LEN"],
+      &["DEBUG", "KEYSTATS"],
+      &["DEBUG", "KEYSTATS", "SAMPLES", "100"],
+      &["AUTH", "pass"],
+      &["AUTH", "user", "pass"],
+      &["HELLO"],
+      &["HELLO", "3"],
+      &["HELLO", "2", "AUTH", "user", "pass"],
+      &["ACL", "SETUSER", "alice", "on", ">pass", "~*", "+get"],
+      &["ACL", "SETUSER", "alice"],
+      &["ACL", "GETUSER", "alice"],
+      &["ACL", "DELUSER", "alice", "bob"],
+      &["ACL", "LIST"],
+      &["ACL", "WHOAMI"],
+      &["ACL", "LOG"],
+      &["ACL", "LOG", "5"],
+      &["ACL", "LOG", "RESET"],
+      &["ACL", "CAT"],
+      &["ACL", "CAT", "read"],
+      &["LATENCY", "LATEST"],
+      &["LATENCY", "RESET"],
+      &["LATENCY", "RESET", "command-timeout"],
+      &["PING"],
+      &["PING", "hello"],
+      &["QUIT"],
+      &["MULTI"],
+      &["EXEC"],
+      &["DISCARD"],
+      &["READONLY"],
+      &["READWRITE"],
+      &["SELECT", "3"],
+      &["OBJECT", "HELP"],
+      &["ACL", "HELP"],
+      &["LATENCY", "HELP"],
+      &["CLIENT", "INFO"],
+      &["CLIENT", "NO-EVICT", "ON"],
+      &["CLIENT", "NO-TOUCH", "OFF"],
+      &["CLIENT", "SETINFO", "LIB-NAME", "redis-py"],
+    ];
+    #[cfg(feature = "hashes")]
+    frames.extend::<[&[&str]; 11]>([
+      &["HSET", "k", "f", "v"],
+      &["HGET", "k", "f"],
+      &["HGETALL", "k"],
+      &["HMGET", "k", "f", "g"],
+      &["HDEL", "k", "f", "g"],
+      &["HEXPIRE", "k", "10", "FIELDS", "2", "f", "g"],
+      &["HPEXPIRE", "k", "1500", "GT", "FIELDS", "1", "f"],
+      &["HPEXPIREAT", "k", "1700000000000", "FIELDS", "1", "f"],
+      &["HTTL", "k", "FIELDS", "1", "f"],
+      &["HPTTL", "k", "FIELDS", "2", "f", "g"],
+      &["HPERSIST", "k", "FIELDS", "1", "f"],
+    ]);
+    #[cfg(feature = "sets")]
+    frames.extend::<[&[&str]; 7]>([
+      &["SADD", "k", "m"],
+      &["SMEMBERS", "k"],
+      &["SCARD", "k"],
+      &["SISMEMBER", "k", "m"],
+      &["SDIFF", "a", "b"],
+      &["SDIFFSTORE", "dest", "a", "b"],
+      &["SREM", "k", "m"],
+    ]);
+    #[cfg(feature = "lists")]
+    frames.extend::<[&[&str]; 6]>([
+      &["LPUSH", "k", "v"],
+      &["RPUSH", "k", "v"],
+      &["LRANGE", "k", "0", "-1"],
+      &["LLEN", "k"],
+      &["LPOP", "k"],
+      &["RPOP", "k"],
+    ]);
+    #[cfg(feature = "json")]
+    frames.extend::<[&[&str]; 6]>([
+      &["JSON.SET", "k", "$", "{\"a\":1}"],
+      &["JSON.GET", "k", "$.a"],
+      &["JSON.DEL", "k", "$.a"],
+      &["JSON.NUMINCRBY", "k", "$.a", "2"],
+      &["JSON.NUMINCRBY", "k", "$.a", "1.5"],
+      &["JSON.NUMINCRBY", "k", "$.a", "1.0"],
+    ]);
+
+    for args in frames {
+      let command = Command::parse(frame(args))
+        .unwrap_or_else(|e| panic!("{args:?} failed to parse: {e}"));
+      assert_eq!(command.to_resp(), frame(args));
+      assert_eq!(Command::parse(command.to_resp()), Ok(command));
+    }
+  }
+
+  #[test]
+  fn parsing_is_case_insensitive_and_normalizes_values() {
+    assert_eq!(
+      Command::parse(frame(&["set", "k", "v"])),
+      Ok(Command::Set {
+        key:     "k".into(),
+        value:   Value::BulkString("v".into()),
+        options: SetOptions::default(),
+      })
+    );
+    assert_eq!(
+      Command::parse(frame(&["set", "k", "v", "get", "px", "100", "nx"])),
+      Ok(Command::SetAndGet {
+        key:     "k".into(),
+        value:   Value::BulkString("v".into()),
+        options: SetOptions {
+          expiration: Some(SetExpiration::Px(100)),
+          condition:  Some(SetCondition::Nx),
+        },
+      })
+    );
+    assert_eq!(
+      Command::parse(frame(&["debug", "hotkeys"])),
+      Ok(Command::DebugHotKeys)
+    );
+
+    // any scalar is accepted as an argument
+    let command = Command::Set {
+      key:     "k".into(),
+      value:   Value::Integer(2),
+      options: SetOptions::default(),
+    };
+    assert_eq!(command.to_resp(), frame(&["SET", "k", "2"]));
+    assert_eq!(
+      Command::parse(Value::Array(vec![
+        Value::SimpleString("SET".into()),
+        Value::SimpleString("k".into()),
+        Value::Integer(2),
+      ])),
+      Command::parse(command.to_resp())
+    );
+  }
+
+  #[test]
+  fn malformed_frames_are_rejected() {
+    assert_eq!(
+      Command::parse(Value::BulkString("GET".into())),
+      Err(ParseError::NotAnArray)
+    );
+    assert_eq!(Command::parse(Value::Array(vec![])), Err(ParseError::Empty));
+    assert_eq!(
+      Command::parse(frame(&["NOPE"])).unwrap_err().to_string(),
+      "unknown command 'NOPE', with args beginning with: "
+    );
+    assert_eq!(
+      Command::parse(frame(&["GTE", "a", "b"]))
+        .unwrap_err()
+        .to_string(),
+      "unknown command 'GTE', with args beginning with: 'a' 'b' (did you mean \
+       'get'?)"
+    );
+    assert_eq!(
+      Command::parse(frame(&["DEBUG", "NOPE"])),
+      Err(ParseError::UnknownSubcommand {
+        command:    "debug".into(),
+        subcommand: "NOPE".into(),
+      })
+    );
+    assert_eq!(
+      Command::parse(frame(&["GET"])),
+      Err(ParseError::WrongArity("get".into()))
+    );
+    assert_eq!(
+      Command::parse(frame(&["GET", "a", "b"])),
+      Err(ParseError::WrongArity("get".into()))
+    );
+    assert_eq!(
+      Command::parse(frame(&["PING", "a", "b"])),
+      Err(ParseError::WrongArity("ping".into()))
+    );
+    // arities are checked before arguments are parsed
+    #[cfg(feature = "hashes")]
+    assert_eq!(
+      Command::parse(frame(&["HTTL", "k", "f"])),
+      Err(ParseError::WrongArity("httl".into()))
+    );
+    assert_eq!(
+      Command::parse(frame(&["ACL"])),
+      Err(ParseError::WrongArity("acl".into()))
+    );
+    #[cfg(feature = "lists")]
+    assert_eq!(
+      Command::parse(frame(&["LRANGE", "k", "zero", "1"])),
+      Err(ArgumentError::NotAnInteger.into())
+    );
+    #[cfg(not(feature = "lists"))]
+    assert!(matches!(
+      Command::parse(frame(&["LRANGE", "k", "0", "1"])),
+      Err(ParseError::UnknownCommand { command, .. }) if command == "LRANGE"
+    ));
+    #[cfg(feature = "hashes")]
+    for args in [
+      &["HTTL", "k", "FIELDS", "2", "f"][..],
+      &["HTTL", "k", "f", "g", "h"],
+      &["HEXPIRE", "k", "10", "SOMETIME", "FIELDS", "1", "f"],
+    ] {
+      assert!(matches!(
+        Command::parse(frame(args)),
+        Err(ParseError::InvalidArgument { .. })
+      ));
+    }
+    assert!(matches!(
+      Command::parse(frame(&["CLIENT", "NO-TOUCH", "maybe"])),
+      Err(ParseError::InvalidArgument { .. })
+    ));
+    #[cfg(feature = "hashes")]
+    assert_eq!(
+      Command::parse(frame(&["HEXPIRE", "k", "soon", "FIELDS", "1", "f"])),
+      Err(ArgumentError::NotAnInteger.into())
+    );
+    for args in [
+      &["SET", "k", "v", "NX", "XX"][..],
+      &["SET", "k", "v", "EX", "1", "KEEPTTL"],
+      &["SET", "k", "v", "GET", "GET"],
+      &["SET", "k", "v", "TTL"],
+      &["SCAN", "0", "COUNT", "0"],
+      &["SCAN", "0", "TYPE"],
+    ] {
+      assert_eq!(
+        Command::parse(frame(args)),
+        Err(ArgumentError::Syntax.into())
+      );
+    }
+    assert_eq!(
+      Command::parse(frame(&["SCAN", "-1"])),
+      Err(ArgumentError::InvalidCursor.into())
+    );
+    assert_eq!(
+      Command::parse(frame(&["INCRBY", "k", "1.5"]))
+        .unwrap_err()
+        .to_string(),
+      "value is not an integer or out of range"
+    );
+    assert!(matches!(
+      Command::parse(frame(&["KEYS", "a*"])),
+      Err(ParseError::InvalidArgument { .. })
+    ));
+    assert!(matches!(
+      Command::parse(Value::Array(vec![
+        Value::BulkString("GET".into()),
+        Value::BulkString(vec![0xff].into()),
+      ])),
+      Err(ParseError::InvalidArgument { .. })
+    ));
+  }
+
+  #[test]
+  fn requests_are_parsed_from_resp_arrays() {
+    let request = |args: &[&str]| {
+      args
+        .iter()
+        .map(|a| Value::BulkString(a.as_bytes().to_vec().into()))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(
+      Command::from_resp(request(&["sEt", "key", "val"])).unwrap(),
+      Command::Set {
+        key:     "key".into(),
+        value:   Value::BulkString("val".into()),
+        options: SetOptions::default(),
+      }
+    );
+    assert_eq!(
+      Command::from_resp(request(&["SET", "key"]))
+        .unwrap_err()
+        .to_string(),
+      "wrong number of arguments for 'set' command"
+    );
+    assert!(matches!(
+      Command::from_resp(vec![]),
+      Err(KraglinError::InvalidCommand(ParseError::Empty))
+    ));
+    assert!(matches!(
+      Command::from_resp(request(&["INCRBY", "k", "x"])),
+      Err(KraglinError::NotAnInteger)
+    ));
+  }
+}
@@ -0,0 +1,1163 @@
+//! Static metadata about each [`Command`]: arity, flags, key positions, and
+//! ACL categories.
+
+use bitflags::bitflags;
+use smol_str::SmolStr;
+
+use super::{ClientAttribute, Command};
+
+bitflags! {
+  /// Properties of a command, as reported by `COMMAND`.
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  pub struct CommandFlags: u8 {
+    /// The command may modify the keyspace.
+    const WRITE = 1 << 0;
+    /// The command never modifies the keyspace.
+    const READONLY = 1 << 1;
+    /// The command may grow memory usage, so it's rejected when over the
+    /// memory limit.
+    const DENYOOM = 1 << 2;
+    /// The command may block the client.
+    const BLOCKING = 1 << 3;
+    /// The command may run before the connection has authenticated.
+    const NO_AUTH = 1 << 4;
+    /// The command administers the server rather than accessing data.
+    const ADMIN = 1 << 5;
+    /// The command may run while a RESP2 connection is subscribed to
+    /// channels.
+    const SUBSCRIBED = 1 << 6;
+  }
+}
+
+bitflags! {
+  /// The ACL categories a command belongs to, so that ACL rules can allow or
+  /// deny whole groups of commands (e.g. `+@read`). They are mostly derived
+  /// from the command's [`CommandFlags`].
+  #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+  pub struct AclCategories: u16 {
+    /// Commands which read data.
+    const READ = 1 << 0;
+    /// Commands which write data.
+    const WRITE = 1 << 1;
+    /// Commands which administer the server.
+    const ADMIN = 1 << 2;
+    /// Commands which are potentially dangerous, e.g. because they're slow
+    /// or reveal server internals.
+    const DANGEROUS = 1 << 3;
+    /// Commands about the connection itself.
+    const CONNECTION = 1 << 4;
+    /// Commands which operate on keys regardless of their type.
+    const KEYSPACE = 1 << 5;
+    /// String commands.
+    const STRING = 1 << 6;
+    /// Hash commands.
+    const HASH = 1 << 7;
+    /// Set commands.
+    const SET = 1 << 8;
+    /// List commands.
+    const LIST = 1 << 9;
+    /// JSON commands.
+    const JSON = 1 << 10;
+    /// Transaction commands.
+    const TRANSACTION = 1 << 11;
+  }
+}
+
+impl AclCategories {
+  /// Looks up a single category by its (case-insensitive) name, without the
+  /// leading `@`.
+  pub fn from_category_name(name: &str) -> Option<Self> {
+    Self::from_name(&name.to_ascii_uppercase())
+  }
+
+  /// Returns the lowercased names of the categories.
+  pub fn names(self) -> impl Iterator<Item = String> {
+    self.iter_names().map(|(name, _)| name.to_ascii_lowercase())
+  }
+}
+
+/// Static metadata about a command, in the shape of a `COMMAND` reply entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommandSpec {
+  /// The number of arguments, including the command (and subcommand) name. A
+  /// negative arity `-n` means "at least `n`".
+  pub arity:     i64,
+  /// The command's flags.
+  pub flags:     CommandFlags,
+  /// The position of the first key argument, or `0` if there are no keys.
+  pub first_key: i64,
+  /// The position of the last key argument. Negative positions count from
+  /// the end, so `-1` means the keys continue to the last argument.
+  pub last_key:  i64,
+  /// The step between key arguments.
+  pub key_step:  i64,
+}
+
+impl CommandSpec {
+  /// Creates a spec with the given arity and flags, and no keys.
+  pub const fn new(arity: i64, flags: CommandFlags) -> Self {
+    CommandSpec {
+      arity,
+      flags,
+      first_key: 0,
+      last_key: 0,
+      key_step: 0,
+    }
+  }
+
+  /// Sets the key positions.
+  const fn keys(self, first_key: i64, last_key: i64, key_step: i64) -> Self {
+    CommandSpec {
+      first_key,
+      last_key,
+      key_step,
+      ..self
+    }
+  }
+
+  /// Sets a single key, at position 1.
+  const fn key(self) -> Self { self.keys(1, 1, 1) }
+
+  /// Whether `argc` arguments (including the name) satisfy the arity.
+  pub fn accepts(&self, argc: usize) -> bool {
+    let argc = argc as i64;
+    if self.arity >= 0 {
+      argc == self.arity
+    } else {
+      argc >= -self.arity
+    }
+  }
+}
+
+/// A command's documentation, as shown by `HELP` subcommands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CommandDocs {
+  /// The command's arguments, after its name (and subcommand name), in
+  /// Redis' syntax, e.g. `<key> [SAMPLES <count>]`.
+  pub arguments: &'static str,
+  /// A one-line description of what the command does.
+  pub summary:   &'static str,
+}
+
+impl CommandDocs {
+  const fn new(arguments: &'static str, summary: &'static str) -> Self {
+    CommandDocs { arguments, summary }
+  }
+}
+
+const READ: CommandFlags = CommandFlags::READONLY;
+const WRITE: CommandFlags = CommandFlags::WRITE;
+const ADMIN: CommandFlags = CommandFlags::ADMIN;
+const GROW: CommandFlags = CommandFlags::WRITE.union(CommandFlags::DENYOOM);
+
+impl Command {
+  /// Returns the command's static metadata.
+  pub fn spec(&self) -> CommandSpec {
+    match self {
+      Command::Set { .. } | Command::SetAndGet { .. } => {
+        CommandSpec::new(-3, GROW).key()
+      }
+      Command::Get { .. } => CommandSpec::new(2, READ).key(),
+      Command::GetDelete { .. } => CommandSpec::new(2, WRITE).key(),
+      Command::MultipleGet { .. } => CommandSpec::new(-2, READ).keys(1, -1, 1),
+      Command::Increment { .. } => CommandSpec::new(2, GROW).key(),
+      Command::IncrementBy { .. } => CommandSpec::new(3, GROW).key(),
+      Command::Keys => CommandSpec::new(2, READ),
+      Command::Scan { .. } => CommandSpec::new(-2, READ),
+      Command::Exists { .. } | Command::Touch { .. } => {
+        CommandSpec::new(-2, READ).keys(1, -1, 1)
+      }
+      Command::Delete { .. } | Command::Unlink { .. } => {
+        CommandSpec::new(-2, WRITE).keys(1, -1, 1)
+      }
+      Command::Expire { .. }
+      | Command::PExpire { .. }
+      | Command::ExpireAt { .. }
+      | Command::PExpireAt { .. } => CommandSpec::new(-3, WRITE).key(),
+      Command::Dump { .. } => CommandSpec::new(2, READ).key(),
+      Command::Restore { .. } => CommandSpec::new(-4, GROW).key(),
+      // like Redis, only the key argument is a key position; the keys after
+      // `KEYS` aren't at fixed positions
+      Command::Migrate { .. } => CommandSpec::new(-6, WRITE).keys(3, 3, 1),
+      Command::Info => CommandSpec::new(1, READ),
+      Command::BackgroundSave => CommandSpec::new(1, ADMIN),
+      Command::MemoryUsage { .. } => CommandSpec::new(3, READ).keys(2, 2, 1),
+      Command::ObjectEncoding { .. } => CommandSpec::new(3, READ).keys(2, 2, 1),
+      Command::DebugHotKeys => CommandSpec::new(2, READ),
+      Command::DebugObject { .. } => CommandSpec::new(3, READ).keys(2, 2, 1),
+      Command::DebugKeyStats { .. } => CommandSpec::new(-2, READ),
+      Command::DebugStringMatchLen => CommandSpec::new(2, ADMIN),
+      Command::Auth { .. } => CommandSpec::new(-2, CommandFlags::NO_AUTH),
+      Command::Hello { .. } => CommandSpec::new(-1, CommandFlags::NO_AUTH),
+      Command::AclSetUser { .. } => CommandSpec::new(-3, ADMIN),
+      Command::AclGetUser { .. } => CommandSpec::new(3, ADMIN),
+      Command::AclDelUser { .. } => CommandSpec::new(-3, ADMIN),
+      Command::AclList => CommandSpec::new(2, ADMIN),
+      Command::AclWhoAmI => CommandSpec::new(2, CommandFlags::empty()),
+      Command::AclLog { .. } | Command::AclLogReset => {
+        CommandSpec::new(-2, ADMIN)
+      }
+      Command::AclCat { .. } => CommandSpec::new(-2, CommandFlags::empty()),
+      Command::LatencyLatest => CommandSpec::new(2, ADMIN),
+      Command::LatencyReset { .. } => CommandSpec::new(-2, ADMIN),
+      Command::Ping { .. } => CommandSpec::new(-1, CommandFlags::SUBSCRIBED),
+      Command::Quit => CommandSpec::new(
+        1,
+        CommandFlags::NO_AUTH.union(CommandFlags::SUBSCRIBED),
+      ),
+      Command::Help { .. } => CommandSpec::new(2, CommandFlags::empty()),
+      Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::ReadOnly
+      | Command::ReadWrite => CommandSpec::new(1, CommandFlags::empty()),
+      Command::Select { .. } => CommandSpec::new(2, CommandFlags::empty()),
+      Command::ClientInfo => CommandSpec::new(2, CommandFlags::empty()),
+      Command::ClientNoEvict { .. } => CommandSpec::new(3, ADMIN),
+      Command::ClientNoTouch { .. } => {
+        CommandSpec::new(3, CommandFlags::empty())
+      }
+      Command::ClientSetInfo { .. } => {
+        CommandSpec::new(4, CommandFlags::empty())
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashSet { .. } => CommandSpec::new(4, GROW).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashGet { .. } => CommandSpec::new(3, READ).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { .. } => CommandSpec::new(2, READ).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { .. } => CommandSpec::new(-3, READ).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashDelete { .. } => CommandSpec::new(-3, WRITE).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashExpire { .. }
+      | Command::HashPExpire { .. }
+      | Command::HashPExpireAt { .. } => CommandSpec::new(-6, WRITE).key(),
+      #[cfg(feature = "hashes")]
+      Command::HashTtl { .. } | Command::HashPTtl { .. } => {
+        CommandSpec::new(-5, READ).key()
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashPersist { .. } => CommandSpec::new(-5, WRITE).key(),
+      #[cfg(feature = "sets")]
+      Command::SetAdd { .. } => CommandSpec::new(3, GROW).key(),
+      #[cfg(feature = "sets")]
+      Command::SetMembers { .. } => CommandSpec::new(2, READ).key(),
+      #[cfg(feature = "sets")]
+      Command::SetCardinality { .. } => CommandSpec::new(2, READ).key(),
+      #[cfg(feature = "sets")]
+      Command::SetIsMember { .. } => CommandSpec::new(3, READ).key(),
+      #[cfg(feature = "sets")]
+      Command::SetDifference { .. } => CommandSpec::new(3, READ).keys(1, -1, 1),
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore { .. } => {
+        CommandSpec::new(4, GROW).keys(1, -1, 1)
+      }
+      #[cfg(feature = "sets")]
+      Command::SetRemove { .. } => CommandSpec::new(3, WRITE).key(),
+      #[cfg(feature = "lists")]
+      Command::LeftPush { .. } | Command::RightPush { .. } => {
+        CommandSpec::new(3, GROW).key()
+      }
+      #[cfg(feature = "lists")]
+      Command::ListRange { .. } => CommandSpec::new(4, READ).key(),
+      #[cfg(feature = "lists")]
+      Command::ListLength { .. } => CommandSpec::new(2, READ).key(),
+      #[cfg(feature = "lists")]
+      Command::LeftPop { .. } | Command::RightPop { .. } => {
+        CommandSpec::new(2, WRITE).key()
+      }
+      #[cfg(feature = "json")]
+      Command::JsonSet { .. } => CommandSpec::new(4, GROW).key(),
+      #[cfg(feature = "json")]
+      Command::JsonGet { .. } => CommandSpec::new(3, READ).key(),
+      #[cfg(feature = "json")]
+      Command::JsonDelete { .. } => CommandSpec::new(3, WRITE).key(),
+      #[cfg(feature = "json")]
+      Command::JsonNumIncrBy { .. } => CommandSpec::new(4, GROW).key(),
+      Command::Custom { spec, .. } => *spec,
+    }
+  }
+
+  /// Returns the command's documentation.
+  pub fn docs(&self) -> CommandDocs {
+    match self {
+      Command::Set { .. } => CommandDocs::new(
+        "<key> <value> [NX|XX] [EX <seconds>|PX <milliseconds>|EXAT \
+         <unix-time-seconds>|PXAT <unix-time-milliseconds>|KEEPTTL]",
+        "Sets the string value of a key, optionally with a time to live or \
+         only if it does or doesn't exist.",
+      ),
+      Command::SetAndGet { .. } => CommandDocs::new(
+        "<key> <value> [NX|XX] [EX <seconds>|PX <milliseconds>|EXAT \
+         <unix-time-seconds>|PXAT <unix-time-milliseconds>|KEEPTTL] GET",
+        "Sets the string value of a key, returning its old value.",
+      ),
+      Command::Get { .. } => {
+        CommandDocs::new("<key>", "Returns the string value of a key.")
+      }
+      Command::GetDelete { .. } => CommandDocs::new(
+        "<key>",
+        "Returns the string value of a key and deletes it.",
+      ),
+      Command::MultipleGet { .. } => CommandDocs::new(
+        "<key> [<key> ...]",
+        "Returns the string values of one or more keys.",
+      ),
+      Command::Increment { .. } => CommandDocs::new(
+        "<key>",
+        "Increments the integer value of a key by one.",
+      ),
+      Command::IncrementBy { .. } => CommandDocs::new(
+        "<key> <increment>",
+        "Increments the integer value of a key by a number.",
+      ),
+      Command::Keys => {
+        CommandDocs::new("*", "Returns every key in the keyspace.")
+      }
+      Command::Scan { .. } => CommandDocs::new(
+        "<cursor> [MATCH <pattern>] [COUNT <count>]",
+        "Iterates over the keys in the keyspace.",
+      ),
+      Command::Exists { .. } => CommandDocs::new(
+        "<key> [<key> ...]",
+        "Counts how many of the given keys exist.",
+      ),
+      Command::Delete { .. } => {
+        CommandDocs::new("<key> [<key> ...]", "Deletes one or more keys.")
+      }
+      Command::Touch { .. } => CommandDocs::new(
+        "<key> [<key> ...]",
+        "Records an access to one or more keys, and counts how many exist.",
+      ),
+      Command::Unlink { .. } => CommandDocs::new(
+        "<key> [<key> ...]",
+        "Deletes one or more keys, reclaiming their memory in the background.",
+      ),
+      Command::Expire { .. } => CommandDocs::new(
+        "<key> <seconds> [NX|XX|GT|LT]",
+        "Sets a time to live, in seconds, on a key.",
+      ),
+      Command::PExpire { .. } => CommandDocs::new(
+        "<key> <milliseconds> [NX|XX|GT|LT]",
+        "Sets a time to live, in milliseconds, on a key.",
+      ),
+      Command::ExpireAt { .. } => CommandDocs::new(
+        "<key> <unix-time-seconds> [NX|XX|GT|LT]",
+        "Sets the unix time, in seconds, at which a key expires.",
+      ),
+      Command::PExpireAt { .. } => CommandDocs::new(
+        "<key> <unix-time-milliseconds> [NX|XX|GT|LT]",
+        "Sets the unix time, in milliseconds, at which a key expires.",
+      ),
+      Command::Dump { .. } => CommandDocs::new(
+        "<key>",
+        "Returns a serialized representation of the value stored at a key.",
+      ),
+      Command::Restore { .. } => CommandDocs::new(
+        "<key> <ttl> <serialized-value> [REPLACE] [ABSTTL]",
+        "Creates a key from the serialized representation of a value.",
+      ),
+      Command::Migrate { .. } => CommandDocs::new(
+        "<host> <port> <key | \"\"> <destination-db> <timeout> [COPY] \
+         [REPLACE] [AUTH <password> | AUTH2 <username> <password>] [KEYS \
+         <key> [<key> ...]]",
+        "Copies or moves keys to another instance.",
+      ),
+      Command::Info => {
+        CommandDocs::new("", "Returns information and statistics.")
+      }
+      Command::BackgroundSave => {
+        CommandDocs::new("", "Asynchronously saves the keyspace to disk.")
+      }
+      Command::MemoryUsage { .. } => CommandDocs::new(
+        "<key>",
+        "Estimates the number of bytes a key and its value use.",
+      ),
+      Command::ObjectEncoding { .. } => CommandDocs::new(
+        "<key>",
+        "Returns the internal encoding of a key's value.",
+      ),
+      Command::DebugHotKeys => {
+        CommandDocs::new("", "Lists the most frequently accessed keys.")
+      }
+      Command::DebugObject { .. } => {
+        CommandDocs::new("<key>", "Describes the internals of a key's value.")
+      }
+      Command::DebugKeyStats { .. } => CommandDocs::new(
+        "[SAMPLES <count>]",
+        "Summarizes the keyspace by type, size, and time to live.",
+      ),
+      Command::DebugStringMatchLen => CommandDocs::new(
+        "",
+        "Fuzzes the glob pattern matcher with random patterns.",
+      ),
+      Command::Auth { .. } => CommandDocs::new(
+        "[<username>] <password>",
+        "Authenticates the connection.",
+      ),
+      Command::Hello { .. } => CommandDocs::new(
+        "[<protover> [AUTH <username> <password>]]",
+        "Switches the connection's RESP version and describes the server.",
+      ),
+      Command::AclSetUser { .. } => CommandDocs::new(
+        "<username> [<rule> ...]",
+        "Creates or modifies a user by applying ACL rules.",
+      ),
+      Command::AclGetUser { .. } => CommandDocs::new(
+        "<username>",
+        "Describes a user's flags, passwords, and permissions.",
+      ),
+      Command::AclDelUser { .. } => {
+        CommandDocs::new("<username> [<username> ...]", "Deletes users.")
+      }
+      Command::AclList => {
+        CommandDocs::new("", "Describes every user as a list of rules.")
+      }
+      Command::AclWhoAmI => {
+        CommandDocs::new("", "Returns the connection's user.")
+      }
+      Command::AclLog { .. } | Command::AclLogReset => CommandDocs::new(
+        "[<count> | RESET]",
+        "Lists recent denied commands and authentication failures.",
+      ),
+      Command::AclCat { .. } => CommandDocs::new(
+        "[<category>]",
+        "Lists the ACL categories, or the commands in one of them.",
+      ),
+      Command::LatencyLatest => CommandDocs::new(
+        "",
+        "Lists the latest and worst latency of each recorded event.",
+      ),
+      Command::LatencyReset { .. } => CommandDocs::new(
+        "[<event> ...]",
+        "Clears the samples of events, or of every event.",
+      ),
+      Command::Ping { .. } => CommandDocs::new(
+        "[<message>]",
+        "Replies with PONG, or echoes the message.",
+      ),
+      Command::Quit => CommandDocs::new("", "Closes the connection."),
+      Command::Multi => CommandDocs::new("", "Starts a transaction."),
+      Command::Exec => {
+        CommandDocs::new("", "Runs the commands queued in a transaction.")
+      }
+      Command::Discard => {
+        CommandDocs::new("", "Discards the commands queued in a transaction.")
+      }
+      Command::ReadOnly => {
+        CommandDocs::new("", "Lets the connection read from a replica.")
+      }
+      Command::ReadWrite => {
+        CommandDocs::new("", "Stops the connection reading from a replica.")
+      }
+      Command::Select { .. } => {
+        CommandDocs::new("<index>", "Switches to another numbered database.")
+      }
+      Command::Help { .. } => CommandDocs::new("", "Prints this help."),
+      Command::ClientInfo => {
+        CommandDocs::new("", "Describes the current connection.")
+      }
+      Command::ClientNoEvict { .. } => CommandDocs::new(
+        "ON|OFF",
+        "Exempts the connection from client eviction.",
+      ),
+      Command::ClientNoTouch { .. } => CommandDocs::new(
+        "ON|OFF",
+        "Stops the connection's commands from updating key access statistics.",
+      ),
+      Command::ClientSetInfo { .. } => CommandDocs::new(
+        "<LIB-NAME libname | LIB-VER libver>",
+        "Records the name or version of the connection's client library.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashSet { .. } => CommandDocs::new(
+        "<key> <field> <value>",
+        "Sets the value of a field in a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashGet { .. } => CommandDocs::new(
+        "<key> <field>",
+        "Returns the value of a field in a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashGetAll { .. } => {
+        CommandDocs::new("<key>", "Returns every field and value in a hash.")
+      }
+      #[cfg(feature = "hashes")]
+      Command::HashMultipleGet { .. } => CommandDocs::new(
+        "<key> <field> [<field> ...]",
+        "Returns the values of fields in a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashDelete { .. } => CommandDocs::new(
+        "<key> <field> [<field> ...]",
+        "Deletes fields from a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashExpire { .. } => CommandDocs::new(
+        "<key> <seconds> [NX|XX|GT|LT] FIELDS <numfields> <field> ...",
+        "Sets a time to live, in seconds, on fields of a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashPExpire { .. } => CommandDocs::new(
+        "<key> <milliseconds> [NX|XX|GT|LT] FIELDS <numfields> <field> ...",
+        "Sets a time to live, in milliseconds, on fields of a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashPExpireAt { .. } => CommandDocs::new(
+        "<key> <unix-time-milliseconds> [NX|XX|GT|LT] FIELDS <numfields> \
+         <field> ...",
+        "Sets the unix time, in milliseconds, at which fields of a hash \
+         expire.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashTtl { .. } => CommandDocs::new(
+        "<key> FIELDS <numfields> <field> ...",
+        "Returns the time to live, in seconds, of fields of a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashPTtl { .. } => CommandDocs::new(
+        "<key> FIELDS <numfields> <field> ...",
+        "Returns the time to live, in milliseconds, of fields of a hash.",
+      ),
+      #[cfg(feature = "hashes")]
+      Command::HashPersist { .. } => CommandDocs::new(
+        "<key> FIELDS <numfields> <field> ...",
+        "Removes the time to live of fields of a hash.",
+      ),
+      #[cfg(feature = "sets")]
+      Command::SetAdd { .. } => {
+        CommandDocs::new("<key> <member>", "Adds a member to a set.")
+      }
+      #[cfg(feature = "sets")]
+      Command::SetMembers { .. } => {
+        CommandDocs::new("<key>", "Returns every member of a set.")
+      }
+      #[cfg(feature = "sets")]
+      Command::SetCardinality { .. } => {
+        CommandDocs::new("<key>", "Returns the number of members in a set.")
+      }
+      #[cfg(feature = "sets")]
+      Command::SetIsMember { .. } => CommandDocs::new(
+        "<key> <member>",
+        "Determines whether a member belongs to a set.",
+      ),
+      #[cfg(feature = "sets")]
+      Command::SetDifference { .. } => CommandDocs::new(
+        "<key> <key>",
+        "Returns the members of the first set which aren't in the second.",
+      ),
+      #[cfg(feature = "sets")]
+      Command::SetDifferenceStore { .. } => CommandDocs::new(
+        "<destination> <key> <key>",
+        "Stores the difference of two sets in a key.",
+      ),
+      #[cfg(feature = "sets")]
+      Command::SetRemove { .. } => {
+        CommandDocs::new("<key> <member>", "Removes a member from a set.")
+      }
+      #[cfg(feature = "lists")]
+      Command::LeftPush { .. } => {
+        CommandDocs::new("<key> <element>", "Prepends an element to a list.")
+      }
+      #[cfg(feature = "lists")]
+      Command::RightPush { .. } => {
+        CommandDocs::new("<key> <element>", "Appends an element to a list.")
+      }
+      #[cfg(feature = "lists")]
+      Command::ListRange { .. } => CommandDocs::new(
+        "<key> <start> <stop>",
+        "Returns a range of elements from a list.",
+      ),
+      #[cfg(feature = "lists")]
+      Command::ListLength { .. } => {
+        CommandDocs::new("<key>", "Returns the length of a list.")
+      }
+      #[cfg(feature = "lists")]
+      Command::LeftPop { .. } => CommandDocs::new(
+        "<key>",
+        "Removes and returns the first element of a list.",
+      ),
+      #[cfg(feature = "lists")]
+      Command::RightPop { .. } => CommandDocs::new(
+        "<key>",
+        "Removes and returns the last element of a list.",
+      ),
+      #[cfg(feature = "json")]
+      Command::JsonSet { .. } => CommandDocs::new(
+        "<key> <path> <json>",
+        "Sets the value at a path in a JSON document.",
+      ),
+      #[cfg(feature = "json")]
+      Command::JsonGet { .. } => CommandDocs::new(
+        "<key> <path>",
+        "Returns the values at a path in a JSON document.",
+      ),
+      #[cfg(feature = "json")]
+      Command::JsonDelete { .. } => CommandDocs::new(
+        "<key> <path>",
+        "Deletes the values at a path in a JSON document.",
+      ),
+      #[cfg(feature = "json")]
+      Command::JsonNumIncrBy { .. } => CommandDocs::new(
+        "<key> <path> <number>",
+        "Increments the numbers at a path in a JSON document.",
+      ),
+      Command::Custom { .. } => CommandDocs::default(),
+    }
+  }
+
+  /// Generates the reply to `HELP` for the command `name`, from the
+  /// documentation of its subcommands. Like Redis, it's a list of lines: a
+  /// usage line, then each subcommand's syntax followed by its indented
+  /// summary.
+  pub fn help(name: &str) -> Vec<String> {
+    let name = name.to_ascii_uppercase();
+    let mut lines = vec![format!(
+      "{name} <subcommand> [<arg> [value] [opt] ...]. Subcommands are:"
+    )];
+    let mut seen = Vec::new();
+    let subcommands = Command::builtins()
+      .into_iter()
+      .filter(|c| c.command_name() == name)
+      .filter(|c| !matches!(c, Command::Help { .. }))
+      .chain([Command::Help {
+        command: name.as_str().into(),
+      }]);
+    for command in subcommands {
+      let Some(subcommand) = command.subcommand_name() else {
+        continue;
+      };
+      if seen.contains(&subcommand) {
+        continue;
+      }
+      seen.push(subcommand);
+      let docs = command.docs();
+      lines.push(format!("{subcommand} {}", docs.arguments).trim().to_owned());
+      lines.push(format!("    {}", docs.summary));
+    }
+    lines
+  }
+
+  /// The number of arguments the command takes, including its name. See
+  /// [`CommandSpec::arity`].
+  pub fn arity(&self) -> i64 { self.spec().arity }
+
+  /// The command's flags.
+  pub fn flags(&self) -> CommandFlags { self.spec().flags }
+
+  /// Whether the command may modify the keyspace.
+  pub fn is_write(&self) -> bool { self.flags().contains(CommandFlags::WRITE) }
+
+  /// Whether the command only reads the keyspace.
+  pub fn is_read_only(&self) -> bool {
+    self.flags().contains(CommandFlags::READONLY)
+  }
+
+  /// The positions of the key arguments in the command's RESP frame (see
+  /// [`Command::to_resp()`]), where the command name is position `0`.
+  pub fn key_positions(&self) -> Vec<usize> {
+    let spec = self.spec();
+    if spec.first_key == 0 {
+      return Vec::new();
+    }
+
+    let argc = match self.to_resp() {
+      crate::value::Value::Array(frame) => frame.len() as i64,
+      _ => unreachable!("commands are encoded as arrays"),
+    };
+    let last = if spec.last_key < 0 {
+      argc + spec.last_key
+    } else {
+      spec.last_key
+    };
+    (spec.first_key..=last)
+      .step_by(spec.key_step as usize)
+      .map(|i| i as usize)
+      .collect()
+  }
+}
+
+impl Command {
+  /// The ACL categories the command belongs to.
+  pub fn acl_categories(&self) -> AclCategories {
+    let flags = self.flags();
+    let mut categories = AclCategories::empty();
+    if flags.contains(CommandFlags::READONLY) {
+      categories |= AclCategories::READ;
+    }
+    if flags.contains(CommandFlags::WRITE) {
+      categories |= AclCategories::WRITE;
+    }
+    if flags.contains(CommandFlags::ADMIN) {
+      categories |= AclCategories::ADMIN | AclCategories::DANGEROUS;
+    }
+    if flags.contains(CommandFlags::NO_AUTH) {
+      categories |= AclCategories::CONNECTION;
+    }
+
+    categories
+      | match self {
+        Command::Set { .. }
+        | Command::SetAndGet { .. }
+        | Command::Get { .. }
+        | Command::GetDelete { .. }
+        | Command::MultipleGet { .. }
+        | Command::Increment { .. }
+        | Command::IncrementBy { .. } => AclCategories::STRING,
+        // `KEYS` can block the server on a large keyspace, and `MIGRATE` on
+        // a slow target
+        Command::Keys | Command::Migrate { .. } => {
+          AclCategories::KEYSPACE | AclCategories::DANGEROUS
+        }
+        Command::Scan { .. }
+        | Command::Exists { .. }
+        | Command::Delete { .. }
+        | Command::Touch { .. }
+        | Command::Unlink { .. }
+        | Command::Expire { .. }
+        | Command::PExpire { .. }
+        | Command::ExpireAt { .. }
+        | Command::PExpireAt { .. }
+        | Command::Dump { .. }
+        | Command::Restore { .. }
+        | Command::MemoryUsage { .. }
+        | Command::ObjectEncoding { .. }
+        | Command::Select { .. } => AclCategories::KEYSPACE,
+        Command::DebugHotKeys
+        | Command::DebugObject { .. }
+        | Command::DebugKeyStats { .. }
+        | Command::DebugStringMatchLen => {
+          AclCategories::ADMIN | AclCategories::DANGEROUS
+        }
+        Command::Multi | Command::Exec | Command::Discard => {
+          AclCategories::TRANSACTION
+        }
+        Command::Ping { .. }
+        | Command::Quit
+        | Command::Hello { .. }
+        | Command::ReadOnly
+        | Command::ReadWrite
+        | Command::ClientInfo
+        | Command::ClientNoEvict { .. }
+        | Command::ClientNoTouch { .. }
+        | Command::ClientSetInfo { .. } => AclCategories::CONNECTION,
+        #[cfg(feature = "hashes")]
+        Command::HashSet { .. }
+        | Command::HashGet { .. }
+        | Command::HashGetAll { .. }
+        | Command::HashMultipleGet { .. }
+        | Command::HashDelete { .. }
+        | Command::HashExpire { .. }
+        | Command::HashPExpire { .. }
+        | Command::HashPExpireAt { .. }
+        | Command::HashTtl { .. }
+        | Command::HashPTtl { .. }
+        | Command::HashPersist { .. } => AclCategories::HASH,
+        #[cfg(feature = "sets")]
+        Command::SetAdd { .. }
+        | Command::SetMembers { .. }
+        | Command::SetCardinality { .. }
+        | Command::SetIsMember { .. }
+        | Command::SetDifference { .. }
+        | Command::SetDifferenceStore { .. }
+        | Command::SetRemove { .. } => AclCategories::SET,
+        #[cfg(feature = "lists")]
+        Command::LeftPush { .. }
+        | Command::RightPush { .. }
+        | Command::ListRange { .. }
+        | Command::ListLength { .. }
+        | Command::LeftPop { .. }
+        | Command::RightPop { .. } => AclCategories::LIST,
+        #[cfg(feature = "json")]
+        Command::JsonSet { .. }
+        | Command::JsonGet { .. }
+        | Command::JsonDelete { .. }
+        | Command::JsonNumIncrBy { .. } => AclCategories::JSON,
+        _ => AclCategories::empty(),
+      }
+  }
+
+  /// One instance of every built-in command (and subcommand), with
+  /// placeholder arguments, for introspection like `ACL CAT`.
+  pub fn builtins() -> Vec<Command> {
+    let key = || smol_str::SmolStr::default();
+    let value = || crate::value::Value::BulkString(Default::default());
+
+    #[allow(unused_mut)]
+    let mut commands = vec![
+      Command::Set {
+        key:     key(),
+        value:   value(),
+        options: Default::default(),
+      },
+      Command::SetAndGet {
+        key:     key(),
+        value:   value(),
+        options: Default::default(),
+      },
+      Command::Get { key: key() },
+      Command::GetDelete { key: key() },
+      Command::MultipleGet { keys: vec![key()] },
+      Command::Increment { key: key() },
+      Command::IncrementBy {
+        key:       key(),
+        increment: 1,
+      },
+      Command::Keys,
+      Command::Scan {
+        cursor:  0,
+        pattern: None,
+        count:   None,
+      },
+      Command::Exists { keys: vec![key()] },
+      Command::Delete { keys: vec![key()] },
+      Command::Touch { keys: vec![key()] },
+      Command::Unlink { keys: vec![key()] },
+      Command::Expire {
+        key:       key(),
+        seconds:   0,
+        condition: None,
+      },
+      Command::PExpire {
+        key:          key(),
+        milliseconds: 0,
+        condition:    None,
+      },
+      Command::ExpireAt {
+        key:       key(),
+        unix_time: 0,
+        condition: None,
+      },
+      Command::PExpireAt {
+        key:          key(),
+        unix_time_ms: 0,
+        condition:    None,
+      },
+      Command::Dump { key: key() },
+      Command::Restore {
+        key:     key(),
+        ttl:     0,
+        payload: Default::default(),
+        replace: false,
+        absttl:  false,
+      },
+      Command::Migrate {
+        host:    Default::default(),
+        port:    0,
+        keys:    vec![key()],
+        db:      0,
+        timeout: 0,
+        copy:    false,
+        replace: false,
+        auth:    None,
+      },
+      Command::Info,
+      Command::BackgroundSave,
+      Command::MemoryUsage { key: key() },
+      Command::ObjectEncoding { key: key() },
+      Command::DebugHotKeys,
+      Command::DebugObject { key: key() },
+      Command::DebugKeyStats { samples: None },
+      Command::DebugStringMatchLen,
+      Command::Auth {
+        username: None,
+        password: Default::default(),
+      },
+      Command::Hello {
+        protover: None,
+        auth:     None,
+      },
+      Command::AclSetUser {
+        username: key(),
+        rules:    vec![],
+      },
+      Command::AclGetUser { username: key() },
+      Command::AclDelUser {
+        usernames: vec![key()],
+      },
+      Command::AclList,
+      Command::AclWhoAmI,
+      Command::AclLog { count: None },
+      Command::AclLogReset,
+      Command::AclCat { category: None },
+      Command::LatencyLatest,
+      Command::LatencyReset { events: vec![] },
+      Command::Ping { message: None },
+      Command::Quit,
+      Command::Multi,
+      Command::Exec,
+      Command::Discard,
+      Command::ReadOnly,
+      Command::ReadWrite,
+      Command::Select { db: 0 },
+      Command::Help {
+        command: "MEMORY".into(),
+      },
+      Command::Help {
+        command: "OBJECT".into(),
+      },
+      Command::Help {
+        command: "DEBUG".into(),
+      },
+      Command::Help {
+        command: "ACL".into(),
+      },
+      Command::Help {
+        command: "CLIENT".into(),
+      },
+      Command::Help {
+        command: "LATENCY".into(),
+      },
+      Command::ClientInfo,
+      Command::ClientNoEvict { enabled: true },
+      Command::ClientNoTouch { enabled: true },
+      Command::ClientSetInfo {
+        attribute: ClientAttribute::LibName,
+        value:     SmolStr::default(),
+      },
+    ];
+    #[cfg(feature = "hashes")]
+    commands.extend([
+      Command::HashSet {
+        key:   key(),
+        field: key(),
+        value: value(),
+      },
+      Command::HashGet {
+        key:   key(),
+        field: key(),
+      },
+      Command::HashGetAll { key: key() },
+      Command::HashMultipleGet {
+        key:    key(),
+        fields: vec![key()],
+      },
+      Command::HashDelete {
+        key:    key(),
+        fields: vec![key()],
+      },
+      Command::HashExpire {
+        key:       key(),
+        seconds:   0,
+        condition: None,
+        fields:    vec![key()],
+      },
+      Command::HashPExpire {
+        key:          key(),
+        milliseconds: 0,
+        condition:    None,
+        fields:       vec![key()],
+      },
+      Command::HashPExpireAt {
+        key:          key(),
+        unix_time_ms: 0,
+        condition:    None,
+        fields:       vec![key()],
+      },
+      Command::HashTtl {
+        key:    key(),
+        fields: vec![key()],
+      },
+      Command::HashPTtl {
+        key:    key(),
+        fields: vec![key()],
+      },
+      Command::HashPersist {
+        key:    key(),
+        fields: vec![key()],
+      },
+    ]);
+    #[cfg(feature = "sets")]
+    commands.extend([
+      Command::SetAdd {
+        key:   key(),
+        value: value(),
+      },
+      Command::SetMembers { key: key() },
+      Command::SetCardinality { key: key() },
+      Command::SetIsMember {
+        key:   key(),
+        value: value(),
+      },
+      Command::SetDifference {
+        set_a: key(),
+        set_b: key(),
+      },
+      Command::SetDifferenceStore {
+        set_a:   key(),
+        set_b:   key(),
+        new_set: key(),
+      },
+      Command::SetRemove {
+        key:   key(),
+        value: value(),
+      },
+    ]);
+    #[cfg(feature = "lists")]
+    commands.extend([
+      Command::LeftPush {
+        key:   key(),
+        value: value(),
+      },
+      Command::RightPush {
+        key:   key(),
+        value: value(),
+      },
+      Command::ListRange {
+        key:   key(),
+        start: 0,
+        end:   -1,
+      },
+      Command::ListLength { key: key() },
+      Command::LeftPop { key: key() },
+      Command::RightPop { key: key() },
+    ]);
+    #[cfg(feature = "json")]
+    commands.extend([
+      Command::JsonSet {
+        key:  key(),
+        path: key(),
+        json: Default::default(),
+      },
+      Command::JsonGet {
+        key:  key(),
+        path: key(),
+      },
+      Command::JsonDelete {
+        key:  key(),
+        path: key(),
+      },
+      Command::JsonNumIncrBy {
+        key:  key(),
+        path: key(),
+        by:   crate::value::Value::Integer(1),
+      },
+    ]);
+    commands
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{AclCategories, CommandFlags};
+  use crate::{command::Command, value::Value};
+
+  fn frame(args: &[&str]) -> Value {
+    Value::Array(
+      args
+        .iter()
+        .map(|a| Value::BulkString(a.as_bytes().to_vec().into()))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn specs_match_parsed_frames() {
+    #[allow(unused_mut)]
+    let mut frames: Vec<&[&str]> = vec![
+      &["SET", "k", "v"],
+      &["SET", "k", "v", "GET"],
+      &["INCRBY", "k", "2"],
+      &["MGET", "a", "b", "c"],
+      &["MEMORY", "USAGE", "k"],
+      &["KEYS", "*"],
+      &["INFO"],
+    ];
+    #[cfg(feature = "hashes")]
+    frames.push(&["HMGET", "k", "f", "g"]);
+    #[cfg(feature = "sets")]
+    frames.push(&["SDIFFSTORE", "dest", "a", "b"]);
+
+    for args in frames {
+      let command = Command::parse(frame(args)).unwrap();
+      assert!(command.spec().accepts(args.len()), "{args:?}");
+
+      // the key positions point at exactly the command's keys
+      let keys = command
+        .key_positions()
+        .into_iter()
+        .map(|i| args[i])
+        .collect::<Vec<_>>();
+      assert_eq!(
+        keys,
+        command
+          .keys()
+          .iter()
+          .map(|k| k.as_str())
+          .collect::<Vec<_>>(),
+        "{args:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn flags_are_consistent() {
+    let set = Command::parse(frame(&["SET", "k", "v"])).unwrap();
+    assert!(set.is_write());
+    assert!(set.flags().contains(CommandFlags::DENYOOM));
+
+    let get = Command::parse(frame(&["GET", "k"])).unwrap();
+    assert!(!get.is_write());
+    assert_eq!(get.flags(), CommandFlags::READONLY);
+
+    let del = Command::parse(frame(&["DEL", "k"])).unwrap();
+    assert!(del.is_write());
+    assert!(!del.flags().contains(CommandFlags::DENYOOM));
+  }
+
+  #[test]
+  fn categories_follow_flags() {
+    for command in Command::builtins() {
+      let categories = command.acl_categories();
+      assert_eq!(
+        categories.contains(AclCategories::WRITE),
+        command.is_write(),
+        "{}",
+        command.full_name()
+      );
+      assert_eq!(
+        categories.contains(AclCategories::READ),
+        command.flags().contains(CommandFlags::READONLY),
+        "{}",
+        command.full_name()
+      );
+    }
+
+    let set = Command::parse(frame(&["SET", "k", "v"])).unwrap();
+    assert_eq!(
+      set.acl_categories(),
+      AclCategories::WRITE | AclCategories::STRING
+    );
+    let setuser = Command::parse(frame(&["ACL", "SETUSER", "u"])).unwrap();
+    assert!(setuser.acl_categories().contains(AclCategories::DANGEROUS));
+    assert_eq!(
+      AclCategories::from_category_name("Read"),
+      Some(AclCategories::READ)
+    );
+  }
+
+  #[test]
+  fn help_lists_each_subcommand_once() {
+    let help = Command::help("object");
+    assert_eq!(help, vec![
+      "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+      "ENCODING <key>",
+      "    Returns the internal encoding of a key's value.",
+      "HELP",
+      "    Prints this help.",
+    ]);
+
+    // `ACL LOG` and `ACL LOG RESET` are both listed under `LOG`
+    let help = Command::help("ACL");
+    assert_eq!(help.iter().filter(|l| l.starts_with("LOG ")).count(), 1);
+    assert!(help.contains(&"WHOAMI".to_owned()));
+    for command in Command::builtins() {
+      if command.subcommand_name().is_some() {
+        assert!(!command.docs().summary.is_empty(), "{command:?}");
+      }
+    }
+  }
+}
@@ -0,0 +1,175 @@
+//! Defines the `CommandTable`, which maps each built-in command's name (and
+//! subcommand name) to its [`CommandSpec`].
+//!
+//! The table is generated from [`Command::builtins()`], so a new command is
+//! registered by adding it there. [`Command::parse()`] looks commands up in
+//! it before parsing their arguments, so unknown commands and wrong arities
+//! are rejected the same way for every command, before anything runs.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use super::{Command, CommandSpec, ParseError};
+
+/// The built-in commands, generated once from [`Command::builtins()`].
+static TABLE: LazyLock<CommandTable> = LazyLock::new(CommandTable::generate);
+
+/// A top-level command in the table.
+enum Entry {
+  /// A command without subcommands.
+  Command(CommandSpec),
+  /// A container command, like `ACL`, with its subcommands by (uppercased)
+  /// name.
+  Container(HashMap<String, CommandSpec>),
+}
+
+/// The spec of every built-in command, by name.
+pub struct CommandTable {
+  entries: HashMap<String, Entry>,
+}
+
+impl CommandTable {
+  /// Returns the table of built-in commands.
+  pub fn get() -> &'static CommandTable { &TABLE }
+
+  fn generate() -> CommandTable {
+    let mut entries = HashMap::new();
+    for command in Command::builtins() {
+      let name = command.command_name().to_ascii_uppercase();
+      let spec = command.spec();
+      match command.subcommand_name() {
+        None => {
+          entries.insert(name, Entry::Command(spec));
+        }
+        Some(subcommand) => {
+          let entry = entries
+            .entry(name)
+            .or_insert_with(|| Entry::Container(HashMap::new()));
+          let Entry::Container(subcommands) = entry else {
+            unreachable!("`{}` has subcommands", command.command_name());
+          };
+          subcommands.insert(subcommand.to_owned(), spec);
+        }
+      }
+    }
+    CommandTable { entries }
+  }
+
+  /// Returns whether `name` (case-insensitive) is a built-in command.
+  pub fn contains(&self, name: &str) -> bool {
+    self.entries.contains_key(&name.to_ascii_uppercase())
+  }
+
+  /// Returns the spec of a built-in command, or of one of its subcommands.
+  /// Names are case-insensitive.
+  pub fn spec(
+    &self,
+    name: &str,
+    subcommand: Option<&str>,
+  ) -> Option<CommandSpec> {
+    match (self.entries.get(&name.to_ascii_uppercase())?, subcommand) {
+      (Entry::Command(spec), None) => Some(*spec),
+      (Entry::Container(subcommands), Some(subcommand)) => {
+        subcommands.get(&subcommand.to_ascii_uppercase()).copied()
+      }
+      _ => None,
+    }
+  }
+
+  /// Returns the name of the built-in command closest to `name`, if one is
+  /// close enough to be a likely typo.
+  pub fn suggest(&self, name: &str) -> Option<&str> {
+    let name = name.to_ascii_uppercase();
+    // allow one edit in short names, and two in longer ones
+    let max_distance = if name.len() <= 4 { 1 } else { 2 };
+    self
+      .entries
+      .keys()
+      .map(|candidate| (edit_distance(&name, candidate), candidate.as_str()))
+      .filter(|&(distance, _)| distance <= max_distance)
+      .min()
+      .map(|(_, candidate)| candidate)
+  }
+
+  /// Checks that `args` (including the command's name) name a built-in
+  /// command and have the right number of arguments for it.
+  pub(super) fn validate(
+    &self,
+    args: &[bytes::Bytes],
+  ) -> Result<(), ParseError> {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    let spec = match self.entries.get(&name) {
+      None => return Err(ParseError::unknown_command(&args[0], &args[1..])),
+      Some(Entry::Command(spec)) => spec,
+      Some(Entry::Container(subcommands)) => {
+        let Some(subcommand) = args.get(1) else {
+          return Err(ParseError::WrongArity(name.to_lowercase()));
+        };
+        let subcommand =
+          String::from_utf8_lossy(subcommand).to_ascii_uppercase();
+        subcommands.get(&subcommand).ok_or_else(|| {
+          ParseError::UnknownSubcommand {
+            command: name.to_lowercase(),
+            subcommand,
+          }
+        })?
+      }
+    };
+    if !spec.accepts(args.len()) {
+      return Err(ParseError::WrongArity(name.to_lowercase()));
+    }
+    Ok(())
+  }
+}
+
+/// Returns the Levenshtein distance between `a` and `b`, counting
+/// transpositions of adjacent characters as one edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let (a, b) = (a.as_bytes(), b.as_bytes());
+  // the rows of the distance matrix two before, one before, and current
+  let mut rows = [(); 3].map(|_| (0..=b.len()).collect::<Vec<_>>());
+  for i in 1..=a.len() {
+    rows.rotate_left(1);
+    let [before, last, current] = &mut rows;
+    current[0] = i;
+    for j in 1..=b.len() {
+      let substitution = usize::from(a[i - 1] != b[j - 1]);
+      current[j] = (last[j] + 1)
+        .min(current[j - 1] + 1)
+        .min(last[j - 1] + substitution);
+      if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+        current[j] = current[j].min(before[j - 2] + 1);
+      }
+    }
+  }
+  rows[2][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CommandTable;
+  use crate::command::Command;
+
+  #[test]
+  fn every_builtin_is_in_the_table() {
+    let table = CommandTable::get();
+    for command in Command::builtins() {
+      let spec = table.spec(command.command_name(), command.subcommand_name());
+      assert_eq!(spec, Some(command.spec()), "{command:?}");
+    }
+    assert!(table.contains("get"));
+    assert!(!table.contains("nope"));
+    assert_eq!(table.spec("ACL", None), None);
+    assert_eq!(table.spec("acl", Some("nope")), None);
+  }
+
+  #[test]
+  fn typos_suggest_the_closest_command() {
+    let table = CommandTable::get();
+    assert_eq!(table.suggest("gte"), Some("GET"));
+    assert_eq!(table.suggest("SETT"), Some("SET"));
+    assert_eq!(table.suggest("incrbyy"), Some("INCRBY"));
+    assert_eq!(table.suggest("xyzzy"), None);
+    assert_eq!(super::edit_distance("", "ab"), 2);
+    assert_eq!(super::edit_distance("kitten", "sitting"), 3);
+  }
+}
@@ -1,7 +1,19 @@
 //! Application-wide configuration.
-use std::borrow::Cow;
+use std::{
+  borrow::Cow,
+  env::VarError,
+  num::NonZeroUsize,
+  path::{Path, PathBuf},
+  time::Duration,
+};
 
-use color_eyre::eyre::{Result, WrapErr};
+use color_eyre::eyre::{bail, Result, WrapErr};
+
+use crate::{
+  backends::{BackendConfig, BackendKind},
+  resp::{RequestLimits, MAX_ARRAY_LEN, MAX_BULK_LEN, MAX_REQUEST_LEN},
+  server::TraceSink,
+};
 
 /// Application-wide configuration.
 ///
@@ -10,9 +22,112 @@ use color_eyre::eyre::{Result, WrapErr};
 ///   connections. Taken from env var `LISTEN_PORT`, defaults to `6379`.
 /// - `listen_host`: the host descriptor the application will listen on for TCP
 ///   connections. Taken from env var `LISTEN_HOST`, defaults to `0.0.0.0`.
+/// - `io_uring`: whether TCP connections are read and written through io_uring
+///   rather than the tokio reactor. Taken from env var `IO_URING` (`yes` or
+///   `no`), defaults to `no`. Needs Linux and the `io-uring` cargo feature.
+/// - `health_port`: the port HTTP health probes (`/livez` and `/readyz`) are
+///   served on, on `listen_host`. Taken from env var `HEALTH_PORT`; unset or
+///   `0` disables them.
+/// - `active_defrag`: whether to periodically shrink over-allocated storage.
+///   Taken from env var `ACTIVE_DEFRAG` (`yes` or `no`), defaults to `no`.
+/// - `active_defrag_interval`: how often to run active defragmentation. Taken
+///   from env var `ACTIVE_DEFRAG_INTERVAL_MS`, defaults to `1000`, and must not
+///   be `0`.
+/// - `compression_threshold`: bulk strings at least this many bytes long are
+///   compressed at rest. Taken from env var `COMPRESSION_THRESHOLD`; unset or
+///   `0` disables compression.
+/// - `normalize_values`: whether values are stored normalized, so that e.g. a
+///   string holding an integer is stored as one however it was sent (see
+///   [`BackendConfig::normalize_values`](crate::backends::BackendConfig::normalize_values)).
+///   Taken from env var `NORMALIZE_VALUES` (`yes` or `no`), defaults to `no`.
+/// - `max_memory`: how many bytes of data the backend may hold before writes
+///   which could add more are rejected with an `OOM` error. Taken from env var
+///   `MAXMEMORY`; unset or `0` removes the limit.
+/// - `requirepass`: the password connections must `AUTH` with before running
+///   other commands. Taken from env var `REQUIREPASS`; unset or empty disables
+///   authentication.
+/// - `rename_commands`: commands to rename or disable, as comma-separated
+///   `NAME=NEW_NAME` pairs (e.g. `CONFIG=MYCONFIG,FLUSHALL=`). An empty new
+///   name disables the command. Taken from env var `RENAME_COMMANDS`, defaults
+///   to none.
+/// - `command_timeout`: how long a single command may run before it's cancelled
+///   with a `BUSY` error. Taken from env var `COMMAND_TIMEOUT_MS`; unset or `0`
+///   disables the timeout.
+/// - `workers`: runs commands on a pool of this many worker tasks, rather than
+///   in each connection's task. Taken from env var `WORKERS`; unset or `0` runs
+///   them inline.
+/// - `max_clients`: how many connections may be open at once; new ones are
+///   rejected with an error beyond that. Taken from env var `MAXCLIENTS`,
+///   defaults to `10000`; `0` removes the limit.
+/// - `idle_timeout`: how long a connection may go without sending a request
+///   before it's closed. Connections subscribed to channels are never closed
+///   for idling. Taken from env var `IDLE_TIMEOUT_MS`; unset or `0` lets them
+///   idle forever.
+/// - `read_timeout`: how long a connection may take to finish sending a request
+///   once it's started, before it's closed. Taken from env var
+///   `READ_TIMEOUT_MS`, defaults to `30000`; `0` disables the timeout.
+/// - `output_timeout`: how long a connection's replies may wait for it to read
+///   them before it's closed as a slow consumer. Taken from env var
+///   `OUTPUT_TIMEOUT_MS`; unset or `0` lets them wait forever.
+/// - `pubsub_output_timeout`: the same, for connections subscribed to channels.
+///   Taken from env var `PUBSUB_OUTPUT_TIMEOUT_MS`, defaults to `60000`; `0`
+///   lets them wait forever.
+/// - `request_limits`: bounds on each request, beyond which the connection is
+///   sent a protocol error and closed (see
+///   [`RequestLimits`](crate::resp::RequestLimits)). The most bytes a request
+///   may take is taken from env var `CLIENT_QUERY_BUFFER_LIMIT`, defaults to 1
+///   GiB; the longest bulk string from `PROTO_MAX_BULK_LEN`, defaults to 512
+///   MiB; and the most arguments from `PROTO_MAX_ARGS`, defaults to
+///   `2147483647`.
+/// - `protocol_trace`: records the bytes every connection sends and receives,
+///   for debugging protocol issues (see
+///   [`ProtocolTrace`](crate::server::ProtocolTrace)). Taken from env var
+///   `PROTOCOL_TRACE`: `log` logs them, and any other value is a directory to
+///   write a trace file per connection to. Unset or empty disables tracing.
+/// - `replica`: whether this node is a replica, whose data only changes when
+///   its master replicates a write. Replicas hide expired data from reads
+///   rather than deleting it themselves, and only serve reads to connections
+///   which send `READONLY`. Taken from env var `REPLICA` (`yes` or `no`),
+///   defaults to `no`.
+/// - `drain_timeout`: how long shutdown waits for open connections to finish
+///   their commands before closing them. Taken from env var `DRAIN_TIMEOUT_MS`,
+///   defaults to `10000`.
+/// - `dir`: the data directory, where snapshots, append-only file segments, and
+///   the cluster configuration are kept (see
+///   [`DataDir`](crate::data_dir::DataDir)). It's created if it doesn't exist,
+///   and locked so that no other instance can use it. Taken from env var `DIR`;
+///   unset or empty runs without one.
+/// - `databases`: how many numbered databases there are, which connections
+///   switch between with `SELECT`. Taken from env var `DATABASES`, defaults to
+///   `16`.
+/// - `backend`: the storage engine to use. Taken from env var `BACKEND`,
+///   defaults to `simple`. The engine must be enabled as a cargo feature.
 pub struct Config {
-  listen_port: usize,
-  listen_host: Cow<'static, str>,
+  listen_port:            usize,
+  listen_host:            Cow<'static, str>,
+  io_uring:               bool,
+  health_port:            Option<usize>,
+  active_defrag:          bool,
+  active_defrag_interval: Duration,
+  compression_threshold:  Option<usize>,
+  normalize_values:       bool,
+  max_memory:             Option<u64>,
+  requirepass:            Option<String>,
+  rename_commands:        Vec<(String, String)>,
+  command_timeout:        Option<Duration>,
+  workers:                Option<usize>,
+  max_clients:            Option<usize>,
+  idle_timeout:           Option<Duration>,
+  read_timeout:           Option<Duration>,
+  output_timeout:         Option<Duration>,
+  pubsub_output_timeout:  Option<Duration>,
+  request_limits:         RequestLimits,
+  protocol_trace:         Option<TraceSink>,
+  replica:                bool,
+  drain_timeout:          Duration,
+  dir:                    Option<PathBuf>,
+  databases:              NonZeroUsize,
+  backend:                BackendKind,
 }
 
 impl Config {
@@ -21,23 +136,340 @@ impl Config {
   /// Returns the host descriptor the application will listen on for TCP
   /// connections.
   pub fn listen_host(&self) -> Cow<'static, str> { self.listen_host.clone() }
+  /// Returns whether TCP connections are read and written through io_uring.
+  pub fn io_uring(&self) -> bool { self.io_uring }
+  /// Returns the port health probes are served on, if they are.
+  pub fn health_port(&self) -> Option<usize> { self.health_port }
+  /// Returns whether active defragmentation is enabled.
+  pub fn active_defrag(&self) -> bool { self.active_defrag }
+  /// Returns how often active defragmentation runs.
+  pub fn active_defrag_interval(&self) -> Duration {
+    self.active_defrag_interval
+  }
+  /// Returns the size above which bulk strings are compressed at rest, if
+  /// compression is enabled.
+  pub fn compression_threshold(&self) -> Option<usize> {
+    self.compression_threshold
+  }
+  /// Returns whether values are stored normalized.
+  pub fn normalize_values(&self) -> bool { self.normalize_values }
+  /// Returns how many bytes of data the backend may hold, if it's limited.
+  pub fn max_memory(&self) -> Option<u64> { self.max_memory }
+  /// Returns the password connections must authenticate with, if any.
+  pub fn requirepass(&self) -> Option<&str> { self.requirepass.as_deref() }
+  /// Returns the commands to rename, as `(name, new_name)` pairs. An empty
+  /// new name disables the command.
+  pub fn rename_commands(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .rename_commands
+      .iter()
+      .map(|(name, new_name)| (name.as_str(), new_name.as_str()))
+  }
+  /// Returns how long a single command may run, if there's a limit.
+  pub fn command_timeout(&self) -> Option<Duration> { self.command_timeout }
+  /// Returns how many workers commands run on, if they run on a worker
+  /// pool.
+  pub fn workers(&self) -> Option<usize> { self.workers }
+  /// Returns how many connections may be open at once, if it's limited.
+  pub fn max_clients(&self) -> Option<usize> { self.max_clients }
+  /// Returns how long a connection may idle, if there's a limit.
+  pub fn idle_timeout(&self) -> Option<Duration> { self.idle_timeout }
+  /// Returns how long a connection may take to send a request, if there's a
+  /// limit.
+  pub fn read_timeout(&self) -> Option<Duration> { self.read_timeout }
+  /// Returns how long a connection's replies may wait to be read, if there's
+  /// a limit.
+  pub fn output_timeout(&self) -> Option<Duration> { self.output_timeout }
+  /// Returns how long the replies of a connection subscribed to channels may
+  /// wait to be read, if there's a limit.
+  pub fn pubsub_output_timeout(&self) -> Option<Duration> {
+    self.pubsub_output_timeout
+  }
+  /// Returns the bounds on each request.
+  pub fn request_limits(&self) -> RequestLimits { self.request_limits }
+  /// Returns where connections' protocol traces are written, if tracing is
+  /// enabled.
+  pub fn protocol_trace(&self) -> Option<&TraceSink> {
+    self.protocol_trace.as_ref()
+  }
+  /// Returns whether this node is a replica.
+  pub fn replica(&self) -> bool { self.replica }
+  /// Returns how long shutdown waits for open connections to finish.
+  pub fn drain_timeout(&self) -> Duration { self.drain_timeout }
+  /// Returns the data directory, if there is one.
+  pub fn dir(&self) -> Option<&Path> { self.dir.as_deref() }
+  /// Returns how many numbered databases there are.
+  pub fn databases(&self) -> NonZeroUsize { self.databases }
+  /// Returns the storage engine to use.
+  pub fn backend(&self) -> BackendKind { self.backend }
+  /// Returns the storage settings to build the backend with. `data_dir` is
+  /// left unset, since it's only known once the server starts.
+  pub fn backend_config(&self) -> BackendConfig {
+    BackendConfig {
+      compression_threshold: self.compression_threshold,
+      normalize_values: self.normalize_values,
+      replica: self.replica,
+      databases: Some(self.databases),
+      max_memory: self.max_memory,
+      ..Default::default()
+    }
+  }
+}
+
+/// Parses a Redis-style boolean setting (`yes`/`no`, or `true`/`false`).
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+  match value.to_ascii_lowercase().as_str() {
+    "yes" | "true" | "1" => Ok(true),
+    "no" | "false" | "0" => Ok(false),
+    _ => bail!("failed to parse `{name}` from env var: expected `yes` or `no`"),
+  }
+}
+
+/// Parses a list of command renames, as comma-separated `NAME=NEW_NAME` pairs.
+fn parse_renames(name: &str, value: &str) -> Result<Vec<(String, String)>> {
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| match pair.split_once('=') {
+      Some((command, new_name)) if !command.trim().is_empty() => {
+        Ok((command.trim().to_owned(), new_name.trim().to_owned()))
+      }
+      _ => bail!(
+        "failed to parse `{name}` from env var: expected `NAME=NEW_NAME`, got \
+         `{pair}`"
+      ),
+    })
+    .collect()
 }
 
 impl Config {
   /// Builds the config from environment variables.
   ///
-  /// This function will only fail if `LISTEN_PORT` cannot be parse to a
-  /// `usize`.
+  /// This function will fail if `LISTEN_PORT` cannot be parsed to a `usize`,
+  /// or if any other setting is malformed.
   pub fn from_env() -> Result<Config> {
+    Config::from_vars(|name| std::env::var(name))
+  }
+
+  /// Builds the config from the variables `var` looks up, as
+  /// [`from_env()`](Config::from_env) does from the environment.
+  fn from_vars(
+    var: impl Fn(&str) -> Result<String, VarError>,
+  ) -> Result<Config> {
     let config = Config {
-      listen_port: std::env::var("LISTEN_PORT")
+      listen_port:            var("LISTEN_PORT")
         .unwrap_or("6379".to_string())
         .parse()
         .wrap_err("failed to parse `LISTEN_PORT` from env var")?,
-      listen_host: std::env::var("LISTEN_HOST")
+      listen_host:            var("LISTEN_HOST")
         .unwrap_or("0.0.0.0".to_string())
         .into(),
+      io_uring:               parse_bool(
+        "IO_URING",
+        &var("IO_URING").unwrap_or("no".to_string()),
+      )?,
+      health_port:            Some(
+        var("HEALTH_PORT")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `HEALTH_PORT` from env var")?,
+      )
+      .filter(|&port| port > 0),
+      active_defrag:          parse_bool(
+        "ACTIVE_DEFRAG",
+        &var("ACTIVE_DEFRAG").unwrap_or("no".to_string()),
+      )?,
+      active_defrag_interval: Duration::from_millis(
+        var("ACTIVE_DEFRAG_INTERVAL_MS")
+          .unwrap_or("1000".to_string())
+          .parse()
+          .wrap_err(
+            "failed to parse `ACTIVE_DEFRAG_INTERVAL_MS` from env var",
+          )?,
+      ),
+      compression_threshold:  Some(
+        var("COMPRESSION_THRESHOLD")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `COMPRESSION_THRESHOLD` from env var")?,
+      )
+      .filter(|&threshold| threshold > 0),
+      normalize_values:       parse_bool(
+        "NORMALIZE_VALUES",
+        &var("NORMALIZE_VALUES").unwrap_or("no".to_string()),
+      )?,
+      max_memory:             Some(
+        var("MAXMEMORY")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `MAXMEMORY` from env var")?,
+      )
+      .filter(|&max_memory| max_memory > 0),
+      requirepass:            var("REQUIREPASS")
+        .ok()
+        .filter(|password| !password.is_empty()),
+      rename_commands:        parse_renames(
+        "RENAME_COMMANDS",
+        &var("RENAME_COMMANDS").unwrap_or_default(),
+      )?,
+      command_timeout:        Some(
+        var("COMMAND_TIMEOUT_MS")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `COMMAND_TIMEOUT_MS` from env var")?,
+      )
+      .filter(|&ms| ms > 0)
+      .map(Duration::from_millis),
+      workers:                Some(
+        var("WORKERS")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `WORKERS` from env var")?,
+      )
+      .filter(|&workers| workers > 0),
+      max_clients:            Some(
+        var("MAXCLIENTS")
+          .unwrap_or("10000".to_string())
+          .parse()
+          .wrap_err("failed to parse `MAXCLIENTS` from env var")?,
+      )
+      .filter(|&max_clients| max_clients > 0),
+      idle_timeout:           Some(
+        var("IDLE_TIMEOUT_MS")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `IDLE_TIMEOUT_MS` from env var")?,
+      )
+      .filter(|&ms| ms > 0)
+      .map(Duration::from_millis),
+      read_timeout:           Some(
+        var("READ_TIMEOUT_MS")
+          .unwrap_or("30000".to_string())
+          .parse()
+          .wrap_err("failed to parse `READ_TIMEOUT_MS` from env var")?,
+      )
+      .filter(|&ms| ms > 0)
+      .map(Duration::from_millis),
+      output_timeout:         Some(
+        var("OUTPUT_TIMEOUT_MS")
+          .unwrap_or("0".to_string())
+          .parse()
+          .wrap_err("failed to parse `OUTPUT_TIMEOUT_MS` from env var")?,
+      )
+      .filter(|&ms| ms > 0)
+      .map(Duration::from_millis),
+      pubsub_output_timeout:  Some(
+        var("PUBSUB_OUTPUT_TIMEOUT_MS")
+          .unwrap_or("60000".to_string())
+          .parse()
+          .wrap_err(
+            "failed to parse `PUBSUB_OUTPUT_TIMEOUT_MS` from env var",
+          )?,
+      )
+      .filter(|&ms| ms > 0)
+      .map(Duration::from_millis),
+      request_limits:         RequestLimits {
+        max_request_len: var("CLIENT_QUERY_BUFFER_LIMIT")
+          .map_or(Ok(MAX_REQUEST_LEN), |limit| limit.parse())
+          .wrap_err(
+            "failed to parse `CLIENT_QUERY_BUFFER_LIMIT` from env var",
+          )?,
+        max_bulk_len:    var("PROTO_MAX_BULK_LEN")
+          .map_or(Ok(MAX_BULK_LEN), |limit| limit.parse())
+          .wrap_err("failed to parse `PROTO_MAX_BULK_LEN` from env var")?,
+        max_args:        var("PROTO_MAX_ARGS")
+          .map_or(Ok(MAX_ARRAY_LEN), |limit| limit.parse())
+          .wrap_err("failed to parse `PROTO_MAX_ARGS` from env var")?,
+      },
+      protocol_trace:         var("PROTOCOL_TRACE")
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| match value.as_str() {
+          "log" => TraceSink::Log,
+          _ => TraceSink::Directory(value.into()),
+        }),
+      replica:                parse_bool(
+        "REPLICA",
+        &var("REPLICA").unwrap_or("no".to_string()),
+      )?,
+      drain_timeout:          Duration::from_millis(
+        var("DRAIN_TIMEOUT_MS")
+          .unwrap_or("10000".to_string())
+          .parse()
+          .wrap_err("failed to parse `DRAIN_TIMEOUT_MS` from env var")?,
+      ),
+      dir:                    var("DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from),
+      databases:              var("DATABASES")
+        .unwrap_or("16".to_string())
+        .parse()
+        .wrap_err("failed to parse `DATABASES` from env var")?,
+      backend:                var("BACKEND")
+        .unwrap_or("simple".to_string())
+        .parse()
+        .wrap_err("failed to parse `BACKEND` from env var")?,
     };
+    if config.active_defrag_interval.is_zero() {
+      bail!("`ACTIVE_DEFRAG_INTERVAL_MS` must be greater than 0");
+    }
+    if config.io_uring && !cfg!(all(feature = "io-uring", target_os = "linux"))
+    {
+      bail!(
+        "`IO_URING` is enabled, but io_uring is not compiled in; rebuild on \
+         Linux with the `io-uring` feature"
+      );
+    }
     Ok(config)
   }
 }
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::{env::VarError, time::Duration};
+
+  use super::Config;
+
+  /// Builds the config from `vars` alone, rather than the environment.
+  fn from_vars(vars: &[(&str, &str)]) -> color_eyre::eyre::Result<Config> {
+    Config::from_vars(|name| {
+      vars
+        .iter()
+        .find(|(var, _)| *var == name)
+        .map(|(_, value)| value.to_string())
+        .ok_or(VarError::NotPresent)
+    })
+  }
+
+  #[test]
+  fn maxmemory_is_passed_to_the_backend() {
+    let config = from_vars(&[("MAXMEMORY", "1048576")]).unwrap();
+    assert_eq!(config.max_memory(), Some(1048576));
+    assert_eq!(config.backend_config().max_memory, Some(1048576));
+
+    // unset or `0` is unlimited
+    assert_eq!(from_vars(&[]).unwrap().max_memory(), None);
+    assert_eq!(from_vars(&[("MAXMEMORY", "0")]).unwrap().max_memory(), None);
+    assert!(from_vars(&[("MAXMEMORY", "lots")]).is_err());
+  }
+
+  #[test]
+  fn active_defrag_interval_must_not_be_zero() {
+    let config = from_vars(&[("ACTIVE_DEFRAG_INTERVAL_MS", "250")]).unwrap();
+    assert_eq!(config.active_defrag_interval(), Duration::from_millis(250));
+    assert_eq!(
+      from_vars(&[]).unwrap().active_defrag_interval(),
+      Duration::from_secs(1)
+    );
+
+    let Err(err) = from_vars(&[("ACTIVE_DEFRAG_INTERVAL_MS", "0")]) else {
+      panic!("a zero interval was accepted");
+    };
+    assert!(
+      err.to_string().contains("ACTIVE_DEFRAG_INTERVAL_MS"),
+      "{err}"
+    );
+  }
+}
@@ -0,0 +1,181 @@
+//! Defines the `DataDir` item, the directory the server keeps its files in.
+//!
+//! Everything the server writes to disk for itself lives in the data
+//! directory, in a fixed layout:
+//!
+//! - `dump.kdb`: the latest full snapshot of database 0.
+//! - `deltas/`: the delta snapshots written on top of it.
+//! - `dump-<n>.kdb` and `deltas-<n>/`: the same for database `n`, for the
+//!   databases after the first.
+//! - `appendonly/`: the append-only file's segments.
+//! - `nodes.conf`: the cluster configuration.
+//! - `kraglin.lock`: locked for as long as a server uses the directory, so that
+//!   two servers can't write the same files. It holds the process ID of the
+//!   server using it.
+
+use std::{
+  fs::{File, OpenOptions, TryLockError},
+  io::{self, Write},
+  path::{Path, PathBuf},
+};
+
+/// The name of the lock file in the data directory.
+pub const LOCK_FILE: &str = "kraglin.lock";
+/// The name of the snapshot file in the data directory.
+pub const SNAPSHOT_FILE: &str = "dump.kdb";
+/// The name of the directory of delta snapshots in the data directory.
+pub const SNAPSHOT_DELTAS_DIR: &str = "deltas";
+/// The name of the directory of append-only file segments in the data
+/// directory.
+pub const AOF_DIR: &str = "appendonly";
+/// The name of the cluster configuration file in the data directory.
+pub const CLUSTER_CONFIG_FILE: &str = "nodes.conf";
+
+/// Returns the name of the snapshot file of database `db` in the data
+/// directory, which is [`SNAPSHOT_FILE`] for database 0.
+pub fn database_snapshot_file(db: usize) -> String {
+  match db {
+    0 => SNAPSHOT_FILE.to_owned(),
+    _ => format!("dump-{db}.kdb"),
+  }
+}
+
+/// Returns the name of the directory of database `db`'s delta snapshots in
+/// the data directory, which is [`SNAPSHOT_DELTAS_DIR`] for database 0.
+pub fn database_deltas_dir(db: usize) -> String {
+  match db {
+    0 => SNAPSHOT_DELTAS_DIR.to_owned(),
+    _ => format!("{SNAPSHOT_DELTAS_DIR}-{db}"),
+  }
+}
+
+/// An error opening a data directory.
+#[derive(Debug, thiserror::Error)]
+pub enum DataDirError {
+  /// The path exists, but isn't a directory.
+  #[error("data directory {} is not a directory", .0.display())]
+  NotADirectory(PathBuf),
+  /// The directory couldn't be created, or files can't be written to it.
+  #[error("data directory {} is not writable: {1}", .0.display())]
+  NotWritable(PathBuf, #[source] io::Error),
+  /// Another server (or another `DataDir` in this one) is using the
+  /// directory.
+  #[error(
+    "data directory {} is locked by another kraglin instance{}",
+    .path.display(),
+    .pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default()
+  )]
+  Locked {
+    /// The directory's path.
+    path: PathBuf,
+    /// The process ID of the server using it, if it could be read.
+    pid:  Option<u32>,
+  },
+}
+
+/// An open data directory. The directory is locked against other servers
+/// until this is dropped.
+#[derive(Debug)]
+pub struct DataDir {
+  path:  PathBuf,
+  /// The locked lock file, unlocked when it's closed.
+  _lock: File,
+}
+
+impl DataDir {
+  /// Opens the data directory at `path`, creating it if it doesn't exist,
+  /// and locks it.
+  ///
+  /// Fails if `path` isn't a writable directory, or if another server has it
+  /// locked. The lock is advisory, and held by the open lock file, so it's
+  /// released even if the server crashes.
+  pub fn open(path: impl Into<PathBuf>) -> Result<DataDir, DataDirError> {
+    let path = path.into();
+    if path.exists() && !path.is_dir() {
+      return Err(DataDirError::NotADirectory(path));
+    }
+    std::fs::create_dir_all(&path)
+      .map_err(|e| DataDirError::NotWritable(path.clone(), e))?;
+
+    // the file mustn't be truncated before it's locked, or the holder's
+    // process ID would be lost
+    let lock_path = path.join(LOCK_FILE);
+    let mut lock = OpenOptions::new()
+      .create(true)
+      .truncate(false)
+      .write(true)
+      .open(&lock_path)
+      .map_err(|e| DataDirError::NotWritable(path.clone(), e))?;
+    match lock.try_lock() {
+      Ok(()) => {}
+      Err(TryLockError::WouldBlock) => {
+        let pid = std::fs::read_to_string(&lock_path)
+          .ok()
+          .and_then(|pid| pid.trim().parse().ok());
+        return Err(DataDirError::Locked { path, pid });
+      }
+      Err(TryLockError::Error(e)) => {
+        return Err(DataDirError::NotWritable(path, e))
+      }
+    }
+    lock
+      .set_len(0)
+      .and_then(|()| writeln!(lock, "{}", std::process::id()))
+      .map_err(|e| DataDirError::NotWritable(path.clone(), e))?;
+
+    Ok(DataDir { path, _lock: lock })
+  }
+
+  /// Returns the directory's path.
+  pub fn path(&self) -> &Path { &self.path }
+
+  /// Returns the path of the snapshot file.
+  pub fn snapshot_path(&self) -> PathBuf { self.path.join(SNAPSHOT_FILE) }
+
+  /// Returns the path of the directory of delta snapshots.
+  pub fn snapshot_deltas_dir(&self) -> PathBuf {
+    self.path.join(SNAPSHOT_DELTAS_DIR)
+  }
+
+  /// Returns the path of the directory of append-only file segments.
+  pub fn aof_dir(&self) -> PathBuf { self.path.join(AOF_DIR) }
+
+  /// Returns the path of the cluster configuration file.
+  pub fn cluster_config_path(&self) -> PathBuf {
+    self.path.join(CLUSTER_CONFIG_FILE)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{DataDir, DataDirError, LOCK_FILE};
+
+  #[test]
+  fn data_dirs_are_created_and_locked() {
+    let path = std::env::temp_dir()
+      .join(format!("kraglin-data-dir-test-{}", std::process::id()))
+      .join("data");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let dir = DataDir::open(&path).unwrap();
+    assert!(path.join(LOCK_FILE).is_file());
+    assert_eq!(dir.snapshot_path(), path.join("dump.kdb"));
+
+    // a second server can't use the directory until the first is done
+    let pid = std::process::id();
+    assert!(matches!(
+      DataDir::open(&path),
+      Err(DataDirError::Locked { pid: Some(p), .. }) if p == pid
+    ));
+    drop(dir);
+    let dir = DataDir::open(&path).unwrap();
+
+    // files aren't directories
+    assert!(matches!(
+      DataDir::open(path.join(LOCK_FILE)),
+      Err(DataDirError::NotADirectory(_))
+    ));
+    drop(dir);
+    std::fs::remove_dir_all(&path).unwrap();
+  }
+}
@@ -0,0 +1,217 @@
+#![deny(missing_docs)]
+#![doc = include_str!("../README.md")]
+
+pub mod aof;
+pub mod backends;
+pub mod buffer_pool;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod crc64;
+pub mod data_dir;
+pub mod resp;
+pub mod server;
+pub mod snapshot;
+pub mod value;
+pub mod version;
+
+/// The conglomerate error type for all [`kraglin`](crate) commands.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum KraglinError {
+  /// This value is the wrong type.
+  #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+  WrongType,
+  /// This string type could not be parsed as an integer.
+  #[error("This string type could not be parsed as an integer.")]
+  CannotParseAsInteger,
+  /// This value is out of range.
+  #[error("This value is out of range")]
+  OutOfRange,
+  /// The command needs the key to exist, but it doesn't.
+  #[error("no such key")]
+  NoSuchKey,
+  /// An expiration command was given a negative time to live, or one too
+  /// large to represent as a deadline.
+  #[error("invalid expire time in '{0}' command")]
+  InvalidExpireTime(String),
+  /// `RESTORE` was given a negative time to live.
+  #[error("Invalid TTL value, must be >= 0")]
+  InvalidTtl,
+  /// `RESTORE` would overwrite an existing key without `REPLACE`.
+  #[error("BUSYKEY Target key name already exists.")]
+  BusyKey,
+  /// A `DUMP` payload's checksum doesn't match its contents, or it was
+  /// written by a newer format version.
+  #[error("ERR DUMP payload version or checksum are wrong")]
+  BadDumpPayload,
+  /// A `DUMP` payload's checksum matches, but its contents don't parse.
+  #[error("Bad data format")]
+  BadDumpFormat,
+  /// `BGSAVE` was sent while a snapshot is already being saved.
+  #[error("Background save already in progress")]
+  BackgroundSaveInProgress,
+  /// A snapshot was requested, but there's no data directory to save it to.
+  #[error("no data directory is configured, so snapshots can't be saved")]
+  NoDataDir,
+  /// A write was rejected because memory usage is over the configured
+  /// maximum.
+  #[error("OOM command not allowed when used memory > 'maxmemory'.")]
+  OutOfMemory,
+  /// A JSON document could not be parsed.
+  #[error("Invalid JSON: {0}")]
+  InvalidJson(String),
+  /// A JSON path could not be parsed.
+  #[error("Invalid JSON path: {0}")]
+  InvalidJsonPath(String),
+  /// A JSON path does not exist in the document.
+  #[error("ERR JSON path does not exist.")]
+  JsonPathNotFound,
+  /// The value at a JSON path is the wrong type for the operation.
+  #[error("Expected JSON {expected} but found {found}.")]
+  JsonWrongType {
+    /// The JSON type the operation needs.
+    expected: &'static str,
+    /// The JSON type found at the path.
+    found:    &'static str,
+  },
+  /// An argument isn't an integer, or doesn't fit in a 64-bit integer.
+  #[error("value is not an integer or out of range")]
+  NotAnInteger,
+  /// An argument isn't a valid floating point number.
+  #[error("value is not a valid float")]
+  NotAFloat,
+  /// A timeout argument isn't a number of seconds, or is too long.
+  #[error("timeout is not a float or out of range")]
+  InvalidTimeout,
+  /// A timeout argument is negative.
+  #[error("timeout is negative")]
+  NegativeTimeout,
+  /// An argument isn't one of the keywords accepted in its position.
+  #[error("syntax error")]
+  SyntaxError,
+  /// A `SCAN` cursor isn't an unsigned 64-bit integer.
+  #[error("invalid cursor")]
+  InvalidCursor,
+  /// A request couldn't be parsed as a command, e.g. because the command is
+  /// unknown or has the wrong number of arguments.
+  #[error(transparent)]
+  InvalidCommand(command::ParseError),
+  /// The command isn't supported here, e.g. a custom command sent straight
+  /// to a backend.
+  #[error("unknown command '{0}'")]
+  UnknownCommand(String),
+  /// The command exists, but the backend doesn't implement it, e.g. the list
+  /// commands on a backend which doesn't store lists.
+  #[error("ERR '{0}' is not supported by this backend")]
+  NotSupported(String),
+  /// The connection must authenticate before running this command.
+  #[error("NOAUTH Authentication required.")]
+  NoAuth,
+  /// `AUTH` was given the wrong username or password.
+  #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+  WrongPass,
+  /// `AUTH` was sent but no password is configured.
+  #[error(
+    "ERR AUTH <password> called without any password configured for the \
+     default user. Are you sure your configuration is correct?"
+  )]
+  AuthNotConfigured,
+  /// `HELLO` asked for a RESP version the server doesn't speak.
+  #[error("NOPROTO unsupported protocol version")]
+  NoProto,
+  /// The connection's user isn't allowed to run this command or access one
+  /// of its keys.
+  #[error("NOPERM {0}")]
+  NoPermission(String),
+  /// An `ACL SETUSER` rule is malformed.
+  #[error("Error in ACL SETUSER modifier '{0}': Syntax error")]
+  InvalidAclRule(String),
+  /// `ACL CAT` was given a category which doesn't exist.
+  #[error("Unknown category '{0}'")]
+  UnknownAclCategory(String),
+  /// `ACL DELUSER` tried to delete the default user.
+  #[error("The 'default' user cannot be removed")]
+  DeleteDefaultUser,
+  /// The connection is subscribed to channels under RESP2, where only the
+  /// subscription commands, `PING`, `QUIT`, and `RESET` may run.
+  #[error(
+    "Can't execute '{0}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / \
+     QUIT / RESET are allowed in this context"
+  )]
+  SubscribedContext(String),
+  /// `MULTI` was sent inside a transaction.
+  #[error("MULTI calls can not be nested")]
+  NestedMulti,
+  /// `EXEC` was sent outside a transaction.
+  #[error("EXEC without MULTI")]
+  ExecWithoutMulti,
+  /// `DISCARD` was sent outside a transaction.
+  #[error("DISCARD without MULTI")]
+  DiscardWithoutMulti,
+  /// A command queued in the transaction was rejected, so `EXEC` discarded
+  /// it.
+  #[error("EXECABORT Transaction discarded because of previous errors.")]
+  ExecAbort,
+  /// A connection was rejected because the server already has as many open
+  /// as it allows.
+  #[error("max number of clients reached")]
+  MaxClients,
+  /// A connection which sent `READONLY` tried to write to a replica.
+  #[error("READONLY You can't write against a read only replica.")]
+  ReadOnlyReplica,
+  /// A connection tried to read from a replica without sending `READONLY`
+  /// first.
+  #[error(
+    "reads from a replica may be stale, so the connection must send READONLY \
+     first"
+  )]
+  ReplicaReadWithoutReadOnly,
+  /// The command ran for longer than the server's command timeout, and was
+  /// cancelled.
+  #[error(
+    "BUSY command '{command}' exceeded the maximum execution time of {} ms",
+    .timeout.as_millis()
+  )]
+  CommandTimeout {
+    /// The full name of the command.
+    command: String,
+    /// The timeout it exceeded.
+    timeout: std::time::Duration,
+  },
+  /// The worker running the command failed before replying.
+  #[error("ERR command execution failed")]
+  ExecutionFailed,
+  /// `MIGRATE` couldn't reach the target instance, or it didn't reply in
+  /// time.
+  #[error("IOERR error or timeout {0} the target instance")]
+  MigrateIo(&'static str),
+  /// The target instance of `MIGRATE` replied with an error.
+  #[error("Target instance replied with error: {0}")]
+  MigrateTarget(String),
+  /// `SELECT` was given the index of a database which doesn't exist.
+  #[error("ERR DB index is out of range")]
+  DbIndexOutOfRange,
+  /// A write was applied locally but could not be replicated.
+  #[error("Failed to replicate write: {0}")]
+  ReplicationFailed(String),
+}
+
+/// Alias for `Result<Value, KraglinError>`
+pub type KraglinResult = Result<value::Value, KraglinError>;
+
+/// Sets up tracing and logging.
+pub fn setup_tracing() {
+  use tracing_error::ErrorLayer;
+  use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+  let fmt_layer = fmt::layer().with_thread_ids(true).with_target(false);
+  let filter_layer = EnvFilter::try_from_default_env()
+    .or_else(|_| EnvFilter::try_new("kraglin=debug,info"))
+    .unwrap();
+
+  tracing_subscriber::registry()
+    .with(filter_layer)
+    .with(fmt_layer)
+    .with(ErrorLayer::default())
+    .init();
+}
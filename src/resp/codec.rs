@@ -0,0 +1,232 @@
+//! Defines `RespCodec`, which frames a connection's requests with a
+//! [`RequestParser`] and serializes its replies, whole or in
+//! [`ReplyChunk`]s, for use with [`tokio_util::codec::Framed`].
+
+use std::io;
+
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{ProtocolError, RequestLimits, RequestParser};
+use crate::{
+  backends::ReplyChunk,
+  value::{write_array_header, write_set_header, Value},
+};
+
+/// An error reading or writing a framed connection.
+#[derive(Debug, thiserror::Error)]
+pub enum RespCodecError {
+  /// The peer sent a malformed frame. The connection can't be read any
+  /// further.
+  #[error(transparent)]
+  Protocol(#[from] ProtocolError),
+  /// Reading from or writing to the connection failed.
+  #[error(transparent)]
+  Io(#[from] io::Error),
+}
+
+/// Decodes requests as RESP2 frames or inline commands, and encodes replies as
+/// RESP2 or RESP3 depending on the version the connection has negotiated.
+#[derive(Debug, Clone)]
+pub struct RespCodec {
+  protocol: u8,
+  limits:   RequestLimits,
+  parser:   RequestParser,
+}
+
+impl Default for RespCodec {
+  fn default() -> Self {
+    RespCodec {
+      protocol: 2,
+      limits:   RequestLimits::default(),
+      parser:   RequestParser::default(),
+    }
+  }
+}
+
+impl RespCodec {
+  /// Creates a codec which encodes replies as RESP2.
+  pub fn new() -> Self { RespCodec::default() }
+
+  /// Creates a codec which encodes replies as RESP2, and rejects requests
+  /// which exceed `limits`.
+  pub fn with_limits(limits: RequestLimits) -> Self {
+    RespCodec {
+      limits,
+      ..RespCodec::default()
+    }
+  }
+
+  /// Returns the RESP version replies are encoded in.
+  pub fn protocol(&self) -> u8 { self.protocol }
+
+  /// Sets the RESP version replies are encoded in. Versions before 3 get
+  /// RESP2.
+  pub fn set_protocol(&mut self, protocol: u8) { self.protocol = protocol; }
+}
+
+impl Decoder for RespCodec {
+  type Item = Value;
+  type Error = RespCodecError;
+
+  fn decode(
+    &mut self,
+    buf: &mut BytesMut,
+  ) -> Result<Option<Value>, Self::Error> {
+    Ok(self.parser.decode(buf, &self.limits)?)
+  }
+
+  /// Decodes what's left once the peer has stopped sending. A trailing
+  /// partial frame is dropped, like a disconnect in the middle of a request.
+  fn decode_eof(
+    &mut self,
+    buf: &mut BytesMut,
+  ) -> Result<Option<Value>, Self::Error> {
+    let frame = self.decode(buf)?;
+    if frame.is_none() {
+      buf.clear();
+      self.parser.reset();
+    }
+    Ok(frame)
+  }
+}
+
+impl Encoder<Value> for RespCodec {
+  type Error = RespCodecError;
+
+  fn encode(
+    &mut self,
+    reply: Value,
+    buf: &mut BytesMut,
+  ) -> Result<(), Self::Error> {
+    reply.write_resp(self.protocol, buf);
+    Ok(())
+  }
+}
+
+impl Encoder<ReplyChunk> for RespCodec {
+  type Error = RespCodecError;
+
+  fn encode(
+    &mut self,
+    chunk: ReplyChunk,
+    buf: &mut BytesMut,
+  ) -> Result<(), Self::Error> {
+    match chunk {
+      ReplyChunk::Value(reply) => reply.write_resp(self.protocol, buf),
+      ReplyChunk::ArrayHeader(len) => write_array_header(buf, len),
+      ReplyChunk::SetHeader(len) => write_set_header(self.protocol, buf, len),
+      ReplyChunk::Elements(elements) => {
+        for element in elements {
+          element.write_resp(self.protocol, buf);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeSet;
+
+  use bytes::BytesMut;
+  use futures::{SinkExt, StreamExt};
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio_util::codec::{Encoder, Framed};
+
+  use super::{RespCodec, RespCodecError};
+  use crate::{
+    backends::ReplyChunk,
+    resp::{ProtocolError, RequestLimits},
+    value::Value,
+  };
+
+  #[tokio::test]
+  async fn frames_are_decoded_and_replies_encoded() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut framed = Framed::new(server, RespCodec::new());
+
+    // a request split across writes is decoded once it's complete
+    client.write_all(b"*1\r\n$4\r\nPI").await.unwrap();
+    client
+      .write_all(b"NG\r\n*1\r\n$4\r\nPING\r\n")
+      .await
+      .unwrap();
+    let ping = Value::Array(vec![Value::BulkString("PING".into())]);
+    assert_eq!(framed.next().await.unwrap().unwrap(), ping);
+    assert_eq!(framed.next().await.unwrap().unwrap(), ping);
+
+    framed.send(Value::Nothing).await.unwrap();
+    framed.codec_mut().set_protocol(3);
+    framed.send(Value::Nothing).await.unwrap();
+    let mut replies = [0; 8];
+    client.read_exact(&mut replies).await.unwrap();
+    assert_eq!(&replies, b"$-1\r\n_\r\n");
+
+    // a partial request left when the peer hangs up is dropped
+    client.write_all(b"*1\r\n").await.unwrap();
+    drop(client);
+    assert!(framed.next().await.is_none());
+  }
+
+  #[test]
+  fn reply_chunks_are_encoded_like_whole_replies() {
+    let set =
+      BTreeSet::from([Value::BulkString("a".into()), Value::Integer(1)]);
+    let members = set.iter().cloned().collect::<Vec<_>>();
+    for protocol in [2, 3] {
+      let mut codec = RespCodec::new();
+      codec.set_protocol(protocol);
+      for (whole, header) in [
+        (Value::Array(members.clone()), ReplyChunk::ArrayHeader(2)),
+        (Value::Set(set.clone()), ReplyChunk::SetHeader(2)),
+      ] {
+        let (mut expected, mut encoded) = (BytesMut::new(), BytesMut::new());
+        codec.encode(whole, &mut expected).unwrap();
+        codec.encode(header, &mut encoded).unwrap();
+        for member in &members {
+          let chunk = ReplyChunk::Elements(vec![member.clone()]);
+          codec.encode(chunk, &mut encoded).unwrap();
+        }
+        assert_eq!(encoded, expected);
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn malformed_frames_fail() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut framed = Framed::new(server, RespCodec::new());
+    client.write_all(b"*x\r\n").await.unwrap();
+    assert!(matches!(
+      framed.next().await,
+      Some(Err(RespCodecError::Protocol(
+        ProtocolError::InvalidMultibulkLength
+      )))
+    ));
+  }
+
+  #[tokio::test]
+  async fn oversized_requests_fail_before_they_are_read() {
+    let (mut client, server) = tokio::io::duplex(64);
+    let mut framed = Framed::new(
+      server,
+      RespCodec::with_limits(RequestLimits {
+        max_request_len: 1024,
+        ..RequestLimits::default()
+      }),
+    );
+    // the bulk string's length is enough to reject it, without its contents
+    client
+      .write_all(b"*2\r\n$3\r\nSET\r\n$2000\r\n")
+      .await
+      .unwrap();
+    assert!(matches!(
+      framed.next().await,
+      Some(Err(RespCodecError::Protocol(
+        ProtocolError::RequestTooLarge
+      )))
+    ));
+  }
+}
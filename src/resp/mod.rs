@@ -0,0 +1,600 @@
+//! Defines `decode()`, an incremental RESP2 parser which frames the bytes
+//! read from a connection into [`Value`]s.
+//!
+//! Reads don't line up with frames: one read may hold several pipelined
+//! requests, or only part of one. [`decode()`] takes the first complete frame
+//! off the front of the read buffer, and leaves a partial frame in place until
+//! more of it has been read. Bulk strings are handed out as views of the read
+//! buffer rather than copies.
+//!
+//! Requests are read with [`decode_request()`], which also accepts inline
+//! commands (`GET foo\r\n`), for typing into `telnet` or `nc`, and enforces
+//! [`RequestLimits`] so that one oversized request can't exhaust the server's
+//! memory. Connections read them with a [`RequestParser`], which keeps how
+//! much of a partial request it has parsed between reads, so that a request
+//! arriving over many reads is only parsed once.
+
+mod codec;
+
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+
+pub use self::codec::{RespCodec, RespCodecError};
+use crate::value::Value;
+
+/// The longest bulk string accepted, like Redis' default `proto-max-bulk-len`.
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// The most elements accepted in an array.
+pub const MAX_ARRAY_LEN: usize = i32::MAX as usize;
+/// The longest line (a simple string, error, integer, or length) accepted.
+/// Longer lines are rejected rather than buffered forever.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+/// How deeply arrays may be nested.
+pub const MAX_DEPTH: usize = 32;
+/// The largest request accepted by default, like Redis' default
+/// `client-query-buffer-limit`.
+pub const MAX_REQUEST_LEN: usize = 1024 * 1024 * 1024;
+
+/// Bounds on the requests [`decode_request_with()`] accepts. A request which
+/// exceeds them is rejected as soon as that's known, often before the rest of
+/// it has been read, rather than buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+  /// The most bytes a single request may take, including its framing.
+  pub max_request_len: usize,
+  /// The longest bulk string a request may hold.
+  pub max_bulk_len:    usize,
+  /// The most arguments (including the command name) a request may have.
+  pub max_args:        usize,
+}
+
+impl Default for RequestLimits {
+  fn default() -> Self {
+    RequestLimits {
+      max_request_len: MAX_REQUEST_LEN,
+      max_bulk_len:    MAX_BULK_LEN,
+      max_args:        MAX_ARRAY_LEN,
+    }
+  }
+}
+
+/// The limits frames which aren't requests (e.g. replies from another
+/// server, or records of the append-only file) are decoded with.
+const FRAME_LIMITS: RequestLimits = RequestLimits {
+  max_request_len: usize::MAX,
+  max_bulk_len:    MAX_BULK_LEN,
+  max_args:        MAX_ARRAY_LEN,
+};
+
+/// A malformed frame. The connection can't be read any further after one,
+/// since where the next frame starts is unknown.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ProtocolError {
+  /// The frame starts with a byte which isn't a RESP2 type.
+  #[error("Protocol error: invalid type byte '{}'", .0.escape_ascii())]
+  InvalidType(u8),
+  /// A bulk string's length is malformed, negative, or longer than
+  /// [`RequestLimits::max_bulk_len`].
+  #[error("Protocol error: invalid bulk length")]
+  InvalidBulkLength,
+  /// An array's length is malformed, negative, or too long.
+  #[error("Protocol error: invalid multibulk length")]
+  InvalidMultibulkLength,
+  /// An integer frame isn't an integer.
+  #[error("Protocol error: invalid integer")]
+  InvalidInteger,
+  /// A line or bulk string isn't terminated by `\r\n`.
+  #[error("Protocol error: expected '\\r\\n'")]
+  ExpectedCrlf,
+  /// A line is longer than [`MAX_LINE_LEN`].
+  #[error("Protocol error: too big line")]
+  LineTooLong,
+  /// Arrays are nested deeper than [`MAX_DEPTH`].
+  #[error("Protocol error: arrays nested too deeply")]
+  TooDeep,
+  /// An inline command is longer than [`MAX_LINE_LEN`].
+  #[error("Protocol error: too big inline request")]
+  InlineTooLong,
+  /// A request has more than [`RequestLimits::max_args`] arguments.
+  #[error("Protocol error: too many arguments")]
+  TooManyArguments,
+  /// A request is longer than [`RequestLimits::max_request_len`].
+  #[error("Protocol error: request is too large")]
+  RequestTooLarge,
+}
+
+/// A frame parsed out of the read buffer, with its strings as ranges of the
+/// buffer.
+#[derive(Debug, Clone)]
+enum Frame {
+  SimpleString(Range<usize>),
+  Error(Range<usize>),
+  Integer(i64),
+  BulkString(Range<usize>),
+  Null,
+  Array(Vec<Frame>),
+}
+
+impl Frame {
+  fn into_value(self, buf: &Bytes) -> Value {
+    let string = |range: Range<usize>| {
+      String::from_utf8_lossy(&buf[range]).as_ref().into()
+    };
+    match self {
+      Frame::SimpleString(range) => Value::SimpleString(string(range)),
+      Frame::Error(range) => Value::Error(string(range)),
+      Frame::Integer(i) => Value::Integer(i),
+      Frame::BulkString(range) => Value::BulkString(buf.slice(range)),
+      Frame::Null => Value::Nothing,
+      Frame::Array(frames) => {
+        Value::Array(frames.into_iter().map(|f| f.into_value(buf)).collect())
+      }
+    }
+  }
+}
+
+/// Takes the first frame off the front of `buf`, or returns `None` (leaving
+/// `buf` as it is) if `buf` doesn't hold a complete frame yet.
+///
+/// Null bulk strings and arrays are decoded as [`Value::Nothing`].
+pub fn decode(buf: &mut BytesMut) -> Result<Option<Value>, ProtocolError> {
+  let Some((frame, len)) = parse(buf, 0, 0, &FRAME_LIMITS)? else {
+    return Ok(None);
+  };
+  let buf = buf.split_to(len).freeze();
+  Ok(Some(frame.into_value(&buf)))
+}
+
+/// Takes the first request off the front of `buf`, like [`decode()`], but
+/// also accepts inline commands: lines which don't start with `*`, like
+/// `GET foo\r\n`, are split on whitespace into an array of bulk strings, the
+/// same as the command would be sent as a frame. Blank lines are skipped.
+///
+/// Requests are bounded by the default [`RequestLimits`].
+pub fn decode_request(
+  buf: &mut BytesMut,
+) -> Result<Option<Value>, ProtocolError> {
+  decode_request_with(buf, &RequestLimits::default())
+}
+
+/// Takes the first request off the front of `buf`, like [`decode_request()`],
+/// but bounded by `limits`.
+///
+/// A partial request is parsed from its start again on every call, so
+/// connections keep a [`RequestParser`] instead.
+pub fn decode_request_with(
+  buf: &mut BytesMut,
+  limits: &RequestLimits,
+) -> Result<Option<Value>, ProtocolError> {
+  RequestParser::default().decode(buf, limits)
+}
+
+/// Decodes requests like [`decode_request_with()`], but keeps how much of a
+/// partial request it has parsed between calls, like Redis' `multibulklen`,
+/// so that each call only parses what's been read since the last one.
+#[derive(Debug, Clone, Default)]
+pub struct RequestParser {
+  /// The elements parsed so far of the partial request's array, and how many
+  /// it has in all, once its header has been read.
+  elements: Option<(Vec<Frame>, usize)>,
+  /// Where the partial request's next element starts in the buffer.
+  pos:      usize,
+}
+
+impl RequestParser {
+  /// Takes the first request off the front of `buf`, like
+  /// [`decode_request_with()`]. A partial request is picked up where the last
+  /// call left off, so `buf` must only have been added to since then (or
+  /// [`reset()`](RequestParser::reset) must have been called).
+  pub fn decode(
+    &mut self,
+    buf: &mut BytesMut,
+    limits: &RequestLimits,
+  ) -> Result<Option<Value>, ProtocolError> {
+    let request = self.decode_inner(buf, limits);
+    if !matches!(request, Ok(None)) {
+      self.reset();
+    }
+    request
+  }
+
+  /// Forgets any partial request, e.g. once the buffer holding it has been
+  /// cleared.
+  pub fn reset(&mut self) { *self = RequestParser::default(); }
+
+  fn decode_inner(
+    &mut self,
+    buf: &mut BytesMut,
+    limits: &RequestLimits,
+  ) -> Result<Option<Value>, ProtocolError> {
+    loop {
+      match buf.first() {
+        None => return Ok(None),
+        Some(b'*') => {
+          let Some((frame, len)) = self.parse_array(buf, limits)? else {
+            // a partial frame can only outgrow the limit with a partial line,
+            // since bulk strings are checked against it up front
+            if buf.len() > limits.max_request_len {
+              return Err(ProtocolError::RequestTooLarge);
+            }
+            return Ok(None);
+          };
+          if len > limits.max_request_len {
+            return Err(ProtocolError::RequestTooLarge);
+          }
+          let buf = buf.split_to(len).freeze();
+          return Ok(Some(frame.into_value(&buf)));
+        }
+        Some(_) => {}
+      }
+      // like Redis, a bare `\n` ends the line too, as some clients send
+      let Some(len) = buf.iter().position(|&b| b == b'\n') else {
+        if buf.len() > MAX_LINE_LEN {
+          return Err(ProtocolError::InlineTooLong);
+        }
+        if buf.len() > limits.max_request_len {
+          return Err(ProtocolError::RequestTooLarge);
+        }
+        return Ok(None);
+      };
+      if len > MAX_LINE_LEN {
+        return Err(ProtocolError::InlineTooLong);
+      }
+      if len + 1 > limits.max_request_len {
+        return Err(ProtocolError::RequestTooLarge);
+      }
+      let line = buf.split_to(len + 1).freeze();
+      let args = line[..len]
+        .split(u8::is_ascii_whitespace)
+        .filter(|arg| !arg.is_empty())
+        .collect::<Vec<_>>();
+      if args.len() > limits.max_args {
+        return Err(ProtocolError::TooManyArguments);
+      }
+      if args.iter().any(|arg| arg.len() > limits.max_bulk_len) {
+        return Err(ProtocolError::InvalidBulkLength);
+      }
+      if !args.is_empty() {
+        return Ok(Some(Value::Array(
+          args
+            .into_iter()
+            .map(|arg| Value::BulkString(line.slice_ref(arg)))
+            .collect(),
+        )));
+      }
+    }
+  }
+
+  /// Parses the array at the front of `buf`, starting from the elements
+  /// parsed by earlier calls. Returns it and where it ends.
+  fn parse_array(
+    &mut self,
+    buf: &[u8],
+    limits: &RequestLimits,
+  ) -> Result<Option<(Frame, usize)>, ProtocolError> {
+    let (elements, len) = match &mut self.elements {
+      Some((elements, len)) => (elements, *len),
+      None => {
+        let Some((line, end)) = line(buf, 1)? else {
+          return Ok(None);
+        };
+        let Some(len) = array_len(&buf[line], 0, limits)? else {
+          return Ok(Some((Frame::Null, end)));
+        };
+        self.pos = end;
+        // every element takes at least 3 bytes, so a huge length can't
+        // allocate more than the buffer already holds
+        let elements = Vec::with_capacity(len.min((buf.len() - end) / 3));
+        let (elements, _) = self.elements.insert((elements, len));
+        (elements, len)
+      }
+    };
+    while elements.len() < len {
+      let Some((frame, next)) = parse(buf, self.pos, 1, limits)? else {
+        return Ok(None);
+      };
+      elements.push(frame);
+      self.pos = next;
+    }
+    Ok(Some((Frame::Array(std::mem::take(elements)), self.pos)))
+  }
+}
+
+/// Parses the frame starting at `pos`, returning it and where it ends.
+/// `limits` bounds its bulk strings, and the length of the outermost array.
+fn parse(
+  buf: &[u8],
+  pos: usize,
+  depth: usize,
+  limits: &RequestLimits,
+) -> Result<Option<(Frame, usize)>, ProtocolError> {
+  let Some(&kind) = buf.get(pos) else {
+    return Ok(None);
+  };
+  let Some((line, end)) = line(buf, pos + 1)? else {
+    return Ok(None);
+  };
+  let frame = match kind {
+    b'+' => Frame::SimpleString(line),
+    b'-' => Frame::Error(line),
+    b':' => {
+      Frame::Integer(integer(&buf[line]).ok_or(ProtocolError::InvalidInteger)?)
+    }
+    b'$' => {
+      let len = match integer(&buf[line]) {
+        Some(-1) => return Ok(Some((Frame::Null, end))),
+        Some(len) => usize::try_from(len)
+          .ok()
+          .filter(|&len| len <= limits.max_bulk_len)
+          .ok_or(ProtocolError::InvalidBulkLength)?,
+        None => return Err(ProtocolError::InvalidBulkLength),
+      };
+      // rejected before it's buffered if the frame can't fit in the limit
+      if end + len + 2 > limits.max_request_len {
+        return Err(ProtocolError::RequestTooLarge);
+      }
+      if buf.len() < end + len + 2 {
+        return Ok(None);
+      }
+      if &buf[end + len..end + len + 2] != b"\r\n" {
+        return Err(ProtocolError::ExpectedCrlf);
+      }
+      return Ok(Some((Frame::BulkString(end..end + len), end + len + 2)));
+    }
+    b'*' => {
+      let Some(len) = array_len(&buf[line], depth, limits)? else {
+        return Ok(Some((Frame::Null, end)));
+      };
+      // every element takes at least 3 bytes, so a huge length can't
+      // allocate more than the buffer already holds
+      let mut frames = Vec::with_capacity(len.min((buf.len() - end) / 3));
+      let mut end = end;
+      for _ in 0..len {
+        let Some((frame, next)) = parse(buf, end, depth + 1, limits)? else {
+          return Ok(None);
+        };
+        frames.push(frame);
+        end = next;
+      }
+      return Ok(Some((Frame::Array(frames), end)));
+    }
+    other => return Err(ProtocolError::InvalidType(other)),
+  };
+  Ok(Some((frame, end)))
+}
+
+/// Parses the length line of an array nested `depth` deep, returning `None`
+/// for a null array.
+fn array_len(
+  line: &[u8],
+  depth: usize,
+  limits: &RequestLimits,
+) -> Result<Option<usize>, ProtocolError> {
+  let len = match integer(line) {
+    Some(-1) => return Ok(None),
+    Some(len) => usize::try_from(len)
+      .ok()
+      .filter(|&len| len <= MAX_ARRAY_LEN)
+      .ok_or(ProtocolError::InvalidMultibulkLength)?,
+    None => return Err(ProtocolError::InvalidMultibulkLength),
+  };
+  if depth == 0 && len > limits.max_args {
+    return Err(ProtocolError::TooManyArguments);
+  }
+  if len > 0 && depth == MAX_DEPTH {
+    return Err(ProtocolError::TooDeep);
+  }
+  Ok(Some(len))
+}
+
+/// Finds the line starting at `pos`, returning its range (without the
+/// `\r\n`) and where the next line starts.
+fn line(
+  buf: &[u8],
+  pos: usize,
+) -> Result<Option<(Range<usize>, usize)>, ProtocolError> {
+  let rest = &buf[pos.min(buf.len())..];
+  let Some(len) = rest.iter().position(|&b| b == b'\r') else {
+    if rest.len() > MAX_LINE_LEN {
+      return Err(ProtocolError::LineTooLong);
+    }
+    return Ok(None);
+  };
+  if len > MAX_LINE_LEN {
+    return Err(ProtocolError::LineTooLong);
+  }
+  match rest.get(len + 1) {
+    None => Ok(None),
+    Some(b'\n') => Ok(Some((pos..pos + len, pos + len + 2))),
+    Some(_) => Err(ProtocolError::ExpectedCrlf),
+  }
+}
+
+fn integer(line: &[u8]) -> Option<i64> {
+  std::str::from_utf8(line).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use bytes::BytesMut;
+
+  use super::{
+    decode, decode_request, decode_request_with, ProtocolError, RequestLimits,
+    RequestParser, MAX_DEPTH,
+  };
+  use crate::value::Value;
+
+  fn decode_all(input: &[u8]) -> Result<Vec<Value>, ProtocolError> {
+    let mut buf = BytesMut::from(input);
+    let mut values = Vec::new();
+    while let Some(value) = decode(&mut buf)? {
+      values.push(value);
+    }
+    assert!(buf.is_empty(), "left over: {buf:?}");
+    Ok(values)
+  }
+
+  #[test]
+  fn every_resp2_type_is_decoded() {
+    let values = decode_all(
+      b"+OK\r\n-ERR oops\r\n:-42\r\n$5\r\nhe\r\no\r\n$0\r\n\r\n$-1\r\n*-1\r\n\
+        *2\r\n$3\r\nGET\r\n*1\r\n:1\r\n*0\r\n",
+    )
+    .unwrap();
+    assert_eq!(values, [
+      Value::SimpleString("OK".into()),
+      Value::Error("ERR oops".into()),
+      Value::Integer(-42),
+      Value::BulkString("he\r\no".into()),
+      Value::BulkString("".into()),
+      Value::Nothing,
+      Value::Nothing,
+      Value::Array(vec![
+        Value::BulkString("GET".into()),
+        Value::Array(vec![Value::Integer(1)]),
+      ]),
+      Value::Array(vec![]),
+    ]);
+  }
+
+  #[test]
+  fn partial_frames_wait_for_more_bytes() {
+    let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\n";
+    let mut buf = BytesMut::new();
+    for (i, &byte) in input.iter().enumerate() {
+      assert_eq!(decode(&mut buf), Ok(None), "after {i} bytes");
+      assert_eq!(buf.len(), i);
+      buf.extend_from_slice(&[byte]);
+    }
+    assert_eq!(
+      decode(&mut buf),
+      Ok(Some(Value::Array(vec![
+        Value::BulkString("SET".into()),
+        Value::BulkString("k".into()),
+        Value::BulkString("value".into()),
+      ])))
+    );
+    assert!(buf.is_empty());
+  }
+
+  #[test]
+  fn partial_requests_resume_where_they_left_off() {
+    let input = b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\nPING\r\n";
+    let limits = RequestLimits::default();
+    let mut parser = RequestParser::default();
+    let mut buf = BytesMut::new();
+    for &byte in &input[..29] {
+      buf.extend_from_slice(&[byte]);
+      assert_eq!(parser.decode(&mut buf, &limits), Ok(None));
+    }
+    // the parsed elements are kept, and only the partial one is parsed again
+    let (elements, len) = parser.elements.as_ref().unwrap();
+    assert_eq!((elements.len(), *len, parser.pos), (2, 3, 20));
+
+    buf.extend_from_slice(&input[29..]);
+    assert_eq!(
+      parser.decode(&mut buf, &limits),
+      Ok(Some(Value::Array(vec![
+        Value::BulkString("SET".into()),
+        Value::BulkString("k".into()),
+        Value::BulkString("value".into()),
+      ])))
+    );
+    assert!(parser.elements.is_none());
+    assert_eq!(
+      parser.decode(&mut buf, &limits),
+      Ok(Some(Value::Array(vec![Value::BulkString("PING".into())])))
+    );
+    assert!(buf.is_empty());
+  }
+
+  #[test]
+  fn malformed_frames_are_rejected() {
+    for (input, error) in [
+      (&b"?\r\n"[..], ProtocolError::InvalidType(b'?')),
+      (b":one\r\n", ProtocolError::InvalidInteger),
+      (b"$-2\r\n", ProtocolError::InvalidBulkLength),
+      (b"$999999999999\r\n", ProtocolError::InvalidBulkLength),
+      (b"*x\r\n", ProtocolError::InvalidMultibulkLength),
+      (b"$3\r\nabcd\r\n", ProtocolError::ExpectedCrlf),
+      (b"+OK\rX", ProtocolError::ExpectedCrlf),
+      (&[b'+'; 70 * 1024], ProtocolError::LineTooLong),
+    ] {
+      assert_eq!(decode_all(input), Err(error), "{}", input.escape_ascii());
+    }
+
+    let nested = "*1\r\n".repeat(MAX_DEPTH + 1);
+    assert_eq!(decode_all(nested.as_bytes()), Err(ProtocolError::TooDeep));
+    assert_eq!(
+      ProtocolError::InvalidType(b'\n').to_string(),
+      "Protocol error: invalid type byte '\\n'"
+    );
+  }
+
+  #[test]
+  fn inline_commands_are_split_on_whitespace() {
+    let mut buf = BytesMut::from(
+      &b"GET foo\r\n\r\n  SET  k\tv \n*1\r\n$4\r\nPING\r\nPIN"[..],
+    );
+    let args = |args: &[&'static str]| {
+      Value::Array(
+        args
+          .iter()
+          .map(|&arg| Value::BulkString(arg.as_bytes().into()))
+          .collect(),
+      )
+    };
+    assert_eq!(decode_request(&mut buf), Ok(Some(args(&["GET", "foo"]))));
+    assert_eq!(decode_request(&mut buf), Ok(Some(args(&["SET", "k", "v"]))));
+    assert_eq!(decode_request(&mut buf), Ok(Some(args(&["PING"]))));
+    // a partial line waits for the rest
+    assert_eq!(decode_request(&mut buf), Ok(None));
+    buf.extend_from_slice(b"G\r\n");
+    assert_eq!(decode_request(&mut buf), Ok(Some(args(&["PING"]))));
+    assert!(buf.is_empty());
+
+    let mut buf = BytesMut::from(&[b'a'; 70 * 1024][..]);
+    assert_eq!(decode_request(&mut buf), Err(ProtocolError::InlineTooLong));
+  }
+
+  #[test]
+  fn requests_over_the_limits_are_rejected() {
+    let limits = RequestLimits {
+      max_request_len: 40,
+      max_bulk_len:    8,
+      max_args:        3,
+    };
+    let decode =
+      |input: &[u8]| decode_request_with(&mut BytesMut::from(input), &limits);
+
+    assert!(matches!(
+      decode(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n"),
+      Ok(Some(_))
+    ));
+    assert!(matches!(decode(b"SET k v\r\n"), Ok(Some(_))));
+    let long_line = [b'a'; 41];
+    for (input, error) in [
+      (&b"*4\r\n"[..], ProtocolError::TooManyArguments),
+      (b"SET k v EX\r\n", ProtocolError::TooManyArguments),
+      (
+        b"*2\r\n$3\r\nGET\r\n$9\r\n",
+        ProtocolError::InvalidBulkLength,
+      ),
+      (b"GET 123456789\r\n", ProtocolError::InvalidBulkLength),
+      // the last bulk string would end past the limit, so it's rejected
+      // before it's read
+      (
+        b"*3\r\n$8\r\naaaaaaaa\r\n$8\r\naaaaaaaa\r\n$8\r\n",
+        ProtocolError::RequestTooLarge,
+      ),
+      (&long_line, ProtocolError::RequestTooLarge),
+      (
+        b"*1\r\n+aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        ProtocolError::RequestTooLarge,
+      ),
+    ] {
+      assert_eq!(decode(input), Err(error), "{}", input.escape_ascii());
+    }
+  }
+}
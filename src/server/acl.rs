@@ -0,0 +1,763 @@
+//! Defines the `Acl` item, which holds the users connections can authenticate
+//! as, and which commands and keys each of them may use.
+//!
+//! Users are configured with Redis-style rules, e.g. `ACL SETUSER alice on
+//! >secret ~cache:* +get +set`:
+//! - `on` / `off`: enables or disables the user.
+//! - `>password` / `<password`: adds or removes a password.
+//! - `#hash` / `!hash`: adds or removes a password by its SHA-256 hex digest.
+//! - `nopass` / `resetpass`: allows any password, or removes every password.
+//! - `~pattern` / `allkeys` / `resetkeys`: allows keys matching a glob pattern,
+//!   allows every key, or removes every key pattern.
+//! - `%R~pattern` / `%W~pattern` / `%RW~pattern`: allows keys matching a glob
+//!   pattern to be read, written, or both (the same as `~pattern`). Commands
+//!   which write need write access to every key they're given, and other
+//!   commands need read access.
+//! - `+command` / `-command`: allows or denies a command.
+//! - `+command|subcommand` / `-command|subcommand`: allows or denies a single
+//!   subcommand, e.g. `+acl|whoami`.
+//! - `+@all` (`allcommands`) / `-@all` (`nocommands`): allows or denies every
+//!   command.
+//! - `+@category` / `-@category`: allows or denies every command in an ACL
+//!   category, e.g. `+@read` or `-@dangerous`. `ACL CAT` lists them.
+//! - `reset`: returns the user to the state of a newly created one.
+//!
+//! Later command rules take precedence over earlier ones.
+
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt::Write,
+  sync::RwLock,
+};
+
+use sha2::{Digest, Sha256};
+use smol_str::SmolStr;
+
+use super::acl_log::{AclLog, AclLogReason};
+use crate::{
+  command::{AclCategories, Command},
+  value::Value,
+  KraglinError,
+};
+
+/// The name of the user connections start as.
+pub const DEFAULT_USER: &str = "default";
+
+/// The SHA-256 digest of a password.
+type PasswordHash = [u8; 32];
+
+fn hash_password(password: &[u8]) -> PasswordHash {
+  Sha256::digest(password).into()
+}
+
+fn to_hex(hash: &PasswordHash) -> String {
+  hash
+    .iter()
+    .fold(String::with_capacity(64), |mut hex, byte| {
+      let _ = write!(hex, "{byte:02x}");
+      hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<PasswordHash> {
+  if hex.len() != 64 || !hex.is_ascii() {
+    return None;
+  }
+  let mut hash = [0; 32];
+  for (i, byte) in hash.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+  }
+  Some(hash)
+}
+
+/// Matches `key` against a Redis-style glob pattern, supporting `*`, `?`,
+/// `[abc]`, `[^abc]`, `[a-z]`, and `\` escapes.
+///
+/// Matching backtracks only to the last `*`, so it takes at most
+/// `O(pattern × key)` steps, even for patterns like `*a*a*a*b`.
+pub(crate) fn glob_match(pattern: &str, key: &str) -> bool {
+  /// Matches the single-character token at the start of `pattern` against
+  /// `c`, returning the token's length if it matches.
+  fn match_one(pattern: &[char], c: char) -> Option<usize> {
+    match pattern[0] {
+      '?' => Some(1),
+      '[' => {
+        let rest = &pattern[1..];
+        let negated = rest.first() == Some(&'^');
+        let mut i = usize::from(negated);
+        let mut found = false;
+        while i < rest.len() && rest[i] != ']' {
+          if rest[i] == '\\' && i + 1 < rest.len() {
+            found |= rest[i + 1] == c;
+            i += 2;
+          } else if i + 2 < rest.len()
+            && rest[i + 1] == '-'
+            && rest[i + 2] != ']'
+          {
+            found |= (rest[i]..=rest[i + 2]).contains(&c);
+            i += 3;
+          } else {
+            found |= rest[i] == c;
+            i += 1;
+          }
+        }
+        // an unterminated class runs to the end of the pattern
+        (found != negated).then_some((i + 2).min(pattern.len()))
+      }
+      '\\' if pattern.len() > 1 => (pattern[1] == c).then_some(2),
+      p => (p == c).then_some(1),
+    }
+  }
+
+  if pattern == "*" {
+    return true;
+  }
+  let pattern = pattern.chars().collect::<Vec<_>>();
+  let key = key.chars().collect::<Vec<_>>();
+
+  let (mut p, mut k) = (0, 0);
+  // where to resume after the last `*`: the pattern after it, and the next
+  // key position for it to swallow
+  let mut star = None;
+  while k < key.len() {
+    if pattern.get(p) == Some(&'*') {
+      p += 1;
+      star = Some((p, k));
+      continue;
+    }
+    if let Some(len) = pattern
+      .get(p)
+      .and_then(|_| match_one(&pattern[p..], key[k]))
+    {
+      p += len;
+      k += 1;
+      continue;
+    }
+    let Some((star_p, star_k)) = star else {
+      return false;
+    };
+    p = star_p;
+    k = star_k + 1;
+    star = Some((star_p, k));
+  }
+  pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches random patterns against random keys for `cycles` rounds, as
+/// `DEBUG STRINGMATCH-LEN` does, to exercise [`glob_match()`] with malformed
+/// and pathological patterns. Panics (or hangs) if the matcher would.
+pub(crate) fn glob_match_fuzz(cycles: usize, seed: u64) {
+  const ALPHABET: &[char] = &['a', 'b', '*', '?', '[', ']', '^', '-', '\\'];
+  // xorshift64, which never yields zero from a non-zero seed
+  let mut state = seed | 1;
+  let mut next = move |bound: usize| {
+    state ^= state << 13;
+    state ^= state >> 7;
+    state ^= state << 17;
+    (state % bound as u64) as usize
+  };
+
+  for _ in 0..cycles {
+    let [pattern, key] = [(); 2].map(|_| {
+      let len = next(33);
+      (0..len)
+        .map(|_| ALPHABET[next(ALPHABET.len())])
+        .collect::<String>()
+    });
+    glob_match(&pattern, &key);
+  }
+}
+
+/// Which commands a [`CommandRule`] applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CommandSelector {
+  All,
+  /// A single ACL category.
+  Category(AclCategories),
+  /// A lowercased command name, or `command|subcommand` name.
+  Command(SmolStr),
+}
+
+impl CommandSelector {
+  fn matches(&self, command: &Command) -> bool {
+    match self {
+      CommandSelector::All => true,
+      CommandSelector::Category(category) => {
+        command.acl_categories().contains(*category)
+      }
+      CommandSelector::Command(name) if name.contains('|') => {
+        command.full_name() == *name
+      }
+      CommandSelector::Command(name) => {
+        command.command_name().eq_ignore_ascii_case(name)
+      }
+    }
+  }
+}
+
+/// Allows or denies a set of commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CommandRule {
+  Allow(CommandSelector),
+  Deny(CommandSelector),
+}
+
+impl CommandRule {
+  fn selector(&self) -> &CommandSelector {
+    match self {
+      CommandRule::Allow(selector) | CommandRule::Deny(selector) => selector,
+    }
+  }
+}
+
+/// A glob pattern of keys a user may access, and whether it may read or
+/// write them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyPattern {
+  pattern: SmolStr,
+  read:    bool,
+  write:   bool,
+}
+
+impl KeyPattern {
+  /// Parses the permissions and pattern of a `%<permissions>~<pattern>`
+  /// rule, given what follows the `%`.
+  fn parse_selector(rule: &str) -> Option<Self> {
+    let (permissions, pattern) = rule.split_once('~')?;
+    if permissions.is_empty() || pattern.is_empty() {
+      return None;
+    }
+    let (mut read, mut write) = (false, false);
+    for permission in permissions.chars() {
+      match permission.to_ascii_uppercase() {
+        'R' => read = true,
+        'W' => write = true,
+        _ => return None,
+      }
+    }
+    Some(KeyPattern {
+      pattern: pattern.into(),
+      read,
+      write,
+    })
+  }
+
+  /// Describes the pattern as the rule which adds it.
+  fn describe(&self) -> String {
+    match (self.read, self.write) {
+      (true, false) => format!("%R~{}", self.pattern),
+      (false, true) => format!("%W~{}", self.pattern),
+      _ => format!("~{}", self.pattern),
+    }
+  }
+}
+
+/// A user, with its passwords and permissions.
+#[derive(Debug, Clone, Default)]
+pub struct User {
+  enabled:   bool,
+  nopass:    bool,
+  passwords: BTreeSet<PasswordHash>,
+  commands:  Vec<CommandRule>,
+  keys:      Vec<KeyPattern>,
+}
+
+impl User {
+  /// Applies a single rule to the user.
+  pub fn apply(&mut self, rule: &str) -> Result<(), KraglinError> {
+    let invalid = || KraglinError::InvalidAclRule(rule.to_owned());
+    match rule.to_ascii_lowercase().as_str() {
+      "on" => self.enabled = true,
+      "off" => self.enabled = false,
+      "nopass" => {
+        self.nopass = true;
+        self.passwords.clear();
+      }
+      "resetpass" => {
+        self.nopass = false;
+        self.passwords.clear();
+      }
+      "allkeys" => {
+        self.keys = vec![KeyPattern {
+          pattern: "*".into(),
+          read:    true,
+          write:   true,
+        }]
+      }
+      "resetkeys" => self.keys.clear(),
+      "allcommands" => {
+        self.push_command_rule(CommandRule::Allow(CommandSelector::All))
+      }
+      "nocommands" => {
+        self.push_command_rule(CommandRule::Deny(CommandSelector::All))
+      }
+      "reset" => *self = User::default(),
+      _ => {
+        let mut chars = rule.chars();
+        let prefix = chars.next();
+        let rest = chars.as_str();
+        match prefix {
+          Some('>') => {
+            self.nopass = false;
+            self.passwords.insert(hash_password(rest.as_bytes()));
+          }
+          Some('<') => {
+            self.passwords.remove(&hash_password(rest.as_bytes()));
+          }
+          Some('#') => {
+            self.nopass = false;
+            self.passwords.insert(from_hex(rest).ok_or_else(invalid)?);
+          }
+          Some('!') => {
+            self.passwords.remove(&from_hex(rest).ok_or_else(invalid)?);
+          }
+          Some('~') if !rest.is_empty() => self.keys.push(KeyPattern {
+            pattern: rest.into(),
+            read:    true,
+            write:   true,
+          }),
+          Some('%') => {
+            self
+              .keys
+              .push(KeyPattern::parse_selector(rest).ok_or_else(invalid)?);
+          }
+          Some('+' | '-') if !rest.is_empty() => {
+            let selector = match rest.strip_prefix('@') {
+              Some(category) if category.eq_ignore_ascii_case("all") => {
+                CommandSelector::All
+              }
+              Some(category) => CommandSelector::Category(
+                AclCategories::from_category_name(category)
+                  .ok_or_else(invalid)?,
+              ),
+              None => {
+                CommandSelector::Command(rest.to_ascii_lowercase().into())
+              }
+            };
+            self.push_command_rule(if prefix == Some('+') {
+              CommandRule::Allow(selector)
+            } else {
+              CommandRule::Deny(selector)
+            });
+          }
+          _ => return Err(invalid()),
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Adds a command rule, dropping earlier rules it completely overrides.
+  fn push_command_rule(&mut self, rule: CommandRule) {
+    match rule.selector() {
+      CommandSelector::All => self.commands.clear(),
+      selector => self.commands.retain(|r| r.selector() != selector),
+    }
+    self.commands.push(rule);
+  }
+
+  /// Whether the user is enabled.
+  pub fn is_enabled(&self) -> bool { self.enabled }
+
+  /// Whether the user accepts any password.
+  pub fn is_nopass(&self) -> bool { self.nopass }
+
+  /// Whether `password` is one of the user's passwords.
+  pub fn check_password(&self, password: &[u8]) -> bool {
+    self.nopass || self.passwords.contains(&hash_password(password))
+  }
+
+  /// Whether the user may run `command`, ignoring the keys it accesses.
+  pub fn can_run(&self, command: &Command) -> bool {
+    self
+      .commands
+      .iter()
+      .rev()
+      .find(|rule| rule.selector().matches(command))
+      .is_some_and(|rule| matches!(rule, CommandRule::Allow(_)))
+  }
+
+  /// Whether the user may access `key`: to write it if `write`, and
+  /// otherwise to read it.
+  pub fn can_access(&self, key: &str, write: bool) -> bool {
+    self.keys.iter().any(|pattern| {
+      (if write { pattern.write } else { pattern.read })
+        && glob_match(&pattern.pattern, key)
+    })
+  }
+
+  /// Describes the user's rules, in the form `ACL LIST` and `ACL SETUSER`
+  /// use.
+  pub fn describe(&self) -> String {
+    let mut rules = vec![if self.enabled { "on" } else { "off" }.to_owned()];
+    if self.nopass {
+      rules.push("nopass".to_owned());
+    }
+    rules.extend(
+      self
+        .passwords
+        .iter()
+        .map(|hash| format!("#{}", to_hex(hash))),
+    );
+    rules.extend(self.keys.iter().map(KeyPattern::describe));
+    rules.push(self.describe_commands());
+    rules.join(" ")
+  }
+
+  fn describe_commands(&self) -> String {
+    if self.commands.is_empty() {
+      return "-@all".to_owned();
+    }
+    self
+      .commands
+      .iter()
+      .map(|rule| {
+        let (sign, selector) = match rule {
+          CommandRule::Allow(selector) => ('+', selector),
+          CommandRule::Deny(selector) => ('-', selector),
+        };
+        match selector {
+          CommandSelector::All => format!("{sign}@all"),
+          CommandSelector::Category(category) => {
+            format!("{sign}@{}", category.names().collect::<String>())
+          }
+          CommandSelector::Command(name) => format!("{sign}{name}"),
+        }
+      })
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  /// Describes the user as an `ACL GETUSER` reply.
+  pub fn to_value(&self) -> Value {
+    let mut flags = vec![Value::SimpleString(
+      if self.enabled { "on" } else { "off" }.into(),
+    )];
+    if self.nopass {
+      flags.push(Value::SimpleString("nopass".into()));
+    }
+    let passwords = self
+      .passwords
+      .iter()
+      .map(|hash| Value::BulkString(to_hex(hash).into()))
+      .collect();
+    let keys = self
+      .keys
+      .iter()
+      .map(KeyPattern::describe)
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    Value::Map(BTreeMap::from([
+      ("flags".into(), Value::Array(flags)),
+      ("passwords".into(), Value::Array(passwords)),
+      (
+        "commands".into(),
+        Value::BulkString(self.describe_commands().into()),
+      ),
+      ("keys".into(), Value::BulkString(keys.into())),
+    ]))
+  }
+}
+
+/// The set of users connections can authenticate as.
+///
+/// There is always a [`DEFAULT_USER`], which connections use until they
+/// authenticate as someone else. By default it is enabled, has no password,
+/// and may run every command on every key.
+///
+/// Denied commands and failed authentication attempts are recorded in the
+/// [`AclLog`].
+#[derive(Debug)]
+pub struct Acl {
+  users: RwLock<BTreeMap<SmolStr, User>>,
+  log:   AclLog,
+}
+
+impl Default for Acl {
+  fn default() -> Self {
+    let mut default_user = User::default();
+    for rule in ["on", "nopass", "allkeys", "allcommands"] {
+      default_user.apply(rule).expect("default rules are valid");
+    }
+    Acl {
+      users: RwLock::new(BTreeMap::from([(
+        SmolStr::new_static(DEFAULT_USER),
+        default_user,
+      )])),
+      log:   AclLog::default(),
+    }
+  }
+}
+
+impl Acl {
+  /// Creates or modifies the user `name` by applying `rules` in order. If any
+  /// rule is invalid, the user is left unchanged.
+  pub fn set_user(
+    &self,
+    name: &str,
+    rules: impl IntoIterator<Item = impl AsRef<str>>,
+  ) -> Result<(), KraglinError> {
+    let mut users = self.users.write().unwrap();
+    let mut user = users.get(name).cloned().unwrap_or_default();
+    for rule in rules {
+      user.apply(rule.as_ref())?;
+    }
+    users.insert(name.into(), user);
+    Ok(())
+  }
+
+  /// Returns a copy of the user `name`, if it exists.
+  pub fn user(&self, name: &str) -> Option<User> {
+    self.users.read().unwrap().get(name).cloned()
+  }
+
+  /// Deletes the named users, returning how many existed. The default user
+  /// can't be deleted.
+  pub fn delete_users(
+    &self,
+    names: impl IntoIterator<Item = impl AsRef<str>>,
+  ) -> Result<usize, KraglinError> {
+    let names = names.into_iter().collect::<Vec<_>>();
+    if names.iter().any(|name| name.as_ref() == DEFAULT_USER) {
+      return Err(KraglinError::DeleteDefaultUser);
+    }
+    let mut users = self.users.write().unwrap();
+    Ok(
+      names
+        .iter()
+        .filter(|name| users.remove(name.as_ref()).is_some())
+        .count(),
+    )
+  }
+
+  /// Describes every user, one per line of `ACL LIST`.
+  pub fn list(&self) -> Vec<String> {
+    self
+      .users
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(name, user)| format!("user {name} {}", user.describe()))
+      .collect()
+  }
+
+  /// Whether `password` authenticates the user `name`.
+  pub fn authenticate(&self, name: &str, password: &[u8]) -> bool {
+    self
+      .users
+      .read()
+      .unwrap()
+      .get(name)
+      .is_some_and(|user| user.is_enabled() && user.check_password(password))
+  }
+
+  /// Resolves which user a connection is acting as, given the user it
+  /// authenticated as (if any). Returns `None` if the connection must
+  /// authenticate first, e.g. because the default user needs a password, or
+  /// because its user has since been disabled or deleted.
+  pub fn resolve(&self, authenticated_as: Option<&str>) -> Option<SmolStr> {
+    let users = self.users.read().unwrap();
+    match authenticated_as {
+      Some(name) => users
+        .get_key_value(name)
+        .filter(|(_, user)| user.is_enabled())
+        .map(|(name, _)| name.clone()),
+      None => users
+        .get(DEFAULT_USER)
+        .filter(|user| user.is_enabled() && user.is_nopass())
+        .map(|_| SmolStr::new_static(DEFAULT_USER)),
+    }
+  }
+
+  /// Returns the log of recent denials.
+  pub fn log(&self) -> &AclLog { &self.log }
+
+  /// Checks that the user `name` may run `command`, including on all of its
+  /// keys.
+  pub fn check(
+    &self,
+    name: &str,
+    command: &Command,
+  ) -> Result<(), KraglinError> {
+    self.denial(name, command).map_err(|(_, error)| error)
+  }
+
+  /// Like [`Acl::check()`], but also records a denial in the log as coming
+  /// from `client`.
+  pub fn check_logged(
+    &self,
+    name: &str,
+    command: &Command,
+    client: &str,
+  ) -> Result<(), KraglinError> {
+    self.denial(name, command).map_err(|(reason, error)| {
+      if let Some(reason) = reason {
+        self.log.record(reason, &command.full_name(), name, client);
+      }
+      error
+    })
+  }
+
+  /// Checks that the user `name` may run `command`, returning why not if it
+  /// can't. Denials because the user doesn't exist have no log reason.
+  fn denial(
+    &self,
+    name: &str,
+    command: &Command,
+  ) -> Result<(), (Option<AclLogReason>, KraglinError)> {
+    let users = self.users.read().unwrap();
+    let Some(user) = users.get(name) else {
+      return Err((None, KraglinError::NoAuth));
+    };
+    if !user.can_run(command) {
+      return Err((
+        Some(AclLogReason::Command),
+        KraglinError::NoPermission(format!(
+          "User {name} has no permissions to run the '{}' command",
+          command.full_name()
+        )),
+      ));
+    }
+    let write = command.is_write();
+    if !command
+      .keys()
+      .into_iter()
+      .all(|key| user.can_access(key, write))
+    {
+      return Err((
+        Some(AclLogReason::Key),
+        KraglinError::NoPermission("No permissions to access a key".to_owned()),
+      ));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{glob_match, glob_match_fuzz, Acl, DEFAULT_USER};
+  use crate::{command::Command, value::Value, KraglinError};
+
+  #[test]
+  fn glob_patterns_match() {
+    assert!(glob_match("*", "anything"));
+    assert!(glob_match("cache:*", "cache:a"));
+    assert!(!glob_match("cache:*", "cach"));
+    assert!(glob_match("h?llo", "hello"));
+    assert!(glob_match("h[ae]llo", "hallo"));
+    assert!(!glob_match("h[^e]llo", "hello"));
+    assert!(glob_match("h[a-c]llo", "hbllo"));
+    assert!(glob_match("a\\*", "a*"));
+    assert!(!glob_match("a\\*", "ab"));
+    assert!(glob_match("*a*b", "xaxxb"));
+    assert!(!glob_match("*a*b", "xaxxc"));
+    assert!(glob_match("a*", "a"));
+    assert!(glob_match("[abc", "b"));
+
+    // backtracking is bounded, so this finishes instantly
+    let key = "a".repeat(64);
+    assert!(!glob_match(&format!("{}b", "*a".repeat(32)), &key));
+    glob_match_fuzz(1_000, 42);
+  }
+
+  #[test]
+  fn rules_grant_and_deny_permissions() {
+    let acl = Acl::default();
+    acl
+      .set_user("alice", ["on", ">secret", "~cache:*", "+@all", "-del"])
+      .unwrap();
+    let get = |key: &str| Command::Get { key: key.into() };
+
+    assert!(acl.authenticate("alice", b"secret"));
+    assert!(!acl.authenticate("alice", b"wrong"));
+    assert!(acl.check("alice", &get("cache:a")).is_ok());
+    assert!(matches!(
+      acl.check("alice", &get("other")),
+      Err(KraglinError::NoPermission(_))
+    ));
+    assert!(matches!(
+      acl.check("alice", &Command::Delete {
+        keys: vec!["cache:a".into()],
+      }),
+      Err(KraglinError::NoPermission(_))
+    ));
+
+    // rules are only applied if they're all valid
+    assert!(acl.set_user("alice", ["off", "bogus"]).is_err());
+    assert!(acl.user("alice").unwrap().is_enabled());
+
+    let description = acl.user("alice").unwrap().describe();
+    assert!(description.starts_with(
+      "on #2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"
+    ));
+    assert!(description.ends_with("~cache:* +@all -del"));
+
+    assert_eq!(acl.delete_users(["alice", "bob"]).unwrap(), 1);
+    assert!(acl.delete_users([DEFAULT_USER]).is_err());
+  }
+
+  #[test]
+  fn categories_and_subcommands_select_commands() {
+    let acl = Acl::default();
+    acl
+      .set_user("reader", ["on", "allkeys", "+@read", "+acl|whoami"])
+      .unwrap();
+    let set = Command::Set {
+      key:     "a".into(),
+      value:   Value::BulkString("1".into()),
+      options: Default::default(),
+    };
+
+    assert!(acl
+      .check("reader", &Command::Get { key: "a".into() })
+      .is_ok());
+    assert!(acl.check("reader", &Command::AclWhoAmI).is_ok());
+    assert!(acl.check("reader", &Command::AclList).is_err());
+    assert!(matches!(
+      acl.check("reader", &set),
+      Err(KraglinError::NoPermission(message)) if message.contains("'set'")
+    ));
+
+    acl.set_user("reader", ["+@all", "-@write"]).unwrap();
+    assert!(acl.check("reader", &set).is_err());
+    assert!(acl.check("reader", &Command::AclList).is_ok());
+    assert!(acl
+      .user("reader")
+      .unwrap()
+      .describe()
+      .ends_with("+@all -@write"));
+
+    assert!(acl.set_user("reader", ["+@bogus"]).is_err());
+  }
+
+  #[test]
+  fn key_patterns_can_allow_only_reads_or_writes() {
+    let acl = Acl::default();
+    acl
+      .set_user("etl", ["on", "+@all", "%R~src:*", "%W~dst:*", "%RW~tmp:*"])
+      .unwrap();
+    let get = |key: &str| Command::Get { key: key.into() };
+    let set = |key: &str| Command::Set {
+      key:     key.into(),
+      value:   Value::BulkString("1".into()),
+      options: Default::default(),
+    };
+
+    assert!(acl.check("etl", &get("src:a")).is_ok());
+    assert!(acl.check("etl", &set("src:a")).is_err());
+    assert!(acl.check("etl", &get("dst:a")).is_err());
+    assert!(acl.check("etl", &set("dst:a")).is_ok());
+    assert!(acl.check("etl", &get("tmp:a")).is_ok());
+    assert!(acl.check("etl", &set("tmp:a")).is_ok());
+
+    assert!(acl
+      .user("etl")
+      .unwrap()
+      .describe()
+      .ends_with("%R~src:* %W~dst:* ~tmp:* +@all"));
+    for bogus in ["%X~a", "%R~", "%~a", "%Ra"] {
+      assert!(acl.set_user("etl", [bogus]).is_err(), "{bogus}");
+    }
+  }
+}
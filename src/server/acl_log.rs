@@ -0,0 +1,187 @@
+//! Defines the `AclLog` item, a bounded record of recent authentication
+//! failures and denied commands, exposed through `ACL LOG`.
+
+use std::{
+  collections::{BTreeMap, VecDeque},
+  fmt,
+  sync::Mutex,
+};
+
+use smol_str::SmolStr;
+
+use crate::{
+  clock::{self, Instant},
+  value::Value,
+};
+
+/// How many entries the log keeps before dropping the oldest, like Redis'
+/// default `acllog-max-len`.
+pub const ACL_LOG_MAX_LEN: usize = 128;
+
+/// Why an [`AclLogEntry`] was recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclLogReason {
+  /// An `AUTH` attempt failed.
+  Auth,
+  /// The user may not run the command.
+  Command,
+  /// The user may not access one of the command's keys.
+  Key,
+}
+
+impl fmt::Display for AclLogReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      AclLogReason::Auth => "auth",
+      AclLogReason::Command => "command",
+      AclLogReason::Key => "key",
+    })
+  }
+}
+
+/// A denied authentication attempt or command.
+///
+/// Repeats of the same denial (same reason, object, user, and client) are
+/// folded into one entry, counting how many times it happened.
+#[derive(Debug, Clone)]
+pub struct AclLogEntry {
+  count:    u64,
+  reason:   AclLogReason,
+  object:   SmolStr,
+  username: SmolStr,
+  client:   String,
+  updated:  Instant,
+}
+
+impl AclLogEntry {
+  /// Returns how many times the denial happened.
+  pub fn count(&self) -> u64 { self.count }
+
+  /// Returns why the entry was recorded.
+  pub fn reason(&self) -> AclLogReason { self.reason }
+
+  /// Returns what was denied: the command's full name, or `AUTH`.
+  pub fn object(&self) -> &str { &self.object }
+
+  /// Returns the user which was denied.
+  pub fn username(&self) -> &str { &self.username }
+
+  /// Returns the address of the client which was denied.
+  pub fn client(&self) -> &str { &self.client }
+
+  /// Describes the entry as an element of an `ACL LOG` reply.
+  pub fn to_value(&self) -> Value {
+    Value::Map(BTreeMap::from([
+      ("count".into(), Value::Integer(self.count as i64)),
+      (
+        "reason".into(),
+        Value::BulkString(self.reason.to_string().into()),
+      ),
+      ("context".into(), Value::BulkString("toplevel".into())),
+      (
+        "object".into(),
+        Value::BulkString(self.object.as_bytes().to_vec().into()),
+      ),
+      (
+        "username".into(),
+        Value::BulkString(self.username.as_bytes().to_vec().into()),
+      ),
+      (
+        "age-seconds".into(),
+        Value::Double(self.updated.elapsed().as_secs_f64()),
+      ),
+      (
+        "client-info".into(),
+        Value::BulkString(format!("addr={}", self.client).into()),
+      ),
+    ]))
+  }
+}
+
+/// A bounded log of recent ACL denials, newest first.
+#[derive(Debug, Default)]
+pub struct AclLog {
+  entries: Mutex<VecDeque<AclLogEntry>>,
+}
+
+impl AclLog {
+  /// Records a denial, folding it into an existing entry if it's a repeat.
+  pub fn record(
+    &self,
+    reason: AclLogReason,
+    object: &str,
+    username: &str,
+    client: &str,
+  ) {
+    let now = clock::now();
+    let mut entries = self.entries.lock().unwrap();
+    let existing = entries.iter().position(|entry| {
+      entry.reason == reason
+        && entry.object == object
+        && entry.username == username
+        && entry.client == client
+    });
+    let entry = match existing.and_then(|i| entries.remove(i)) {
+      Some(mut entry) => {
+        entry.count += 1;
+        entry.updated = now;
+        entry
+      }
+      None => AclLogEntry {
+        count: 1,
+        reason,
+        object: object.into(),
+        username: username.into(),
+        client: client.to_owned(),
+        updated: now,
+      },
+    };
+    entries.push_front(entry);
+    entries.truncate(ACL_LOG_MAX_LEN);
+  }
+
+  /// Returns up to `count` of the most recent entries, newest first.
+  pub fn entries(&self, count: usize) -> Vec<AclLogEntry> {
+    let entries = self.entries.lock().unwrap();
+    entries.iter().take(count).cloned().collect()
+  }
+
+  /// Clears the log.
+  pub fn reset(&self) { self.entries.lock().unwrap().clear(); }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{AclLog, AclLogReason, ACL_LOG_MAX_LEN};
+  use crate::value::Value;
+
+  #[tokio::test(start_paused = true)]
+  async fn log_folds_repeats_and_is_bounded() {
+    let log = AclLog::default();
+    log.record(AclLogReason::Auth, "AUTH", "alice", "127.0.0.1:1");
+    log.record(AclLogReason::Command, "set", "alice", "127.0.0.1:1");
+    log.record(AclLogReason::Auth, "AUTH", "alice", "127.0.0.1:1");
+
+    let entries = log.entries(10);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].reason(), AclLogReason::Auth);
+    assert_eq!(entries[0].count(), 2);
+    assert_eq!(entries[1].object(), "set");
+    assert_eq!(log.entries(1).len(), 1);
+
+    tokio::time::advance(std::time::Duration::from_secs(5)).await;
+    let entry = log.entries(1).remove(0).to_value();
+    let Value::Map(entry) = entry else {
+      panic!("expected a map");
+    };
+    assert_eq!(entry["age-seconds"], Value::Double(5.0));
+
+    for i in 0..ACL_LOG_MAX_LEN + 10 {
+      log.record(AclLogReason::Key, "get", "alice", &i.to_string());
+    }
+    assert_eq!(log.entries(usize::MAX).len(), ACL_LOG_MAX_LEN);
+
+    log.reset();
+    assert!(log.entries(10).is_empty());
+  }
+}
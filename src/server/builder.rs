@@ -0,0 +1,560 @@
+//! Defines the `ServerBuilder` item, for composing a server programmatically.
+
+use std::{
+  future::Future, net::SocketAddr, path::PathBuf, pin::Pin, sync::Arc,
+  time::Duration,
+};
+
+use bytes::Bytes;
+use color_eyre::eyre::{Result, WrapErr};
+use tokio::{
+  net::{TcpListener, UnixListener},
+  sync::watch,
+  task::JoinHandle,
+};
+
+use super::{
+  dispatch::{CommandInterceptor, Dispatcher},
+  health,
+  listener::{ListenAddr, Listener},
+  registry::CommandRegistry,
+  run,
+  supervisor::ConnectionStats,
+  trace::ProtocolTrace,
+  websocket::WebSocketListener,
+  workers::{Executor, WorkerPool},
+  ConnectionLimits,
+};
+use crate::{
+  backends::{self, Backend},
+  command::CommandFlags,
+  resp::RequestLimits,
+  KraglinResult,
+};
+
+/// A listener to bind when the server starts.
+enum ListenerSpec {
+  Tcp(String),
+  Unix(PathBuf),
+  WebSocket(String),
+  #[cfg(all(feature = "io-uring", target_os = "linux"))]
+  Uring(String),
+  Custom(Box<dyn Listener>),
+}
+
+type StartHook = Box<dyn FnOnce(&[ListenAddr]) + Send>;
+type ShutdownHook = Box<dyn FnOnce() + Send>;
+type ShutdownSignal = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Composes a server from a backend, a set of listeners, and lifecycle hooks.
+///
+/// ```no_run
+/// # async fn example() -> color_eyre::Result<()> {
+/// use kraglin::{
+///   backends::{simple::SimpleBackend, Backend, BackendConfig},
+///   server::ServerBuilder,
+/// };
+///
+/// let backend = SimpleBackend::new(BackendConfig::default())?;
+/// ServerBuilder::new(backend)
+///   .tcp("0.0.0.0:6379")
+///   .unix("/tmp/kraglin.sock")
+///   .on_start(|addrs| println!("listening on {addrs:?}"))
+///   .serve()
+///   .await
+/// # }
+/// ```
+pub struct ServerBuilder<B: Backend> {
+  backend:       Arc<B>,
+  listeners:     Vec<ListenerSpec>,
+  interceptors:  Vec<Arc<dyn CommandInterceptor>>,
+  commands:      CommandRegistry<B>,
+  requirepass:   Option<String>,
+  active_defrag: Option<Duration>,
+  trace:         Option<ProtocolTrace>,
+  timeout:       Option<Duration>,
+  replica:       bool,
+  limits:        ConnectionLimits,
+  /// The number of workers and the queue length, if commands run on a
+  /// worker pool.
+  workers:       Option<(usize, usize)>,
+  /// How long shutdown waits for open connections before closing them, if
+  /// it gives up at all.
+  drain_timeout: Option<Duration>,
+  /// The address to serve health probes on, if they're served.
+  health:        Option<String>,
+  signal:        Option<ShutdownSignal>,
+  on_start:      Vec<StartHook>,
+  on_shutdown:   Vec<ShutdownHook>,
+}
+
+impl<B: Backend> ServerBuilder<B> {
+  /// Creates a builder serving from `backend`, with no listeners.
+  pub fn new(backend: B) -> Self {
+    Self::with_shared_backend(Arc::new(backend))
+  }
+
+  /// Creates a builder serving from a backend which is shared with other
+  /// code.
+  pub fn with_shared_backend(backend: Arc<B>) -> Self {
+    ServerBuilder {
+      backend,
+      listeners: Vec::new(),
+      interceptors: Vec::new(),
+      commands: CommandRegistry::default(),
+      requirepass: None,
+      active_defrag: None,
+      trace: None,
+      timeout: None,
+      replica: false,
+      limits: ConnectionLimits::default(),
+      workers: None,
+      drain_timeout: None,
+      health: None,
+      signal: None,
+      on_start: Vec::new(),
+      on_shutdown: Vec::new(),
+    }
+  }
+
+  /// Adds a TCP listener on `address` (e.g. `"0.0.0.0:6379"`). Port `0` binds
+  /// an ephemeral port.
+  pub fn tcp(mut self, address: impl Into<String>) -> Self {
+    self.listeners.push(ListenerSpec::Tcp(address.into()));
+    self
+  }
+
+  /// Adds a unix socket listener at `path`.
+  pub fn unix(mut self, path: impl Into<PathBuf>) -> Self {
+    self.listeners.push(ListenerSpec::Unix(path.into()));
+    self
+  }
+
+  /// Adds a WebSocket listener on `address`, which serves RESP over
+  /// WebSocket connections. See [`WebSocketListener`].
+  pub fn websocket(mut self, address: impl Into<String>) -> Self {
+    self.listeners.push(ListenerSpec::WebSocket(address.into()));
+    self
+  }
+
+  /// Adds a TCP listener on `address` whose connections are read and written
+  /// through io_uring. See [`UringListener`](super::UringListener).
+  #[cfg(all(feature = "io-uring", target_os = "linux"))]
+  pub fn io_uring(mut self, address: impl Into<String>) -> Self {
+    self.listeners.push(ListenerSpec::Uring(address.into()));
+    self
+  }
+
+  /// Adds an already-bound custom listener, e.g. one which terminates TLS.
+  pub fn listener(mut self, listener: impl Listener) -> Self {
+    self
+      .listeners
+      .push(ListenerSpec::Custom(Box::new(listener)));
+    self
+  }
+
+  /// Adds an interceptor which runs around every dispatched command.
+  /// Interceptors run in the order they are added.
+  pub fn interceptor(mut self, interceptor: impl CommandInterceptor) -> Self {
+    self.interceptors.push(Arc::new(interceptor));
+    self
+  }
+
+  /// Registers a custom command. See [`CommandRegistry::register()`].
+  ///
+  /// # Panics
+  /// Panics if `name` is a built-in command or is already registered.
+  pub fn command<F, Fut>(
+    mut self,
+    name: &str,
+    arity: i64,
+    flags: CommandFlags,
+    handler: F,
+  ) -> Self
+  where
+    F: Fn(Arc<B>, Vec<Bytes>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = KraglinResult> + Send + 'static,
+  {
+    self.commands.register(name, arity, flags, handler);
+    self
+  }
+
+  /// Renames a command, or disables it if `new_name` is empty. See
+  /// [`CommandRegistry::rename()`].
+  ///
+  /// # Panics
+  /// Panics if `new_name` is already used by another rename.
+  pub fn rename_command(mut self, name: &str, new_name: &str) -> Self {
+    self.commands.rename(name, new_name);
+    self
+  }
+
+  /// Disables a command, so clients can't run it.
+  pub fn disable_command(mut self, name: &str) -> Self {
+    self.commands.disable(name);
+    self
+  }
+
+  /// Requires connections to authenticate with `password` before running
+  /// commands. See [`Dispatcher::with_requirepass()`].
+  pub fn requirepass(mut self, password: impl Into<String>) -> Self {
+    self.requirepass = Some(password.into());
+    self
+  }
+
+  /// Runs active defragmentation on the backend every `interval`.
+  pub fn active_defrag(mut self, interval: Duration) -> Self {
+    self.active_defrag = Some(interval);
+    self
+  }
+
+  /// Cancels commands which run for longer than `timeout`. See
+  /// [`Dispatcher::with_command_timeout()`].
+  pub fn command_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = Some(timeout);
+    self
+  }
+
+  /// Serves as a replica, whose keys are only read by connections which
+  /// opted in with `READONLY`. See [`Dispatcher::with_replica()`].
+  pub fn replica(mut self) -> Self {
+    self.replica = true;
+    self
+  }
+
+  /// Rejects new connections once `max_clients` are open, replying with an
+  /// error and closing them, rather than accepting connections without
+  /// bound.
+  pub fn max_clients(mut self, max_clients: usize) -> Self {
+    self.limits.max_clients = Some(max_clients);
+    self
+  }
+
+  /// Closes connections which send no requests for `timeout`. Connections
+  /// subscribed to channels wait for messages rather than requests, so
+  /// they're never closed for idling.
+  pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+    self.limits.idle_timeout = Some(timeout);
+    self
+  }
+
+  /// Closes connections which start sending a request but don't finish it
+  /// within `timeout`, so that clients which stall mid-request (or trickle
+  /// one in a byte at a time) can't hold connections open.
+  pub fn read_timeout(mut self, timeout: Duration) -> Self {
+    self.limits.read_timeout = Some(timeout);
+    self
+  }
+
+  /// Closes connections whose replies have waited more than `timeout` to be
+  /// written, counting from when a write first had to wait for the peer to
+  /// read earlier ones, so that clients which stop reading can't make the
+  /// server buffer their replies without bound. Connections subscribed to
+  /// channels use [`pubsub_output_timeout()`](Self::pubsub_output_timeout)
+  /// instead.
+  pub fn output_timeout(mut self, timeout: Duration) -> Self {
+    self.limits.output_timeout = Some(timeout);
+    self
+  }
+
+  /// Closes connections subscribed to channels whose replies have waited
+  /// more than `timeout` to be written. See
+  /// [`output_timeout()`](Self::output_timeout).
+  pub fn pubsub_output_timeout(mut self, timeout: Duration) -> Self {
+    self.limits.pubsub_output_timeout = Some(timeout);
+    self
+  }
+
+  /// Rejects requests which exceed `limits`, replying with a protocol error
+  /// and closing the connection, rather than buffering them. Defaults to
+  /// [`RequestLimits::default()`].
+  pub fn request_limits(mut self, limits: RequestLimits) -> Self {
+    self.limits.request = limits;
+    self
+  }
+
+  /// Runs commands on a pool of `workers` worker tasks, fed by a queue with
+  /// room for `queue_len` commands, rather than in each connection's task.
+  /// See [`WorkerPool`].
+  pub fn worker_pool(mut self, workers: usize, queue_len: usize) -> Self {
+    self.workers = Some((workers, queue_len));
+    self
+  }
+
+  /// Records the bytes every connection sends and receives, for debugging
+  /// protocol issues. See [`ProtocolTrace`].
+  pub fn protocol_trace(mut self, trace: ProtocolTrace) -> Self {
+    self.trace = Some(trace);
+    self
+  }
+
+  /// Closes connections which are still open `timeout` after shutdown starts,
+  /// rather than waiting for them indefinitely. Connections close once
+  /// they're between commands, so this cuts off those running long (e.g.
+  /// blocking) commands.
+  pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+    self.drain_timeout = Some(timeout);
+    self
+  }
+
+  /// Serves HTTP health probes on `address` (e.g. `"0.0.0.0:8080"`):
+  /// `GET /livez` for liveness, and `GET /readyz` for readiness, which
+  /// fails while the server shuts down or if the backend isn't ready (see
+  /// [`Backend::readiness()`]).
+  pub fn health_probes(mut self, address: impl Into<String>) -> Self {
+    self.health = Some(address.into());
+    self
+  }
+
+  /// Shuts the server down gracefully once `signal` completes, as if by
+  /// [`ServerHandle::shutdown()`]. See [`shutdown_signal()`] for a signal
+  /// which completes on `SIGINT` or `SIGTERM`.
+  ///
+  /// [`shutdown_signal()`]: super::shutdown_signal()
+  pub fn shutdown_on(
+    mut self,
+    signal: impl Future<Output = ()> + Send + 'static,
+  ) -> Self {
+    self.signal = Some(Box::pin(signal));
+    self
+  }
+
+  /// Adds a hook which runs once every listener is bound, before any
+  /// connections are accepted.
+  pub fn on_start(
+    mut self,
+    hook: impl FnOnce(&[ListenAddr]) + Send + 'static,
+  ) -> Self {
+    self.on_start.push(Box::new(hook));
+    self
+  }
+
+  /// Adds a hook which runs after the server has shut down, every connection
+  /// has finished, and the backend has run its
+  /// [`shutdown()`](Backend::shutdown).
+  pub fn on_shutdown(mut self, hook: impl FnOnce() + Send + 'static) -> Self {
+    self.on_shutdown.push(Box::new(hook));
+    self
+  }
+
+  /// Binds the listeners and starts serving in the background, returning a
+  /// handle to the running server.
+  pub async fn start(self) -> Result<ServerHandle<B>> {
+    let mut listeners = Vec::<Box<dyn Listener>>::new();
+    for spec in self.listeners {
+      listeners.push(match spec {
+        ListenerSpec::Tcp(address) => {
+          Box::new(TcpListener::bind(&address).await.wrap_err_with(|| {
+            format!("failed to bind TCP listener {address}")
+          })?)
+        }
+        ListenerSpec::Unix(path) => {
+          Box::new(UnixListener::bind(&path).wrap_err_with(|| {
+            format!("failed to bind unix listener {}", path.display())
+          })?)
+        }
+        ListenerSpec::WebSocket(address) => {
+          Box::new(WebSocketListener::bind(&address).await.wrap_err_with(
+            || format!("failed to bind WebSocket listener {address}"),
+          )?)
+        }
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        ListenerSpec::Uring(address) => {
+          Box::new(super::UringListener::bind(&address).await.wrap_err_with(
+            || format!("failed to bind io_uring listener {address}"),
+          )?)
+        }
+        ListenerSpec::Custom(listener) => listener,
+      });
+    }
+    let health = match self.health {
+      Some(address) => {
+        Some(TcpListener::bind(&address).await.wrap_err_with(|| {
+          format!("failed to bind health probe listener {address}")
+        })?)
+      }
+      None => None,
+    };
+    let health_addr = health
+      .as_ref()
+      .map(TcpListener::local_addr)
+      .transpose()
+      .wrap_err("failed to get health probe listener address")?;
+    let addrs = listeners
+      .iter()
+      .map(|l| l.local_addr())
+      .collect::<Result<Vec<_>, _>>()
+      .wrap_err("failed to get listener address")?;
+    for addr in &addrs {
+      tracing::info!("listening on {addr}");
+    }
+
+    for hook in self.on_start {
+      hook(&addrs);
+    }
+
+    let defrag = self.active_defrag.map(|interval| {
+      backends::spawn_defrag_task(self.backend.clone(), interval)
+    });
+
+    let mut dispatcher =
+      Dispatcher::new(self.backend, self.interceptors, self.commands);
+    if let Some(password) = self.requirepass {
+      dispatcher = dispatcher.with_requirepass(password);
+    }
+    if let Some(timeout) = self.timeout {
+      dispatcher = dispatcher.with_command_timeout(timeout);
+    }
+    if self.replica {
+      dispatcher = dispatcher.with_replica();
+    }
+
+    let dispatcher = Arc::new(dispatcher);
+    let executor = match self.workers {
+      Some((workers, queue_len)) => {
+        Executor::Pool(WorkerPool::new(dispatcher, workers, queue_len))
+      }
+      None => Executor::Inline(dispatcher),
+    };
+    let executor = Arc::new(executor);
+
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let shutdown = Arc::new(shutdown);
+    let signal = self.signal.map(|signal| {
+      // a weak reference, so dropping the handle still shuts the server down
+      let shutdown = Arc::downgrade(&shutdown);
+      tokio::spawn(async move {
+        signal.await;
+        if let Some(shutdown) = shutdown.upgrade() {
+          let _ = shutdown.send(true);
+        }
+      })
+    });
+
+    let health = health.map(|listener| {
+      if let Some(addr) = health_addr {
+        tracing::info!("serving health probes on {addr}");
+      }
+      tokio::spawn(health::serve_probes(
+        listener,
+        executor.dispatcher().backend().clone(),
+        shutdown_rx.clone(),
+      ))
+    });
+
+    let on_shutdown = self.on_shutdown;
+    let trace = self.trace;
+    let limits = self.limits;
+    let drain_timeout = self.drain_timeout;
+    let stats = Arc::new(ConnectionStats::default());
+    let connection_stats = stats.clone();
+    let task = tokio::spawn({
+      let executor = executor.clone();
+      async move {
+        let result = run(
+          listeners,
+          executor.clone(),
+          trace,
+          stats,
+          limits,
+          drain_timeout,
+          shutdown_rx,
+        )
+        .await;
+        if let Some(defrag) = defrag {
+          defrag.abort();
+        }
+        if let Some(signal) = signal {
+          signal.abort();
+        }
+        if let Some(health) = health {
+          health.abort();
+        }
+        if let Err(e) = executor.dispatcher().backend().shutdown().await {
+          tracing::error!("backend failed to shut down cleanly: {e:#}");
+        }
+        for hook in on_shutdown {
+          hook();
+        }
+        result
+      }
+    });
+
+    Ok(ServerHandle {
+      addrs,
+      health_addr,
+      executor,
+      connection_stats,
+      shutdown,
+      task,
+    })
+  }
+
+  /// Binds the listeners and serves until one of them fails.
+  pub async fn serve(self) -> Result<()> { self.start().await?.wait().await }
+}
+
+/// A handle to a server started with [`ServerBuilder::start()`].
+///
+/// The server shuts down when [`ServerHandle::shutdown()`] is called, or in
+/// the background when the handle is dropped.
+pub struct ServerHandle<B: Backend> {
+  addrs:            Vec<ListenAddr>,
+  health_addr:      Option<SocketAddr>,
+  executor:         Arc<Executor<B>>,
+  connection_stats: Arc<ConnectionStats>,
+  shutdown:         Arc<watch::Sender<bool>>,
+  task:             JoinHandle<Result<()>>,
+}
+
+impl<B: Backend> ServerHandle<B> {
+  /// Returns the addresses the server is listening on, in the order the
+  /// listeners were added.
+  pub fn addrs(&self) -> &[ListenAddr] { &self.addrs }
+
+  /// Returns the address of the first TCP listener, if there is one.
+  pub fn tcp_addr(&self) -> Option<SocketAddr> {
+    self.addrs.iter().find_map(|addr| match addr {
+      ListenAddr::Tcp(addr) => Some(*addr),
+      _ => None,
+    })
+  }
+
+  /// Returns the address health probes are served on, if they are.
+  pub fn health_addr(&self) -> Option<SocketAddr> { self.health_addr }
+
+  /// Returns the server's backend, for inspecting or seeding its data
+  /// directly.
+  pub fn backend(&self) -> &Arc<B> { self.dispatcher().backend() }
+
+  /// Returns the server's dispatcher, which runs commands through the
+  /// registered interceptors.
+  pub fn dispatcher(&self) -> &Arc<Dispatcher<B>> { self.executor.dispatcher() }
+
+  /// Returns the server's executor, which runs connections' commands inline
+  /// or on its worker pool.
+  pub fn executor(&self) -> &Arc<Executor<B>> { &self.executor }
+
+  /// Returns counts of the connections the server has served, including
+  /// those whose handlers panicked.
+  pub fn connection_stats(&self) -> &Arc<ConnectionStats> {
+    &self.connection_stats
+  }
+
+  /// Gracefully shuts the server down: stops accepting connections, closes
+  /// open ones (or, past the drain timeout, aborts them), waits for them to
+  /// finish, and shuts the backend down.
+  pub async fn shutdown(self) -> Result<()> {
+    let _ = self.shutdown.send(true);
+    self.wait().await
+  }
+
+  /// Waits for the server to stop, which only happens if a listener fails,
+  /// its shutdown signal completes, or it's shut down elsewhere.
+  pub async fn wait(self) -> Result<()> {
+    // keep the shutdown sender alive while waiting, so that the server isn't
+    // shut down by it being dropped
+    let _shutdown = self.shutdown;
+    self.task.await.wrap_err("server task panicked")?
+  }
+}
@@ -0,0 +1,345 @@
+//! Defines the `ConnectionContext` item, which holds per-connection state.
+
+use std::{
+  mem::size_of,
+  sync::atomic::{AtomicU64, Ordering},
+  time::Duration,
+};
+
+use smol_str::SmolStr;
+
+use super::acl::DEFAULT_USER;
+use crate::{
+  clock::{self, Instant},
+  command::{ClientAttribute, Command},
+};
+
+/// The next connection ID to hand out. IDs start at 1, like Redis' `CLIENT
+/// ID`.
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The commands a connection has queued since `MULTI`.
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+  commands: Vec<Command>,
+  aborted:  bool,
+}
+
+impl Transaction {
+  /// Returns the queued commands, in order.
+  pub fn commands(&self) -> &[Command] { &self.commands }
+
+  /// Consumes the transaction, returning the queued commands.
+  pub fn into_commands(self) -> Vec<Command> { self.commands }
+
+  /// Returns whether a command was rejected while queuing, so `EXEC` must
+  /// discard the transaction.
+  pub fn is_aborted(&self) -> bool { self.aborted }
+}
+
+/// State belonging to a single client connection, carried through its
+/// connection loop and handed to every command it dispatches.
+#[derive(Debug, Clone)]
+pub struct ConnectionContext {
+  id:               u64,
+  peer:             String,
+  authenticated:    Option<SmolStr>,
+  /// The connection's name, shown by `CLIENT INFO`. It's empty if it hasn't
+  /// been named.
+  name:             SmolStr,
+  /// The index of the database the connection's commands run against.
+  db:               usize,
+  no_evict:         bool,
+  no_touch:         bool,
+  /// Whether the connection has opted into reading from a replica with
+  /// `READONLY`.
+  read_only:        bool,
+  /// The RESP version the connection speaks, 2 or 3.
+  protocol:         u8,
+  /// How many channels and patterns the connection is subscribed to.
+  subscriptions:    usize,
+  /// The client library's name and version, set with `CLIENT SETINFO`.
+  lib_name:         SmolStr,
+  lib_ver:          SmolStr,
+  transaction:      Option<Transaction>,
+  created:          Instant,
+  last_interaction: Instant,
+  /// The full name of the last command dispatched, like `acl|setuser`.
+  last_command:     Option<String>,
+  commands:         u64,
+  /// Whether the connection is closed once the reply to its current command
+  /// is written, after `QUIT`.
+  closing:          bool,
+}
+
+impl ConnectionContext {
+  /// Creates the context for a new connection from `peer`, assigning it a
+  /// unique ID.
+  pub fn new(peer: impl Into<String>) -> Self {
+    let now = clock::now();
+    ConnectionContext {
+      id:               NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+      peer:             peer.into(),
+      authenticated:    None,
+      name:             SmolStr::default(),
+      db:               0,
+      no_evict:         false,
+      no_touch:         false,
+      read_only:        false,
+      protocol:         2,
+      subscriptions:    0,
+      lib_name:         SmolStr::default(),
+      lib_ver:          SmolStr::default(),
+      transaction:      None,
+      created:          now,
+      last_interaction: now,
+      last_command:     None,
+      commands:         0,
+      closing:          false,
+    }
+  }
+
+  /// Returns the connection's unique ID.
+  pub fn id(&self) -> u64 { self.id }
+
+  /// Returns the address of the connected peer.
+  pub fn peer(&self) -> &str { &self.peer }
+
+  /// Returns whether the connection has authenticated with `AUTH`.
+  pub fn is_authenticated(&self) -> bool { self.authenticated.is_some() }
+
+  /// Returns the user the connection authenticated as with `AUTH`, if any.
+  /// Connections which haven't authenticated act as the default user.
+  pub fn authenticated_as(&self) -> Option<&str> {
+    self.authenticated.as_deref()
+  }
+
+  /// Marks the connection as authenticated as `user`.
+  pub fn set_authenticated(&mut self, user: impl Into<SmolStr>) {
+    self.authenticated = Some(user.into());
+  }
+
+  /// Returns the connection's name. It's empty if it hasn't been named.
+  pub fn name(&self) -> &str { &self.name }
+
+  /// Names the connection, or clears its name if `name` is empty.
+  pub fn set_name(&mut self, name: impl Into<SmolStr>) {
+    self.name = name.into();
+  }
+
+  /// Returns the index of the database the connection's commands run
+  /// against. Connections start on database 0.
+  pub fn db(&self) -> usize { self.db }
+
+  /// Switches the database the connection's commands run against.
+  pub fn set_db(&mut self, db: usize) { self.db = db; }
+
+  /// Returns whether the connection is exempt from client eviction, set with
+  /// `CLIENT NO-EVICT`.
+  pub fn is_no_evict(&self) -> bool { self.no_evict }
+
+  /// Sets whether the connection is exempt from client eviction.
+  pub fn set_no_evict(&mut self, no_evict: bool) { self.no_evict = no_evict; }
+
+  /// Returns whether the connection's commands leave the access statistics
+  /// of their keys untouched, set with `CLIENT NO-TOUCH`.
+  pub fn is_no_touch(&self) -> bool { self.no_touch }
+
+  /// Sets whether the connection's commands leave the access statistics of
+  /// their keys untouched.
+  pub fn set_no_touch(&mut self, no_touch: bool) { self.no_touch = no_touch; }
+
+  /// Returns whether the connection has opted into reading from a replica,
+  /// set with `READONLY` and cleared with `READWRITE`.
+  pub fn is_read_only(&self) -> bool { self.read_only }
+
+  /// Sets whether the connection has opted into reading from a replica.
+  pub fn set_read_only(&mut self, read_only: bool) {
+    self.read_only = read_only;
+  }
+
+  /// Returns the RESP version the connection speaks, 2 or 3.
+  pub fn protocol(&self) -> u8 { self.protocol }
+
+  /// Sets the RESP version the connection speaks.
+  pub fn set_protocol(&mut self, protocol: u8) { self.protocol = protocol; }
+
+  /// Returns how many channels and patterns the connection is subscribed to.
+  pub fn subscriptions(&self) -> usize { self.subscriptions }
+
+  /// Sets how many channels and patterns the connection is subscribed to.
+  pub fn set_subscriptions(&mut self, subscriptions: usize) {
+    self.subscriptions = subscriptions;
+  }
+
+  /// Returns whether the connection is in subscribe mode, where RESP2
+  /// connections may only run commands with the
+  /// [`SUBSCRIBED`](crate::command::CommandFlags::SUBSCRIBED) flag. RESP3
+  /// connections can interleave replies and pushed messages, so they may run
+  /// anything.
+  pub fn in_subscribed_context(&self) -> bool {
+    self.subscriptions > 0 && self.protocol < 3
+  }
+
+  /// Returns the name of the connection's client library, set with `CLIENT
+  /// SETINFO LIB-NAME`. It's empty if it hasn't been set.
+  pub fn lib_name(&self) -> &str { &self.lib_name }
+
+  /// Returns the version of the connection's client library, set with
+  /// `CLIENT SETINFO LIB-VER`. It's empty if it hasn't been set.
+  pub fn lib_ver(&self) -> &str { &self.lib_ver }
+
+  /// Sets an attribute of the connection's client library.
+  pub fn set_client_attribute(
+    &mut self,
+    attribute: ClientAttribute,
+    value: impl Into<SmolStr>,
+  ) {
+    match attribute {
+      ClientAttribute::LibName => self.lib_name = value.into(),
+      ClientAttribute::LibVer => self.lib_ver = value.into(),
+    }
+  }
+
+  /// Returns whether the connection is queuing commands after `MULTI`.
+  pub fn in_transaction(&self) -> bool { self.transaction.is_some() }
+
+  /// Starts queuing commands, for `MULTI`.
+  pub fn begin_transaction(&mut self) {
+    self.transaction = Some(Transaction::default());
+  }
+
+  /// Queues a command to run on `EXEC`. Does nothing outside a transaction.
+  pub fn queue_command(&mut self, command: Command) {
+    if let Some(transaction) = &mut self.transaction {
+      transaction.commands.push(command);
+    }
+  }
+
+  /// Marks the transaction as aborted, because a command was rejected while
+  /// queuing. Does nothing outside a transaction.
+  pub fn abort_transaction(&mut self) {
+    if let Some(transaction) = &mut self.transaction {
+      transaction.aborted = true;
+    }
+  }
+
+  /// Stops queuing commands, for `EXEC` or `DISCARD`, returning the
+  /// transaction if there was one.
+  pub fn end_transaction(&mut self) -> Option<Transaction> {
+    self.transaction.take()
+  }
+
+  /// Closes the connection once the reply to its current command has been
+  /// written, for `QUIT`.
+  pub fn close(&mut self) { self.closing = true; }
+
+  /// Returns whether the connection is closed once the reply to its current
+  /// command has been written.
+  pub fn is_closing(&self) -> bool { self.closing }
+
+  /// Returns how long the connection has been open.
+  pub fn age(&self) -> Duration { self.created.elapsed() }
+
+  /// Returns how long it's been since the connection last sent a command.
+  pub fn idle(&self) -> Duration { self.last_interaction.elapsed() }
+
+  /// Returns the full name of the last command the connection sent, like
+  /// `acl|setuser`.
+  pub fn last_command(&self) -> Option<&str> { self.last_command.as_deref() }
+
+  /// Returns how many commands the connection has sent.
+  pub fn commands_processed(&self) -> u64 { self.commands }
+
+  /// Updates the connection's statistics for a command it sent.
+  pub fn record_command(&mut self, command: &Command) {
+    self.last_interaction = clock::now();
+    self.last_command = Some(command.full_name());
+    self.commands += 1;
+  }
+
+  /// The approximate number of bytes used by the connection's state.
+  pub fn memory_usage(&self) -> usize {
+    size_of::<Self>()
+      + self.peer.capacity()
+      + self.last_command.as_ref().map_or(0, String::capacity)
+  }
+
+  /// Describes the connection as a line of `field=value` pairs, in the
+  /// format of `CLIENT INFO`.
+  pub fn info(&self) -> String {
+    let mut flags = String::new();
+    if self.no_evict {
+      flags.push('e');
+    }
+    if self.no_touch {
+      flags.push('T');
+    }
+    if self.subscriptions > 0 {
+      flags.push('P');
+    }
+    if self.read_only {
+      flags.push('r');
+    }
+    if self.transaction.is_some() {
+      flags.push('x');
+    }
+    if flags.is_empty() {
+      flags.push('N');
+    }
+
+    format!(
+      "id={} addr={} name={} age={} idle={} flags={flags} db={} sub={} psub=0 \
+       ssub=0 multi={} tot-cmds={} tot-mem={} user={} cmd={} resp={} \
+       lib-name={} lib-ver={}\n",
+      self.id,
+      self.peer,
+      self.name,
+      self.age().as_secs(),
+      self.idle().as_secs(),
+      self.db,
+      self.subscriptions,
+      self
+        .transaction
+        .as_ref()
+        .map_or(-1, |t| t.commands.len() as i64),
+      self.commands,
+      self.memory_usage(),
+      self.authenticated_as().unwrap_or(DEFAULT_USER),
+      self.last_command().unwrap_or("NULL"),
+      self.protocol,
+      self.lib_name,
+      self.lib_ver,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::ConnectionContext;
+  use crate::command::ClientAttribute;
+
+  #[test]
+  fn info_describes_the_connection() {
+    let mut ctx = ConnectionContext::new("127.0.0.1:1234");
+    assert!(ctx.info().contains(" name= "));
+    assert!(ctx.info().contains(" db=0 "));
+
+    ctx.set_name("worker");
+    ctx.set_db(2);
+    ctx.set_protocol(3);
+    ctx.set_authenticated("alice");
+    ctx.set_client_attribute(ClientAttribute::LibName, "redis-py");
+    let info = ctx.info();
+    for field in [
+      "addr=127.0.0.1:1234",
+      "name=worker",
+      "db=2",
+      "resp=3",
+      "user=alice",
+      "lib-name=redis-py",
+    ] {
+      assert!(info.contains(&format!(" {field} ")), "{field} in {info}");
+    }
+  }
+}
@@ -0,0 +1,1642 @@
+//! Defines the `Dispatcher`, which runs commands against the backend through
+//! a chain of [`CommandInterceptor`]s.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::{Stream, StreamExt};
+use smol_str::SmolStr;
+
+use super::{
+  acl::{glob_match_fuzz, Acl, DEFAULT_USER},
+  acl_log::AclLogReason,
+  context::ConnectionContext,
+  error_stats::ErrorStats,
+  latency::{LatencyMonitor, COMMAND_TIMEOUT_EVENT},
+  migrate,
+  registry::CommandRegistry,
+  timeouts::BlockingTimeouts,
+};
+use crate::{
+  backends::{Backend, ReplyChunk},
+  clock,
+  command::{AclCategories, Command, CommandFlags, ParseError},
+  value::{error_code, Value},
+  version, KraglinError, KraglinResult,
+};
+
+/// How many random patterns `DEBUG STRINGMATCH-LEN` matches.
+const STRINGMATCH_LEN_CYCLES: usize = 100_000;
+
+/// What a [`CommandInterceptor`] decides to do with a command before it runs.
+#[derive(Debug)]
+pub enum Intercept {
+  /// Pass the command, possibly rewritten, on to the next interceptor and
+  /// then the backend.
+  Continue(Command),
+  /// Skip the backend and reply with this result instead.
+  Reply(KraglinResult),
+}
+
+/// Hooks which run around every command the server dispatches.
+///
+/// Interceptors can be used for auditing, rate limiting, rewriting, or
+/// metrics. They run in the order they were registered before a command, and
+/// in reverse order after it.
+pub trait CommandInterceptor: Send + Sync + 'static {
+  /// Runs before `command` is executed. The default passes it on unchanged.
+  fn before(&self, ctx: &mut ConnectionContext, command: Command) -> Intercept {
+    let _ = ctx;
+    Intercept::Continue(command)
+  }
+
+  /// Runs after `command` has executed (or been answered by an earlier
+  /// interceptor), and may replace its `result`. The default does nothing.
+  fn after(
+    &self,
+    ctx: &mut ConnectionContext,
+    command: &Command,
+    result: &mut KraglinResult,
+  ) {
+    let _ = (ctx, command, result);
+  }
+}
+
+/// Runs commands against a backend through a chain of interceptors.
+pub struct Dispatcher<B: Backend> {
+  backend:         Arc<B>,
+  interceptors:    Vec<Arc<dyn CommandInterceptor>>,
+  commands:        CommandRegistry<B>,
+  acl:             Acl,
+  timeouts:        BlockingTimeouts,
+  latency:         LatencyMonitor,
+  errors:          ErrorStats,
+  /// How long a command may run before it's cancelled, if there's a limit.
+  command_timeout: Option<Duration>,
+  /// Whether the server is a replica, whose keys are only read by
+  /// connections which opted in with `READONLY`.
+  replica:         bool,
+}
+
+impl<B: Backend> Dispatcher<B> {
+  /// Creates a dispatcher for `backend` with the given interceptors and
+  /// custom commands.
+  pub fn new(
+    backend: Arc<B>,
+    interceptors: Vec<Arc<dyn CommandInterceptor>>,
+    commands: CommandRegistry<B>,
+  ) -> Self {
+    Dispatcher {
+      backend,
+      interceptors,
+      commands,
+      acl: Acl::default(),
+      timeouts: BlockingTimeouts::new(),
+      latency: LatencyMonitor::default(),
+      errors: ErrorStats::default(),
+      command_timeout: None,
+      replica: false,
+    }
+  }
+
+  /// Requires connections to authenticate with `password` (using `AUTH`)
+  /// before running any command without the
+  /// [`NO_AUTH`](CommandFlags::NO_AUTH) flag, by making it the default
+  /// user's only password.
+  pub fn with_requirepass(self, password: impl AsRef<str>) -> Self {
+    let rule = format!(">{}", password.as_ref());
+    self
+      .acl
+      .set_user(DEFAULT_USER, ["resetpass", rule.as_str()])
+      .expect("password rules are valid");
+    self
+  }
+
+  /// Cancels commands which run for longer than `timeout`, replying with a
+  /// `BUSY` error and recording a
+  /// [`COMMAND_TIMEOUT_EVENT`](super::COMMAND_TIMEOUT_EVENT) in the latency
+  /// monitor. Blocking commands, which wait by design, are exempt.
+  ///
+  /// A cancelled command may have been partly applied.
+  pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+    self.command_timeout = Some(timeout);
+    self
+  }
+
+  /// Serves as a replica. Its data may lag behind its master's, so
+  /// connections must opt into reading keys with `READONLY`, as cluster
+  /// clients which balance reads across replicas do. Connections which have
+  /// are rejected if they write, since that would diverge the replica from
+  /// its master; those which haven't, like the master's replication link,
+  /// may write but not read.
+  pub fn with_replica(mut self) -> Self {
+    self.replica = true;
+    self
+  }
+
+  /// Returns the users connections can authenticate as.
+  pub fn acl(&self) -> &Acl { &self.acl }
+
+  /// Returns the timeouts of the connections blocked on blocking commands.
+  pub fn blocking_timeouts(&self) -> &BlockingTimeouts { &self.timeouts }
+
+  /// Returns the latency monitor, which records latency spikes like command
+  /// timeouts.
+  pub fn latency(&self) -> &LatencyMonitor { &self.latency }
+
+  /// Returns the counts of the errors commands have replied with.
+  pub fn errors(&self) -> &ErrorStats { &self.errors }
+
+  /// Returns the backend commands are dispatched to.
+  pub fn backend(&self) -> &Arc<B> { &self.backend }
+
+  /// Parses a command from a RESP frame, including custom commands.
+  pub fn parse(&self, frame: Value) -> Result<Command, ParseError> {
+    self.commands.parse(frame)
+  }
+
+  /// Parses a command sent by the connection `ctx`. A command which fails to
+  /// parse inside a transaction aborts it, so that `EXEC` discards the
+  /// transaction rather than running it without the command.
+  pub fn parse_for(
+    &self,
+    ctx: &mut ConnectionContext,
+    frame: Value,
+  ) -> Result<Command, ParseError> {
+    let result = self.parse(frame);
+    if result.is_err() {
+      ctx.abort_transaction();
+    }
+    result
+  }
+
+  /// Runs `command` on behalf of the connection `ctx`.
+  ///
+  /// If an interceptor replies to the command itself, later interceptors and
+  /// the backend are skipped, but the `after` hooks of the interceptors which
+  /// already ran still see the reply.
+  ///
+  /// Errors are counted in the [`errors()`](Dispatcher::errors), and logged
+  /// with the command, its first key, and the connection which sent it.
+  pub async fn dispatch(
+    &self,
+    ctx: &mut ConnectionContext,
+    mut command: Command,
+  ) -> KraglinResult {
+    ctx.record_command(&command);
+    let mut reply = None;
+    let mut ran = 0;
+    for interceptor in &self.interceptors {
+      ran += 1;
+      match interceptor.before(ctx, command.clone()) {
+        Intercept::Continue(rewritten) => command = rewritten,
+        Intercept::Reply(result) => {
+          reply = Some(result);
+          break;
+        }
+      }
+    }
+
+    let mut result = match reply {
+      Some(result) => result,
+      None => self.execute_with_timeout(ctx, command.clone()).await,
+    };
+    for interceptor in self.interceptors[..ran].iter().rev() {
+      interceptor.after(ctx, &command, &mut result);
+    }
+    if let Err(e) = &result {
+      self.record_error(ctx.id(), ctx.peer(), &command, e);
+    }
+    result
+  }
+
+  /// Whether `command` from the connection `ctx` is run with
+  /// [`dispatch_streaming()`](Dispatcher::dispatch_streaming) rather than
+  /// [`dispatch()`](Dispatcher::dispatch): its reply can be huge (it's `KEYS`
+  /// or `SMEMBERS`), and nothing needs the whole reply at once, as
+  /// interceptors and the command timeout do, or queues the command, as
+  /// transactions do.
+  pub fn streams(&self, ctx: &ConnectionContext, command: &Command) -> bool {
+    streams_reply(command)
+      && self.interceptors.is_empty()
+      && self.command_timeout.is_none()
+      && !ctx.in_transaction()
+  }
+
+  /// Runs `command`, which [`streams()`](Dispatcher::streams), on behalf of
+  /// the connection `ctx`, producing its reply in chunks as the backend does
+  /// (see [`Backend::execute_streaming()`]). Errors are counted and logged
+  /// as by [`dispatch()`](Dispatcher::dispatch).
+  pub fn dispatch_streaming<'a>(
+    &'a self,
+    ctx: &mut ConnectionContext,
+    command: Command,
+  ) -> impl Stream<Item = Result<ReplyChunk, KraglinError>> + Send + 'a {
+    ctx.record_command(&command);
+    let (client, peer) = (ctx.id(), ctx.peer().to_owned());
+    let failed = command.clone();
+    let chunks = match self.check(ctx, &command) {
+      Ok(_) => self
+        .backend
+        .execute_streaming(ctx.db(), command, !ctx.is_no_touch())
+        .left_stream(),
+      Err(e) => futures::stream::iter([Err(e)]).right_stream(),
+    };
+    chunks.inspect(move |chunk| {
+      if let Err(e) = chunk {
+        self.record_error(client, &peer, &failed, e);
+      }
+    })
+  }
+
+  /// Counts a failure of `command` in the [`errors()`](Dispatcher::errors),
+  /// and logs it with the command, its first key, and the connection which
+  /// sent it.
+  fn record_error(
+    &self,
+    client: u64,
+    peer: &str,
+    command: &Command,
+    e: &KraglinError,
+  ) {
+    let (name, message) = (command.full_name(), e.to_string());
+    let code = error_code(&message);
+    self.errors.record(&name, code);
+    tracing::debug!(
+      client,
+      peer,
+      command = name,
+      key = command.keys().first().map(|key| key.as_str()),
+      code,
+      "command failed: {message}"
+    );
+  }
+
+  /// Checks that the connection `ctx` may run `command`, returning the user
+  /// it runs as, if it's checked against one.
+  fn check(
+    &self,
+    ctx: &ConnectionContext,
+    command: &Command,
+  ) -> Result<Option<SmolStr>, KraglinError> {
+    // commands which may run before authenticating skip the permission
+    // checks too
+    let user = if command.flags().contains(CommandFlags::NO_AUTH) {
+      None
+    } else {
+      let user = self
+        .acl
+        .resolve(ctx.authenticated_as())
+        .ok_or(KraglinError::NoAuth)?;
+      self.acl.check_logged(&user, command, ctx.peer())?;
+      Some(user)
+    };
+    if ctx.in_subscribed_context()
+      && !command.flags().contains(CommandFlags::SUBSCRIBED)
+    {
+      return Err(KraglinError::SubscribedContext(command.full_name()));
+    }
+    if self.replica {
+      if ctx.is_read_only() && command.is_write() {
+        return Err(KraglinError::ReadOnlyReplica);
+      }
+      if !ctx.is_read_only()
+        && command.is_read_only()
+        && !command.keys().is_empty()
+      {
+        return Err(KraglinError::ReplicaReadWithoutReadOnly);
+      }
+    }
+    Ok(user)
+  }
+
+  /// Runs a command, cancelling it if it exceeds the command timeout.
+  async fn execute_with_timeout(
+    &self,
+    ctx: &mut ConnectionContext,
+    command: Command,
+  ) -> KraglinResult {
+    let timeout = match self.command_timeout {
+      Some(timeout) if !command.flags().contains(CommandFlags::BLOCKING) => {
+        timeout
+      }
+      _ => return self.execute(ctx, command).await,
+    };
+    let name = command.full_name();
+    match clock::timeout(timeout, self.execute(ctx, command)).await {
+      Ok(result) => result,
+      Err(_) => {
+        tracing::warn!(
+          "`{name}` from {} exceeded the command timeout; cancelled it",
+          ctx.peer()
+        );
+        self.latency.record(COMMAND_TIMEOUT_EVENT, timeout);
+        Err(KraglinError::CommandTimeout {
+          command: name,
+          timeout,
+        })
+      }
+    }
+  }
+
+  /// Whether a transaction's queued `commands` can run together on a snapshot
+  /// of the backend, rather than one at a time: they must all be backend
+  /// reads which the connection may still run. Denials aren't logged here,
+  /// since the commands are checked again when run one at a time.
+  fn runs_on_snapshot(
+    &self,
+    ctx: &ConnectionContext,
+    commands: &[Command],
+  ) -> bool {
+    let Some(user) = self.acl.resolve(ctx.authenticated_as()) else {
+      return false;
+    };
+    !ctx.in_subscribed_context()
+      && commands.iter().all(|command| {
+        command.is_read_only()
+          && !matches!(command, Command::Custom { .. })
+          // `TOUCH` touches even under `NO-TOUCH`, which a snapshot can't
+          && !(ctx.is_no_touch() && matches!(command, Command::Touch { .. }))
+          && self.acl.check(&user, command).is_ok()
+      })
+  }
+
+  /// Whether a transaction's queued `commands` can run together as a unit on
+  /// the backend, so that no other connection's commands land in between
+  /// them: they must all be backend commands, rather than ones handled here.
+  fn runs_as_batch(
+    &self,
+    ctx: &ConnectionContext,
+    commands: &[Command],
+  ) -> bool {
+    commands.iter().all(|command| {
+      runs_on_backend(command)
+        // `TOUCH` touches even under `NO-TOUCH`, which the batch can't
+        && !(ctx.is_no_touch() && matches!(command, Command::Touch { .. }))
+    })
+  }
+
+  /// Authenticates the connection `ctx` as `username`, logging a failure.
+  fn authenticate(
+    &self,
+    ctx: &mut ConnectionContext,
+    username: SmolStr,
+    password: &[u8],
+  ) -> Result<(), KraglinError> {
+    if !self.acl.authenticate(&username, password) {
+      self
+        .acl
+        .log()
+        .record(AclLogReason::Auth, "auth", &username, ctx.peer());
+      return Err(KraglinError::WrongPass);
+    }
+    ctx.set_authenticated(username);
+    Ok(())
+  }
+
+  /// Describes the server to the connection `ctx`, as `HELLO` replies.
+  fn hello(&self, ctx: &ConnectionContext) -> Value {
+    let text = |s: &str| Value::BulkString(s.to_owned().into());
+    Value::Map(BTreeMap::from([
+      ("server".into(), text("kraglin")),
+      ("version".into(), text(version::VERSION)),
+      ("git_sha1".into(), text(version::GIT_SHA)),
+      ("build_features".into(), text(version::FEATURES)),
+      ("proto".into(), Value::Integer(ctx.protocol().into())),
+      ("id".into(), Value::Integer(ctx.id() as i64)),
+      ("mode".into(), text("standalone")),
+      (
+        "role".into(),
+        text(if self.replica { "replica" } else { "master" }),
+      ),
+      ("modules".into(), Value::Array(vec![])),
+    ]))
+  }
+
+  /// Runs a command on the backend, or with its handler if it's custom.
+  /// Connection commands are handled here.
+  async fn execute(
+    &self,
+    ctx: &mut ConnectionContext,
+    command: Command,
+  ) -> KraglinResult {
+    let user = match self.check(ctx, &command) {
+      Ok(user) => user,
+      Err(e) => {
+        // a rejected command would be missing from the transaction
+        ctx.abort_transaction();
+        return Err(e);
+      }
+    };
+    if ctx.in_transaction()
+      && !matches!(
+        command,
+        Command::Multi | Command::Exec | Command::Discard | Command::Quit
+      )
+    {
+      ctx.queue_command(command);
+      return Ok(Value::SimpleString("QUEUED".into()));
+    }
+
+    match command {
+      Command::Multi => {
+        if ctx.in_transaction() {
+          ctx.abort_transaction();
+          return Err(KraglinError::NestedMulti);
+        }
+        ctx.begin_transaction();
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::Exec => {
+        let transaction = ctx
+          .end_transaction()
+          .ok_or(KraglinError::ExecWithoutMulti)?;
+        if transaction.is_aborted() {
+          return Err(KraglinError::ExecAbort);
+        }
+        let commands = transaction.into_commands();
+        if self.runs_on_snapshot(ctx, &commands) {
+          let replies = self
+            .backend
+            .execute_read_only(ctx.db(), commands, !ctx.is_no_touch())
+            .await;
+          return Ok(Value::Array(
+            replies
+              .into_iter()
+              .map(|r| r.unwrap_or_else(|e| Value::Error(e.to_string().into())))
+              .collect(),
+          ));
+        }
+        if self.runs_as_batch(ctx, &commands) {
+          let touch = !ctx.is_no_touch();
+          // denied commands reply with their error in place, and the rest
+          // run together
+          let checks = commands
+            .iter()
+            .map(|command| self.check(ctx, command).err())
+            .collect::<Vec<_>>();
+          let allowed = commands
+            .into_iter()
+            .zip(&checks)
+            .filter(|(_, denied)| denied.is_none())
+            .map(|(command, _)| command)
+            .collect();
+          let mut results = self
+            .backend
+            .execute_batch(ctx.db(), allowed, touch)
+            .await
+            .into_iter();
+          return Ok(Value::Array(
+            checks
+              .into_iter()
+              .map(|denied| match denied {
+                Some(e) => Err(e),
+                None => results.next().expect("every allowed command ran"),
+              })
+              .map(|r| r.unwrap_or_else(|e| Value::Error(e.to_string().into())))
+              .collect(),
+          ));
+        }
+        // transactions which the connection takes part in run one command at
+        // a time
+        let mut replies = Vec::new();
+        for command in commands {
+          // queued commands can't be `EXEC`, so this recurses only once
+          let result = Box::pin(self.execute(ctx, command)).await;
+          replies.push(
+            result.unwrap_or_else(|e| Value::Error(e.to_string().into())),
+          );
+        }
+        Ok(Value::Array(replies))
+      }
+      Command::Discard => {
+        ctx
+          .end_transaction()
+          .ok_or(KraglinError::DiscardWithoutMulti)?;
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::Auth { username, password } => {
+        if username.is_none()
+          && self.acl.user(DEFAULT_USER).is_some_and(|u| u.is_nopass())
+        {
+          return Err(KraglinError::AuthNotConfigured);
+        }
+        let username = username.unwrap_or(SmolStr::new_static(DEFAULT_USER));
+        self.authenticate(ctx, username, &password)?;
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::Hello { protover, auth } => {
+        let protocol = match protover {
+          None => ctx.protocol(),
+          Some(version @ (2 | 3)) => version as u8,
+          Some(_) => return Err(KraglinError::NoProto),
+        };
+        match auth {
+          Some((username, password)) => {
+            self.authenticate(ctx, username, &password)?
+          }
+          None if self.acl.resolve(ctx.authenticated_as()).is_none() => {
+            return Err(KraglinError::NoAuth);
+          }
+          None => {}
+        }
+        ctx.set_protocol(protocol);
+        Ok(self.hello(ctx))
+      }
+      Command::AclSetUser { username, rules } => {
+        self.acl.set_user(&username, rules)?;
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::AclGetUser { username } => Ok(
+        self
+          .acl
+          .user(&username)
+          .map_or(Value::Nothing, |user| user.to_value()),
+      ),
+      Command::AclDelUser { usernames } => {
+        Ok(Value::Integer(self.acl.delete_users(usernames)? as i64))
+      }
+      Command::AclList => Ok(Value::Array(
+        self
+          .acl
+          .list()
+          .into_iter()
+          .map(|line| Value::BulkString(line.into()))
+          .collect(),
+      )),
+      Command::AclWhoAmI => {
+        let user = user.expect("ACL WHOAMI requires authentication");
+        Ok(Value::BulkString(user.as_bytes().to_vec().into()))
+      }
+      Command::AclLog { count } => Ok(Value::Array(
+        self
+          .acl
+          .log()
+          .entries(count.unwrap_or(10))
+          .iter()
+          .map(|entry| entry.to_value())
+          .collect(),
+      )),
+      Command::AclLogReset => {
+        self.acl.log().reset();
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::AclCat { category: None } => Ok(Value::Array(
+        AclCategories::all()
+          .names()
+          .map(|name| Value::BulkString(name.into()))
+          .collect(),
+      )),
+      Command::AclCat {
+        category: Some(name),
+      } => {
+        let category = AclCategories::from_category_name(&name)
+          .ok_or_else(|| KraglinError::UnknownAclCategory(name.to_string()))?;
+        let mut names = Command::builtins()
+          .into_iter()
+          .filter(|command| command.acl_categories().contains(category))
+          .map(|command| command.full_name())
+          .collect::<Vec<_>>();
+        names.dedup();
+        Ok(Value::Array(
+          names
+            .into_iter()
+            .map(|name| Value::BulkString(name.into()))
+            .collect(),
+        ))
+      }
+      Command::Info => {
+        match self
+          .backend
+          .execute_in(ctx.db(), Command::Info, true)
+          .await?
+        {
+          Value::BulkString(info) => {
+            let mut info = String::from_utf8_lossy(&info).into_owned();
+            self.errors.write_info(&mut info);
+            Ok(Value::BulkString(info.into()))
+          }
+          reply => Ok(reply),
+        }
+      }
+      Command::LatencyLatest => Ok(self.latency.to_value()),
+      Command::LatencyReset { events } => {
+        Ok(Value::Integer(self.latency.reset(&events) as i64))
+      }
+      Command::DebugStringMatchLen => {
+        glob_match_fuzz(STRINGMATCH_LEN_CYCLES, clock::unix_time_ms());
+        Ok(Value::SimpleString(
+          "Apparently kraglin did not crash: test passed".into(),
+        ))
+      }
+      // subscribed RESP2 connections can't tell a reply from a message, so
+      // `PING` replies in the shape of one
+      Command::Ping { message } if ctx.in_subscribed_context() => {
+        Ok(Value::Array(vec![
+          Value::BulkString("pong".into()),
+          Value::BulkString(message.unwrap_or_default()),
+        ]))
+      }
+      Command::Ping { message: None } => Ok(Value::SimpleString("PONG".into())),
+      Command::Ping {
+        message: Some(message),
+      } => Ok(Value::BulkString(message)),
+      Command::Help { command } => Ok(Value::Array(
+        Command::help(&command)
+          .into_iter()
+          .map(|line| Value::SimpleString(line.into()))
+          .collect(),
+      )),
+      Command::Quit => {
+        ctx.close();
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::ReadOnly => {
+        ctx.set_read_only(true);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::ReadWrite => {
+        ctx.set_read_only(false);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::Select { db } => {
+        if db >= self.backend.databases() {
+          return Err(KraglinError::DbIndexOutOfRange);
+        }
+        ctx.set_db(db);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::ClientInfo => Ok(Value::BulkString(ctx.info().into())),
+      Command::ClientNoEvict { enabled } => {
+        ctx.set_no_evict(enabled);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::ClientNoTouch { enabled } => {
+        ctx.set_no_touch(enabled);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      Command::ClientSetInfo { attribute, value } => {
+        ctx.set_client_attribute(attribute, value);
+        Ok(Value::SimpleString("OK".into()))
+      }
+      command @ Command::Migrate { .. } => {
+        migrate::migrate(&*self.backend, ctx.db(), command).await
+      }
+      Command::Custom { name, args, .. } => {
+        match self.commands.execute(&self.backend, &name, args) {
+          Some(result) => result.await,
+          None => Err(KraglinError::UnknownCommand(name.to_lowercase())),
+        }
+      }
+      command => {
+        // `TOUCH` exists to touch keys, so it does even under `NO-TOUCH`
+        let touch =
+          !ctx.is_no_touch() || matches!(command, Command::Touch { .. });
+        self.backend.execute_in(ctx.db(), command, touch).await
+      }
+    }
+  }
+}
+
+/// Whether `command`'s reply can be huge, so that it's worth streaming.
+fn streams_reply(command: &Command) -> bool {
+  match command {
+    Command::Keys => true,
+    #[cfg(feature = "sets")]
+    Command::SetMembers { .. } => true,
+    _ => false,
+  }
+}
+
+/// Whether [`Dispatcher::execute()`] hands `command` to the backend as it
+/// is, rather than handling it itself.
+fn runs_on_backend(command: &Command) -> bool {
+  !matches!(
+    command,
+    Command::Multi
+      | Command::Exec
+      | Command::Discard
+      | Command::Auth { .. }
+      | Command::Hello { .. }
+      | Command::AclSetUser { .. }
+      | Command::AclGetUser { .. }
+      | Command::AclDelUser { .. }
+      | Command::AclList
+      | Command::AclWhoAmI
+      | Command::AclLog { .. }
+      | Command::AclLogReset
+      | Command::AclCat { .. }
+      | Command::Info
+      | Command::LatencyLatest
+      | Command::LatencyReset { .. }
+      | Command::DebugStringMatchLen
+      | Command::Ping { .. }
+      | Command::Help { .. }
+      | Command::Quit
+      | Command::ReadOnly
+      | Command::ReadWrite
+      | Command::Select { .. }
+      | Command::ClientInfo
+      | Command::ClientNoEvict { .. }
+      | Command::ClientNoTouch { .. }
+      | Command::ClientSetInfo { .. }
+      | Command::Migrate { .. }
+      | Command::Custom { .. }
+  )
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::{
+    num::NonZeroU32,
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      Arc, Mutex,
+    },
+    time::Duration,
+  };
+
+  use futures::StreamExt;
+
+  use super::{
+    AclLogReason, CommandInterceptor, CommandRegistry, ConnectionContext,
+    Dispatcher, Intercept,
+  };
+  use crate::{
+    backends::{
+      simple::SimpleBackend, Backend, BackendConfig, BackendExt, ReplyChunk,
+    },
+    command::{parse_timeout, Command, CommandFlags, ParseError},
+    value::Value,
+    KraglinError, KraglinResult,
+  };
+
+  fn frame(args: &[&str]) -> Value {
+    Value::Array(
+      args
+        .iter()
+        .map(|a| Value::BulkString(a.as_bytes().to_vec().into()))
+        .collect(),
+    )
+  }
+
+  /// Records the name of every command it sees.
+  #[derive(Default)]
+  struct Audit(Mutex<Vec<String>>);
+
+  impl CommandInterceptor for Arc<Audit> {
+    fn after(
+      &self,
+      ctx: &mut ConnectionContext,
+      command: &Command,
+      result: &mut KraglinResult,
+    ) {
+      self.0.lock().unwrap().push(format!(
+        "{} {} {}",
+        ctx.peer(),
+        command.command_name(),
+        result.is_ok()
+      ));
+    }
+  }
+
+  /// Rewrites `GET a` to `GET b`.
+  struct Redirect;
+
+  impl CommandInterceptor for Redirect {
+    fn before(&self, _: &mut ConnectionContext, command: Command) -> Intercept {
+      match command {
+        Command::Get { key } if key == "a" => {
+          Intercept::Continue(Command::Get { key: "b".into() })
+        }
+        command => Intercept::Continue(command),
+      }
+    }
+  }
+
+  /// Rejects everything after the first `limit` commands.
+  struct RateLimit {
+    limit: usize,
+    seen:  AtomicUsize,
+  }
+
+  impl CommandInterceptor for RateLimit {
+    fn before(&self, _: &mut ConnectionContext, command: Command) -> Intercept {
+      if self.seen.fetch_add(1, Ordering::SeqCst) < self.limit {
+        Intercept::Continue(command)
+      } else {
+        Intercept::Reply(Err(KraglinError::OutOfRange))
+      }
+    }
+  }
+
+  #[tokio::test]
+  async fn interceptors_wrap_dispatch() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let audit = Arc::new(Audit::default());
+    let dispatcher = Dispatcher::new(
+      Arc::new(backend),
+      vec![
+        Arc::new(audit.clone()),
+        Arc::new(Redirect),
+        Arc::new(RateLimit {
+          limit: 2,
+          seen:  AtomicUsize::new(0),
+        }),
+      ],
+      CommandRegistry::default(),
+    );
+    let mut ctx = ConnectionContext::new("test");
+
+    let set = Command::Set {
+      key:     "b".into(),
+      value:   Value::Integer(1),
+      options: Default::default(),
+    };
+    dispatcher.dispatch(&mut ctx, set).await.unwrap();
+    let get = Command::Get { key: "a".into() };
+    assert_eq!(
+      dispatcher.dispatch(&mut ctx, get.clone()).await.unwrap(),
+      Value::Integer(1)
+    );
+    assert!(dispatcher.dispatch(&mut ctx, get).await.is_err());
+
+    assert_eq!(*audit.0.lock().unwrap(), vec![
+      "test SET true",
+      "test GET true",
+      "test GET false"
+    ]);
+  }
+
+  #[tokio::test]
+  async fn custom_commands_run_their_handler() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut commands = CommandRegistry::default();
+    // `INCRBOTH a b` increments two keys, returning the sum
+    commands.register(
+      "incrboth",
+      3,
+      CommandFlags::WRITE,
+      |backend: Arc<SimpleBackend>, args| async move {
+        let mut sum = 0;
+        for key in args {
+          let key = String::from_utf8_lossy(&key).into_owned();
+          sum += i64::try_from(backend.INCR(key).await?)?;
+        }
+        Ok(Value::Integer(sum))
+      },
+    );
+    let audit = Arc::new(Audit::default());
+    let dispatcher = Dispatcher::new(
+      Arc::new(backend),
+      vec![Arc::new(audit.clone())],
+      commands,
+    );
+    let mut ctx = ConnectionContext::new("test");
+
+    let command = dispatcher.parse(frame(&["IncrBoth", "a", "b"])).unwrap();
+    assert!(command.is_write());
+    assert_eq!(command.to_resp(), frame(&["INCRBOTH", "a", "b"]));
+    for expected in [2, 4] {
+      assert_eq!(
+        dispatcher
+          .dispatch(&mut ctx, command.clone())
+          .await
+          .unwrap(),
+        Value::Integer(expected)
+      );
+    }
+    assert_eq!(
+      dispatcher.backend().GET("b").await.unwrap(),
+      Value::Integer(2)
+    );
+
+    assert_eq!(
+      dispatcher.parse(frame(&["INCRBOTH", "a"])),
+      Err(ParseError::WrongArity("incrboth".into()))
+    );
+    assert!(dispatcher.parse(frame(&["GET", "a"])).is_ok());
+    assert_eq!(*audit.0.lock().unwrap(), vec![
+      "test INCRBOTH true",
+      "test INCRBOTH true"
+    ]);
+  }
+
+  #[tokio::test]
+  async fn requirepass_rejects_commands_until_auth() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let dispatcher =
+      Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default())
+        .with_requirepass("hunter2");
+    let mut ctx = ConnectionContext::new("test");
+    let get = Command::Get { key: "a".into() };
+    let auth = |username: Option<&str>, password: &str| Command::Auth {
+      username: username.map(Into::into),
+      password: password.to_owned().into(),
+    };
+
+    assert!(matches!(
+      dispatcher.dispatch(&mut ctx, get.clone()).await,
+      Err(KraglinError::NoAuth)
+    ));
+    assert!(matches!(
+      dispatcher.dispatch(&mut ctx, auth(None, "hunter3")).await,
+      Err(KraglinError::WrongPass)
+    ));
+    assert!(matches!(
+      dispatcher
+        .dispatch(&mut ctx, auth(Some("admin"), "hunter2"))
+        .await,
+      Err(KraglinError::WrongPass)
+    ));
+    assert!(!ctx.is_authenticated());
+
+    dispatcher
+      .dispatch(&mut ctx, auth(Some("default"), "hunter2"))
+      .await
+      .unwrap();
+    assert!(ctx.is_authenticated());
+    assert_eq!(
+      dispatcher.dispatch(&mut ctx, get).await.unwrap(),
+      Value::Nothing
+    );
+
+    // without `requirepass`, AUTH is an error but nothing is rejected
+    let dispatcher = Dispatcher::new(
+      dispatcher.backend().clone(),
+      vec![],
+      CommandRegistry::default(),
+    );
+    let mut ctx = ConnectionContext::new("test");
+    assert!(matches!(
+      dispatcher.dispatch(&mut ctx, auth(None, "hunter2")).await,
+      Err(KraglinError::AuthNotConfigured)
+    ));
+    dispatcher
+      .dispatch(&mut ctx, Command::Get { key: "a".into() })
+      .await
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn hello_negotiates_the_protocol() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default())
+        .with_requirepass("hunter2");
+    let ctx = &mut ConnectionContext::new("test");
+
+    // unauthenticated connections must authenticate as part of `HELLO`
+    assert!(matches!(
+      run(d, ctx, &["HELLO", "3"]).await,
+      Err(KraglinError::NoAuth)
+    ));
+    assert!(matches!(
+      run(d, ctx, &["HELLO", "3", "AUTH", "default", "hunter3"]).await,
+      Err(KraglinError::WrongPass)
+    ));
+    assert!(matches!(
+      run(d, ctx, &["HELLO", "4", "AUTH", "default", "hunter2"]).await,
+      Err(KraglinError::NoProto)
+    ));
+    assert_eq!(ctx.protocol(), 2);
+    assert!(!ctx.is_authenticated());
+
+    let Value::Map(info) =
+      run(d, ctx, &["HELLO", "3", "AUTH", "default", "hunter2"])
+        .await
+        .unwrap()
+    else {
+      panic!("HELLO replies with a map");
+    };
+    assert!(ctx.is_authenticated());
+    assert_eq!(ctx.protocol(), 3);
+    assert_eq!(info["proto"], Value::Integer(3));
+    assert_eq!(info["id"], Value::Integer(ctx.id() as i64));
+    assert_eq!(info["server"], Value::BulkString("kraglin".into()));
+    assert_eq!(
+      info["git_sha1"],
+      Value::BulkString(crate::version::GIT_SHA.into())
+    );
+
+    // without a version, the connection keeps its own
+    let Value::Map(info) = run(d, ctx, &["HELLO"]).await.unwrap() else {
+      panic!("HELLO replies with a map");
+    };
+    assert_eq!(info["proto"], Value::Integer(3));
+    run(d, ctx, &["HELLO", "2"]).await.unwrap();
+    assert_eq!(ctx.protocol(), 2);
+  }
+
+  /// Parses and dispatches a command.
+  async fn run(
+    dispatcher: &Dispatcher<SimpleBackend>,
+    ctx: &mut ConnectionContext,
+    args: &[&str],
+  ) -> KraglinResult {
+    let command = dispatcher.parse(frame(args)).unwrap();
+    dispatcher.dispatch(ctx, command).await
+  }
+
+  #[tokio::test]
+  async fn acl_users_are_enforced() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let dispatcher =
+      Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let d = &dispatcher;
+
+    // connections start as the default user, which may do anything
+    let admin = &mut ConnectionContext::new("admin");
+    run(d, admin, &[
+      "ACL", "SETUSER", "alice", "on", ">secret", "~cache:*", "+get", "+set",
+    ])
+    .await
+    .unwrap();
+    assert_eq!(
+      run(d, admin, &["ACL", "WHOAMI"]).await.unwrap(),
+      Value::BulkString("default".into())
+    );
+
+    let alice = &mut ConnectionContext::new("alice");
+    run(d, alice, &["AUTH", "alice", "secret"]).await.unwrap();
+    run(d, alice, &["SET", "cache:a", "1"]).await.unwrap();
+    for denied in [&["GET", "other"][..], &["DEL", "cache:a"], &["ACL", "LIST"]]
+    {
+      assert!(matches!(
+        run(d, alice, denied).await,
+        Err(KraglinError::NoPermission(_))
+      ));
+    }
+
+    assert_eq!(
+      run(d, admin, &["ACL", "LIST"]).await.unwrap(),
+      Value::Array(vec![
+        Value::BulkString(
+          "user alice on \
+           #2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b \
+           ~cache:* +get +set"
+            .into()
+        ),
+        Value::BulkString("user default on nopass ~* +@all".into()),
+      ])
+    );
+
+    // deleted users can no longer run commands
+    assert_eq!(
+      run(d, admin, &["ACL", "DELUSER", "alice"]).await.unwrap(),
+      Value::Integer(1)
+    );
+    assert!(matches!(
+      run(d, alice, &["GET", "cache:a"]).await,
+      Err(KraglinError::NoAuth)
+    ));
+  }
+
+  #[tokio::test]
+  async fn acl_log_records_denials() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let admin = &mut ConnectionContext::new("admin");
+    let alice = &mut ConnectionContext::new("10.0.0.1:1234");
+    run(d, admin, &[
+      "ACL", "SETUSER", "alice", "on", ">pw", "~a", "+get",
+    ])
+    .await
+    .unwrap();
+
+    assert!(run(d, alice, &["AUTH", "alice", "wrong"]).await.is_err());
+    run(d, alice, &["AUTH", "alice", "pw"]).await.unwrap();
+    assert!(run(d, alice, &["SET", "a", "1"]).await.is_err());
+    assert!(run(d, alice, &["GET", "b"]).await.is_err());
+    assert!(run(d, alice, &["GET", "b"]).await.is_err());
+
+    let entries = d.acl().log().entries(10);
+    let summary = entries
+      .iter()
+      .map(|e| (e.reason(), e.object(), e.username(), e.count()))
+      .collect::<Vec<_>>();
+    assert_eq!(summary, [
+      (AclLogReason::Key, "get", "alice", 2),
+      (AclLogReason::Command, "set", "alice", 1),
+      (AclLogReason::Auth, "auth", "alice", 1),
+    ]);
+    assert_eq!(entries[0].client(), "10.0.0.1:1234");
+
+    let Value::Array(log) = run(d, admin, &["ACL", "LOG", "1"]).await.unwrap()
+    else {
+      panic!("expected an array");
+    };
+    assert_eq!(log.len(), 1);
+
+    run(d, admin, &["ACL", "LOG", "RESET"]).await.unwrap();
+    assert_eq!(
+      run(d, admin, &["ACL", "LOG"]).await.unwrap(),
+      Value::Array(vec![])
+    );
+  }
+
+  #[tokio::test]
+  async fn acl_cat_lists_categories_and_their_commands() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+
+    let Value::Array(categories) = run(d, ctx, &["ACL", "CAT"]).await.unwrap()
+    else {
+      panic!("expected an array");
+    };
+    assert!(categories.contains(&Value::BulkString("dangerous".into())));
+
+    let Value::Array(admin) =
+      run(d, ctx, &["ACL", "CAT", "ADMIN"]).await.unwrap()
+    else {
+      panic!("expected an array");
+    };
+    assert!(admin.contains(&Value::BulkString("acl|setuser".into())));
+    assert!(!admin.contains(&Value::BulkString("get".into())));
+
+    assert!(matches!(
+      run(d, ctx, &["ACL", "CAT", "bogus"]).await,
+      Err(KraglinError::UnknownAclCategory(_))
+    ));
+  }
+
+  #[tokio::test]
+  async fn client_no_touch_leaves_hot_keys_alone() {
+    let backend = SimpleBackend::new(BackendConfig {
+      hot_keys_sample_rate: NonZeroU32::new(1),
+      ..Default::default()
+    })
+    .unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("monitor");
+
+    run(d, ctx, &["CLIENT", "NO-TOUCH", "ON"]).await.unwrap();
+    assert!(ctx.is_no_touch());
+    run(d, ctx, &["GET", "a"]).await.unwrap();
+    assert_eq!(
+      run(d, ctx, &["DEBUG", "HOTKEYS"]).await.unwrap(),
+      Value::Array(vec![])
+    );
+    run(d, ctx, &["TOUCH", "b"]).await.unwrap();
+    assert_eq!(
+      run(d, ctx, &["DEBUG", "HOTKEYS"]).await.unwrap(),
+      Value::Array(vec![Value::BulkString("b".into()), Value::Integer(1)])
+    );
+
+    run(d, ctx, &["CLIENT", "NO-TOUCH", "OFF"]).await.unwrap();
+    run(d, ctx, &["GET", "a"]).await.unwrap();
+    assert_eq!(
+      run(d, ctx, &["DEBUG", "HOTKEYS"]).await.unwrap(),
+      Value::Array(vec![
+        Value::BulkString("a".into()),
+        Value::Integer(1),
+        Value::BulkString("b".into()),
+        Value::Integer(1),
+      ])
+    );
+
+    run(d, ctx, &["CLIENT", "NO-EVICT", "ON"]).await.unwrap();
+    assert!(ctx.is_no_evict());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn client_info_describes_the_connection() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("127.0.0.1:6000");
+
+    run(d, ctx, &["SET", "a", "1"]).await.unwrap();
+    assert_eq!(ctx.last_command(), Some("set"));
+    tokio::time::advance(Duration::from_secs(3)).await;
+    assert_eq!(ctx.idle(), Duration::from_secs(3));
+
+    run(d, ctx, &["CLIENT", "NO-TOUCH", "ON"]).await.unwrap();
+    run(d, ctx, &["CLIENT", "SETINFO", "lib-name", "redis-rs"])
+      .await
+      .unwrap();
+    assert!(d
+      .parse(frame(&["CLIENT", "SETINFO", "LIB-VER", "1 2"]))
+      .is_err());
+    let Value::BulkString(info) =
+      run(d, ctx, &["CLIENT", "INFO"]).await.unwrap()
+    else {
+      panic!("expected a bulk string");
+    };
+    let info = std::str::from_utf8(&info).unwrap();
+    let fields = info
+      .trim_end()
+      .split(' ')
+      .filter_map(|field| field.split_once('='))
+      .collect::<std::collections::HashMap<_, _>>();
+    assert_eq!(fields["id"], ctx.id().to_string());
+    assert_eq!(fields["addr"], "127.0.0.1:6000");
+    assert_eq!(fields["age"], "3");
+    assert_eq!(fields["idle"], "0");
+    assert_eq!(fields["flags"], "T");
+    assert_eq!(fields["tot-cmds"], "4");
+    assert_eq!(fields["user"], "default");
+    assert_eq!(fields["cmd"], "client|info");
+    assert_eq!(fields["lib-name"], "redis-rs");
+    assert_eq!(fields["lib-ver"], "");
+    assert!(info.ends_with('\n'));
+  }
+
+  #[tokio::test]
+  async fn debug_stringmatch_len_fuzzes_the_glob_matcher() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+
+    assert_eq!(
+      run(d, ctx, &["DEBUG", "STRINGMATCH-LEN"]).await.unwrap(),
+      Value::SimpleString(
+        "Apparently kraglin did not crash: test passed".into()
+      )
+    );
+  }
+
+  #[tokio::test]
+  async fn subscribed_resp2_connections_only_run_subscribe_commands() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+
+    assert_eq!(
+      run(d, ctx, &["PING"]).await.unwrap(),
+      Value::SimpleString("PONG".into())
+    );
+    ctx.set_subscriptions(1);
+    assert!(matches!(
+      run(d, ctx, &["GET", "a"]).await,
+      Err(KraglinError::SubscribedContext(name)) if name == "get"
+    ));
+    assert!(matches!(
+      run(d, ctx, &["CLIENT", "INFO"]).await,
+      Err(KraglinError::SubscribedContext(name)) if name == "client|info"
+    ));
+    assert_eq!(
+      run(d, ctx, &["PING", "hi"]).await.unwrap(),
+      Value::Array(vec![
+        Value::BulkString("pong".into()),
+        Value::BulkString("hi".into())
+      ])
+    );
+
+    // RESP3 connections may run anything while subscribed
+    ctx.set_protocol(3);
+    assert_eq!(run(d, ctx, &["GET", "a"]).await.unwrap(), Value::Nothing);
+    assert_eq!(
+      run(d, ctx, &["PING", "hi"]).await.unwrap(),
+      Value::BulkString("hi".into())
+    );
+  }
+
+  #[tokio::test]
+  async fn replicas_only_serve_reads_to_read_only_connections() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default())
+        .with_replica();
+    let ctx = &mut ConnectionContext::new("test");
+    let ok = || Value::SimpleString("OK".into());
+
+    // like the master's replication link, connections which haven't opted
+    // into reading may write, but not read
+    assert_eq!(run(d, ctx, &["SET", "a", "1"]).await.unwrap(), ok());
+    assert!(matches!(
+      run(d, ctx, &["GET", "a"]).await,
+      Err(KraglinError::ReplicaReadWithoutReadOnly)
+    ));
+    assert_eq!(
+      run(d, ctx, &["PING"]).await.unwrap(),
+      Value::SimpleString("PONG".into())
+    );
+
+    assert_eq!(run(d, ctx, &["READONLY"]).await.unwrap(), ok());
+    assert!(ctx.info().contains(" flags=r "));
+    assert_eq!(
+      run(d, ctx, &["GET", "a"]).await.unwrap(),
+      Value::BulkString("1".into())
+    );
+    assert!(matches!(
+      run(d, ctx, &["SET", "a", "2"]).await,
+      Err(KraglinError::ReadOnlyReplica)
+    ));
+
+    assert_eq!(run(d, ctx, &["READWRITE"]).await.unwrap(), ok());
+    assert_eq!(run(d, ctx, &["SET", "a", "2"]).await.unwrap(), ok());
+  }
+
+  #[tokio::test]
+  async fn select_switches_between_separate_databases() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+    let ok = || Value::SimpleString("OK".into());
+
+    run(d, ctx, &["SET", "a", "0"]).await.unwrap();
+    assert_eq!(run(d, ctx, &["SELECT", "1"]).await.unwrap(), ok());
+    assert!(ctx.info().contains(" db=1 "));
+    assert_eq!(run(d, ctx, &["GET", "a"]).await.unwrap(), Value::Nothing);
+    run(d, ctx, &["SET", "a", "1"]).await.unwrap();
+
+    // the index is checked before the connection switches
+    assert!(matches!(
+      run(d, ctx, &["SELECT", "16"]).await,
+      Err(KraglinError::DbIndexOutOfRange)
+    ));
+    assert!(d.parse(frame(&["SELECT", "-1"])).is_err());
+    assert_eq!(
+      run(d, ctx, &["GET", "a"]).await.unwrap(),
+      Value::BulkString("1".into())
+    );
+
+    // commands queued after a `SELECT` run in the database it selects
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    run(d, ctx, &["SELECT", "0"]).await.unwrap();
+    run(d, ctx, &["GET", "a"]).await.unwrap();
+    assert_eq!(
+      run(d, ctx, &["EXEC"]).await.unwrap(),
+      Value::Array(vec![ok(), Value::BulkString("0".into())])
+    );
+    assert!(ctx.info().contains(" db=0 "));
+  }
+
+  #[tokio::test]
+  async fn errors_are_counted_by_command_and_code() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+
+    run(d, ctx, &["SET", "a", "x"]).await.unwrap();
+    run(d, ctx, &["INCR", "a"]).await.unwrap_err();
+    run(d, ctx, &["ACL", "SETUSER", "a", "nope"])
+      .await
+      .unwrap_err();
+    #[cfg(feature = "sets")]
+    run(d, ctx, &["SADD", "a", "1"]).await.unwrap_err();
+
+    assert_eq!(d.errors().command_failures("incr"), 1);
+    assert_eq!(d.errors().command_failures("acl|setuser"), 1);
+    assert_eq!(d.errors().command_failures("set"), 0);
+    assert_eq!(d.errors().code_count("ERR"), 2);
+
+    let Value::BulkString(info) = run(d, ctx, &["INFO"]).await.unwrap() else {
+      panic!("INFO should return a bulk string");
+    };
+    let info = String::from_utf8(info.to_vec()).unwrap();
+    assert!(info.contains("\r\ncmdstat_incr:failed_calls=1\r\n"));
+    assert!(info.contains("\r\nerrorstat_ERR:count=2\r\n"));
+    #[cfg(feature = "sets")]
+    assert!(info.contains("\r\nerrorstat_WRONGTYPE:count=1\r\n"));
+  }
+
+  #[tokio::test]
+  async fn exec_aborts_transactions_with_rejected_commands() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+    let ok = || Value::SimpleString("OK".into());
+    let queued = || Value::SimpleString("QUEUED".into());
+
+    assert!(matches!(
+      run(d, ctx, &["EXEC"]).await,
+      Err(KraglinError::ExecWithoutMulti)
+    ));
+
+    // a command which fails to parse aborts the transaction
+    assert_eq!(run(d, ctx, &["MULTI"]).await.unwrap(), ok());
+    assert_eq!(run(d, ctx, &["SET", "a", "1"]).await.unwrap(), queued());
+    assert!(d.parse_for(ctx, frame(&["NOSUCHCOMMAND"])).is_err());
+    assert!(d.parse_for(ctx, frame(&["GET"])).is_err());
+    assert!(matches!(
+      run(d, ctx, &["EXEC"]).await,
+      Err(KraglinError::ExecAbort)
+    ));
+    assert_eq!(run(d, ctx, &["GET", "a"]).await.unwrap(), Value::Nothing);
+
+    // so does one which is rejected before it's queued
+    run(d, ctx, &[
+      "ACL", "SETUSER", "alice", "on", "nopass", "+@all", "~a",
+    ])
+    .await
+    .unwrap();
+    run(d, ctx, &["AUTH", "alice", "x"]).await.unwrap();
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    assert!(matches!(
+      run(d, ctx, &["GET", "b"]).await,
+      Err(KraglinError::NoPermission(_))
+    ));
+    assert!(matches!(
+      run(d, ctx, &["MULTI"]).await,
+      Err(KraglinError::NestedMulti)
+    ));
+    assert!(matches!(
+      run(d, ctx, &["EXEC"]).await,
+      Err(KraglinError::ExecAbort)
+    ));
+
+    // errors while running don't abort the transaction
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    assert!(ctx.info().contains(" flags=x "));
+    run(d, ctx, &["SET", "a", "x"]).await.unwrap();
+    run(d, ctx, &["INCR", "a"]).await.unwrap();
+    run(d, ctx, &["GET", "a"]).await.unwrap();
+    assert!(ctx.info().contains(" multi=3 "));
+    let Value::Array(replies) = run(d, ctx, &["EXEC"]).await.unwrap() else {
+      panic!("expected an array");
+    };
+    assert_eq!(replies.len(), 3);
+    assert!(matches!(replies[1], Value::Error(_)));
+    assert_eq!(replies[2], Value::BulkString("x".into()));
+
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    run(d, ctx, &["SET", "a", "y"]).await.unwrap();
+    assert_eq!(run(d, ctx, &["DISCARD"]).await.unwrap(), ok());
+    assert_eq!(
+      run(d, ctx, &["GET", "a"]).await.unwrap(),
+      Value::BulkString("x".into())
+    );
+    assert!(matches!(
+      run(d, ctx, &["DISCARD"]).await,
+      Err(KraglinError::DiscardWithoutMulti)
+    ));
+  }
+
+  #[tokio::test]
+  async fn read_only_transactions_run_on_a_snapshot() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+    run(d, ctx, &["SET", "a", "1"]).await.unwrap();
+
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    run(d, ctx, &["GET", "a"]).await.unwrap();
+    run(d, ctx, &["MGET", "a", "b"]).await.unwrap();
+    run(d, ctx, &["EXISTS", "a", "b"]).await.unwrap();
+    assert!(d.runs_on_snapshot(ctx, &[
+      Command::parse(frame(&["GET", "a"])).unwrap(),
+      Command::parse(frame(&["KEYS", "*"])).unwrap(),
+    ]));
+    assert_eq!(
+      run(d, ctx, &["EXEC"]).await.unwrap(),
+      Value::Array(vec![
+        Value::BulkString("1".into()),
+        Value::Array(vec![Value::BulkString("1".into()), Value::Nothing]),
+        Value::Integer(1),
+      ])
+    );
+
+    // anything which writes, or is handled by the dispatcher, can't
+    let set = Command::parse(frame(&["SET", "a", "2"])).unwrap();
+    let ping = Command::parse(frame(&["PING"])).unwrap();
+    assert!(!d.runs_on_snapshot(ctx, &[set]));
+    assert!(!d.runs_on_snapshot(ctx, &[ping]));
+  }
+
+  #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+  async fn transactions_run_without_other_writes_in_between() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d = Arc::new(Dispatcher::new(
+      Arc::new(backend),
+      vec![],
+      CommandRegistry::default(),
+    ));
+    let writer = tokio::spawn({
+      let d = d.clone();
+      async move {
+        let ctx = &mut ConnectionContext::new("writer");
+        for _ in 0..2000 {
+          run(&d, ctx, &["SET", "n", "0"]).await.unwrap();
+          tokio::task::yield_now().await;
+        }
+      }
+    });
+
+    let ctx = &mut ConnectionContext::new("test");
+    for _ in 0..500 {
+      run(&d, ctx, &["MULTI"]).await.unwrap();
+      run(&d, ctx, &["INCR", "n"]).await.unwrap();
+      run(&d, ctx, &["INCR", "n"]).await.unwrap();
+      let Value::Array(replies) = run(&d, ctx, &["EXEC"]).await.unwrap() else {
+        panic!("expected an array");
+      };
+      let Value::Integer(first) = replies[0] else {
+        panic!("expected an integer");
+      };
+      // the writer's `SET` never lands between the increments
+      assert_eq!(replies[1], Value::Integer(first + 1));
+    }
+    writer.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn large_replies_stream_unless_something_needs_them_whole() {
+    let backend = || SimpleBackend::new(BackendConfig::default()).unwrap();
+    let d =
+      &Dispatcher::new(Arc::new(backend()), vec![], CommandRegistry::default());
+    let ctx = &mut ConnectionContext::new("test");
+    run(d, ctx, &["SELECT", "1"]).await.unwrap();
+    run(d, ctx, &["SET", "a", "1"]).await.unwrap();
+
+    assert!(d.streams(ctx, &Command::Keys));
+    assert!(!d.streams(ctx, &Command::Get { key: "a".into() }));
+    let chunks = d
+      .dispatch_streaming(ctx, Command::Keys)
+      .collect::<Vec<_>>()
+      .await;
+    assert_eq!(
+      chunks.into_iter().collect::<Result<Vec<_>, _>>().unwrap(),
+      [
+        ReplyChunk::ArrayHeader(1),
+        ReplyChunk::Elements(vec![Value::BulkString("a".into())]),
+      ]
+    );
+    // failures are counted like other commands'
+    run(d, ctx, &["ACL", "SETUSER", "default", "-keys"])
+      .await
+      .unwrap();
+    let chunks = d
+      .dispatch_streaming(ctx, Command::Keys)
+      .collect::<Vec<_>>()
+      .await;
+    assert!(matches!(chunks[..], [Err(KraglinError::NoPermission(_))]));
+    assert_eq!(d.errors().command_failures("keys"), 1);
+
+    // transactions queue the command, and interceptors and the command
+    // timeout need the whole reply
+    run(d, ctx, &["MULTI"]).await.unwrap();
+    assert!(!d.streams(ctx, &Command::Keys));
+    let intercepted = Dispatcher::new(
+      Arc::new(backend()),
+      vec![Arc::new(Redirect)],
+      CommandRegistry::default(),
+    );
+    let timed =
+      Dispatcher::new(Arc::new(backend()), vec![], CommandRegistry::default())
+        .with_command_timeout(Duration::from_secs(5));
+    let ctx = &ConnectionContext::new("test");
+    assert!(!intercepted.streams(ctx, &Command::Keys));
+    assert!(!timed.streams(ctx, &Command::Keys));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn slow_commands_are_cancelled_and_recorded() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut commands = CommandRegistry::default();
+    commands.register(
+      "sleep",
+      2,
+      CommandFlags::empty(),
+      |_: Arc<SimpleBackend>, args| async move {
+        tokio::time::sleep(parse_timeout(&args[0])?).await;
+        Ok(Value::SimpleString("OK".into()))
+      },
+    );
+    let d = &Dispatcher::new(Arc::new(backend), vec![], commands)
+      .with_command_timeout(Duration::from_secs(5));
+    let ctx = &mut ConnectionContext::new("test");
+
+    assert!(run(d, ctx, &["SLEEP", "1"]).await.is_ok());
+    assert!(matches!(
+      run(d, ctx, &["SLEEP", "-1"]).await,
+      Err(KraglinError::NegativeTimeout)
+    ));
+    let err = run(d, ctx, &["SLEEP", "60"]).await.unwrap_err();
+    assert!(matches!(err, KraglinError::CommandTimeout { .. }));
+    assert_eq!(
+      err.to_string(),
+      "BUSY command 'sleep' exceeded the maximum execution time of 5000 ms"
+    );
+
+    let Value::Array(latest) =
+      run(d, ctx, &["LATENCY", "LATEST"]).await.unwrap()
+    else {
+      panic!("expected an array");
+    };
+    let Value::Array(event) = &latest[0] else {
+      panic!("expected an array");
+    };
+    assert_eq!(event[0], Value::BulkString("command-timeout".into()));
+    assert_eq!(event[2..], [Value::Integer(5000), Value::Integer(5000)]);
+
+    assert_eq!(
+      run(d, ctx, &["LATENCY", "RESET"]).await.unwrap(),
+      Value::Integer(1)
+    );
+    assert_eq!(
+      run(d, ctx, &["LATENCY", "LATEST"]).await.unwrap(),
+      Value::Array(vec![])
+    );
+  }
+}
@@ -0,0 +1,59 @@
+//! Defines the `ErrorStats` item, which counts the errors replied to clients
+//! by command and by error code, exposed through `INFO`.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+use smol_str::SmolStr;
+
+/// Counts of the errors commands have replied with, like Redis' error
+/// statistics, so that operators can tell which commands fail, and how.
+#[derive(Debug, Default)]
+pub struct ErrorStats {
+  counts: Mutex<ErrorCounts>,
+}
+
+#[derive(Debug, Default)]
+struct ErrorCounts {
+  /// Failures by the command's full name.
+  by_command: BTreeMap<String, u64>,
+  /// Errors by their code, like `WRONGTYPE`.
+  by_code:    BTreeMap<SmolStr, u64>,
+}
+
+impl ErrorStats {
+  /// Records that `command`, by its full name, failed with an error whose
+  /// code is `code`.
+  pub fn record(&self, command: &str, code: &str) {
+    let mut counts = self.counts.lock().unwrap();
+    *counts.by_command.entry(command.to_owned()).or_default() += 1;
+    *counts.by_code.entry(code.into()).or_default() += 1;
+  }
+
+  /// Returns how many times `command`, by its full name, has failed.
+  pub fn command_failures(&self, command: &str) -> u64 {
+    let counts = self.counts.lock().unwrap();
+    counts.by_command.get(command).copied().unwrap_or_default()
+  }
+
+  /// Returns how many errors with `code` have been replied.
+  pub fn code_count(&self, code: &str) -> u64 {
+    let counts = self.counts.lock().unwrap();
+    counts.by_code.get(code).copied().unwrap_or_default()
+  }
+
+  /// Appends the counts to an `INFO` reply, as Redis' `Commandstats` and
+  /// `Errorstats` sections, which list `cmdstat_<command>:failed_calls=<n>`
+  /// and `errorstat_<code>:count=<n>` for every command and code which has
+  /// failed.
+  pub fn write_info(&self, info: &mut String) {
+    let counts = self.counts.lock().unwrap();
+    let _ = write!(info, "\r\n# Commandstats\r\n");
+    for (command, failures) in &counts.by_command {
+      let _ = write!(info, "cmdstat_{command}:failed_calls={failures}\r\n");
+    }
+    let _ = write!(info, "\r\n# Errorstats\r\n");
+    for (code, count) in &counts.by_code {
+      let _ = write!(info, "errorstat_{code}:count={count}\r\n");
+    }
+  }
+}
@@ -0,0 +1,100 @@
+//! Defines the HTTP health probes, which report whether the server is alive
+//! and ready for traffic, e.g. for Kubernetes' liveness and readiness probes.
+//!
+//! The probes are served on their own TCP socket, apart from the RESP
+//! listeners, and speak just enough HTTP/1.1 for probes: `GET /livez` replies
+//! `200` as long as the server is running, and `GET /readyz` replies `200`
+//! if it's ready for traffic, or `503` with the reason it isn't. A server
+//! which is shutting down isn't ready, and neither is one whose backend
+//! reports that it isn't (see [`Backend::readiness()`]).
+
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::{
+  io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+  net::{TcpListener, TcpStream},
+  sync::watch,
+};
+
+use crate::{backends::Backend, clock};
+
+/// The longest request line which is accepted.
+const MAX_REQUEST_LINE_LEN: usize = 1024;
+
+/// How long a probe has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves probes from `listener` for the server whose backend is `backend`,
+/// until the task is aborted. `shutdown` is the server's shutdown signal.
+pub(crate) async fn serve_probes<B: Backend>(
+  listener: TcpListener,
+  backend: Arc<B>,
+  shutdown: watch::Receiver<bool>,
+) {
+  loop {
+    let (stream, addr) = match listener.accept().await {
+      Ok(accepted) => accepted,
+      Err(e) => {
+        tracing::warn!("failed to accept health probe: {e}");
+        continue;
+      }
+    };
+    let backend = backend.clone();
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+      let probe = respond(stream, &*backend, &shutdown);
+      match clock::timeout(REQUEST_TIMEOUT, probe).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::debug!("health probe from {addr} failed: {e}"),
+        Err(_) => tracing::debug!("health probe from {addr} timed out"),
+      }
+    });
+  }
+}
+
+/// Reads a probe's request from `stream`, and replies with the status of
+/// the probed endpoint.
+async fn respond<B: Backend>(
+  stream: TcpStream,
+  backend: &B,
+  shutdown: &watch::Receiver<bool>,
+) -> io::Result<()> {
+  let mut socket = BufReader::new(stream);
+  let mut request_line = Vec::new();
+  (&mut socket)
+    .take(MAX_REQUEST_LINE_LEN as u64)
+    .read_until(b'\n', &mut request_line)
+    .await?;
+
+  let mut parts = request_line.split(|&b| b == b' ');
+  let (status, body) = match (parts.next(), parts.next()) {
+    (Some(b"GET"), Some(b"/livez")) => ("200 OK", "ok".to_owned()),
+    (Some(b"GET"), Some(b"/readyz")) => match readiness(backend, shutdown) {
+      Ok(()) => ("200 OK", "ok".to_owned()),
+      Err(reason) => ("503 Service Unavailable", reason),
+    },
+    (Some(b"GET"), Some(_)) => ("404 Not Found", "not found".to_owned()),
+    _ => ("400 Bad Request", "bad request".to_owned()),
+  };
+  // the rest of the request is ignored, so the connection is closed after
+  // the response
+  let response = format!(
+    "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: \
+     {}\r\nConnection: close\r\n\r\n{body}",
+    body.len()
+  );
+  let stream = socket.get_mut();
+  stream.write_all(response.as_bytes()).await?;
+  stream.shutdown().await
+}
+
+/// Returns whether the server is ready for traffic, or why not.
+fn readiness<B: Backend>(
+  backend: &B,
+  shutdown: &watch::Receiver<bool>,
+) -> Result<(), String> {
+  if *shutdown.borrow() {
+    return Err("shutting down".to_owned());
+  }
+  backend.readiness()
+}
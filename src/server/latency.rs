@@ -0,0 +1,93 @@
+//! Defines the `LatencyMonitor` item, which records latency spikes by event,
+//! exposed through `LATENCY LATEST`.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+use smol_str::SmolStr;
+
+use crate::{clock, value::Value};
+
+/// The event recorded when a command exceeds the dispatcher's command
+/// timeout.
+pub const COMMAND_TIMEOUT_EVENT: &str = "command-timeout";
+
+/// The latest and worst latency of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencySample {
+  /// When the event last happened, in seconds since the unix epoch.
+  pub latest_at: u64,
+  /// How long the event took the last time it happened.
+  pub latest:    Duration,
+  /// The longest the event has taken.
+  pub max:       Duration,
+}
+
+/// The latency spikes recorded for each event, like Redis' latency monitor.
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+  events: Mutex<BTreeMap<SmolStr, LatencySample>>,
+}
+
+impl LatencyMonitor {
+  /// Records that `event` took `latency`.
+  pub fn record(&self, event: &str, latency: Duration) {
+    let latest_at = clock::unix_time().as_secs();
+    self
+      .events
+      .lock()
+      .unwrap()
+      .entry(event.into())
+      .and_modify(|sample| {
+        sample.latest_at = latest_at;
+        sample.latest = latency;
+        sample.max = sample.max.max(latency);
+      })
+      .or_insert(LatencySample {
+        latest_at,
+        latest: latency,
+        max: latency,
+      });
+  }
+
+  /// Returns the sample for `event`, if it has happened.
+  pub fn sample(&self, event: &str) -> Option<LatencySample> {
+    self.events.lock().unwrap().get(event).copied()
+  }
+
+  /// Clears the samples of `events`, or of every event if it's empty,
+  /// returning how many were cleared.
+  pub fn reset(&self, events: &[SmolStr]) -> usize {
+    let mut samples = self.events.lock().unwrap();
+    if events.is_empty() {
+      let cleared = samples.len();
+      samples.clear();
+      return cleared;
+    }
+    events
+      .iter()
+      .filter(|event| samples.remove(*event).is_some())
+      .count()
+  }
+
+  /// Describes every event as a `LATENCY LATEST` reply: the event's name,
+  /// when it last happened, and its latest and maximum latency in
+  /// milliseconds.
+  pub fn to_value(&self) -> Value {
+    Value::Array(
+      self
+        .events
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(event, sample)| {
+          Value::Array(vec![
+            Value::BulkString(event.as_bytes().to_vec().into()),
+            Value::Integer(sample.latest_at as i64),
+            Value::Integer(sample.latest.as_millis() as i64),
+            Value::Integer(sample.max.as_millis() as i64),
+          ])
+        })
+        .collect(),
+    )
+  }
+}
@@ -0,0 +1,92 @@
+//! Defines the `Listener` trait, which abstracts over the sockets the server
+//! accepts connections on.
+
+use std::{fmt, io, net::SocketAddr, path::PathBuf};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::{
+  io::{AsyncRead, AsyncWrite},
+  net::{TcpListener, UnixListener},
+};
+
+/// A bidirectional byte stream which a connection is served over.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for S {}
+
+/// A boxed [`AsyncStream`], as returned by [`Listener::accept()`].
+pub type BoxedStream = Box<dyn AsyncStream>;
+
+/// The address a [`Listener`] is bound to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+  /// A TCP socket address.
+  Tcp(SocketAddr),
+  /// A unix socket path.
+  Unix(PathBuf),
+  /// The TCP socket address of a
+  /// [`WebSocketListener`](super::WebSocketListener).
+  WebSocket(SocketAddr),
+  /// The address of a custom listener, described for logging.
+  Other(String),
+}
+
+impl fmt::Display for ListenAddr {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ListenAddr::Tcp(addr) => write!(f, "{addr}"),
+      ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+      ListenAddr::WebSocket(addr) => write!(f, "ws://{addr}"),
+      ListenAddr::Other(description) => f.write_str(description),
+    }
+  }
+}
+
+/// A source of client connections.
+///
+/// This is implemented for [`TcpListener`], [`UnixListener`], and
+/// [`WebSocketListener`](super::WebSocketListener). Other
+/// transports, like TLS, can be served by implementing it for a wrapper
+/// which performs the handshake in [`Listener::accept()`].
+pub trait Listener: Send + Sync + 'static {
+  /// Waits for the next connection, returning its stream and a description
+  /// of the peer for logging.
+  fn accept(&self) -> BoxFuture<'_, io::Result<(BoxedStream, String)>>;
+
+  /// Returns the address the listener is bound to.
+  fn local_addr(&self) -> io::Result<ListenAddr>;
+}
+
+impl Listener for TcpListener {
+  fn accept(&self) -> BoxFuture<'_, io::Result<(BoxedStream, String)>> {
+    TcpListener::accept(self)
+      .map(|accepted| {
+        let (stream, addr) = accepted?;
+        Ok((Box::new(stream) as BoxedStream, addr.to_string()))
+      })
+      .boxed()
+  }
+
+  fn local_addr(&self) -> io::Result<ListenAddr> {
+    TcpListener::local_addr(self).map(ListenAddr::Tcp)
+  }
+}
+
+impl Listener for UnixListener {
+  fn accept(&self) -> BoxFuture<'_, io::Result<(BoxedStream, String)>> {
+    UnixListener::accept(self)
+      .map(|accepted| {
+        let (stream, addr) = accepted?;
+        Ok((Box::new(stream) as BoxedStream, format!("{addr:?}")))
+      })
+      .boxed()
+  }
+
+  fn local_addr(&self) -> io::Result<ListenAddr> {
+    let addr = UnixListener::local_addr(self)?;
+    Ok(match addr.as_pathname() {
+      Some(path) => ListenAddr::Unix(path.to_owned()),
+      None => ListenAddr::Other(format!("{addr:?}")),
+    })
+  }
+}
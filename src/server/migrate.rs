@@ -0,0 +1,194 @@
+//! Defines how `MIGRATE` runs, which copies or moves keys to another kraglin
+//! or Redis instance, e.g. to reshard by hand without cluster mode.
+//!
+//! Keys are migrated the way Redis migrates them: each is serialized with
+//! `DUMP` and sent to the target as a `RESTORE` (with `ABSTTL` and its
+//! deadline, if it expires), pipelined after an `AUTH`
+//! and a `SELECT` of the destination database if they're needed. The keys
+//! the target restores are then deleted here, unless `COPY` was given, so a
+//! key the target rejects (e.g. because it already exists and `REPLACE`
+//! wasn't given) stays where it was.
+
+use std::{future::Future, io, time::Duration};
+
+use bytes::BytesMut;
+use tokio::{
+  io::{AsyncReadExt, AsyncWriteExt},
+  net::TcpStream,
+};
+
+use crate::{
+  backends::Backend,
+  clock,
+  command::Command,
+  resp,
+  value::{Dump, Value},
+  KraglinError, KraglinResult,
+};
+
+/// How long the target may take to connect or reply when `MIGRATE` is given
+/// a timeout which isn't positive, like in Redis.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Runs `command`, which must be a [`Command::Migrate`], against database
+/// `from` of `backend`.
+pub(crate) async fn migrate<B: Backend>(
+  backend: &B,
+  from: usize,
+  command: Command,
+) -> KraglinResult {
+  let Command::Migrate {
+    host,
+    port,
+    keys,
+    db,
+    timeout,
+    copy,
+    replace,
+    auth,
+  } = command
+  else {
+    unreachable!("only `MIGRATE` is migrated");
+  };
+
+  let mut dumps = Vec::new();
+  for key in keys {
+    let dump = backend
+      .execute_in(from, Command::Dump { key: key.clone() }, true)
+      .await?;
+    if let Value::BulkString(payload) = dump {
+      dumps.push((key, payload));
+    }
+  }
+  if dumps.is_empty() {
+    return Ok(Value::SimpleString("NOKEY".into()));
+  }
+
+  let timeout = u64::try_from(timeout)
+    .ok()
+    .filter(|&ms| ms > 0)
+    .map_or(DEFAULT_TIMEOUT, Duration::from_millis);
+  let mut target = Target::connect(&host, port, timeout).await?;
+
+  let mut requests = Vec::new();
+  if let Some((username, password)) = auth {
+    requests.push(Command::Auth { username, password }.to_resp());
+  }
+  if db != 0 {
+    requests.push(Value::Array(vec![
+      Value::BulkString("SELECT".into()),
+      Value::BulkString(db.to_string().into()),
+    ]));
+  }
+  let setup = requests.len();
+  requests.extend(dumps.iter().map(|(key, payload)| {
+    let deadline = Dump::decode(payload)
+      .ok()
+      .and_then(|dump| dump.deadline)
+      .and_then(|deadline| i64::try_from(deadline).ok());
+    Command::Restore {
+      key: key.clone(),
+      ttl: deadline.unwrap_or(0),
+      payload: payload.clone(),
+      replace,
+      absttl: deadline.is_some(),
+    }
+    .to_resp()
+  }));
+  target.send(requests).await?;
+
+  for _ in 0..setup {
+    if let Value::Error(e) = target.reply().await? {
+      return Err(KraglinError::MigrateTarget(e.to_string()));
+    }
+  }
+  let (mut migrated, mut error) = (Vec::new(), None);
+  for (key, _) in dumps {
+    match target.reply().await? {
+      Value::Error(e) => {
+        error.get_or_insert(e);
+      }
+      _ => migrated.push(key),
+    }
+  }
+  if !copy && !migrated.is_empty() {
+    backend
+      .execute_in(from, Command::Delete { keys: migrated }, true)
+      .await?;
+  }
+
+  match error {
+    Some(e) => Err(KraglinError::MigrateTarget(e.to_string())),
+    None => Ok(Value::SimpleString("OK".into())),
+  }
+}
+
+/// A connection to the instance keys are migrated to.
+struct Target {
+  stream:  TcpStream,
+  /// Replies which have been read but not yet decoded.
+  buffer:  BytesMut,
+  /// How long each step of the migration may take.
+  timeout: Duration,
+}
+
+impl Target {
+  /// Connects to the target at `host` and `port`.
+  async fn connect(
+    host: &str,
+    port: u16,
+    timeout: Duration,
+  ) -> Result<Self, KraglinError> {
+    let stream =
+      within(timeout, "connecting to", TcpStream::connect((host, port)))
+        .await?;
+    Ok(Target {
+      stream,
+      buffer: BytesMut::new(),
+      timeout,
+    })
+  }
+
+  /// Sends `requests` to the target, without waiting for their replies.
+  async fn send(&mut self, requests: Vec<Value>) -> Result<(), KraglinError> {
+    let mut buf = BytesMut::new();
+    for request in requests {
+      request.write_resp(2, &mut buf);
+    }
+    within(self.timeout, "writing to", self.stream.write_all(&buf)).await
+  }
+
+  /// Reads the target's next reply.
+  async fn reply(&mut self) -> KraglinResult {
+    let read = async {
+      loop {
+        if let Some(reply) = resp::decode(&mut self.buffer)
+          .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        {
+          return Ok(reply);
+        }
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+          return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+      }
+    };
+    within(self.timeout, "reading from", read).await
+  }
+}
+
+/// Runs `step` of talking to the target, failing if it errors or takes
+/// longer than `timeout`. `action` describes the step for the error.
+async fn within<T>(
+  timeout: Duration,
+  action: &'static str,
+  step: impl Future<Output = io::Result<T>>,
+) -> Result<T, KraglinError> {
+  match clock::timeout(timeout, step).await {
+    Ok(Ok(value)) => Ok(value),
+    Ok(Err(e)) => {
+      tracing::debug!("`MIGRATE` failed {action} the target instance: {e}");
+      Err(KraglinError::MigrateIo(action))
+    }
+    Err(_) => Err(KraglinError::MigrateIo(action)),
+  }
+}
@@ -0,0 +1,1251 @@
+//! The server, which accepts connections and serves them from a [`Backend`].
+
+mod acl;
+mod acl_log;
+mod builder;
+mod context;
+mod dispatch;
+mod error_stats;
+mod health;
+mod latency;
+mod listener;
+mod migrate;
+mod registry;
+mod supervisor;
+mod timeouts;
+mod trace;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod websocket;
+mod workers;
+
+use std::{
+  future::Future, net::SocketAddr, sync::Arc, task::Poll, time::Duration,
+};
+
+use color_eyre::eyre::{Result, WrapErr};
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::{
+  io::AsyncWriteExt,
+  sync::{watch, Semaphore},
+  task::JoinSet,
+};
+use tokio_util::codec::{Framed, FramedParts};
+
+#[cfg(feature = "simple")]
+pub(crate) use self::acl::glob_match;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use self::uring::UringListener;
+pub use self::{
+  acl::{Acl, User, DEFAULT_USER},
+  acl_log::{AclLog, AclLogEntry, AclLogReason, ACL_LOG_MAX_LEN},
+  builder::{ServerBuilder, ServerHandle},
+  context::{ConnectionContext, Transaction},
+  dispatch::{CommandInterceptor, Dispatcher, Intercept},
+  error_stats::ErrorStats,
+  latency::{LatencyMonitor, LatencySample, COMMAND_TIMEOUT_EVENT},
+  listener::{AsyncStream, BoxedStream, ListenAddr, Listener},
+  registry::CommandRegistry,
+  supervisor::ConnectionStats,
+  timeouts::{BlockingTimeouts, Timeout},
+  trace::{
+    ProtocolTrace, TraceSink, DEFAULT_MAX_CONNECTION_LEN,
+    DEFAULT_MAX_RECORD_LEN,
+  },
+  websocket::WebSocketListener,
+  workers::{Executor, Priority, WorkerPool, DEFAULT_WORKER_QUEUE_LEN},
+};
+use crate::{
+  aof,
+  backends::{Backend, BackendConfig, ReplyChunk},
+  buffer_pool::BufferPool,
+  clock,
+  config::Config,
+  data_dir::DataDir,
+  resp::{RequestLimits, RespCodec, RespCodecError},
+  value::Value,
+  KraglinError,
+};
+
+/// Runs the server as configured by `config`, until the listener fails or the
+/// process is asked to stop with `SIGINT` or `SIGTERM`.
+pub async fn serve<B: Backend>(config: Config) -> Result<()> {
+  // held until the server stops, keeping other instances from writing the
+  // same files
+  let data_dir = match config.dir() {
+    Some(dir) => {
+      let dir = DataDir::open(dir)?;
+      tracing::info!("using data directory {}", dir.path().display());
+      aof::recover(&dir.aof_dir())?;
+      Some(dir)
+    }
+    None => None,
+  };
+
+  let backend = B::new(BackendConfig {
+    data_dir: data_dir.as_ref().map(|dir| dir.path().to_owned()),
+    ..config.backend_config()
+  })?;
+
+  let address = format!("{}:{}", config.listen_host(), config.listen_port());
+  let mut builder = match config.io_uring() {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    true => ServerBuilder::new(backend).io_uring(address),
+    _ => ServerBuilder::new(backend).tcp(address),
+  };
+  if let Some(port) = config.health_port() {
+    builder =
+      builder.health_probes(format!("{}:{}", config.listen_host(), port));
+  }
+  if config.replica() {
+    builder = builder.replica();
+  }
+  if config.active_defrag() {
+    builder = builder.active_defrag(config.active_defrag_interval());
+  }
+  if let Some(password) = config.requirepass() {
+    builder = builder.requirepass(password.to_owned());
+  }
+  for (name, new_name) in config.rename_commands() {
+    builder = builder.rename_command(name, new_name);
+  }
+  if let Some(timeout) = config.command_timeout() {
+    builder = builder.command_timeout(timeout);
+  }
+  if let Some(workers) = config.workers() {
+    builder = builder.worker_pool(workers, DEFAULT_WORKER_QUEUE_LEN);
+  }
+  if let Some(sink) = config.protocol_trace() {
+    builder = builder.protocol_trace(ProtocolTrace::new(sink.clone()));
+  }
+  if let Some(max_clients) = config.max_clients() {
+    builder = builder.max_clients(max_clients);
+  }
+  if let Some(timeout) = config.idle_timeout() {
+    builder = builder.idle_timeout(timeout);
+  }
+  if let Some(timeout) = config.read_timeout() {
+    builder = builder.read_timeout(timeout);
+  }
+  if let Some(timeout) = config.output_timeout() {
+    builder = builder.output_timeout(timeout);
+  }
+  if let Some(timeout) = config.pubsub_output_timeout() {
+    builder = builder.pubsub_output_timeout(timeout);
+  }
+  let result = builder
+    .request_limits(config.request_limits())
+    .drain_timeout(config.drain_timeout())
+    .shutdown_on(shutdown_signal())
+    .serve()
+    .await;
+  // the backend has flushed its data by now, so another instance may start
+  drop(data_dir);
+  result
+}
+
+/// Limits on connections, which close or reject those which exceed them.
+#[derive(Debug, Clone, Copy, Default)]
+struct ConnectionLimits {
+  /// How many connections may be open at once.
+  max_clients:           Option<usize>,
+  /// How long a connection may wait between requests before it's closed.
+  idle_timeout:          Option<Duration>,
+  /// How long a connection may take to finish sending a request once it's
+  /// started, before it's closed.
+  read_timeout:          Option<Duration>,
+  /// How long a connection's replies may wait to be written before it's
+  /// closed as a slow consumer.
+  output_timeout:        Option<Duration>,
+  /// The same, for connections subscribed to channels.
+  pubsub_output_timeout: Option<Duration>,
+  /// Bounds on the size of each request.
+  request:               RequestLimits,
+}
+
+impl ConnectionLimits {
+  /// How long the replies of the connection with `ctx` may wait to be
+  /// written.
+  fn output_timeout(&self, ctx: &ConnectionContext) -> Option<Duration> {
+    match ctx.subscriptions() {
+      0 => self.output_timeout,
+      _ => self.pubsub_output_timeout,
+    }
+  }
+}
+
+/// What every accept loop shares.
+struct Shared<B: Backend> {
+  executor:     Arc<Executor<B>>,
+  buffer_pool:  Arc<BufferPool>,
+  trace:        Option<ProtocolTrace>,
+  stats:        Arc<ConnectionStats>,
+  limits:       ConnectionLimits,
+  /// A permit per connection which may be open at once, if the number is
+  /// limited.
+  client_slots: Option<Arc<Semaphore>>,
+}
+
+/// Accepts and serves connections on every listener until one of them fails
+/// or `shutdown` is signalled. Connections which exceed `limits` are closed,
+/// or rejected with an error if there are already too many.
+async fn run<B: Backend>(
+  listeners: Vec<Box<dyn Listener>>,
+  executor: Arc<Executor<B>>,
+  trace: Option<ProtocolTrace>,
+  stats: Arc<ConnectionStats>,
+  limits: ConnectionLimits,
+  drain_timeout: Option<Duration>,
+  shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+  let shared = Arc::new(Shared {
+    executor,
+    buffer_pool: Arc::new(BufferPool::default()),
+    trace,
+    stats,
+    limits,
+    client_slots: limits.max_clients.map(|max| Arc::new(Semaphore::new(max))),
+  });
+
+  let mut accept_loops = JoinSet::new();
+  for listener in listeners {
+    accept_loops.spawn(accept_loop(
+      listener,
+      shared.clone(),
+      drain_timeout,
+      shutdown.clone(),
+    ));
+  }
+  while let Some(result) = accept_loops.join_next().await {
+    result.wrap_err("accept loop panicked")??;
+  }
+  Ok(())
+}
+
+/// Accepts and serves connections from one listener until it fails or
+/// `shutdown` is signalled. On shutdown, stops accepting, signals open
+/// connections to close, and waits for them to finish, aborting those still
+/// open after `drain_timeout`.
+async fn accept_loop<B: Backend>(
+  listener: Box<dyn Listener>,
+  shared: Arc<Shared<B>>,
+  drain_timeout: Option<Duration>,
+  mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+  let mut connections = JoinSet::new();
+
+  loop {
+    tracing::debug!("waiting for new connection");
+    let (stream, addr) = tokio::select! {
+      accepted = listener.accept() => {
+        accepted.wrap_err("failed to accept connection")?
+      }
+      _ = shutdown.wait_for(|&shutdown| shutdown) => break,
+      // reap finished connections so the set doesn't grow forever
+      Some(_) = connections.join_next() => continue,
+    };
+
+    // held until the connection closes
+    let permit = match &shared.client_slots {
+      Some(slots) => match slots.clone().try_acquire_owned() {
+        Ok(permit) => Some(permit),
+        Err(_) => {
+          tracing::warn!(
+            "rejecting connection from {addr}: max number of clients reached"
+          );
+          shared.stats.record_rejected();
+          connections.spawn(reject(stream, KraglinError::MaxClients));
+          continue;
+        }
+      },
+      None => None,
+    };
+    tracing::info!("accepted connection from {addr}");
+    let stream = match &shared.trace {
+      Some(trace) => trace.wrap(stream, &addr),
+      None => stream,
+    };
+
+    let shared = shared.clone();
+    let shutdown = shutdown.clone();
+    connections.spawn(async move {
+      let handler = process_stream(
+        stream,
+        ConnectionContext::new(addr.clone()),
+        shared.executor.clone(),
+        shared.buffer_pool.clone(),
+        shared.stats.clone(),
+        shared.limits,
+        shutdown,
+      );
+      supervisor::supervise(&addr, &shared.stats, handler).await;
+      drop(permit);
+    });
+  }
+
+  tracing::info!(
+    "shutting down; waiting for {} connections",
+    connections.len()
+  );
+  let drain = async { while connections.join_next().await.is_some() {} };
+  match drain_timeout {
+    Some(timeout) => {
+      if clock::timeout(timeout, drain).await.is_err() {
+        tracing::warn!(
+          "{} connections still open after the drain timeout; closing them",
+          connections.len()
+        );
+        connections.shutdown().await;
+      }
+    }
+    None => drain.await,
+  }
+  Ok(())
+}
+
+/// Completes when the process receives `SIGINT` (e.g. from Ctrl-C) or, on
+/// unix, `SIGTERM`, for [`ServerBuilder::shutdown_on()`].
+pub async fn shutdown_signal() {
+  let interrupt = async {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+      tracing::error!("failed to listen for SIGINT: {e}");
+      std::future::pending::<()>().await;
+    }
+  };
+  #[cfg(unix)]
+  let terminate = async {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+      Ok(mut terminate) => {
+        terminate.recv().await;
+      }
+      Err(e) => {
+        tracing::error!("failed to listen for SIGTERM: {e}");
+        std::future::pending::<()>().await;
+      }
+    }
+  };
+  #[cfg(not(unix))]
+  let terminate = std::future::pending::<()>();
+
+  tokio::select! {
+    _ = interrupt => tracing::info!("received SIGINT; shutting down"),
+    _ = terminate => tracing::info!("received SIGTERM; shutting down"),
+  }
+}
+
+/// Binds to an ephemeral port on localhost and runs the full server there
+/// in-process, with the given backend.
+///
+/// This is intended for integration testing applications against a real RESP
+/// endpoint. The returned handle has the bound address. The server shuts down
+/// when [`EphemeralServer::shutdown()`] is called, or in the background when
+/// the handle is dropped.
+pub async fn spawn_ephemeral<B: Backend>(
+  backend: B,
+) -> Result<EphemeralServer<B>> {
+  let handle = ServerBuilder::new(backend)
+    .tcp("127.0.0.1:0")
+    .start()
+    .await?;
+  let addr = handle.tcp_addr().expect("server has a TCP listener");
+  tracing::debug!("spawned ephemeral server on {addr}");
+  Ok(EphemeralServer { addr, handle })
+}
+
+/// A handle to a server started with [`spawn_ephemeral()`].
+pub struct EphemeralServer<B: Backend> {
+  addr:   SocketAddr,
+  handle: ServerHandle<B>,
+}
+
+impl<B: Backend> EphemeralServer<B> {
+  /// Returns the address the server is listening on.
+  pub fn addr(&self) -> SocketAddr { self.addr }
+
+  /// Returns the server's backend, for inspecting or seeding its data
+  /// directly.
+  pub fn backend(&self) -> &Arc<B> { self.handle.backend() }
+
+  /// Gracefully shuts the server down: stops accepting connections, closes
+  /// open ones, and waits for them to finish.
+  pub async fn shutdown(self) -> Result<()> { self.handle.shutdown().await }
+}
+
+/// Replies to a connection which won't be served with `error`, and closes it.
+async fn reject(mut stream: BoxedStream, error: KraglinError) {
+  let mut reply = bytes::BytesMut::new();
+  Value::Error(error.to_string().into()).write_resp2(&mut reply);
+  // the peer may already be gone, which is fine
+  let _ = stream.write_all(&reply).await;
+  let _ = stream.shutdown().await;
+}
+
+/// The capacity requested for a connection's read and write buffers from the
+/// pool.
+const READ_BUFFER_CAPACITY: usize = 1024;
+/// How many bytes of replies are buffered before they're written, even if
+/// the requests already read haven't all been handled.
+const REPLY_FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// A connection, framed into requests and replies.
+type Connection = Framed<BoxedStream, RespCodec>;
+
+/// Serves a connection: reads commands from `stream`, runs them with
+/// `executor`, and writes back their replies, until the peer disconnects,
+/// exceeds one of the timeouts in `limits`, or `shutdown` is signalled.
+/// Connections closed as slow consumers are counted in `stats`.
+async fn process_stream<B: Backend>(
+  stream: BoxedStream,
+  ctx: ConnectionContext,
+  executor: Arc<Executor<B>>,
+  buffer_pool: Arc<BufferPool>,
+  stats: Arc<ConnectionStats>,
+  limits: ConnectionLimits,
+  shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+  let mut read_buf = buffer_pool.acquire(READ_BUFFER_CAPACITY);
+  let mut write_buf = buffer_pool.acquire(READ_BUFFER_CAPACITY);
+  let mut parts =
+    FramedParts::new::<Value>(stream, RespCodec::with_limits(limits.request));
+  parts.read_buf = std::mem::take(&mut *read_buf);
+  parts.write_buf = std::mem::take(&mut *write_buf);
+  let mut connection = Framed::from_parts(parts);
+  connection.set_backpressure_boundary(REPLY_FLUSH_THRESHOLD);
+
+  let result =
+    serve_connection(&mut connection, ctx, &executor, &stats, limits, shutdown)
+      .await;
+
+  // the buffers go back to the pool with whatever capacity they have left
+  let parts = connection.into_parts();
+  *read_buf = parts.read_buf;
+  *write_buf = parts.write_buf;
+  result
+}
+
+/// How a wait for the next request from a connection ended.
+enum Wait {
+  /// The connection sent a request, failed, or closed.
+  Frame(Option<Result<Value, RespCodecError>>),
+  /// The connection sent nothing for the idle timeout.
+  Idle,
+  /// The connection started sending a request, but didn't finish it within
+  /// the read timeout.
+  Stalled,
+}
+
+/// Waits for the next request from `connection`, giving up after
+/// `idle_timeout` if it sends nothing, or `read_timeout` after it starts
+/// sending a request (or if part of one is already buffered) without
+/// finishing it.
+async fn next_frame(
+  connection: &mut Connection,
+  idle_timeout: Option<Duration>,
+  read_timeout: Option<Duration>,
+) -> Wait {
+  let mut idle = idle_timeout.map(|timeout| Box::pin(clock::sleep(timeout)));
+  let mut read = None;
+  std::future::poll_fn(|cx| {
+    if let Poll::Ready(frame) = connection.poll_next_unpin(cx) {
+      return Poll::Ready(Wait::Frame(frame));
+    }
+    // everything the peer has sent is buffered by now, so anything there is
+    // part of a request
+    if connection.read_buffer().is_empty() {
+      if let Some(idle) = &mut idle {
+        if idle.as_mut().poll(cx).is_ready() {
+          return Poll::Ready(Wait::Idle);
+        }
+      }
+    } else if let Some(timeout) = read_timeout {
+      let read = read.get_or_insert_with(|| Box::pin(clock::sleep(timeout)));
+      if read.as_mut().poll(cx).is_ready() {
+        return Poll::Ready(Wait::Stalled);
+      }
+    }
+    Poll::Pending
+  })
+  .await
+}
+
+/// A write of replies to a connection.
+enum Write {
+  /// Buffers a reply, writing the buffer out once it's past
+  /// [`REPLY_FLUSH_THRESHOLD`].
+  Feed(Value),
+  /// Buffers a reply and writes the buffer out.
+  Send(Value),
+  /// Buffers a chunk of a streamed reply, like [`Write::Feed`].
+  Chunk(ReplyChunk),
+  /// Writes the buffer out.
+  Flush,
+}
+
+/// Runs `write` on `connection`, unless the connection is a slow consumer:
+/// one whose replies have waited `timeout` since `backlog`, when a write
+/// first had to wait for it to read earlier ones. Returns `false` without
+/// finishing the write if it is. `backlog` is reset once the write buffer
+/// drains.
+async fn write_replies(
+  connection: &mut Connection,
+  write: Write,
+  backlog: &mut Option<clock::Instant>,
+  timeout: Option<Duration>,
+) -> Result<bool> {
+  let result = {
+    let mut write = std::pin::pin!(async {
+      match write {
+        Write::Feed(reply) => connection.feed(reply).await,
+        Write::Send(reply) => connection.send(reply).await,
+        Write::Chunk(chunk) => connection.feed(chunk).await,
+        Write::Flush => SinkExt::<Value>::flush(connection).await,
+      }
+    });
+    // writes which don't have to wait for the peer don't start the clock
+    match futures::poll!(write.as_mut()) {
+      Poll::Ready(result) => Some(result),
+      Poll::Pending => {
+        let since = *backlog.get_or_insert_with(clock::now);
+        match timeout {
+          Some(timeout) => clock::timeout_at(since + timeout, write).await.ok(),
+          None => Some(write.await),
+        }
+      }
+    }
+  };
+  let Some(result) = result else {
+    return Ok(false);
+  };
+  result.wrap_err("failed to write data to socket")?;
+  if connection.write_buffer().is_empty() {
+    *backlog = None;
+  }
+  Ok(true)
+}
+
+/// Logs and counts the closing of a slow consumer.
+fn evict(ctx: &ConnectionContext, stats: &ConnectionStats) {
+  tracing::warn!(
+    "closing connection from {}: replies weren't read within the output \
+     timeout",
+    ctx.peer()
+  );
+  stats.record_evicted();
+}
+
+async fn serve_connection<B: Backend>(
+  connection: &mut Connection,
+  mut ctx: ConnectionContext,
+  executor: &Executor<B>,
+  stats: &ConnectionStats,
+  limits: ConnectionLimits,
+  mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+  let mut backlog = None;
+  loop {
+    // replies to everything already read are written together, which saves
+    // a write per command for pipelined clients
+    let frame = match connection.next().now_or_never() {
+      Some(frame) => frame,
+      None => {
+        let timeout = limits.output_timeout(&ctx);
+        if !write_replies(connection, Write::Flush, &mut backlog, timeout)
+          .await?
+        {
+          evict(&ctx, stats);
+          return Ok(());
+        }
+        // subscribers wait for messages, not requests, so they may idle
+        let idle_timeout = match ctx.subscriptions() {
+          0 => limits.idle_timeout,
+          _ => None,
+        };
+        tokio::select! {
+          wait = next_frame(connection, idle_timeout, limits.read_timeout) => {
+            match wait {
+              Wait::Frame(frame) => frame,
+              Wait::Idle => {
+                tracing::debug!(
+                  "closing connection from {}: idle timeout",
+                  ctx.peer()
+                );
+                return Ok(());
+              }
+              Wait::Stalled => {
+                tracing::debug!(
+                  "closing connection from {}: timed out reading a request",
+                  ctx.peer()
+                );
+                return Ok(());
+              }
+            }
+          }
+          _ = shutdown.wait_for(|&shutdown| shutdown) => return Ok(()),
+        }
+      }
+    };
+
+    // Decoding hands out reference-counted views of the read buffer rather
+    // than copies, so payloads can be kept (e.g. as `Value::BulkString`s)
+    // without reallocating. The buffer reclaims its allocation once every
+    // view of it has been dropped.
+    let frame = match frame {
+      Some(Ok(frame)) => frame,
+      None => return Ok(()),
+      Some(Err(RespCodecError::Protocol(e))) => {
+        // where the next frame starts is unknown, so like Redis, reply with
+        // the error and close the connection
+        tracing::debug!("closing connection from {}: {e}", ctx.peer());
+        let error = Write::Send(Value::Error(e.to_string().into()));
+        let timeout = limits.output_timeout(&ctx);
+        if !write_replies(connection, error, &mut backlog, timeout).await? {
+          evict(&ctx, stats);
+        }
+        return Ok(());
+      }
+      Some(Err(e)) => {
+        return Err(e).wrap_err("failed to read data from socket");
+      }
+    };
+
+    let reply = match executor.dispatcher().parse_for(&mut ctx, frame) {
+      // replies which can be huge are written out chunk by chunk as the
+      // backend produces them, on the connection's task even with a worker
+      // pool, rather than being built whole first
+      Ok(command) if executor.dispatcher().streams(&ctx, &command) => {
+        let timeout = limits.output_timeout(&ctx);
+        let chunks =
+          executor.dispatcher().dispatch_streaming(&mut ctx, command);
+        let mut chunks = std::pin::pin!(chunks);
+        let mut started = false;
+        while let Some(chunk) = chunks.next().await {
+          let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) if !started => {
+              ReplyChunk::Value(Value::Error(e.to_string().into()))
+            }
+            // the reply can't be finished, so the client can't be told
+            Err(e) => return Err(e).wrap_err("failed to stream a reply"),
+          };
+          started = true;
+          let write = Write::Chunk(chunk);
+          if !write_replies(connection, write, &mut backlog, timeout).await? {
+            evict(&ctx, stats);
+            return Ok(());
+          }
+        }
+        continue;
+      }
+      Ok(command) => executor
+        .dispatch(&mut ctx, command)
+        .await
+        .unwrap_or_else(|e| Value::Error(e.to_string().into())),
+      Err(e) => Value::Error(e.to_string().into()),
+    };
+    connection.codec_mut().set_protocol(ctx.protocol());
+    let closing = ctx.is_closing();
+    let write = if closing {
+      Write::Send(reply)
+    } else {
+      Write::Feed(reply)
+    };
+    let timeout = limits.output_timeout(&ctx);
+    if !write_replies(connection, write, &mut backlog, timeout).await? {
+      evict(&ctx, stats);
+      return Ok(());
+    }
+    if closing {
+      return Ok(());
+    }
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::{
+    net::SocketAddr,
+    sync::{
+      atomic::{AtomicBool, Ordering},
+      Arc,
+    },
+    time::Duration,
+  };
+
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+  };
+
+  use super::{
+    spawn_ephemeral, ConnectionContext, ListenAddr, ServerBuilder, ServerHandle,
+  };
+  use crate::{
+    backends::{simple::SimpleBackend, Backend, BackendConfig, BackendExt},
+    command::{Command, CommandFlags},
+    value::Value,
+    KraglinError, KraglinResult,
+  };
+
+  #[tokio::test]
+  async fn ephemeral_server_serves_and_shuts_down() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let server = spawn_ephemeral(backend).await.unwrap();
+    let addr = server.addr();
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut buf = [0; 7];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"+PONG\r\n");
+
+    // inline commands, as typed into `telnet`, work too
+    stream.write_all(b"PING hi\r\n").await.unwrap();
+    let mut echo = [0; 8];
+    stream.read_exact(&mut echo).await.unwrap();
+    assert_eq!(&echo, b"$2\r\nhi\r\n");
+
+    // replies switch to RESP3 once it's negotiated with `HELLO`
+    stream.write_all(b"HELLO 3\r\nGET a\r\n").await.unwrap();
+    let mut hello = [0; 3];
+    stream.read_exact(&mut hello).await.unwrap();
+    assert_eq!(&hello, b"%9\r");
+    let mut rest = Vec::new();
+    while !rest.ends_with(b"_\r\n") {
+      rest.push(stream.read_u8().await.unwrap());
+    }
+
+    // open connections are closed on shutdown
+    server.shutdown().await.unwrap();
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+    assert!(TcpStream::connect(addr).await.is_err());
+  }
+
+  #[cfg(feature = "lists")]
+  #[tokio::test]
+  async fn unimplemented_list_commands_are_rejected_as_unsupported() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let server = spawn_ephemeral(backend).await.unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream.write_all(b"LPUSH l a\r\nPING\r\n").await.unwrap();
+    let mut replies = [0; 54];
+    stream.read_exact(&mut replies).await.unwrap();
+    assert_eq!(
+      &replies,
+      b"-ERR 'lpush' is not supported by this backend\r\n+PONG\r\n"
+    );
+
+    server.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn streamed_replies_come_from_the_connections_database() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let server = spawn_ephemeral(backend).await.unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream
+      .write_all(b"SET a 1\r\nSELECT 1\r\nSET c 1\r\nSET b 1\r\nKEYS *\r\n")
+      .await
+      .unwrap();
+    let expected = b"+OK\r\n+OK\r\n+OK\r\n+OK\r\n*2\r\n$1\r\nb\r\n$1\r\nc\r\n";
+    let mut replies = [0; 38];
+    stream.read_exact(&mut replies).await.unwrap();
+    assert_eq!(&replies, expected);
+
+    #[cfg(feature = "sets")]
+    {
+      stream
+        .write_all(
+          b"SADD s y\r\nSADD s x\r\nSMEMBERS s\r\nSMEMBERS a\r\nSMEMBERS b\r\n",
+        )
+        .await
+        .unwrap();
+      // `a` is only in database 0
+      let expected = b":1\r\n:1\r\n*2\r\n$1\r\nx\r\n$1\r\ny\r\n*0\r\n\
+        -WRONGTYPE Operation against a key holding the wrong kind of value\r\n";
+      let mut replies = [0; 98];
+      stream.read_exact(&mut replies).await.unwrap();
+      assert_eq!(&replies, expected);
+    }
+
+    server.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn writes_over_maxmemory_are_rejected() {
+    let backend = SimpleBackend::new(BackendConfig {
+      max_memory: Some(1),
+      ..Default::default()
+    })
+    .unwrap();
+    let server = spawn_ephemeral(backend).await.unwrap();
+
+    // the first write takes the keyspace over the limit, and later ones are
+    // refused
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    stream.write_all(b"SET a x\r\nSET b x\r\n").await.unwrap();
+    let mut replies = [0; 63];
+    stream.read_exact(&mut replies).await.unwrap();
+    assert_eq!(
+      &replies,
+      b"+OK\r\n-OOM command not allowed when used memory > 'maxmemory'.\r\n"
+    );
+
+    server.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn quit_closes_the_connection_even_before_authenticating() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .requirepass("secret")
+      .start()
+      .await
+      .unwrap();
+
+    let mut stream = TcpStream::connect(handle.tcp_addr().unwrap())
+      .await
+      .unwrap();
+    // pipelined commands after `QUIT` are never run
+    stream
+      .write_all(b"GET a\r\nQUIT\r\nAUTH secret\r\n")
+      .await
+      .unwrap();
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies).await.unwrap();
+    assert_eq!(replies, "-NOAUTH Authentication required.\r\n+OK\r\n");
+
+    handle.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn builder_composes_listeners_and_hooks() {
+    let dir = std::env::temp_dir()
+      .join(format!("kraglin-builder-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("kraglin.sock");
+    let _ = std::fs::remove_file(&socket);
+
+    let started = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::new(AtomicBool::new(false));
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .unix(&socket)
+      .on_start({
+        let started = started.clone();
+        move |addrs| {
+          assert_eq!(addrs.len(), 2);
+          started.store(true, Ordering::SeqCst);
+        }
+      })
+      .on_shutdown({
+        let stopped = stopped.clone();
+        move || stopped.store(true, Ordering::SeqCst)
+      })
+      .start()
+      .await
+      .unwrap();
+    assert!(started.load(Ordering::SeqCst));
+    assert_eq!(handle.addrs()[1], ListenAddr::Unix(socket.clone()));
+
+    let mut tcp = TcpStream::connect(handle.tcp_addr().unwrap())
+      .await
+      .unwrap();
+    let mut unix = UnixStream::connect(&socket).await.unwrap();
+    for stream in [&mut tcp as &mut dyn super::AsyncStream, &mut unix] {
+      stream.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+      let mut buf = [0; 7];
+      stream.read_exact(&mut buf).await.unwrap();
+      assert_eq!(&buf, b"+PONG\r\n");
+    }
+
+    handle.shutdown().await.unwrap();
+    assert!(stopped.load(Ordering::SeqCst));
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn shutdown_signals_close_stalled_connections_after_draining() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let (signal, signalled) = tokio::sync::oneshot::channel::<()>();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .command("STALL", 1, CommandFlags::empty(), |_, _| {
+        std::future::pending()
+      })
+      .drain_timeout(Duration::from_millis(50))
+      .shutdown_on(async {
+        let _ = signalled.await;
+      })
+      .start()
+      .await
+      .unwrap();
+
+    let mut stream = TcpStream::connect(handle.tcp_addr().unwrap())
+      .await
+      .unwrap();
+    stream.write_all(b"STALL\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    // the stalled command never finishes, so the connection is closed once
+    // the drain timeout is up
+    signal.send(()).unwrap();
+    handle.wait().await.unwrap();
+    let mut buf = [0; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+  }
+
+  #[tokio::test]
+  async fn connections_beyond_max_clients_are_rejected() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .max_clients(1)
+      .start()
+      .await
+      .unwrap();
+    let addr = handle.tcp_addr().unwrap();
+
+    let mut first = TcpStream::connect(addr).await.unwrap();
+    first.write_all(b"PING\r\n").await.unwrap();
+    let mut buf = [0; 7];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"+PONG\r\n");
+
+    let mut second = TcpStream::connect(addr).await.unwrap();
+    let mut reply = String::new();
+    second.read_to_string(&mut reply).await.unwrap();
+    assert_eq!(reply, "-ERR max number of clients reached\r\n");
+    assert_eq!(handle.connection_stats().rejected(), 1);
+
+    // the slot is freed once the first connection closes
+    drop(first);
+    let mut third = loop {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+      let mut stream = TcpStream::connect(addr).await.unwrap();
+      stream.write_all(b"PING\r\n").await.unwrap();
+      let mut buf = [0; 1];
+      if stream.read_exact(&mut buf).await.is_ok() && &buf == b"+" {
+        break stream;
+      }
+    };
+    let mut rest = [0; 6];
+    third.read_exact(&mut rest).await.unwrap();
+    assert_eq!(&rest, b"PONG\r\n");
+
+    handle.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn idle_and_stalled_connections_are_closed() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .idle_timeout(Duration::from_millis(200))
+      .read_timeout(Duration::from_millis(50))
+      .start()
+      .await
+      .unwrap();
+    let addr = handle.tcp_addr().unwrap();
+
+    // a request resets the idle timeout
+    let mut idle = TcpStream::connect(addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    idle.write_all(b"PING\r\n").await.unwrap();
+    let mut buf = [0; 7];
+    idle.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"+PONG\r\n");
+    let mut rest = Vec::new();
+    idle.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+
+    // half a request is given much less time than an idle connection
+    let mut stalled = TcpStream::connect(addr).await.unwrap();
+    stalled.write_all(b"*1\r\n$4\r\nPI").await.unwrap();
+    let started = std::time::Instant::now();
+    stalled.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+    assert!(started.elapsed() < Duration::from_millis(200));
+
+    handle.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn slow_consumers_are_closed() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .output_timeout(Duration::from_millis(100))
+      .start()
+      .await
+      .unwrap();
+    let addr = handle.tcp_addr().unwrap();
+    handle
+      .backend()
+      .SET("big", Value::BulkString(vec![b'x'; 1 << 20].into()))
+      .await
+      .unwrap();
+
+    // a consumer which reads its replies is never closed, however long it
+    // takes between requests
+    let mut reader = TcpStream::connect(addr).await.unwrap();
+    for _ in 0..2 {
+      reader.write_all(b"GET big\r\n").await.unwrap();
+      let mut reply = vec![0; (1 << 20) + 12];
+      reader.read_exact(&mut reply).await.unwrap();
+      tokio::time::sleep(Duration::from_millis(150)).await;
+    }
+
+    // and one which stops reading is, once its replies have waited on it
+    // for the timeout
+    let mut slow = TcpStream::connect(addr).await.unwrap();
+    slow.write_all(&b"GET big\r\n".repeat(64)).await.unwrap();
+    while handle.connection_stats().evicted() == 0 {
+      tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let mut rest = Vec::new();
+    let _ = slow.read_to_end(&mut rest).await;
+    assert!(rest.len() < 64 << 20);
+
+    reader.write_all(b"PING\r\n").await.unwrap();
+    let mut buf = [0; 7];
+    reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"+PONG\r\n");
+    assert_eq!(handle.connection_stats().evicted(), 1);
+
+    handle.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn health_probes_report_readiness_until_shutdown() {
+    async fn probe(addr: std::net::SocketAddr, path: &str) -> String {
+      let mut stream = TcpStream::connect(addr).await.unwrap();
+      stream
+        .write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+        .await
+        .unwrap();
+      let mut response = String::new();
+      stream.read_to_string(&mut response).await.unwrap();
+      response
+    }
+
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let (signal, signalled) = tokio::sync::oneshot::channel::<()>();
+    let handle = ServerBuilder::new(backend)
+      .tcp("127.0.0.1:0")
+      .health_probes("127.0.0.1:0")
+      .command("STALL", 1, CommandFlags::empty(), |_, _| {
+        std::future::pending()
+      })
+      .drain_timeout(Duration::from_millis(200))
+      .shutdown_on(async {
+        let _ = signalled.await;
+      })
+      .start()
+      .await
+      .unwrap();
+    let health = handle.health_addr().unwrap();
+
+    assert!(probe(health, "/livez").await.starts_with("HTTP/1.1 200"));
+    assert!(probe(health, "/readyz").await.starts_with("HTTP/1.1 200"));
+    assert!(probe(health, "/nope").await.starts_with("HTTP/1.1 404"));
+
+    // a stalled connection keeps the server draining, but it's no longer
+    // ready for traffic
+    let mut stream = TcpStream::connect(handle.tcp_addr().unwrap())
+      .await
+      .unwrap();
+    stream.write_all(b"STALL\r\n").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    signal.send(()).unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let response = probe(health, "/readyz").await;
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+    assert!(response.ends_with("shutting down"), "{response}");
+    assert!(probe(health, "/livez").await.starts_with("HTTP/1.1 200"));
+
+    handle.wait().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn migrate_copies_or_moves_keys_to_another_instance() {
+    async fn start() -> ServerHandle<SimpleBackend> {
+      ServerBuilder::new(SimpleBackend::new(BackendConfig::default()).unwrap())
+        .tcp("127.0.0.1:0")
+        .requirepass("secret")
+        .start()
+        .await
+        .unwrap()
+    }
+    async fn migrate(
+      source: &ServerHandle<SimpleBackend>,
+      target: SocketAddr,
+      args: &[&str],
+    ) -> KraglinResult {
+      let port = target.port().to_string();
+      let frame = ["MIGRATE", "127.0.0.1", &port]
+        .iter()
+        .chain(args)
+        .map(|arg| Value::BulkString(arg.to_string().into()))
+        .collect();
+      let mut ctx = ConnectionContext::new("test");
+      ctx.set_authenticated("default");
+      let command = Command::parse(Value::Array(frame)).unwrap();
+      source.dispatcher().dispatch(&mut ctx, command).await
+    }
+
+    let (source, target) = (start().await, start().await);
+    let addr = target.tcp_addr().unwrap();
+    let (here, there) = (source.backend(), target.backend());
+    let ok = Value::SimpleString("OK".into());
+    for key in ["a", "b", "c"] {
+      here.SET(key, key).await.unwrap();
+    }
+
+    let args = ["a", "0", "1000", "AUTH", "secret"];
+    assert_eq!(migrate(&source, addr, &args).await.unwrap(), ok);
+    assert_eq!(here.GET("a").await.unwrap(), Value::Nothing);
+    assert_eq!(
+      there.GET("a").await.unwrap(),
+      Value::SimpleString("a".into())
+    );
+
+    // missing keys are skipped, and copied keys are kept
+    let args = [
+      "", "0", "0", "COPY", "AUTH2", "default", "secret", "KEYS", "b", "c",
+      "missing",
+    ];
+    assert_eq!(migrate(&source, addr, &args).await.unwrap(), ok);
+    assert_eq!(
+      here.GET("b").await.unwrap(),
+      Value::SimpleString("b".into())
+    );
+    assert_eq!(
+      there.GET("c").await.unwrap(),
+      Value::SimpleString("c".into())
+    );
+
+    // keys the target already has are only overwritten with `REPLACE`, and
+    // otherwise stay here
+    let args = ["b", "0", "1000", "AUTH", "secret"];
+    assert!(matches!(
+      migrate(&source, addr, &args).await,
+      Err(KraglinError::MigrateTarget(e)) if e.starts_with("BUSYKEY")
+    ));
+    assert_eq!(
+      here.GET("b").await.unwrap(),
+      Value::SimpleString("b".into())
+    );
+    let args = ["b", "0", "1000", "REPLACE", "AUTH", "secret"];
+    assert_eq!(migrate(&source, addr, &args).await.unwrap(), ok);
+    assert_eq!(here.GET("b").await.unwrap(), Value::Nothing);
+
+    assert!(matches!(
+      migrate(&source, addr, &["c", "0", "1000"]).await,
+      Err(KraglinError::MigrateTarget(e)) if e.starts_with("NOAUTH")
+    ));
+    assert_eq!(
+      migrate(&source, addr, &["missing", "0", "1000"])
+        .await
+        .unwrap(),
+      Value::SimpleString("NOKEY".into())
+    );
+
+    target.shutdown().await.unwrap();
+    assert!(matches!(
+      migrate(&source, addr, &["c", "0", "1000"]).await,
+      Err(KraglinError::MigrateIo("connecting to"))
+    ));
+    source.shutdown().await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn websocket_connections_are_bridged() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .websocket("127.0.0.1:0")
+      .start()
+      .await
+      .unwrap();
+    let ListenAddr::WebSocket(addr) = handle.addrs()[0] else {
+      panic!("expected a WebSocket address");
+    };
+
+    // a request which isn't an upgrade is rejected
+    let mut plain = TcpStream::connect(addr).await.unwrap();
+    plain
+      .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+      .await
+      .unwrap();
+    let mut response = String::new();
+    plain.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+    let mut ws = TcpStream::connect(addr).await.unwrap();
+    ws.write_all(
+      b"GET /chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+        Connection: keep-alive, Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n",
+    )
+    .await
+    .unwrap();
+    let expected = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: \
+      websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: \
+      s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+    let mut response = vec![0; expected.len()];
+    ws.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, expected);
+
+    // a masked binary frame holding a `PING`
+    let ping = b"*1\r\n$4\r\nPING\r\n";
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![0x82, 0x80 | ping.len() as u8];
+    frame.extend(mask);
+    frame.extend(ping.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    ws.write_all(&frame).await.unwrap();
+    let mut reply = [0; 9];
+    ws.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"\x82\x07+PONG\r\n");
+
+    // closing the server closes the connection with a close frame
+    handle.shutdown().await.unwrap();
+    let mut close = [0; 4];
+    ws.read_exact(&mut close).await.unwrap();
+    assert_eq!(close, [0x88, 0x02, 0x03, 0xE8]);
+  }
+
+  #[cfg(all(feature = "io-uring", target_os = "linux"))]
+  #[tokio::test]
+  async fn io_uring_connections_serve_pipelines_and_large_values() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let handle = ServerBuilder::new(backend)
+      .io_uring("127.0.0.1:0")
+      .start()
+      .await
+      .unwrap();
+    let mut stream = TcpStream::connect(handle.tcp_addr().unwrap())
+      .await
+      .unwrap();
+
+    // larger than a single read or write, so both are split
+    let value = "v".repeat(200_000);
+    let mut request =
+      format!("*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$200000\r\n{value}\r\n");
+    request.push_str("*2\r\n$3\r\nGET\r\n$1\r\nk\r\n");
+    request.push_str("*1\r\n$4\r\nPING\r\n");
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let expected = format!("+OK\r\n$200000\r\n{value}\r\n+PONG\r\n");
+    let mut reply = vec![0; expected.len()];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(reply, expected.as_bytes());
+
+    drop(stream);
+    handle.shutdown().await.unwrap();
+  }
+}
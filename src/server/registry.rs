@@ -0,0 +1,264 @@
+//! Defines the `CommandRegistry`, which holds commands registered at runtime,
+//! and commands which have been renamed or disabled.
+
+use std::{
+  collections::{HashMap, HashSet},
+  future::Future,
+  sync::Arc,
+};
+
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+use smol_str::SmolStr;
+
+use crate::{
+  backends::Backend,
+  command::{Command, CommandFlags, CommandSpec, CommandTable, ParseError},
+  value::Value,
+  KraglinResult,
+};
+
+/// A custom command's handler, which receives the backend and the command's
+/// arguments (not including its name).
+type Handler<B> = Box<
+  dyn Fn(Arc<B>, Vec<Bytes>) -> BoxFuture<'static, KraglinResult> + Send + Sync,
+>;
+
+struct Registered<B> {
+  spec:    CommandSpec,
+  handler: Handler<B>,
+}
+
+/// A set of custom commands, which extend the built-in [`Command`]s without
+/// editing them.
+///
+/// Custom commands are parsed into [`Command::Custom`], so interceptors see
+/// them like any other command, and are run by their handler instead of the
+/// backend.
+///
+/// Commands (built-in or custom) can also be renamed or disabled, e.g. to hide
+/// administrative commands from clients. Renamed commands are parsed as the
+/// original command, so interceptors and ACL rules see their original name.
+pub struct CommandRegistry<B: Backend> {
+  commands: HashMap<SmolStr, Registered<B>>,
+  /// Maps new (uppercased) names to the names of the commands they run.
+  renamed:  HashMap<SmolStr, SmolStr>,
+  /// The (uppercased) names which no longer run their original command.
+  hidden:   HashSet<SmolStr>,
+}
+
+impl<B: Backend> Default for CommandRegistry<B> {
+  fn default() -> Self {
+    CommandRegistry {
+      commands: HashMap::new(),
+      renamed:  HashMap::new(),
+      hidden:   HashSet::new(),
+    }
+  }
+}
+
+impl<B: Backend> CommandRegistry<B> {
+  /// Registers the command `name` (case-insensitive), run by `handler`.
+  ///
+  /// `arity` counts the command name, and may be negative to mean "at least",
+  /// as in [`CommandSpec::arity`]. Handlers typically run built-in commands
+  /// against the backend they're given.
+  ///
+  /// # Panics
+  /// Panics if `name` is a built-in command or is already registered.
+  pub fn register<F, Fut>(
+    &mut self,
+    name: &str,
+    arity: i64,
+    flags: CommandFlags,
+    handler: F,
+  ) where
+    F: Fn(Arc<B>, Vec<Bytes>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = KraglinResult> + Send + 'static,
+  {
+    let name = SmolStr::from(name.to_ascii_uppercase());
+    assert!(
+      !CommandTable::get().contains(&name),
+      "cannot register `{name}`: it is a built-in command"
+    );
+    assert!(
+      !self.commands.contains_key(&name),
+      "cannot register `{name}`: it is already registered"
+    );
+
+    self.commands.insert(name, Registered {
+      spec:    CommandSpec::new(arity, flags),
+      handler: Box::new(move |backend, args| handler(backend, args).boxed()),
+    });
+  }
+
+  /// Renames the command `name` to `new_name` (both case-insensitive), so
+  /// clients must send `new_name` to run it. An empty `new_name` disables the
+  /// command instead, like Redis' `rename-command NAME ""`.
+  ///
+  /// # Panics
+  /// Panics if `new_name` is already used by another rename.
+  pub fn rename(&mut self, name: &str, new_name: &str) {
+    let name = SmolStr::from(name.to_ascii_uppercase());
+    let new_name = SmolStr::from(new_name.to_ascii_uppercase());
+    self.hidden.insert(name.clone());
+    if new_name.is_empty() {
+      return;
+    }
+    assert!(
+      !self.renamed.contains_key(&new_name),
+      "cannot rename to `{new_name}`: it is already a renamed command"
+    );
+    self.renamed.insert(new_name, name);
+  }
+
+  /// Disables the command `name` (case-insensitive), so clients can't run it.
+  pub fn disable(&mut self, name: &str) { self.rename(name, "") }
+
+  /// Whether no commands are registered, renamed, or disabled.
+  pub fn is_empty(&self) -> bool {
+    self.commands.is_empty() && self.hidden.is_empty()
+  }
+
+  /// Parses a command from a RESP frame, like [`Command::parse()`], but also
+  /// recognizing registered, renamed, and disabled commands.
+  pub fn parse(&self, mut frame: Value) -> Result<Command, ParseError> {
+    let name = match &frame {
+      Value::Array(args) => args
+        .first()
+        .and_then(Value::to_argument)
+        .map(|name| String::from_utf8_lossy(&name).into_owned()),
+      _ => None,
+    };
+    let mut name = name.map(|name| name.to_ascii_uppercase());
+    if let Some(original) =
+      name.as_deref().and_then(|name| self.renamed.get(name))
+    {
+      if let Value::Array(args) = &mut frame {
+        args[0] =
+          Value::BulkString(Bytes::copy_from_slice(original.as_bytes()));
+      }
+      name = Some(original.to_string());
+    } else if name
+      .as_deref()
+      .is_some_and(|name| self.hidden.contains(name))
+    {
+      let Value::Array(args) = &frame else {
+        unreachable!("the frame has a name");
+      };
+      let args = args
+        .iter()
+        .filter_map(Value::to_argument)
+        .collect::<Vec<_>>();
+      return Err(
+        ParseError::unknown_command(&args[0], &args[1..]).without_suggestion(),
+      );
+    }
+
+    let Some(registered) =
+      name.and_then(|name| self.commands.get_key_value(name.as_str()))
+    else {
+      return Command::parse(frame).map_err(|e| match &e {
+        // don't suggest disabled commands
+        ParseError::UnknownCommand {
+          suggestion: Some(suggestion),
+          ..
+        } if self
+          .hidden
+          .contains(suggestion.to_ascii_uppercase().as_str()) =>
+        {
+          e.without_suggestion()
+        }
+        _ => e,
+      });
+    };
+
+    let (name, registered) = registered;
+    let Value::Array(args) = frame else {
+      unreachable!("the frame has a name");
+    };
+    if !registered.spec.accepts(args.len()) {
+      return Err(ParseError::WrongArity(name.to_lowercase()));
+    }
+    let args = args
+      .iter()
+      .skip(1)
+      .map(|arg| arg.to_argument().ok_or(ParseError::NotAnArray))
+      .collect::<Result<_, _>>()?;
+    Ok(Command::Custom {
+      name: name.clone(),
+      args,
+      spec: registered.spec,
+    })
+  }
+
+  /// Runs a custom command's handler, or returns `None` if `name` isn't
+  /// registered.
+  pub(crate) fn execute(
+    &self,
+    backend: &Arc<B>,
+    name: &str,
+    args: Vec<Bytes>,
+  ) -> Option<BoxFuture<'static, KraglinResult>> {
+    let registered = self.commands.get(name)?;
+    Some((registered.handler)(backend.clone(), args))
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use super::CommandRegistry;
+  use crate::{
+    backends::simple::SimpleBackend,
+    command::{Command, ParseError},
+    value::Value,
+  };
+
+  fn frame(args: &[&str]) -> Value {
+    Value::Array(
+      args
+        .iter()
+        .map(|arg| Value::BulkString(arg.to_string().into()))
+        .collect(),
+    )
+  }
+
+  #[test]
+  fn commands_can_be_renamed_and_disabled() {
+    let mut registry = CommandRegistry::<SimpleBackend>::default();
+    registry.rename("keys", "secret-keys");
+    registry.disable("DEBUG");
+
+    assert_eq!(
+      registry.parse(frame(&["SECRET-KEYS", "*"])).unwrap(),
+      Command::Keys
+    );
+    assert_eq!(
+      registry
+        .parse(frame(&["keys", "*"]))
+        .unwrap_err()
+        .to_string(),
+      "unknown command 'keys', with args beginning with: '*' "
+    );
+    assert_eq!(
+      registry.parse(frame(&["debug", "hotkeys"])),
+      Err(ParseError::UnknownCommand {
+        command:    "debug".into(),
+        args:       "'hotkeys' ".into(),
+        suggestion: None,
+      })
+    );
+    // disabled commands aren't suggested for typos
+    assert!(matches!(
+      registry.parse(frame(&["debgu"])),
+      Err(ParseError::UnknownCommand {
+        suggestion: None,
+        ..
+      })
+    ));
+    assert_eq!(
+      registry.parse(frame(&["GET", "a"])).unwrap(),
+      Command::Get { key: "a".into() }
+    );
+  }
+}
@@ -0,0 +1,139 @@
+//! Defines `supervise()`, which runs a connection's handler and reports how
+//! it ended, and `ConnectionStats`, which counts the outcomes.
+//!
+//! A handler which panics only takes its own connection down: the panic is
+//! caught, logged with the peer's address, and counted, rather than
+//! disappearing into an ignored `JoinError`. Per-connection resources are
+//! released by a guard which is dropped however the handler ends.
+
+use std::{
+  any::Any,
+  future::Future,
+  panic::AssertUnwindSafe,
+  sync::atomic::{AtomicU64, Ordering},
+};
+
+use color_eyre::eyre::Result;
+use futures::FutureExt;
+
+/// Counts the connections a server has served, and how they ended.
+#[derive(Debug, Default)]
+pub struct ConnectionStats {
+  accepted:  AtomicU64,
+  connected: AtomicU64,
+  failed:    AtomicU64,
+  panicked:  AtomicU64,
+  rejected:  AtomicU64,
+  evicted:   AtomicU64,
+}
+
+impl ConnectionStats {
+  /// Returns how many connections have been accepted.
+  pub fn accepted(&self) -> u64 { self.accepted.load(Ordering::Relaxed) }
+
+  /// Returns how many connections are currently open.
+  pub fn connected(&self) -> u64 { self.connected.load(Ordering::Relaxed) }
+
+  /// Returns how many connections ended with an error, like a failed read.
+  pub fn failed(&self) -> u64 { self.failed.load(Ordering::Relaxed) }
+
+  /// Returns how many connections ended because their handler panicked.
+  pub fn panicked(&self) -> u64 { self.panicked.load(Ordering::Relaxed) }
+
+  /// Returns how many connections were rejected because the server already
+  /// had as many open as it allows.
+  pub fn rejected(&self) -> u64 { self.rejected.load(Ordering::Relaxed) }
+
+  /// Returns how many connections were closed as slow consumers, because
+  /// their replies weren't read within the output timeout.
+  pub fn evicted(&self) -> u64 { self.evicted.load(Ordering::Relaxed) }
+
+  /// Counts a rejected connection, which is never accepted.
+  pub(crate) fn record_rejected(&self) {
+    self.rejected.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Counts a connection closed as a slow consumer.
+  pub(crate) fn record_evicted(&self) {
+    self.evicted.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+/// Marks a connection as open until it's dropped, which happens whether its
+/// handler returns or panics.
+struct ConnectionGuard<'a> {
+  stats: &'a ConnectionStats,
+}
+
+impl<'a> ConnectionGuard<'a> {
+  fn new(stats: &'a ConnectionStats) -> Self {
+    stats.accepted.fetch_add(1, Ordering::Relaxed);
+    stats.connected.fetch_add(1, Ordering::Relaxed);
+    ConnectionGuard { stats }
+  }
+}
+
+impl Drop for ConnectionGuard<'_> {
+  fn drop(&mut self) { self.stats.connected.fetch_sub(1, Ordering::Relaxed); }
+}
+
+/// Runs `handler`, the handler of a connection from `peer`, logging and
+/// counting how it ended. Never panics itself.
+pub(crate) async fn supervise(
+  peer: &str,
+  stats: &ConnectionStats,
+  handler: impl Future<Output = Result<()>>,
+) {
+  let _guard = ConnectionGuard::new(stats);
+  match AssertUnwindSafe(handler).catch_unwind().await {
+    Ok(Ok(())) => tracing::debug!("connection from {peer} closed"),
+    Ok(Err(e)) => {
+      stats.failed.fetch_add(1, Ordering::Relaxed);
+      tracing::debug!("connection from {peer} failed: {e:?}");
+    }
+    Err(panic) => {
+      stats.panicked.fetch_add(1, Ordering::Relaxed);
+      tracing::error!(
+        "connection from {peer} panicked: {}",
+        panic_message(&*panic)
+      );
+    }
+  }
+}
+
+/// Returns the message a panic was raised with, if it has one.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+  if let Some(message) = panic.downcast_ref::<&str>() {
+    message
+  } else if let Some(message) = panic.downcast_ref::<String>() {
+    message
+  } else {
+    "<non-string panic payload>"
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use color_eyre::eyre::eyre;
+
+  use super::{supervise, ConnectionStats};
+
+  #[tokio::test]
+  async fn outcomes_are_counted_and_panics_contained() {
+    let stats = ConnectionStats::default();
+
+    supervise("a", &stats, async { Ok(()) }).await;
+    supervise("b", &stats, async { Err(eyre!("read failed")) }).await;
+    supervise("c", &stats, async {
+      assert_eq!(stats.connected(), 1);
+      panic!("boom")
+    })
+    .await;
+
+    assert_eq!(stats.accepted(), 3);
+    assert_eq!(stats.failed(), 1);
+    assert_eq!(stats.panicked(), 1);
+    // the panicking connection was still closed
+    assert_eq!(stats.connected(), 0);
+  }
+}
@@ -0,0 +1,226 @@
+//! Defines `BlockingTimeouts`, which fires the timeouts of every blocked
+//! client from a single task.
+//!
+//! Blocking commands (like `BLPOP` or `WAIT`) race the event they're waiting
+//! for against a [`Timeout`]. Rather than each blocked client sleeping in its
+//! own timer, their deadlines are kept in one min-heap, and one task sleeps
+//! until the earliest and wakes everyone whose deadline has passed.
+
+use std::{
+  cmp::Reverse,
+  collections::{BinaryHeap, HashMap},
+  future::Future,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  task::{Context, Poll},
+};
+
+use tokio::sync::{oneshot, Notify};
+
+use crate::clock::{self, Instant};
+
+/// Cancelled timeouts stay in the heap until they're popped, so the heap is
+/// rebuilt without them once they outnumber the live ones by this factor.
+const COMPACTION_FACTOR: usize = 2;
+
+#[derive(Default)]
+struct State {
+  deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+  waiters:   HashMap<u64, oneshot::Sender<()>>,
+  next_id:   u64,
+  started:   bool,
+  closed:    bool,
+}
+
+impl State {
+  /// Fires every timeout whose deadline is at or before `now`, returning the
+  /// next deadline.
+  fn fire(&mut self, now: Instant) -> Option<Instant> {
+    while let Some(&Reverse((deadline, id))) = self.deadlines.peek() {
+      if deadline > now {
+        return Some(deadline);
+      }
+      self.deadlines.pop();
+      if let Some(waiter) = self.waiters.remove(&id) {
+        let _ = waiter.send(());
+      }
+    }
+    None
+  }
+
+  fn compact(&mut self) {
+    if self.deadlines.len() > COMPACTION_FACTOR * self.waiters.len() + 64 {
+      let waiters = &self.waiters;
+      self
+        .deadlines
+        .retain(|Reverse((_, id))| waiters.contains_key(id));
+    }
+  }
+}
+
+#[derive(Default)]
+struct Shared {
+  state:   Mutex<State>,
+  changed: Notify,
+}
+
+/// The deadlines of every blocked client, fired by a single task.
+///
+/// The task is spawned on the first call to
+/// [`timeout_at()`](BlockingTimeouts::timeout_at), so this can be created
+/// outside a tokio runtime. It stops when this is dropped.
+#[derive(Default)]
+pub struct BlockingTimeouts {
+  shared: Arc<Shared>,
+}
+
+impl BlockingTimeouts {
+  /// Creates an empty set of timeouts.
+  pub fn new() -> Self { BlockingTimeouts::default() }
+
+  /// Returns a future which resolves at `deadline`. Dropping it cancels the
+  /// timeout.
+  ///
+  /// Commands which block forever (e.g. with a timeout of `0`) shouldn't
+  /// register one at all.
+  pub fn timeout_at(&self, deadline: Instant) -> Timeout {
+    let (tx, rx) = oneshot::channel();
+    let mut state = self.shared.state.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+
+    let earliest = state.deadlines.peek().map(|Reverse((d, _))| *d);
+    state.deadlines.push(Reverse((deadline, id)));
+    state.waiters.insert(id, tx);
+    if !state.started {
+      state.started = true;
+      tokio::spawn(drive(self.shared.clone()));
+    } else if earliest.is_none_or(|earliest| deadline < earliest) {
+      self.shared.changed.notify_one();
+    }
+
+    Timeout {
+      id,
+      rx,
+      shared: self.shared.clone(),
+    }
+  }
+
+  /// Returns how many timeouts are pending.
+  pub fn len(&self) -> usize { self.shared.state.lock().unwrap().waiters.len() }
+
+  /// Returns whether no timeouts are pending.
+  pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl Drop for BlockingTimeouts {
+  fn drop(&mut self) {
+    self.shared.state.lock().unwrap().closed = true;
+    self.shared.changed.notify_one();
+  }
+}
+
+/// Sleeps until the earliest deadline, fires the timeouts which are due, and
+/// repeats, until the [`BlockingTimeouts`] is dropped.
+async fn drive(shared: Arc<Shared>) {
+  loop {
+    let next = {
+      let mut state = shared.state.lock().unwrap();
+      if state.closed {
+        return;
+      }
+      state.fire(clock::now())
+    };
+    match next {
+      Some(deadline) => tokio::select! {
+        _ = clock::sleep_until(deadline) => {}
+        _ = shared.changed.notified() => {}
+      },
+      None => shared.changed.notified().await,
+    }
+  }
+}
+
+/// A pending timeout, which resolves at its deadline. Created by
+/// [`BlockingTimeouts::timeout_at()`].
+pub struct Timeout {
+  id:     u64,
+  rx:     oneshot::Receiver<()>,
+  shared: Arc<Shared>,
+}
+
+impl Future for Timeout {
+  type Output = ();
+
+  fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    // if the timeouts were dropped, the deadline will never fire, so the
+    // timeout resolves rather than blocking forever
+    Pin::new(&mut self.rx).poll(cx).map(|_| ())
+  }
+}
+
+impl Drop for Timeout {
+  fn drop(&mut self) {
+    let mut state = self.shared.state.lock().unwrap();
+    if state.waiters.remove(&self.id).is_some() {
+      state.compact();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use futures::{future::join_all, FutureExt};
+
+  use super::BlockingTimeouts;
+  use crate::clock;
+
+  #[tokio::test(start_paused = true)]
+  async fn timeouts_fire_at_their_deadlines() {
+    let timeouts = BlockingTimeouts::new();
+    let start = clock::now();
+
+    let waits = [30, 10, 20].map(|secs| {
+      let timeout = timeouts.timeout_at(start + Duration::from_secs(secs));
+      tokio::spawn(async move {
+        timeout.await;
+        start.elapsed()
+      })
+    });
+    // a timeout registered later but due sooner still fires first
+    let early = timeouts.timeout_at(start + Duration::from_secs(5));
+    early.await;
+    assert_eq!(start.elapsed(), Duration::from_secs(5));
+
+    let elapsed = join_all(waits).await;
+    assert_eq!(
+      elapsed.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+      [30, 10, 20].map(Duration::from_secs)
+    );
+    assert!(timeouts.is_empty());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn dropped_timeouts_are_cancelled() {
+    let timeouts = BlockingTimeouts::new();
+    let deadline = clock::now() + Duration::from_secs(10);
+
+    let kept = timeouts.timeout_at(deadline);
+    let mut cancelled = (0..1000)
+      .map(|_| timeouts.timeout_at(deadline))
+      .collect::<Vec<_>>();
+    assert_eq!(timeouts.len(), 1001);
+    cancelled.clear();
+    assert_eq!(timeouts.len(), 1);
+    // the heap doesn't keep cancelled deadlines around
+    assert!(timeouts.shared.state.lock().unwrap().deadlines.len() < 100);
+
+    let mut kept = kept.boxed();
+    assert!((&mut kept).now_or_never().is_none());
+    tokio::time::advance(Duration::from_secs(10)).await;
+    kept.await;
+    assert!(timeouts.is_empty());
+  }
+}
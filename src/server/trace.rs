@@ -0,0 +1,298 @@
+//! Defines `ProtocolTrace`, a debug mode which records every byte each
+//! connection sends and receives, so protocol bugs between kraglin and a
+//! client library can be diagnosed offline.
+//!
+//! Each read from a client is recorded as a request record (`<`), and each
+//! write to it as a response record (`>`), with the time since the
+//! connection was accepted:
+//!
+//! ```text
+//! # connection 3 from 127.0.0.1:52814
+//!        112 < 14 *1\r\n$4\r\nPING\r\n
+//!        348 > 7 +PONG\r\n
+//! ```
+//!
+//! Records are written synchronously, so tracing slows connections down; it
+//! isn't meant to be left on in production.
+
+use std::{
+  fmt::Write as _,
+  fs::File,
+  io::{self, LineWriter, Write as _},
+  path::{Path, PathBuf},
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::listener::{AsyncStream, BoxedStream};
+use crate::clock::{self, Instant};
+
+/// The default for [`ProtocolTrace::max_record_len()`].
+pub const DEFAULT_MAX_RECORD_LEN: usize = 512;
+/// The default for [`ProtocolTrace::max_connection_len()`].
+pub const DEFAULT_MAX_CONNECTION_LEN: usize = 1024 * 1024;
+
+/// Where a [`ProtocolTrace`] writes its records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceSink {
+  /// Logs each record as a `tracing` event, with the `kraglin::protocol`
+  /// target.
+  Log,
+  /// Writes each connection's records to its own file in this directory,
+  /// named `connection-<id>.trace`. The directory must exist.
+  Directory(PathBuf),
+}
+
+/// Records the bytes every connection sends and receives. See the
+/// [module docs](self) for the format.
+#[derive(Debug, Clone)]
+pub struct ProtocolTrace {
+  sink:               TraceSink,
+  max_record_len:     usize,
+  max_connection_len: usize,
+  next_id:            Arc<AtomicU64>,
+}
+
+impl ProtocolTrace {
+  /// Creates a trace which writes to `sink`, with the default size caps.
+  pub fn new(sink: TraceSink) -> Self {
+    ProtocolTrace {
+      sink,
+      max_record_len: DEFAULT_MAX_RECORD_LEN,
+      max_connection_len: DEFAULT_MAX_CONNECTION_LEN,
+      next_id: Arc::new(AtomicU64::new(1)),
+    }
+  }
+
+  /// Sets how many bytes of each read or write are recorded. Longer ones are
+  /// truncated, but still recorded with their full length.
+  pub fn max_record_len(mut self, len: usize) -> Self {
+    self.max_record_len = len;
+    self
+  }
+
+  /// Sets how many bytes are recorded for each connection, across all of its
+  /// records. Once a connection reaches this, its trace stops.
+  pub fn max_connection_len(mut self, len: usize) -> Self {
+    self.max_connection_len = len;
+    self
+  }
+
+  /// Wraps a newly accepted connection from `peer`, so that it's recorded.
+  /// If its trace file can't be created, the connection is served untraced.
+  pub(crate) fn wrap(&self, stream: BoxedStream, peer: &str) -> BoxedStream {
+    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+    let output = match &self.sink {
+      TraceSink::Log => Output::Log,
+      TraceSink::Directory(dir) => {
+        let path = dir.join(format!("connection-{id}.trace"));
+        match create_trace_file(&path, id, peer) {
+          Ok(file) => Output::File(file),
+          Err(e) => {
+            tracing::warn!(
+              "failed to create protocol trace {}: {e}",
+              path.display()
+            );
+            return stream;
+          }
+        }
+      }
+    };
+    Box::new(TracedStream {
+      inner:    stream,
+      recorder: Recorder {
+        id,
+        output,
+        accepted: clock::now(),
+        recorded: 0,
+        max_record_len: self.max_record_len,
+        max_connection_len: self.max_connection_len,
+      },
+    })
+  }
+}
+
+fn create_trace_file(
+  path: &Path,
+  id: u64,
+  peer: &str,
+) -> io::Result<LineWriter<File>> {
+  let mut file = LineWriter::new(File::create(path)?);
+  writeln!(file, "# connection {id} from {peer}")?;
+  Ok(file)
+}
+
+enum Output {
+  Log,
+  File(LineWriter<File>),
+}
+
+/// Which way a record's bytes went.
+#[derive(Clone, Copy)]
+enum Direction {
+  Request,
+  Response,
+}
+
+struct Recorder {
+  id:                 u64,
+  output:             Output,
+  accepted:           Instant,
+  recorded:           usize,
+  max_record_len:     usize,
+  max_connection_len: usize,
+}
+
+impl Recorder {
+  fn record(&mut self, direction: Direction, bytes: &[u8]) {
+    if bytes.is_empty() || self.recorded > self.max_connection_len {
+      return;
+    }
+    let remaining = self.max_connection_len - self.recorded;
+    if remaining == 0 {
+      // mark the end of the trace once, rather than stopping silently
+      self.recorded += 1;
+      let marker =
+        format!("# trace stopped after {} bytes", self.max_connection_len);
+      self.write(&marker);
+      return;
+    }
+
+    let recorded = bytes.len().min(self.max_record_len).min(remaining);
+    self.recorded += recorded;
+    let mut line = format!(
+      "{:>10} {} {} ",
+      self.accepted.elapsed().as_micros(),
+      match direction {
+        Direction::Request => '<',
+        Direction::Response => '>',
+      },
+      bytes.len()
+    );
+    for &byte in &bytes[..recorded] {
+      let _ = write!(line, "{}", byte.escape_ascii());
+    }
+    if recorded < bytes.len() {
+      line.push_str("...");
+    }
+    self.write(&line);
+  }
+
+  fn write(&mut self, record: &str) {
+    match &mut self.output {
+      Output::Log => {
+        tracing::info!(target: "kraglin::protocol", "[{}] {record}", self.id)
+      }
+      Output::File(file) => {
+        if let Err(e) = writeln!(file, "{record}") {
+          tracing::warn!("failed to write protocol trace: {e}");
+          // stop tracing rather than failing on every record
+          self.recorded = self.max_connection_len + 1;
+        }
+      }
+    }
+  }
+}
+
+/// A stream which records what passes through it.
+struct TracedStream<S> {
+  inner:    S,
+  recorder: Recorder,
+}
+
+impl<S: AsyncStream> AsyncRead for TracedStream<S> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let filled = buf.filled().len();
+    let this = &mut *self;
+    let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+    if let Poll::Ready(Ok(())) = result {
+      this
+        .recorder
+        .record(Direction::Request, &buf.filled()[filled..]);
+    }
+    result
+  }
+}
+
+impl<S: AsyncStream> AsyncWrite for TracedStream<S> {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    let this = &mut *self;
+    let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+    if let Poll::Ready(Ok(written)) = result {
+      this.recorder.record(Direction::Response, &buf[..written]);
+    }
+    result
+  }
+
+  fn poll_flush(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_shutdown(cx)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+  use super::{ProtocolTrace, TraceSink};
+
+  #[tokio::test]
+  async fn connections_are_recorded_up_to_the_caps() {
+    let dir = std::env::temp_dir()
+      .join(format!("kraglin-trace-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let trace = ProtocolTrace::new(TraceSink::Directory(dir.clone()))
+      .max_record_len(8)
+      .max_connection_len(20);
+    let (client, server) = tokio::io::duplex(64);
+    let mut server = trace.wrap(Box::new(server), "peer");
+    let mut client = client;
+
+    client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut buf = [0; 14];
+    server.read_exact(&mut buf).await.unwrap();
+    server.write_all(b"+PONG\r\n").await.unwrap();
+    server.write_all(b"+more than the cap\r\n").await.unwrap();
+    server.write_all(b"+unrecorded\r\n").await.unwrap();
+    drop(server);
+
+    let contents =
+      std::fs::read_to_string(dir.join("connection-1.trace")).unwrap();
+    let records = contents
+      .lines()
+      .map(|line| line.trim_start().split_once(' ').map_or(line, |r| r.1))
+      .collect::<Vec<_>>();
+    assert_eq!(records, [
+      "connection 1 from peer",
+      "< 14 *1\\r\\n$4\\r\\n...",
+      "> 7 +PONG\\r\\n",
+      "> 20 +more...",
+      "trace stopped after 20 bytes",
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
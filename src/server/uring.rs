@@ -0,0 +1,723 @@
+//! Defines the `UringListener` item, which accepts TCP connections whose
+//! reads and writes are completed by io_uring, rather than polled for
+//! readiness by the tokio reactor.
+//!
+//! Each listener owns a ring. Connections submit their reads and writes to it
+//! directly, and a driver thread waits on its completion queue, waking the
+//! tasks whose operations have completed. A request's bytes are then already
+//! in the connection's buffer when its task runs, where a readiness-based
+//! read takes a wakeup and another syscall to fetch them. Connections are
+//! still [`AsyncRead`] and [`AsyncWrite`] streams, so they're served by the
+//! same connection loop as every other listener. Accepting stays on the tokio
+//! reactor, since it's off the hot path.
+
+use std::{
+  collections::HashMap,
+  ffi::c_void,
+  io,
+  mem::size_of,
+  net::{Shutdown, SocketAddr},
+  os::fd::{AsRawFd, OwnedFd},
+  pin::Pin,
+  ptr::null_mut,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+  },
+  task::{ready, Context, Poll, Waker},
+  time::Duration,
+};
+
+use futures::{future::BoxFuture, FutureExt};
+use rustix::{
+  io::Errno,
+  io_uring::{
+    addr_or_splice_off_in_union, io_uring_cqe, io_uring_enter, io_uring_params,
+    io_uring_ptr, io_uring_setup, io_uring_sqe, io_uring_user_data, len_union,
+    op_flags_union, IoringEnterFlags, IoringFeatureFlags, IoringOp,
+    IoringSetupFlags, IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING,
+  },
+  mm::{mmap, munmap, MapFlags, ProtFlags},
+  net::SendFlags,
+};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::{TcpListener, ToSocketAddrs},
+};
+
+use super::listener::{BoxedStream, ListenAddr, Listener};
+
+/// How many submissions the ring holds. Each is handed to the kernel as soon
+/// as it's queued, so this only bounds those queued while the kernel is
+/// refusing more.
+const SUBMISSION_ENTRIES: u32 = 256;
+/// How many completions the ring holds. Each connection has at most a read
+/// and a write in flight; the kernel holds back completions beyond this until
+/// the driver has made room.
+const COMPLETION_ENTRIES: u32 = 4096;
+/// How many bytes a connection reads at once.
+const READ_LEN: usize = 64 * 1024;
+
+/// The `user_data` of operations whose completions are ignored, like
+/// cancellations.
+const IGNORED: u64 = 0;
+/// The `user_data` of the no-op which tells the driver to stop, once every
+/// operation in flight has completed.
+const STOP: u64 = 1;
+
+/// A region of the ring mapped into memory, unmapped on drop.
+struct Mapping {
+  ptr: *mut c_void,
+  len: usize,
+}
+
+// SAFETY: the mapping is only accessed through the queues, whose access is
+// synchronized by the `Driver`.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+  fn new(ring: &OwnedFd, offset: u64, len: usize) -> io::Result<Self> {
+    // SAFETY: a fresh shared mapping of the ring aliases nothing else.
+    let ptr = unsafe {
+      mmap(
+        null_mut(),
+        len,
+        ProtFlags::READ | ProtFlags::WRITE,
+        MapFlags::SHARED | MapFlags::POPULATE,
+        ring,
+        offset,
+      )
+    }?;
+    Ok(Mapping { ptr, len })
+  }
+
+  /// Returns a pointer `offset` bytes into the mapping.
+  ///
+  /// # Safety
+  /// `offset` must be within the mapping.
+  unsafe fn at<T>(&self, offset: u32) -> *mut T {
+    unsafe { self.ptr.cast::<u8>().add(offset as usize).cast() }
+  }
+}
+
+impl Drop for Mapping {
+  fn drop(&mut self) {
+    // SAFETY: the queues pointing into the mapping hold it, so they're gone.
+    let _ = unsafe { munmap(self.ptr, self.len) };
+  }
+}
+
+/// The ring's mapped regions.
+struct Mappings {
+  sq:   Mapping,
+  /// The completion queue's own mapping, unless the kernel maps both queues
+  /// together.
+  cq:   Option<Mapping>,
+  sqes: Mapping,
+}
+
+/// The submission side of the ring, which tasks queue operations on.
+struct SubmissionQueue {
+  _mappings: Arc<Mappings>,
+  head:      *const AtomicU32,
+  tail:      *const AtomicU32,
+  mask:      u32,
+  entries:   u32,
+  sqes:      *mut io_uring_sqe,
+}
+
+// SAFETY: the queue is only used under the `Driver`'s lock.
+unsafe impl Send for SubmissionQueue {}
+
+impl SubmissionQueue {
+  fn head(&self) -> &AtomicU32 {
+    // SAFETY: the pointer is into the mapping the queue holds.
+    unsafe { &*self.head }
+  }
+
+  fn tail(&self) -> &AtomicU32 {
+    // SAFETY: as above.
+    unsafe { &*self.tail }
+  }
+
+  /// Queues `sqe`, returning `false` if the queue is full.
+  fn push(&mut self, sqe: io_uring_sqe) -> bool {
+    // only this side moves the tail
+    let tail = self.tail().load(Ordering::Relaxed);
+    if tail.wrapping_sub(self.head().load(Ordering::Acquire)) == self.entries {
+      return false;
+    }
+    // SAFETY: the index is masked into the array, which the kernel isn't
+    // reading past the head.
+    unsafe { self.sqes.add((tail & self.mask) as usize).write(sqe) };
+    self.tail().store(tail.wrapping_add(1), Ordering::Release);
+    true
+  }
+
+  /// The number of queued submissions the kernel hasn't taken yet.
+  fn pending(&self) -> u32 {
+    let tail = self.tail().load(Ordering::Relaxed);
+    tail.wrapping_sub(self.head().load(Ordering::Acquire))
+  }
+}
+
+/// The completion side of the ring, which only the driver thread reads.
+struct CompletionQueue {
+  _mappings: Arc<Mappings>,
+  head:      *const AtomicU32,
+  tail:      *const AtomicU32,
+  mask:      u32,
+  cqes:      *const io_uring_cqe,
+}
+
+// SAFETY: the queue is moved to the driver thread, and only used there.
+unsafe impl Send for CompletionQueue {}
+
+impl CompletionQueue {
+  /// Takes the next completion, as its `user_data` and result.
+  fn pop(&mut self) -> Option<(u64, i32)> {
+    // SAFETY: the pointers are into the mapping the queue holds.
+    let (head, tail) = unsafe { (&*self.head, &*self.tail) };
+    let index = head.load(Ordering::Relaxed);
+    if index == tail.load(Ordering::Acquire) {
+      return None;
+    }
+    // SAFETY: the index is masked into the array, and the kernel is done
+    // with entries before the tail.
+    let cqe = unsafe { &*self.cqes.add((index & self.mask) as usize) };
+    let completion = (cqe.user_data.u64_(), cqe.res);
+    head.store(index.wrapping_add(1), Ordering::Release);
+    Some(completion)
+  }
+}
+
+/// Sets up a ring, returning its two queues.
+fn setup() -> io::Result<(OwnedFd, SubmissionQueue, CompletionQueue)> {
+  let mut params = io_uring_params::default();
+  params.flags = IoringSetupFlags::CQSIZE;
+  params.cq_entries = COMPLETION_ENTRIES;
+  // SAFETY: `ATTACH_WQ` isn't set.
+  let ring = unsafe { io_uring_setup(SUBMISSION_ENTRIES, &mut params) }?;
+
+  let (sq_off, cq_off) = (params.sq_off, params.cq_off);
+  let sq_len =
+    sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+  let cq_len = cq_off.cqes as usize
+    + params.cq_entries as usize * size_of::<io_uring_cqe>();
+  let single = params.features.contains(IoringFeatureFlags::SINGLE_MMAP);
+  let mappings = Arc::new(Mappings {
+    sq:   Mapping::new(
+      &ring,
+      IORING_OFF_SQ_RING,
+      if single { sq_len.max(cq_len) } else { sq_len },
+    )?,
+    cq:   match single {
+      true => None,
+      false => Some(Mapping::new(&ring, IORING_OFF_CQ_RING, cq_len)?),
+    },
+    sqes: Mapping::new(
+      &ring,
+      IORING_OFF_SQES,
+      params.sq_entries as usize * size_of::<io_uring_sqe>(),
+    )?,
+  });
+
+  let (sq, cq) = (&mappings.sq, mappings.cq.as_ref().unwrap_or(&mappings.sq));
+  // SAFETY: the offsets are the kernel's, into the regions it sized.
+  let (submissions, completions) = unsafe {
+    // each entry of the indirection array stays pointing at its own slot
+    let array = sq.at::<u32>(sq_off.array);
+    for index in 0..params.sq_entries {
+      array.add(index as usize).write(index);
+    }
+    (
+      SubmissionQueue {
+        _mappings: mappings.clone(),
+        head:      sq.at(sq_off.head),
+        tail:      sq.at(sq_off.tail),
+        mask:      *sq.at::<u32>(sq_off.ring_mask),
+        entries:   params.sq_entries,
+        sqes:      mappings.sqes.ptr.cast(),
+      },
+      CompletionQueue {
+        _mappings: mappings.clone(),
+        head:      cq.at(cq_off.head),
+        tail:      cq.at(cq_off.tail),
+        mask:      *cq.at::<u32>(cq_off.ring_mask),
+        cqes:      cq.at(cq_off.cqes),
+      },
+    )
+  };
+  Ok((ring, submissions, completions))
+}
+
+/// An operation in flight, which owns the buffer the kernel is using until
+/// the operation completes.
+struct Operation {
+  state: Mutex<OperationState>,
+}
+
+struct OperationState {
+  result: Option<i32>,
+  waker:  Option<Waker>,
+  buf:    Vec<u8>,
+}
+
+impl Operation {
+  fn new(buf: Vec<u8>, waker: &Waker) -> Arc<Self> {
+    Arc::new(Operation {
+      state: Mutex::new(OperationState {
+        result: None,
+        waker: Some(waker.clone()),
+        buf,
+      }),
+    })
+  }
+
+  fn complete(&self, result: i32) {
+    let mut state = self.state.lock().unwrap();
+    state.result = Some(result);
+    if let Some(waker) = state.waker.take() {
+      waker.wake();
+    }
+  }
+
+  /// Fails the operation with `error` without the kernel having completed it.
+  /// Its buffer is leaked, since the kernel may still write to it.
+  fn fail(&self, error: Errno) {
+    let mut state = self.state.lock().unwrap();
+    std::mem::forget(std::mem::take(&mut state.buf));
+    state.result = Some(-error.raw_os_error());
+    if let Some(waker) = state.waker.take() {
+      waker.wake();
+    }
+  }
+
+  /// Waits for the operation to complete, returning its result and buffer.
+  fn poll(&self, cx: &mut Context<'_>) -> Poll<(i32, Vec<u8>)> {
+    let mut state = self.state.lock().unwrap();
+    match state.result {
+      Some(result) => Poll::Ready((result, std::mem::take(&mut state.buf))),
+      None => {
+        if !state
+          .waker
+          .as_ref()
+          .is_some_and(|w| w.will_wake(cx.waker()))
+        {
+          state.waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+      }
+    }
+  }
+}
+
+/// Converts an operation's result into the byte count it's for.
+fn check(result: i32) -> io::Result<usize> {
+  usize::try_from(result).map_err(|_| io::Error::from_raw_os_error(-result))
+}
+
+/// A ring shared by a listener's connections.
+struct Driver {
+  ring:        OwnedFd,
+  submissions: Mutex<Submissions>,
+}
+
+struct Submissions {
+  queue:     SubmissionQueue,
+  in_flight: HashMap<u64, Arc<Operation>>,
+  next_id:   u64,
+  stopping:  bool,
+}
+
+impl Driver {
+  /// Starts `sqe`, which completes `operation`. Returns the operation's id.
+  fn start(
+    &self,
+    mut sqe: io_uring_sqe,
+    operation: Arc<Operation>,
+  ) -> io::Result<u64> {
+    let mut submissions = self.submissions.lock().unwrap();
+    if submissions.stopping {
+      return Err(io::Error::other("the io_uring driver has stopped"));
+    }
+    let id = submissions.next_id;
+    sqe.user_data = io_uring_user_data::from_u64(id);
+    self.push(&mut submissions, sqe)?;
+    submissions.next_id += 1;
+    submissions.in_flight.insert(id, operation);
+    Ok(id)
+  }
+
+  /// Submits `sqe` without tracking its completion.
+  fn submit(&self, sqe: io_uring_sqe) -> io::Result<()> {
+    self.push(&mut self.submissions.lock().unwrap(), sqe)
+  }
+
+  fn push(
+    &self,
+    submissions: &mut Submissions,
+    sqe: io_uring_sqe,
+  ) -> io::Result<()> {
+    if !submissions.queue.push(sqe) {
+      return Err(io::Error::other("the io_uring submission queue is full"));
+    }
+    self.flush(submissions);
+    Ok(())
+  }
+
+  /// Hands the kernel whatever is queued. If it refuses (e.g. while its
+  /// completions overflow), they stay queued until the driver retries.
+  fn flush(&self, submissions: &Submissions) {
+    let pending = submissions.queue.pending();
+    if pending == 0 {
+      return;
+    }
+    // SAFETY: every queued operation's buffer is held by `in_flight` until
+    // it completes.
+    let result = unsafe {
+      io_uring_enter(&self.ring, pending, 0, IoringEnterFlags::empty())
+    };
+    match result {
+      Ok(_) | Err(Errno::BUSY | Errno::AGAIN | Errno::INTR) => {}
+      Err(e) => tracing::warn!("failed to submit to io_uring: {e}"),
+    }
+  }
+}
+
+/// Waits for completions and wakes the tasks waiting on them, until the
+/// driver is stopped and nothing is left in flight.
+fn drive(driver: Arc<Driver>, mut completions: CompletionQueue) {
+  loop {
+    // SAFETY: nothing is submitted.
+    let result = unsafe {
+      io_uring_enter(&driver.ring, 0, 1, IoringEnterFlags::GETEVENTS)
+    };
+    match result {
+      Ok(_) | Err(Errno::INTR) => {}
+      Err(e) => {
+        // nothing will complete the operations in flight, so they're failed
+        // instead of leaving their tasks waiting forever
+        tracing::error!("io_uring driver failed to wait: {e}");
+        let mut submissions = driver.submissions.lock().unwrap();
+        submissions.stopping = true;
+        for (_, operation) in submissions.in_flight.drain() {
+          operation.fail(e);
+        }
+        return;
+      }
+    }
+
+    let mut submissions = driver.submissions.lock().unwrap();
+    while let Some((id, result)) = completions.pop() {
+      if id == STOP {
+        submissions.stopping = true;
+      } else if let Some(operation) = submissions.in_flight.remove(&id) {
+        operation.complete(result);
+      }
+    }
+    if submissions.stopping && submissions.in_flight.is_empty() {
+      return;
+    }
+    driver.flush(&submissions);
+  }
+}
+
+/// Keeps a ring's driver running while the listener or any of its
+/// connections are open.
+struct DriverHandle(Arc<Driver>);
+
+impl DriverHandle {
+  fn start() -> io::Result<Self> {
+    let (ring, queue, completions) = setup()?;
+    let driver = Arc::new(Driver {
+      ring,
+      submissions: Mutex::new(Submissions {
+        queue,
+        in_flight: HashMap::new(),
+        next_id: STOP + 1,
+        stopping: false,
+      }),
+    });
+    let thread_driver = driver.clone();
+    std::thread::Builder::new()
+      .name("kraglin-io-uring".into())
+      .spawn(move || drive(thread_driver, completions))?;
+    Ok(DriverHandle(driver))
+  }
+}
+
+impl Drop for DriverHandle {
+  fn drop(&mut self) {
+    // the queue only stays full while the kernel is refusing submissions, so
+    // this retries until it makes room, unless the driver is already gone
+    loop {
+      let mut submissions = self.0.submissions.lock().unwrap();
+      if submissions.stopping {
+        return;
+      }
+      let sqe = io_uring_sqe {
+        opcode: IoringOp::Nop,
+        user_data: io_uring_user_data::from_u64(STOP),
+        ..Default::default()
+      };
+      if submissions.queue.push(sqe) {
+        self.0.flush(&submissions);
+        return;
+      }
+      self.0.flush(&submissions);
+      drop(submissions);
+      std::thread::sleep(Duration::from_millis(1));
+    }
+  }
+}
+
+/// A connection accepted by a [`UringListener`].
+struct UringStream {
+  socket:    std::net::TcpStream,
+  driver:    Arc<DriverHandle>,
+  /// The read in flight, if any, with its id.
+  read:      Option<(u64, Arc<Operation>)>,
+  /// Bytes read but not yet returned, from `read_pos` on.
+  read_buf:  Vec<u8>,
+  read_pos:  usize,
+  /// The write in flight, if any, with its id and how much of its buffer
+  /// earlier submissions sent.
+  write:     Option<(u64, Arc<Operation>, usize)>,
+  /// The buffer for the next write, kept to reuse its allocation.
+  write_buf: Vec<u8>,
+}
+
+impl UringStream {
+  fn new(socket: std::net::TcpStream, driver: Arc<DriverHandle>) -> Self {
+    UringStream {
+      socket,
+      driver,
+      read: None,
+      read_buf: Vec::new(),
+      read_pos: 0,
+      write: None,
+      write_buf: Vec::new(),
+    }
+  }
+
+  fn start_read(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+    let mut buf = std::mem::take(&mut self.read_buf);
+    buf.clear();
+    self.read_pos = 0;
+    buf.reserve(READ_LEN);
+    let sqe = io_uring_sqe {
+      opcode: IoringOp::Recv,
+      fd: self.socket.as_raw_fd(),
+      addr_or_splice_off_in: addr_or_splice_off_in_union {
+        addr: io_uring_ptr::new(buf.as_mut_ptr().cast()),
+      },
+      len: len_union {
+        len: u32::try_from(buf.capacity()).unwrap_or(u32::MAX),
+      },
+      ..Default::default()
+    };
+    let operation = Operation::new(buf, cx.waker());
+    let id = self.driver.0.start(sqe, operation.clone())?;
+    self.read = Some((id, operation));
+    Ok(())
+  }
+
+  /// Sends `buf` from `offset` on.
+  fn start_write(
+    &mut self,
+    buf: Vec<u8>,
+    offset: usize,
+    cx: &mut Context<'_>,
+  ) -> io::Result<()> {
+    let sqe = io_uring_sqe {
+      opcode: IoringOp::Send,
+      fd: self.socket.as_raw_fd(),
+      addr_or_splice_off_in: addr_or_splice_off_in_union {
+        addr: io_uring_ptr::new(buf[offset..].as_ptr().cast_mut().cast()),
+      },
+      len: len_union {
+        len: u32::try_from(buf.len() - offset).unwrap_or(u32::MAX),
+      },
+      op_flags: op_flags_union {
+        send_flags: SendFlags::NOSIGNAL,
+      },
+      ..Default::default()
+    };
+    let operation = Operation::new(buf, cx.waker());
+    let id = self.driver.0.start(sqe, operation.clone())?;
+    self.write = Some((id, operation, offset));
+    Ok(())
+  }
+
+  /// Waits for the write in flight to be sent in full, resubmitting what's
+  /// left of it after a short send.
+  fn poll_sent(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    loop {
+      let Some((_, operation, offset)) = &self.write else {
+        return Poll::Ready(Ok(()));
+      };
+      let (result, buf) = ready!(operation.poll(cx));
+      let offset = *offset;
+      self.write = None;
+      let sent = match check(result) {
+        Ok(0) if offset < buf.len() => {
+          Err(io::Error::from(io::ErrorKind::WriteZero))
+        }
+        Ok(sent) => Ok(offset + sent),
+        Err(e) => Err(e),
+      };
+      match sent {
+        Ok(sent) if sent < buf.len() => self.start_write(buf, sent, cx)?,
+        sent => {
+          self.write_buf = buf;
+          return Poll::Ready(sent.map(|_| ()));
+        }
+      }
+    }
+  }
+}
+
+impl AsyncRead for UringStream {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    out: &mut ReadBuf<'_>,
+  ) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    loop {
+      let buffered = &this.read_buf[this.read_pos..];
+      if !buffered.is_empty() {
+        let len = buffered.len().min(out.remaining());
+        out.put_slice(&buffered[..len]);
+        this.read_pos += len;
+        return Poll::Ready(Ok(()));
+      }
+
+      let Some((_, operation)) = &this.read else {
+        this.start_read(cx)?;
+        continue;
+      };
+      let (result, mut buf) = ready!(operation.poll(cx));
+      this.read = None;
+      let read = check(result);
+      if let Ok(len) = read {
+        // SAFETY: the kernel initialized the first `len` bytes, within the
+        // capacity the read was submitted with.
+        unsafe { buf.set_len(len) };
+      }
+      this.read_buf = buf;
+      this.read_pos = 0;
+      // a read of nothing is the end of the stream
+      if read? == 0 {
+        return Poll::Ready(Ok(()));
+      }
+    }
+  }
+}
+
+impl AsyncWrite for UringStream {
+  fn poll_write(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    data: &[u8],
+  ) -> Poll<io::Result<usize>> {
+    let this = self.get_mut();
+    ready!(this.poll_sent(cx))?;
+    if data.is_empty() {
+      return Poll::Ready(Ok(0));
+    }
+    // the write is reported as done once it's submitted; a failure is
+    // returned by the next write or flush
+    let mut buf = std::mem::take(&mut this.write_buf);
+    buf.clear();
+    buf.extend_from_slice(data);
+    this.start_write(buf, 0, cx)?;
+    Poll::Ready(Ok(data.len()))
+  }
+
+  fn poll_flush(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<io::Result<()>> {
+    self.get_mut().poll_sent(cx)
+  }
+
+  fn poll_shutdown(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<io::Result<()>> {
+    let this = self.get_mut();
+    ready!(this.poll_sent(cx))?;
+    match this.socket.shutdown(Shutdown::Write) {
+      Err(e) if e.kind() != io::ErrorKind::NotConnected => Poll::Ready(Err(e)),
+      _ => Poll::Ready(Ok(())),
+    }
+  }
+}
+
+impl Drop for UringStream {
+  fn drop(&mut self) {
+    // the operations hold their buffers until they complete, which their
+    // cancellation hurries along
+    let read = self.read.take().map(|(id, _)| id);
+    let write = self.write.take().map(|(id, ..)| id);
+    for id in read.into_iter().chain(write) {
+      let sqe = io_uring_sqe {
+        opcode: IoringOp::AsyncCancel,
+        addr_or_splice_off_in: addr_or_splice_off_in_union {
+          user_data: io_uring_user_data::from_u64(id),
+        },
+        user_data: io_uring_user_data::from_u64(IGNORED),
+        ..Default::default()
+      };
+      if let Err(e) = self.driver.0.submit(sqe) {
+        tracing::warn!("failed to cancel an io_uring operation: {e}");
+      }
+    }
+  }
+}
+
+/// A [`Listener`] which accepts TCP connections, and reads and writes them
+/// through io_uring. Only available on Linux, with the `io-uring` feature.
+pub struct UringListener {
+  listener: TcpListener,
+  driver:   Arc<DriverHandle>,
+}
+
+impl UringListener {
+  /// Binds a TCP listener to `address` (e.g. `"0.0.0.0:6379"`), and sets up
+  /// the ring its connections use.
+  pub async fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+    let listener = TcpListener::bind(address).await?;
+    Ok(UringListener {
+      listener,
+      driver: Arc::new(DriverHandle::start()?),
+    })
+  }
+
+  /// Returns the address the listener is bound to.
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+}
+
+impl Listener for UringListener {
+  fn accept(&self) -> BoxFuture<'_, io::Result<(BoxedStream, String)>> {
+    async {
+      let (stream, addr) = self.listener.accept().await?;
+      // the ring waits on the socket itself, so it's no longer registered
+      // with the reactor, nor non-blocking
+      let socket = stream.into_std()?;
+      socket.set_nonblocking(false)?;
+      let stream = UringStream::new(socket, self.driver.clone());
+      Ok((Box::new(stream) as BoxedStream, addr.to_string()))
+    }
+    .boxed()
+  }
+
+  fn local_addr(&self) -> io::Result<ListenAddr> {
+    self.listener.local_addr().map(ListenAddr::Tcp)
+  }
+}
@@ -0,0 +1,413 @@
+//! Defines the `WebSocketListener` item, which serves RESP over WebSocket
+//! (RFC 6455) so that browsers and edge runtimes can connect directly.
+//!
+//! Each connection is bridged to an in-memory stream: the payloads of the
+//! client's data frames are written to it as a RESP byte stream, and the
+//! server's replies are sent back as binary frames. Like TCP segments, frame
+//! boundaries needn't line up with RESP messages.
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::{
+  io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+    BufReader, DuplexStream,
+  },
+  net::{TcpListener, TcpStream, ToSocketAddrs},
+  sync::{mpsc, Mutex},
+  task::JoinHandle,
+};
+
+use super::listener::{BoxedStream, ListenAddr, Listener};
+use crate::clock;
+
+/// The GUID which the client's key is hashed with in the handshake.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest handshake request which is accepted.
+const MAX_HANDSHAKE_LEN: usize = 8 * 1024;
+
+/// How long a client has to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest frame payload which is accepted from a client.
+const MAX_FRAME_LEN: u64 = 64 * 1024 * 1024;
+
+/// The size of the buffer between a connection's socket and the server.
+const BRIDGE_BUFFER_LEN: usize = 64 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A [`Listener`] which accepts WebSocket connections on a TCP socket.
+///
+/// Handshakes run in the background, so slow or malformed clients don't
+/// hold up others; [`Listener::accept()`] only returns upgraded connections.
+pub struct WebSocketListener {
+  addr:     SocketAddr,
+  accepted: Mutex<mpsc::Receiver<io::Result<(BoxedStream, String)>>>,
+  task:     JoinHandle<()>,
+}
+
+impl WebSocketListener {
+  /// Binds a WebSocket listener to `address` (e.g. `"0.0.0.0:6380"`). The
+  /// path of the upgrade request is ignored.
+  pub async fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+    let listener = TcpListener::bind(address).await?;
+    let addr = listener.local_addr()?;
+    let (tx, rx) = mpsc::channel(64);
+    let task = tokio::spawn(accept_loop(listener, tx));
+    Ok(WebSocketListener {
+      addr,
+      accepted: Mutex::new(rx),
+      task,
+    })
+  }
+}
+
+impl Drop for WebSocketListener {
+  fn drop(&mut self) { self.task.abort(); }
+}
+
+impl Listener for WebSocketListener {
+  fn accept(&self) -> BoxFuture<'_, io::Result<(BoxedStream, String)>> {
+    async {
+      self.accepted.lock().await.recv().await.unwrap_or_else(|| {
+        Err(io::Error::other("the WebSocket accept loop stopped"))
+      })
+    }
+    .boxed()
+  }
+
+  fn local_addr(&self) -> io::Result<ListenAddr> {
+    Ok(ListenAddr::WebSocket(self.addr))
+  }
+}
+
+/// Accepts TCP connections, upgrading each in its own task and sending the
+/// upgraded ones to `accepted`. Stops when the listener is dropped.
+async fn accept_loop(
+  listener: TcpListener,
+  accepted: mpsc::Sender<io::Result<(BoxedStream, String)>>,
+) {
+  loop {
+    let (stream, addr) = match listener.accept().await {
+      Ok(accepted) => accepted,
+      Err(e) => {
+        let _ = accepted.send(Err(e)).await;
+        return;
+      }
+    };
+    let accepted = accepted.clone();
+    tokio::spawn(async move {
+      match clock::timeout(HANDSHAKE_TIMEOUT, upgrade(stream)).await {
+        Ok(Ok(stream)) => {
+          let _ = accepted.send(Ok((stream, addr.to_string()))).await;
+        }
+        Ok(Err(e)) => {
+          tracing::debug!("WebSocket handshake from {addr} failed: {e}")
+        }
+        Err(_) => tracing::debug!("WebSocket handshake from {addr} timed out"),
+      }
+    });
+  }
+}
+
+/// Performs the server side of the opening handshake, and then bridges the
+/// connection to an in-memory stream which is returned.
+async fn upgrade(stream: TcpStream) -> io::Result<BoxedStream> {
+  let mut socket = BufReader::new(stream);
+
+  let mut request = Vec::new();
+  while !request.ends_with(b"\r\n\r\n") {
+    if socket.read_until(b'\n', &mut request).await? == 0 {
+      return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if request.len() > MAX_HANDSHAKE_LEN {
+      return Err(io::Error::other("handshake request is too long"));
+    }
+  }
+
+  let key = match handshake_key(&request) {
+    Ok(key) => key,
+    Err(reason) => {
+      socket
+        .write_all(
+          format!(
+            "HTTP/1.1 400 Bad Request\r\nContent-Length: {}\r\nConnection: \
+             close\r\n\r\n{reason}",
+            reason.len()
+          )
+          .as_bytes(),
+        )
+        .await?;
+      return Err(io::Error::other(reason));
+    }
+  };
+  socket
+    .write_all(
+      format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: \
+         websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+      )
+      .as_bytes(),
+    )
+    .await?;
+
+  let (server, bridged) = tokio::io::duplex(BRIDGE_BUFFER_LEN);
+  tokio::spawn(bridge(socket, bridged));
+  Ok(Box::new(server))
+}
+
+/// Validates an upgrade request, returning its `Sec-WebSocket-Key`.
+fn handshake_key(request: &[u8]) -> Result<&str, &'static str> {
+  let request =
+    std::str::from_utf8(request).map_err(|_| "request isn't valid UTF-8")?;
+  let mut lines = request.split("\r\n");
+  let request_line = lines.next().unwrap_or_default();
+  if !request_line.starts_with("GET ") || !request_line.ends_with(" HTTP/1.1") {
+    return Err("expected a GET request over HTTP/1.1");
+  }
+
+  let (mut upgrade, mut connection, mut version, mut key) =
+    (false, false, false, None);
+  for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+    let value = value.trim();
+    let has_token = |token: &str| {
+      value
+        .split(',')
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+      "upgrade" => upgrade = has_token("websocket"),
+      "connection" => connection = has_token("upgrade"),
+      "sec-websocket-version" => version = value == "13",
+      "sec-websocket-key" => key = Some(value),
+      _ => {}
+    }
+  }
+
+  if !upgrade || !connection {
+    return Err("expected a WebSocket upgrade request");
+  }
+  if !version {
+    return Err("only WebSocket version 13 is supported");
+  }
+  key.ok_or("missing Sec-WebSocket-Key")
+}
+
+/// Computes the `Sec-WebSocket-Accept` header for a client's key.
+fn accept_key(key: &str) -> String {
+  base64(&sha1(format!("{key}{HANDSHAKE_GUID}").as_bytes()))
+}
+
+/// Shuttles data between a WebSocket `socket` and the server's end of a
+/// connection, until either side closes.
+async fn bridge(socket: BufReader<TcpStream>, bridged: DuplexStream) {
+  let (mut socket_rx, socket_tx) = tokio::io::split(socket);
+  let socket_tx = Mutex::new(socket_tx);
+  let (mut bridged_rx, mut bridged_tx) = tokio::io::split(bridged);
+
+  let inbound = async {
+    loop {
+      let (opcode, payload) = read_frame(&mut socket_rx).await?;
+      match opcode {
+        OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+          bridged_tx.write_all(&payload).await?
+        }
+        OPCODE_PING => {
+          let mut tx = socket_tx.lock().await;
+          write_frame(&mut *tx, OPCODE_PONG, &payload).await?
+        }
+        OPCODE_PONG => {}
+        OPCODE_CLOSE => {
+          // echo the close frame's status code back, as the protocol requires
+          let mut tx = socket_tx.lock().await;
+          let status = payload.get(..2).unwrap_or_default();
+          return write_frame(&mut *tx, OPCODE_CLOSE, status).await;
+        }
+        _ => return Err(io::Error::other("unknown WebSocket opcode")),
+      }
+    }
+  };
+
+  let outbound = async {
+    let mut buf = vec![0; BRIDGE_BUFFER_LEN];
+    loop {
+      let n = bridged_rx.read(&mut buf).await?;
+      let mut tx = socket_tx.lock().await;
+      if n == 0 {
+        // the server closed the connection: 1000 is a normal closure
+        return write_frame(&mut *tx, OPCODE_CLOSE, &1000u16.to_be_bytes())
+          .await;
+      }
+      write_frame(&mut *tx, OPCODE_BINARY, &buf[..n]).await?;
+    }
+  };
+
+  let result: io::Result<()> = tokio::select! {
+    result = inbound => result,
+    result = outbound => result,
+  };
+  if let Err(e) = result {
+    tracing::debug!("WebSocket connection failed: {e}");
+  }
+}
+
+/// Reads one frame from a client, returning its opcode and unmasked payload.
+async fn read_frame<R: AsyncRead + Unpin>(
+  reader: &mut R,
+) -> io::Result<(u8, Vec<u8>)> {
+  let mut header = [0; 2];
+  reader.read_exact(&mut header).await?;
+  let opcode = header[0] & 0x0F;
+  if header[1] & 0x80 == 0 {
+    return Err(io::Error::other("client frames must be masked"));
+  }
+
+  let len = match header[1] & 0x7F {
+    126 => reader.read_u16().await? as u64,
+    127 => reader.read_u64().await?,
+    len => len as u64,
+  };
+  if len > MAX_FRAME_LEN || (opcode >= OPCODE_CLOSE && len > 125) {
+    return Err(io::Error::other("WebSocket frame is too long"));
+  }
+
+  let mut mask = [0; 4];
+  reader.read_exact(&mut mask).await?;
+  let mut payload = vec![0; len as usize];
+  reader.read_exact(&mut payload).await?;
+  for (i, byte) in payload.iter_mut().enumerate() {
+    *byte ^= mask[i % 4];
+  }
+  Ok((opcode, payload))
+}
+
+/// Writes one unfragmented, unmasked frame, as servers send them.
+async fn write_frame<W: AsyncWrite + Unpin>(
+  writer: &mut W,
+  opcode: u8,
+  payload: &[u8],
+) -> io::Result<()> {
+  let mut frame = Vec::with_capacity(payload.len() + 10);
+  frame.push(0x80 | opcode);
+  match payload.len() {
+    len @ 0..=125 => frame.push(len as u8),
+    len @ 126..=0xFFFF => {
+      frame.push(126);
+      frame.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    len => {
+      frame.push(127);
+      frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+  }
+  frame.extend_from_slice(payload);
+  writer.write_all(&frame).await?;
+  writer.flush().await
+}
+
+/// Computes the SHA-1 digest of `data`, which the handshake requires.
+fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut h: [u32; 5] =
+    [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+  for block in message.chunks_exact(64) {
+    let mut w = [0u32; 80];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+      w[i] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    for i in 16..80 {
+      w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = h;
+    for (i, word) in w.iter().enumerate() {
+      let (f, k) = match i {
+        0..=19 => ((b & c) | (!b & d), 0x5A827999),
+        20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+        _ => (b ^ c ^ d, 0xCA62C1D6),
+      };
+      let temp = a
+        .rotate_left(5)
+        .wrapping_add(f)
+        .wrapping_add(e)
+        .wrapping_add(k)
+        .wrapping_add(*word);
+      (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+    }
+    for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+      *h = h.wrapping_add(v);
+    }
+  }
+
+  let mut digest = [0; 20];
+  for (chunk, word) in digest.chunks_exact_mut(4).zip(h) {
+    chunk.copy_from_slice(&word.to_be_bytes());
+  }
+  digest
+}
+
+/// Encodes `data` as padded standard base64.
+fn base64(data: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+  let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+  for chunk in data.chunks(3) {
+    let bytes = [
+      chunk[0],
+      *chunk.get(1).unwrap_or(&0),
+      *chunk.get(2).unwrap_or(&0),
+    ];
+    let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+    for i in 0..4 {
+      if i <= chunk.len() {
+        encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+      } else {
+        encoded.push('=');
+      }
+    }
+  }
+  encoded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{accept_key, base64, sha1};
+
+  #[test]
+  fn accept_key_matches_the_rfc_example() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(
+      base64(&sha1(b"abc")),
+      base64(&[
+        0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e, 0x25, 0x71,
+        0x78, 0x50, 0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+      ])
+    );
+    // from RFC 6455, section 1.3
+    assert_eq!(
+      accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+      "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+  }
+}
@@ -0,0 +1,352 @@
+//! Defines the `Executor` item, which decides where connections' commands
+//! run: inline in each connection's task, or on a shared pool of workers.
+//!
+//! With a [`WorkerPool`], commands from every connection are fed into
+//! bounded queues, which a fixed number of worker tasks drain. Many busy
+//! connections then share the workers fairly rather than competing for the
+//! runtime, and a full queue pushes back on the connections filling it.
+//!
+//! Commands are queued in one of two lanes by their [`Priority`]. Workers
+//! always take from the high priority lane first, so that operators can
+//! still inspect a server saturated with heavy data commands.
+
+use std::{panic::AssertUnwindSafe, sync::Arc};
+
+use futures::FutureExt;
+use tokio::{
+  sync::{mpsc, oneshot, Mutex},
+  task::JoinSet,
+};
+
+use super::{
+  context::ConnectionContext, dispatch::Dispatcher, supervisor::panic_message,
+};
+use crate::{
+  backends::Backend,
+  command::{AclCategories, Command, CommandFlags},
+  KraglinError, KraglinResult,
+};
+
+/// The default for [`WorkerPool::new()`]'s `queue_len`.
+pub const DEFAULT_WORKER_QUEUE_LEN: usize = 1024;
+
+/// Which lane of a [`WorkerPool`]'s queue a command waits in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+  /// Admin and health commands, like `PING`, `INFO`, `CLIENT`, and `ACL`,
+  /// which run before any queued normal priority command.
+  High,
+  /// Every other command.
+  Normal,
+}
+
+impl Priority {
+  /// Classifies `command`. Custom commands are high priority if they're
+  /// flagged [`ADMIN`](CommandFlags::ADMIN).
+  pub fn of(command: &Command) -> Priority {
+    let high = command.flags().contains(CommandFlags::ADMIN)
+      || command.acl_categories().contains(AclCategories::CONNECTION)
+      || matches!(command, Command::Info | Command::Help { .. });
+    if high {
+      Priority::High
+    } else {
+      Priority::Normal
+    }
+  }
+}
+
+/// A command waiting for a worker, with the context of the connection which
+/// sent it. The context is handed back with the reply.
+struct Job {
+  ctx:     ConnectionContext,
+  command: Command,
+  reply:   oneshot::Sender<(ConnectionContext, KraglinResult)>,
+}
+
+/// The receiving ends of the queues, shared by the workers.
+struct Queues {
+  high:   mpsc::Receiver<Job>,
+  normal: mpsc::Receiver<Job>,
+}
+
+/// A fixed number of worker tasks which run commands from a shared, bounded
+/// queue. The workers stop once the pool is dropped and the queue drains.
+pub struct WorkerPool<B: Backend> {
+  high:       mpsc::Sender<Job>,
+  normal:     mpsc::Sender<Job>,
+  dispatcher: Arc<Dispatcher<B>>,
+  workers:    JoinSet<()>,
+}
+
+impl<B: Backend> WorkerPool<B> {
+  /// Spawns `workers` workers running commands through `dispatcher`, with
+  /// room for `queue_len` commands to wait for them in each lane.
+  ///
+  /// # Panics
+  /// Panics if `workers` or `queue_len` is zero.
+  pub fn new(
+    dispatcher: Arc<Dispatcher<B>>,
+    workers: usize,
+    queue_len: usize,
+  ) -> Self {
+    assert!(workers > 0, "a worker pool needs at least one worker");
+    let (high, high_rx) = mpsc::channel(queue_len);
+    let (normal, normal_rx) = mpsc::channel(queue_len);
+    let queues = Arc::new(Mutex::new(Queues {
+      high:   high_rx,
+      normal: normal_rx,
+    }));
+
+    let mut set = JoinSet::new();
+    for _ in 0..workers {
+      set.spawn(work(dispatcher.clone(), queues.clone()));
+    }
+    WorkerPool {
+      high,
+      normal,
+      dispatcher,
+      workers: set,
+    }
+  }
+
+  /// Returns how many workers the pool has.
+  pub fn workers(&self) -> usize { self.workers.len() }
+
+  /// Queues `command` and waits for a worker to run it on behalf of the
+  /// connection `ctx`. Waits for room first if the queue is full.
+  pub async fn dispatch(
+    &self,
+    ctx: &mut ConnectionContext,
+    command: Command,
+  ) -> KraglinResult {
+    let queue = match Priority::of(&command) {
+      Priority::High => &self.high,
+      Priority::Normal => &self.normal,
+    };
+    let (reply, rx) = oneshot::channel();
+    let job = Job {
+      ctx: ctx.clone(),
+      command,
+      reply,
+    };
+    if queue.send(job).await.is_err() {
+      return Err(KraglinError::ExecutionFailed);
+    }
+    // the job is only dropped without a reply if its command panicked, in
+    // which case the connection keeps its context as it was
+    let (new_ctx, result) =
+      rx.await.map_err(|_| KraglinError::ExecutionFailed)?;
+    *ctx = new_ctx;
+    result
+  }
+}
+
+/// Runs jobs from the queues, high priority ones first, until they're closed
+/// and drained.
+async fn work<B: Backend>(
+  dispatcher: Arc<Dispatcher<B>>,
+  queues: Arc<Mutex<Queues>>,
+) {
+  loop {
+    let job = {
+      let mut queues = queues.lock().await;
+      let Queues { high, normal } = &mut *queues;
+      tokio::select! {
+        biased;
+        Some(job) = high.recv() => job,
+        Some(job) = normal.recv() => job,
+        else => return,
+      }
+    };
+    let Job {
+      mut ctx,
+      command,
+      reply,
+    } = job;
+    // a command which panics drops its reply rather than the worker, so
+    // the pool never shrinks
+    let dispatched = AssertUnwindSafe(dispatcher.dispatch(&mut ctx, command))
+      .catch_unwind()
+      .await;
+    match dispatched {
+      Ok(result) => {
+        let _ = reply.send((ctx, result));
+      }
+      Err(panic) => tracing::error!(
+        "command from {} panicked: {}",
+        ctx.peer(),
+        panic_message(&*panic)
+      ),
+    }
+  }
+}
+
+/// Where connections' commands run.
+pub enum Executor<B: Backend> {
+  /// In each connection's own task.
+  Inline(Arc<Dispatcher<B>>),
+  /// On a shared pool of workers.
+  Pool(WorkerPool<B>),
+}
+
+impl<B: Backend> Executor<B> {
+  /// Returns the dispatcher commands are run through.
+  pub fn dispatcher(&self) -> &Arc<Dispatcher<B>> {
+    match self {
+      Executor::Inline(dispatcher) => dispatcher,
+      Executor::Pool(pool) => &pool.dispatcher,
+    }
+  }
+
+  /// Runs `command` on behalf of the connection `ctx`.
+  pub async fn dispatch(
+    &self,
+    ctx: &mut ConnectionContext,
+    command: Command,
+  ) -> KraglinResult {
+    match self {
+      Executor::Inline(dispatcher) => dispatcher.dispatch(ctx, command).await,
+      Executor::Pool(pool) => pool.dispatch(ctx, command).await,
+    }
+  }
+}
+
+#[cfg(all(test, feature = "simple"))]
+mod tests {
+  use std::{sync::Arc, time::Duration};
+
+  use futures::future::join_all;
+
+  use super::{Priority, WorkerPool};
+  use crate::{
+    backends::{simple::SimpleBackend, Backend, BackendConfig},
+    clock,
+    command::{Command, CommandFlags},
+    server::{CommandRegistry, ConnectionContext, Dispatcher},
+    value::Value,
+    KraglinError,
+  };
+
+  #[tokio::test(start_paused = true)]
+  async fn workers_share_a_queue_and_prioritize_connection_commands() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut commands = CommandRegistry::default();
+    commands.register(
+      "slow",
+      1,
+      CommandFlags::empty(),
+      |_: Arc<SimpleBackend>, _| async {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        Ok(Value::SimpleString("OK".into()))
+      },
+    );
+    let dispatcher =
+      Arc::new(Dispatcher::new(Arc::new(backend), vec![], commands));
+    let pool = WorkerPool::new(dispatcher.clone(), 2, 16);
+    assert_eq!(pool.workers(), 2);
+    let slow = || {
+      dispatcher
+        .parse(Value::Array(vec![Value::BulkString("SLOW".into())]))
+        .unwrap()
+    };
+
+    // four slow commands on two workers take two rounds
+    let start = tokio::time::Instant::now();
+    let replies = join_all((0..4).map(|i| {
+      let pool = &pool;
+      let command = slow();
+      async move {
+        let mut ctx = ConnectionContext::new(format!("client {i}"));
+        pool.dispatch(&mut ctx, command).await
+      }
+    }))
+    .await;
+    assert!(replies.iter().all(Result::is_ok));
+    assert_eq!(start.elapsed(), Duration::from_secs(2));
+
+    // with one worker busy and others queued, `PING` runs next
+    let pool = WorkerPool::new(dispatcher.clone(), 1, 16);
+    let start = tokio::time::Instant::now();
+    let (_, pinged) = tokio::join!(
+      join_all((0..3).map(|_| {
+        let pool = &pool;
+        let command = slow();
+        async move {
+          let mut ctx = ConnectionContext::new("client");
+          pool.dispatch(&mut ctx, command).await
+        }
+      })),
+      async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut ctx = ConnectionContext::new("health check");
+        pool
+          .dispatch(&mut ctx, Command::Ping { message: None })
+          .await
+          .unwrap();
+        start.elapsed()
+      }
+    );
+    assert_eq!(pinged, Duration::from_secs(1));
+
+    // contexts come back updated
+    let mut ctx = ConnectionContext::new("test");
+    pool
+      .dispatch(&mut ctx, Command::ClientNoTouch { enabled: true })
+      .await
+      .unwrap();
+    assert!(ctx.is_no_touch());
+  }
+
+  #[tokio::test]
+  async fn workers_survive_panicking_commands() {
+    let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+    let mut commands = CommandRegistry::default();
+    commands.register(
+      "boom",
+      1,
+      CommandFlags::empty(),
+      |_: Arc<SimpleBackend>, _| async { panic!("boom") },
+    );
+    let dispatcher =
+      Arc::new(Dispatcher::new(Arc::new(backend), vec![], commands));
+    let pool = WorkerPool::new(dispatcher.clone(), 1, 16);
+    let boom = dispatcher
+      .parse(Value::Array(vec![Value::BulkString("BOOM".into())]))
+      .unwrap();
+
+    let mut ctx = ConnectionContext::new("test");
+    for _ in 0..3 {
+      assert!(matches!(
+        pool.dispatch(&mut ctx, boom.clone()).await,
+        Err(KraglinError::ExecutionFailed)
+      ));
+    }
+    // the only worker is still there to run the next command
+    let ping = pool.dispatch(&mut ctx, Command::Ping { message: None });
+    let pong = clock::timeout(Duration::from_secs(5), ping)
+      .await
+      .expect("no worker ran the command");
+    assert_eq!(pong.unwrap(), Value::SimpleString("PONG".into()));
+  }
+
+  #[test]
+  fn admin_and_health_commands_are_high_priority() {
+    let high = [
+      Command::Ping { message: None },
+      Command::Info,
+      Command::ClientInfo,
+      Command::AclList,
+      Command::LatencyLatest,
+      Command::Help {
+        command: "CLIENT".into(),
+      },
+    ];
+    for command in high {
+      assert_eq!(Priority::of(&command), Priority::High, "{command:?}");
+    }
+    let normal = [Command::Get { key: "k".into() }, Command::Keys];
+    for command in normal {
+      assert_eq!(Priority::of(&command), Priority::Normal, "{command:?}");
+    }
+  }
+}
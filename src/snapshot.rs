@@ -0,0 +1,466 @@
+//! Reading and writing snapshot files.
+//!
+//! A snapshot holds keys as they were at one moment. It starts with
+//! [`MAGIC`], the format version as a little-endian `u16`, and its
+//! generation as a little-endian `u64`. The entries follow, each a key and
+//! its [`Dump`] payload, both prefixed with their lengths as little-endian
+//! `u64`s. It ends with the [CRC-64](crate::crc64) of everything before it,
+//! so that a damaged snapshot is refused on startup rather than loaded as
+//! garbage. Each payload also has its own checksum.
+//!
+//! A full snapshot holds the whole keyspace. A delta snapshot only holds the
+//! keys which changed since the snapshot before it, with an empty payload for
+//! each key which was deleted. [`SnapshotStore`] keeps a full snapshot and the
+//! deltas written on top of it, and merges them when they're loaded.
+//!
+//! Snapshots are written to a temporary file which replaces the old one once
+//! it's been synced, so a crash while writing leaves the old snapshot intact.
+
+use std::{
+  collections::HashMap,
+  fs::File,
+  io::{BufWriter, Write},
+  path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Result, WrapErr};
+use smol_str::SmolStr;
+
+use crate::{
+  crc64::crc64,
+  value::{Dump, DumpError},
+};
+
+/// The bytes every snapshot starts with.
+pub const MAGIC: &[u8; 8] = b"KRAGLIN\0";
+/// The version of the snapshot format written by [`write()`]. Version 1
+/// snapshots have no generation, and are read as generation 0.
+pub const SNAPSHOT_VERSION: u16 = 2;
+/// The number of deltas which can be written on top of a full snapshot
+/// before the next save writes a full snapshot instead.
+pub const MAX_DELTAS: usize = 16;
+
+/// An error reading a snapshot with [`decode()`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SnapshotError {
+  /// The file doesn't start with [`MAGIC`], so isn't a snapshot.
+  #[error("not a snapshot file")]
+  NotASnapshot,
+  /// The snapshot was written by a newer version of the format.
+  #[error("unsupported snapshot version {0}")]
+  UnsupportedVersion(u16),
+  /// The checksum doesn't match the contents, e.g. because the file was
+  /// truncated or damaged on disk.
+  #[error("checksum mismatch: expected {expected:#018x}, found {found:#018x}")]
+  ChecksumMismatch {
+    /// The checksum recorded at the end of the file.
+    expected: u64,
+    /// The checksum of the contents.
+    found:    u64,
+  },
+  /// The checksum matches, but an entry is malformed.
+  #[error("entry {entry} is malformed: {reason}")]
+  Malformed {
+    /// The index of the entry.
+    entry:  usize,
+    /// What's wrong with it.
+    reason: String,
+  },
+}
+
+/// The contents of a snapshot file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+  /// The generation of the full snapshot, which its deltas share.
+  pub generation: u64,
+  /// The keys and their values, or `None` for keys a delta deletes.
+  pub entries:    Vec<(SmolStr, Option<Dump>)>,
+}
+
+/// Serializes `entries` into a snapshot of the given generation.
+pub fn encode<'a>(
+  generation: u64,
+  entries: impl IntoIterator<Item = (&'a SmolStr, Option<Dump>)>,
+) -> Vec<u8> {
+  let mut buf = Vec::from(*MAGIC);
+  buf.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+  buf.extend_from_slice(&generation.to_le_bytes());
+  for (key, dump) in entries {
+    let payload = dump.map(|dump| dump.encode()).unwrap_or_default();
+    buf.extend_from_slice(&(key.len() as u64).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    buf.extend_from_slice(&payload);
+  }
+  let checksum = crc64(0, &buf);
+  buf.extend_from_slice(&checksum.to_le_bytes());
+  buf
+}
+
+/// Deserializes a snapshot written by [`encode()`], after verifying its
+/// checksum.
+pub fn decode(data: &[u8]) -> Result<Snapshot, SnapshotError> {
+  if data.len() < MAGIC.len() + 2 || !data.starts_with(MAGIC) {
+    return Err(SnapshotError::NotASnapshot);
+  }
+  let version = u16::from_le_bytes([data[MAGIC.len()], data[MAGIC.len() + 1]]);
+  if version > SNAPSHOT_VERSION {
+    return Err(SnapshotError::UnsupportedVersion(version));
+  }
+  let header_len = MAGIC.len() + 2 + if version >= 2 { 8 } else { 0 };
+  let Some((checked, expected)) = data
+    .len()
+    .checked_sub(8)
+    .filter(|&len| len >= header_len)
+    .map(|len| data.split_at(len))
+  else {
+    return Err(SnapshotError::ChecksumMismatch {
+      expected: 0,
+      found:    crc64(0, data),
+    });
+  };
+  let expected = u64::from_le_bytes(expected.try_into().unwrap());
+  let found = crc64(0, checked);
+  if expected != found {
+    return Err(SnapshotError::ChecksumMismatch { expected, found });
+  }
+
+  let generation = match version {
+    1 => 0,
+    _ => u64::from_le_bytes(
+      checked[MAGIC.len() + 2..header_len].try_into().unwrap(),
+    ),
+  };
+  let mut rest = &checked[header_len..];
+  let mut entries = Vec::new();
+  while !rest.is_empty() {
+    let malformed = |reason: &str| SnapshotError::Malformed {
+      entry:  entries.len(),
+      reason: reason.to_owned(),
+    };
+    let key = take_prefixed(&mut rest).ok_or_else(|| malformed("truncated"))?;
+    let key = std::str::from_utf8(key)
+      .map_err(|_| malformed("the key isn't valid UTF-8"))?;
+    let payload =
+      take_prefixed(&mut rest).ok_or_else(|| malformed("truncated"))?;
+    let dump = match payload {
+      [] => None,
+      payload => Some(Dump::decode(payload).map_err(|e: DumpError| {
+        malformed(&format!("the payload of `{key}` is invalid: {e}"))
+      })?),
+    };
+    entries.push((SmolStr::from(key), dump));
+  }
+  Ok(Snapshot {
+    generation,
+    entries,
+  })
+}
+
+/// Takes a slice prefixed with its length as a little-endian `u64` off the
+/// front of `data`.
+fn take_prefixed<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+  let (len, rest) = data.split_first_chunk::<8>()?;
+  let len = usize::try_from(u64::from_le_bytes(*len)).ok()?;
+  if rest.len() < len {
+    return None;
+  }
+  let (taken, rest) = rest.split_at(len);
+  *data = rest;
+  Some(taken)
+}
+
+/// Reads the snapshot at `path`. Fails with a description of the damage if
+/// it's corrupt.
+pub fn read(path: &Path) -> Result<Snapshot> {
+  let data = std::fs::read(path)
+    .wrap_err_with(|| format!("failed to read {}", path.display()))?;
+  decode(&data).wrap_err_with(|| {
+    format!("snapshot {} is corrupt and can't be loaded", path.display())
+  })
+}
+
+/// Writes `entries` as the snapshot at `path`, atomically replacing any
+/// snapshot already there.
+pub fn write<'a>(
+  path: &Path,
+  generation: u64,
+  entries: impl IntoIterator<Item = (&'a SmolStr, Option<Dump>)>,
+) -> Result<()> {
+  let temp = path.with_extension("tmp");
+  let write = || -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(&temp)?);
+    file.write_all(&encode(generation, entries))?;
+    file.into_inner()?.sync_all()?;
+    std::fs::rename(&temp, path)
+  };
+  write().wrap_err_with(|| format!("failed to write {}", path.display()))
+}
+
+/// A full snapshot and the deltas written on top of it.
+///
+/// Deltas are numbered in the order they're written, and only apply to the
+/// full snapshot of their generation. Writing a full snapshot starts a new
+/// generation before the old deltas are removed, so if the server crashes in
+/// between, the leftovers are ignored rather than applied to the wrong
+/// snapshot.
+#[derive(Debug)]
+pub struct SnapshotStore {
+  path:       PathBuf,
+  deltas_dir: PathBuf,
+  /// The generation of the full snapshot, or `None` if there isn't one.
+  generation: Option<u64>,
+  /// The deltas which apply to the full snapshot, in order.
+  deltas:     Vec<PathBuf>,
+  /// The number of the next delta.
+  next_delta: u64,
+}
+
+impl SnapshotStore {
+  /// Opens the full snapshot at `path` and the deltas in `deltas_dir`,
+  /// returning the keys they hold once the deltas are applied. Neither has
+  /// to exist.
+  pub fn open(
+    path: impl Into<PathBuf>,
+    deltas_dir: impl Into<PathBuf>,
+  ) -> Result<(SnapshotStore, Vec<(SmolStr, Dump)>)> {
+    let (path, deltas_dir) = (path.into(), deltas_dir.into());
+    let (generation, mut keys) = if path.exists() {
+      let snapshot = read(&path)?;
+      let keys = snapshot
+        .entries
+        .into_iter()
+        .filter_map(|(key, dump)| Some((key, dump?)))
+        .collect::<HashMap<_, _>>();
+      (Some(snapshot.generation), keys)
+    } else {
+      (None, HashMap::new())
+    };
+
+    let mut deltas = Vec::new();
+    let mut next_delta = 0;
+    for (number, delta_path) in list_deltas(&deltas_dir)? {
+      next_delta = number + 1;
+      let delta = read(&delta_path)?;
+      if Some(delta.generation) != generation {
+        tracing::warn!(
+          "ignoring {}, which was written for another snapshot",
+          delta_path.display()
+        );
+        continue;
+      }
+      for (key, dump) in delta.entries {
+        match dump {
+          Some(dump) => keys.insert(key, dump),
+          None => keys.remove(&key),
+        };
+      }
+      deltas.push(delta_path);
+    }
+
+    let store = SnapshotStore {
+      path,
+      deltas_dir,
+      generation,
+      deltas,
+      next_delta,
+    };
+    Ok((store, keys.into_iter().collect()))
+  }
+
+  /// Returns whether a full snapshot has been written.
+  pub fn has_full(&self) -> bool { self.generation.is_some() }
+
+  /// Returns the number of deltas on top of the full snapshot.
+  pub fn deltas(&self) -> usize { self.deltas.len() }
+
+  /// Returns whether the next save should be a full snapshot rather than a
+  /// delta, given how many of the keyspace's `keys` have `changed`: when
+  /// there's no full snapshot yet, when there are already [`MAX_DELTAS`]
+  /// deltas, or when most keys changed anyway.
+  pub fn wants_full(&self, changed: usize, keys: usize) -> bool {
+    self.generation.is_none()
+      || self.deltas.len() >= MAX_DELTAS
+      || changed.saturating_mul(2) >= keys
+  }
+
+  /// Writes `entries` as a new full snapshot, replacing the old one and its
+  /// deltas.
+  pub fn write_full<'a>(
+    &mut self,
+    entries: impl IntoIterator<Item = (&'a SmolStr, Dump)>,
+  ) -> Result<()> {
+    let generation = self.generation.map_or(0, |g| g + 1);
+    write(
+      &self.path,
+      generation,
+      entries.into_iter().map(|(key, dump)| (key, Some(dump))),
+    )?;
+    self.generation = Some(generation);
+
+    for (_, path) in list_deltas(&self.deltas_dir)? {
+      std::fs::remove_file(&path)
+        .wrap_err_with(|| format!("failed to remove {}", path.display()))?;
+    }
+    self.deltas.clear();
+    Ok(())
+  }
+
+  /// Writes the changed `entries`, with `None` for deleted keys, as a delta
+  /// on top of the full snapshot, which must exist.
+  pub fn write_delta<'a>(
+    &mut self,
+    entries: impl IntoIterator<Item = (&'a SmolStr, Option<Dump>)>,
+  ) -> Result<()> {
+    let generation = self
+      .generation
+      .expect("deltas are only written on top of a full snapshot");
+    std::fs::create_dir_all(&self.deltas_dir).wrap_err_with(|| {
+      format!("failed to create {}", self.deltas_dir.display())
+    })?;
+    let path = self.deltas_dir.join(format!("{:010}.kdb", self.next_delta));
+    write(&path, generation, entries)?;
+    self.next_delta += 1;
+    self.deltas.push(path);
+    Ok(())
+  }
+}
+
+/// Returns the deltas in `dir` and their numbers, in order. A missing
+/// directory has none.
+fn list_deltas(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+  let entries = match std::fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => {
+      return Err(e)
+        .wrap_err_with(|| format!("failed to list {}", dir.display()))
+    }
+  };
+  let mut deltas = Vec::new();
+  for entry in entries {
+    let path = entry
+      .wrap_err_with(|| format!("failed to list {}", dir.display()))?
+      .path();
+    // temporary files left by a crash while writing are skipped
+    let number = path
+      .extension()
+      .filter(|extension| *extension == "kdb")
+      .and_then(|_| path.file_stem()?.to_str()?.parse().ok());
+    if let Some(number) = number {
+      deltas.push((number, path));
+    }
+  }
+  deltas.sort();
+  Ok(deltas)
+}
+
+#[cfg(test)]
+mod tests {
+  use smol_str::SmolStr;
+
+  use super::{
+    decode, encode, read, write, Snapshot, SnapshotError, SnapshotStore,
+  };
+  use crate::value::{Dump, StoredValue};
+
+  fn dump(value: &str) -> Dump {
+    Dump {
+      value:           StoredValue::BulkString(value.to_owned().into()),
+      deadline:        None,
+      field_deadlines: vec![],
+    }
+  }
+
+  fn entries() -> Vec<(SmolStr, Option<Dump>)> {
+    vec![("a".into(), Some(dump("a"))), ("b".into(), None)]
+  }
+
+  #[test]
+  fn snapshots_round_trip() {
+    let entries = entries();
+    let data = encode(3, entries.iter().map(|(k, d)| (k, d.clone())));
+    assert_eq!(
+      decode(&data),
+      Ok(Snapshot {
+        generation: 3,
+        entries:    entries.clone(),
+      })
+    );
+
+    let path = std::env::temp_dir()
+      .join(format!("kraglin-snapshot-test-{}.kdb", std::process::id()));
+    write(&path, 0, entries.iter().map(|(k, d)| (k, d.clone()))).unwrap();
+    assert_eq!(read(&path).unwrap().entries, entries);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn corrupt_snapshots_are_refused() {
+    let data = encode(0, entries().iter().map(|(k, d)| (k, d.clone())));
+
+    let mut flipped = data.clone();
+    flipped[20] ^= 0x40;
+    assert!(matches!(
+      decode(&flipped),
+      Err(SnapshotError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+      decode(&data[..data.len() - 5]),
+      Err(SnapshotError::ChecksumMismatch { .. })
+    ));
+    assert_eq!(
+      decode(b"*1\r\n$4\r\nPING\r\n"),
+      Err(SnapshotError::NotASnapshot)
+    );
+  }
+
+  #[test]
+  fn deltas_are_merged_onto_their_snapshot() {
+    let dir = std::env::temp_dir().join(format!(
+      "kraglin-snapshot-store-test-{}",
+      std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let open = || SnapshotStore::open(dir.join("dump.kdb"), dir.join("deltas"));
+    let sorted = |mut keys: Vec<(SmolStr, Dump)>| {
+      keys.sort_by(|a, b| a.0.cmp(&b.0));
+      keys
+    };
+    let (a, b, c) =
+      (SmolStr::from("a"), SmolStr::from("b"), SmolStr::from("c"));
+
+    let (mut store, keys) = open().unwrap();
+    assert!(keys.is_empty());
+    assert!(store.wants_full(0, 0));
+    store
+      .write_full([(&a, dump("1")), (&b, dump("1"))])
+      .unwrap();
+    store.write_delta([(&a, Some(dump("2")))]).unwrap();
+    store
+      .write_delta([(&b, None), (&c, Some(dump("2")))])
+      .unwrap();
+    assert!(!store.wants_full(1, 10));
+    assert!(store.wants_full(5, 10));
+
+    let (mut store, keys) = open().unwrap();
+    assert_eq!(store.deltas(), 2);
+    assert_eq!(sorted(keys), vec![
+      (a.clone(), dump("2")),
+      (c.clone(), dump("2"))
+    ]);
+
+    // deltas of an old generation, left by a crash during a full save, are
+    // ignored
+    let stale = std::fs::read(dir.join("deltas/0000000000.kdb")).unwrap();
+    store.write_full([(&a, dump("3"))]).unwrap();
+    assert_eq!(store.deltas(), 0);
+    std::fs::write(dir.join("deltas/0000000005.kdb"), stale).unwrap();
+    let (store, keys) = open().unwrap();
+    assert_eq!(store.deltas(), 0);
+    assert_eq!(keys, vec![(a, dump("3"))]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
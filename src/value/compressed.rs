@@ -0,0 +1,61 @@
+//! Defines the `CompressedBytes` item, a bulk string compressed at rest.
+
+use bytes::Bytes;
+
+/// A bulk string stored LZ4-compressed.
+///
+/// This is transparent to clients: it's created when a large bulk string is
+/// written and decompressed back into a [`Value::BulkString`] when read.
+///
+/// [`Value::BulkString`]: super::Value::BulkString
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CompressedBytes {
+  compressed: Bytes,
+  /// The length of the uncompressed bytes.
+  len:        usize,
+}
+
+impl CompressedBytes {
+  /// Compresses `bytes`, or returns `None` if that wouldn't save space.
+  pub fn compress(bytes: &[u8]) -> Option<Self> {
+    let compressed = lz4_flex::block::compress(bytes);
+    (compressed.len() < bytes.len()).then(|| CompressedBytes {
+      compressed: compressed.into(),
+      len:        bytes.len(),
+    })
+  }
+
+  /// Decompresses the bytes.
+  pub fn decompress(&self) -> Bytes {
+    lz4_flex::block::decompress(&self.compressed, self.len)
+      .expect("compressed bytes were produced by `compress()`")
+      .into()
+  }
+
+  /// The length of the uncompressed bytes.
+  pub fn len(&self) -> usize { self.len }
+
+  /// Whether the uncompressed bytes are empty. Empty strings are never
+  /// compressed, so this is always `false`.
+  pub fn is_empty(&self) -> bool { self.len == 0 }
+
+  /// The length of the compressed bytes.
+  pub fn compressed_len(&self) -> usize { self.compressed.len() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::CompressedBytes;
+
+  #[test]
+  fn compression_round_trips() {
+    let bytes = br#"{"field":"value"}"#.repeat(64);
+    let compressed = CompressedBytes::compress(&bytes).unwrap();
+    assert!(compressed.compressed_len() < bytes.len());
+    assert_eq!(compressed.len(), bytes.len());
+    assert_eq!(compressed.decompress(), bytes);
+
+    // incompressible input is left alone
+    assert!(CompressedBytes::compress(b"abc").is_none());
+  }
+}
@@ -0,0 +1,182 @@
+//! `Display` implementations for `Value`.
+
+use std::fmt::{self, Display, Formatter, Write};
+
+use super::Value;
+
+/// Writes the bytes as a double-quoted string, escaping quotes, backslashes,
+/// and non-printable characters like `redis-cli` does.
+fn write_quoted(f: &mut Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+  f.write_char('"')?;
+  for &b in bytes {
+    match b {
+      b'"' => f.write_str("\\\"")?,
+      b'\\' => f.write_str("\\\\")?,
+      b'\n' => f.write_str("\\n")?,
+      b'\r' => f.write_str("\\r")?,
+      b'\t' => f.write_str("\\t")?,
+      0x20..=0x7e => f.write_char(b as char)?,
+      _ => write!(f, "\\x{b:02x}")?,
+    }
+  }
+  f.write_char('"')
+}
+
+/// Formats the value compactly on a single line, e.g. `["a", 1, {"b": nil}]`.
+impl Display for Value {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::SimpleString(s) => write_quoted(f, s.as_bytes()),
+      Value::BulkString(b) => write_quoted(f, b),
+      Value::Integer(i) => write!(f, "{i}"),
+      Value::Double(d) => write!(f, "{d}"),
+      Value::Boolean(b) => write!(f, "{b}"),
+      Value::BigNumber(n) => write!(f, "{n}"),
+      Value::Nothing => f.write_str("nil"),
+      Value::Error(e) => write!(f, "(error) {e}"),
+      Value::Array(a) => {
+        f.write_char('[')?;
+        for (i, v) in a.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write!(f, "{v}")?;
+        }
+        f.write_char(']')
+      }
+      Value::Map(m) => {
+        f.write_char('{')?;
+        for (i, (k, v)) in m.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write_quoted(f, k.as_bytes())?;
+          write!(f, ": {v}")?;
+        }
+        f.write_char('}')
+      }
+      Value::Set(s) => {
+        f.write_str("~{")?;
+        for (i, v) in s.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write!(f, "{v}")?;
+        }
+        f.write_char('}')
+      }
+    }
+  }
+}
+
+/// A `redis-cli`-style multi-line rendering of a [`Value`], created with
+/// [`Value::pretty()`].
+///
+/// ```text
+/// 1) "a"
+/// 2) (integer) 1
+/// 3) 1) "nested"
+///    2) (nil)
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Pretty<'a>(&'a Value);
+
+impl Value {
+  /// Returns a `redis-cli`-style multi-line renderer for the value.
+  pub fn pretty(&self) -> Pretty<'_> { Pretty(self) }
+}
+
+impl Display for Pretty<'_> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write_pretty(self.0, f, 0)
+  }
+}
+
+/// Writes an aggregate's elements one per line, each prefixed with its index
+/// and `marker`. Continuation lines are indented by `indent`.
+fn write_elements<'a, T: 'a>(
+  f: &mut Formatter<'_>,
+  elements: impl ExactSizeIterator<Item = T>,
+  marker: char,
+  indent: usize,
+  mut write_element: impl FnMut(&mut Formatter<'_>, T, usize) -> fmt::Result,
+) -> fmt::Result {
+  let width = elements.len().to_string().len();
+  for (i, element) in elements.enumerate() {
+    if i > 0 {
+      write!(f, "\n{:indent$}", "")?;
+    }
+    let prefix = format!("{:>width$}{marker} ", i + 1);
+    f.write_str(&prefix)?;
+    write_element(f, element, indent + prefix.len())?;
+  }
+  Ok(())
+}
+
+fn write_pretty(
+  value: &Value,
+  f: &mut Formatter<'_>,
+  indent: usize,
+) -> fmt::Result {
+  match value {
+    Value::SimpleString(s) => write_quoted(f, s.as_bytes()),
+    Value::BulkString(b) => write_quoted(f, b),
+    Value::Integer(i) => write!(f, "(integer) {i}"),
+    Value::Double(d) => write!(f, "(double) {d}"),
+    Value::Boolean(b) => write!(f, "({b})"),
+    Value::BigNumber(n) => write!(f, "(big number) {n}"),
+    Value::Nothing => f.write_str("(nil)"),
+    Value::Error(e) => write!(f, "(error) {e}"),
+    Value::Array(a) if a.is_empty() => f.write_str("(empty array)"),
+    Value::Map(m) if m.is_empty() => f.write_str("(empty hash)"),
+    Value::Set(s) if s.is_empty() => f.write_str("(empty set)"),
+    Value::Array(a) => {
+      write_elements(f, a.iter(), ')', indent, |f, v, indent| {
+        write_pretty(v, f, indent)
+      })
+    }
+    Value::Map(m) => {
+      write_elements(f, m.iter(), '#', indent, |f, (k, v), indent| {
+        write_quoted(f, k.as_bytes())?;
+        f.write_str(" => ")?;
+        write_pretty(v, f, indent)
+      })
+    }
+    Value::Set(s) => {
+      write_elements(f, s.iter(), '~', indent, |f, v, indent| {
+        write_pretty(v, f, indent)
+      })
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::value::Value;
+
+  #[test]
+  fn display_is_compact() {
+    let value = Value::Array(vec![
+      "a".into(),
+      1.into(),
+      Value::BulkString("x\ny\x01".into()),
+      Value::Map([("k".into(), Value::Nothing)].into_iter().collect()),
+    ]);
+    assert_eq!(value.to_string(), r#"["a", 1, "x\ny\x01", {"k": nil}]"#);
+  }
+
+  #[test]
+  fn pretty_indents_nested_values() {
+    let value = Value::Array(vec![
+      "a".into(),
+      1.into(),
+      Value::Array(vec!["nested".into(), Value::Nothing]),
+      Value::Array(vec![]),
+    ]);
+    assert_eq!(
+      value.pretty().to_string(),
+      "1) \"a\"\n2) (integer) 1\n3) 1) \"nested\"\n   2) (nil)\n4) (empty \
+       array)"
+    );
+  }
+}
@@ -0,0 +1,394 @@
+//! Defines the `Dump` item, the serialized form of a key's value used by
+//! `DUMP`, `RESTORE`, and snapshots.
+//!
+//! A payload is the value, then the deadlines of its expiring hash fields,
+//! then the key's deadline (a `0` byte if it has none, or a `1` byte and the
+//! deadline), then a footer: the format version as a little-endian `u16`, and
+//! the
+//! [CRC-64](crate::crc64) of everything before it as a little-endian `u64`.
+//! The checksum is verified before anything is deserialized, so a corrupt
+//! payload is rejected rather than read as garbage.
+//!
+//! Values are encoded as a type byte followed by the contents. Integers are
+//! little-endian, and lengths are `u64`s.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use smol_str::SmolStr;
+
+use super::{StoredValue, Value};
+use crate::{crc64::crc64, KraglinError};
+
+/// The version of the payload format written by [`Dump::encode()`]. Version
+/// 1 payloads have no key deadline, and are read as not expiring.
+pub const DUMP_VERSION: u16 = 2;
+
+/// The length of the footer: the version and the checksum.
+const FOOTER_LEN: usize = 2 + 8;
+
+const SIMPLE_STRING: u8 = 0;
+const INTEGER: u8 = 1;
+const BULK_STRING: u8 = 2;
+const ARRAY: u8 = 3;
+const BOOLEAN: u8 = 4;
+const DOUBLE: u8 = 5;
+const BIG_NUMBER: u8 = 6;
+const MAP: u8 = 7;
+const SET: u8 = 8;
+const NOTHING: u8 = 9;
+const ERROR: u8 = 10;
+const JSON: u8 = 11;
+
+/// An error reading a payload.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DumpError {
+  /// The payload was written by a newer version of the format, or its
+  /// checksum doesn't match, e.g. because it was corrupted or truncated.
+  #[error("payload version or checksum are wrong")]
+  VersionOrChecksum,
+  /// The checksum matches but the contents don't parse, which only happens
+  /// if the payload was crafted.
+  #[error("bad data format")]
+  BadFormat,
+}
+
+impl From<DumpError> for KraglinError {
+  fn from(error: DumpError) -> Self {
+    match error {
+      DumpError::VersionOrChecksum => KraglinError::BadDumpPayload,
+      DumpError::BadFormat => KraglinError::BadDumpFormat,
+    }
+  }
+}
+
+/// A key's value, its deadline, and the deadlines of its expiring hash
+/// fields, as serialized by `DUMP`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dump {
+  /// The value. Compressed strings are serialized decompressed.
+  pub value:           StoredValue,
+  /// The key's deadline as a unix time in milliseconds, if it expires.
+  /// `RESTORE` ignores it in favour of its own time to live, but snapshots
+  /// keep it.
+  pub deadline:        Option<u64>,
+  /// The hash fields which expire, and their deadlines as unix times in
+  /// milliseconds.
+  pub field_deadlines: Vec<(SmolStr, u64)>,
+}
+
+impl Dump {
+  /// Serializes the dump into a checksummed payload.
+  pub fn encode(&self) -> Bytes {
+    let mut buf = BytesMut::new();
+    match &self.value {
+      StoredValue::SimpleString(s) => {
+        put_tagged_str(&mut buf, SIMPLE_STRING, s)
+      }
+      StoredValue::Integer(i) => {
+        buf.put_u8(INTEGER);
+        buf.put_i64_le(*i);
+      }
+      StoredValue::BulkString(b) => put_tagged_bytes(&mut buf, BULK_STRING, b),
+      StoredValue::CompressedBulkString(c) => {
+        put_tagged_bytes(&mut buf, BULK_STRING, &c.decompress())
+      }
+      StoredValue::Array(a) => put_array(&mut buf, ARRAY, a),
+      StoredValue::Boolean(b) => put_value(&mut buf, &Value::Boolean(*b)),
+      StoredValue::Double(d) => put_value(&mut buf, &Value::Double(*d)),
+      StoredValue::BigNumber(n) => {
+        put_tagged_str(&mut buf, BIG_NUMBER, &n.to_string())
+      }
+      StoredValue::Map(m) => put_map(&mut buf, m),
+      StoredValue::Set(s) => put_array(&mut buf, SET, s),
+      StoredValue::Json(j) => put_tagged_str(&mut buf, JSON, &j.to_string()),
+    }
+    buf.put_u64_le(self.field_deadlines.len() as u64);
+    for (field, deadline) in &self.field_deadlines {
+      put_bytes(&mut buf, field.as_bytes());
+      buf.put_u64_le(*deadline);
+    }
+    match self.deadline {
+      Some(deadline) => {
+        buf.put_u8(1);
+        buf.put_u64_le(deadline);
+      }
+      None => buf.put_u8(0),
+    }
+
+    buf.put_u16_le(DUMP_VERSION);
+    let checksum = crc64(0, &buf);
+    buf.put_u64_le(checksum);
+    buf.freeze()
+  }
+
+  /// Deserializes a payload written by [`encode()`](Dump::encode), after
+  /// verifying its checksum.
+  pub fn decode(payload: &[u8]) -> Result<Dump, DumpError> {
+    let body_len = payload
+      .len()
+      .checked_sub(FOOTER_LEN)
+      .ok_or(DumpError::VersionOrChecksum)?;
+    let (checked, checksum) = payload.split_at(body_len + 2);
+    let version =
+      u16::from_le_bytes([checked[body_len], checked[body_len + 1]]);
+    let checksum = u64::from_le_bytes(checksum.try_into().unwrap());
+    if version > DUMP_VERSION || crc64(0, checked) != checksum {
+      return Err(DumpError::VersionOrChecksum);
+    }
+
+    let mut reader = Reader(&payload[..body_len]);
+    let value = reader.stored_value()?;
+    let mut field_deadlines = Vec::new();
+    for _ in 0..reader.u64()? {
+      field_deadlines.push((reader.str()?, reader.u64()?));
+    }
+    let deadline = match version {
+      1 => None,
+      _ => match reader.u8()? {
+        0 => None,
+        1 => Some(reader.u64()?),
+        _ => return Err(DumpError::BadFormat),
+      },
+    };
+    if !reader.0.is_empty() {
+      return Err(DumpError::BadFormat);
+    }
+    Ok(Dump {
+      value,
+      deadline,
+      field_deadlines,
+    })
+  }
+}
+
+fn put_bytes(buf: &mut BytesMut, bytes: &[u8]) {
+  buf.put_u64_le(bytes.len() as u64);
+  buf.put_slice(bytes);
+}
+
+fn put_tagged_bytes(buf: &mut BytesMut, tag: u8, bytes: &[u8]) {
+  buf.put_u8(tag);
+  put_bytes(buf, bytes);
+}
+
+fn put_tagged_str(buf: &mut BytesMut, tag: u8, s: &str) {
+  put_tagged_bytes(buf, tag, s.as_bytes());
+}
+
+fn put_array<'a>(
+  buf: &mut BytesMut,
+  tag: u8,
+  values: impl IntoIterator<Item = &'a Value, IntoIter: ExactSizeIterator>,
+) {
+  let values = values.into_iter();
+  buf.put_u8(tag);
+  buf.put_u64_le(values.len() as u64);
+  for value in values {
+    put_value(buf, value);
+  }
+}
+
+fn put_map(buf: &mut BytesMut, map: &BTreeMap<SmolStr, Value>) {
+  buf.put_u8(MAP);
+  buf.put_u64_le(map.len() as u64);
+  for (field, value) in map {
+    put_bytes(buf, field.as_bytes());
+    put_value(buf, value);
+  }
+}
+
+fn put_value(buf: &mut BytesMut, value: &Value) {
+  match value {
+    Value::SimpleString(s) => put_tagged_str(buf, SIMPLE_STRING, s),
+    Value::Integer(i) => {
+      buf.put_u8(INTEGER);
+      buf.put_i64_le(*i);
+    }
+    Value::BulkString(b) => put_tagged_bytes(buf, BULK_STRING, b),
+    Value::Array(a) => put_array(buf, ARRAY, a),
+    Value::Boolean(b) => {
+      buf.put_u8(BOOLEAN);
+      buf.put_u8(u8::from(*b));
+    }
+    Value::Double(d) => {
+      buf.put_u8(DOUBLE);
+      buf.put_f64_le(*d);
+    }
+    Value::BigNumber(n) => put_tagged_str(buf, BIG_NUMBER, &n.to_string()),
+    Value::Map(m) => put_map(buf, m),
+    Value::Set(s) => put_array(buf, SET, s),
+    Value::Nothing => buf.put_u8(NOTHING),
+    Value::Error(e) => put_tagged_str(buf, ERROR, e),
+  }
+}
+
+/// A cursor over a payload's body.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+  fn take(&mut self, len: usize) -> Result<&[u8], DumpError> {
+    if self.0.len() < len {
+      return Err(DumpError::BadFormat);
+    }
+    let (taken, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(taken)
+  }
+
+  fn u8(&mut self) -> Result<u8, DumpError> { Ok(self.take(1)?[0]) }
+
+  fn u64(&mut self) -> Result<u64, DumpError> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  /// Reads a length, which can't be longer than what's left of the payload
+  /// (as every element takes at least a byte), so that a crafted length
+  /// can't make the reader allocate wildly.
+  fn len(&mut self) -> Result<usize, DumpError> {
+    usize::try_from(self.u64()?)
+      .ok()
+      .filter(|&len| len <= self.0.len())
+      .ok_or(DumpError::BadFormat)
+  }
+
+  fn bytes(&mut self) -> Result<Bytes, DumpError> {
+    let len = self.len()?;
+    Ok(Bytes::copy_from_slice(self.take(len)?))
+  }
+
+  fn str(&mut self) -> Result<SmolStr, DumpError> {
+    let len = self.len()?;
+    std::str::from_utf8(self.take(len)?)
+      .map(SmolStr::from)
+      .map_err(|_| DumpError::BadFormat)
+  }
+
+  /// Reads a top-level value, which can be a JSON document but not a
+  /// nested-only type.
+  fn stored_value(&mut self) -> Result<StoredValue, DumpError> {
+    if self.0.first() == Some(&JSON) {
+      self.u8()?;
+      let json = self.str()?;
+      return serde_json::from_str(&json)
+        .map(StoredValue::Json)
+        .map_err(|_| DumpError::BadFormat);
+    }
+    Ok(match self.value()? {
+      Value::SimpleString(s) => StoredValue::SimpleString(s),
+      Value::Integer(i) => StoredValue::Integer(i),
+      Value::BulkString(b) => StoredValue::BulkString(b),
+      Value::Array(a) => StoredValue::Array(a),
+      Value::Boolean(b) => StoredValue::Boolean(b),
+      Value::Double(d) => StoredValue::Double(d),
+      Value::BigNumber(n) => StoredValue::BigNumber(n),
+      Value::Map(m) => StoredValue::Map(m),
+      Value::Set(s) => StoredValue::Set(s),
+      Value::Nothing | Value::Error(_) => return Err(DumpError::BadFormat),
+    })
+  }
+
+  fn value(&mut self) -> Result<Value, DumpError> {
+    Ok(match self.u8()? {
+      SIMPLE_STRING => Value::SimpleString(self.str()?),
+      INTEGER => Value::Integer(self.u64()? as i64),
+      BULK_STRING => Value::BulkString(self.bytes()?),
+      ARRAY => {
+        let len = self.len()?;
+        Value::Array((0..len).map(|_| self.value()).collect::<Result<_, _>>()?)
+      }
+      BOOLEAN => Value::Boolean(self.u8()? != 0),
+      DOUBLE => Value::Double(f64::from_bits(self.u64()?)),
+      BIG_NUMBER => {
+        Value::BigNumber(self.str()?.parse().map_err(|_| DumpError::BadFormat)?)
+      }
+      MAP => {
+        let len = self.len()?;
+        Value::Map(
+          (0..len)
+            .map(|_| Ok((self.str()?, self.value()?)))
+            .collect::<Result<_, _>>()?,
+        )
+      }
+      SET => {
+        let len = self.len()?;
+        Value::Set(
+          (0..len)
+            .map(|_| self.value())
+            .collect::<Result<BTreeSet<_>, _>>()?,
+        )
+      }
+      NOTHING => Value::Nothing,
+      ERROR => Value::Error(self.str()?),
+      _ => return Err(DumpError::BadFormat),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Dump, DumpError, FOOTER_LEN};
+  use crate::{
+    crc64::crc64,
+    value::{StoredValue, Value},
+  };
+
+  #[test]
+  fn dumps_round_trip() {
+    let values = [
+      StoredValue::BulkString("hello".into()),
+      StoredValue::Integer(-3),
+      StoredValue::BigNumber("123456789012345678901234567890".parse().unwrap()),
+      StoredValue::Array(vec![
+        Value::Double(1.5),
+        Value::Boolean(true),
+        Value::Nothing,
+      ]),
+      StoredValue::Map([("f".into(), Value::SimpleString("v".into()))].into()),
+      StoredValue::Set([Value::Integer(1), Value::Integer(2)].into()),
+      StoredValue::Json(serde_json::json!({ "a": [1, "b", null] })),
+    ];
+    for value in values {
+      let dump = Dump {
+        value,
+        deadline: Some(1_700_000_000_500),
+        field_deadlines: vec![("f".into(), 1_700_000_000_000)],
+      };
+      assert_eq!(Dump::decode(&dump.encode()), Ok(dump));
+    }
+  }
+
+  #[test]
+  fn corrupt_payloads_are_rejected() {
+    let payload = Dump {
+      value:           StoredValue::BulkString("hello".into()),
+      deadline:        None,
+      field_deadlines: vec![],
+    }
+    .encode();
+
+    let mut flipped = payload.to_vec();
+    flipped[3] ^= 1;
+    assert_eq!(Dump::decode(&flipped), Err(DumpError::VersionOrChecksum));
+    assert_eq!(
+      Dump::decode(&payload[..payload.len() - 1]),
+      Err(DumpError::VersionOrChecksum)
+    );
+    assert_eq!(Dump::decode(b""), Err(DumpError::VersionOrChecksum));
+  }
+
+  #[test]
+  fn version_1_payloads_have_no_deadline() {
+    let dump = Dump {
+      value:           StoredValue::Integer(1),
+      deadline:        None,
+      field_deadlines: vec![],
+    };
+    // a version 1 payload is a version 2 one without the deadline byte
+    let v2 = dump.encode();
+    let mut payload = v2[..v2.len() - FOOTER_LEN - 1].to_vec();
+    payload.extend_from_slice(&1u16.to_le_bytes());
+    payload.extend_from_slice(&crc64(0, &payload).to_le_bytes());
+    assert_eq!(Dump::decode(&payload), Ok(dump));
+  }
+}
@@ -0,0 +1,138 @@
+//! Conversions between [`Value`] and [`serde_json::Value`].
+//!
+//! | `Value`        | JSON                                                  |
+//! |----------------|-------------------------------------------------------|
+//! | `SimpleString` | string                                                |
+//! | `BulkString`   | string (invalid UTF-8 is replaced with `U+FFFD`)      |
+//! | `Integer`      | number                                                |
+//! | `Double`       | number, or `"inf"`/`"-inf"`/`"nan"` if not finite     |
+//! | `BigNumber`    | number if it fits in an `i64`/`u64`, else a string    |
+//! | `Boolean`      | boolean                                               |
+//! | `Array`        | array                                                 |
+//! | `Map`          | object                                                |
+//! | `Set`          | array, in the set's order                             |
+//! | `Nothing`      | `null`                                                |
+//!
+//! In the other direction, JSON strings become `BulkString`s (since they may
+//! contain line breaks), numbers become `Integer`s, `BigNumber`s (above
+//! `i64::MAX`), or `Double`s, and `null` becomes `Nothing`. Converting any
+//! JSON value to a [`Value`] and back is lossless; converting a [`Value`] to
+//! JSON and back preserves its contents but not necessarily its variant.
+
+use serde_json::Number;
+
+use super::Value;
+
+fn double_to_json(d: f64) -> serde_json::Value {
+  match Number::from_f64(d) {
+    Some(n) => serde_json::Value::Number(n),
+    None if d.is_nan() => "nan".into(),
+    None if d > 0.0 => "inf".into(),
+    None => "-inf".into(),
+  }
+}
+
+impl From<Value> for serde_json::Value {
+  fn from(value: Value) -> Self {
+    match value {
+      Value::SimpleString(s) | Value::Error(s) => {
+        serde_json::Value::String(s.into())
+      }
+      Value::BulkString(b) => {
+        serde_json::Value::String(String::from_utf8_lossy(&b).into_owned())
+      }
+      Value::Integer(i) => i.into(),
+      Value::Double(d) => double_to_json(d),
+      Value::BigNumber(n) => {
+        if let Ok(i) = i64::try_from(&n) {
+          i.into()
+        } else if let Ok(u) = u64::try_from(&n) {
+          u.into()
+        } else {
+          n.to_string().into()
+        }
+      }
+      Value::Boolean(b) => b.into(),
+      Value::Array(a) => {
+        serde_json::Value::Array(a.into_iter().map(Into::into).collect())
+      }
+      Value::Map(m) => serde_json::Value::Object(
+        m.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+      ),
+      Value::Set(s) => {
+        serde_json::Value::Array(s.into_iter().map(Into::into).collect())
+      }
+      Value::Nothing => serde_json::Value::Null,
+    }
+  }
+}
+
+fn number_to_value(n: Number) -> Value {
+  if let Some(i) = n.as_i64() {
+    Value::Integer(i)
+  } else if let Some(u) = n.as_u64() {
+    Value::BigNumber(u.into())
+  } else {
+    Value::Double(n.as_f64().expect("JSON numbers are i64, u64, or f64"))
+  }
+}
+
+impl From<serde_json::Value> for Value {
+  fn from(value: serde_json::Value) -> Self {
+    match value {
+      serde_json::Value::Null => Value::Nothing,
+      serde_json::Value::Bool(b) => Value::Boolean(b),
+      serde_json::Value::Number(n) => number_to_value(n),
+      serde_json::Value::String(s) => Value::BulkString(s.into()),
+      serde_json::Value::Array(a) => {
+        Value::Array(a.into_iter().map(Into::into).collect())
+      }
+      serde_json::Value::Object(o) => {
+        Value::Map(o.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use crate::value::Value;
+
+  #[test]
+  fn json_round_trips_through_value() {
+    let json = json!({
+      "string": "a\r\nb",
+      "int": -3,
+      "big": u64::MAX,
+      "double": 1.5,
+      "bool": true,
+      "null": null,
+      "array": [1, [2, {"nested": "x"}]],
+    });
+    let value = Value::from(json.clone());
+    assert_eq!(serde_json::Value::from(value), json);
+  }
+
+  #[test]
+  fn values_convert_to_json() {
+    let value = Value::Array(vec![
+      Value::SimpleString("a".into()),
+      Value::BulkString(vec![b'b', 0xff].into()),
+      Value::Double(f64::INFINITY),
+      Value::BigNumber("123456789012345678901234567890".parse().unwrap()),
+      Value::Nothing,
+    ]);
+    assert_eq!(
+      serde_json::Value::from(value),
+      json!([
+        "a",
+        "b\u{fffd}",
+        "inf",
+        "123456789012345678901234567890",
+        null
+      ])
+    );
+  }
+}
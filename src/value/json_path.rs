@@ -0,0 +1,269 @@
+//! Defines the `JsonPath` item, a parsed path into a JSON document.
+
+use std::str::FromStr;
+
+use crate::KraglinError;
+
+/// One step of a [`JsonPath`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+  /// An object member, from `.name` or `['name']`.
+  Member(String),
+  /// An array element, from `[index]`. Negative indices count from the end.
+  Index(i64),
+}
+
+/// A path addressing a single value within a JSON document.
+///
+/// This supports the definite subset of JSONPath: a `$` root followed by
+/// `.member`, `['member']`/`["member"]`, and `[index]` segments (negative
+/// indices count from the end of the array). The legacy RedisJSON syntax is
+/// also accepted, where `.` is the root and the leading `.` may be omitted
+/// (`a.b` is `$.a.b`). Wildcards, slices, filters, and recursive descent are
+/// not supported.
+///
+/// Like RedisJSON, commands reply to a `$` path with an array of the values it
+/// matched, and to a legacy path with the value itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+  segments: Vec<Segment>,
+  legacy:   bool,
+}
+
+fn invalid(path: &str) -> KraglinError {
+  KraglinError::InvalidJsonPath(path.to_owned())
+}
+
+impl FromStr for JsonPath {
+  type Err = KraglinError;
+
+  fn from_str(path: &str) -> Result<Self, Self::Err> {
+    let legacy;
+    let mut rest = match path {
+      "$" | "." => "",
+      _ if path.starts_with('$') => &path[1..],
+      _ if path.starts_with('.') || path.starts_with('[') => path,
+      _ => {
+        legacy = format!(".{path}");
+        &legacy
+      }
+    };
+
+    let mut segments = Vec::new();
+    while let Some(c) = rest.chars().next() {
+      match c {
+        '.' => {
+          let end = rest[1..].find(['.', '[']).map_or(rest.len(), |i| i + 1);
+          let name = &rest[1..end];
+          if name.is_empty() || name == "*" {
+            return Err(invalid(path));
+          }
+          segments.push(Segment::Member(name.to_owned()));
+          rest = &rest[end..];
+        }
+        '[' => {
+          let close = rest.find(']').ok_or_else(|| invalid(path))?;
+          let inner = &rest[1..close];
+          let quoted = inner
+            .strip_prefix('\'')
+            .and_then(|s| s.strip_suffix('\''))
+            .or_else(|| {
+              inner.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+            });
+          segments.push(match quoted {
+            Some(name) => Segment::Member(name.to_owned()),
+            None => Segment::Index(inner.parse().map_err(|_| invalid(path))?),
+          });
+          rest = &rest[close + 1..];
+        }
+        _ => return Err(invalid(path)),
+      }
+    }
+
+    Ok(JsonPath {
+      segments,
+      legacy: !path.starts_with('$'),
+    })
+  }
+}
+
+/// Resolves a possibly negative index into an array of length `len`.
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+  let len = i64::try_from(len).ok()?;
+  let index = if index < 0 { len + index } else { index };
+  (0..len).contains(&index).then_some(index as usize)
+}
+
+fn step<'a>(
+  value: &'a serde_json::Value,
+  segment: &Segment,
+) -> Option<&'a serde_json::Value> {
+  match (value, segment) {
+    (serde_json::Value::Object(o), Segment::Member(name)) => o.get(name),
+    (serde_json::Value::Array(a), Segment::Index(i)) => {
+      a.get(resolve_index(*i, a.len())?)
+    }
+    _ => None,
+  }
+}
+
+fn step_mut<'a>(
+  value: &'a mut serde_json::Value,
+  segment: &Segment,
+) -> Option<&'a mut serde_json::Value> {
+  match (value, segment) {
+    (serde_json::Value::Object(o), Segment::Member(name)) => o.get_mut(name),
+    (serde_json::Value::Array(a), Segment::Index(i)) => {
+      let i = resolve_index(*i, a.len())?;
+      a.get_mut(i)
+    }
+    _ => None,
+  }
+}
+
+impl JsonPath {
+  /// Whether this path addresses the whole document.
+  pub fn is_root(&self) -> bool { self.segments.is_empty() }
+
+  /// Whether this path was written in the legacy syntax rather than starting
+  /// with `$`.
+  pub fn is_legacy(&self) -> bool { self.legacy }
+
+  /// Gets the value at this path.
+  pub fn get<'a>(
+    &self,
+    document: &'a serde_json::Value,
+  ) -> Option<&'a serde_json::Value> {
+    self
+      .segments
+      .iter()
+      .try_fold(document, |value, segment| step(value, segment))
+  }
+
+  /// Gets the value at this path mutably.
+  pub fn get_mut<'a>(
+    &self,
+    document: &'a mut serde_json::Value,
+  ) -> Option<&'a mut serde_json::Value> {
+    self
+      .segments
+      .iter()
+      .try_fold(document, |value, segment| step_mut(value, segment))
+  }
+
+  /// Sets the value at this path. An existing value is replaced; a missing
+  /// object member is added if its parent object exists. Returns whether the
+  /// value was set.
+  pub fn set(
+    &self,
+    document: &mut serde_json::Value,
+    new: serde_json::Value,
+  ) -> bool {
+    let Some((last, parents)) = self.segments.split_last() else {
+      *document = new;
+      return true;
+    };
+    let parent = parents
+      .iter()
+      .try_fold(document, |value, segment| step_mut(value, segment));
+
+    match (parent, last) {
+      (Some(serde_json::Value::Object(o)), Segment::Member(name)) => {
+        o.insert(name.clone(), new);
+        true
+      }
+      (Some(parent), last) => match step_mut(parent, last) {
+        Some(value) => {
+          *value = new;
+          true
+        }
+        None => false,
+      },
+      (None, _) => false,
+    }
+  }
+
+  /// Removes the value at this path, returning it. The root can't be removed
+  /// this way; delete the key instead.
+  pub fn remove(
+    &self,
+    document: &mut serde_json::Value,
+  ) -> Option<serde_json::Value> {
+    let (last, parents) = self.segments.split_last()?;
+    let parent = parents
+      .iter()
+      .try_fold(document, |value, segment| step_mut(value, segment))?;
+
+    match (parent, last) {
+      (serde_json::Value::Object(o), Segment::Member(name)) => o.remove(name),
+      (serde_json::Value::Array(a), Segment::Index(i)) => {
+        let i = resolve_index(*i, a.len())?;
+        Some(a.remove(i))
+      }
+      _ => None,
+    }
+  }
+}
+
+/// The name of a JSON value's type, for error messages.
+pub fn json_type_name(value: &serde_json::Value) -> &'static str {
+  match value {
+    serde_json::Value::Null => "null",
+    serde_json::Value::Bool(_) => "boolean",
+    serde_json::Value::Number(_) => "number",
+    serde_json::Value::String(_) => "string",
+    serde_json::Value::Array(_) => "array",
+    serde_json::Value::Object(_) => "object",
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::JsonPath;
+
+  #[test]
+  fn paths_parse_and_resolve() {
+    let doc = json!({"a": {"b": [1, 2, {"c d": true}]}});
+
+    for (path, expected) in [
+      ("$", Some(&doc)),
+      (".", Some(&doc)),
+      ("$.a.b[0]", Some(&json!(1))),
+      ("a.b[-1]['c d']", Some(&json!(true))),
+      (".a[\"b\"][1]", Some(&json!(2))),
+      ("$.a.missing", None),
+      ("$.a.b[3]", None),
+    ] {
+      let path = path.parse::<JsonPath>().unwrap();
+      assert_eq!(path.get(&doc), expected);
+    }
+    assert!(!"$.a".parse::<JsonPath>().unwrap().is_legacy());
+    assert!("a".parse::<JsonPath>().unwrap().is_legacy());
+
+    for path in ["$..a", "$.a.*", "$[", "$a", "$.a[x]"] {
+      assert!(
+        path.parse::<JsonPath>().is_err(),
+        "{path} should be invalid"
+      );
+    }
+  }
+
+  #[test]
+  fn set_and_remove() {
+    let mut doc = json!({"a": [1, 2]});
+    let path = |p: &str| p.parse::<JsonPath>().unwrap();
+
+    assert!(path("$.b").set(&mut doc, json!("new")));
+    assert!(path("$.a[-1]").set(&mut doc, json!(3)));
+    assert!(!path("$.a[5]").set(&mut doc, json!(3)));
+    assert!(!path("$.x.y").set(&mut doc, json!(3)));
+    assert_eq!(doc, json!({"a": [1, 3], "b": "new"}));
+
+    assert_eq!(path("$.a[0]").remove(&mut doc), Some(json!(1)));
+    assert_eq!(path("$.b").remove(&mut doc), Some(json!("new")));
+    assert_eq!(path("$").remove(&mut doc), None);
+    assert_eq!(doc, json!({"a": [3]}));
+  }
+}
@@ -0,0 +1,572 @@
+//! Defines the `Value` and `StoredValue` items.
+
+mod compressed;
+mod display;
+mod dump;
+mod json;
+mod json_path;
+mod resp;
+mod size;
+
+use std::{
+  cmp::Ordering,
+  collections::{BTreeMap, BTreeSet},
+  hash::Hasher,
+};
+
+use educe::Educe;
+use smol_str::SmolStr;
+
+pub use self::{
+  compressed::CompressedBytes,
+  display::Pretty,
+  dump::{Dump, DumpError, DUMP_VERSION},
+  json_path::{json_type_name, JsonPath},
+  resp::{error_code, write_array_header, write_set_header},
+  size::{field_size, str_size},
+};
+use crate::KraglinError;
+
+fn f64_hash<H: Hasher>(s: &f64, state: &mut H) {
+  decorum::hash::FloatHash::float_hash(s, state);
+}
+
+fn json_hash<H: Hasher>(j: &serde_json::Value, state: &mut H) {
+  state.write(j.to_string().as_bytes());
+}
+
+/// The base value type in [`kraglin`](crate).
+///
+/// This represents every type that can be sent, received, or used as a key's
+/// value. Errors are only values when they're nested in a reply, like the
+/// replies to the commands in a transaction; otherwise they're
+/// [`KraglinError`](crate::KraglinError)s.
+#[derive(Debug, Clone, Educe)]
+#[educe(Hash)]
+pub enum Value {
+  /// A simple string. A simple string is not allowed to contain carraige
+  /// return (`\r`) or line feed (`\n`) characters.
+  SimpleString(SmolStr),
+  /// An integer described by an [`i64`].
+  Integer(i64),
+  /// A string of arbitrary length containing arbitrary bytes.
+  BulkString(bytes::Bytes),
+  /// An array of any number of [`Value`] types.
+  Array(Vec<Value>),
+  /// A boolean value.
+  Boolean(bool),
+  /// A double precision float ([`f64`]).
+  Double(#[educe(Hash(method(f64_hash)))] f64),
+  /// A number which allows for values larger than an [`i64`].
+  BigNumber(dashu_int::IBig),
+  /// A string dictionary of [`Value`]s.
+  Map(BTreeMap<SmolStr, Value>),
+  /// A set of [`Value`]s. Follows the set definition of [`BTreeSet`].
+  Set(BTreeSet<Value>),
+  /// An unset value.
+  Nothing,
+  /// An error nested in a reply, e.g. `EXECABORT ...`.
+  Error(SmolStr),
+}
+
+/// Values are ordered and compared structurally: values of different variants
+/// are never equal (so `Integer(2)`, `BigNumber(2)`, and `SimpleString("2")`
+/// are all distinct), and are ordered by variant first. Doubles are compared
+/// with [`f64::total_cmp()`], so `NaN` equals itself and `-0.0` is less than
+/// `0.0`.
+///
+/// Where Redis would compare values as strings (e.g. set members), normalize
+/// them with [`Value::to_argument()`] first.
+impl Ord for Value {
+  fn cmp(&self, other: &Self) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+      match value {
+        Value::Nothing => 0,
+        Value::Boolean(_) => 1,
+        Value::Integer(_) => 2,
+        Value::BigNumber(_) => 3,
+        Value::Double(_) => 4,
+        Value::SimpleString(_) => 5,
+        Value::BulkString(_) => 6,
+        Value::Array(_) => 7,
+        Value::Set(_) => 8,
+        Value::Map(_) => 9,
+        Value::Error(_) => 10,
+      }
+    }
+
+    match (self, other) {
+      (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+      (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+      (Value::BigNumber(a), Value::BigNumber(b)) => a.cmp(b),
+      (Value::Double(a), Value::Double(b)) => a.total_cmp(b),
+      (Value::SimpleString(a), Value::SimpleString(b)) => a.cmp(b),
+      (Value::BulkString(a), Value::BulkString(b)) => a.cmp(b),
+      (Value::Array(a), Value::Array(b)) => a.cmp(b),
+      (Value::Set(a), Value::Set(b)) => a.cmp(b),
+      (Value::Map(a), Value::Map(b)) => a.cmp(b),
+      (Value::Error(a), Value::Error(b)) => a.cmp(b),
+      _ => rank(self).cmp(&rank(other)),
+    }
+  }
+}
+
+impl PartialOrd for Value {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+
+impl Eq for Value {}
+
+impl Value {
+  /// Converts the value into a single command argument: the bytes it would
+  /// be sent as in a RESP command. Returns `None` for aggregates and
+  /// `Nothing`, which can't be represented as one argument.
+  ///
+  /// This is also the canonical form of set members, so that members which
+  /// would be sent identically (like `Integer(2)` and `SimpleString("2")`)
+  /// are the same member.
+  pub fn to_argument(&self) -> Option<bytes::Bytes> {
+    match self {
+      Value::SimpleString(s) => {
+        Some(bytes::Bytes::copy_from_slice(s.as_bytes()))
+      }
+      Value::BulkString(b) => Some(b.clone()),
+      Value::Integer(i) => Some(i.to_string().into()),
+      Value::Double(d) => Some(d.to_string().into()),
+      Value::BigNumber(n) => Some(n.to_string().into()),
+      Value::Boolean(b) => Some(if *b { "1" } else { "0" }.into()),
+      Value::Array(_)
+      | Value::Map(_)
+      | Value::Set(_)
+      | Value::Nothing
+      | Value::Error(_) => None,
+    }
+  }
+
+  /// The number of bytes the value takes when serialized compactly: scalars
+  /// count the bytes of their argument form (see [`Value::to_argument()`]),
+  /// and aggregates the sum of their elements (and map fields).
+  pub fn serialized_length(&self) -> usize {
+    match self {
+      Value::Array(a) => a.iter().map(Value::serialized_length).sum(),
+      Value::Map(m) => {
+        m.iter().map(|(k, v)| k.len() + v.serialized_length()).sum()
+      }
+      Value::Set(s) => s.iter().map(Value::serialized_length).sum(),
+      value => value.to_argument().map_or(0, |a| a.len()),
+    }
+  }
+}
+
+/// The stored version of [`Value`]. The main difference is the absence of
+/// `Nothing`.
+#[derive(Debug, Clone, PartialEq, Educe)]
+#[educe(Hash)]
+pub enum StoredValue {
+  /// A simple string. A simple string is not allowed to contain carraige
+  /// return (`\r`) or line feed (`\n`) characters.
+  SimpleString(SmolStr),
+  /// An integer described by an [`i64`].
+  Integer(i64),
+  /// A string of arbitrary length containing arbitrary bytes.
+  BulkString(bytes::Bytes),
+  /// A [`StoredValue::BulkString`] which has been compressed at rest. Reads
+  /// as a [`Value::BulkString`].
+  CompressedBulkString(CompressedBytes),
+  /// An array of any number of [`Value`] types.
+  Array(Vec<Value>),
+  /// A boolean value.
+  Boolean(bool),
+  /// A double precision float ([`f64`]).
+  Double(#[educe(Hash(method(f64_hash)))] f64),
+  /// A number which allows for values larger than an [`i64`].
+  BigNumber(dashu_int::IBig),
+  /// A string dictionary of [`Value`]s.
+  Map(BTreeMap<SmolStr, Value>),
+  /// A set of [`Value`]s. Follows the set definition of [`BTreeSet`].
+  Set(BTreeSet<Value>),
+  /// A JSON document, addressed with [`JsonPath`]s by the `JSON.*` commands.
+  Json(#[educe(Hash(method(json_hash)))] serde_json::Value),
+}
+
+impl StoredValue {
+  /// The name of the value's type, as reported by `TYPE`.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      StoredValue::SimpleString(_)
+      | StoredValue::Integer(_)
+      | StoredValue::BulkString(_)
+      | StoredValue::CompressedBulkString(_)
+      | StoredValue::Boolean(_)
+      | StoredValue::Double(_)
+      | StoredValue::BigNumber(_) => "string",
+      StoredValue::Array(_) => "list",
+      StoredValue::Map(_) => "hash",
+      StoredValue::Set(_) => "set",
+      StoredValue::Json(_) => "ReJSON-RL",
+    }
+  }
+
+  /// The name of the value's internal representation, as reported by
+  /// `OBJECT ENCODING`.
+  pub fn encoding(&self) -> &'static str {
+    match self {
+      StoredValue::SimpleString(_) => "simplestring",
+      StoredValue::Integer(_) => "int",
+      StoredValue::BulkString(_) => "raw",
+      StoredValue::CompressedBulkString(_) => "lz4",
+      StoredValue::Array(_) => "array",
+      StoredValue::Boolean(_) => "bool",
+      StoredValue::Double(_) => "double",
+      StoredValue::BigNumber(_) => "bignum",
+      StoredValue::Map(_) => "btreemap",
+      StoredValue::Set(_) => "btreeset",
+      StoredValue::Json(_) => "json",
+    }
+  }
+
+  /// The number of bytes the value takes when serialized compactly, as
+  /// reported by `DEBUG OBJECT`. See [`Value::serialized_length()`].
+  pub fn serialized_length(&self) -> usize {
+    match self {
+      StoredValue::SimpleString(s) => s.len(),
+      StoredValue::BulkString(b) => b.len(),
+      StoredValue::CompressedBulkString(c) => c.compressed_len(),
+      StoredValue::Array(a) => a.iter().map(Value::serialized_length).sum(),
+      StoredValue::Map(m) => {
+        m.iter().map(|(k, v)| k.len() + v.serialized_length()).sum()
+      }
+      StoredValue::Set(s) => s.iter().map(Value::serialized_length).sum(),
+      StoredValue::Json(j) => j.to_string().len(),
+      StoredValue::Integer(i) => Value::Integer(*i).serialized_length(),
+      StoredValue::Boolean(b) => Value::Boolean(*b).serialized_length(),
+      StoredValue::Double(d) => Value::Double(*d).serialized_length(),
+      StoredValue::BigNumber(n) => n.to_string().len(),
+    }
+  }
+
+  /// Returns the contents of a simple string, or of a bulk string which is
+  /// valid UTF-8.
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      StoredValue::SimpleString(s) => Some(s),
+      StoredValue::BulkString(b) => std::str::from_utf8(b).ok(),
+      _ => None,
+    }
+  }
+
+  /// Returns the value as an integer, parsing strings if needed.
+  ///
+  /// Fails with [`KraglinError::WrongType`] for non-integer types,
+  /// [`KraglinError::OutOfRange`] for big numbers which don't fit in an
+  /// [`i64`], and [`KraglinError::CannotParseAsInteger`] for strings which
+  /// don't parse.
+  pub fn as_int(&self) -> Result<i64, KraglinError> {
+    match self {
+      StoredValue::Integer(i) => Ok(*i),
+      StoredValue::BigNumber(n) => {
+        i64::try_from(n).map_err(|_| KraglinError::OutOfRange)
+      }
+      StoredValue::SimpleString(_) | StoredValue::BulkString(_) => self
+        .as_str()
+        .and_then(|s| s.parse().ok())
+        .ok_or(KraglinError::CannotParseAsInteger),
+      // only strings far longer than any integer are compressed
+      StoredValue::CompressedBulkString(_) => {
+        Err(KraglinError::CannotParseAsInteger)
+      }
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+
+  /// Compresses the value if it's a bulk string at least `threshold` bytes
+  /// long and compression saves space. Otherwise, returns it unchanged.
+  pub fn compress_above(self, threshold: usize) -> StoredValue {
+    match self {
+      StoredValue::BulkString(b) if b.len() >= threshold => {
+        match CompressedBytes::compress(&b) {
+          Some(c) => StoredValue::CompressedBulkString(c),
+          None => StoredValue::BulkString(b),
+        }
+      }
+      other => other,
+    }
+  }
+
+  /// Returns the value in its canonical representation, for backends which
+  /// store values normalized (see
+  /// [`BackendConfig::normalize_values`](crate::backends::BackendConfig::normalize_values)):
+  ///
+  /// - Strings holding an integer in its canonical form (no `+`, no leading
+  ///   zeros, and not `-0`), and big numbers which fit in an [`i64`], become
+  ///   [`StoredValue::Integer`]s.
+  /// - Other simple strings become [`StoredValue::BulkString`]s.
+  ///
+  /// The normalized value's string form is the same as the original's, byte
+  /// for byte; only the type it's read back as changes. Other values are
+  /// returned unchanged.
+  pub fn normalize(self) -> StoredValue {
+    match self {
+      StoredValue::SimpleString(s) => match canonical_integer(s.as_bytes()) {
+        Some(i) => StoredValue::Integer(i),
+        None => {
+          StoredValue::BulkString(bytes::Bytes::copy_from_slice(s.as_bytes()))
+        }
+      },
+      StoredValue::BulkString(b) => match canonical_integer(&b) {
+        Some(i) => StoredValue::Integer(i),
+        None => StoredValue::BulkString(b),
+      },
+      StoredValue::BigNumber(n) => match i64::try_from(&n) {
+        Ok(i) => StoredValue::Integer(i),
+        Err(_) => StoredValue::BigNumber(n),
+      },
+      other => other,
+    }
+  }
+
+  /// Returns the value as a JSON document, or fails with
+  /// [`KraglinError::WrongType`].
+  pub fn as_json(&self) -> Result<&serde_json::Value, KraglinError> {
+    match self {
+      StoredValue::Json(j) => Ok(j),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+
+  /// Returns the value as a mutable JSON document, or fails with
+  /// [`KraglinError::WrongType`].
+  pub fn as_json_mut(
+    &mut self,
+  ) -> Result<&mut serde_json::Value, KraglinError> {
+    match self {
+      StoredValue::Json(j) => Ok(j),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+
+  /// Returns the value if it's a string (see [`StoredValue::type_name()`]),
+  /// or fails with [`KraglinError::WrongType`].
+  pub fn as_string(&self) -> Result<&StoredValue, KraglinError> {
+    match self.type_name() {
+      "string" => Ok(self),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+
+  /// Returns the value as a set, or fails with [`KraglinError::WrongType`].
+  pub fn as_set(&self) -> Result<&BTreeSet<Value>, KraglinError> {
+    match self {
+      StoredValue::Set(s) => Ok(s),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+
+  /// Returns the value as a hash, or fails with [`KraglinError::WrongType`].
+  pub fn as_map(&self) -> Result<&BTreeMap<SmolStr, Value>, KraglinError> {
+    match self {
+      StoredValue::Map(m) => Ok(m),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+/// Parses `s` as an integer if it's written exactly as the integer formats,
+/// so that formatting it again gives back the same bytes.
+fn canonical_integer(s: &[u8]) -> Option<i64> {
+  // no `i64` takes more than 20 bytes
+  if s.len() > 20 {
+    return None;
+  }
+  let i = std::str::from_utf8(s).ok()?.parse::<i64>().ok()?;
+  (i.to_string().as_bytes() == s).then_some(i)
+}
+
+impl From<Value> for Option<StoredValue> {
+  fn from(value: Value) -> Self {
+    match value {
+      Value::SimpleString(s) => Some(StoredValue::SimpleString(s)),
+      Value::Integer(i) => Some(StoredValue::Integer(i)),
+      Value::BulkString(bs) => Some(StoredValue::BulkString(bs)),
+      Value::Array(a) => Some(StoredValue::Array(a)),
+      Value::Boolean(b) => Some(StoredValue::Boolean(b)),
+      Value::Double(d) => Some(StoredValue::Double(d)),
+      Value::BigNumber(bn) => Some(StoredValue::BigNumber(bn)),
+      Value::Map(m) => Some(StoredValue::Map(m)),
+      Value::Set(s) => Some(StoredValue::Set(s)),
+      Value::Nothing | Value::Error(_) => None,
+    }
+  }
+}
+
+impl From<Option<StoredValue>> for Value {
+  fn from(value: Option<StoredValue>) -> Self {
+    match value {
+      Some(StoredValue::SimpleString(s)) => Value::SimpleString(s),
+      Some(StoredValue::Integer(i)) => Value::Integer(i),
+      Some(StoredValue::BulkString(bs)) => Value::BulkString(bs),
+      Some(StoredValue::CompressedBulkString(c)) => {
+        Value::BulkString(c.decompress())
+      }
+      Some(StoredValue::Array(a)) => Value::Array(a),
+      Some(StoredValue::Boolean(b)) => Value::Boolean(b),
+      Some(StoredValue::Double(d)) => Value::Double(d),
+      Some(StoredValue::BigNumber(bn)) => Value::BigNumber(bn),
+      Some(StoredValue::Map(m)) => Value::Map(m),
+      Some(StoredValue::Set(s)) => Value::Set(s),
+      Some(StoredValue::Json(j)) => j.into(),
+      None => Value::Nothing,
+    }
+  }
+}
+
+impl From<i64> for Value {
+  fn from(value: i64) -> Self { Value::Integer(value) }
+}
+
+impl From<i32> for Value {
+  fn from(value: i32) -> Self { Value::Integer(value.into()) }
+}
+
+impl From<u32> for Value {
+  fn from(value: u32) -> Self { Value::Integer(value.into()) }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self { Value::Boolean(value) }
+}
+
+impl From<f64> for Value {
+  fn from(value: f64) -> Self { Value::Double(value) }
+}
+
+impl From<dashu_int::IBig> for Value {
+  fn from(value: dashu_int::IBig) -> Self { Value::BigNumber(value) }
+}
+
+impl From<&str> for Value {
+  fn from(value: &str) -> Self { Value::SimpleString(value.into()) }
+}
+
+impl From<String> for Value {
+  fn from(value: String) -> Self { Value::SimpleString(value.into()) }
+}
+
+impl From<SmolStr> for Value {
+  fn from(value: SmolStr) -> Self { Value::SimpleString(value) }
+}
+
+impl From<bytes::Bytes> for Value {
+  fn from(value: bytes::Bytes) -> Self { Value::BulkString(value) }
+}
+
+impl From<&[u8]> for Value {
+  fn from(value: &[u8]) -> Self {
+    Value::BulkString(bytes::Bytes::copy_from_slice(value))
+  }
+}
+
+impl From<Vec<u8>> for Value {
+  fn from(value: Vec<u8>) -> Self { Value::BulkString(value.into()) }
+}
+
+impl From<Vec<Value>> for Value {
+  fn from(value: Vec<Value>) -> Self { Value::Array(value) }
+}
+
+impl From<BTreeMap<SmolStr, Value>> for Value {
+  fn from(value: BTreeMap<SmolStr, Value>) -> Self { Value::Map(value) }
+}
+
+impl TryFrom<Value> for i64 {
+  type Error = KraglinError;
+
+  /// Converts integers, big numbers in range, and strings which parse as
+  /// integers.
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::Integer(i) => Ok(i),
+      Value::BigNumber(n) => {
+        i64::try_from(n).map_err(|_| KraglinError::OutOfRange)
+      }
+      Value::SimpleString(s) => {
+        s.parse().map_err(|_| KraglinError::CannotParseAsInteger)
+      }
+      Value::BulkString(b) => std::str::from_utf8(&b)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(KraglinError::CannotParseAsInteger),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl TryFrom<Value> for f64 {
+  type Error = KraglinError;
+
+  /// Converts doubles and integers.
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::Double(d) => Ok(d),
+      Value::Integer(i) => Ok(i as f64),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl TryFrom<Value> for bool {
+  type Error = KraglinError;
+
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::Boolean(b) => Ok(b),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl TryFrom<Value> for String {
+  type Error = KraglinError;
+
+  /// Converts simple strings, and bulk strings which are valid UTF-8.
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::SimpleString(s) => Ok(s.into()),
+      Value::BulkString(b) => {
+        String::from_utf8(b.into()).map_err(|_| KraglinError::WrongType)
+      }
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl TryFrom<Value> for bytes::Bytes {
+  type Error = KraglinError;
+
+  /// Converts bulk strings and simple strings.
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::BulkString(b) => Ok(b),
+      Value::SimpleString(s) => Ok(bytes::Bytes::copy_from_slice(s.as_bytes())),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
+
+impl<T: TryFrom<Value, Error = KraglinError>> TryFrom<Value> for Vec<T> {
+  type Error = KraglinError;
+
+  /// Converts arrays whose elements all convert to `T`.
+  fn try_from(value: Value) -> Result<Self, Self::Error> {
+    match value {
+      Value::Array(a) => a.into_iter().map(T::try_from).collect(),
+      _ => Err(KraglinError::WrongType),
+    }
+  }
+}
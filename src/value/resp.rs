@@ -0,0 +1,236 @@
+//! Serialization of `Value`s to RESP3, and to RESP2 for clients which haven't
+//! negotiated RESP3.
+
+use std::fmt::Write;
+
+use bytes::{BufMut, BytesMut};
+
+use super::Value;
+
+impl Value {
+  /// Serializes the value as RESP3, appending it to `buf`.
+  pub fn write_resp3(&self, buf: &mut BytesMut) { self.write(buf, true) }
+
+  /// Serializes the value as RESP2, appending it to `buf`. Types RESP2 lacks
+  /// are downgraded like Redis does: booleans become integers, doubles and
+  /// big numbers become bulk strings, maps become flat arrays of keys and
+  /// values, sets become arrays, and `Nothing` becomes a null bulk string.
+  pub fn write_resp2(&self, buf: &mut BytesMut) { self.write(buf, false) }
+
+  /// Serializes the value in the RESP version `protocol` (as set with
+  /// `HELLO`), appending it to `buf`. Versions before 3 get RESP2.
+  pub fn write_resp(&self, protocol: u8, buf: &mut BytesMut) {
+    self.write(buf, protocol >= 3)
+  }
+
+  fn write(&self, buf: &mut BytesMut, resp3: bool) {
+    match self {
+      // simple strings can't hold line breaks, but a malformed one mustn't
+      // break the protocol
+      Value::SimpleString(s) if s.contains(['\r', '\n']) => {
+        write_bulk(buf, s.as_bytes())
+      }
+      Value::SimpleString(s) => write_line(buf, '+', s),
+      Value::Error(e) => write_error(buf, e),
+      Value::Integer(i) => write_line(buf, ':', i),
+      Value::BulkString(b) => write_bulk(buf, b),
+      Value::Array(a) => {
+        write_line(buf, '*', a.len());
+        for v in a {
+          v.write(buf, resp3);
+        }
+      }
+      Value::Boolean(b) if resp3 => {
+        write_line(buf, '#', if *b { 't' } else { 'f' })
+      }
+      Value::Boolean(b) => write_line(buf, ':', u8::from(*b)),
+      Value::Double(d) if resp3 => write_line(buf, ',', Double(*d)),
+      Value::Double(d) => write_bulk(buf, Double(*d).to_string().as_bytes()),
+      Value::BigNumber(n) if resp3 => write_line(buf, '(', n),
+      Value::BigNumber(n) => write_bulk(buf, n.to_string().as_bytes()),
+      Value::Map(m) => {
+        if resp3 {
+          write_line(buf, '%', m.len());
+        } else {
+          write_line(buf, '*', m.len() * 2);
+        }
+        for (k, v) in m {
+          write_bulk(buf, k.as_bytes());
+          v.write(buf, resp3);
+        }
+      }
+      Value::Set(s) => {
+        write_line(buf, if resp3 { '~' } else { '*' }, s.len());
+        for v in s {
+          v.write(buf, resp3);
+        }
+      }
+      Value::Nothing if resp3 => buf.put_slice(b"_\r\n"),
+      Value::Nothing => buf.put_slice(b"$-1\r\n"),
+    }
+  }
+}
+
+/// Writes the header of an array of `len` elements, for replies whose
+/// elements are serialized one at a time after it.
+pub fn write_array_header(buf: &mut BytesMut, len: usize) {
+  write_line(buf, '*', len)
+}
+
+/// Writes the header of a set of `len` elements in the RESP version
+/// `protocol`, which is an array's in RESP2. See [`write_array_header()`].
+pub fn write_set_header(protocol: u8, buf: &mut BytesMut, len: usize) {
+  write_line(buf, if protocol >= 3 { '~' } else { '*' }, len)
+}
+
+/// Formats a double like Redis: `inf`, `-inf`, and `nan` for the special
+/// values, and the shortest representation which round-trips otherwise.
+struct Double(f64);
+
+impl std::fmt::Display for Double {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if self.0.is_nan() {
+      f.write_str("nan")
+    } else {
+      write!(f, "{}", self.0)
+    }
+  }
+}
+
+/// Writes a type byte, `content`, and a line break.
+fn write_line(buf: &mut BytesMut, kind: char, content: impl std::fmt::Display) {
+  // writing to a `BytesMut` can't fail
+  let _ = write!(buf, "{kind}{content}\r\n");
+}
+
+fn write_bulk(buf: &mut BytesMut, bytes: &[u8]) {
+  write_line(buf, '$', bytes.len());
+  buf.put_slice(bytes);
+  buf.put_slice(b"\r\n");
+}
+
+/// Returns the code an error with `message` is sent with: its first word if
+/// that's an uppercase word (like `NOAUTH`), and otherwise the generic `ERR`.
+pub fn error_code(message: &str) -> &str {
+  let code = message.split(' ').next().unwrap_or_default();
+  if !code.is_empty() && code.bytes().all(|b| b.is_ascii_uppercase()) {
+    code
+  } else {
+    "ERR"
+  }
+}
+
+/// Writes an error, prefixed with the generic `ERR` code unless it starts
+/// with a code of its own (see [`error_code()`]). Line breaks are replaced
+/// with spaces.
+fn write_error(buf: &mut BytesMut, message: &str) {
+  buf.put_u8(b'-');
+  if message.split(' ').next() != Some(error_code(message)) {
+    buf.put_slice(b"ERR ");
+  }
+  for b in message.bytes() {
+    buf.put_u8(if matches!(b, b'\r' | b'\n') { b' ' } else { b });
+  }
+  buf.put_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::{BTreeMap, BTreeSet};
+
+  use bytes::BytesMut;
+
+  use crate::{resp::decode, value::Value};
+
+  fn resp3(value: &Value) -> String {
+    let mut buf = BytesMut::new();
+    value.write_resp3(&mut buf);
+    String::from_utf8(buf.to_vec()).unwrap()
+  }
+
+  fn resp2(value: &Value) -> String {
+    let mut buf = BytesMut::new();
+    value.write_resp2(&mut buf);
+    String::from_utf8(buf.to_vec()).unwrap()
+  }
+
+  #[test]
+  fn every_variant_is_serialized() {
+    let map = Value::Map(BTreeMap::from([
+      ("a".into(), Value::Integer(1)),
+      ("b".into(), Value::Nothing),
+    ]));
+    let set = Value::Set(BTreeSet::from([Value::Boolean(true)]));
+    for (value, resp3_form, resp2_form) in [
+      (Value::SimpleString("OK".into()), "+OK\r\n", "+OK\r\n"),
+      (
+        Value::SimpleString("a\nb".into()),
+        "$3\r\na\nb\r\n",
+        "$3\r\na\nb\r\n",
+      ),
+      (Value::Integer(-3), ":-3\r\n", ":-3\r\n"),
+      (
+        Value::BulkString("hi".into()),
+        "$2\r\nhi\r\n",
+        "$2\r\nhi\r\n",
+      ),
+      (Value::Boolean(false), "#f\r\n", ":0\r\n"),
+      (Value::Double(1.5), ",1.5\r\n", "$3\r\n1.5\r\n"),
+      (
+        Value::Double(f64::NEG_INFINITY),
+        ",-inf\r\n",
+        "$4\r\n-inf\r\n",
+      ),
+      (Value::Double(f64::NAN), ",nan\r\n", "$3\r\nnan\r\n"),
+      (
+        Value::BigNumber("12345678901234567890".parse().unwrap()),
+        "(12345678901234567890\r\n",
+        "$20\r\n12345678901234567890\r\n",
+      ),
+      (
+        map,
+        "%2\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n_\r\n",
+        "*4\r\n$1\r\na\r\n:1\r\n$1\r\nb\r\n$-1\r\n",
+      ),
+      (set, "~1\r\n#t\r\n", "*1\r\n:1\r\n"),
+      (Value::Nothing, "_\r\n", "$-1\r\n"),
+      (
+        Value::Array(vec![Value::Integer(1), Value::Array(vec![])]),
+        "*2\r\n:1\r\n*0\r\n",
+        "*2\r\n:1\r\n*0\r\n",
+      ),
+    ] {
+      assert_eq!(resp3(&value), resp3_form, "{value:?}");
+      assert_eq!(resp2(&value), resp2_form, "{value:?}");
+    }
+  }
+
+  #[test]
+  fn errors_get_a_code() {
+    for (message, expected) in [
+      (
+        "NOAUTH Authentication required.",
+        "-NOAUTH Authentication required.\r\n",
+      ),
+      ("no such key", "-ERR no such key\r\n"),
+      ("Protocol error: x", "-ERR Protocol error: x\r\n"),
+      ("ERR two\r\nlines", "-ERR two  lines\r\n"),
+    ] {
+      assert_eq!(resp3(&Value::Error(message.into())), expected);
+    }
+  }
+
+  #[test]
+  fn resp2_replies_decode_to_themselves() {
+    let value = Value::Array(vec![
+      Value::SimpleString("OK".into()),
+      Value::Integer(7),
+      Value::BulkString("bulk\r\nstring".into()),
+      Value::Array(vec![Value::Nothing]),
+    ]);
+    let mut buf = BytesMut::new();
+    value.write_resp2(&mut buf);
+    assert_eq!(decode(&mut buf), Ok(Some(value)));
+    assert!(buf.is_empty());
+  }
+}
@@ -0,0 +1,120 @@
+//! Approximate memory accounting for `Value` and `StoredValue`.
+
+use std::mem::{size_of, size_of_val};
+
+use smol_str::SmolStr;
+
+use super::{StoredValue, Value};
+
+/// Approximates the number of heap bytes owned by the string. Short strings
+/// are stored inline and own none.
+pub fn str_size(s: &SmolStr) -> usize {
+  if s.is_heap_allocated() {
+    s.len()
+  } else {
+    0
+  }
+}
+
+/// Approximates the number of bytes used by a map field and its value.
+pub fn field_size(field: &SmolStr, value: &Value) -> usize {
+  size_of::<SmolStr>() + str_size(field) + value.approximate_size()
+}
+
+fn elements_size<'a>(elements: impl IntoIterator<Item = &'a Value>) -> usize {
+  elements.into_iter().map(Value::approximate_size).sum()
+}
+
+/// Approximates the number of bytes used by a JSON value.
+fn json_size(value: &serde_json::Value) -> usize {
+  size_of::<serde_json::Value>()
+    + match value {
+      serde_json::Value::String(s) => s.len(),
+      serde_json::Value::Array(a) => a.iter().map(json_size).sum(),
+      serde_json::Value::Object(o) => o
+        .iter()
+        .map(|(k, v)| size_of::<String>() + k.len() + json_size(v))
+        .sum(),
+      serde_json::Value::Null
+      | serde_json::Value::Bool(_)
+      | serde_json::Value::Number(_) => 0,
+    }
+}
+
+impl Value {
+  /// Approximates the number of bytes used by the value: its inline size
+  /// plus the heap allocations of its strings and elements, recursively.
+  ///
+  /// Allocator overhead and unused collection capacity are not counted.
+  pub fn approximate_size(&self) -> usize {
+    size_of::<Value>()
+      + match self {
+        Value::SimpleString(s) | Value::Error(s) => str_size(s),
+        Value::BulkString(b) => b.len(),
+        Value::Array(a) => elements_size(a),
+        Value::Map(m) => m.iter().map(|(k, v)| field_size(k, v)).sum(),
+        Value::Set(s) => elements_size(s),
+        Value::BigNumber(n) => size_of_val(n.as_sign_words().1),
+        Value::Integer(_)
+        | Value::Boolean(_)
+        | Value::Double(_)
+        | Value::Nothing => 0,
+      }
+  }
+}
+
+impl StoredValue {
+  /// Approximates the number of bytes used by the value. See
+  /// [`Value::approximate_size()`].
+  pub fn approximate_size(&self) -> usize {
+    size_of::<StoredValue>()
+      + match self {
+        StoredValue::SimpleString(s) => str_size(s),
+        StoredValue::BulkString(b) => b.len(),
+        StoredValue::CompressedBulkString(c) => c.compressed_len(),
+        StoredValue::Array(a) => elements_size(a),
+        StoredValue::Map(m) => m.iter().map(|(k, v)| field_size(k, v)).sum(),
+        StoredValue::Set(s) => elements_size(s),
+        StoredValue::Json(j) => json_size(j) - size_of::<serde_json::Value>(),
+        StoredValue::BigNumber(n) => size_of_val(n.as_sign_words().1),
+        StoredValue::Integer(_)
+        | StoredValue::Boolean(_)
+        | StoredValue::Double(_) => 0,
+      }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::mem::size_of;
+
+  use smol_str::SmolStr;
+
+  use crate::value::{StoredValue, Value};
+
+  #[test]
+  fn approximate_size_counts_nested_allocations() {
+    let long = "x".repeat(100);
+    let scalar = Value::Integer(1).approximate_size();
+    assert_eq!(scalar, size_of::<Value>());
+
+    let string = Value::SimpleString(long.as_str().into());
+    assert_eq!(string.approximate_size(), scalar + 100);
+
+    let array = Value::Array(vec![string.clone(), Value::Integer(1)]);
+    assert_eq!(array.approximate_size(), 3 * scalar + 100);
+
+    let map = StoredValue::Map(
+      [(SmolStr::from(long.as_str()), array.clone())]
+        .into_iter()
+        .collect(),
+    );
+    assert_eq!(
+      map.approximate_size(),
+      size_of::<StoredValue>()
+        + size_of::<SmolStr>()
+        + 100
+        + array.approximate_size()
+    );
+  }
+}
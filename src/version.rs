@@ -0,0 +1,29 @@
+//! Identifies the running build: its version, the git commit it was built
+//! from, and the features it was compiled with. These are recorded by the
+//! build script, and shown in the startup banner, the `Server` section of
+//! `INFO`, and `HELLO`'s reply, so that a bug report can say exactly what's
+//! running.
+
+/// The crate version, like `0.1.0`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The (abbreviated) git commit the build is from, or `unknown` if it wasn't
+/// built from a git checkout.
+pub const GIT_SHA: &str = env!("KRAGLIN_GIT_SHA");
+
+/// Whether the checkout had uncommitted changes to tracked files when built.
+pub const GIT_DIRTY: bool =
+  matches!(env!("KRAGLIN_GIT_DIRTY").as_bytes(), b"1");
+
+/// The enabled cargo features, sorted and comma-separated, like
+/// `hashes,simple`.
+pub const FEATURES: &str = env!("KRAGLIN_FEATURES");
+
+/// Describes the build in one line, like `kraglin 0.1.0 (1a2b3c4d5e6f,
+/// features: hashes,simple)`, for the startup banner.
+pub fn banner() -> String {
+  format!(
+    "kraglin {VERSION} ({GIT_SHA}{}, features: {FEATURES})",
+    if GIT_DIRTY { "-dirty" } else { "" }
+  )
+}
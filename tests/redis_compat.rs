@@ -0,0 +1,289 @@
+//! Wire-level compatibility tests, which drive kraglin over TCP with the
+//! `redis` client crate and check that every implemented command replies
+//! exactly as Redis does.
+//!
+//! The expectations are tables in the style of Redis' own TCL suite (e.g.
+//! `assert_equal OK [r set foo bar]`), replayed in order on one connection,
+//! so cases can be ported from `tests/unit/type/*.tcl` line by line.
+
+#![cfg(feature = "simple")]
+
+use kraglin::{
+  backends::{simple::SimpleBackend, Backend, BackendConfig},
+  server::{ServerBuilder, ServerHandle},
+};
+use redis::{aio::MultiplexedConnection, Value};
+
+/// What a command is expected to reply with.
+enum Expect {
+  /// This exact reply.
+  Reply(Value),
+  /// An array or set reply with these elements, in any order.
+  Unordered(Vec<Value>),
+  /// An error reply with this code (e.g. `WRONGTYPE`).
+  Error(&'static str),
+}
+
+use Expect::{Error, Reply, Unordered};
+
+fn bulk(s: &str) -> Value { Value::BulkString(s.as_bytes().to_vec()) }
+
+async fn start(
+  configure: impl FnOnce(
+    ServerBuilder<SimpleBackend>,
+  ) -> ServerBuilder<SimpleBackend>,
+) -> (ServerHandle<SimpleBackend>, MultiplexedConnection) {
+  let backend = SimpleBackend::new(BackendConfig::default()).unwrap();
+  let server = configure(ServerBuilder::new(backend).tcp("127.0.0.1:0"))
+    .start()
+    .await
+    .unwrap();
+  let url = format!("redis://{}", server.tcp_addr().unwrap());
+  let connection = redis::Client::open(url)
+    .unwrap()
+    .get_multiplexed_async_connection()
+    .await
+    .unwrap();
+  (server, connection)
+}
+
+/// Replays `cases` in order on `connection`, checking each reply.
+async fn replay(
+  connection: &mut MultiplexedConnection,
+  cases: &[(&[&str], Expect)],
+) {
+  for (args, expect) in cases {
+    let mut command = redis::cmd(args[0]);
+    for arg in &args[1..] {
+      command.arg(*arg);
+    }
+    let reply = command.query_async::<Value>(connection).await;
+
+    match (expect, reply) {
+      (Reply(expected), Ok(reply)) => {
+        assert_eq!(&reply, expected, "{args:?}")
+      }
+      (
+        Unordered(expected),
+        Ok(Value::Array(mut elements) | Value::Set(mut elements)),
+      ) => {
+        let mut expected = expected.clone();
+        let key = |v: &Value| format!("{v:?}");
+        elements.sort_by_key(key);
+        expected.sort_by_key(key);
+        assert_eq!(elements, expected, "{args:?}");
+      }
+      (Error(code), Err(error)) => {
+        assert_eq!(error.code(), Some(*code), "{args:?}: {error}")
+      }
+      (_, reply) => panic!("{args:?}: unexpected reply {reply:?}"),
+    }
+  }
+}
+
+#[tokio::test]
+async fn strings_and_keyspace() {
+  let (server, mut r) = start(|b| b).await;
+  replay(&mut r, &[
+    (&["SET", "foo", "bar"], Reply(Value::Okay)),
+    (&["GET", "foo"], Reply(bulk("bar"))),
+    (&["GET", "missing"], Reply(Value::Nil)),
+    (&["SET", "foo", "baz"], Reply(Value::Okay)),
+    (
+      &["MGET", "foo", "missing"],
+      Reply(Value::Array(vec![bulk("baz"), Value::Nil])),
+    ),
+    (&["INCR", "novar"], Reply(Value::Int(1))),
+    (&["INCR", "novar"], Reply(Value::Int(2))),
+    (&["SET", "novar", "17179869184"], Reply(Value::Okay)),
+    (&["INCR", "novar"], Reply(Value::Int(17179869185))),
+    (&["GET", "novar"], Reply(bulk("17179869185"))),
+    (&["SET", "foo", "notanumber"], Reply(Value::Okay)),
+    (&["INCR", "foo"], Error("ERR")),
+    (&["SET", "foo", "9223372036854775807"], Reply(Value::Okay)),
+    (&["INCR", "foo"], Error("ERR")),
+    (&["EXISTS", "foo"], Reply(Value::Int(1))),
+    (&["EXISTS", "missing"], Reply(Value::Int(0))),
+    (&["DEL", "foo"], Reply(Value::Int(1))),
+    (&["DEL", "foo"], Reply(Value::Int(0))),
+    (&["KEYS", "*"], Unordered(vec![bulk("novar")])),
+    (&["DUMP", "missing"], Reply(Value::Nil)),
+    (&["RESTORE", "novar", "0", "garbage"], Error("BUSYKEY")),
+    (&["RESTORE", "new", "0", "garbage"], Error("ERR")),
+    (&["RESTORE", "new", "-1", "garbage"], Error("ERR")),
+    (&["EXPIRE", "missing", "100"], Reply(Value::Int(0))),
+    (&["SET", "temp", "x"], Reply(Value::Okay)),
+    (&["EXPIRE", "temp", "100", "XX"], Reply(Value::Int(0))),
+    (&["EXPIRE", "temp", "100", "NX"], Reply(Value::Int(1))),
+    (&["EXPIRE", "temp", "50", "GT"], Reply(Value::Int(0))),
+    (&["PEXPIRE", "temp", "50000", "LT"], Reply(Value::Int(1))),
+    (&["EXPIRE", "temp", "100", "SOON"], Error("ERR")),
+    (&["EXPIRE", "temp", "9223372036854775807"], Error("ERR")),
+    (&["PEXPIREAT", "temp", "1"], Reply(Value::Int(1))),
+    (&["EXISTS", "temp"], Reply(Value::Int(0))),
+    (
+      &["MIGRATE", "127.0.0.1", "1", "missing", "0", "1000"],
+      Reply(Value::SimpleString("NOKEY".into())),
+    ),
+    (
+      &["MIGRATE", "127.0.0.1", "1", "novar", "0", "1000"],
+      Error("IOERR"),
+    ),
+    (
+      &[
+        "MIGRATE",
+        "127.0.0.1",
+        "1",
+        "novar",
+        "0",
+        "1000",
+        "KEYS",
+        "a",
+      ],
+      Error("ERR"),
+    ),
+    (&["BGSAVE"], Error("ERR")),
+    (&["READONLY"], Reply(Value::Okay)),
+    (&["READWRITE"], Reply(Value::Okay)),
+    (&["SELECT", "1"], Reply(Value::Okay)),
+    (&["KEYS", "*"], Unordered(vec![])),
+    (&["SELECT", "16"], Error("ERR")),
+    (&["SELECT", "-1"], Error("ERR")),
+    (&["SELECT", "0"], Reply(Value::Okay)),
+    (&["EXISTS", "novar"], Reply(Value::Int(1))),
+    (&["GET"], Error("ERR")),
+    (&["NOSUCHCOMMAND"], Error("ERR")),
+  ])
+  .await;
+  server.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "hashes")]
+#[tokio::test]
+async fn hashes() {
+  let (server, mut r) = start(|b| b).await;
+  replay(&mut r, &[
+    (&["HSET", "smallhash", "a", "1"], Reply(Value::Int(1))),
+    (&["HSET", "smallhash", "a", "2"], Reply(Value::Int(0))),
+    (&["HSET", "smallhash", "b", "3"], Reply(Value::Int(1))),
+    (&["HGET", "smallhash", "a"], Reply(bulk("2"))),
+    (&["HGET", "smallhash", "missing"], Reply(Value::Nil)),
+    (&["HGET", "missing", "a"], Reply(Value::Nil)),
+    (
+      &["HMGET", "smallhash", "a", "missing", "b"],
+      Reply(Value::Array(vec![bulk("2"), Value::Nil, bulk("3")])),
+    ),
+    (
+      &["HGETALL", "smallhash"],
+      Unordered(vec![bulk("a"), bulk("2"), bulk("b"), bulk("3")]),
+    ),
+    (&["HGETALL", "missing"], Unordered(vec![])),
+    (&["HSET", "smallhash", "c", "4"], Reply(Value::Int(1))),
+    (&["HDEL", "smallhash", "c", "missing"], Reply(Value::Int(1))),
+    (
+      &["HEXPIRE", "smallhash", "100", "FIELDS", "2", "a", "missing"],
+      Reply(Value::Array(vec![Value::Int(1), Value::Int(-2)])),
+    ),
+    (
+      &["HTTL", "smallhash", "FIELDS", "2", "a", "b"],
+      Reply(Value::Array(vec![Value::Int(100), Value::Int(-1)])),
+    ),
+    (
+      &["HPERSIST", "smallhash", "FIELDS", "1", "a"],
+      Reply(Value::Array(vec![Value::Int(1)])),
+    ),
+    (
+      &["HEXPIRE", "smallhash", "0", "FIELDS", "1", "b"],
+      Reply(Value::Array(vec![Value::Int(2)])),
+    ),
+    (&["HGET", "smallhash", "b"], Reply(Value::Nil)),
+    (&["SET", "str", "x"], Reply(Value::Okay)),
+    (&["HSET", "str", "a", "1"], Error("WRONGTYPE")),
+    (&["HGET", "str", "a"], Error("WRONGTYPE")),
+  ])
+  .await;
+  server.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "sets")]
+#[tokio::test]
+async fn sets() {
+  let (server, mut r) = start(|b| b).await;
+  replay(&mut r, &[
+    (&["SADD", "myset", "foo"], Reply(Value::Int(1))),
+    (&["SADD", "myset", "foo"], Reply(Value::Int(0))),
+    (&["SADD", "myset", "bar"], Reply(Value::Int(1))),
+    (&["SCARD", "myset"], Reply(Value::Int(2))),
+    (&["SISMEMBER", "myset", "foo"], Reply(Value::Int(1))),
+    (&["SISMEMBER", "myset", "missing"], Reply(Value::Int(0))),
+    (
+      &["SMEMBERS", "myset"],
+      Unordered(vec![bulk("foo"), bulk("bar")]),
+    ),
+    (&["SADD", "other", "bar"], Reply(Value::Int(1))),
+    (&["SDIFF", "myset", "other"], Unordered(vec![bulk("foo")])),
+    (
+      &["SDIFFSTORE", "diff", "myset", "other"],
+      Reply(Value::Int(1)),
+    ),
+    (&["SMEMBERS", "diff"], Unordered(vec![bulk("foo")])),
+    (
+      &["SDIFFSTORE", "diff", "other", "myset"],
+      Reply(Value::Int(0)),
+    ),
+    (&["EXISTS", "diff"], Reply(Value::Int(0))),
+    (&["SREM", "myset", "foo"], Reply(Value::Int(1))),
+    (&["SREM", "myset", "foo"], Reply(Value::Int(0))),
+    (&["SREM", "myset", "bar"], Reply(Value::Int(1))),
+    (&["EXISTS", "myset"], Reply(Value::Int(0))),
+    (&["SET", "str", "x"], Reply(Value::Okay)),
+    (&["SADD", "str", "a"], Error("WRONGTYPE")),
+  ])
+  .await;
+  server.shutdown().await.unwrap();
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn json() {
+  let (server, mut r) = start(|b| b).await;
+  replay(&mut r, &[
+    (
+      &["JSON.SET", "doc", "$", r#"{"a":1,"b":[1,2]}"#],
+      Reply(Value::Okay),
+    ),
+    (&["JSON.GET", "doc", "$.a"], Reply(bulk("[1]"))),
+    (&["JSON.NUMINCRBY", "doc", "$.a", "2"], Reply(bulk("[3]"))),
+    (&["JSON.DEL", "doc", "$.b"], Reply(Value::Int(1))),
+    (&["JSON.GET", "doc", "$"], Reply(bulk(r#"[{"a":3}]"#))),
+    (&["JSON.SET", "new", "$.a", "1"], Error("ERR")),
+  ])
+  .await;
+  server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn auth_and_acl() {
+  let (server, mut r) = start(|b| b.requirepass("hunter2")).await;
+  replay(&mut r, &[
+    (&["GET", "foo"], Error("NOAUTH")),
+    (&["HELLO", "2"], Error("NOAUTH")),
+    (
+      &["HELLO", "4", "AUTH", "default", "hunter2"],
+      Error("NOPROTO"),
+    ),
+    (&["AUTH", "wrong"], Error("WRONGPASS")),
+    (&["AUTH", "hunter2"], Reply(Value::Okay)),
+    (&["ACL", "WHOAMI"], Reply(bulk("default"))),
+    (
+      &["ACL", "SETUSER", "alice", "on", ">pw", "~cache:*", "+get"],
+      Reply(Value::Okay),
+    ),
+    (&["AUTH", "alice", "pw"], Reply(Value::Okay)),
+    (&["GET", "cache:a"], Reply(Value::Nil)),
+    (&["GET", "other"], Error("NOPERM")),
+    (&["SET", "cache:a", "1"], Error("NOPERM")),
+  ])
+  .await;
+  server.shutdown().await.unwrap();
+}